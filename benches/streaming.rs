@@ -0,0 +1,187 @@
+//! Pushes uploads through the document channel the way the REST API does, in
+//! actix-sized chunks, and reads them out onto a loopback socket the way the
+//! IPP client does, under a few stream tunings, upload chunk sizes, and with
+//! and without spooling to disk on the way.
+//!
+//! Run with `cargo bench --bench streaming`. To see what a change does, save a
+//! baseline before it with `cargo bench --bench streaming -- --save-baseline
+//! before` and compare after it with `-- --baseline before`.
+
+#[allow(dead_code)]
+#[path = "../src/streaming.rs"]
+mod streaming;
+
+use actix_web::web::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::SinkExt;
+use std::time::Duration;
+use streaming::StreamTuning;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+const UPLOAD_BYTES: usize = 32 * 1024 * 1024;
+/// About what actix hands over per read of a request body
+const UPLOAD_CHUNK: usize = 8 * 1024;
+/// What the IPP client reads at a time, and spooled documents are read off
+/// disk in
+const WRITE_SIZE: usize = 64 * 1024;
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .unwrap()
+}
+
+/// A connection to somewhere that reads everything sent to it, standing in
+/// for CUPS.
+async fn printer() -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buffer = vec![0; WRITE_SIZE];
+        while socket.read(&mut buffer).await.unwrap_or(0) > 0 {}
+    });
+    TcpStream::connect(address).await.unwrap()
+}
+
+/// Starts an upload through a channel tuned by `tuning`, arriving
+/// `chunk_size` bytes at a time, and hands back what the printer side reads.
+fn upload(tuning: StreamTuning, chunk_size: usize) -> impl AsyncRead + Unpin {
+    let (mut tx, reader) = tuning.channel();
+    tokio::spawn(async move {
+        let chunk = Bytes::from(vec![0; chunk_size]);
+        for _ in 0..UPLOAD_BYTES / chunk_size {
+            if tx.send(Ok(chunk.clone())).await.is_err() {
+                break;
+            }
+        }
+    });
+    reader.compat()
+}
+
+/// Copies `document` onto the printer connection a write at a time.
+async fn send(mut document: impl AsyncRead + Unpin) {
+    let mut printer = printer().await;
+    let mut buffer = vec![0; WRITE_SIZE];
+    let mut sent = 0;
+    loop {
+        match document.read(&mut buffer).await.unwrap() {
+            0 => break,
+            n => {
+                printer.write_all(&buffer[..n]).await.unwrap();
+                sent += n;
+            }
+        }
+    }
+    assert_eq!(sent, UPLOAD_BYTES);
+}
+
+/// Writes the whole upload to an unlinked file first, as
+/// `KPRINT_SPOOL_UPLOADS` does, then sends it from there.
+async fn spool_and_send(mut document: impl AsyncRead + Unpin) {
+    let mut file = tokio::fs::File::from_std(tempfile().unwrap());
+    tokio::io::copy(&mut document, &mut file).await.unwrap();
+    file.flush().await.unwrap();
+    file.rewind().await.unwrap();
+    send(file).await;
+}
+
+fn tempfile() -> std::io::Result<std::fs::File> {
+    let path = std::env::temp_dir().join(format!("kprint-bench-{}", std::process::id()));
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)?;
+    std::fs::remove_file(&path)?;
+    Ok(file)
+}
+
+fn channel_buffer(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("channel buffer");
+    group.throughput(Throughput::Bytes(UPLOAD_BYTES as u64));
+    for buffer in [1, 4, 16, 64] {
+        let tuning = StreamTuning {
+            buffer,
+            coalesce_bytes: 0,
+        };
+        group.bench_with_input(
+            BenchmarkId::from_parameter(buffer),
+            &tuning,
+            |b, &tuning| {
+                b.to_async(&runtime)
+                    .iter(|| send(upload(tuning, UPLOAD_CHUNK)))
+            },
+        );
+    }
+    group.finish();
+}
+
+fn coalescing(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("coalescing");
+    group.throughput(Throughput::Bytes(UPLOAD_BYTES as u64));
+    for coalesce_bytes in [0, 64 * 1024, 256 * 1024] {
+        let tuning = StreamTuning {
+            coalesce_bytes,
+            ..StreamTuning::default()
+        };
+        group.bench_with_input(
+            BenchmarkId::from_parameter(coalesce_bytes),
+            &tuning,
+            |b, &tuning| {
+                b.to_async(&runtime)
+                    .iter(|| send(upload(tuning, UPLOAD_CHUNK)))
+            },
+        );
+    }
+    group.finish();
+}
+
+fn upload_chunks(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("upload chunk");
+    group.throughput(Throughput::Bytes(UPLOAD_BYTES as u64));
+    for chunk_size in [1024, UPLOAD_CHUNK, 64 * 1024] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(chunk_size),
+            &chunk_size,
+            |b, &chunk_size| {
+                b.to_async(&runtime)
+                    .iter(|| send(upload(StreamTuning::default(), chunk_size)))
+            },
+        );
+    }
+    group.finish();
+}
+
+fn spooling(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("spooling");
+    group.throughput(Throughput::Bytes(UPLOAD_BYTES as u64));
+    group.bench_function("direct", |b| {
+        b.to_async(&runtime)
+            .iter(|| send(upload(StreamTuning::default(), UPLOAD_CHUNK)))
+    });
+    group.bench_function("spooled", |b| {
+        b.to_async(&runtime)
+            .iter(|| spool_and_send(upload(StreamTuning::default(), UPLOAD_CHUNK)))
+    });
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    // Every iteration is a whole upload, so fewer, longer samples
+    config = Criterion::default()
+        .sample_size(10)
+        .measurement_time(Duration::from_secs(10));
+    targets = channel_buffer, coalescing, upload_chunks, spooling
+}
+criterion_main!(benches);
@@ -0,0 +1,57 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // protox compiles the protos in pure Rust, so building doesn't need protoc
+    let descriptors = protox::compile(["proto/kprint.proto"], ["proto"])?;
+    tonic_build::configure()
+        .build_client(false)
+        .compile_fds(descriptors)?;
+    println!("cargo:rerun-if-changed=proto");
+    build_info();
+    Ok(())
+}
+
+/// Tells the build what it's building, for `GET /api/version`.
+fn build_info() {
+    // Builds without the repo, like in a container, can say it themselves
+    let sha = std::env::var("KPRINT_GIT_SHA").ok().or_else(|| {
+        let output = Command::new("git")
+            .args(["rev-parse", "--short=12", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())?;
+        Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+    });
+    if let Some(sha) = sha {
+        println!("cargo:rustc-env=KPRINT_GIT_SHA={sha}");
+    }
+    println!("cargo:rerun-if-env-changed=KPRINT_GIT_SHA");
+    if Path::new(".git/HEAD").exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        println!("cargo:rerun-if-changed=.git/refs/heads");
+    }
+
+    // Reproducible builds set when they're from
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let built_at = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or_default()
+        });
+    println!("cargo:rustc-env=KPRINT_BUILT_AT={built_at}");
+
+    let mut features = std::env::vars()
+        .filter_map(|(name, _)| {
+            let feature = name.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_lowercase().replace('_', "-"))
+        })
+        .collect::<Vec<_>>();
+    features.sort();
+    println!("cargo:rustc-env=KPRINT_FEATURES={}", features.join(","));
+}
@@ -0,0 +1,189 @@
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use openidconnect::{
+    core::{
+        CoreAuthDisplay, CoreClaimName, CoreClaimType, CoreClient, CoreClientAuthMethod,
+        CoreDeviceAuthorizationResponse, CoreGrantType, CoreJsonWebKey, CoreJsonWebKeyType,
+        CoreJsonWebKeyUse, CoreJweContentEncryptionAlgorithm, CoreJweKeyManagementAlgorithm,
+        CoreJwsSigningAlgorithm, CoreResponseMode, CoreResponseType, CoreSubjectIdentifierType,
+        CoreTokenResponse,
+    },
+    reqwest::async_http_client,
+    AdditionalProviderMetadata, AuthType, ClientId, DeviceAuthorizationUrl, IssuerUrl,
+    OAuth2TokenResponse, ProviderMetadata, RefreshToken, Scope,
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+// The core provider metadata doesn't know about the device authorization
+// endpoint, so we have to ask for it ourselves
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct DeviceEndpointProviderMetadata {
+    device_authorization_endpoint: DeviceAuthorizationUrl,
+}
+impl AdditionalProviderMetadata for DeviceEndpointProviderMetadata {}
+type DeviceProviderMetadata = ProviderMetadata<
+    DeviceEndpointProviderMetadata,
+    CoreAuthDisplay,
+    CoreClientAuthMethod,
+    CoreClaimName,
+    CoreClaimType,
+    CoreGrantType,
+    CoreJweContentEncryptionAlgorithm,
+    CoreJweKeyManagementAlgorithm,
+    CoreJwsSigningAlgorithm,
+    CoreJsonWebKeyType,
+    CoreJsonWebKeyUse,
+    CoreJsonWebKey,
+    CoreResponseMode,
+    CoreResponseType,
+    CoreSubjectIdentifierType,
+>;
+
+/// Tokens from a previous login, cached on disk between runs.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CachedToken {
+    id_token: String,
+    refresh_token: Option<String>,
+    expires_at: DateTime<Utc>,
+}
+
+impl CachedToken {
+    fn from_response(response: &CoreTokenResponse) -> anyhow::Result<Self> {
+        let id_token = response
+            .extra_fields()
+            .id_token()
+            .context("SSO didn't hand back an ID token")?
+            .to_string();
+        // Leave some slack so a token doesn't expire in the middle of an upload
+        let lifetime = response
+            .expires_in()
+            .and_then(|lifetime| Duration::from_std(lifetime).ok())
+            .unwrap_or_else(|| Duration::minutes(5));
+        Ok(CachedToken {
+            id_token,
+            refresh_token: response
+                .refresh_token()
+                .map(|token| token.secret().to_string()),
+            expires_at: Utc::now() + lifetime - Duration::seconds(30),
+        })
+    }
+}
+
+pub struct Login {
+    issuer: String,
+    client_id: String,
+}
+
+impl Login {
+    pub fn new(issuer: String, client_id: String) -> Self {
+        Login { issuer, client_id }
+    }
+
+    fn cache_path() -> anyhow::Result<PathBuf> {
+        Ok(dirs::cache_dir()
+            .context("Couldn't figure out where to cache your login")?
+            .join("kprint")
+            .join("token.json"))
+    }
+
+    async fn load() -> Option<CachedToken> {
+        let contents = tokio::fs::read(Self::cache_path().ok()?).await.ok()?;
+        serde_json::from_slice(&contents).ok()
+    }
+
+    async fn save(token: &CachedToken) -> anyhow::Result<()> {
+        let path = Self::cache_path()?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&path, serde_json::to_vec(token)?).await?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn logout() -> anyhow::Result<()> {
+        match tokio::fs::remove_file(Self::cache_path()?).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn client(&self) -> anyhow::Result<CoreClient> {
+        let issuer_url = IssuerUrl::new(self.issuer.clone()).context("Invalid issuer URL")?;
+        let provider_metadata =
+            DeviceProviderMetadata::discover_async(issuer_url, async_http_client)
+                .await
+                .context("Couldn't reach SSO")?;
+        let device_authorization_endpoint = provider_metadata
+            .additional_metadata()
+            .device_authorization_endpoint
+            .clone();
+        Ok(CoreClient::from_provider_metadata(
+            provider_metadata,
+            ClientId::new(self.client_id.clone()),
+            None,
+        )
+        .set_device_authorization_uri(device_authorization_endpoint)
+        .set_auth_type(AuthType::RequestBody))
+    }
+
+    /// Walks the user through the device code flow, printing the link they
+    /// need to visit.
+    pub async fn login(&self) -> anyhow::Result<String> {
+        let client = self.client().await?;
+        let details: CoreDeviceAuthorizationResponse = client
+            .exchange_device_code()?
+            .add_scope(Scope::new("profile".to_string()))
+            .request_async(async_http_client)
+            .await
+            .context("Couldn't start logging in")?;
+
+        match details.verification_uri_complete() {
+            Some(uri) => eprintln!("To log in, open {}", uri.secret()),
+            None => eprintln!(
+                "To log in, open {} and enter the code {}",
+                details.verification_uri().as_str(),
+                details.user_code().secret()
+            ),
+        }
+
+        let response = client
+            .exchange_device_access_token(&details)
+            .request_async(async_http_client, tokio::time::sleep, None)
+            .await
+            .context("Logging in failed")?;
+        let token = CachedToken::from_response(&response)?;
+        Self::save(&token).await?;
+        eprintln!("Logged in!");
+        Ok(token.id_token)
+    }
+
+    /// An ID token to send to kprint, logging in again only when the cached
+    /// one can't be used or refreshed.
+    pub async fn id_token(&self) -> anyhow::Result<String> {
+        let Some(cached) = Self::load().await else {
+            return self.login().await;
+        };
+        if cached.expires_at > Utc::now() {
+            return Ok(cached.id_token);
+        }
+        if let Some(refresh_token) = cached.refresh_token {
+            let client = self.client().await?;
+            let refreshed = client
+                .exchange_refresh_token(&RefreshToken::new(refresh_token))
+                .request_async(async_http_client)
+                .await;
+            if let Ok(response) = refreshed {
+                let token = CachedToken::from_response(&response)?;
+                Self::save(&token).await?;
+                return Ok(token.id_token);
+            }
+        }
+        self.login().await
+    }
+}
@@ -0,0 +1,241 @@
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use kprint_client::models::{
+    ColorMode, DuplexMode, Envelope, Media, MediaMargins, PageSelection, Poster, PrintOffset,
+    PrintOptions,
+};
+use kprint_client::KprintClient;
+use std::path::{Path, PathBuf};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+mod login;
+use login::Login;
+
+/// Print to CSH printers from the terminal
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// Where kprint is running
+    #[arg(long, env = "KPRINT_URL")]
+    server: String,
+    /// The SSO realm to log in with
+    #[arg(
+        long,
+        env = "KPRINT_ISSUER",
+        default_value = "https://sso.csh.rit.edu/auth/realms/csh"
+    )]
+    issuer: String,
+    #[arg(long, env = "KPRINT_CLIENT_ID", default_value = "kprint")]
+    client_id: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Log in to SSO, even if there's already a saved login
+    Login,
+    /// Forget the saved login
+    Logout,
+    /// Print a document
+    Print {
+        file: PathBuf,
+        #[arg(long, short)]
+        printer: String,
+        /// Print on both sides of the page
+        #[arg(long, short)]
+        duplex: bool,
+        /// With --duplex, flip on the short edge, for landscape documents
+        #[arg(long, requires = "duplex")]
+        short_edge: bool,
+        #[arg(long, short)]
+        color: bool,
+        /// Pages to print, like `1-3,5`. Prints everything by default
+        #[arg(long, default_value = "")]
+        pages: PageSelection,
+        #[arg(long, short = 'n', default_value_t = 1)]
+        copies: u32,
+        /// Defaults to the file's name
+        #[arg(long, short)]
+        title: Option<String>,
+        /// Put a page in front saying who printed it, for PDFs
+        #[arg(long)]
+        cover_sheet: bool,
+        /// Let another printer in the same pool take it, if this one fails
+        #[arg(long)]
+        allow_fallback: bool,
+        /// With --copies, print each copy as a job of its own, so other
+        /// people's jobs can get in between
+        #[arg(long)]
+        separate_jobs: bool,
+        /// Label the job with what it's for, like `evals`. Can be given more
+        /// than once
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// The paper to print on, as JSON like
+        /// `{"xDimension":91440,"yDimension":121920,"source":"main-roll"}`,
+        /// for printers like the plotter that need it spelled out
+        #[arg(long)]
+        media: Option<String>,
+        /// How far in from each edge to print, in hundredths of a millimeter,
+        /// as JSON like `{"top":635,"bottom":635,"left":635,"right":635}`
+        #[arg(long)]
+        margins: Option<String>,
+        /// How far to move what's printed, in hundredths of a millimeter
+        /// right and down, as JSON like `{"x":200,"y":-100}`
+        #[arg(long)]
+        offset: Option<String>,
+        /// Print each page across a grid of sheets, columns by rows, like
+        /// `3x2`, with crop marks to trim them to
+        #[arg(long)]
+        poster: Option<Poster>,
+        /// Print on an envelope: number-10, monarch, dl or c5
+        #[arg(long, value_parser = parse_envelope)]
+        envelope: Option<Envelope>,
+    },
+    /// List the jobs you've printed
+    Jobs,
+    /// Cancel a job
+    Cancel { id: Uuid },
+}
+
+fn content_type(file: &Path) -> &'static str {
+    match file.extension().and_then(|ext| ext.to_str()) {
+        Some("pdf") => "application/pdf",
+        Some("ps") => "application/postscript",
+        Some("txt") => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Spells out what kprint said went wrong, down to each bad field.
+fn describe(err: kprint_client::Error) -> anyhow::Error {
+    let kprint_client::Error::Api { body, .. } = &err else {
+        return err.into();
+    };
+    let mut message = body.message.clone();
+    for field in body.fields() {
+        message += &format!("\n  {}: {}", field.field, field.message);
+    }
+    anyhow::anyhow!(message)
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let login = Login::new(cli.issuer, cli.client_id);
+    let token = match cli.command {
+        Command::Login => {
+            login.login().await?;
+            return Ok(());
+        }
+        Command::Logout => return Login::logout().await,
+        _ => login.id_token().await?,
+    };
+    let kprint = KprintClient::new(cli.server, token);
+
+    match cli.command {
+        Command::Print {
+            file,
+            printer,
+            duplex,
+            short_edge,
+            color,
+            pages,
+            copies,
+            title,
+            cover_sheet,
+            allow_fallback,
+            separate_jobs,
+            tags,
+            media,
+            margins,
+            offset,
+            poster,
+            envelope,
+        } => {
+            let title = title.unwrap_or_else(|| {
+                file.file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default()
+            });
+            let options = PrintOptions {
+                sides: match (duplex, short_edge) {
+                    (false, _) => DuplexMode::OneSided,
+                    (true, false) => DuplexMode::TwoSidedLongEdge,
+                    (true, true) => DuplexMode::TwoSidedShortEdge,
+                },
+                color_mode: if color {
+                    ColorMode::Color
+                } else {
+                    ColorMode::Grayscale
+                },
+                pages,
+                copies,
+                title,
+                cover_sheet,
+                allow_fallback,
+                separate_jobs,
+                tags,
+                media: media
+                    .as_deref()
+                    .map(serde_json::from_str::<Media>)
+                    .transpose()
+                    .context(
+                        "--media isn't media, like {\"xDimension\":91440,\"yDimension\":121920}",
+                    )?,
+                margins: margins
+                    .as_deref()
+                    .map(serde_json::from_str::<MediaMargins>)
+                    .transpose()
+                    .context("--margins isn't margins, like {\"top\":635,\"bottom\":635,\"left\":635,\"right\":635}")?,
+                offset: offset
+                    .as_deref()
+                    .map(serde_json::from_str::<PrintOffset>)
+                    .transpose()
+                    .context("--offset isn't an offset, like {\"x\":200,\"y\":-100}")?,
+                poster,
+                envelope,
+            };
+            let document = tokio::fs::File::open(&file)
+                .await
+                .with_context(|| format!("Couldn't open {}", file.display()))?;
+            let job = kprint
+                .print(
+                    &printer,
+                    &options,
+                    content_type(&file),
+                    reqwest::Body::wrap_stream(ReaderStream::new(document)),
+                )
+                .await
+                .map_err(describe)?;
+            println!(
+                "Sent to {} as job {} ({:?})",
+                job.printer, job.id, job.state
+            );
+        }
+        Command::Jobs => {
+            for job in kprint.jobs().await.map_err(describe)? {
+                println!(
+                    "{}  {:<12} {}  {}",
+                    job.id,
+                    job.printer,
+                    job.submitted_at.format("%Y-%m-%d %H:%M"),
+                    job.title
+                );
+            }
+        }
+        Command::Cancel { id } => {
+            kprint.cancel(id).await.map_err(describe)?;
+            println!("Canceled {id}");
+        }
+        Command::Login | Command::Logout => unreachable!(),
+    }
+    Ok(())
+}
+
+fn parse_envelope(envelope: &str) -> Result<Envelope, String> {
+    serde_json::from_value(serde_json::Value::String(envelope.to_string()))
+        .map_err(|_| format!("{envelope:?} isn't number-10, monarch, dl or c5"))
+}
@@ -0,0 +1,234 @@
+//! A typed client for kprint's v1 API.
+//!
+//! ```no_run
+//! # async fn run(token: String) -> Result<(), kprint_client::Error> {
+//! let kprint = kprint_client::KprintClient::new("https://print.csh.rit.edu", token);
+//! for job in kprint.jobs().await? {
+//!     println!("{} on {}", job.title, job.printer);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod models;
+pub mod pages;
+
+use models::{
+    BroadcastResult, ErrorBody, InlinePrint, JobCreated, JobReceipt, JobStatus, JobSummary,
+    PrintFromUrl, PrintOptions,
+};
+use reqwest::{Body, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use uuid::Uuid;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// kprint understood the request and refused it
+    #[error("{}", .body.message)]
+    Api { status: StatusCode, body: ErrorBody },
+    /// kprint failed without saying why, probably from somewhere in front of it
+    #[error("kprint replied with {0}")]
+    UnexpectedStatus(StatusCode),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+}
+
+pub struct KprintClient {
+    http: reqwest::Client,
+    base_url: String,
+    token: String,
+}
+
+impl KprintClient {
+    /// `base_url` is wherever kprint is served from, without the `/api` part.
+    /// `token` is a CSH SSO ID token for whoever the requests are on behalf of.
+    pub fn new(base_url: impl Into<String>, token: impl Into<String>) -> Self {
+        Self::with_client(reqwest::Client::new(), base_url, token)
+    }
+
+    /// Like [`KprintClient::new`], reusing an HTTP client the caller already has.
+    pub fn with_client(
+        http: reqwest::Client,
+        base_url: impl Into<String>,
+        token: impl Into<String>,
+    ) -> Self {
+        KprintClient {
+            http,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            token: token.into(),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/api/v1{path}", self.base_url)
+    }
+
+    async fn send(request: RequestBuilder) -> Result<reqwest::Response, Error> {
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        match response.json::<ErrorBody>().await {
+            Ok(body) => Err(Error::Api { status, body }),
+            Err(_) => Err(Error::UnexpectedStatus(status)),
+        }
+    }
+
+    async fn json<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, Error> {
+        Ok(Self::send(request).await?.json().await?)
+    }
+
+    /// Prints `document` on `printer`. The document is streamed as-is, so
+    /// `content_type` should describe it, like `application/pdf`.
+    pub async fn print(
+        &self,
+        printer: &str,
+        options: &PrintOptions,
+        content_type: &str,
+        document: impl Into<Body>,
+    ) -> Result<JobCreated, Error> {
+        let request = self
+            .http
+            .post(self.url(&format!("/printers/{printer}/print")))
+            .bearer_auth(&self.token)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .query(&options.query())
+            .body(document);
+        Self::json(request).await
+    }
+
+    /// Prints `document` on every one of `printers`, as a job of its own on
+    /// each. Printers that turn it away don't stop the others, so how each
+    /// went is in the results.
+    pub async fn broadcast(
+        &self,
+        printers: &[&str],
+        options: &PrintOptions,
+        content_type: &str,
+        document: impl Into<Body>,
+    ) -> Result<Vec<BroadcastResult>, Error> {
+        let request = self
+            .http
+            .post(self.url("/print/broadcast"))
+            .bearer_auth(&self.token)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .query(&[("printers", printers.join(","))])
+            .query(&options.query())
+            .body(document);
+        Self::json(request).await
+    }
+
+    /// Prints a small `document` on `printer`, sending it inline as base64
+    /// instead of streaming it. kprint refuses bodies over 2 MiB.
+    pub async fn print_inline(
+        &self,
+        printer: &str,
+        options: &PrintOptions,
+        document: &[u8],
+    ) -> Result<JobCreated, Error> {
+        use base64::Engine;
+        let document = base64::engine::general_purpose::STANDARD.encode(document);
+        let request = self
+            .http
+            .post(self.url(&format!("/printers/{printer}/print-inline")))
+            .bearer_auth(&self.token)
+            .json(&InlinePrint::new(options, document));
+        Self::json(request).await
+    }
+
+    /// Has kprint fetch the document at `url` and print it on `printer`, which
+    /// saves uploading something that's already online.
+    pub async fn print_url(
+        &self,
+        printer: &str,
+        options: &PrintOptions,
+        url: &str,
+    ) -> Result<JobCreated, Error> {
+        let request = self
+            .http
+            .post(self.url(&format!("/printers/{printer}/print-url")))
+            .bearer_auth(&self.token)
+            .query(&options.query())
+            .json(&PrintFromUrl {
+                url: url.to_string(),
+            });
+        Self::json(request).await
+    }
+
+    /// Opens a job on `printer` that documents are added to one at a time
+    /// with [`KprintClient::add_document`], so they print as one job once
+    /// it's closed with [`KprintClient::close_job`].
+    pub async fn open_job(
+        &self,
+        printer: &str,
+        options: &PrintOptions,
+    ) -> Result<JobCreated, Error> {
+        let request = self
+            .http
+            .post(self.url(&format!("/printers/{printer}/jobs")))
+            .bearer_auth(&self.token)
+            .query(&options.query());
+        Self::json(request).await
+    }
+
+    /// Adds `document` to open job `id`, after whatever was added before it.
+    pub async fn add_document(
+        &self,
+        id: Uuid,
+        content_type: &str,
+        document: impl Into<Body>,
+    ) -> Result<(), Error> {
+        let request = self
+            .http
+            .post(self.url(&format!("/jobs/{id}/documents")))
+            .bearer_auth(&self.token)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(document);
+        Self::send(request).await?;
+        Ok(())
+    }
+
+    /// Closes open job `id`, so its documents print.
+    pub async fn close_job(&self, id: Uuid) -> Result<JobStatus, Error> {
+        Self::json(
+            self.http
+                .post(self.url(&format!("/jobs/{id}/close")))
+                .bearer_auth(&self.token),
+        )
+        .await
+    }
+
+    /// Every job the token's owner has submitted, newest first.
+    pub async fn jobs(&self) -> Result<Vec<JobSummary>, Error> {
+        Self::json(self.http.get(self.url("/jobs")).bearer_auth(&self.token)).await
+    }
+
+    pub async fn job(&self, id: Uuid) -> Result<JobStatus, Error> {
+        Self::json(
+            self.http
+                .get(self.url(&format!("/jobs/{id}")))
+                .bearer_auth(&self.token),
+        )
+        .await
+    }
+
+    /// What the job was and what it cost, for keeping.
+    pub async fn receipt(&self, id: Uuid) -> Result<JobReceipt, Error> {
+        Self::json(
+            self.http
+                .get(self.url(&format!("/jobs/{id}/receipt?format=json")))
+                .bearer_auth(&self.token),
+        )
+        .await
+    }
+
+    pub async fn cancel(&self, id: Uuid) -> Result<(), Error> {
+        let request = self
+            .http
+            .delete(self.url(&format!("/jobs/{id}")))
+            .bearer_auth(&self.token);
+        Self::send(request).await?;
+        Ok(())
+    }
+}
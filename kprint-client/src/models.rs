@@ -0,0 +1,1223 @@
+//! Everything that goes over the wire, shared with the server so the two
+//! can't drift apart.
+
+pub use crate::pages::PageSelection;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Stable, machine-readable identifiers for everything that can go wrong.
+///
+/// Frontends are expected to branch on these, so once a code ships it must
+/// keep its meaning. Add new codes rather than repurposing old ones.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    InternalError,
+    InvalidRequest,
+    Unauthenticated,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    UnsupportedApiVersion,
+    ValidationFailed,
+    PrinterNotFound,
+    JobNotFound,
+    JobNotCancelable,
+    JobNotPending,
+    JobRejected,
+    PrinterPaused,
+    PrinterInMaintenance,
+    PrinterUnavailable,
+    PrinterUnhealthy,
+    PreviewNotFound,
+    DocumentFetchFailed,
+    DocumentTooLarge,
+    UnsupportedDocumentType,
+    DocumentInfected,
+    ScannerUnavailable,
+    UploadNotFound,
+    UploadOffsetMismatch,
+    UploadIncomplete,
+    QuotaExceeded,
+    QuotaUnavailable,
+    NotEligible,
+    UserSuspended,
+    EligibilityUnavailable,
+    GuestCodeInvalid,
+    GuestCodeUsedUp,
+    PickupLinkInvalid,
+    JobLinkInvalid,
+    JobNotHeld,
+    IppProtocolError,
+    CupsUnreachable,
+    SpoolIoError,
+    ConversionFailed,
+    DatabaseError,
+    PrinterRefused,
+    PrinterStopped,
+    CupsProxyAuthFailed,
+    FeatureFlagNotFound,
+    DigestMismatch,
+    RequestTimedOut,
+    JobNotOpen,
+    // Per-field codes, used inside the details of VALIDATION_FAILED
+    MissingValue,
+    UnsupportedValue,
+    ValueOutOfRange,
+    ValueTooLong,
+    PageRangeInvalid,
+}
+
+/// The JSON body of every error response from API v1 onward.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct ErrorBody {
+    pub code: ErrorCode,
+    /// Human readable description, not meant to be parsed
+    pub message: String,
+    /// Extra structured context, whose shape depends on `code`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(value_type = Option<Object>))]
+    pub details: Option<serde_json::Value>,
+    /// Matches the `X-Request-Id` response header, for finding the request in the logs
+    pub request_id: Option<String>,
+}
+
+impl ErrorBody {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ErrorBody {
+            code,
+            message: message.into(),
+            details: None,
+            request_id: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    /// The bad fields of a `VALIDATION_FAILED` error, empty for any other code.
+    pub fn fields(&self) -> Vec<FieldError> {
+        self.details
+            .as_ref()
+            .and_then(|details| details.get("fields"))
+            .and_then(|fields| serde_json::from_value(fields.clone()).ok())
+            .unwrap_or_default()
+    }
+}
+
+/// One bad field in a request, as reported under `details.fields` of a
+/// `VALIDATION_FAILED` error.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct FieldError {
+    /// Name of the field as the client sent it
+    pub field: String,
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, code: ErrorCode, message: impl Into<String>) -> Self {
+        FieldError {
+            field: field.to_string(),
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn missing(field: &str) -> Self {
+        Self::new(
+            field,
+            ErrorCode::MissingValue,
+            format!("{field} is required"),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplexMode {
+    TwoSidedLongEdge,
+    TwoSidedShortEdge,
+    OneSided,
+}
+
+impl DuplexMode {
+    pub const ALL: &'static [DuplexMode] = &[
+        DuplexMode::TwoSidedLongEdge,
+        DuplexMode::TwoSidedShortEdge,
+        DuplexMode::OneSided,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorMode {
+    Grayscale,
+    Color,
+}
+
+impl ColorMode {
+    pub const ALL: &'static [ColorMode] = &[ColorMode::Grayscale, ColorMode::Color];
+}
+
+/// The paper a job's printed on, spelled out as an IPP `media-col`, for
+/// printers like the plotter that ignore a plain `media` name. Lengths are
+/// in hundredths of a millimeter, the way IPP has them, and anything left
+/// out is up to the printer.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Media {
+    /// Width, which needs `yDimension` along with it
+    #[cfg_attr(feature = "utoipa", schema(example = 91440))]
+    pub x_dimension: Option<u32>,
+    /// Length, which needs `xDimension` along with it
+    #[cfg_attr(feature = "utoipa", schema(example = 121920))]
+    pub y_dimension: Option<u32>,
+    pub margins: Option<MediaMargins>,
+    /// `media-source` keyword for where the paper comes from, like
+    /// `main-roll` or `tray-2`
+    pub source: Option<String>,
+    /// `media-type` keyword for what the paper is, like `photographic-glossy`
+    #[serde(rename = "type")]
+    pub media_type: Option<String>,
+}
+
+/// How far in from each edge a job's printed, in hundredths of a millimeter.
+/// All 0 prints borderless, on printers that can.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(deny_unknown_fields)]
+pub struct MediaMargins {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+/// How far a job's moved on the page, in hundredths of a millimeter, for
+/// printers that don't quite line up. `x` moves it right and `y` moves it
+/// down, and either can be negative.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(deny_unknown_fields)]
+pub struct PrintOffset {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// One page blown up across a grid of sheets, `columns` across and `rows`
+/// down, to be trimmed and taped together. Written like `3x2`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(deny_unknown_fields)]
+pub struct Poster {
+    pub columns: u32,
+    pub rows: u32,
+}
+
+impl Poster {
+    /// Sheets each page is printed on.
+    pub fn sheets(&self) -> u32 {
+        self.columns * self.rows
+    }
+}
+
+/// Envelope sizes kprint knows how to feed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub enum Envelope {
+    /// 4⅛ by 9½ inches, the usual business envelope
+    #[serde(rename = "number-10")]
+    Number10,
+    /// 3⅞ by 7½ inches
+    #[serde(rename = "monarch")]
+    Monarch,
+    /// 110 by 220 millimeters
+    #[serde(rename = "dl")]
+    Dl,
+    /// 162 by 229 millimeters, which takes A5 flat
+    #[serde(rename = "c5")]
+    C5,
+}
+
+impl Envelope {
+    pub const ALL: &'static [Envelope] = &[
+        Envelope::Number10,
+        Envelope::Monarch,
+        Envelope::Dl,
+        Envelope::C5,
+    ];
+}
+
+/// Who an envelope's to and from, a line at a time, to be laid out on one.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct EnvelopeAddress {
+    pub envelope: Envelope,
+    /// The return address, in the top left corner, if there is one
+    #[serde(default)]
+    pub from: Vec<String>,
+    #[cfg_attr(feature = "utoipa", schema(example = json!(["Computer Science House", "Rochester, NY 14623"])))]
+    pub to: Vec<String>,
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("{0:?} isn't a grid of sheets, like 3x2")]
+pub struct ParsePosterError(String);
+
+impl FromStr for Poster {
+    type Err = ParsePosterError;
+
+    fn from_str(poster: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParsePosterError(poster.to_string());
+        let (columns, rows) = poster.trim().split_once(['x', 'X']).ok_or_else(invalid)?;
+        Ok(Poster {
+            columns: columns.trim().parse().map_err(|_| invalid())?,
+            rows: rows.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl Display for Poster {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.columns, self.rows)
+    }
+}
+
+/// Print options which have been checked over and are ready to be turned into
+/// IPP attributes.
+#[derive(Debug, Clone)]
+pub struct PrintOptions {
+    pub sides: DuplexMode,
+    pub color_mode: ColorMode,
+    pub pages: PageSelection,
+    pub copies: u32,
+    pub title: String,
+    /// Put a page in front saying who printed the document, and what it is
+    pub cover_sheet: bool,
+    /// Send the job to another printer in the same pool if this one fails
+    /// printing it
+    pub allow_fallback: bool,
+    /// Print each copy as a job of its own, one after another
+    pub separate_jobs: bool,
+    /// Labels kept with the job in the history, never sent to the printer
+    pub tags: Vec<String>,
+    /// The paper to print on, when it has to be spelled out
+    pub media: Option<Media>,
+    /// How far in from each edge to print, whatever paper it is
+    pub margins: Option<MediaMargins>,
+    /// How far to move what's printed on the page
+    pub offset: Option<PrintOffset>,
+    /// Print each page across several sheets
+    pub poster: Option<Poster>,
+    /// Print on an envelope, fed the way the printer takes them
+    pub envelope: Option<Envelope>,
+}
+
+impl PrintOptions {
+    /// The options as the query string the print endpoint expects.
+    pub fn query(&self) -> Vec<(&'static str, String)> {
+        let mut query = vec![
+            ("sides", keyword(&self.sides)),
+            ("colorMode", keyword(&self.color_mode)),
+            ("pages", self.pages.to_string()),
+            ("copies", self.copies.to_string()),
+            ("title", self.title.clone()),
+            ("coverSheet", self.cover_sheet.to_string()),
+            ("allowFallback", self.allow_fallback.to_string()),
+            ("separateJobs", self.separate_jobs.to_string()),
+            ("tags", self.tags.join(",")),
+        ];
+        if let Some(media) = &self.media {
+            let media = serde_json::to_string(media).expect("media always serializes");
+            query.push(("media", media));
+        }
+        if let Some(margins) = &self.margins {
+            let margins = serde_json::to_string(margins).expect("margins always serialize");
+            query.push(("margins", margins));
+        }
+        if let Some(offset) = &self.offset {
+            let offset = serde_json::to_string(offset).expect("offsets always serialize");
+            query.push(("offset", offset));
+        }
+        if let Some(poster) = &self.poster {
+            query.push(("poster", poster.to_string()));
+        }
+        if let Some(envelope) = &self.envelope {
+            query.push(("envelope", keyword(envelope)));
+        }
+        query
+    }
+}
+
+/// Keyword enums serialize as a plain string, which is the same name the
+/// server parses back out of the query.
+fn keyword<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(keyword)) => keyword,
+        _ => unreachable!("keywords always serialize to strings"),
+    }
+}
+
+/// Where a job is in its life, mirroring the IPP `job-state` enum.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
+#[serde(rename_all = "kebab-case")]
+pub enum JobState {
+    Pending,
+    PendingHeld,
+    Processing,
+    ProcessingStopped,
+    Canceled,
+    Aborted,
+    Completed,
+    /// The printer didn't say, or said something IPP doesn't define
+    #[serde(other)]
+    Unknown,
+}
+
+impl JobState {
+    pub fn from_ipp(value: i32) -> Self {
+        match value {
+            3 => Self::Pending,
+            4 => Self::PendingHeld,
+            5 => Self::Processing,
+            6 => Self::ProcessingStopped,
+            7 => Self::Canceled,
+            8 => Self::Aborted,
+            9 => Self::Completed,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// What a printer is up to, mirroring the IPP `printer-state` enum.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
+#[serde(rename_all = "kebab-case")]
+pub enum PrinterState {
+    Idle,
+    Processing,
+    Stopped,
+    /// The printer didn't answer in time, so kprint can't say
+    Unreachable,
+    /// The printer didn't say, or said something IPP doesn't define
+    #[serde(other)]
+    Unknown,
+}
+
+impl PrinterState {
+    pub fn from_ipp(value: i32) -> Self {
+        match value {
+            3 => Self::Idle,
+            4 => Self::Processing,
+            5 => Self::Stopped,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// What would happen to a job, if it were submitted.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct DryRun {
+    pub printer: String,
+    /// Pages in the document, when kprint can count them, which it can for
+    /// PDFs. Absent when no document was sent.
+    pub document_pages: Option<u32>,
+    /// Pages that would be printed, after page ranges, copies, and any
+    /// cover sheet
+    pub impressions: Option<u32>,
+    /// Sheets of paper that would be used
+    pub sheets: Option<u32>,
+    /// Options the printer would force, in place of what was asked for
+    #[serde(default)]
+    pub overrides: Vec<AppliedOverride>,
+    /// What it would take out of a quota, when kprint can count the pages
+    #[serde(default)]
+    pub quota_units: Option<u32>,
+    /// The pages that would be printed, like `1-3,5`, once trimmed to the
+    /// document's length. Absent for every page.
+    #[serde(default)]
+    pub page_ranges: Option<String>,
+}
+
+/// What's left of a printer's queue after an admin cleared it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct QueuePurged {
+    pub printer: String,
+    /// How many jobs were canceled
+    pub canceled: u32,
+}
+
+/// A class of printers on the CUPS server, which jobs sent to go to
+/// whichever of its members is free.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct PrinterClass {
+    pub name: String,
+    /// Names of the printers in it, as CUPS knows them
+    pub members: Vec<String>,
+}
+
+/// Body of a request to move a job to another printer.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct MoveJob {
+    /// Name of the printer to move it to, as configured in `KPRINT_PRINTERS`
+    pub printer: String,
+}
+
+/// Body of a request to print a document kprint fetches itself.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PrintFromUrl {
+    /// An `https` URL on one of the hosts kprint is allowed to fetch from,
+    /// like a Google Drive export link or a raw GitHub file
+    pub url: String,
+}
+
+/// What a member wants when they leave options out of a print request.
+/// Anything that isn't set has to be given every time, as usual.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Preferences {
+    /// Where `POST /print` sends documents
+    pub printer: Option<String>,
+    pub sides: Option<DuplexMode>,
+    pub color_mode: Option<ColorMode>,
+    /// Whether PDFs get a cover sheet
+    pub cover_sheet: Option<bool>,
+    /// Whether to be emailed a receipt when each job finishes, if kprint
+    /// sends them
+    pub confirmation_email: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct UploadLinks {
+    /// `PATCH` chunks here, `HEAD` it to find the offset to resume from, or
+    /// `DELETE` it to give up
+    #[serde(rename = "self")]
+    pub upload: String,
+    /// `POST` here once every byte has arrived
+    pub print: String,
+}
+
+impl UploadLinks {
+    pub fn for_upload(id: &Uuid) -> Self {
+        let url = format!("/api/v1/uploads/{id}");
+        UploadLinks {
+            print: format!("{url}/print"),
+            upload: url,
+        }
+    }
+}
+
+/// A document being uploaded a chunk at a time.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct UploadSession {
+    pub id: Uuid,
+    pub printer: String,
+    /// How big the document is, in bytes
+    pub length: u64,
+    /// How many bytes have arrived, which is where the next chunk starts
+    pub offset: u64,
+    /// When the upload is thrown away if nothing more arrives
+    pub expires_at: DateTime<Utc>,
+    pub links: UploadLinks,
+}
+
+/// A whole print request in one JSON body, for small documents from clients
+/// that would rather not stream one.
+///
+/// The options mean the same as the print endpoint's query parameters, and
+/// are all required just like those.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct InlinePrint {
+    /// The document itself, in standard base64
+    pub document_base64: String,
+    #[cfg_attr(feature = "utoipa", schema(value_type = Option<DuplexMode>))]
+    pub sides: Option<String>,
+    #[cfg_attr(feature = "utoipa", schema(value_type = Option<ColorMode>))]
+    pub color_mode: Option<String>,
+    /// Comma separated page ranges to print, like `1-3,5`. Empty prints every page.
+    pub pages: Option<String>,
+    pub copies: Option<u32>,
+    pub title: Option<String>,
+    pub cover_sheet: Option<bool>,
+    pub allow_fallback: Option<bool>,
+    #[serde(default)]
+    pub separate_jobs: Option<bool>,
+    /// Labels kept with the job in the history, like `["evals"]`
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub media: Option<Media>,
+    #[serde(default)]
+    pub margins: Option<MediaMargins>,
+    #[serde(default)]
+    pub offset: Option<PrintOffset>,
+    #[serde(default)]
+    pub poster: Option<Poster>,
+    #[serde(default)]
+    #[cfg_attr(feature = "utoipa", schema(value_type = Option<Envelope>))]
+    pub envelope: Option<String>,
+}
+
+impl InlinePrint {
+    pub fn new(options: &PrintOptions, document_base64: String) -> Self {
+        InlinePrint {
+            document_base64,
+            sides: Some(keyword(&options.sides)),
+            color_mode: Some(keyword(&options.color_mode)),
+            pages: Some(options.pages.to_string()),
+            copies: Some(options.copies),
+            title: Some(options.title.clone()),
+            cover_sheet: Some(options.cover_sheet),
+            allow_fallback: Some(options.allow_fallback),
+            separate_jobs: Some(options.separate_jobs),
+            tags: options.tags.clone(),
+            media: options.media.clone(),
+            margins: options.margins,
+            offset: options.offset,
+            poster: options.poster,
+            envelope: options.envelope.as_ref().map(keyword),
+        }
+    }
+}
+
+/// An admin's note that a printer is out of service, whatever the printer
+/// itself says.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+#[serde(rename_all = "camelCase")]
+pub struct MaintenanceNotice {
+    /// Shown to anyone who tries to print, like "Out of toner until Tuesday"
+    pub message: String,
+    /// Username of the admin who put the printer into maintenance
+    pub set_by: String,
+    pub since: DateTime<Utc>,
+}
+
+/// Body of a request to put a printer into maintenance.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct SetMaintenance {
+    pub message: String,
+}
+
+/// Body of a request to change which log lines kprint writes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct SetLogLevel {
+    /// In `RUST_LOG`'s syntax, like `kprint::api=debug,info`
+    pub filter: String,
+}
+
+/// A switch for part of kprint, which admins can flip while it's running.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct FeatureFlag {
+    /// Like `spooled-uploads`
+    pub name: String,
+    pub description: String,
+    /// Whether it's on right now
+    pub enabled: bool,
+    /// Whether it'd be on without an admin's say, from its default or a
+    /// `KPRINT_FLAG_*` setting
+    pub configured: bool,
+    /// Set while an admin has it on or off whatever it's configured as
+    pub overridden: Option<FlagOverride>,
+}
+
+/// An admin's say on whether a flag's on.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct FlagOverride {
+    pub enabled: bool,
+    /// Username of the admin who set it
+    pub set_by: String,
+    pub since: DateTime<Utc>,
+}
+
+/// Body of a request to turn a flag on or off.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct SetFeatureFlag {
+    pub enabled: bool,
+}
+
+/// How much of what's printed is also sent to the test printer.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Mirroring {
+    /// The test printer, from `KPRINT_MIRROR_PRINTER`, if there is one
+    pub printer: Option<String>,
+    /// Share of jobs mirrored, from 0 to 100
+    pub percent: u8,
+    /// Whether mirrored jobs are printed on the test printer, document and
+    /// all, rather than only having their attributes checked
+    pub documents: bool,
+}
+
+/// Body of a request to change how much is mirrored.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct SetMirroring {
+    /// Share of jobs to mirror, from 0 to 100
+    pub percent: u8,
+    #[serde(default)]
+    pub documents: bool,
+}
+
+/// Which build of kprint a server's running.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct BuildInfo {
+    /// kprint's own version, like `0.1.0`
+    pub version: String,
+    /// The commit it was built from, if the build knew
+    pub git_sha: Option<String>,
+    pub built_at: DateTime<Utc>,
+    /// The cargo features it was built with
+    pub features: Vec<String>,
+    /// The API versions it serves, where `0` is the unversioned `/api`
+    pub api_versions: Vec<u32>,
+}
+
+/// Where kprint got one of its settings from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum ConfigSource {
+    /// The `--config` file, for what the environment left out
+    File,
+    /// The profile `KPRINT_ENV` picked out of the config file, over the rest
+    /// of the file
+    Profile,
+    Environment,
+    /// Given when kprint was started, over everything else
+    CommandLine,
+}
+
+/// One setting a running kprint picked up.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct ConfigSetting {
+    /// The environment variable it's in, like `KPRINT_CUPS_URL`
+    pub name: String,
+    /// What it's set to, with secrets masked
+    pub value: String,
+    pub source: ConfigSource,
+}
+
+/// Which log lines kprint writes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct LogLevel {
+    pub filter: String,
+    /// What it was before, to put back once it's not needed
+    pub previous: String,
+}
+
+/// An admin's note that a member can't print for now.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Suspension {
+    pub username: String,
+    /// Shown to the member whenever they try to print
+    pub reason: String,
+    /// Username of the admin who suspended them
+    pub set_by: String,
+    pub since: DateTime<Utc>,
+    /// When they can print again, or `None` until an admin says so
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Body of a request to suspend a member's printing.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct SetSuspension {
+    pub reason: String,
+    /// RFC 3339. Leave it out to suspend them until an admin lifts it.
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// A code that lets a visitor without an SSO account print, for a while, on
+/// behalf of the member who sponsored them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct GuestCode {
+    pub id: Uuid,
+    /// The code itself, only ever shown when it's made. kprint keeps a hash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    /// Username of the member guest jobs are printed as
+    pub sponsor: String,
+    /// Username of the admin who made it
+    pub created_by: String,
+    /// Who it's for, or anything else worth remembering about it
+    pub note: Option<String>,
+    /// How many pages it can print in all
+    pub pages: u32,
+    pub pages_used: u32,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Body of a request to make a guest code.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct CreateGuestCode {
+    /// Username of the member guest jobs are printed as. Leave it out to
+    /// sponsor them yourself.
+    pub sponsor: Option<String>,
+    /// How many pages it can print in all, up to 500
+    pub pages: u32,
+    /// RFC 3339, at most a week away
+    pub expires_at: DateTime<Utc>,
+    pub note: Option<String>,
+}
+
+/// A printer's current status, as reported by the printer, along with any
+/// maintenance notice an admin left on it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+#[serde(rename_all = "camelCase")]
+pub struct PrinterStatus {
+    pub name: String,
+    pub state: PrinterState,
+    /// Keywords explaining the state, like `media-empty` or `toner-low`
+    pub state_reasons: Vec<String>,
+    /// Free-form explanation from the printer, when it gives one
+    pub message: Option<String>,
+    pub accepting_jobs: bool,
+    /// Paused by an admin, so jobs won't be taken until it's resumed
+    pub paused: bool,
+    /// Set while the printer is in maintenance, when jobs are turned away
+    /// whether or not it's paused
+    pub maintenance: Option<MaintenanceNotice>,
+    /// Set while jobs are turned away because too many in a row couldn't get
+    /// through to the printer, until when that lasts
+    pub unhealthy_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct JobLinks {
+    /// Where to fetch the job's current status
+    #[serde(rename = "self")]
+    pub status: String,
+    /// `DELETE` this to cancel the job
+    pub cancel: String,
+    /// `POST` documents here one at a time, while the job's open
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub documents: Option<String>,
+    /// `POST` here once every document's been added, to print them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub close: Option<String>,
+    /// A PNG of the first page, once it's rendered, if kprint renders
+    /// previews
+    #[serde(default)]
+    pub preview: String,
+    /// What the job was and what it cost, once it's been sent
+    #[serde(default)]
+    pub receipt: String,
+}
+
+impl JobLinks {
+    pub fn for_job(id: &Uuid) -> Self {
+        let url = format!("/api/v1/jobs/{id}");
+        JobLinks {
+            status: url.clone(),
+            cancel: url.clone(),
+            documents: None,
+            close: None,
+            preview: format!("{url}/preview"),
+            receipt: format!("{url}/receipt"),
+        }
+    }
+
+    /// Links for a job that's open for documents to be added to.
+    pub fn for_open_job(id: &Uuid) -> Self {
+        let url = format!("/api/v1/jobs/{id}");
+        JobLinks {
+            documents: Some(format!("{url}/documents")),
+            close: Some(format!("{url}/close")),
+            ..JobLinks::for_job(id)
+        }
+    }
+}
+
+/// Reply to a successful submission.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct JobCreated {
+    /// kprint's id for the job, used in every other job endpoint
+    pub id: Uuid,
+    /// The printer's own id for the job, for cross-referencing with CUPS
+    pub ipp_job_id: Option<i32>,
+    pub printer: String,
+    pub state: JobState,
+    /// Pages the printer expects to print, when it reports that up front
+    pub pages: Option<i32>,
+    /// Quota units charged for this job, when a quota applies
+    pub quota_consumed: Option<u32>,
+    /// The pages being printed, like `1-3,5`, once trimmed to the
+    /// document's length. Absent for every page.
+    #[serde(default)]
+    pub page_ranges: Option<String>,
+    /// Options the printer forced, in place of what was asked for
+    #[serde(default)]
+    pub overrides: Vec<AppliedOverride>,
+    /// With `separateJobs`, how many more copies are sent after this one, as
+    /// jobs of their own once it's printed
+    #[serde(default)]
+    pub copies_to_follow: u32,
+    pub submitted_at: DateTime<Utc>,
+    pub links: JobLinks,
+    /// Set when the printer holds jobs until they're picked up at its
+    /// release station
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pickup: Option<PickupLink>,
+}
+
+/// A signed link that picks up one held job at a release station, without
+/// logging in.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PickupLink {
+    /// What to put in the QR code the release station scans
+    pub token: String,
+    /// `GET` this to see the job, and `POST` to `{url}/release` to print it
+    pub url: String,
+    /// When the link stops working, though the job stays held
+    pub expires_at: DateTime<Utc>,
+}
+
+/// How one printer took a broadcast document. Exactly one of `job` and
+/// `error` is set.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct BroadcastResult {
+    pub printer: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job: Option<JobCreated>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorBody>,
+}
+
+/// An option a printer always prints with, whatever the job asked for.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct AppliedOverride {
+    /// Which option, named like the query parameter
+    pub option: String,
+    pub requested: String,
+    pub applied: String,
+}
+
+/// What kprint itself knows about a job, without asking the printer.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct JobSummary {
+    pub id: Uuid,
+    pub ipp_job_id: Option<i32>,
+    pub printer: String,
+    pub title: String,
+    pub submitted_at: DateTime<Utc>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub links: JobLinks,
+}
+
+/// A job's current status, as reported by the printer.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatus {
+    pub id: Uuid,
+    pub ipp_job_id: Option<i32>,
+    pub printer: String,
+    pub title: String,
+    pub state: JobState,
+    /// Keywords explaining the state, like `job-printing` or `job-canceled-by-user`
+    pub state_reasons: Vec<String>,
+    pub submitted_at: DateTime<Utc>,
+    /// Why the job never reached the printer, when it was spooled first and
+    /// sending it on failed
+    pub failure: Option<String>,
+    /// The printer the job was first sent to, when it failed there and
+    /// kprint sent it on to `printer` instead
+    pub rerouted_from: Option<String>,
+    /// Every job attribute the printer returned, rendered as text
+    pub attributes: BTreeMap<String, String>,
+}
+
+/// How much a member can print before their quota runs out, in units. A
+/// page is a unit unless kprint is set up to charge more for some, like for
+/// color.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+#[serde(rename_all = "camelCase")]
+pub struct Quota {
+    /// The tier their groups put them in, like `actives`. Absent when none
+    /// does, and there's no limit.
+    pub tier: Option<String>,
+    /// Units the tier allows each month or semester. Absent when it's
+    /// unlimited.
+    pub limit: Option<u32>,
+    /// Units printed so far this period
+    pub used: u32,
+    /// Units left this period. Absent when it's unlimited.
+    pub remaining: Option<u32>,
+    pub period_start: DateTime<Utc>,
+    /// When `used` goes back to zero
+    pub period_end: DateTime<Utc>,
+}
+
+/// What everyone used of their quotas in a period that's over.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaPeriod {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Everyone who printed in it, alphabetically
+    pub users: Vec<QuotaUsage>,
+}
+
+/// What one member took out of their quota.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaUsage {
+    pub username: String,
+    pub jobs: u32,
+    pub units: u32,
+}
+
+/// Quota units, by what they were charged for.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaUnits {
+    /// For pages printed in grayscale
+    pub grayscale: u32,
+    /// For pages printed in color
+    pub color: u32,
+    /// Taken off for sheets printed on both sides
+    pub duplex_discount: u32,
+    /// What was charged in all
+    pub total: u32,
+}
+
+/// What kprint remembers about a job after the printer has forgotten it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    pub id: Uuid,
+    pub owner: String,
+    pub printer: String,
+    pub title: String,
+    pub submitted_at: DateTime<Utc>,
+    pub sides: DuplexMode,
+    pub color_mode: ColorMode,
+    pub copies: u32,
+    /// Pages printed, counting every copy, once the printer has said
+    pub pages: Option<u32>,
+    /// Pages kprint counted the job would print before sending it, which it
+    /// only does when they come out of a quota
+    #[serde(default)]
+    pub counted_pages: Option<u32>,
+    /// What kprint took out of its owner's quota for `counted_pages`, at
+    /// what printing cost then
+    #[serde(default)]
+    pub quota_units: Option<QuotaUnits>,
+    /// The job's state when kprint last heard about it
+    pub state: JobState,
+    /// Why the job never reached the printer, if it didn't
+    pub failure: Option<String>,
+    /// What it was printed for, as tagged when it was submitted
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// What a job was and what it cost, to keep for paperwork like getting paid
+/// back for printing done for a committee.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct JobReceipt {
+    pub id: Uuid,
+    /// Who sent the job
+    pub owner: String,
+    pub printer: String,
+    pub title: String,
+    pub submitted_at: DateTime<Utc>,
+    pub sides: DuplexMode,
+    pub color_mode: ColorMode,
+    pub copies: u32,
+    /// Pages printed, counting every copy, once the printer has said, or
+    /// what kprint counted before sending it if it hasn't
+    pub pages: Option<u32>,
+    /// The job's state when kprint last heard about it
+    pub state: JobState,
+    /// Why the job never reached the printer, if it didn't
+    pub failure: Option<String>,
+    /// What it was printed for, as tagged when it was submitted
+    pub tags: Vec<String>,
+    /// What the job took out of its owner's quota, which is nothing if it
+    /// didn't print
+    pub quota_units: QuotaUnits,
+    /// When the receipt was made
+    pub issued_at: DateTime<Utc>,
+}
+
+/// How the printers were used over a stretch of time.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct UsageStats {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Every printer with jobs in the window, alphabetically
+    pub printers: Vec<PrinterUsage>,
+}
+
+/// How one printer was used. Pages only count jobs the printer reported
+/// pages for, and `jobsWithoutPages` says how many it didn't.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PrinterUsage {
+    pub printer: String,
+    pub jobs: u32,
+    pub pages: u32,
+    pub jobs_without_pages: u32,
+    pub color_jobs: u32,
+    pub color_pages: u32,
+    /// Grayscale and bi-level together
+    pub mono_jobs: u32,
+    pub mono_pages: u32,
+    /// Jobs that never reached the printer, or that it aborted
+    pub failed_jobs: u32,
+    /// `failedJobs` out of `jobs`, from 0 to 1
+    pub failure_rate: f64,
+    /// Each day with any jobs, oldest first, in kprint's time zone
+    pub days: Vec<DailyUsage>,
+    /// Each hour of the day with any jobs, busiest first, in kprint's time
+    /// zone
+    pub busiest_hours: Vec<HourlyUsage>,
+}
+
+/// A summary of the last week or month, as sent to admins on a schedule.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct UsageReport {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub jobs: u32,
+    /// Only counting jobs the printers reported pages for
+    pub pages: u32,
+    pub failed_jobs: u32,
+    /// Quota units the jobs came to, whether or not their owners had a quota
+    #[serde(default)]
+    pub units: QuotaUnits,
+    /// Whoever printed the most pages, most first
+    pub top_users: Vec<UserUsage>,
+    pub printers: Vec<PrinterUsage>,
+    /// Printers running low on something, as the report went out
+    pub supply_warnings: Vec<SupplyWarning>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct UserUsage {
+    pub username: String,
+    pub jobs: u32,
+    pub pages: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct SupplyWarning {
+    pub printer: String,
+    /// The printer's own keywords, like `toner-low` or `media-empty`
+    pub reasons: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct DailyUsage {
+    pub date: NaiveDate,
+    pub jobs: u32,
+    pub pages: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct HourlyUsage {
+    /// From 0 to 23
+    pub hour: u32,
+    pub jobs: u32,
+}
+
+/// A freshly generated password for adding kprint as a printer in an
+/// operating system's print dialog.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PrintPassword {
+    pub username: String,
+    /// Only ever shown this once
+    pub password: String,
+    /// Where to point the print dialog, with `{printer}` standing in for a
+    /// printer's name
+    pub printer_uri: String,
+}
@@ -0,0 +1,218 @@
+//! Which pages of a document to print.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Inclusive page ranges, kept sorted with no two overlapping or touching,
+/// so `3-1,2,7-8,9` is the same selection as `1-3,7-9`. Empty means every
+/// page.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageSelection {
+    ranges: Vec<(i32, i32)>,
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ParsePagesError {
+    #[error("{0:?} isn't a page or a range of pages, like 3 or 1-5")]
+    NotARange(String),
+    #[error("Pages are numbered from 1, but got {0:?}")]
+    NotPositive(String),
+}
+
+impl PageSelection {
+    /// Every page of the document.
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// The pages in any of `ranges`. Ranges may run backwards, like `(5, 3)`,
+    /// which is the same as `(3, 5)`.
+    pub fn new(ranges: impl IntoIterator<Item = (i32, i32)>) -> Self {
+        let mut ranges = ranges
+            .into_iter()
+            .map(|(start, end)| (start.min(end), start.max(end)))
+            .collect::<Vec<_>>();
+        ranges.sort();
+        let mut merged: Vec<(i32, i32)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some((_, last_end)) if start <= last_end.saturating_add(1) => {
+                    *last_end = (*last_end).max(end);
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+        PageSelection { ranges: merged }
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The ranges, in order, or none for every page.
+    pub fn ranges(&self) -> &[(i32, i32)] {
+        &self.ranges
+    }
+
+    pub fn contains(&self, page: i32) -> bool {
+        self.is_all()
+            || self
+                .ranges
+                .iter()
+                .any(|(start, end)| (start..=end).contains(&&page))
+    }
+
+    /// How many pages are picked out of a document with `document_pages`.
+    pub fn count(&self, document_pages: u32) -> u32 {
+        if self.is_all() {
+            return document_pages;
+        }
+        let last = document_pages as i32;
+        self.ranges
+            .iter()
+            .filter(|(start, _)| *start <= last)
+            .map(|&(start, end)| (end.min(last) - start + 1) as u32)
+            .sum()
+    }
+
+    /// The same pages, leaving out any past `last`, or `None` if that's all of
+    /// them.
+    pub fn clamp(&self, last: i32) -> Option<Self> {
+        if self.is_all() {
+            return Some(Self::all());
+        }
+        let ranges = self
+            .ranges
+            .iter()
+            .filter(|(start, _)| *start <= last)
+            .map(|&(start, end)| (start, end.min(last)))
+            .collect::<Vec<_>>();
+        (!ranges.is_empty()).then_some(PageSelection { ranges })
+    }
+}
+
+fn parse_page(page: &str, term: &str) -> Result<i32, ParsePagesError> {
+    let page = page
+        .trim()
+        .parse::<i32>()
+        .map_err(|_| ParsePagesError::NotARange(term.to_string()))?;
+    if page < 1 {
+        return Err(ParsePagesError::NotPositive(term.to_string()));
+    }
+    Ok(page)
+}
+
+impl FromStr for PageSelection {
+    type Err = ParsePagesError;
+
+    /// Parses comma separated pages and ranges, like `1-3,5`. Empty picks
+    /// every page.
+    fn from_str(pages: &str) -> Result<Self, Self::Err> {
+        if pages.trim().is_empty() {
+            return Ok(Self::all());
+        }
+        let ranges = pages
+            .split(',')
+            .map(|term| {
+                let term = term.trim();
+                let (start, end) = term.split_once('-').unwrap_or((term, term));
+                Ok((parse_page(start, term)?, parse_page(end, term)?))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(ranges))
+    }
+}
+
+/// Written the way it's parsed, like `1-3,5`, or empty for every page.
+impl Display for PageSelection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, &(start, end)) in self.ranges.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            match start == end {
+                true => write!(f, "{start}")?,
+                false => write!(f, "{start}-{end}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(pages: &str) -> PageSelection {
+        pages.parse().unwrap()
+    }
+
+    #[test]
+    fn empty_is_every_page() {
+        assert!(parse("").is_all());
+        assert!(parse("  ").is_all());
+        assert!(parse("").contains(1000));
+    }
+
+    #[test]
+    fn single_pages_are_kept() {
+        assert_eq!(parse("5").ranges(), &[(5, 5)]);
+        assert_eq!(parse("5-5").ranges(), &[(5, 5)]);
+        assert_eq!(parse("1,3").ranges(), &[(1, 1), (3, 3)]);
+    }
+
+    #[test]
+    fn backwards_ranges_are_turned_around() {
+        assert_eq!(parse("5-3").ranges(), &[(3, 5)]);
+        assert_eq!(parse("9-7,1").ranges(), &[(1, 1), (7, 9)]);
+    }
+
+    #[test]
+    fn overlapping_and_touching_ranges_are_merged() {
+        assert_eq!(parse("1-5,3-8").ranges(), &[(1, 8)]);
+        assert_eq!(parse("1-10,2-3").ranges(), &[(1, 10)]);
+        assert_eq!(parse("1-3,4-6").ranges(), &[(1, 6)]);
+        assert_eq!(parse("3,3,3").ranges(), &[(3, 3)]);
+        assert_eq!(parse("7-9,1-2").ranges(), &[(1, 2), (7, 9)]);
+    }
+
+    #[test]
+    fn bad_pages_are_rejected() {
+        assert_eq!(
+            "0-3".parse::<PageSelection>(),
+            Err(ParsePagesError::NotPositive("0-3".to_string()))
+        );
+        assert_eq!(
+            "a".parse::<PageSelection>(),
+            Err(ParsePagesError::NotARange("a".to_string()))
+        );
+        assert!("1-".parse::<PageSelection>().is_err());
+        assert!("1,,2".parse::<PageSelection>().is_err());
+        assert!("-3".parse::<PageSelection>().is_err());
+    }
+
+    #[test]
+    fn written_like_it_is_parsed() {
+        assert_eq!(parse(" 5-3 , 1,2 ").to_string(), "1-5");
+        assert_eq!(parse("1,3-4").to_string(), "1,3-4");
+        assert_eq!(parse("").to_string(), "");
+        let selection = parse("2,4-6,9");
+        assert_eq!(parse(&selection.to_string()), selection);
+    }
+
+    #[test]
+    fn counts_only_pages_the_document_has() {
+        assert_eq!(parse("").count(7), 7);
+        assert_eq!(parse("1-3,5").count(10), 4);
+        assert_eq!(parse("2-5,9").count(3), 2);
+        assert_eq!(parse("50-60").count(3), 0);
+    }
+
+    #[test]
+    fn clamps_to_the_last_page() {
+        assert_eq!(parse("1-5,7-9").clamp(3), Some(parse("1-3")));
+        assert_eq!(parse("3-5").clamp(3), Some(parse("3")));
+        assert_eq!(parse("50-60").clamp(3), None);
+        assert_eq!(parse("").clamp(3), Some(PageSelection::all()));
+    }
+}
@@ -0,0 +1,837 @@
+//! Endpoints for looking after the printers, which only members of
+//! `KPRINT_ADMIN_GROUPS` can use.
+
+use crate::api::{job_created, paged};
+use crate::app::AppState;
+use crate::audit::{self, AuditEvent};
+use crate::auth::Admin;
+use crate::config;
+use crate::error::{ErrorBody, KprintError};
+use crate::export::{self, ExportQuery};
+use crate::flags::Flag;
+use crate::listing::{Key, ListQuery};
+use crate::logging::{self, Pii};
+use crate::service::{self, Pause};
+use crate::stats::StatsQuery;
+use crate::version::ApiVersion;
+use actix_web::{
+    delete, get,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    post, put,
+    web::{Data, Json, Path, Query},
+    HttpRequest, HttpResponse,
+};
+use kprint_client::models::{
+    ColorMode, ConfigSetting, CreateGuestCode, DuplexMode, FeatureFlag, GuestCode, HistoryEntry,
+    JobCreated, LogLevel, MaintenanceNotice, Mirroring, MoveJob, PageSelection, PrintOptions,
+    PrinterClass, PrinterStatus, QueuePurged, QuotaPeriod, SetFeatureFlag, SetLogLevel,
+    SetMaintenance, SetMirroring, SetSuspension, Suspension, UsageReport, UsageStats,
+};
+use uuid::Uuid;
+
+/// Print a test page
+///
+/// Prints a page kprint draws itself, with a grid, color bars, and what the
+/// printer reports about itself, for checking a printer over after
+/// maintenance.
+#[utoipa::path(
+    params(
+        ("printer" = String, Path, description = "Name of the printer, as configured in `KPRINT_PRINTERS`"),
+    ),
+    responses(
+        (status = 201, description = "The test page was sent to the printer", body = JobCreated,
+            headers(("Location" = String, description = "The new job's status URL"))),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+        (status = 404, description = "No printer with that name", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[post("/admin/printers/{printer}/test-page")]
+pub async fn test_page(
+    printer: Path<String>,
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+    version: ApiVersion,
+) -> Result<HttpResponse, KprintError> {
+    let printer = printer.into_inner();
+    // A printer that won't say how it's doing is exactly what a test page is
+    // for, so that doesn't stop it printing
+    let status = match service::printer_status(&app_data, &printer).await {
+        Ok(status) => Some(status),
+        Err(err @ KprintError::PrinterNotFound(_)) => return Err(err),
+        Err(err) => {
+            log::warn!("Couldn't get the status of {printer} for its test page: {err}");
+            None
+        }
+    };
+    let uri = app_data.printers[&printer].uri().to_string();
+    let page = crate::test_page::render(&printer, status.as_ref(), &uri, admin.username())
+        .map_err(KprintError::Conversion)?;
+    log::info!("{} printed a test page on {printer}", Pii(admin.username()));
+
+    let options = PrintOptions {
+        sides: DuplexMode::OneSided,
+        color_mode: ColorMode::Color,
+        pages: PageSelection::all(),
+        copies: 1,
+        title: "kprint test page".to_string(),
+        cover_sheet: false,
+        allow_fallback: false,
+        separate_jobs: false,
+        tags: vec![],
+        media: None,
+        margins: None,
+        offset: None,
+        poster: None,
+        envelope: None,
+    };
+    let submitted = service::submit(
+        &app_data,
+        admin.username(),
+        true,
+        admin.groups(),
+        printer,
+        options,
+        page.into(),
+    )
+    .await?;
+    Ok(job_created(&app_data, version, submitted))
+}
+
+/// Clear a printer's queue
+///
+/// Cancels every job waiting on or printing at the printer, whoever sent it,
+/// including jobs that didn't come through kprint.
+#[utoipa::path(
+    params(
+        ("printer" = String, Path, description = "Name of the printer, as configured in `KPRINT_PRINTERS`"),
+    ),
+    responses(
+        (status = 200, description = "The queue was cleared", body = QueuePurged),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+        (status = 404, description = "No printer with that name", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[delete("/admin/printers/{printer}/jobs")]
+pub async fn purge_queue(
+    printer: Path<String>,
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+) -> Result<Json<QueuePurged>, KprintError> {
+    let printer = printer.into_inner();
+    let canceled = service::purge_queue(&app_data, admin.username(), &printer).await?;
+    Ok(Json(QueuePurged { printer, canceled }))
+}
+
+/// Cancel anyone's job
+#[utoipa::path(
+    params(("id" = Uuid, Path, description = "kprint's id for the job")),
+    responses(
+        (status = 204, description = "The job was canceled"),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+        (status = 404, description = "No such job", body = ErrorBody),
+        (status = 409, description = "The job already finished, or was already canceled", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[delete("/admin/jobs/{id}")]
+pub async fn cancel_job(
+    id: Path<Uuid>,
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+) -> Result<HttpResponse, KprintError> {
+    service::cancel_any_job(&app_data, admin.username(), id.into_inner()).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Move a job to the front of the queue
+///
+/// For when something has to print now, like an event's materials stuck
+/// behind a long queue. Only jobs still waiting to print can be moved.
+#[utoipa::path(
+    params(("id" = Uuid, Path, description = "kprint's id for the job")),
+    responses(
+        (status = 204, description = "The job is at the front of the queue"),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+        (status = 404, description = "No such job", body = ErrorBody),
+        (status = 409, description = "The job is already printing, or done", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[post("/admin/jobs/{id}/bump")]
+pub async fn bump_job(
+    id: Path<Uuid>,
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+) -> Result<HttpResponse, KprintError> {
+    service::bump_job(&app_data, admin.username(), id.into_inner()).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Move a job to another printer
+///
+/// For when a printer jams or runs out of toner with jobs still queued on
+/// it, which can be moved to one nearby rather than sent again. Only jobs
+/// still waiting to print can be moved, and only between printers on CUPS.
+#[utoipa::path(
+    params(("id" = Uuid, Path, description = "kprint's id for the job")),
+    request_body = MoveJob,
+    responses(
+        (status = 204, description = "The job is on the other printer's queue"),
+        (status = 400, description = "One of the printers is a null printer", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+        (status = 404, description = "No such job, or no printer with that name", body = ErrorBody),
+        (status = 409, description = "The job is already printing, or done", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[post("/admin/jobs/{id}/move")]
+pub async fn move_job(
+    id: Path<Uuid>,
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+    Json(body): Json<MoveJob>,
+) -> Result<HttpResponse, KprintError> {
+    service::move_job(&app_data, admin.username(), id.into_inner(), &body.printer).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// List printer classes
+///
+/// The classes set up on CUPS, and which printers are in each, for seeing
+/// which printers CUPS shares jobs between.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Every class on CUPS", body = Vec<PrinterClass>),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[get("/admin/classes")]
+pub async fn list_classes(
+    app_data: Data<AppState>,
+    _admin: Admin,
+) -> Result<Json<Vec<PrinterClass>>, KprintError> {
+    Ok(Json(service::printer_classes(&app_data).await?))
+}
+
+/// Pause a printer
+///
+/// Jobs sent to a paused printer are turned away with `PRINTER_PAUSED` until
+/// it's resumed, and anything already queued waits.
+#[utoipa::path(
+    params(
+        ("printer" = String, Path, description = "Name of the printer, as configured in `KPRINT_PRINTERS`"),
+    ),
+    responses(
+        (status = 200, description = "The printer was paused", body = PrinterStatus),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+        (status = 404, description = "No printer with that name", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[post("/admin/printers/{printer}/pause")]
+pub async fn pause_printer(
+    printer: Path<String>,
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+) -> Result<Json<PrinterStatus>, KprintError> {
+    let status = service::set_paused(&app_data, admin.username(), &printer, Pause::Pause).await?;
+    Ok(Json(status))
+}
+
+/// Resume a paused printer
+#[utoipa::path(
+    params(
+        ("printer" = String, Path, description = "Name of the printer, as configured in `KPRINT_PRINTERS`"),
+    ),
+    responses(
+        (status = 200, description = "The printer was resumed", body = PrinterStatus),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+        (status = 404, description = "No printer with that name", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[post("/admin/printers/{printer}/resume")]
+pub async fn resume_printer(
+    printer: Path<String>,
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+) -> Result<Json<PrinterStatus>, KprintError> {
+    let status = service::set_paused(&app_data, admin.username(), &printer, Pause::Resume).await?;
+    Ok(Json(status))
+}
+
+/// Put a printer into maintenance
+///
+/// Until it's taken out again, jobs sent to the printer are turned away with
+/// `PRINTER_IN_MAINTENANCE` and the message, whatever CUPS thinks of it.
+/// Setting it again replaces the message.
+#[utoipa::path(
+    params(
+        ("printer" = String, Path, description = "Name of the printer, as configured in `KPRINT_PRINTERS`"),
+    ),
+    request_body = SetMaintenance,
+    responses(
+        (status = 200, description = "The printer is in maintenance", body = MaintenanceNotice),
+        (status = 400, description = "The message was empty", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+        (status = 404, description = "No printer with that name", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[put("/admin/printers/{printer}/maintenance")]
+pub async fn set_maintenance(
+    printer: Path<String>,
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+    Json(body): Json<SetMaintenance>,
+) -> Result<Json<MaintenanceNotice>, KprintError> {
+    let notice =
+        service::set_maintenance(&app_data, admin.username(), &printer, body.message).await?;
+    Ok(Json(notice))
+}
+
+/// Take a printer out of maintenance
+#[utoipa::path(
+    params(
+        ("printer" = String, Path, description = "Name of the printer, as configured in `KPRINT_PRINTERS`"),
+    ),
+    responses(
+        (status = 204, description = "The printer is out of maintenance, or already was"),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+        (status = 404, description = "No printer with that name", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[delete("/admin/printers/{printer}/maintenance")]
+pub async fn clear_maintenance(
+    printer: Path<String>,
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+) -> Result<HttpResponse, KprintError> {
+    service::clear_maintenance(&app_data, admin.username(), &printer).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Change what's logged
+///
+/// Replaces the `RUST_LOG` filter kprint started with until it's changed
+/// again or kprint restarts, for turning up one module's logging while
+/// something's going wrong without restarting and losing what's going on.
+/// The audit log is written whatever the filter says.
+#[utoipa::path(
+    request_body = SetLogLevel,
+    responses(
+        (status = 200, description = "Lines are being filtered the new way", body = LogLevel),
+        (status = 400, description = "The filter isn't in `RUST_LOG`'s syntax", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[put("/admin/log-level")]
+pub async fn set_log_level(
+    Admin(admin): Admin,
+    Json(body): Json<SetLogLevel>,
+) -> Result<Json<LogLevel>, KprintError> {
+    let previous = logging::set_filter(&body.filter).map_err(KprintError::InvalidRequest)?;
+    audit::record(AuditEvent::AdminSetLogLevel {
+        admin: admin.username(),
+        filter: &body.filter,
+        previous: &previous,
+    });
+    Ok(Json(LogLevel {
+        filter: body.filter,
+        previous,
+    }))
+}
+
+/// Show the configuration
+///
+/// Every `KPRINT_*` setting this instance is running with, where it came
+/// from, and what it's set to, with tokens, passwords, keys and the like
+/// masked. Settings kprint doesn't know about are included, since a
+/// misspelled one is often what's wrong.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Every setting, by name", body = Vec<ConfigSetting>),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[get("/admin/config")]
+pub async fn show_config(_admin: Admin) -> Json<Vec<ConfigSetting>> {
+    Json(config::effective())
+}
+
+/// List feature flags
+///
+/// Each of the newer parts of kprint that can be switched off, whether it's
+/// on, and whether that's down to its configuration or an admin.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Every flag", body = Vec<FeatureFlag>),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[get("/admin/flags")]
+pub async fn list_flags(app_data: Data<AppState>, _admin: Admin) -> Json<Vec<FeatureFlag>> {
+    Json(app_data.flags.all())
+}
+
+fn flag(name: &str) -> Result<Flag, KprintError> {
+    Flag::from_name(name).ok_or_else(|| KprintError::FeatureFlagNotFound(name.to_string()))
+}
+
+/// Turn a feature flag on or off
+///
+/// Overrides what `KPRINT_FLAG_*` says until it's cleared, on this instance
+/// only, and across restarts if `KPRINT_FLAGS_FILE` is set. Jobs already
+/// under way finish the way they started.
+#[utoipa::path(
+    params(
+        ("flag" = String, Path, description = "Name of the flag, like `spooled-uploads`"),
+    ),
+    request_body = SetFeatureFlag,
+    responses(
+        (status = 200, description = "The flag is on or off as asked", body = FeatureFlag),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+        (status = 404, description = "No flag with that name", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[put("/admin/flags/{flag}")]
+pub async fn set_flag(
+    name: Path<String>,
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+    Json(body): Json<SetFeatureFlag>,
+) -> Result<Json<FeatureFlag>, KprintError> {
+    let set = app_data
+        .flags
+        .set(flag(&name)?, body.enabled, admin.username())
+        .await
+        .map_err(KprintError::Database)?;
+    audit::record(AuditEvent::AdminSetFeatureFlag {
+        admin: admin.username(),
+        flag: &name,
+        enabled: Some(body.enabled),
+    });
+    Ok(Json(set))
+}
+
+/// Clear a feature flag's override
+///
+/// Puts the flag back to how it's configured.
+#[utoipa::path(
+    params(
+        ("flag" = String, Path, description = "Name of the flag, like `spooled-uploads`"),
+    ),
+    responses(
+        (status = 200, description = "The flag is as configured", body = FeatureFlag),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+        (status = 404, description = "No flag with that name", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[delete("/admin/flags/{flag}")]
+pub async fn clear_flag(
+    name: Path<String>,
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+) -> Result<Json<FeatureFlag>, KprintError> {
+    let cleared = app_data
+        .flags
+        .clear(flag(&name)?)
+        .await
+        .map_err(KprintError::Database)?;
+    audit::record(AuditEvent::AdminSetFeatureFlag {
+        admin: admin.username(),
+        flag: &name,
+        enabled: None,
+    });
+    Ok(Json(cleared))
+}
+
+/// Show mirroring
+///
+/// Which test printer jobs are mirrored to, and how many of them.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "How jobs are being mirrored", body = Mirroring),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[get("/admin/mirror")]
+pub async fn show_mirroring(app_data: Data<AppState>, _admin: Admin) -> Json<Mirroring> {
+    Json(app_data.mirror.status())
+}
+
+/// Change mirroring
+///
+/// Sends `percent` of jobs to the `KPRINT_MIRROR_PRINTER` test printer as
+/// well as their own, until kprint restarts, for seeing what it makes of
+/// them. Mirrored jobs only have their attributes checked with Validate-Job,
+/// unless `documents` is set, when they're printed on it too. What it says
+/// goes in the log under `kprint::mirror`, and never affects the real job.
+#[utoipa::path(
+    request_body = SetMirroring,
+    responses(
+        (status = 200, description = "Jobs are being mirrored as asked", body = Mirroring),
+        (status = 400, description = "There's no test printer, or `percent` is over 100", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[put("/admin/mirror")]
+pub async fn set_mirroring(
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+    Json(body): Json<SetMirroring>,
+) -> Result<Json<Mirroring>, KprintError> {
+    let (percent, documents) = (body.percent, body.documents);
+    let mirroring = app_data.mirror.set(body)?;
+    audit::record(AuditEvent::AdminSetMirroring {
+        admin: admin.username(),
+        percent,
+        documents,
+    });
+    Ok(Json(mirroring))
+}
+
+/// List suspended members
+///
+/// Everyone whose printing is suspended right now, soonest to be let back
+/// first, then those suspended until an admin lifts it.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Every suspension in effect", body = Vec<Suspension>),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[get("/admin/suspensions")]
+pub async fn list_suspensions(app_data: Data<AppState>, _admin: Admin) -> Json<Vec<Suspension>> {
+    Json(app_data.suspensions.list())
+}
+
+/// Suspend a member's printing
+///
+/// Until `until`, or until it's lifted, their jobs are turned away with
+/// `USER_SUSPENDED` and the reason, however they're sent. Their SSO account
+/// is left alone. Suspending them again replaces the reason and expiry.
+#[utoipa::path(
+    params(
+        ("username" = String, Path, description = "The member's CSH username"),
+    ),
+    request_body = SetSuspension,
+    responses(
+        (status = 200, description = "The member is suspended", body = Suspension),
+        (status = 400, description = "The reason was empty, or `until` has passed", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[put("/admin/users/{username}/suspension")]
+pub async fn suspend_user(
+    username: Path<String>,
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+    Json(body): Json<SetSuspension>,
+) -> Result<Json<Suspension>, KprintError> {
+    let suspension = service::suspend(&app_data, admin.username(), &username, body).await?;
+    Ok(Json(suspension))
+}
+
+/// Lift a member's suspension
+#[utoipa::path(
+    params(
+        ("username" = String, Path, description = "The member's CSH username"),
+    ),
+    responses(
+        (status = 204, description = "The member can print again, or already could"),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[delete("/admin/users/{username}/suspension")]
+pub async fn unsuspend_user(
+    username: Path<String>,
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+) -> Result<HttpResponse, KprintError> {
+    service::unsuspend(&app_data, admin.username(), &username).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// List guest codes
+///
+/// Every guest code that hasn't expired, soonest to expire first, including
+/// ones that have printed all their pages. The codes themselves aren't shown.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Every unexpired guest code", body = Vec<GuestCode>),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[get("/admin/guest-codes")]
+pub async fn list_guest_codes(app_data: Data<AppState>, _admin: Admin) -> Json<Vec<GuestCode>> {
+    Json(app_data.guests.list())
+}
+
+/// Make a guest code
+///
+/// Lets a visitor without an SSO account print up to `pages` pages until
+/// `expiresAt`, through the guest print endpoint. Their jobs are printed as
+/// the sponsor, so they show up in the sponsor's history. The code is only
+/// in this response, so hand it over now.
+#[utoipa::path(
+    request_body = CreateGuestCode,
+    responses(
+        (status = 201, description = "The new guest code", body = GuestCode),
+        (status = 400, description = "`pages` or `expiresAt` was out of range", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[post("/admin/guest-codes")]
+pub async fn create_guest_code(
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+    Json(body): Json<CreateGuestCode>,
+) -> Result<HttpResponse, KprintError> {
+    let code = service::create_guest_code(&app_data, admin.username(), body).await?;
+    Ok(HttpResponse::Created().json(code))
+}
+
+/// Revoke a guest code
+#[utoipa::path(
+    params(
+        ("id" = Uuid, Path, description = "The guest code's id"),
+    ),
+    responses(
+        (status = 204, description = "The code no longer works, if it ever did"),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[delete("/admin/guest-codes/{id}")]
+pub async fn revoke_guest_code(
+    id: Path<Uuid>,
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+) -> Result<HttpResponse, KprintError> {
+    service::revoke_guest_code(&app_data, admin.username(), *id).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Printer usage stats
+///
+/// Jobs and pages per day, the color and mono split, the busiest hours of the
+/// day, and how often jobs failed, for each printer, over the jobs submitted
+/// in the window. Counted from kprint's job history, so jobs from before it
+/// kept one aren't included.
+#[utoipa::path(
+    params(StatsQuery),
+    responses(
+        (status = 200, description = "Usage for every printer with jobs in the window", body = UsageStats),
+        (status = 400, description = "`from` or `to` isn't an RFC 3339 time", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[get("/admin/stats")]
+pub async fn stats(
+    app_data: Data<AppState>,
+    _admin: Admin,
+    Query(query): Query<StatsQuery>,
+) -> Json<UsageStats> {
+    Json(crate::stats::usage(&app_data.history, query))
+}
+
+/// Preview the usage report
+///
+/// The summary that's sent to admins every week or month when
+/// `KPRINT_REPORT_SCHEDULE` is set, over whatever window is asked for:
+/// total jobs and pages, failures, who printed the most, and which printers
+/// are running low on something right now.
+#[utoipa::path(
+    params(StatsQuery),
+    responses(
+        (status = 200, description = "The report for the window", body = UsageReport),
+        (status = 400, description = "`from` or `to` isn't an RFC 3339 time", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[get("/admin/report")]
+pub async fn report(
+    app_data: Data<AppState>,
+    _admin: Admin,
+    Query(query): Query<StatsQuery>,
+) -> Json<UsageReport> {
+    Json(crate::reports::report(&app_data, query).await)
+}
+
+/// Past quota periods
+///
+/// What everyone took out of their quota in each period that's over, newest
+/// first. Periods are archived as they end, so they're still here after
+/// everyone's quota has started again.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Every archived period", body = Vec<QuotaPeriod>),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[get("/admin/quota/periods")]
+pub async fn quota_periods(app_data: Data<AppState>, _admin: Admin) -> Json<Vec<QuotaPeriod>> {
+    Json(app_data.quotas.archived())
+}
+
+/// Export the job history
+///
+/// Every job submitted in the window, oldest first, as CSV with a header row
+/// or as a JSON array, with just the columns asked for. The export streams,
+/// so it's fine to ask for all of it.
+#[utoipa::path(
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "The history, as a file to download", content(
+            (String = "text/csv"),
+            (Vec<HistoryEntry> = "application/json"),
+        )),
+        (status = 400, description = "The format or some of the columns aren't known, listed under `details.fields`", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[get("/admin/history/export")]
+pub async fn export_history(
+    app_data: Data<AppState>,
+    Admin(admin): Admin,
+    Query(query): Query<ExportQuery>,
+) -> Result<HttpResponse, KprintError> {
+    let (format, columns) = query.validate().map_err(KprintError::Validation)?;
+    let entries = app_data.history.search(&query.terms(), |entry| {
+        query.from.is_none_or(|from| entry.submitted_at >= from)
+            && query.to.is_none_or(|to| entry.submitted_at < to)
+            && crate::stats::tagged(entry, query.tag.as_deref())
+    });
+    log::info!(
+        "{} exported {} jobs from the history",
+        Pii(admin.username()),
+        entries.len()
+    );
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "kprint-history.{}",
+                format.extension()
+            ))],
+        })
+        .streaming(export::rows(entries, format, columns)))
+}
+
+/// List the job history
+///
+/// Every job in kprint's history, whoever submitted it, newest first unless
+/// you ask otherwise, a page at a time. When there are more, the `Link`
+/// header has the next page.
+#[utoipa::path(
+    params(ListQuery),
+    responses(
+        (status = 200, description = "A page of the history", body = Vec<HistoryEntry>,
+            headers(("Link" = String, description = "The next page, when there is one"))),
+        (status = 400, description = "Some of the parameters were invalid, listed under `details.fields`", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Not a kprint admin", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "admin",
+)]
+#[get("/admin/history")]
+pub async fn list_history(
+    app_data: Data<AppState>,
+    _admin: Admin,
+    Query(query): Query<ListQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, KprintError> {
+    let listing = query.validate().map_err(KprintError::Validation)?;
+    let entries = app_data.history.search(&listing.search, |entry| {
+        listing
+            .user
+            .as_deref()
+            .is_none_or(|user| entry.owner == user)
+    });
+    let page = listing.page(entries, |entry| Key {
+        submitted_at: entry.submitted_at,
+        id: entry.id,
+        printer: &entry.printer,
+        title: &entry.title,
+        state: entry.state,
+        tags: &entry.tags,
+    });
+    Ok(paged(&req, page.next, page.items))
+}
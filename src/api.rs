@@ -1,246 +1,1510 @@
 use crate::app::AppState;
 use crate::auth::AuthenticatedUser;
+use crate::confirmations;
+use crate::digest::{self, Verification};
+use crate::envelope;
+use crate::error::{ErrorBody, ErrorCode, FieldError, KprintError};
+use crate::flags::Flag;
+use crate::ipp_server::printer_uri;
+use crate::job_links::{self, LinkQuery, SignedJob};
+use crate::jobs::job_url;
+use crate::listing::{self, Key, ListQuery};
+use crate::logging::Pii;
+use crate::openapi::Document;
+use crate::options::{self, PrintQuery};
+use crate::receipt::{self, ReceiptQuery};
+use crate::service::{self, Submitted};
+use crate::spool::{Upload, TUS_RESUMABLE, TUS_VERSION, UPLOAD_LENGTH, UPLOAD_OFFSET};
+use crate::version::ApiVersion;
 use actix_web::{
-    error::ErrorNotFound,
+    delete,
     error::PayloadError,
-    http::StatusCode,
-    post,
-    web::{Data, Json, Path, Payload, Query},
-    HttpResponse, Responder, ResponseError,
+    get,
+    http::{header, StatusCode},
+    patch, post, put, route,
+    web::{Bytes, Data, Json, Path, Payload, Query},
+    CustomizeResponder, HttpRequest, HttpResponse,
 };
-use futures::{StreamExt, TryStreamExt};
-use ipp::prelude::*;
+use futures::{channel::mpsc, SinkExt, StreamExt, TryStreamExt};
+use ipp::prelude::IppPayload;
 use itertools::Itertools;
-use serde::{Deserialize, Serialize};
-use std::{
-    fmt::{Display, Formatter},
-    num::ParseIntError,
-    str::FromStr,
+use kprint_client::models::{
+    BroadcastResult, DryRun, EnvelopeAddress, InlinePrint, JobCreated, JobLinks, JobReceipt,
+    JobState, JobStatus, JobSummary, Preferences, PrintFromUrl, PrintOptions, PrintPassword,
+    PrinterStatus, Quota, UploadLinks, UploadSession,
 };
-use tokio_util::compat::TokioAsyncReadCompatExt;
-use tokio_util::io::StreamReader;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
 
-#[derive(Serialize, Debug, Clone)]
-struct SuccessReply {
+/// What the legacy API replies with, kept as-is for old clients.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct SuccessReply {
     message: &'static str,
     job_link: Option<String>,
     job_id: Option<i32>,
 }
 
-#[derive(Debug, Clone)]
-pub struct ParseRangeError {
-    error: ParseIntError,
-    bad_range: String,
-}
-
-impl Display for ParseRangeError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "Range Parsing Error: {} in {}",
-            self.error, self.bad_range
-        )
+fn io_error(err: PayloadError) -> std::io::Error {
+    match err {
+        PayloadError::Incomplete(Some(err)) | PayloadError::Io(err) => err,
+        other => std::io::Error::other(other),
     }
 }
-impl std::error::Error for ParseRangeError {}
 
-#[derive(thiserror::Error, Debug)]
-pub enum KprintError {
-    #[error("an unspecified internal error occurred: {0}")]
-    InternalError(#[from] anyhow::Error),
-    #[error("Actix error: {0}")]
-    Actix(#[from] actix_web::error::Error),
-    #[error("Page range parse failure: {0}")]
-    PageRange(#[from] ParseRangeError),
+/// Feeds a request body into one of the [`service`] document channels, on a
+/// task of its own since the body can't leave this thread.
+pub fn forward_payload(payload: Payload, tx: mpsc::Sender<std::io::Result<Bytes>>) {
+    actix_web::rt::spawn(async move {
+        if let Err(err) = payload.map_err(io_error).map(Ok).forward(tx).await {
+            log::warn!("Hung up! Cancelling the reader! {err}");
+        }
+    });
 }
 
-impl ResponseError for KprintError {
-    fn status_code(&self) -> StatusCode {
-        match &self {
-            Self::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Self::Actix(err) => err.as_response_error().status_code(),
-            Self::PageRange(_) => StatusCode::PRECONDITION_FAILED,
+/// Like [`forward_payload`], but hashing the body as it goes, for `req`'s
+/// digest header to be checked against once it's all arrived. A body that
+/// doesn't match ends with an error instead, so whatever's reading it never
+/// mistakes it for the whole document.
+fn forward_verified(
+    req: &HttpRequest,
+    payload: Payload,
+    mut tx: mpsc::Sender<std::io::Result<Bytes>>,
+) -> Result<Verification, KprintError> {
+    let verification = Verification::new(digest::expected(req.headers())?);
+    let Some(expected) = verification.expected() else {
+        forward_payload(payload, tx);
+        return Ok(verification);
+    };
+    let mut hasher = expected.hasher();
+    let verifying = verification.clone();
+    actix_web::rt::spawn(async move {
+        let mut payload = payload;
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk.map_err(io_error);
+            let failed = match &chunk {
+                Ok(bytes) => {
+                    hasher.update(bytes);
+                    false
+                }
+                Err(_) => true,
+            };
+            if let Err(err) = tx.send(chunk).await {
+                log::warn!("Hung up! Cancelling the reader! {err}");
+                return;
+            }
+            if failed {
+                return;
+            }
         }
-    }
-
-    fn error_response(&self) -> HttpResponse {
-        if let Self::Actix(err) = self {
-            err.as_response_error().error_response()
-        } else {
-            HttpResponse::build(self.status_code()).body(self.to_string())
+        if !verifying.finish(hasher) {
+            let mismatch = std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "The document doesn't match its digest",
+            );
+            let _ = tx.send(Err(mismatch)).await;
         }
-    }
+    });
+    Ok(verification)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
-enum DuplexMode {
-    TwoSidedLongEdge,
-    TwoSidedShortEdge,
-    OneSided,
+/// Checks `options`, with whatever `username` left out filled in from their
+/// preferences.
+fn print_options(
+    app_data: &AppState,
+    username: &str,
+    options: PrintQuery,
+) -> Result<PrintOptions, KprintError> {
+    options
+        .or_preferences(&app_data.preferences.get(username))
+        .validate()
+        .map_err(KprintError::Validation)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
-enum ColorMode {
-    Grayscale,
-    Color,
+/// List the printers
+///
+/// Every configured printer and how it's doing. The printers are all asked at
+/// once, and any that don't answer within a few seconds are listed as
+/// `unreachable` rather than holding up the rest.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Every printer, alphabetically", body = Vec<PrinterStatus>),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printers",
+)]
+#[get("/printers")]
+pub async fn list_printers(
+    app_data: Data<AppState>,
+    _user: AuthenticatedUser,
+) -> Json<Vec<PrinterStatus>> {
+    Json(service::fleet_status(&app_data).await)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct PrintOptions {
-    // "sides": A keyword that specifies whether to do two sided printing. Values include 'one-sided', 'two-sided-long-edge' (typical 2-sided printing for portrait Documents), and 'two-sided-short-edge' (2-sided printing for landscape Documents).
-    sides: DuplexMode,
-    // "print-color-mode": A keyword specifying the color printing mode to use. The value 'color' specifies a full-color print, 'monochrome' specifies a grayscale print, and 'bi-level' specifies a black-and-white (no shades of gray) print.
-    color_mode: ColorMode,
-    pages: String,
-    copies: u32,
-    title: String,
+/// Print a document
+///
+/// The request body is the raw document, which is streamed straight through to
+/// the printer as it's uploaded. When kprint spools uploads, the whole document
+/// is written to disk first instead and the job is accepted before it's sent
+/// on, so the status says how that went.
+#[utoipa::path(
+    params(
+        ("printer" = String, Path, description = "Name of the printer, as configured in `KPRINT_PRINTERS`"),
+        ("Repr-Digest" = Option<String>, Header, description = "What the document hashes to, like `sha-256=:<base64>:`, to check it arrived intact. `Digest` works too"),
+        PrintQuery,
+    ),
+    request_body(content = Document, content_type = "application/octet-stream"),
+    responses(
+        (status = 201, description = "The job was accepted by the printer", body = JobCreated,
+            headers(("Location" = String, description = "The new job's status URL"))),
+        (status = 202, description = "The document was spooled, and is on its way to the printer", body = JobCreated,
+            headers(("Location" = String, description = "The new job's status URL"))),
+        (status = 400, description = "Some of the print options were invalid, listed under `details.fields`, or the document doesn't match its digest", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 404, description = "No printer with that name", body = ErrorBody),
+        (status = 415, description = "The document isn't in a format the printers can print", body = ErrorBody),
+        (status = 422, description = "The printer turned the document or options down, with its IPP status under `details.ippStatus`", body = ErrorBody),
+        (status = 502, description = "The printer couldn't be reached, or refused for reasons of its own", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[post("/printers/{printer}/print")]
+pub async fn print(
+    printer: Path<String>,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    Query(options): Query<PrintQuery>,
+    version: ApiVersion,
+    req: HttpRequest,
+    payload: Payload,
+) -> Result<CustomizeResponder<HttpResponse>, KprintError> {
+    let printer = printer.into_inner();
+    print_to(&app_data, &user, printer, options, version, &req, payload).await
 }
 
-fn parse_one_in_range(term: &str) -> Result<i32, ParseRangeError> {
-    term.parse().map_err(|error| ParseRangeError {
-        error,
-        bad_range: term.to_string(),
-    })
+/// Print a document to your printer
+///
+/// Just like printing to a printer by name, but to the one saved in your
+/// preferences, or CUPS's default printer if you haven't saved one.
+#[utoipa::path(
+    params(
+        ("Repr-Digest" = Option<String>, Header, description = "What the document hashes to, like `sha-256=:<base64>:`, to check it arrived intact. `Digest` works too"),
+        PrintQuery,
+    ),
+    request_body(content = Document, content_type = "application/octet-stream"),
+    responses(
+        (status = 201, description = "The job was accepted by the printer", body = JobCreated,
+            headers(("Location" = String, description = "The new job's status URL"))),
+        (status = 202, description = "The document was spooled, and is on its way to the printer", body = JobCreated,
+            headers(("Location" = String, description = "The new job's status URL"))),
+        (status = 400, description = "You haven't saved a printer and CUPS doesn't have a default, or some of the print options were invalid, listed under `details.fields`, or the document doesn't match its digest", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 404, description = "Your saved printer isn't around anymore", body = ErrorBody),
+        (status = 415, description = "The document isn't in a format the printers can print", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[post("/print")]
+pub async fn print_default(
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    Query(options): Query<PrintQuery>,
+    version: ApiVersion,
+    req: HttpRequest,
+    payload: Payload,
+) -> Result<CustomizeResponder<HttpResponse>, KprintError> {
+    let username = user.username();
+    let printer = match app_data.preferences.get(username).printer {
+        Some(printer) => printer,
+        None => service::default_printer(&app_data)
+            .await?
+            .ok_or_else(|| KprintError::Validation(vec![FieldError::missing("printer")]))?,
+    };
+    print_to(&app_data, &user, printer, options, version, &req, payload).await
+}
+
+async fn print_to(
+    app_data: &Data<AppState>,
+    user: &AuthenticatedUser,
+    printer: String,
+    options: PrintQuery,
+    version: ApiVersion,
+    req: &HttpRequest,
+    payload: Payload,
+) -> Result<CustomizeResponder<HttpResponse>, KprintError> {
+    let username = user.username();
+    log::debug!("Got a print request from {}", Pii(username));
+    let options = print_options(app_data, username, options)?;
+
+    let (tx, document) = service::document_channel(&app_data.streaming);
+    let verification = forward_verified(req, payload, tx)?;
+    let submitted = submit_upload(app_data, user, printer, options, version, document).await;
+    verification.respond(submitted)
 }
 
-fn parse_range(range: &str) -> Result<(i32, i32), ParseRangeError> {
-    let (start, end) = range.split_once('-').unwrap_or((range, range));
-    log::debug!("Parsing a range between {start:?} and {end:?}");
-    Ok((parse_one_in_range(start)?, parse_one_in_range(end)?))
+async fn submit_upload(
+    app_data: &Data<AppState>,
+    user: &AuthenticatedUser,
+    printer: String,
+    options: PrintOptions,
+    version: ApiVersion,
+    document: IppPayload,
+) -> Result<HttpResponse, KprintError> {
+    let (username, admin) = (user.username(), user.is_admin(&app_data.admin_groups));
+    let groups = user.groups();
+    let spool_uploads = app_data
+        .spool_uploads
+        .as_ref()
+        .filter(|_| app_data.flags.enabled(Flag::SpooledUploads));
+    if let Some(dir) = spool_uploads {
+        let document = crate::document::spool(document, dir).await?;
+        let submitted = service::submit_spooled(
+            app_data.clone(),
+            username,
+            admin,
+            groups,
+            printer,
+            options,
+            document,
+        )
+        .await?;
+        return Ok(job_spooled(app_data, version, submitted));
+    }
+    let submitted = service::submit(
+        app_data,
+        username,
+        admin,
+        groups,
+        printer,
+        options,
+        document.into(),
+    )
+    .await?;
+    Ok(job_created(app_data, version, submitted))
 }
 
+/// The header guests send their code in
+const GUEST_CODE: header::HeaderName = header::HeaderName::from_static("x-guest-code");
+
+/// Print a document as a guest
+///
+/// For visitors without an SSO account, who print with a code an admin made
+/// for them instead, sent in `X-Guest-Code`. The job is printed as the member
+/// who sponsored the code, tagged `guest`, and its pages come out of what the
+/// code has left. Only PDFs can be printed this way, since their pages have to
+/// be counted first.
+#[utoipa::path(
+    context_path = "/guest",
+    params(
+        ("printer" = String, Path, description = "Name of the printer, as configured in `KPRINT_PRINTERS`"),
+        ("X-Guest-Code" = String, Header, description = "The guest code"),
+        ("Repr-Digest" = Option<String>, Header, description = "What the document hashes to, like `sha-256=:<base64>:`, to check it arrived intact. `Digest` works too"),
+        PrintQuery,
+    ),
+    request_body(content = Document, content_type = "application/pdf"),
+    responses(
+        (status = 201, description = "The job was accepted by the printer", body = JobCreated,
+            headers(("Location" = String, description = "The new job's status URL"))),
+        (status = 400, description = "The document isn't a PDF, or some of the print options were invalid, listed under `details.fields`, or the document doesn't match its digest", body = ErrorBody),
+        (status = 401, description = "Missing, expired or revoked guest code", body = ErrorBody),
+        (status = 403, description = "The code doesn't have enough pages left", body = ErrorBody),
+        (status = 404, description = "No printer with that name", body = ErrorBody),
+        (status = 415, description = "The document isn't in a format the printers can print", body = ErrorBody),
+        (status = 422, description = "The printer wouldn't take the job", body = ErrorBody),
+    ),
+    security(()),
+    tag = "printing",
+)]
 #[post("/printers/{printer}/print")]
-pub async fn print(
+pub async fn print_as_guest(
     printer: Path<String>,
+    app_data: Data<AppState>,
+    Query(options): Query<PrintQuery>,
+    version: ApiVersion,
+    req: HttpRequest,
+    payload: Payload,
+) -> Result<CustomizeResponder<HttpResponse>, KprintError> {
+    let code = req
+        .headers()
+        .get(GUEST_CODE)
+        .and_then(|code| code.to_str().ok())
+        .ok_or(KprintError::GuestCodeInvalid)?;
+    // Guests don't have preferences to fall back on
+    let options = options.validate().map_err(KprintError::Validation)?;
+
+    let (tx, document) = service::document_channel(&app_data.streaming);
+    let verification = forward_verified(&req, payload, tx)?;
+    let submitted = service::submit_as_guest(
+        &app_data,
+        code,
+        printer.into_inner(),
+        options,
+        document.into(),
+    )
+    .await;
+    let created = submitted.map(|submitted| job_created(&app_data, version, submitted));
+    verification.respond(created)
+}
+
+/// Which printers a broadcast goes to.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BroadcastQuery {
+    /// Comma separated names of the printers, as configured in
+    /// `KPRINT_PRINTERS`
+    #[param(example = "lounge,l-floor,user-center")]
+    printers: Option<String>,
+}
+
+/// Print a document on several printers
+///
+/// Takes the same options and body as the print endpoint, and prints the
+/// document as a job of its own on every printer named in `printers`, like
+/// for putting an agenda on every floor. The document is spooled first, then
+/// sent to all of them at once. One printer turning the job away doesn't stop
+/// the rest, so each printer's result is listed, in the order they were
+/// named.
+#[utoipa::path(
+    params(
+        BroadcastQuery,
+        ("Repr-Digest" = Option<String>, Header, description = "What the document hashes to, like `sha-256=:<base64>:`, to check it arrived intact. `Digest` works too"),
+        PrintQuery,
+    ),
+    request_body(content = Document, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "How each printer took the job", body = Vec<BroadcastResult>),
+        (status = 400, description = "No printers were named, or some of the print options were invalid, listed under `details.fields`, or the document doesn't match its digest", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 415, description = "The document isn't in a format the printers can print", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[post("/print/broadcast")]
+pub async fn print_broadcast(
     app_data: Data<AppState>,
     user: AuthenticatedUser,
-    Query(options): Query<PrintOptions>,
+    Query(broadcast): Query<BroadcastQuery>,
+    Query(options): Query<PrintQuery>,
+    req: HttpRequest,
     payload: Payload,
-) -> Result<impl Responder, KprintError> {
-    log::debug!(
-        "Got a print request from {}",
-        user.claims.preferred_username().unwrap().as_str()
-    );
-    let printer = match app_data.printers.get(&*printer) {
-        Some(printer) => printer,
-        None => {
-            return Err(ErrorNotFound(format!("Printer named {printer} doesn't exist!")).into())
+) -> Result<CustomizeResponder<Json<Vec<BroadcastResult>>>, KprintError> {
+    let printers = broadcast
+        .printers
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|printer| !printer.is_empty())
+        .map(str::to_string)
+        // Named twice is still one job
+        .unique()
+        .collect::<Vec<_>>();
+    if printers.is_empty() {
+        return Err(KprintError::Validation(vec![FieldError::missing(
+            "printers",
+        )]));
+    }
+    let (username, admin) = (user.username(), user.is_admin(&app_data.admin_groups));
+    let options = print_options(&app_data, username, options)?;
+
+    let (tx, document) = service::document_channel(&app_data.streaming);
+    let verification = forward_verified(&req, payload, tx)?;
+    let dir = crate::spool::spool_dir().map_err(KprintError::SpoolIo)?;
+    // All of it's arrived once it's spooled, so this is as soon as a
+    // mismatch can be caught
+    let document = crate::document::spool(document, &dir).await;
+    verification.check()?;
+    let document = document?;
+    let results = service::broadcast(
+        &app_data,
+        username,
+        admin,
+        user.groups(),
+        printers,
+        options,
+        document,
+    )
+    .await?;
+    verification.respond(Ok(Json(
+        results
+            .into_iter()
+            .map(|(printer, submitted)| match submitted {
+                Ok(submitted) => BroadcastResult {
+                    printer,
+                    job: Some(created(&app_data, submitted)),
+                    error: None,
+                },
+                Err(err) => {
+                    log::info!("Broadcasting to {printer} failed: {err}");
+                    BroadcastResult {
+                        printer,
+                        job: None,
+                        error: Some(err.body()),
+                    }
+                }
+            })
+            .collect(),
+    )))
+}
+
+/// Check a job without printing it
+///
+/// Takes exactly what the print endpoint takes and runs it past the printer
+/// without printing anything. The document is optional; without one the
+/// options are still checked, but there's nothing to count pages of.
+#[utoipa::path(
+    params(
+        ("printer" = String, Path, description = "Name of the printer, as configured in `KPRINT_PRINTERS`"),
+        ("Repr-Digest" = Option<String>, Header, description = "What the document hashes to, like `sha-256=:<base64>:`, to check it arrived intact. `Digest` works too"),
+        PrintQuery,
+    ),
+    request_body(content = Option<Document>, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "The printer would take the job", body = DryRun),
+        (status = 400, description = "Some of the print options were invalid, listed under `details.fields`, or the document doesn't match its digest", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 404, description = "No printer with that name", body = ErrorBody),
+        (status = 415, description = "The document isn't in a format the printers can print", body = ErrorBody),
+        (status = 422, description = "The printer wouldn't take the job", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[post("/printers/{printer}/dry-run")]
+pub async fn dry_run(
+    printer: Path<String>,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    Query(options): Query<PrintQuery>,
+    req: HttpRequest,
+    payload: Payload,
+) -> Result<CustomizeResponder<Json<DryRun>>, KprintError> {
+    let username = user.username();
+    let options = print_options(&app_data, username, options)?;
+
+    let (tx, document) = service::document_channel(&app_data.streaming);
+    let verification = forward_verified(&req, payload, tx)?;
+
+    let printer = printer.into_inner();
+    let admin = user.is_admin(&app_data.admin_groups);
+    let dry_run = service::dry_run(
+        &app_data,
+        username,
+        admin,
+        user.groups(),
+        &printer,
+        options,
+        document,
+    )
+    .await;
+    verification.check()?;
+    let dry_run = dry_run?;
+    verification.respond(Ok(Json(DryRun {
+        printer,
+        document_pages: dry_run.document_pages,
+        impressions: dry_run.impressions,
+        sheets: dry_run.sheets,
+        overrides: dry_run.overrides,
+        quota_units: dry_run.quota_units,
+        page_ranges: options::written_pages(&dry_run.page_ranges),
+    })))
+}
+
+/// The reply to a successful print, in whichever shape `version` expects.
+pub fn job_created(app_data: &AppState, version: ApiVersion, submitted: Submitted) -> HttpResponse {
+    if version == ApiVersion::Legacy {
+        // The printer's own job-uri is useless to clients, who can neither reach
+        // nor authenticate to CUPS, so point them at kprint instead
+        let job_link = Some(job_links::signed_url(
+            app_data,
+            job_url(version, &submitted.job.id),
+            &submitted.job.id,
+        ));
+        return HttpResponse::Ok().json(SuccessReply {
+            message: "lmao",
+            job_link,
+            job_id: submitted.job.ipp_job_id,
+        });
+    }
+    let created = created(app_data, submitted);
+    HttpResponse::Created()
+        .insert_header((header::LOCATION, created.links.status.clone()))
+        .json(created)
+}
+
+fn created(
+    app_data: &AppState,
+    Submitted {
+        job,
+        state,
+        pages,
+        overrides,
+        quota_consumed,
+        page_ranges,
+        copies_to_follow,
+    }: Submitted,
+) -> JobCreated {
+    let pickup = app_data
+        .pickup
+        .as_ref()
+        .filter(|pickup| pickup.holds(&job.printer))
+        .map(|pickup| pickup.link(job.id));
+    JobCreated {
+        id: job.id,
+        ipp_job_id: job.ipp_job_id,
+        printer: job.printer,
+        state,
+        pages,
+        quota_consumed,
+        page_ranges: options::written_pages(&page_ranges),
+        overrides,
+        copies_to_follow,
+        submitted_at: job.submitted_at,
+        links: job_links::signed(app_data, JobLinks::for_job(&job.id), &job.id),
+        pickup,
+    }
+}
+
+/// Like [`job_created`], but `202 Accepted`, since the printer doesn't have
+/// the job yet.
+fn job_spooled(app_data: &AppState, version: ApiVersion, submitted: Submitted) -> HttpResponse {
+    let mut response = job_created(app_data, version, submitted);
+    if response.status() == StatusCode::CREATED {
+        *response.status_mut() = StatusCode::ACCEPTED;
+    }
+    response
+}
+
+/// Print a small document sent inline
+///
+/// The whole request is one JSON body, with the document in base64, which is
+/// easier than streaming for serverless functions and embedded clients. Bodies
+/// are capped at 2 MiB, so anything bigger should use the streaming endpoint.
+#[utoipa::path(
+    params(("printer" = String, Path, description = "Name of the printer, as configured in `KPRINT_PRINTERS`")),
+    request_body = InlinePrint,
+    responses(
+        (status = 201, description = "The job was accepted by the printer", body = JobCreated,
+            headers(("Location" = String, description = "The new job's status URL"))),
+        (status = 400, description = "Some of the print options or the document were invalid, listed under `details.fields`", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 404, description = "No printer with that name", body = ErrorBody),
+        (status = 415, description = "The document isn't in a format the printers can print", body = ErrorBody),
+        (status = 413, description = "The body is over 2 MiB", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[post("/printers/{printer}/print-inline")]
+pub async fn print_inline(
+    printer: Path<String>,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    version: ApiVersion,
+    Json(body): Json<InlinePrint>,
+) -> Result<HttpResponse, KprintError> {
+    use base64::Engine;
+    let username = user.username();
+    let options = PrintQuery {
+        sides: body.sides,
+        color_mode: body.color_mode,
+        pages: body.pages,
+        copies: body.copies.map(|copies| copies.to_string()),
+        title: body.title,
+        cover_sheet: body.cover_sheet.map(|cover_sheet| cover_sheet.to_string()),
+        allow_fallback: body
+            .allow_fallback
+            .map(|allow_fallback| allow_fallback.to_string()),
+        separate_jobs: body
+            .separate_jobs
+            .map(|separate_jobs| separate_jobs.to_string()),
+        tags: Some(body.tags.join(",")),
+        media: body
+            .media
+            .map(|media| serde_json::to_string(&media).expect("media always serializes")),
+        margins: body
+            .margins
+            .map(|margins| serde_json::to_string(&margins).expect("margins always serialize")),
+        offset: body
+            .offset
+            .map(|offset| serde_json::to_string(&offset).expect("offsets always serialize")),
+        poster: body.poster.map(|poster| poster.to_string()),
+        envelope: body.envelope,
+    }
+    .or_preferences(&app_data.preferences.get(username))
+    .validate();
+    let document = base64::engine::general_purpose::STANDARD
+        .decode(body.document_base64.trim())
+        .map_err(|err| {
+            FieldError::new(
+                "documentBase64",
+                ErrorCode::UnsupportedValue,
+                format!("The document isn't valid base64: {err}"),
+            )
+        });
+    let (options, document) = match (options, document) {
+        (Ok(options), Ok(document)) => (options, document),
+        (options, document) => {
+            let mut fields = options.err().unwrap_or_default();
+            fields.extend(document.err());
+            return Err(KprintError::Validation(fields));
         }
     };
 
-    // Empty string is the same as all pages
-    let mut page_ranges = if !options.pages.trim().is_empty() {
-        options
-            .pages
-            .split(',')
-            .map(|term| parse_range(term.trim()))
-            .filter(|entry| {
-                entry
-                    .as_ref()
-                    .map(|(start, end)| end > start)
-                    .unwrap_or(true)
+    let submitted = service::submit(
+        &app_data,
+        username,
+        user.is_admin(&app_data.admin_groups),
+        user.groups(),
+        printer.into_inner(),
+        options,
+        document.into(),
+    )
+    .await?;
+    Ok(job_created(&app_data, version, submitted))
+}
+
+/// Print a document from a URL
+///
+/// kprint downloads the document itself and streams it to the printer, so
+/// nothing has to be uploaded. Only `https` URLs on the hosts kprint is
+/// configured to trust are fetched, which by default are Google Drive and raw
+/// GitHub files. Documents the printers can reach themselves, like ones on
+/// shared storage, are fetched by the printer instead, if kprint's configured
+/// to let it.
+#[utoipa::path(
+    params(
+        ("printer" = String, Path, description = "Name of the printer, as configured in `KPRINT_PRINTERS`"),
+        PrintQuery,
+    ),
+    request_body = PrintFromUrl,
+    responses(
+        (status = 201, description = "The job was accepted by the printer", body = JobCreated,
+            headers(("Location" = String, description = "The new job's status URL"))),
+        (status = 400, description = "Some of the print options were invalid, or the URL isn't allowed or the printer can't fetch it, listed under `details.fields`", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 404, description = "No printer with that name", body = ErrorBody),
+        (status = 413, description = "The document is bigger than kprint will fetch", body = ErrorBody),
+        (status = 415, description = "The URL isn't a printable document", body = ErrorBody),
+        (status = 502, description = "The document couldn't be downloaded", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[post("/printers/{printer}/print-url")]
+pub async fn print_url(
+    printer: Path<String>,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    Query(options): Query<PrintQuery>,
+    version: ApiVersion,
+    Json(body): Json<PrintFromUrl>,
+) -> Result<HttpResponse, KprintError> {
+    let username = user.username();
+    let options = print_options(&app_data, username, options)?;
+    if let Some(url) = app_data.fetch.by_reference(&body.url) {
+        log::debug!("Having {printer} fetch {url} for {}", Pii(username));
+        let submitted = service::submit_uri(
+            &app_data,
+            username,
+            user.is_admin(&app_data.admin_groups),
+            user.groups(),
+            printer.into_inner(),
+            options,
+            &url,
+        )
+        .await?;
+        return Ok(job_created(&app_data, version, submitted));
+    }
+    let document = app_data.fetch.fetch(&body.url, &app_data.streaming).await?;
+    log::debug!("Printing {} for {}", body.url, Pii(username));
+    let submitted = service::submit(
+        &app_data,
+        username,
+        user.is_admin(&app_data.admin_groups),
+        user.groups(),
+        printer.into_inner(),
+        options,
+        document.into(),
+    )
+    .await?;
+    Ok(job_created(&app_data, version, submitted))
+}
+
+/// Lay out an envelope's addresses
+///
+/// A PDF the size of the envelope with the return address in the top left
+/// corner and the address it's going to in the middle, ready to print with
+/// the same `envelope` option. Lines are plain ASCII; anything else is left
+/// out.
+#[utoipa::path(
+    request_body = EnvelopeAddress,
+    responses(
+        (status = 200, description = "The envelope, laid out", content_type = "application/pdf", body = Vec<u8>),
+        (status = 400, description = "There's no address to send it to, or an address doesn't fit, listed under `details.fields`", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[post("/envelopes")]
+pub async fn lay_out_envelope(
+    _user: AuthenticatedUser,
+    Json(address): Json<EnvelopeAddress>,
+) -> Result<HttpResponse, KprintError> {
+    let fields = envelope::check(&address);
+    if !fields.is_empty() {
+        return Err(KprintError::Validation(fields));
+    }
+    let pdf = envelope::render(&address).map_err(KprintError::Conversion)?;
+    Ok(HttpResponse::Ok().content_type("application/pdf").body(pdf))
+}
+
+/// List your jobs
+///
+/// The jobs you've submitted through this kprint instance, newest first
+/// unless you ask otherwise, a page at a time. When there are more, the
+/// `Link` header has the next page.
+#[utoipa::path(
+    params(ListQuery),
+    responses(
+        (status = 200, description = "A page of your jobs", body = Vec<JobSummary>,
+            headers(("Link" = String, description = "The next page, when there is one"))),
+        (status = 400, description = "Some of the parameters were invalid, listed under `details.fields`", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Only admins can list someone else's jobs", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "jobs",
+)]
+#[get("/jobs")]
+pub async fn list_jobs(
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    Query(query): Query<ListQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, KprintError> {
+    let listing = query.validate().map_err(KprintError::Validation)?;
+    let username = match listing.user.as_deref() {
+        Some(other) if other != user.username() => {
+            if !user.is_admin(&app_data.admin_groups) {
+                return Err(KprintError::Forbidden);
+            }
+            other
+        }
+        _ => user.username(),
+    };
+    let page = listing.page(app_data.jobs.owned_by(username), |job| Key {
+        submitted_at: job.submitted_at,
+        id: job.id,
+        printer: &job.printer,
+        title: &job.title,
+        tags: &job.tags,
+        // Jobs still on their way to the printer aren't in the history yet
+        state: app_data
+            .history
+            .get(&job.id)
+            .map_or(JobState::Pending, |entry| entry.state),
+    });
+    Ok(paged(
+        &req,
+        page.next,
+        page.items
+            .into_iter()
+            .map(|job| {
+                let mut summary = JobSummary::from(job);
+                summary.links = job_links::signed(&app_data, summary.links, &summary.id);
+                summary
             })
-            .collect::<Result<Vec<(i32, i32)>, ParseRangeError>>()?
+            .collect(),
+    ))
+}
+
+/// Look up a job from its pickup link
+///
+/// For release stations, which scan the link's QR code rather than logging
+/// in. Works for as long as the link does, whoever's asking.
+#[utoipa::path(
+    context_path = "/pickup",
+    params(("token" = String, Path, description = "The `token` from the job's pickup link")),
+    responses(
+        (status = 200, description = "The job's current status", body = JobStatus),
+        (status = 401, description = "The link isn't one kprint made, or has expired", body = ErrorBody),
+        (status = 404, description = "kprint doesn't know about the job anymore", body = ErrorBody),
+    ),
+    security(()),
+    tag = "jobs",
+)]
+#[get("/{token}")]
+pub async fn pickup_job(
+    token: Path<String>,
+    app_data: Data<AppState>,
+) -> Result<Json<JobStatus>, KprintError> {
+    Ok(Json(service::pickup_status(&app_data, &token).await?))
+}
+
+/// Release a held job from its pickup link
+///
+/// Tells the printer to go ahead and print the job it's been holding, for
+/// the member standing at its release station.
+#[utoipa::path(
+    context_path = "/pickup",
+    params(("token" = String, Path, description = "The `token` from the job's pickup link")),
+    responses(
+        (status = 204, description = "The job is printing"),
+        (status = 401, description = "The link isn't one kprint made, or has expired", body = ErrorBody),
+        (status = 404, description = "kprint doesn't know about the job anymore", body = ErrorBody),
+        (status = 409, description = "The job isn't being held, so it's already printed or been canceled", body = ErrorBody),
+    ),
+    security(()),
+    tag = "jobs",
+)]
+#[post("/{token}/release")]
+pub async fn release_job(
+    token: Path<String>,
+    app_data: Data<AppState>,
+) -> Result<HttpResponse, KprintError> {
+    service::release_job(&app_data, &token).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// A page of a listing, linking to the page after `next` if there is one.
+pub fn paged<T: Serialize>(req: &HttpRequest, next: Option<String>, items: Vec<T>) -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+    if let Some(next) = next {
+        response.insert_header(listing::next_link(req, &next));
+    }
+    response.json(items)
+}
+
+/// How fresh a job's status has to be.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatusQuery {
+    /// Ask the printer now, rather than going by its queue as of a few
+    /// seconds ago. Only for admins
+    #[serde(default)]
+    fresh: bool,
+}
+
+/// Get a job's status
+///
+/// Asks the printer for the job's current attributes on the caller's behalf,
+/// since CUPS itself is out of reach for most clients. Jobs still waiting or
+/// printing are looked up on their printer's queue, which is only asked for
+/// every couple of seconds however many clients are polling it.
+#[utoipa::path(
+    params(("id" = Uuid, Path, description = "kprint's id for the job"), LinkQuery, StatusQuery),
+    responses(
+        (status = 200, description = "The job's current status", body = JobStatus),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Only admins can ask for a fresh status, or the link's signature is wrong or has expired", body = ErrorBody),
+        (status = 404, description = "No such job, or it isn't yours", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "jobs",
+)]
+#[get("/jobs/{id}")]
+pub async fn job_status(
+    SignedJob(id): SignedJob,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    Query(query): Query<StatusQuery>,
+) -> Result<Json<JobStatus>, KprintError> {
+    let username = user.username();
+    let status = if query.fresh {
+        if !user.is_admin(&app_data.admin_groups) {
+            return Err(KprintError::Forbidden);
+        }
+        service::fresh_job_status(&app_data, username, id).await?
     } else {
-        vec![]
+        service::job_status(&app_data, username, id).await?
     };
-    page_ranges.sort_by_key(|(start, _end)| *start);
-    let page_ranges = page_ranges
-        .into_iter()
-        .coalesce(|(prev_start, prev_end), (this_start, this_end)| {
-            if prev_end >= this_start {
-                // Merge the two ranges if they have overlap
-                Ok((prev_start, this_end))
-            } else {
-                Err(((prev_start, prev_end), (this_start, this_end)))
-            }
+    Ok(Json(status))
+}
+
+/// Get a preview of a job
+///
+/// A small PNG of the first page, for PDF jobs. Previews are rendered after
+/// the job is submitted, so one may not be ready straight away.
+#[utoipa::path(
+    params(("id" = Uuid, Path, description = "kprint's id for the job"), LinkQuery),
+    responses(
+        (status = 200, description = "The first page of the job", content_type = "image/png", body = Vec<u8>),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "The link's signature is wrong or has expired", body = ErrorBody),
+        (status = 404, description = "No such job, or it isn't yours, or there's no preview of it", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "jobs",
+)]
+#[get("/jobs/{id}/preview")]
+pub async fn job_preview(
+    SignedJob(id): SignedJob,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, KprintError> {
+    let username = user.username();
+    let preview = service::job_preview(&app_data, username, id)?;
+    Ok(HttpResponse::Ok()
+        .content_type("image/png")
+        .insert_header(header::CacheControl(vec![header::CacheDirective::Private]))
+        .body(preview))
+}
+
+/// Get a receipt for a job
+///
+/// Who sent the job, what it was, when, how many pages it came to and what
+/// it took out of their quota, for paperwork like getting paid back for
+/// printing done for a committee. It's a page of PDF unless JSON is asked
+/// for. Receipts come from the history, so there's one for every job long
+/// after the printer's forgotten it. When job links are signed, the job's
+/// `self` link's query string lets this through too.
+#[utoipa::path(
+    params(("id" = Uuid, Path, description = "kprint's id for the job"), LinkQuery, ReceiptQuery),
+    responses(
+        (status = 200, description = "The job's receipt", content(
+            (Vec<u8> = "application/pdf"),
+            (JobReceipt = "application/json"),
+        )),
+        (status = 400, description = "The format isn't known, listed under `details.fields`", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "The link's signature is wrong or has expired", body = ErrorBody),
+        (status = 404, description = "No such job, or it isn't yours", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "jobs",
+)]
+#[get("/jobs/{id}/receipt")]
+pub async fn job_receipt(
+    SignedJob(id): SignedJob,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    Query(query): Query<ReceiptQuery>,
+) -> Result<HttpResponse, KprintError> {
+    let format = query.validate().map_err(KprintError::Validation)?;
+    let username = user.username();
+    let receipt = service::job_receipt(&app_data, username, id)?;
+    if format == receipt::Format::Json {
+        return Ok(HttpResponse::Ok().json(receipt));
+    }
+    let pdf = receipt::render(&receipt).map_err(KprintError::Conversion)?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/pdf")
+        .insert_header(header::ContentDisposition {
+            disposition: header::DispositionType::Inline,
+            parameters: vec![header::DispositionParam::Filename(format!(
+                "kprint-receipt-{id}.pdf"
+            ))],
         })
-        .map(|(min, max)| IppValue::RangeOfInteger { min, max })
-        .map(|range| IppAttribute::new("page-ranges", range))
-        .collect::<Vec<_>>();
+        .insert_header(header::CacheControl(vec![header::CacheDirective::Private]))
+        .body(pdf))
+}
+
+/// Cancel a job
+#[utoipa::path(
+    params(("id" = Uuid, Path, description = "kprint's id for the job"), LinkQuery),
+    responses(
+        (status = 204, description = "The job was canceled"),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "The link's signature is wrong or has expired", body = ErrorBody),
+        (status = 404, description = "No such job, or it isn't yours", body = ErrorBody),
+        (status = 409, description = "The job already finished, or was already canceled", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "jobs",
+)]
+#[delete("/jobs/{id}")]
+pub async fn cancel_job(
+    SignedJob(id): SignedJob,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, KprintError> {
+    let username = user.username();
+    service::cancel_job(&app_data, username, id).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
 
-    log::debug!("Here's where we landed with panges: {page_ranges:?}");
+/// Open a job for several documents
+///
+/// For printing separate documents as one job, so they come out collated and
+/// stapled together. The printer makes the job straight away, with the print
+/// options given here, and then each document `POST`ed to its `documents`
+/// link is sent on to it in the order they arrive. `POST` to its `close` link
+/// once they're all there to print them. Jobs left 15 minutes without a new
+/// document are canceled.
+#[utoipa::path(
+    params(
+        ("printer" = String, Path, description = "Name of the printer, as configured in `KPRINT_PRINTERS`"),
+        PrintQuery,
+    ),
+    responses(
+        (status = 201, description = "The job is open for documents", body = JobCreated,
+            headers(("Location" = String, description = "The new job's status URL"))),
+        (status = 400, description = "Some of the print options were invalid, listed under `details.fields`", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 404, description = "No printer with that name", body = ErrorBody),
+        (status = 422, description = "The printer turned the options down, with its IPP status under `details.ippStatus`", body = ErrorBody),
+        (status = 502, description = "The printer couldn't be reached, or can't take jobs like this", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[post("/printers/{printer}/jobs")]
+pub async fn open_job(
+    printer: Path<String>,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    Query(options): Query<PrintQuery>,
+    version: ApiVersion,
+) -> Result<HttpResponse, KprintError> {
+    let username = user.username();
+    let options = print_options(&app_data, username, options)?;
+    let submitted = service::open_job(
+        &app_data,
+        username,
+        user.is_admin(&app_data.admin_groups),
+        printer.into_inner(),
+        options,
+    )
+    .await?;
+    if version == ApiVersion::Legacy {
+        return Ok(job_created(&app_data, version, submitted));
+    }
+    let mut created = created(&app_data, submitted);
+    created.links = job_links::signed(&app_data, JobLinks::for_open_job(&created.id), &created.id);
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, created.links.status.clone()))
+        .json(created))
+}
 
-    let (tx, rx) = futures::channel::mpsc::channel(1);
-    actix_web::rt::spawn(async move {
-        if let Err(err) = payload
-            .map_err(|err| match err {
-                PayloadError::Incomplete(Some(err)) | PayloadError::Io(err) => err,
-                other => std::io::Error::other(other),
-            })
-            .map(Ok)
-            .forward(tx)
-            .await
-        {
-            log::warn!("Hung up! Cancelling the reader! {err}");
-        }
-    });
+/// Add a document to an open job
+///
+/// The request body is the raw document, which is streamed straight through to
+/// the printer as it's uploaded. If it doesn't all get there, the whole job is
+/// canceled.
+#[utoipa::path(
+    params(
+        ("id" = Uuid, Path, description = "kprint's id for the job"),
+        LinkQuery,
+        ("Repr-Digest" = Option<String>, Header, description = "What the document hashes to, like `sha-256=:<base64>:`, to check it arrived intact. `Digest` works too"),
+    ),
+    request_body(content = Document, content_type = "application/octet-stream"),
+    responses(
+        (status = 204, description = "The document was added to the job"),
+        (status = 400, description = "The document doesn't match its digest", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "Your quota doesn't have enough left for it, or the link's signature is wrong or has expired", body = ErrorBody),
+        (status = 404, description = "No such job, or it isn't yours", body = ErrorBody),
+        (status = 409, description = "The job isn't open, because it was closed, canceled or left too long", body = ErrorBody),
+        (status = 415, description = "The document isn't in a format the printers can print", body = ErrorBody),
+        (status = 502, description = "The printer couldn't be reached", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "jobs",
+)]
+#[post("/jobs/{id}/documents")]
+pub async fn add_document(
+    SignedJob(id): SignedJob,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+    payload: Payload,
+) -> Result<CustomizeResponder<HttpResponse>, KprintError> {
+    let (tx, document) = service::document_channel(&app_data.streaming);
+    let verification = forward_verified(&req, payload, tx)?;
+    let added = service::add_document(
+        &app_data,
+        user.username(),
+        user.groups(),
+        id,
+        document.into(),
+    )
+    .await;
+    verification.respond(added.map(|_| HttpResponse::NoContent().finish()))
+}
 
-    let payload = IppPayload::new_async(StreamReader::new(rx).compat());
-
-    let operation = IppOperationBuilder::print_job(printer.uri().clone(), payload)
-        .user_name(user.claims.preferred_username().unwrap().as_str())
-        .job_title(options.title)
-        .attribute(IppAttribute::new(
-            "sides",
-            IppValue::Keyword(
-                serde_variant::to_variant_name(&options.sides)
-                    .unwrap()
-                    .to_string(),
-            ),
-        ))
-        .attribute(IppAttribute::new(
-            "print-color-mode",
-            IppValue::Keyword(
-                serde_variant::to_variant_name(&options.color_mode)
-                    .unwrap()
-                    .to_string(),
-            ),
-        ))
-        .attributes(page_ranges)
-        .attribute(IppAttribute::new(
-            "copies",
-            IppValue::Integer(options.copies as i32),
-        ))
-        .build();
-
-    log::debug!("Sending operation to printer!");
-    let response = printer.send(operation).await.map_err(anyhow::Error::from)?;
-    let attributes = response.attributes();
-    let job_link = attributes
-        .groups()
-        .iter()
-        .find_map(|group| group.attributes().get("job-uri"))
-        .and_then(|job_uri| {
-            job_uri
-                .value()
-                .as_uri()
-                .and_then(|uri| Uri::from_str(uri).ok())
-        })
-        .map(|job_uri| {
-            let mut parts = job_uri.into_parts();
-            parts.scheme = Some("https".parse().unwrap());
-            Uri::from_parts(parts).unwrap()
-        })
-        .map(|uri| uri.to_string());
-    let job_id = attributes
-        .groups()
-        .iter()
-        .find_map(|group| group.attributes().get("job-id"))
-        .and_then(|job_uri| job_uri.value().as_integer().copied());
-
-    log::debug!(
-        "Reply from print server! Header: {:?} Attributes {:?} Payload {:?}",
-        response.header(),
-        response.attributes(),
-        response.to_bytes()
-    );
-    Ok(Json(SuccessReply {
-        message: "lmao",
-        job_link,
-        job_id,
+/// Close an open job
+///
+/// Tells the printer the job has every document, so it prints them.
+#[utoipa::path(
+    params(("id" = Uuid, Path, description = "kprint's id for the job"), LinkQuery),
+    responses(
+        (status = 200, description = "The job's closed, and its status now", body = JobStatus),
+        (status = 400, description = "No documents have been added to the job", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "The link's signature is wrong or has expired", body = ErrorBody),
+        (status = 404, description = "No such job, or it isn't yours", body = ErrorBody),
+        (status = 409, description = "The job isn't open, because it was closed, canceled or left too long", body = ErrorBody),
+        (status = 502, description = "The printer couldn't be reached", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "jobs",
+)]
+#[post("/jobs/{id}/close")]
+pub async fn close_job(
+    SignedJob(id): SignedJob,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<Json<JobStatus>, KprintError> {
+    let status = service::close_job(&app_data, user.username(), id).await?;
+    Ok(Json(status))
+}
+
+/// Make a print password
+///
+/// Print dialogs can't log in with SSO, so adding kprint as a printer on your
+/// computer takes a password just for that. Making a new one replaces the old.
+#[utoipa::path(
+    responses(
+        (status = 201, description = "Your new print password", body = PrintPassword),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[post("/me/print-password")]
+pub async fn create_print_password(
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+) -> Result<HttpResponse, KprintError> {
+    let username = user.username().to_string();
+    let password = app_data
+        .print_passwords
+        .generate(&username)
+        .await
+        .map_err(KprintError::Database)?;
+    Ok(HttpResponse::Created().json(PrintPassword {
+        username,
+        password,
+        printer_uri: printer_uri(&req.connection_info(), "{printer}"),
     }))
 }
+
+/// Revoke your print password
+#[utoipa::path(
+    responses(
+        (status = 204, description = "The password no longer works, if there was one"),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[delete("/me/print-password")]
+pub async fn revoke_print_password(
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, KprintError> {
+    let username = user.username();
+    app_data
+        .print_passwords
+        .revoke(username)
+        .await
+        .map_err(KprintError::Database)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Get your printing preferences
+///
+/// The options used when you leave them out of a print request.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Your preferences, with anything you haven't set left out", body = Preferences),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[get("/me/preferences")]
+pub async fn get_preferences(
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+) -> Json<Preferences> {
+    let username = user.username();
+    Json(app_data.preferences.get(username))
+}
+
+/// Save your printing preferences
+///
+/// Replaces all of them, so anything left out is unset. With
+/// `confirmationEmail` on, the receipt for each job is emailed to the
+/// address on your token once it's finished, if kprint sends them.
+#[utoipa::path(
+    request_body = Preferences,
+    responses(
+        (status = 200, description = "Your preferences were saved", body = Preferences),
+        (status = 400, description = "There's no printer by that name, or confirmation emails can't be sent, listed under `details.fields`", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[put("/me/preferences")]
+pub async fn set_preferences(
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    Json(preferences): Json<Preferences>,
+) -> Result<Json<Preferences>, KprintError> {
+    let username = user.username();
+    if let Some(printer) = &preferences.printer {
+        if !app_data.printers.contains_key(printer) {
+            return Err(KprintError::Validation(vec![FieldError::new(
+                "printer",
+                ErrorCode::UnsupportedValue,
+                format!("There's no printer called {printer:?}"),
+            )]));
+        }
+    }
+    if preferences.confirmation_email == Some(true) {
+        let problem = match (&app_data.confirmations, confirmations::address(&user)) {
+            (None, _) => Some("kprint doesn't send confirmation emails"),
+            (_, None) => Some("Your token doesn't have an email address to send them to"),
+            _ => None,
+        };
+        if let Some(problem) = problem {
+            return Err(KprintError::Validation(vec![FieldError::new(
+                "confirmationEmail",
+                ErrorCode::UnsupportedValue,
+                problem,
+            )]));
+        }
+    }
+    app_data
+        .preferences
+        .set(username, preferences.clone())
+        .await
+        .map_err(KprintError::Database)?;
+    Ok(Json(preferences))
+}
+
+/// Get your printing quota
+///
+/// Which tier your groups put you in, and how many units you've used and
+/// have left this month or semester. Jobs are turned away once they'd take
+/// more than what's left.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Your quota this period", body = Quota),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 502, description = "The quota provider couldn't be reached", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[get("/quota")]
+pub async fn get_quota(
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<Json<Quota>, KprintError> {
+    let quota = app_data
+        .quotas
+        .quota(&app_data, user.username(), user.groups())
+        .await?;
+    Ok(Json(quota))
+}
+
+fn upload_session(upload: Upload) -> UploadSession {
+    UploadSession {
+        links: UploadLinks::for_upload(&upload.id),
+        id: upload.id,
+        printer: upload.printer,
+        length: upload.length,
+        offset: upload.offset,
+        expires_at: upload.expires_at,
+    }
+}
+
+/// Reads a numeric tus header, like `Upload-Offset`.
+fn byte_count(req: &HttpRequest, name: &header::HeaderName) -> Result<u64, KprintError> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or_else(|| KprintError::InvalidRequest(format!("{name} must be a number of bytes")))
+}
+
+/// Start a resumable upload
+///
+/// For documents too big to trust to one request. Say how big the document is
+/// in `Upload-Length`, then send it in chunks to the new upload, and print it
+/// once it's all there. The print options are checked now, so mistakes show up
+/// before anything is uploaded.
+#[utoipa::path(
+    params(
+        ("printer" = String, Path, description = "Name of the printer, as configured in `KPRINT_PRINTERS`"),
+        ("Upload-Length" = u64, Header, description = "Size of the whole document, in bytes"),
+        PrintQuery,
+    ),
+    responses(
+        (status = 201, description = "The upload is ready for its first chunk", body = UploadSession,
+            headers(("Location" = String, description = "Where to send the chunks"))),
+        (status = 400, description = "Some of the print options were invalid, listed under `details.fields`", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 404, description = "No printer with that name", body = ErrorBody),
+        (status = 413, description = "The document is bigger than kprint accepts", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[post("/printers/{printer}/uploads")]
+pub async fn create_upload(
+    printer: Path<String>,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    Query(options): Query<PrintQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, KprintError> {
+    let username = user.username();
+    let printer = printer.into_inner();
+    if !app_data.printers.contains_key(&printer) {
+        return Err(KprintError::PrinterNotFound(printer));
+    }
+    let options = print_options(&app_data, username, options)?;
+    let length = byte_count(&req, &UPLOAD_LENGTH)?;
+    let upload = app_data
+        .uploads
+        .create(username, printer, options, length)
+        .await?;
+    let session = upload_session(upload);
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, session.links.upload.clone()))
+        .insert_header((UPLOAD_OFFSET, session.offset))
+        .insert_header((TUS_RESUMABLE, TUS_VERSION))
+        .json(session))
+}
+
+/// Check on an upload
+///
+/// Also answers `HEAD`, with just the `Upload-Offset` and `Upload-Length`
+/// headers, which is how tus clients find where to resume from.
+#[utoipa::path(
+    get,
+    params(("id" = Uuid, Path, description = "kprint's id for the upload")),
+    responses(
+        (status = 200, description = "How far the upload has got", body = UploadSession,
+            headers(("Upload-Offset" = u64, description = "Where the next chunk starts"))),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 404, description = "No such upload, it expired, or it isn't yours", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[route("/uploads/{id}", method = "GET", method = "HEAD")]
+pub async fn upload_status(
+    id: Path<Uuid>,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, KprintError> {
+    let username = user.username();
+    let upload = app_data.uploads.get(username, id.into_inner())?;
+    Ok(HttpResponse::Ok()
+        .insert_header((UPLOAD_OFFSET, upload.offset))
+        .insert_header((UPLOAD_LENGTH, upload.length))
+        .insert_header((TUS_RESUMABLE, TUS_VERSION))
+        .insert_header(header::CacheControl(vec![header::CacheDirective::NoStore]))
+        .json(upload_session(upload)))
+}
+
+/// Send the next chunk of an upload
+///
+/// `Upload-Offset` has to match how much of the upload kprint already has. If
+/// the connection drops partway through, whatever arrived is kept, so ask for
+/// the offset again and carry on from there.
+#[utoipa::path(
+    params(
+        ("id" = Uuid, Path, description = "kprint's id for the upload"),
+        ("Upload-Offset" = u64, Header, description = "Where in the document this chunk starts"),
+    ),
+    request_body(content = Document, content_type = "application/offset+octet-stream"),
+    responses(
+        (status = 204, description = "The chunk was saved",
+            headers(("Upload-Offset" = u64, description = "Where the next chunk starts"))),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 404, description = "No such upload, it expired, or it isn't yours", body = ErrorBody),
+        (status = 409, description = "The chunk doesn't start where the upload left off", body = ErrorBody),
+        (status = 413, description = "The chunk goes past the end of the document", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[patch("/uploads/{id}")]
+pub async fn upload_chunk(
+    id: Path<Uuid>,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+    payload: Payload,
+) -> Result<HttpResponse, KprintError> {
+    let username = user.username();
+    let offset = byte_count(&req, &UPLOAD_OFFSET)?;
+    let upload = app_data
+        .uploads
+        .append(username, id.into_inner(), offset, payload.map_err(io_error))
+        .await?;
+    Ok(HttpResponse::NoContent()
+        .insert_header((UPLOAD_OFFSET, upload.offset))
+        .insert_header((TUS_RESUMABLE, TUS_VERSION))
+        .finish())
+}
+
+/// Print a finished upload
+#[utoipa::path(
+    params(("id" = Uuid, Path, description = "kprint's id for the upload")),
+    responses(
+        (status = 201, description = "The job was accepted by the printer", body = JobCreated,
+            headers(("Location" = String, description = "The new job's status URL"))),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 404, description = "No such upload, it expired, or it isn't yours", body = ErrorBody),
+        (status = 415, description = "The document isn't in a format the printers can print", body = ErrorBody),
+        (status = 409, description = "Some of the document hasn't arrived yet", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[post("/uploads/{id}/print")]
+pub async fn print_upload(
+    id: Path<Uuid>,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    version: ApiVersion,
+) -> Result<HttpResponse, KprintError> {
+    let username = user.username();
+    let (upload, file) = app_data.uploads.finish(username, id.into_inner()).await?;
+    let submitted = service::submit(
+        &app_data,
+        username,
+        user.is_admin(&app_data.admin_groups),
+        user.groups(),
+        upload.printer,
+        upload.options,
+        file.into_std().await.into(),
+    )
+    .await?;
+    Ok(job_created(&app_data, version, submitted))
+}
+
+/// Give up on an upload
+#[utoipa::path(
+    params(("id" = Uuid, Path, description = "kprint's id for the upload")),
+    responses(
+        (status = 204, description = "The upload and everything sent so far are gone"),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 404, description = "No such upload, it expired, or it isn't yours", body = ErrorBody),
+    ),
+    security(("csh_sso" = [])),
+    tag = "printing",
+)]
+#[delete("/uploads/{id}")]
+pub async fn delete_upload(
+    id: Path<Uuid>,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, KprintError> {
+    let username = user.username();
+    app_data.uploads.remove(username, id.into_inner()).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
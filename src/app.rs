@@ -1,37 +1,387 @@
-use crate::api::print;
-use actix_web::web::{self, scope};
+use crate::admin;
+use crate::api::{
+    add_document, cancel_job, close_job, create_print_password, create_upload, delete_upload,
+    dry_run, get_preferences, get_quota, job_preview, job_receipt, job_status, lay_out_envelope,
+    list_jobs, list_printers, open_job, pickup_job, print, print_as_guest, print_broadcast,
+    print_default, print_inline, print_upload, print_url, release_job, revoke_print_password,
+    set_preferences, upload_chunk, upload_status,
+};
+use crate::auth::CSHAuth;
+use crate::breaker::Breakers;
+use crate::build_info;
+use crate::clamav::Scanner;
+use crate::confirmations::Confirmations;
+use crate::copies::SeparateCopies;
+use crate::cover::CoverSheets;
+use crate::dialect::PrinterDialects;
+use crate::eligibility::Eligibility;
+use crate::envelope::EnvelopeTrays;
+use crate::error::KprintError;
+use crate::fallback::Fallback;
+use crate::fetch::FetchPolicy;
+use crate::flags::Flags;
+use crate::graphql;
+use crate::guests::GuestCodes;
+use crate::history::History;
+use crate::ipp_client::{self, IppClient, PrinterTimeouts};
+use crate::ipp_server;
+use crate::issuers::Issuers;
+use crate::job_links::JobLinkSigner;
+use crate::jobs::JobStore;
+use crate::maintenance::Maintenance;
+use crate::margins::PdfMargins;
+use crate::metrics;
+use crate::mirror::Mirror;
+use crate::null_printer::NullPrinters;
+use crate::openapi::ApiDoc;
+use crate::overrides::Overrides;
+use crate::packets::PacketStore;
+use crate::passwords::PrintPasswords;
+use crate::pickup::Pickup;
+use crate::preferences::PreferenceStore;
+use crate::preview::Previews;
+use crate::printer_cache::PrinterCache;
+use crate::queue_cache::QueueCache;
+use crate::quota::Quotas;
+use crate::recording::Recorder;
+use crate::retry::RetryPolicy;
+use crate::spool::{self, UploadStore};
+use crate::stamp::Stamps;
+use crate::streaming::StreamTuning;
+use crate::suspensions::Suspensions;
+use crate::timeout::{RequestTimeouts, Timeouts};
+use crate::version::{ApiVersion, ApiVersioning};
+use actix_files::{Files, NamedFile};
+use actix_web::{
+    dev::{fn_service, ServiceRequest, ServiceResponse},
+    web::{self, scope},
+};
+use anyhow::Context;
 use ipp::prelude::*;
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
 
-pub fn configure_app(cfg: &mut web::ServiceConfig) {
-    cfg.service(scope("/api").service(print));
+/// Every route, checking tokens with `issuers`, which are already discovered.
+pub fn configure_app(cfg: &mut web::ServiceConfig, issuers: Option<Issuers>) {
+    let auth = CSHAuth::new(issuers);
+    // Anything more specific than `/api` has to be registered first, otherwise
+    // the `/api` scope swallows its requests and 404s them
+    cfg.service(
+        // Guests don't have SSO accounts, so their codes are checked instead
+        scope("/api/v1/guest")
+            .wrap(ApiVersioning::new(ApiVersion::V1))
+            .configure(configure_guest),
+    )
+    // Release stations have the job's signed link instead
+    .service(
+        scope("/api/v1/pickup")
+            .wrap(ApiVersioning::new(ApiVersion::V1))
+            .service(pickup_job)
+            .service(release_job),
+    )
+    .service(
+        scope("/api/v1")
+            .wrap(Timeouts)
+            .wrap(auth.clone())
+            .wrap(ApiVersioning::new(ApiVersion::V1))
+            .configure(configure_api)
+            .configure(graphql::configure),
+    )
+    // Prometheus has no SSO account to scrape with, and it's only numbers
+    .service(metrics::metrics)
+    // Ahead of `/api`, since it's public too
+    .service(build_info::version)
+    // The docs are deliberately public so people can read them before logging in
+    .service(SwaggerUi::new("/api/docs/{_:.*}").url("/api/openapi.json", ApiDoc::openapi()))
+    .service(
+        scope("/api")
+            .wrap(Timeouts)
+            .wrap(auth)
+            .wrap(ApiVersioning::new(ApiVersion::Legacy))
+            .configure(configure_api),
+    )
+    // Print dialogs only speak Basic auth, which this checks for itself
+    .configure(ipp_server::configure);
+
+    // Lets small deployments serve the frontend without a web server in front.
+    // This has to come last, since it claims every path nothing else did
+    if let Ok(static_dir) = std::env::var("KPRINT_STATIC_DIR") {
+        cfg.service(frontend(static_dir.into()));
+    }
+}
+
+/// Serves the single page app, answering any path that isn't a real file with
+/// `index.html` so client-side routes survive a reload.
+fn frontend(static_dir: PathBuf) -> Files {
+    let index = static_dir.join("index.html");
+    Files::new("/", static_dir)
+        .index_file("index.html")
+        .default_handler(fn_service(move |req: ServiceRequest| {
+            let index = index.clone();
+            async move {
+                let (req, _payload) = req.into_parts();
+                let response = NamedFile::open_async(index).await?.into_response(&req);
+                Ok(ServiceResponse::new(req, response))
+            }
+        }))
+}
+
+fn configure_guest(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(
+        web::QueryConfig::default()
+            .error_handler(|err, _req| KprintError::InvalidRequest(err.to_string()).into()),
+    )
+    .service(print_as_guest);
+}
+
+fn configure_api(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(
+        web::QueryConfig::default()
+            .error_handler(|err, _req| KprintError::InvalidRequest(err.to_string()).into()),
+    )
+    .service(list_printers)
+    .service(print)
+    .service(print_default)
+    .service(print_broadcast)
+    .service(dry_run)
+    .service(print_inline)
+    .service(print_url)
+    .service(lay_out_envelope)
+    .service(create_upload)
+    .service(upload_status)
+    .service(upload_chunk)
+    .service(print_upload)
+    .service(delete_upload)
+    .service(list_jobs)
+    .service(job_status)
+    .service(job_preview)
+    .service(job_receipt)
+    .service(cancel_job)
+    .service(open_job)
+    .service(add_document)
+    .service(close_job)
+    .service(create_print_password)
+    .service(revoke_print_password)
+    .service(get_preferences)
+    .service(set_preferences)
+    .service(get_quota)
+    .service(admin::test_page)
+    .service(admin::purge_queue)
+    .service(admin::cancel_job)
+    .service(admin::bump_job)
+    .service(admin::move_job)
+    .service(admin::list_classes)
+    .service(admin::pause_printer)
+    .service(admin::resume_printer)
+    .service(admin::set_maintenance)
+    .service(admin::clear_maintenance)
+    .service(admin::set_log_level)
+    .service(admin::show_config)
+    .service(admin::list_flags)
+    .service(admin::set_flag)
+    .service(admin::clear_flag)
+    .service(admin::show_mirroring)
+    .service(admin::set_mirroring)
+    .service(admin::list_suspensions)
+    .service(admin::suspend_user)
+    .service(admin::unsuspend_user)
+    .service(admin::list_guest_codes)
+    .service(admin::create_guest_code)
+    .service(admin::revoke_guest_code)
+    .service(admin::stats)
+    .service(admin::report)
+    .service(admin::quota_periods)
+    .service(admin::export_history)
+    .service(admin::list_history);
 }
 
+/// Who's an admin when `KPRINT_ADMIN_GROUPS` doesn't say otherwise
+const DEFAULT_ADMIN_GROUPS: &str = "rtp";
+
 pub struct AppState {
-    pub printers: HashMap<String, AsyncIppClient>,
+    pub printers: HashMap<String, IppClient>,
+    /// What each printer last said about itself
+    pub printer_cache: PrinterCache,
+    /// What's on each printer's queue, as of a moment ago
+    pub queue_cache: QueueCache,
+    /// Printers jobs are being turned away from after too many failures
+    pub breakers: Breakers,
+    /// Jobs that can be sent to another printer if theirs fails them
+    pub fallback: Fallback,
+    /// Copies waiting to be printed as jobs of their own
+    pub copies: SeparateCopies,
+    /// Members of any of these groups are kprint admins
+    pub admin_groups: Vec<String>,
+    pub jobs: JobStore,
+    /// Jobs open for more documents to be added to
+    pub packets: PacketStore,
+    /// Every job sent on, for as long as kprint keeps a history
+    pub history: History,
+    pub maintenance: Maintenance,
+    /// Which of the newer parts of kprint are switched on
+    pub flags: Flags,
+    /// The test printer jobs are copied to, and how many
+    pub mirror: Mirror,
+    /// Members admins have stopped from printing for a while
+    pub suspensions: Suspensions,
+    /// Codes visitors can print with on a member's behalf
+    pub guests: GuestCodes,
+    pub print_passwords: PrintPasswords,
+    /// What each member wants when they leave options out
+    pub preferences: PreferenceStore,
+    pub fetch: FetchPolicy,
+    pub uploads: UploadStore,
+    pub streaming: StreamTuning,
+    /// How long requests that aren't uploads get to be answered
+    pub request_timeouts: RequestTimeouts,
+    /// Where uploads to the print endpoint are written in whole before
+    /// they're printed, when they are
+    pub spool_uploads: Option<PathBuf>,
+    /// How jobs that fail to send for now are tried again
+    pub retries: RetryPolicy,
+    /// Checks documents for viruses before they're printed, if clamd is set up
+    pub scanner: Option<Scanner>,
+    pub stamps: Stamps,
+    /// Printers that ignore margins and offsets, whose PDFs get them drawn in
+    pub pdf_margins: PdfMargins,
+    pub covers: CoverSheets,
+    pub envelope_trays: EnvelopeTrays,
+    /// Options printers force, whatever jobs ask for
+    pub overrides: Overrides,
+    /// How much each tier of member can print
+    pub quotas: Quotas,
+    /// Checks members are in good standing before they print, if set up
+    pub eligibility: Option<Eligibility>,
+    pub previews: Option<Previews>,
+    /// Signs links for picking up held jobs, if there's a release station
+    pub pickup: Option<Pickup>,
+    /// Signs links to jobs, if they have to be
+    pub job_links: Option<JobLinkSigner>,
+    /// Emails members about their finished jobs, if it's turned on
+    pub confirmations: Option<Confirmations>,
+}
+
+/// Where `printer` is on the CUPS server at `cups`, if that's somewhere
+/// kprint could send it jobs.
+fn printer_uri(cups: &str, printer: &str) -> anyhow::Result<Uri> {
+    if printer.contains(['/', '?', '#']) {
+        anyhow::bail!("{printer:?} can't be a printer name, since it'd change its URI");
+    }
+    let uri = Uri::try_from(format!("{cups}/printers/{printer}"))
+        .with_context(|| format!("KPRINT_CUPS_URL {cups:?} isn't a URI"))?;
+    if !matches!(uri.scheme_str(), Some("http" | "https" | "ipp" | "ipps")) {
+        anyhow::bail!("KPRINT_CUPS_URL {cups:?} has to be http, https, ipp or ipps");
+    }
+    if uri.host().unwrap_or_default().is_empty() {
+        anyhow::bail!("KPRINT_CUPS_URL {cups:?} doesn't say which host CUPS is on");
+    }
+    Ok(uri)
 }
 
 pub async fn get_app_data() -> anyhow::Result<AppState> {
-    let printers = std::env::var("KPRINT_PRINTERS")
-        .expect("No KPRINT_PRINTERS")
-        .to_string();
-    let printers = printers.split(' ');
+    let printers = std::env::var("KPRINT_PRINTERS").context("No KPRINT_PRINTERS")?;
+    let printers = printers.split_whitespace();
+    // Only null printers can do without one
     let cups = std::env::var("KPRINT_CUPS_URL")
-        .expect("No KPRINT_CUPS_URL")
-        .to_string();
+        .ok()
+        .map(|cups| cups.trim_end_matches('/').to_string());
 
-    let printers = printers
-        .map(|printer| {
-            let mut client_builder =
-                AsyncIppClient::builder(Uri::try_from(format!("{cups}/printers/{printer}"))?);
-            if let Ok(token) = std::env::var("KPRINT_CUPS_PROXY_TOKEN") {
-                client_builder = client_builder.http_header("Authorization", token);
-            } else {
-                log::warn!("No KPRINT_CUPS_PROXY_TOKEN environment variable was provided! Is your cups server secure?");
+    let token = std::env::var("KPRINT_CUPS_PROXY_TOKEN").ok();
+    if token.is_none() && cups.is_some() {
+        log::warn!("No KPRINT_CUPS_PROXY_TOKEN environment variable was provided! Is your cups server secure?");
+    }
+    let timeouts = PrinterTimeouts::from_env()?;
+    let dialects = PrinterDialects::from_env()?;
+    let recorder = Recorder::from_env()?.map(Arc::new);
+    let null_printers = NullPrinters::from_env()?;
+    // Shared by every printer that gives up connecting at the same time, so
+    // they all draw on the same connection pool
+    let mut clients = HashMap::<Duration, reqwest::Client>::new();
+    let mut client = |printer: &str| {
+        if let Some(name) = printer.strip_prefix("null:") {
+            let uri = Uri::try_from(format!("null://localhost/printers/{name}"))?;
+            let client = IppClient::null(uri, null_printers.printer(name)?);
+            return Ok((name.to_string(), client));
+        }
+        if crate::demo::enabled() {
+            crate::demo::check_printer(printer)?;
+        }
+        let Some(cups) = &cups else {
+            anyhow::bail!("No KPRINT_CUPS_URL, which {printer} needs to be reached through");
+        };
+        let uri = printer_uri(cups, printer)?;
+        let timeouts = timeouts.of(printer);
+        let http = match clients.get(&timeouts.connect) {
+            Some(http) => http.clone(),
+            None => {
+                let http = ipp_client::http_client(token.as_deref(), timeouts.connect)?;
+                clients.insert(timeouts.connect, http.clone());
+                http
             }
-            Ok((printer.to_string(), client_builder.build()))
-        })
-        .collect::<anyhow::Result<HashMap<String, AsyncIppClient>>>()?;
+        };
+        Ok((
+            printer.to_string(),
+            IppClient::new(uri, http, timeouts, dialects.of(printer), recorder.clone()),
+        ))
+    };
+    let printers = printers
+        .map(&mut client)
+        .collect::<anyhow::Result<HashMap<String, IppClient>>>()?;
+    let mirror = match std::env::var("KPRINT_MIRROR_PRINTER") {
+        Ok(printer) => Some(client(&printer).context("Bad KPRINT_MIRROR_PRINTER")?),
+        Err(_) => None,
+    };
+
+    let admin_groups = std::env::var("KPRINT_ADMIN_GROUPS")
+        .unwrap_or_else(|_| DEFAULT_ADMIN_GROUPS.to_string())
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+
+    let spool_uploads = match std::env::var("KPRINT_SPOOL_UPLOADS") {
+        Ok(spool) if spool.parse()? => Some(spool::spool_dir()?),
+        _ => None,
+    };
 
-    Ok(AppState { printers })
+    Ok(AppState {
+        printers,
+        admin_groups,
+        printer_cache: PrinterCache::from_env()?,
+        queue_cache: QueueCache::from_env()?,
+        breakers: Breakers::from_env()?,
+        fallback: Fallback::from_env()?,
+        copies: SeparateCopies::default(),
+        jobs: JobStore::default(),
+        packets: PacketStore::default(),
+        history: History::from_env()?,
+        maintenance: Maintenance::from_env()?,
+        flags: Flags::from_env()?,
+        mirror: Mirror::from_env(mirror)?,
+        suspensions: Suspensions::from_env()?,
+        guests: GuestCodes::from_env()?,
+        print_passwords: PrintPasswords::from_env()?,
+        preferences: PreferenceStore::from_env()?,
+        fetch: FetchPolicy::from_env()?,
+        uploads: UploadStore::from_env()?,
+        streaming: StreamTuning::from_env()?,
+        request_timeouts: RequestTimeouts::from_env()?,
+        spool_uploads,
+        retries: RetryPolicy::from_env()?,
+        scanner: Scanner::from_env()?,
+        stamps: Stamps::from_env()?,
+        pdf_margins: PdfMargins::from_env(),
+        covers: CoverSheets::from_env(),
+        envelope_trays: EnvelopeTrays::from_env()?,
+        overrides: Overrides::from_env()?,
+        quotas: Quotas::from_env()?,
+        eligibility: Eligibility::from_env()?,
+        previews: Previews::from_env()?,
+        pickup: Pickup::from_env()?,
+        job_links: JobLinkSigner::from_env()?,
+        confirmations: Confirmations::from_env()?,
+    })
 }
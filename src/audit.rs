@@ -0,0 +1,127 @@
+//! A record of security-relevant events, kept apart from the regular logs
+//! under the `kprint::audit` target so it can be routed and retained on its
+//! own.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum AuditEvent<'a> {
+    /// The virus scanner found something in a document, which wasn't printed
+    InfectedDocument {
+        username: &'a str,
+        printer: &'a str,
+        title: &'a str,
+        signature: &'a str,
+    },
+    /// The virus scanner couldn't be asked about a document, which wasn't
+    /// printed either
+    ScanFailed {
+        username: &'a str,
+        printer: &'a str,
+        title: &'a str,
+        error: &'a str,
+    },
+    /// An admin canceled someone's job
+    AdminCanceledJob {
+        admin: &'a str,
+        job: Uuid,
+        owner: &'a str,
+        printer: &'a str,
+    },
+    /// An admin moved someone's job to the front of the queue
+    AdminBumpedJob {
+        admin: &'a str,
+        job: Uuid,
+        owner: &'a str,
+        printer: &'a str,
+    },
+    /// An admin moved someone's job to another printer
+    AdminMovedJob {
+        admin: &'a str,
+        job: Uuid,
+        owner: &'a str,
+        from: &'a str,
+        to: &'a str,
+    },
+    /// An admin canceled everything queued on a printer
+    AdminPurgedQueue {
+        admin: &'a str,
+        printer: &'a str,
+        canceled: u32,
+    },
+    /// An admin paused a printer
+    AdminPausedPrinter { admin: &'a str, printer: &'a str },
+    /// An admin resumed a paused printer
+    AdminResumedPrinter { admin: &'a str, printer: &'a str },
+    /// An admin put a printer into maintenance
+    AdminSetMaintenance {
+        admin: &'a str,
+        printer: &'a str,
+        message: &'a str,
+    },
+    /// An admin took a printer out of maintenance
+    AdminClearedMaintenance { admin: &'a str, printer: &'a str },
+    /// An admin suspended someone's printing
+    AdminSuspendedUser {
+        admin: &'a str,
+        username: &'a str,
+        reason: &'a str,
+        until: Option<DateTime<Utc>>,
+    },
+    /// An admin lifted someone's suspension before it ran out
+    AdminUnsuspendedUser { admin: &'a str, username: &'a str },
+    /// An admin made a code for a guest to print with
+    AdminCreatedGuestCode {
+        admin: &'a str,
+        code: Uuid,
+        sponsor: &'a str,
+        pages: u32,
+        expires_at: DateTime<Utc>,
+    },
+    /// An admin revoked a guest code before it expired
+    AdminRevokedGuestCode { admin: &'a str, code: Uuid },
+    /// An admin changed which log lines are written
+    AdminSetLogLevel {
+        admin: &'a str,
+        filter: &'a str,
+        previous: &'a str,
+    },
+    /// An admin turned a feature flag on or off, or put it back to how it's
+    /// configured
+    AdminSetFeatureFlag {
+        admin: &'a str,
+        flag: &'a str,
+        enabled: Option<bool>,
+    },
+    /// An admin changed how many jobs are mirrored to the test printer
+    AdminSetMirroring {
+        admin: &'a str,
+        percent: u8,
+        documents: bool,
+    },
+    /// A held job was released at its printer's release station
+    JobReleased {
+        job: Uuid,
+        owner: &'a str,
+        printer: &'a str,
+    },
+    /// A guest printed with a code, as its sponsor
+    GuestPrinted {
+        code: Uuid,
+        sponsor: &'a str,
+        job: Uuid,
+        printer: &'a str,
+        pages: u32,
+    },
+}
+
+/// Writes `event` to the audit log, as one line of JSON.
+pub fn record(event: AuditEvent) {
+    match serde_json::to_string(&event) {
+        Ok(line) => log::warn!(target: "kprint::audit", "{line}"),
+        Err(err) => log::error!(target: "kprint::audit", "Couldn't record {event:?}: {err}"),
+    }
+}
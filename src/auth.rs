@@ -1,40 +1,36 @@
+use crate::app::AppState;
+use crate::error::KprintError;
+use crate::issuers::{self, Issuers};
 use actix_web::body::MessageBody;
-use futures::future::LocalBoxFuture;
-use futures::FutureExt;
+use futures::future::{ready, LocalBoxFuture, Ready};
 use openidconnect::AdditionalClaims;
-use std::rc::Rc;
-use std::{
-    str::FromStr,
-    task::{Context, Poll},
-};
+use std::task::{Context, Poll};
 use uuid::Uuid;
 
 use actix_web::{
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
-    FromRequest, HttpMessage, HttpResponse,
+    FromRequest, HttpMessage, ResponseError,
 };
 use openidconnect::{
     core::{
-        CoreClient, CoreGenderClaim, CoreJsonWebKeyType, CoreJweContentEncryptionAlgorithm,
-        CoreJwsSigningAlgorithm, CoreProviderMetadata,
+        CoreGenderClaim, CoreJsonWebKeyType, CoreJweContentEncryptionAlgorithm,
+        CoreJwsSigningAlgorithm,
     },
-    reqwest::async_http_client,
-    Audience, ClientId, IdToken, IdTokenClaims, IssuerUrl, Nonce, NonceVerifier,
+    IdToken, IdTokenClaims,
 };
 use serde::{Deserialize, Serialize};
-use tokio::sync::OnceCell;
 
+/// Checks requests have a good SSO token, with issuers that have already
+/// been discovered, so no request waits on SSO or finds it unreachable.
+#[derive(Clone)]
 pub struct CSHAuth {
-    client: Rc<OnceCell<CoreClient>>,
-    client_id: String,
+    /// Nothing for a demo, where everyone's the demo user
+    issuers: Option<Issuers>,
 }
 
 impl CSHAuth {
-    pub fn new(client_id: String) -> Self {
-        CSHAuth {
-            client_id,
-            client: Rc::new(OnceCell::const_new()),
-        }
+    pub fn new(issuers: Option<Issuers>) -> Self {
+        CSHAuth { issuers }
     }
 }
 
@@ -52,46 +48,28 @@ where
     type Error = actix_web::Error;
     type InitError = ();
     type Transform = CSHAuthService<S>;
-    type Future = LocalBoxFuture<'static, Result<Self::Transform, Self::InitError>>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        let client = get_client(self.client_id.clone(), self.client.clone());
-        Box::pin(client.map(|client| client.map(|client| CSHAuthService { service, client })))
+        ready(Ok(CSHAuthService {
+            service,
+            issuers: self.issuers.clone(),
+        }))
     }
 }
 
-async fn get_client(client_id: String, client: Rc<OnceCell<CoreClient>>) -> Result<CoreClient, ()> {
-    client
-        .get_or_try_init(|| async move {
-            let issuer_url = IssuerUrl::new("https://sso.csh.rit.edu/auth/realms/csh".to_string())
-                .expect("Failed to validate issuer URL");
-            let provider_metadata =
-                CoreProviderMetadata::discover_async(issuer_url, &async_http_client)
-                    .await
-                    .expect("Failed to get provider metadata");
-
-            // Set up the config for the GitLab OAuth2 process.
-            Ok(CoreClient::from_provider_metadata(
-                provider_metadata,
-                ClientId::new(client_id),
-                None,
-            ))
-        })
-        .await
-        .cloned()
-}
-
-// Please don't use this... I just don't know how computers work :(
-struct NullNonceVerifier;
-impl NonceVerifier for NullNonceVerifier {
-    fn verify(self, _nonce: Option<&Nonce>) -> Result<(), String> {
-        Ok(())
+/// The issuers every API checks tokens with, which demos go without.
+pub async fn issuers() -> anyhow::Result<Option<Issuers>> {
+    match crate::demo::enabled() {
+        true => Ok(None),
+        false => Ok(Some(Issuers::discover(issuers::from_env()?).await?)),
     }
 }
 
 pub struct CSHAuthService<S> {
     service: S,
-    client: CoreClient,
+    /// Nothing for a demo, where everyone's the demo user
+    issuers: Option<Issuers>,
 }
 
 impl<S, B> Service<ServiceRequest> for CSHAuthService<S>
@@ -111,39 +89,30 @@ where
     #[allow(unused_must_use)]
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let unauthorized = |req: ServiceRequest| -> Self::Future {
-            Box::pin(async { Ok(req.into_response(HttpResponse::Unauthorized().finish())) })
-        };
-
-        let token = match req.headers().get("Authorization").map(|x| x.to_str()) {
-            Some(Ok(x)) => x.trim_start_matches("Bearer ").to_string(),
-            _ => {
-                log::warn!("Authorization header didn't start with `Bearer`!");
-                return unauthorized(req);
-            }
+            Box::pin(async { Ok(req.into_response(KprintError::Unauthenticated.error_response())) })
         };
 
-        let token = match CshIdToken::from_str(&token) {
-            Ok(token) => token,
-            Err(err) => {
-                log::warn!("Token couldn't be parsed: {err}");
-                return unauthorized(req);
-            }
-        };
-        let verifier = self
-            .client
-            .id_token_verifier()
-            .set_other_audience_verifier_fn(|audience| {
-                audience == &Audience::new("account".to_owned())
-            });
-        let claims = match token.into_claims(&verifier, NullNonceVerifier) {
-            Ok(claims) => claims,
-            Err(err) => {
-                log::warn!("Couldn't verify token: {err}");
-                return unauthorized(req);
+        let user = match &self.issuers {
+            Some(issuers) => {
+                let token = match req.headers().get("Authorization").map(|x| x.to_str()) {
+                    Some(Ok(x)) => x.trim_start_matches("Bearer ").to_string(),
+                    _ => {
+                        log::warn!("Authorization header didn't start with `Bearer`!");
+                        return unauthorized(req);
+                    }
+                };
+                let Some(claims) = issuers.verify(&token) else {
+                    return unauthorized(req);
+                };
+                AuthenticatedUser { claims }
             }
+            None => crate::demo::user(),
         };
 
-        req.extensions_mut().insert(AuthenticatedUser { claims });
+        if let Some(app_data) = req.app_data::<actix_web::web::Data<AppState>>() {
+            crate::confirmations::remember(app_data, &user);
+        }
+        req.extensions_mut().insert(user);
 
         let future = self.service.call(req);
         Box::pin(async move {
@@ -155,7 +124,10 @@ where
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct CshClaims {
+    /// Service accounts from other realms can be in none
+    #[serde(default)]
     pub groups: Vec<String>,
+    #[serde(default)]
     pub uuid: Uuid,
 }
 
@@ -176,6 +148,49 @@ pub struct AuthenticatedUser {
     pub claims: CshIdTokenClaims,
 }
 
+impl AuthenticatedUser {
+    pub fn username(&self) -> &str {
+        self.claims.preferred_username().unwrap().as_str()
+    }
+
+    /// Every group they're in, according to SSO.
+    pub fn groups(&self) -> &[String] {
+        &self.claims.additional_claims().groups
+    }
+
+    /// Whether they're in any of `admin_groups`.
+    pub fn is_admin(&self, admin_groups: &[String]) -> bool {
+        self.groups()
+            .iter()
+            .any(|group| admin_groups.contains(group))
+    }
+}
+
+/// An [`AuthenticatedUser`] who's also in one of the admin groups.
+#[derive(Debug, Clone)]
+pub struct Admin(pub AuthenticatedUser);
+
+impl FromRequest for Admin {
+    type Error = actix_web::error::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        _payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let admin_groups = req
+            .app_data::<actix_web::web::Data<AppState>>()
+            .map(|app_data| app_data.admin_groups.clone())
+            .unwrap_or_default();
+        let result = match req.extensions().get::<AuthenticatedUser>() {
+            Some(user) if user.is_admin(&admin_groups) => Ok(Admin(user.clone())),
+            Some(_) => Err(KprintError::Forbidden.into()),
+            None => Err(KprintError::Unauthenticated.into()),
+        };
+        Box::pin(async { result })
+    }
+}
+
 impl FromRequest for AuthenticatedUser {
     type Error = actix_web::error::Error;
     type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
@@ -186,7 +201,7 @@ impl FromRequest for AuthenticatedUser {
     ) -> Self::Future {
         let result = match req.extensions().get::<Self>() {
             Some(user) => Ok(user.clone()),
-            None => Err(actix_web::error::ErrorUnauthorized("")),
+            None => Err(KprintError::Unauthenticated.into()),
         };
         Box::pin(async { result })
     }
@@ -0,0 +1,134 @@
+//! Quotas kept by a house economy service, rather than by kprint.
+//!
+//! Turned on by pointing `KPRINT_QUOTA_PROVIDER_URL` at the service, with
+//! `KPRINT_QUOTA_PROVIDER_TOKEN` sent as a bearer token when it's set. Before
+//! a job is sent kprint asks `GET {url}/members/{username}/balance`, which
+//! answers with something like
+//! `{"remaining": 120, "used": 80, "limit": 200, "tier": "actives",
+//! "resetsAt": "2026-11-01T04:00:00Z"}`, where a `null` or missing
+//! `remaining` means there's no limit. Once the printer has the job it's
+//! charged with `POST {url}/members/{username}/debits`, sending
+//! `{"jobId", "units", "printer", "title"}`. Until then the job's units are
+//! set aside by kprint, and taken off what the service says is remaining.
+//!
+//! Jobs kprint can't count the units of aren't charged, since the service is
+//! only told about a job once.
+
+use crate::app::AppState;
+use crate::jobs::JobRecord;
+use crate::quota::QuotaProvider;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use kprint_client::models::Quota;
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use reqwest::Url;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// How long the service gets to answer, since jobs wait on it
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct HttpProvider {
+    url: Url,
+    http: reqwest::Client,
+}
+
+/// What the service says a member has.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Balance {
+    remaining: Option<u32>,
+    #[serde(default)]
+    used: u32,
+    limit: Option<u32>,
+    tier: Option<String>,
+    resets_at: Option<DateTime<Utc>>,
+}
+
+impl HttpProvider {
+    /// Reads where the service is, or `None` if there isn't one.
+    pub fn from_env() -> Option<anyhow::Result<Self>> {
+        let url = std::env::var("KPRINT_QUOTA_PROVIDER_URL").ok()?;
+        Some((|| {
+            let url = Url::parse(&url)?;
+            if url.cannot_be_a_base() {
+                anyhow::bail!("KPRINT_QUOTA_PROVIDER_URL {url} can't have paths added to it");
+            }
+            let mut headers = HeaderMap::new();
+            if let Ok(token) = std::env::var("KPRINT_QUOTA_PROVIDER_TOKEN") {
+                let mut token = HeaderValue::from_str(&format!("Bearer {token}"))?;
+                token.set_sensitive(true);
+                headers.insert(header::AUTHORIZATION, token);
+            }
+            let http = reqwest::Client::builder()
+                .timeout(TIMEOUT)
+                .user_agent(concat!("kprint/", env!("CARGO_PKG_VERSION")))
+                .default_headers(headers)
+                .build()?;
+            Ok(HttpProvider { url, http })
+        })())
+    }
+
+    /// `{url}/members/{username}/{endpoint}`, with `username` escaped.
+    fn member_url(&self, username: &str, endpoint: &str) -> Url {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .expect("checked in from_env")
+            .pop_if_empty()
+            .extend(["members", username, endpoint]);
+        url
+    }
+}
+
+impl QuotaProvider for HttpProvider {
+    fn quota<'a>(
+        &'a self,
+        app_data: &'a AppState,
+        username: &'a str,
+        _groups: &'a [String],
+    ) -> BoxFuture<'a, anyhow::Result<Quota>> {
+        Box::pin(async move {
+            let body = self
+                .http
+                .get(self.member_url(username, "balance"))
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?;
+            let balance: Balance = serde_json::from_slice(&body)?;
+            let (period_start, period_end) = app_data.quotas.period(Utc::now());
+            Ok(Quota {
+                tier: balance.tier,
+                limit: balance.limit,
+                used: balance.used,
+                remaining: balance.remaining,
+                period_start,
+                period_end: balance.resets_at.unwrap_or(period_end),
+            })
+        })
+    }
+
+    fn debit<'a>(&'a self, job: &'a JobRecord, units: u32) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let debit = serde_json::json!({
+                "jobId": job.id,
+                "units": units,
+                "printer": job.printer,
+                "title": job.title,
+            });
+            self.http
+                .post(self.member_url(&job.owner, "debits"))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(serde_json::to_vec(&debit)?)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+
+    fn keeps_usage(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,136 @@
+//! Reads back a request `KPRINT_IPP_RECORD_DIR` recorded, and optionally sends
+//! it again, to see what a printer makes of it now.
+//!
+//! `ipp-replay <recording.json>` shows the request and what CUPS said back,
+//! attribute by attribute. `ipp-replay <recording.json> <printer-url>` sends
+//! it to another printer, like `http://localhost:631/printers/test`, with its
+//! `printer-uri` pointed there, and shows that printer's reply. It goes
+//! through the proxy in front of CUPS with `KPRINT_CUPS_PROXY_TOKEN`, if it's
+//! set. Documents cut off when they were recorded are sent cut off.
+//!
+//! Run with `cargo run --bin ipp-replay -- <recording.json> [printer-url]`.
+
+#[allow(dead_code)]
+#[path = "../recording.rs"]
+mod recording;
+
+use anyhow::Context;
+use ipp::parser::IppParser;
+use ipp::prelude::*;
+use ipp::reader::IppReader;
+use recording::Exchange;
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use std::io::Cursor;
+
+/// The parts of a recorded message, and whatever came after its attributes.
+fn parse(message: &[u8]) -> anyhow::Result<(IppHeader, IppAttributes, Vec<u8>)> {
+    let (header, attributes, reader) =
+        IppParser::new(IppReader::new(Cursor::new(message.to_vec())))
+            .parse_parts()
+            .context("That isn't IPP")?;
+    let rest = reader.into_inner();
+    let document = rest.get_ref()[rest.position() as usize..].to_vec();
+    Ok((header, attributes, document))
+}
+
+fn show(attributes: &IppAttributes) {
+    for group in attributes.groups() {
+        println!("  {:?}", group.tag());
+        let mut attributes = group.attributes().values().collect::<Vec<_>>();
+        attributes.sort_by_key(|attribute| attribute.name());
+        for attribute in attributes {
+            println!("    {} = {}", attribute.name(), attribute.value());
+        }
+    }
+}
+
+fn show_response(response: &[u8]) -> anyhow::Result<StatusCode> {
+    let (header, attributes, _) = parse(response)?;
+    let status = header.status_code();
+    println!("Response: {status:?}");
+    show(&attributes);
+    Ok(status)
+}
+
+/// Sends `request` to `url` as it was, apart from who it's for.
+async fn replay(
+    mut header: IppHeader,
+    mut attributes: IppAttributes,
+    document: Vec<u8>,
+    url: &str,
+) -> anyhow::Result<Vec<u8>> {
+    let uri = url.parse::<Uri>().context("That isn't a printer's URL")?;
+    for group in attributes.groups_mut() {
+        if group.tag() == DelimiterTag::OperationAttributes {
+            group.attributes_mut().insert(
+                IppAttribute::PRINTER_URI.to_string(),
+                IppAttribute::new(IppAttribute::PRINTER_URI, IppValue::Uri(uri.to_string())),
+            );
+        }
+    }
+    // A request of its own, as far as the printer's concerned
+    header.request_id = 1;
+    let mut body = header.to_bytes().to_vec();
+    body.extend_from_slice(&attributes.to_bytes());
+    body.extend_from_slice(&document);
+
+    let mut request = reqwest::Client::new()
+        .post(url)
+        .header(CONTENT_TYPE, "application/ipp")
+        .body(body);
+    if let Ok(token) = std::env::var("KPRINT_CUPS_PROXY_TOKEN") {
+        request = request.header(AUTHORIZATION, token);
+    }
+    let response = request.send().await?.error_for_status()?.bytes().await?;
+    Ok(response.to_vec())
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (Some(file), url, None) = (args.next(), args.next(), args.next()) else {
+        anyhow::bail!("Usage: ipp-replay <recording.json> [printer-url]");
+    };
+    let exchange: Exchange = serde_json::from_slice(&std::fs::read(&file)?)
+        .with_context(|| format!("{file} isn't a recording"))?;
+
+    println!(
+        "{} to {} at {}",
+        exchange.operation, exchange.uri, exchange.at
+    );
+    let (header, attributes, document) = parse(&exchange.request)?;
+    println!("Request:");
+    show(&attributes);
+    match exchange.truncated {
+        true => println!(
+            "  and the first {} bytes of a {} byte document",
+            document.len(),
+            exchange.request_length - (exchange.request.len() - document.len()) as u64
+        ),
+        false => println!("  and a {} byte document", document.len()),
+    }
+    let recorded = match (&exchange.response, &exchange.error) {
+        (Some(response), _) => Some(show_response(response)?),
+        (None, error) => {
+            println!(
+                "No response: {}",
+                error.as_deref().unwrap_or("none recorded")
+            );
+            None
+        }
+    };
+
+    let Some(url) = url else {
+        return Ok(());
+    };
+    println!();
+    println!("Sending it to {url}");
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let response = runtime.block_on(replay(header, attributes, document, &url))?;
+    let status = show_response(&response)?;
+    if let Some(recorded) = recorded.filter(|&recorded| recorded != status) {
+        println!("That's not what was recorded, which was {recorded:?}");
+    }
+    Ok(())
+}
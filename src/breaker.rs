@@ -0,0 +1,137 @@
+//! Turning jobs away from printers that keep failing, so people find out
+//! straight away instead of each waiting out the timeouts and retries.
+//!
+//! Once `KPRINT_BREAKER_THRESHOLD` jobs in a row couldn't get through to a
+//! printer, jobs sent to it fail fast for `KPRINT_BREAKER_COOLDOWN` seconds.
+//! After that they're let through again, and the first to get through closes
+//! the breaker, while another failure starts a new cooldown.
+
+use crate::error::KprintError;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const DEFAULT_THRESHOLD: u32 = 5;
+const DEFAULT_COOLDOWN: Duration = Duration::seconds(60);
+
+/// Whether a printer's breaker is letting jobs through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed,
+    /// Jobs are turned away until the cooldown's over
+    Open,
+    /// The cooldown's over, and the next job decides whether it closes or
+    /// opens again
+    HalfOpen,
+}
+
+impl BreakerState {
+    pub const ALL: [BreakerState; 3] = [
+        BreakerState::Closed,
+        BreakerState::Open,
+        BreakerState::HalfOpen,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half_open",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Breaker {
+    /// Jobs in a row that didn't get through
+    failures: u32,
+    /// When jobs are let through again, once it's tripped
+    open_until: Option<DateTime<Utc>>,
+}
+
+pub struct Breakers {
+    threshold: u32,
+    cooldown: Duration,
+    printers: Mutex<HashMap<String, Breaker>>,
+}
+
+impl Breakers {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let threshold = match std::env::var("KPRINT_BREAKER_THRESHOLD") {
+            Ok(threshold) => threshold.parse()?,
+            Err(_) => DEFAULT_THRESHOLD,
+        };
+        let cooldown = match std::env::var("KPRINT_BREAKER_COOLDOWN") {
+            Ok(seconds) => Duration::seconds(seconds.parse()?),
+            Err(_) => DEFAULT_COOLDOWN,
+        };
+        Ok(Breakers {
+            threshold,
+            cooldown,
+            printers: Mutex::default(),
+        })
+    }
+
+    /// Until when jobs for `printer` are being turned away, if they are.
+    pub fn open_until(&self, printer: &str) -> Option<DateTime<Utc>> {
+        let printers = self.printers.lock().unwrap();
+        printers
+            .get(printer)
+            .and_then(|breaker| breaker.open_until)
+            .filter(|until| *until > Utc::now())
+    }
+
+    pub fn state(&self, printer: &str) -> BreakerState {
+        let printers = self.printers.lock().unwrap();
+        match printers.get(printer).and_then(|breaker| breaker.open_until) {
+            Some(until) if until > Utc::now() => BreakerState::Open,
+            Some(_) => BreakerState::HalfOpen,
+            None => BreakerState::Closed,
+        }
+    }
+
+    /// Fails if jobs for `printer` are being turned away.
+    pub fn check(&self, printer: &str) -> Result<(), KprintError> {
+        match self.open_until(printer) {
+            Some(retry_at) => Err(KprintError::PrinterUnhealthy {
+                printer: printer.to_string(),
+                retry_at,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Notes that a job got through to `printer`.
+    pub fn succeeded(&self, printer: &str) {
+        let mut printers = self.printers.lock().unwrap();
+        if let Some(breaker) = printers.remove(printer) {
+            if breaker.open_until.is_some() {
+                log::info!("{printer} is taking jobs again");
+            }
+        }
+    }
+
+    /// Turns jobs away from `printer` for a cooldown straight away, for when
+    /// it's known to be down before any job has tried it.
+    pub fn trip(&self, printer: &str) {
+        let mut printers = self.printers.lock().unwrap();
+        let breaker = printers.entry(printer.to_string()).or_default();
+        breaker.open_until = Some(Utc::now() + self.cooldown);
+    }
+
+    /// Notes that a job couldn't get through to `printer`, tripping its
+    /// breaker if that's too many in a row.
+    pub fn failed(&self, printer: &str) {
+        let mut printers = self.printers.lock().unwrap();
+        let breaker = printers.entry(printer.to_string()).or_default();
+        breaker.failures += 1;
+        if breaker.failures >= self.threshold {
+            let until = Utc::now() + self.cooldown;
+            log::warn!(
+                "{} jobs in a row failed on {printer}, turning jobs away until {until}",
+                breaker.failures
+            );
+            breaker.open_until = Some(until);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! What this build of kprint is, so the frontend can tell when it's talking
+//! to a server it wasn't built for, and whoever's deploying can tell when
+//! the new one's up.
+
+use crate::version::ApiVersion;
+use actix_web::{get, web::Json};
+use chrono::DateTime;
+use kprint_client::models::BuildInfo;
+
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_sha: option_env!("KPRINT_GIT_SHA").map(str::to_string),
+        built_at: env!("KPRINT_BUILT_AT")
+            .parse()
+            .ok()
+            .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+            .unwrap_or_default(),
+        features: env!("KPRINT_FEATURES")
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .map(str::to_string)
+            .collect(),
+        api_versions: ApiVersion::SUPPORTED
+            .iter()
+            .map(ApiVersion::number)
+            .collect(),
+    }
+}
+
+/// Which version of kprint this is and what it was built from. Public, like
+/// the docs, so deploys can be checked on without logging in, and outside
+/// the versioned API, since it's what clients check before picking a version.
+#[get("/api/version")]
+pub async fn version() -> Json<BuildInfo> {
+    Json(build_info())
+}
@@ -0,0 +1,121 @@
+//! Virus scanning with clamd, for the few documents that would rather not be
+//! printed.
+//!
+//! Turned on by pointing `KPRINT_CLAMD_ADDRESS` at clamd, either as
+//! `host:port` or as the path to its Unix socket. Documents are kept on disk
+//! while they're scanned, since there's no taking back what's already gone to
+//! the printer, so clamd's `StreamMaxLength` needs to be at least as big as
+//! the biggest document people print.
+
+use crate::document::Document;
+use crate::error::KprintError;
+use futures::AsyncReadExt;
+use ipp::prelude::IppPayload;
+use std::path::PathBuf;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use uuid::Uuid;
+
+/// How much of the document goes to clamd at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// What clamd made of a document.
+enum Verdict {
+    Clean,
+    Infected(String),
+}
+
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+pub struct Scanner {
+    address: String,
+    spool: PathBuf,
+}
+
+impl Scanner {
+    /// The scanner `KPRINT_CLAMD_ADDRESS` points at, or `None` if scanning is
+    /// turned off.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(address) = std::env::var("KPRINT_CLAMD_ADDRESS") else {
+            return Ok(None);
+        };
+        Ok(Some(Scanner {
+            address,
+            spool: crate::spool::spool_dir()?,
+        }))
+    }
+
+    async fn connect(&self) -> std::io::Result<Box<dyn Connection>> {
+        if self.address.starts_with('/') {
+            Ok(Box::new(
+                tokio::net::UnixStream::connect(&self.address).await?,
+            ))
+        } else {
+            Ok(Box::new(
+                tokio::net::TcpStream::connect(&self.address).await?,
+            ))
+        }
+    }
+
+    /// Streams `document` to clamd with `INSTREAM`, keeping a copy at `copy`.
+    async fn instream(
+        &self,
+        mut document: IppPayload,
+        copy: &mut tokio::fs::File,
+    ) -> std::io::Result<Verdict> {
+        let mut clamd = self.connect().await?;
+        clamd.write_all(b"zINSTREAM\0").await?;
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            let read = document.read(&mut buffer).await?;
+            // Each chunk is prefixed with its length, and a zero length ends it
+            clamd.write_all(&(read as u32).to_be_bytes()).await?;
+            if read == 0 {
+                break;
+            }
+            clamd.write_all(&buffer[..read]).await?;
+            copy.write_all(&buffer[..read]).await?;
+        }
+        copy.flush().await?;
+
+        // Replies to z-prefixed commands end in a NUL, rather than a newline
+        let mut reply = vec![];
+        BufReader::new(clamd).read_until(b'\0', &mut reply).await?;
+        let reply = String::from_utf8_lossy(&reply);
+        let reply = reply.trim_end_matches('\0').trim();
+        match reply.strip_prefix("stream: ") {
+            Some("OK") => Ok(Verdict::Clean),
+            Some(found) if found.ends_with(" FOUND") => Ok(Verdict::Infected(
+                found.trim_end_matches(" FOUND").to_string(),
+            )),
+            _ => Err(std::io::Error::other(format!("clamd said {reply:?}"))),
+        }
+    }
+
+    /// Scans `document`, handing back an identical one to print if it's clean.
+    /// Documents that can't be scanned aren't printed either.
+    pub async fn scan(&self, mut document: Document) -> Result<Document, KprintError> {
+        let path = self.spool.join(format!("scan-{}", Uuid::new_v4()));
+        let mut copy = tokio::fs::File::create(&path)
+            .await
+            .map_err(KprintError::SpoolIo)?;
+        let verdict = self.instream(document.payload(), &mut copy).await;
+        drop(copy);
+
+        let opened = match verdict {
+            Ok(Verdict::Clean) => tokio::fs::File::open(&path).await,
+            Ok(Verdict::Infected(signature)) => {
+                let _ = tokio::fs::remove_file(&path).await;
+                return Err(KprintError::DocumentInfected(signature));
+            }
+            Err(err) => {
+                let _ = tokio::fs::remove_file(&path).await;
+                return Err(KprintError::ScannerUnavailable(err.to_string()));
+            }
+        };
+        // Already open, if it's clean, so it can be printed after this
+        let _ = tokio::fs::remove_file(&path).await;
+        let file = opened.map_err(KprintError::SpoolIo)?;
+        Ok(file.into_std().await.into())
+    }
+}
@@ -0,0 +1,352 @@
+//! Where kprint's settings come from.
+//!
+//! Everything in kprint reads its settings from `KPRINT_*` environment
+//! variables, so the other layers are put in terms of those: a TOML file
+//! named by `--config` fills in whatever the environment leaves out, and
+//! arguments on the command line override both. Keys in the file are the
+//! variables' names without the `KPRINT_`, in lowercase, with tables adding
+//! their name in front, so
+//!
+//! ```toml
+//! cups_url = "https://cups.csh.rit.edu"
+//! printers = ["hulk", "thor"]
+//!
+//! [breaker]
+//! threshold = 3
+//! ```
+//!
+//! sets `KPRINT_CUPS_URL`, `KPRINT_PRINTERS=hulk thor` and
+//! `KPRINT_BREAKER_THRESHOLD`.
+//!
+//! Files can have profiles too, for the same kprint run somewhere else, each
+//! a table under `profiles` that's laid out like the rest of the file:
+//!
+//! ```toml
+//! [profiles.staging]
+//! cups_url = "https://cups-staging.csh.rit.edu"
+//! printers = ["null:lounge"]
+//! ```
+//!
+//! `KPRINT_ENV`, or `--env`, picks one, whose settings go over the rest of
+//! the file's. A file with profiles has to have one picked, so nothing runs
+//! with the printers meant for somewhere else just because it was left out.
+
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand};
+use kprint_client::models::{ConfigSetting, ConfigSource};
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// Where the REST API, and everything else served over HTTP, listens when
+/// `KPRINT_PORT` doesn't say otherwise
+const DEFAULT_PORT: u16 = 8080;
+
+/// Everything in this prefix is a setting
+const PREFIX: &str = "KPRINT";
+
+/// Prints to CSH printers through CUPS
+#[derive(Parser)]
+#[command(version)]
+pub struct Cli {
+    /// What to do, which is to serve if it's left out
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Serve the API
+    Serve(ConfigArgs),
+    /// Check kprint could start with its configuration, and print what it'd
+    /// start with
+    CheckConfig(ConfigArgs),
+    /// Write out files made from kprint's own definitions
+    #[command(subcommand)]
+    Gen(Generate),
+}
+
+#[derive(Subcommand)]
+pub enum Generate {
+    /// TypeScript definitions of everything the API takes and gives back
+    Types {
+        /// Where to write them, rather than printing them
+        #[arg(long, short)]
+        out: Option<PathBuf>,
+    },
+}
+
+#[derive(Args, Default)]
+pub struct ConfigArgs {
+    /// A TOML file of settings, for any the environment leaves out
+    #[arg(long, env = "KPRINT_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Which of the config file's profiles to use, like `staging`
+    #[arg(long, env = "KPRINT_ENV")]
+    pub env: Option<String>,
+    /// What to serve HTTP on [default: 8080]
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// The CUPS server printers are on
+    #[arg(long)]
+    pub cups_url: Option<String>,
+    /// The printers to print to, separated by spaces
+    #[arg(long)]
+    pub printers: Option<String>,
+    /// Any other setting, like `--set breaker_threshold=3`. Can be given more
+    /// than once
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = setting)]
+    pub settings: Vec<(String, String)>,
+    /// Serve made-up printers and jobs, with no CUPS or SSO needed
+    #[arg(long)]
+    pub demo: bool,
+}
+
+fn setting(setting: &str) -> Result<(String, String), String> {
+    let (key, value) = setting
+        .split_once('=')
+        .ok_or_else(|| format!("{setting:?} isn't KEY=VALUE"))?;
+    Ok((var_name(&[key]), value.to_string()))
+}
+
+/// The environment variable `path` through the config file is.
+fn var_name(path: &[&str]) -> String {
+    let mut name = PREFIX.to_string();
+    for key in path {
+        name.push('_');
+        name.push_str(&key.to_uppercase().replace('-', "_"));
+    }
+    name
+}
+
+/// Where `check-config` says a setting came from.
+fn describe(source: ConfigSource) -> &'static str {
+    match source {
+        ConfigSource::File => "config file",
+        ConfigSource::Profile => "config file's profile",
+        ConfigSource::Environment => "environment",
+        ConfigSource::CommandLine => "command line",
+    }
+}
+
+/// The settings kprint ended up with, and where each came from.
+#[derive(Clone)]
+pub struct Settings {
+    sources: BTreeMap<String, ConfigSource>,
+}
+
+/// What kprint was started with, once it's been worked out
+static LOADED: OnceLock<Settings> = OnceLock::new();
+
+/// Puts the config file and command line into the environment, under
+/// whatever was already there and over it respectively.
+pub fn load(args: &ConfigArgs) -> anyhow::Result<Settings> {
+    let mut sources = std::env::vars()
+        .filter(|(name, _)| is_setting(name))
+        .map(|(name, _)| (name, ConfigSource::Environment))
+        .collect::<BTreeMap<_, _>>();
+    if let Some(path) = &args.config {
+        let file = std::fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read {}", path.display()))?;
+        let mut table = file
+            .parse::<toml::Table>()
+            .with_context(|| format!("{} isn't TOML", path.display()))?;
+        let profile = profile(&mut table, args.env.as_deref())
+            .with_context(|| format!("in {}", path.display()))?;
+        let mut vars = vec![];
+        if let Some(profile) = &profile {
+            flatten(&mut vec![], profile, &mut vars)
+                .with_context(|| format!("in {}", path.display()))?;
+        }
+        let from_profile = vars.len();
+        flatten(&mut vec![], &table, &mut vars)
+            .with_context(|| format!("in {}", path.display()))?;
+        for (at, (name, value)) in vars.into_iter().enumerate() {
+            if let Entry::Vacant(entry) = sources.entry(name) {
+                std::env::set_var(entry.key(), value);
+                entry.insert(match at < from_profile {
+                    true => ConfigSource::Profile,
+                    false => ConfigSource::File,
+                });
+            }
+        }
+    } else if let Some(env) = &args.env {
+        anyhow::bail!("KPRINT_ENV is {env:?}, but there's no config file for its profile to be in");
+    }
+    let named = [
+        ("KPRINT_PORT", args.port.map(|port| port.to_string())),
+        ("KPRINT_CUPS_URL", args.cups_url.clone()),
+        ("KPRINT_PRINTERS", args.printers.clone()),
+    ];
+    let demo = crate::demo::SETTINGS
+        .iter()
+        .filter(|_| args.demo)
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+    let named = named
+        .into_iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value?)));
+    if args.demo {
+        crate::demo::enable();
+    }
+    for (name, value) in demo.chain(named).chain(args.settings.iter().cloned()) {
+        std::env::set_var(&name, value);
+        sources.insert(name, ConfigSource::CommandLine);
+    }
+    let settings = Settings { sources };
+    let _ = LOADED.set(settings.clone());
+    Ok(settings)
+}
+
+/// Takes the profiles out of `table`, giving back the one `env` picks.
+fn profile(table: &mut toml::Table, env: Option<&str>) -> anyhow::Result<Option<toml::Table>> {
+    let profiles = match (table.remove("profiles"), env) {
+        (Some(toml::Value::Table(profiles)), _) => profiles,
+        (Some(_), _) => anyhow::bail!("profiles has to be a table of profiles"),
+        (None, Some(env)) => anyhow::bail!("KPRINT_ENV is {env:?}, but there aren't any profiles"),
+        (None, None) => return Ok(None),
+    };
+    let names = profiles.keys().cloned().collect::<Vec<_>>().join(", ");
+    let Some(env) = env else {
+        anyhow::bail!("KPRINT_ENV has to pick one of the profiles, which are {names}");
+    };
+    match profiles.get(env) {
+        Some(toml::Value::Table(profile)) => Ok(Some(profile.clone())),
+        Some(_) => anyhow::bail!("profiles.{env} has to be a table"),
+        None => anyhow::bail!("There's no {env:?} profile, only {names}"),
+    }
+}
+
+fn is_setting(name: &str) -> bool {
+    name.strip_prefix(PREFIX)
+        .is_some_and(|rest| rest.starts_with('_'))
+}
+
+/// Turns `table` into the environment variables it sets, with `path` being
+/// the tables it's in.
+fn flatten<'a>(
+    path: &mut Vec<&'a str>,
+    table: &'a toml::Table,
+    vars: &mut Vec<(String, String)>,
+) -> anyhow::Result<()> {
+    for (key, value) in table {
+        path.push(key);
+        match value {
+            toml::Value::Table(table) => flatten(path, table, vars)?,
+            toml::Value::Array(values) => {
+                let values = values
+                    .iter()
+                    .map(|value| scalar(value).with_context(|| path.join(".")))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                vars.push((var_name(path), values.join(" ")));
+            }
+            value => vars.push((var_name(path), scalar(value)?)),
+        }
+        path.pop();
+    }
+    Ok(())
+}
+
+fn scalar(value: &toml::Value) -> anyhow::Result<String> {
+    Ok(match value {
+        toml::Value::String(value) => value.clone(),
+        toml::Value::Integer(value) => value.to_string(),
+        toml::Value::Float(value) => value.to_string(),
+        toml::Value::Boolean(value) => value.to_string(),
+        toml::Value::Datetime(value) => value.to_string(),
+        toml::Value::Array(_) | toml::Value::Table(_) => {
+            anyhow::bail!("Lists can only hold plain values")
+        }
+    })
+}
+
+/// What to serve HTTP on.
+pub fn port() -> anyhow::Result<u16> {
+    match std::env::var("KPRINT_PORT") {
+        Ok(port) => port.parse().context("Bad KPRINT_PORT"),
+        Err(_) => Ok(DEFAULT_PORT),
+    }
+}
+
+/// Whether setting `name` is too secret to show anyone.
+pub fn is_secret(name: &str) -> bool {
+    ["TOKEN", "SECRET", "PASSWORD", "KEY"]
+        .iter()
+        .any(|secret| name.contains(secret))
+}
+
+/// `value` with anything secret in it masked, which is all of it for
+/// settings like tokens, and the password in URLs that have one.
+fn redact(name: &str, value: String) -> String {
+    if is_secret(name) {
+        return "(redacted)".to_string();
+    }
+    match reqwest::Url::parse(&value) {
+        Ok(mut url) if url.password().is_some() => {
+            let _ = url.set_password(Some("redacted"));
+            url.to_string()
+        }
+        _ => value,
+    }
+}
+
+impl Settings {
+    /// Each setting, its value, with secrets masked, and where it came from.
+    pub fn effective(&self) -> Vec<ConfigSetting> {
+        self.sources
+            .iter()
+            .filter_map(|(name, source)| {
+                let value = std::env::var(name).ok()?;
+                Some(ConfigSetting {
+                    name: name.clone(),
+                    value: redact(name, value),
+                    source: *source,
+                })
+            })
+            .collect()
+    }
+
+    /// Prints each setting, for `check-config`.
+    pub fn print(&self) {
+        for setting in self.effective() {
+            println!(
+                "{}={}  # from the {}",
+                setting.name,
+                setting.value,
+                describe(setting.source)
+            );
+        }
+    }
+}
+
+/// What kprint's running with. Anything it didn't see when it started,
+/// which is everything in tests, is taken to be from the environment.
+pub fn effective() -> Vec<ConfigSetting> {
+    let mut sources = LOADED
+        .get()
+        .map(|settings| settings.sources.clone())
+        .unwrap_or_default();
+    for (name, _) in std::env::vars().filter(|(name, _)| is_setting(name)) {
+        sources.entry(name).or_insert(ConfigSource::Environment);
+    }
+    Settings { sources }.effective()
+}
+
+/// Sets up everything kprint would at startup, without serving anything, so
+/// bad settings turn up before a deploy rather than during it.
+pub async fn check() -> anyhow::Result<()> {
+    port()?;
+    if let Ok(port) = std::env::var("KPRINT_GRPC_PORT") {
+        port.parse::<u16>().context("Bad KPRINT_GRPC_PORT")?;
+    }
+    crate::probe::enabled().context("Bad KPRINT_PROBE_PRINTERS")?;
+    crate::app::get_app_data().await?;
+    crate::auth::issuers().await?;
+    if let Some(config) = crate::email::EmailConfig::from_env() {
+        config?;
+    }
+    if let Some(config) = crate::reports::ReportConfig::from_env() {
+        config?;
+    }
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! Emails members the receipt for each job once it's finished, if they've
+//! asked for that in their preferences with `confirmationEmail`.
+//!
+//! Turned on by setting `KPRINT_CONFIRMATION_EMAILS` to `true`. They're sent
+//! through the SMTP server email printing uses (`KPRINT_EMAIL_SMTP_HOST`,
+//! `KPRINT_EMAIL_ADDRESS`, `KPRINT_EMAIL_USERNAME` and
+//! `KPRINT_EMAIL_PASSWORD`), to the `email` claim on the last token the
+//! member used, and say whether the job printed, with its receipt attached.
+
+use crate::app::AppState;
+use crate::auth::AuthenticatedUser;
+use crate::logging::Pii;
+use crate::receipt;
+use kprint_client::models::{HistoryEntry, JobReceipt, JobState};
+use lettre::{
+    message::{header::ContentType, Attachment, Mailbox, MultiPart, SinglePart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+pub struct Confirmations {
+    smtp: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    /// Where each member's email claim last said to write to
+    addresses: RwLock<HashMap<String, Mailbox>>,
+}
+
+impl Confirmations {
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let enabled = std::env::var("KPRINT_CONFIRMATION_EMAILS")
+            .map(|enabled| enabled.parse())
+            .unwrap_or(Ok(false))?;
+        if !enabled {
+            return Ok(None);
+        }
+        let var = |name: &str| {
+            std::env::var(name).map_err(|_| anyhow::anyhow!("Confirmation emails need {name}"))
+        };
+        Ok(Some(Confirmations {
+            smtp: AsyncSmtpTransport::<Tokio1Executor>::relay(&var("KPRINT_EMAIL_SMTP_HOST")?)?
+                .credentials(Credentials::new(
+                    var("KPRINT_EMAIL_USERNAME")?,
+                    var("KPRINT_EMAIL_PASSWORD")?,
+                ))
+                .build(),
+            from: var("KPRINT_EMAIL_ADDRESS")?.parse()?,
+            addresses: RwLock::default(),
+        }))
+    }
+
+    fn address(&self, username: &str) -> Option<Mailbox> {
+        self.addresses.read().unwrap().get(username).cloned()
+    }
+}
+
+/// The address `user`'s token says is theirs, unless SSO says it's not
+/// theirs after all.
+pub fn address(user: &AuthenticatedUser) -> Option<Mailbox> {
+    if user.claims.email_verified() == Some(false) {
+        return None;
+    }
+    user.claims.email()?.as_str().parse().ok()
+}
+
+/// Notes where to send `user`'s confirmations, whenever they make a request.
+pub fn remember(app_data: &AppState, user: &AuthenticatedUser) {
+    let Some(confirmations) = &app_data.confirmations else {
+        return;
+    };
+    let Some(address) = address(user) else {
+        return;
+    };
+    let username = user.username();
+    if confirmations.address(username).as_ref() == Some(&address) {
+        return;
+    }
+    confirmations
+        .addresses
+        .write()
+        .unwrap()
+        .insert(username.to_string(), address);
+}
+
+/// What happened to the job, as the start of a sentence.
+fn outcome(receipt: &JobReceipt) -> &'static str {
+    match receipt.state {
+        JobState::Completed if receipt.failure.is_none() => "Printed",
+        JobState::Canceled => "Canceled",
+        _ => "Didn't print",
+    }
+}
+
+/// The confirmation for `entry`'s job, which has just finished, if its
+/// owner wants one and kprint knows where to send it.
+pub fn confirmation(app_data: &AppState, entry: HistoryEntry) -> Option<lettre::Message> {
+    let confirmations = app_data.confirmations.as_ref()?;
+    if app_data.preferences.get(&entry.owner).confirmation_email != Some(true) {
+        return None;
+    }
+    let to = confirmations.address(&entry.owner)?;
+    let receipt = receipt::of(app_data, entry);
+    let message = (|| {
+        let pdf = receipt::render(&receipt)?;
+        let mut text = format!(
+            "{} {} on {}.\n\n",
+            outcome(&receipt),
+            receipt.title,
+            receipt.printer
+        );
+        for (_, line) in receipt::lines(&receipt) {
+            text += &line;
+            text.push('\n');
+        }
+        let message = lettre::Message::builder()
+            .from(confirmations.from.clone())
+            .to(to)
+            .subject(format!("{}: {}", outcome(&receipt), receipt.title))
+            .multipart(
+                MultiPart::mixed()
+                    .singlepart(SinglePart::plain(text))
+                    .singlepart(
+                        Attachment::new(format!("kprint-receipt-{}.pdf", receipt.id))
+                            .body(pdf, ContentType::parse("application/pdf")?),
+                    ),
+            )?;
+        anyhow::Ok(message)
+    })();
+    match message {
+        Ok(message) => Some(message),
+        Err(err) => {
+            log::warn!(
+                "Couldn't write {}'s confirmation for job {}: {err}",
+                Pii(&receipt.owner),
+                receipt.id
+            );
+            None
+        }
+    }
+}
+
+/// Emails the owner of `entry`'s job, which has just finished, if they want
+/// to hear about it, without waiting for it to send.
+pub fn finished(app_data: &AppState, entry: HistoryEntry) {
+    let id = entry.id;
+    let (Some(confirmations), Some(message)) =
+        (&app_data.confirmations, confirmation(app_data, entry))
+    else {
+        return;
+    };
+    let smtp = confirmations.smtp.clone();
+    tokio::spawn(async move {
+        if let Err(err) = smtp.send(message).await {
+            log::warn!("Couldn't send the confirmation for job {id}: {err}");
+        }
+    });
+}
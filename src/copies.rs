@@ -0,0 +1,131 @@
+//! Printing copies as jobs of their own, for `separateJobs`.
+//!
+//! The first copy is sent straight away, and kprint holds on to the document
+//! for the rest. Each one after is sent once the printer is done with the one
+//! before, so anyone else's jobs queued in the meantime print in between
+//! rather than waiting out the whole run. Canceling the copy that's printing,
+//! or it failing, drops the ones still to come.
+
+use crate::app::AppState;
+use crate::document::Document;
+use crate::service;
+use actix_web::web::Data;
+use kprint_client::models::{JobState, PrintOptions};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How often the copy that's printing is checked on
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// How long a copy is waited on before the rest are given up on, so a job
+/// stuck in a queue doesn't hold on to its document forever
+const WAIT_LIMIT: Duration = Duration::from_secs(24 * 60 * 60);
+
+struct Following {
+    options: PrintOptions,
+    document: Document,
+    /// Copies still to be sent
+    remaining: u32,
+    since: Instant,
+}
+
+/// The copies waiting on the one before them, by the id of that job.
+#[derive(Default)]
+pub struct SeparateCopies {
+    following: Mutex<HashMap<Uuid, Following>>,
+}
+
+impl SeparateCopies {
+    /// Sends `remaining` more copies of `document` once job `id` has printed,
+    /// one at a time.
+    pub fn follow(&self, id: Uuid, options: PrintOptions, document: Document, remaining: u32) {
+        if remaining == 0 {
+            return;
+        }
+        self.following.lock().unwrap().insert(
+            id,
+            Following {
+                options,
+                document,
+                remaining,
+                since: Instant::now(),
+            },
+        );
+    }
+
+    /// Checks on every copy that's printing each [`POLL_INTERVAL`], for as
+    /// long as kprint runs.
+    pub fn follow_in_background(app_data: Data<AppState>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let ids = {
+                    let mut following = app_data.copies.following.lock().unwrap();
+                    following.retain(|id, copies| {
+                        let waiting = copies.since.elapsed() < WAIT_LIMIT;
+                        if !waiting {
+                            log::warn!(
+                                "Gave up on {} more copies waiting on job {id}",
+                                copies.remaining
+                            );
+                        }
+                        waiting
+                    });
+                    following.keys().copied().collect::<Vec<_>>()
+                };
+                futures::future::join_all(ids.into_iter().map(|id| check(&app_data, id))).await;
+            }
+        });
+    }
+}
+
+async fn check(app_data: &AppState, id: Uuid) {
+    let Some(job) = app_data.jobs.get(&id) else {
+        app_data.copies.following.lock().unwrap().remove(&id);
+        return;
+    };
+    let status = match service::job_status(app_data, &job.owner, id).await {
+        Ok(status) => status,
+        Err(err) => {
+            log::debug!("Couldn't check on job {id} to send its next copy: {err}");
+            return;
+        }
+    };
+    match status.state {
+        JobState::Completed => {}
+        JobState::Aborted | JobState::Canceled => {
+            if let Some(copies) = app_data.copies.following.lock().unwrap().remove(&id) {
+                log::info!(
+                    "Job {id} didn't finish, so its {} other copies won't be printed",
+                    copies.remaining
+                );
+            }
+            return;
+        }
+        _ => return,
+    }
+
+    // Out of the map while it's sent, so the next check can't send it too
+    let Some(copies) = app_data.copies.following.lock().unwrap().remove(&id) else {
+        return;
+    };
+    let Some(document) = copies.document.try_clone() else {
+        return;
+    };
+    match service::print_copy(app_data, &job, copies.options.clone(), document).await {
+        Ok(next) => app_data.copies.follow(
+            next,
+            copies.options,
+            copies.document,
+            copies.remaining - 1,
+        ),
+        Err(err) => log::warn!(
+            "Couldn't send the next copy of job {id} to {}, so its {} other copies won't be printed: {err}",
+            job.printer,
+            copies.remaining
+        ),
+    }
+}
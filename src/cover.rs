@@ -0,0 +1,170 @@
+//! Cover sheets: a page kprint draws itself and puts in front of a PDF,
+//! saying whose it is, since the cheaper printers can't print banners.
+//!
+//! The QR code on it links to the job in the web UI, when `KPRINT_PUBLIC_URL`
+//! says where that is.
+
+use crate::pdf;
+use chrono::{DateTime, Local};
+use lopdf::{dictionary, Dictionary, Object, Stream};
+use qrcode::{Color, QrCode};
+use uuid::Uuid;
+
+const FONT: &str = "KprintCoverFont";
+
+/// How far the cover's contents stay from the edges of the page
+const MARGIN: f32 = 72.0;
+/// Side of the QR code, in points
+const QR_SIZE: f32 = 144.0;
+/// Characters of the title per line, and lines of it, before it's cut off
+const TITLE_WIDTH: usize = 48;
+const TITLE_LINES: usize = 4;
+
+/// The job a cover sheet is for.
+pub struct Cover {
+    pub job: Uuid,
+    pub username: String,
+    pub title: String,
+    pub printer: String,
+    pub at: DateTime<Local>,
+}
+
+#[derive(Clone)]
+pub struct CoverSheets {
+    public_url: Option<String>,
+}
+
+/// Breaks `text` into lines of about `width` characters, cutting it off if it
+/// takes more than `lines`.
+fn wrap(text: &str, width: usize, lines: usize) -> Vec<String> {
+    let mut wrapped: Vec<String> = vec![];
+    for word in text.split_whitespace() {
+        match wrapped.last_mut() {
+            Some(line) if line.len() + 1 + word.len() <= width => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => wrapped.push(word.chars().take(width).collect()),
+        }
+    }
+    if wrapped.len() > lines {
+        wrapped.truncate(lines);
+        if let Some(last) = wrapped.last_mut() {
+            last.push_str("...");
+        }
+    }
+    wrapped
+}
+
+/// Drawing instructions for a QR code of `data`, with its bottom left corner
+/// at `(x, y)`.
+fn qr_content(data: &str, x: f32, y: f32) -> anyhow::Result<String> {
+    let code = QrCode::new(data)?;
+    let width = code.width();
+    let module = QR_SIZE / width as f32;
+    let mut content = String::from("0 g ");
+    for (i, color) in code.to_colors().into_iter().enumerate() {
+        if color == Color::Dark {
+            let (row, column) = (i / width, i % width);
+            let top = y + QR_SIZE - row as f32 * module;
+            content += &format!(
+                "{} {} {module} {module} re ",
+                x + column as f32 * module,
+                top - module
+            );
+        }
+    }
+    content += "f ";
+    Ok(content)
+}
+
+impl CoverSheets {
+    pub fn from_env() -> Self {
+        CoverSheets {
+            public_url: std::env::var("KPRINT_PUBLIC_URL")
+                .ok()
+                .map(|url| url.trim_end_matches('/').to_string()),
+        }
+    }
+
+    /// Puts a cover sheet for `cover` in front of `pdf`. With `blank_back`,
+    /// a blank page goes after it too, so a two-sided job doesn't start on
+    /// the back of the cover.
+    pub fn prepend(&self, pdf: &[u8], cover: &Cover, blank_back: bool) -> anyhow::Result<Vec<u8>> {
+        let mut document = pdf::load(pdf)?;
+        let pages = document.get_pages();
+        let first_page = *pages
+            .values()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("the PDF has no pages"))?;
+        let [x0, y0, x1, y1] = pdf::media_box(&document, first_page);
+        let media_box = Object::Array([x0, y0, x1, y1].map(Object::Real).to_vec());
+
+        let mut lines = vec![(36.0, cover.username.clone()), (12.0, String::new())];
+        lines.extend(
+            wrap(&cover.title, TITLE_WIDTH, TITLE_LINES)
+                .into_iter()
+                .map(|line| (18.0, line)),
+        );
+        lines.extend([
+            (12.0, String::new()),
+            (12.0, format!("Printed on {}", cover.printer)),
+            (
+                12.0,
+                cover.at.format("%A %B %-d, %Y at %-I:%M %p").to_string(),
+            ),
+            (
+                12.0,
+                match pages.len() {
+                    1 => "1 page".to_string(),
+                    pages => format!("{pages} pages"),
+                },
+            ),
+            (12.0, String::new()),
+            (9.0, format!("Job {}", cover.job)),
+        ]);
+
+        let mut content = format!("BT 0 g {} {} Td ", x0 + MARGIN, y1 - MARGIN);
+        for (size, line) in lines {
+            content += &format!(
+                "/{FONT} {size} Tf 0 {} Td ({}) Tj ",
+                -size * 1.3,
+                pdf::string(&line)
+            );
+        }
+        content += "ET ";
+        if let Some(public_url) = &self.public_url {
+            let link = format!("{public_url}/jobs/{}", cover.job);
+            content += &qr_content(&link, x1 - MARGIN - QR_SIZE, y0 + MARGIN)?;
+        }
+
+        let font = pdf::helvetica(&mut document);
+        let content = document.add_object(Stream::new(Dictionary::new(), content.into_bytes()));
+        let pages_id = document
+            .catalog()?
+            .get(b"Pages")
+            .and_then(Object::as_reference)?;
+        let cover_page = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => media_box.clone(),
+            "Resources" => dictionary! { "Font" => dictionary! { FONT => font } },
+            "Contents" => content,
+        });
+        let mut new_pages = vec![Object::Reference(cover_page)];
+        if blank_back {
+            new_pages.push(Object::Reference(document.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "MediaBox" => media_box,
+            })));
+        }
+
+        let tree = document.get_dictionary_mut(pages_id)?;
+        let count = tree.get(b"Count").and_then(Object::as_i64).unwrap_or(0);
+        tree.set("Count", count + new_pages.len() as i64);
+        let kids = tree.get_mut(b"Kids")?.as_array_mut()?;
+        kids.splice(0..0, new_pages);
+        pdf::save(document)
+    }
+}
@@ -0,0 +1,117 @@
+//! CUPS's own operations, for what plain IPP has no way to ask: which
+//! printer is the server's default, which printers are grouped into classes,
+//! and moving a job from one printer to another.
+//!
+//! They're sent through a printer's client like anything else, since CUPS
+//! answers them wherever they're posted.
+
+use crate::error::KprintError;
+use crate::ipp_client::IppClient;
+use crate::jobs::{find_attribute, find_keywords};
+use ipp::prelude::*;
+use kprint_client::models::PrinterClass;
+
+/// A request for CUPS operation `operation`, asking for `attributes` back.
+fn request(printer: &IppClient, operation: Operation, attributes: &[&str]) -> IppRequestResponse {
+    let mut request =
+        IppRequestResponse::new(IppVersion::v1_1(), operation, Some(printer.uri().clone()));
+    let requested = attributes
+        .iter()
+        .map(|attribute| IppValue::Keyword(attribute.to_string()))
+        .collect();
+    request.attributes_mut().add(
+        DelimiterTag::OperationAttributes,
+        IppAttribute::new(
+            IppAttribute::REQUESTED_ATTRIBUTES,
+            IppValue::Array(requested),
+        ),
+    );
+    request
+}
+
+fn failed(operation: &str, response: &IppRequestResponse) -> KprintError {
+    KprintError::IppProtocol(format!(
+        "{operation} failed: {:?}",
+        response.header().status_code()
+    ))
+}
+
+/// The name of the server's default printer, if it has one.
+pub async fn get_default(cups: &IppClient) -> Result<Option<String>, KprintError> {
+    let request = request(
+        cups,
+        Operation::CupsGetDefault,
+        &[IppAttribute::PRINTER_NAME],
+    );
+    let response = cups.send(request).await?;
+    match response.header().status_code() {
+        status if status.is_success() => Ok(find_attribute(
+            response.attributes(),
+            IppAttribute::PRINTER_NAME,
+        )
+        .map(ToString::to_string)),
+        StatusCode::ClientErrorNotFound => Ok(None),
+        _ => Err(failed("CUPS-Get-Default", &response)),
+    }
+}
+
+/// Every class on the server, and which printers are in it.
+pub async fn get_classes(cups: &IppClient) -> Result<Vec<PrinterClass>, KprintError> {
+    let request = request(
+        cups,
+        Operation::CupsGetClasses,
+        &[IppAttribute::PRINTER_NAME, "member-names"],
+    );
+    let response = cups.send(request).await?;
+    match response.header().status_code() {
+        status if status.is_success() => {}
+        // There aren't any
+        StatusCode::ClientErrorNotFound => return Ok(vec![]),
+        _ => return Err(failed("CUPS-Get-Classes", &response)),
+    }
+    Ok(response
+        .attributes()
+        .groups_of(DelimiterTag::PrinterAttributes)
+        .filter_map(|group| {
+            let mut attributes = IppAttributes::new();
+            attributes.groups_mut().push(group.clone());
+            let name = find_attribute(&attributes, IppAttribute::PRINTER_NAME)?.to_string();
+            Some(PrinterClass {
+                name,
+                members: find_keywords(&attributes, "member-names"),
+            })
+        })
+        .collect())
+}
+
+/// Asks CUPS to move job `ipp_job_id` from `printer` to the printer at `to`,
+/// on `username`'s behalf, leaving what it answered for the caller to make
+/// sense of.
+pub async fn move_job(
+    printer: &IppClient,
+    ipp_job_id: i32,
+    to: &Uri,
+    username: &str,
+) -> Result<IppRequestResponse, KprintError> {
+    let mut request = IppRequestResponse::new(
+        IppVersion::v1_1(),
+        Operation::CupsMoveJob,
+        Some(printer.uri().clone()),
+    );
+    for attribute in [
+        IppAttribute::new(IppAttribute::JOB_ID, IppValue::Integer(ipp_job_id)),
+        IppAttribute::new(
+            IppAttribute::REQUESTING_USER_NAME,
+            IppValue::NameWithoutLanguage(username.to_string()),
+        ),
+    ] {
+        request
+            .attributes_mut()
+            .add(DelimiterTag::OperationAttributes, attribute);
+    }
+    request.attributes_mut().add(
+        DelimiterTag::JobAttributes,
+        IppAttribute::new("job-printer-uri", IppValue::Uri(to.to_string())),
+    );
+    Ok(printer.send(request).await?)
+}
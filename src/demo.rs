@@ -0,0 +1,210 @@
+//! A kprint that needs nothing else running, for designing the frontend
+//! against and for workshops, started with `kprint serve --demo`.
+//!
+//! Its printers are null printers, so there's no CUPS, and there's no SSO
+//! either: every request to the API is taken to be from [`USER`], who's an
+//! admin, whatever token it has or doesn't. Since that's no security at all,
+//! only `--demo` turns it on, never a setting that could be left in a config
+//! file, and kprint won't start a demo with any printer that isn't a null
+//! printer. When it starts a few weeks of
+//! made-up history is recorded, so there's something to report on, and a
+//! handful of jobs are printed so the queues have something in them.
+
+use crate::app::AppState;
+use crate::auth::{AuthenticatedUser, CshClaims};
+use crate::error::KprintError;
+use chrono::{Duration, Utc};
+use kprint_client::models::{
+    ColorMode, DuplexMode, HistoryEntry, JobState, PageSelection, PrintOptions,
+};
+use openidconnect::{
+    Audience, EndUserUsername, IdTokenClaims, IssuerUrl, StandardClaims, SubjectIdentifier,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use uuid::Uuid;
+
+/// Who everyone using a demo is
+pub const USER: &str = "demo";
+
+/// Set by `--demo`, and nothing else
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// What `--demo` sets, over whatever the environment and config file say
+pub const SETTINGS: &[(&str, &str)] = &[
+    ("KPRINT_PRINTERS", "null:hulk null:thor null:loki"),
+    // Long enough to see jobs wait their turn
+    ("KPRINT_NULL_PRINT_SECONDS", "30"),
+];
+
+/// How far back the made-up history goes
+const HISTORY_DAYS: i64 = 28;
+
+/// The same made-up history every time
+const SEED: u64 = 0x6b70_7269_6e74;
+
+const MEMBERS: &[&str] = &["skyz", "mom", "jmf", "ram", "babysitter", "ehouse"];
+
+const TITLES: &[&str] = &[
+    "Constitution",
+    "House meeting agenda",
+    "Evals packet",
+    "Imagine RIT poster",
+    "Resume",
+    "Lab report",
+    "Seminar slides",
+    "Major project writeup",
+    "Financial committee budget",
+    "Boarding pass",
+];
+
+const TAGS: &[&str] = &["evals", "financial", "imagine", "social"];
+
+/// Makes this a demo, for `--demo`.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether kprint's serving a demo.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Turns away `printer` for a demo unless it's a null printer, since anyone
+/// can do anything to a demo's printers.
+pub fn check_printer(printer: &str) -> anyhow::Result<()> {
+    if !printer.starts_with("null:") {
+        anyhow::bail!(
+            "{printer} isn't a null printer, and a demo lets anyone print as an admin, \
+            so it can only have null printers"
+        );
+    }
+    Ok(())
+}
+
+/// [`USER`], as though SSO had vouched for them.
+pub fn user() -> AuthenticatedUser {
+    let now = Utc::now();
+    let claims = IdTokenClaims::new(
+        IssuerUrl::new("https://sso.csh.rit.edu/auth/realms/demo".to_string())
+            .expect("the demo issuer is a URL"),
+        vec![Audience::new("kprint".to_string())],
+        now + Duration::days(1),
+        now,
+        StandardClaims::new(SubjectIdentifier::new(USER.to_string()))
+            .set_preferred_username(Some(EndUserUsername::new(USER.to_string()))),
+        CshClaims {
+            groups: vec!["member".to_string(), "rtp".to_string()],
+            uuid: Uuid::nil(),
+        },
+    );
+    AuthenticatedUser { claims }
+}
+
+/// Fills a new demo's history and queues. A history that's already got
+/// something in it, from a `KPRINT_HISTORY_FILE` kept between demos, is
+/// left alone.
+pub async fn seed(app_data: &AppState) {
+    let mut printers = app_data.printers.keys().cloned().collect::<Vec<_>>();
+    printers.sort();
+    if printers.is_empty() {
+        return;
+    }
+    if app_data.history.filter(|_| true).is_empty() {
+        for entry in history(&printers) {
+            app_data.history.record(entry);
+        }
+    }
+    for (printer, title) in printers.iter().cycle().zip(&TITLES[..4]) {
+        if let Err(err) = print(app_data, printer, title).await {
+            log::warn!("Couldn't print the demo's {title:?} on {printer}: {err}");
+        }
+    }
+}
+
+/// A few jobs a day for [`HISTORY_DAYS`], by members on `printers`, mostly
+/// printed, in the order they were submitted.
+fn history(printers: &[String]) -> Vec<HistoryEntry> {
+    let mut rng = fastrand::Rng::with_seed(SEED);
+    let now = Utc::now();
+    let mut entries = (0..HISTORY_DAYS * 4)
+        .map(|_| {
+            let submitted_at = now - Duration::minutes(rng.i64(60..HISTORY_DAYS * 24 * 60));
+            let copies = match rng.u8(..10) {
+                0 => 2,
+                _ => 1,
+            };
+            let pages = rng.u32(1..=12) * copies;
+            let (state, failure) = match rng.u8(..20) {
+                0 => (JobState::Canceled, None),
+                1 => (JobState::Aborted, None),
+                2 => (
+                    JobState::Unknown,
+                    Some("The printer couldn't be reached".to_string()),
+                ),
+                _ => (JobState::Completed, None),
+            };
+            HistoryEntry {
+                id: Uuid::from_u128(rng.u128(..)),
+                owner: MEMBERS[rng.usize(..MEMBERS.len())].to_string(),
+                printer: printers[rng.usize(..printers.len())].clone(),
+                title: TITLES[rng.usize(..TITLES.len())].to_string(),
+                submitted_at,
+                sides: match rng.bool() {
+                    true => DuplexMode::TwoSidedLongEdge,
+                    false => DuplexMode::OneSided,
+                },
+                color_mode: match rng.u8(..4) {
+                    0 => ColorMode::Color,
+                    _ => ColorMode::Grayscale,
+                },
+                copies,
+                pages: (state == JobState::Completed).then_some(pages),
+                counted_pages: None,
+                quota_units: None,
+                state,
+                failure,
+                tags: match rng.u8(..3) {
+                    0 => vec![TAGS[rng.usize(..TAGS.len())].to_string()],
+                    _ => vec![],
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+    entries.sort_by_key(|entry| entry.submitted_at);
+    entries
+}
+
+/// Prints a test page called `title` on `printer` as [`USER`].
+async fn print(app_data: &AppState, printer: &str, title: &str) -> Result<(), KprintError> {
+    let uri = app_data.printers[printer].uri().to_string();
+    let page =
+        crate::test_page::render(printer, None, &uri, USER).map_err(KprintError::Conversion)?;
+    let options = PrintOptions {
+        sides: DuplexMode::OneSided,
+        color_mode: ColorMode::Grayscale,
+        pages: PageSelection::all(),
+        copies: 1,
+        title: title.to_string(),
+        cover_sheet: false,
+        allow_fallback: false,
+        separate_jobs: false,
+        tags: vec![],
+        media: None,
+        margins: None,
+        offset: None,
+        poster: None,
+        envelope: None,
+    };
+    let user = user();
+    crate::service::submit(
+        app_data,
+        USER,
+        true,
+        user.groups(),
+        printer.to_string(),
+        options,
+        page.into(),
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,173 @@
+//! Speaking IPP the way older printers want it, for devices that only know
+//! IPP/1.0 or 1.1, or that choke on anything that isn't plain ASCII.
+//!
+//! `KPRINT_PRINTER_DIALECTS` changes how requests to some printers are sent,
+//! like `oldhp:version=1.1 oldhp:charset=us-ascii oldhp:language=en-us`.
+//! `version` is the IPP version every request says it is, rather than the
+//! one each is built with. `charset` is `attributes-charset`, `utf-8` unless
+//! it's `us-ascii`, which has anything outside ASCII in names and text sent
+//! as `?`. `language` is `attributes-natural-language`, `en` by default.
+
+use anyhow::Context;
+use ipp::prelude::*;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Charset {
+    #[default]
+    Utf8,
+    UsAscii,
+}
+
+impl Charset {
+    fn name(self) -> &'static str {
+        match self {
+            Charset::Utf8 => "utf-8",
+            Charset::UsAscii => "us-ascii",
+        }
+    }
+
+    /// `text`, with whatever this charset can't say swapped out.
+    fn encode(self, text: &str) -> String {
+        match self {
+            Charset::Utf8 => text.to_string(),
+            Charset::UsAscii => text
+                .chars()
+                .map(|c| if c.is_ascii() { c } else { '?' })
+                .collect(),
+        }
+    }
+}
+
+/// How requests to one printer are sent. The default leaves them as they're
+/// built.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dialect {
+    pub version: Option<IppVersion>,
+    pub charset: Charset,
+    pub language: Option<String>,
+}
+
+impl Dialect {
+    fn set(&mut self, setting: &str, value: &str) -> anyhow::Result<()> {
+        match setting {
+            "version" => {
+                self.version = Some(match value {
+                    "1.0" => IppVersion::v1_0(),
+                    "1.1" => IppVersion::v1_1(),
+                    "2.0" => IppVersion::v2_0(),
+                    "2.1" => IppVersion::v2_1(),
+                    "2.2" => IppVersion::v2_2(),
+                    _ => anyhow::bail!("IPP/{value} isn't a version, try 1.0, 1.1 or 2.0"),
+                })
+            }
+            "charset" => {
+                self.charset = match value.to_lowercase().as_str() {
+                    "utf-8" => Charset::Utf8,
+                    "us-ascii" => Charset::UsAscii,
+                    _ => anyhow::bail!("{value:?} isn't a charset kprint can send, try us-ascii"),
+                }
+            }
+            "language" => {
+                if value.is_empty() || !value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                {
+                    anyhow::bail!("{value:?} isn't a language tag, like en-us");
+                }
+                self.language = Some(value.to_lowercase());
+            }
+            _ => anyhow::bail!("{setting:?} isn't a setting, try version, charset or language"),
+        }
+        Ok(())
+    }
+
+    /// Rewrites `request` to be how the printer wants it.
+    pub fn apply(&self, request: &mut IppRequestResponse) {
+        if *self == Dialect::default() {
+            return;
+        }
+        if let Some(version) = self.version {
+            request.header_mut().version = version;
+        }
+        for group in request.attributes_mut().groups_mut() {
+            let operation = group.tag() == DelimiterTag::OperationAttributes;
+            for attribute in group.attributes_mut().values_mut() {
+                let value = match attribute.name() {
+                    IppAttribute::ATTRIBUTES_CHARSET if operation => {
+                        IppValue::Charset(self.charset.name().to_string())
+                    }
+                    IppAttribute::ATTRIBUTES_NATURAL_LANGUAGE if operation => {
+                        match &self.language {
+                            Some(language) => IppValue::NaturalLanguage(language.clone()),
+                            None => continue,
+                        }
+                    }
+                    _ if self.charset == Charset::Utf8 => continue,
+                    _ => self.encode(attribute.value()),
+                };
+                *attribute = IppAttribute::new(attribute.name(), value);
+            }
+        }
+    }
+
+    fn encode(&self, value: &IppValue) -> IppValue {
+        let charset = self.charset;
+        match value {
+            IppValue::TextWithoutLanguage(text) => {
+                IppValue::TextWithoutLanguage(charset.encode(text))
+            }
+            IppValue::NameWithoutLanguage(name) => {
+                IppValue::NameWithoutLanguage(charset.encode(name))
+            }
+            IppValue::TextWithLanguage { language, text } => IppValue::TextWithLanguage {
+                language: language.clone(),
+                text: charset.encode(text),
+            },
+            IppValue::NameWithLanguage { language, name } => IppValue::NameWithLanguage {
+                language: language.clone(),
+                name: charset.encode(name),
+            },
+            IppValue::Array(values) => {
+                IppValue::Array(values.iter().map(|value| self.encode(value)).collect())
+            }
+            IppValue::Collection(members) => IppValue::Collection(
+                members
+                    .iter()
+                    .map(|(name, value)| (name.clone(), self.encode(value)))
+                    .collect(),
+            ),
+            value => value.clone(),
+        }
+    }
+}
+
+/// The dialect of every printer that doesn't speak the default one.
+#[derive(Debug, Clone, Default)]
+pub struct PrinterDialects {
+    printers: HashMap<String, Dialect>,
+}
+
+impl PrinterDialects {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut printers = HashMap::<String, Dialect>::new();
+        if let Ok(dialects) = std::env::var("KPRINT_PRINTER_DIALECTS") {
+            for entry in dialects.split_whitespace() {
+                let Some((printer, (setting, value))) = entry
+                    .split_once(':')
+                    .and_then(|(printer, setting)| Some((printer, setting.split_once('=')?)))
+                else {
+                    anyhow::bail!("{entry:?} should look like printer:setting=value");
+                };
+                printers
+                    .entry(printer.to_string())
+                    .or_default()
+                    .set(setting, value)
+                    .with_context(|| format!("in {entry:?}"))?;
+            }
+        }
+        Ok(PrinterDialects { printers })
+    }
+
+    pub fn of(&self, printer: &str) -> Dialect {
+        self.printers.get(printer).cloned().unwrap_or_default()
+    }
+}
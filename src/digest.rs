@@ -0,0 +1,265 @@
+//! Checking uploads arrived the way they were sent.
+//!
+//! A print request can say what its document hashes to in a `Repr-Digest`
+//! header (RFC 9530), like
+//!
+//! ```text
+//! Repr-Digest: sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:
+//! ```
+//!
+//! or in the older `Digest` header (RFC 3230), like
+//!
+//! ```text
+//! Digest: SHA-256=X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=
+//! ```
+//!
+//! The document's hashed as it streams through, and if it doesn't match
+//! when it's all arrived, the job fails with `DIGEST_MISMATCH` rather than
+//! half a document printing. The stream to the printer is cut off before it
+//! ends, so the printer never has the whole job to print either. Successful
+//! replies carry the digest kprint worked out, in the same header it was
+//! asked with.
+
+use crate::error::KprintError;
+use actix_web::http::header::{HeaderMap, HeaderName};
+use actix_web::{CustomizeResponder, Responder};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha2::{Digest as _, Sha256, Sha512};
+use std::sync::{Arc, OnceLock};
+
+pub const REPR_DIGEST: HeaderName = HeaderName::from_static("repr-digest");
+pub const DIGEST: HeaderName = HeaderName::from_static("digest");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Algorithm {
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    /// Strongest first, for picking one when a request gives several
+    const ALL: [Algorithm; 2] = [Algorithm::Sha512, Algorithm::Sha256];
+
+    /// Its name in `Repr-Digest`, and in `Digest` too, where names aren't
+    /// case sensitive but are usually written in uppercase
+    fn name(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha-256",
+            Algorithm::Sha512 => "sha-512",
+        }
+    }
+
+    fn len(self) -> usize {
+        match self {
+            Algorithm::Sha256 => 32,
+            Algorithm::Sha512 => 64,
+        }
+    }
+
+    fn hasher(self) -> Hasher {
+        match self {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+        }
+    }
+}
+
+pub enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(bytes),
+            Hasher::Sha512(hasher) => hasher.update(bytes),
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+        }
+    }
+}
+
+/// Which header a digest came in, so it's answered in the same one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Header {
+    Repr,
+    Legacy,
+}
+
+impl Header {
+    fn name(self) -> HeaderName {
+        match self {
+            Header::Repr => REPR_DIGEST,
+            Header::Legacy => DIGEST,
+        }
+    }
+
+    fn format(self, algorithm: Algorithm, digest: &[u8]) -> String {
+        let digest = STANDARD.encode(digest);
+        match self {
+            Header::Repr => format!("{}=:{digest}:", algorithm.name()),
+            Header::Legacy => format!("{}={digest}", algorithm.name().to_uppercase()),
+        }
+    }
+}
+
+/// What a request says its document hashes to.
+#[derive(Debug, Clone)]
+pub struct Expected {
+    header: Header,
+    algorithm: Algorithm,
+    digest: Vec<u8>,
+}
+
+/// The digest the request's headers say to expect, if they give one.
+/// `Repr-Digest` wins over `Digest`, and the strongest algorithm of those
+/// given. Digests kprint can't check are ignored, but it's an error for none
+/// of them to be ones it can.
+pub fn expected(headers: &HeaderMap) -> Result<Option<Expected>, KprintError> {
+    let (header, value) = match (headers.get(REPR_DIGEST), headers.get(DIGEST)) {
+        (Some(value), _) => (Header::Repr, value),
+        (None, Some(value)) => (Header::Legacy, value),
+        (None, None) => return Ok(None),
+    };
+    let name = header.name();
+    let value = value
+        .to_str()
+        .map_err(|_| KprintError::InvalidRequest(format!("{name} isn't text")))?;
+    let mut given = vec![];
+    for member in value.split(',') {
+        let (algorithm, digest) = member.split_once('=').ok_or_else(|| {
+            KprintError::InvalidRequest(format!("{name} should be algorithm=digest"))
+        })?;
+        let algorithm = algorithm.trim().to_lowercase();
+        let Some(algorithm) = Algorithm::ALL
+            .into_iter()
+            .find(|known| known.name() == algorithm)
+        else {
+            continue;
+        };
+        let digest = match header {
+            // A structured field byte sequence, maybe with parameters after
+            Header::Repr => digest
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .strip_prefix(':')
+                .and_then(|digest| digest.strip_suffix(':')),
+            Header::Legacy => Some(digest.trim()),
+        };
+        let digest = digest
+            .and_then(|digest| STANDARD.decode(digest).ok())
+            .filter(|digest| digest.len() == algorithm.len())
+            .ok_or_else(|| {
+                KprintError::InvalidRequest(format!(
+                    "The {} digest in {name} isn't one, in base64",
+                    algorithm.name()
+                ))
+            })?;
+        given.push((algorithm, digest));
+    }
+    let strongest = Algorithm::ALL
+        .into_iter()
+        .find_map(|algorithm| given.iter().find(|(given, _)| *given == algorithm));
+    match strongest {
+        Some((algorithm, digest)) => Ok(Some(Expected {
+            header,
+            algorithm: *algorithm,
+            digest: digest.clone(),
+        })),
+        None => Err(KprintError::InvalidRequest(format!(
+            "{name} doesn't have a sha-256 or sha-512 digest, which are the ones kprint checks"
+        ))),
+    }
+}
+
+impl Expected {
+    pub fn hasher(&self) -> Hasher {
+        self.algorithm.hasher()
+    }
+}
+
+/// How an upload's digest turned out, once it's all arrived.
+#[derive(Debug)]
+struct Computed {
+    digest: Vec<u8>,
+    matched: bool,
+}
+
+/// Shared between whatever's hashing an upload as it streams through, and
+/// the handler that answers for it.
+#[derive(Debug, Clone, Default)]
+pub struct Verification {
+    expected: Option<Expected>,
+    computed: Arc<OnceLock<Computed>>,
+}
+
+impl Verification {
+    pub fn new(expected: Option<Expected>) -> Self {
+        Verification {
+            expected,
+            computed: Arc::default(),
+        }
+    }
+
+    pub fn expected(&self) -> Option<&Expected> {
+        self.expected.as_ref()
+    }
+
+    /// Notes what the whole upload hashed to, saying whether that's what was
+    /// expected.
+    pub fn finish(&self, hasher: Hasher) -> bool {
+        let Some(expected) = &self.expected else {
+            return true;
+        };
+        let digest = hasher.finalize();
+        let matched = digest == expected.digest;
+        let _ = self.computed.set(Computed { digest, matched });
+        matched
+    }
+
+    /// Fails with `DIGEST_MISMATCH` if the upload's arrived and didn't match.
+    pub fn check(&self) -> Result<(), KprintError> {
+        let Some(expected) = &self.expected else {
+            return Ok(());
+        };
+        match self.computed.get() {
+            Some(computed) if !computed.matched => Err(KprintError::DigestMismatch {
+                algorithm: expected.algorithm.name(),
+                expected: STANDARD.encode(&expected.digest),
+                computed: STANDARD.encode(&computed.digest),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Answers with `result`, along with the digest that was worked out, or
+    /// with `DIGEST_MISMATCH` if it wasn't the expected one, whatever went
+    /// wrong because of it.
+    pub fn respond<R: Responder>(
+        &self,
+        result: Result<R, KprintError>,
+    ) -> Result<CustomizeResponder<R>, KprintError> {
+        if let Err(mismatch) = self.check() {
+            if result.is_ok() {
+                log::warn!("A document printed even though its digest didn't match");
+            }
+            return Err(mismatch);
+        }
+        let mut response = result?.customize();
+        if let (Some(expected), Some(computed)) = (&self.expected, self.computed.get()) {
+            let header = expected.header;
+            let value = header.format(expected.algorithm, &computed.digest);
+            response = response.insert_header((header.name(), value));
+        }
+        Ok(response)
+    }
+}
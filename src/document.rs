@@ -0,0 +1,169 @@
+//! Documents on their way to the printer, and whether kprint could send them
+//! again if the printer didn't take them the first time.
+
+use crate::error::KprintError;
+use actix_web::web::Bytes;
+use futures::{io::Cursor, AsyncReadExt};
+use ipp::prelude::IppPayload;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio_util::compat::{FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt};
+use tokio_util::io::StreamReader;
+use uuid::Uuid;
+
+/// Most of a spooled document read off disk at a time
+const READ_SIZE: usize = 64 * 1024;
+
+pub enum Document {
+    /// Still arriving from whoever's sending it, so it can only be read once
+    Streaming(IppPayload),
+    /// Held whole in memory
+    Buffered(Bytes),
+    /// On disk, in a file that's usually already been unlinked
+    Spooled(Arc<std::fs::File>),
+}
+
+impl From<IppPayload> for Document {
+    fn from(payload: IppPayload) -> Self {
+        Document::Streaming(payload)
+    }
+}
+
+impl From<Vec<u8>> for Document {
+    fn from(bytes: Vec<u8>) -> Self {
+        Document::Buffered(bytes.into())
+    }
+}
+
+impl From<std::fs::File> for Document {
+    fn from(file: std::fs::File) -> Self {
+        Document::Spooled(Arc::new(file))
+    }
+}
+
+impl Document {
+    /// Whether every call to [`Document::payload`] reads the whole document.
+    pub fn replayable(&self) -> bool {
+        !matches!(self, Document::Streaming(_))
+    }
+
+    /// A payload reading the document from the start. Streaming documents
+    /// hand over what's left of them, so reading one again finds it empty.
+    pub fn payload(&mut self) -> IppPayload {
+        match self {
+            Document::Streaming(payload) => std::mem::take(payload),
+            Document::Buffered(bytes) => IppPayload::new_async(Cursor::new(bytes.clone())),
+            Document::Spooled(file) => IppPayload::new_async(read_from_start(file.clone())),
+        }
+    }
+
+    /// Another handle on the same document, if it's one that can be read
+    /// again.
+    pub fn try_clone(&self) -> Option<Document> {
+        match self {
+            Document::Streaming(_) => None,
+            Document::Buffered(bytes) => Some(Document::Buffered(bytes.clone())),
+            Document::Spooled(file) => Some(Document::Spooled(file.clone())),
+        }
+    }
+
+    /// Up to the first `len` bytes of the document, leaving it to be read
+    /// from the start as before.
+    pub async fn head(&mut self, len: usize) -> Result<Bytes, KprintError> {
+        match self {
+            Document::Buffered(bytes) => Ok(bytes.slice(..len.min(bytes.len()))),
+            Document::Spooled(file) => {
+                let file = file.clone();
+                let head = tokio::task::spawn_blocking(move || {
+                    let mut head = vec![0; len];
+                    let mut read = 0;
+                    while read < len {
+                        match file.read_at(&mut head[read..], read as u64)? {
+                            0 => break,
+                            more => read += more,
+                        }
+                    }
+                    head.truncate(read);
+                    std::io::Result::Ok(head)
+                })
+                .await
+                .map_err(|err| KprintError::InternalError(err.into()))?
+                .map_err(KprintError::SpoolIo)?;
+                Ok(head.into())
+            }
+            Document::Streaming(payload) => {
+                let mut rest = std::mem::take(payload);
+                let mut head = vec![];
+                (&mut rest)
+                    .take(len as u64)
+                    .read_to_end(&mut head)
+                    .await
+                    .map_err(KprintError::SpoolIo)?;
+                let head = Bytes::from(head);
+                // Put back in front of what hasn't arrived yet
+                *payload = IppPayload::new_async(Cursor::new(head.clone()).chain(rest));
+                Ok(head)
+            }
+        }
+    }
+
+    /// Reads the whole document into memory.
+    pub async fn into_bytes(mut self) -> Result<Vec<u8>, KprintError> {
+        if let Document::Buffered(bytes) = self {
+            return Ok(bytes.into());
+        }
+        let mut bytes = vec![];
+        self.payload()
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(KprintError::SpoolIo)?;
+        Ok(bytes)
+    }
+}
+
+/// Writes all of `document` to a file in `dir`, so whoever's sending it isn't
+/// held to the printer's pace.
+pub async fn spool(document: IppPayload, dir: &Path) -> Result<Document, KprintError> {
+    let path = dir.join(format!("spool-{}", Uuid::new_v4()));
+    let mut file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .await
+        .map_err(KprintError::SpoolIo)?;
+    // Unlinked straight away, so it's gone however kprint exits
+    let _ = tokio::fs::remove_file(&path).await;
+    tokio::io::copy(&mut document.compat(), &mut file)
+        .await
+        .map_err(KprintError::SpoolIo)?;
+    file.flush().await.map_err(KprintError::SpoolIo)?;
+    Ok(file.into_std().await.into())
+}
+
+/// Reads `file` from the start, at offsets of its own rather than the file's,
+/// so an abandoned read can't throw off the next one.
+fn read_from_start(
+    file: Arc<std::fs::File>,
+) -> impl futures::AsyncRead + Send + Sync + Unpin + 'static {
+    let chunks = futures::stream::try_unfold(0, move |offset| {
+        let file = file.clone();
+        async move {
+            let chunk = tokio::task::spawn_blocking(move || {
+                let mut chunk = vec![0; READ_SIZE];
+                let read = file.read_at(&mut chunk, offset)?;
+                chunk.truncate(read);
+                std::io::Result::Ok(chunk)
+            })
+            .await??;
+            if chunk.is_empty() {
+                return std::io::Result::Ok(None);
+            }
+            let next = offset + chunk.len() as u64;
+            Ok(Some((Bytes::from(chunk), next)))
+        }
+    });
+    StreamReader::new(Box::pin(chunks)).compat()
+}
@@ -0,0 +1,146 @@
+//! Checking members are in good standing before they print, so an SSO
+//! account alone, like an alum's, isn't enough.
+//!
+//! Turned on by setting `KPRINT_ELIGIBILITY_URL` to the membership API, with
+//! a `{username}` path segment where the member goes, like
+//! `https://conditional.csh.rit.edu/api/members/{username}/standing`. It's
+//! sent `KPRINT_ELIGIBILITY_TOKEN` as a bearer token when that's set, and
+//! should answer `{"eligible": true}`, or `{"eligible": false, "reason":
+//! "..."}` with the reason shown to the member. A 404 means they aren't a
+//! member at all.
+//!
+//! Answers are kept for `KPRINT_ELIGIBILITY_CACHE_TTL` seconds. When the API
+//! can't be reached jobs are turned away, unless `KPRINT_ELIGIBILITY_FAIL_OPEN`
+//! is `true`, in which case they're let through. Admins are never checked,
+//! so they can still print when the API's down.
+
+use crate::error::KprintError;
+use crate::logging::Pii;
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use reqwest::{StatusCode, Url};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(15 * 60);
+/// How long the API gets to answer, since jobs wait on it
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// The path segment of `KPRINT_ELIGIBILITY_URL` the member goes in, as it
+/// looks once parsed
+const USERNAME: &str = "%7Busername%7D";
+/// What members are told when the API doesn't say why they can't print
+const DEFAULT_REASON: &str = "only members in good standing can print";
+
+#[derive(Deserialize)]
+struct Standing {
+    eligible: bool,
+    reason: Option<String>,
+}
+
+/// Whether a member can print, and why not when they can't.
+type Verdict = Result<(), String>;
+
+pub struct Eligibility {
+    url: Url,
+    http: reqwest::Client,
+    cache_ttl: Duration,
+    fail_open: bool,
+    verdicts: Mutex<HashMap<String, (Instant, Verdict)>>,
+}
+
+impl Eligibility {
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(url) = std::env::var("KPRINT_ELIGIBILITY_URL") else {
+            return Ok(None);
+        };
+        let url = Url::parse(&url)?;
+        if !url
+            .path_segments()
+            .is_some_and(|mut segments| segments.any(|segment| segment == USERNAME))
+        {
+            anyhow::bail!("KPRINT_ELIGIBILITY_URL needs a {{username}} path segment");
+        }
+        let mut headers = HeaderMap::new();
+        if let Ok(token) = std::env::var("KPRINT_ELIGIBILITY_TOKEN") {
+            let mut token = HeaderValue::from_str(&format!("Bearer {token}"))?;
+            token.set_sensitive(true);
+            headers.insert(header::AUTHORIZATION, token);
+        }
+        let http = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .user_agent(concat!("kprint/", env!("CARGO_PKG_VERSION")))
+            .default_headers(headers)
+            .build()?;
+        let cache_ttl = match std::env::var("KPRINT_ELIGIBILITY_CACHE_TTL") {
+            Ok(seconds) => Duration::from_secs(seconds.parse()?),
+            Err(_) => DEFAULT_CACHE_TTL,
+        };
+        let fail_open = match std::env::var("KPRINT_ELIGIBILITY_FAIL_OPEN") {
+            Ok(fail_open) => fail_open.parse()?,
+            Err(_) => false,
+        };
+        Ok(Some(Eligibility {
+            url,
+            http,
+            cache_ttl,
+            fail_open,
+            verdicts: Mutex::default(),
+        }))
+    }
+
+    /// Turns `username` away unless they're in good standing.
+    pub async fn check(&self, username: &str) -> Result<(), KprintError> {
+        let cached = self
+            .verdicts
+            .lock()
+            .unwrap()
+            .get(username)
+            .filter(|(checked_at, _)| checked_at.elapsed() < self.cache_ttl)
+            .map(|(_, verdict)| verdict.clone());
+        let verdict = match cached {
+            Some(verdict) => verdict,
+            None => match self.ask(username).await {
+                Ok(verdict) => {
+                    self.verdicts
+                        .lock()
+                        .unwrap()
+                        .insert(username.to_string(), (Instant::now(), verdict.clone()));
+                    verdict
+                }
+                Err(err) if self.fail_open => {
+                    log::warn!(
+                        "Couldn't check {}'s standing, letting them print: {err}",
+                        Pii(username)
+                    );
+                    Ok(())
+                }
+                Err(err) => {
+                    log::warn!("Couldn't check {}'s standing: {err}", Pii(username));
+                    return Err(KprintError::EligibilityUnavailable);
+                }
+            },
+        };
+        verdict.map_err(KprintError::NotEligible)
+    }
+
+    /// What the API says about `username`.
+    async fn ask(&self, username: &str) -> anyhow::Result<Verdict> {
+        // Escaped the way a path segment would be
+        let mut escaped = Url::parse("http://kprint/").unwrap();
+        escaped.path_segments_mut().unwrap().pop().push(username);
+        let url = self.url.as_str().replace(USERNAME, &escaped.path()[1..]);
+        let response = self.http.get(url).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Err(DEFAULT_REASON.to_string()));
+        }
+        let body = response.error_for_status()?.bytes().await?;
+        let standing: Standing = serde_json::from_slice(&body)?;
+        Ok(match standing.eligible {
+            true => Ok(()),
+            false => Err(standing
+                .reason
+                .unwrap_or_else(|| DEFAULT_REASON.to_string())),
+        })
+    }
+}
@@ -0,0 +1,261 @@
+//! Email-to-print: a mailbox that prints the PDFs members send it, and replies
+//! saying how it went.
+//!
+//! Turned on by setting `KPRINT_EMAIL_IMAP_HOST`, along with
+//! `KPRINT_EMAIL_SMTP_HOST`, `KPRINT_EMAIL_ADDRESS`, `KPRINT_EMAIL_USERNAME`,
+//! `KPRINT_EMAIL_PASSWORD`, and `KPRINT_EMAIL_PRINTER` for where jobs go when
+//! the subject doesn't name a printer.
+
+use crate::app::AppState;
+use crate::logging::Pii;
+use crate::options::MAX_TITLE_LENGTH;
+use crate::service::{self, Submitted};
+use actix_web::web::Data;
+use kprint_client::models::{ColorMode, DuplexMode, PageSelection, PrintOptions};
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Tokio1Executor,
+};
+use mail_parser::{MessageParser, MimeHeaders};
+use std::time::Duration;
+
+/// How long to wait between checks for new mail
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Only mail from addresses on this domain gets printed, as the member whose
+/// username is in front of the `@`
+const MEMBER_DOMAIN: &str = "csh.rit.edu";
+
+pub struct EmailConfig {
+    imap_host: String,
+    smtp_host: String,
+    address: Mailbox,
+    username: String,
+    password: String,
+    printer: String,
+}
+
+impl EmailConfig {
+    /// Reads the gateway's settings, or `None` if it isn't turned on.
+    pub fn from_env() -> Option<anyhow::Result<Self>> {
+        let imap_host = std::env::var("KPRINT_EMAIL_IMAP_HOST").ok()?;
+        let var = |name: &str| {
+            std::env::var(name).map_err(|_| anyhow::anyhow!("Email printing needs {name}"))
+        };
+        Some((|| {
+            Ok(EmailConfig {
+                imap_host,
+                smtp_host: var("KPRINT_EMAIL_SMTP_HOST")?,
+                address: var("KPRINT_EMAIL_ADDRESS")?.parse()?,
+                username: var("KPRINT_EMAIL_USERNAME")?,
+                password: var("KPRINT_EMAIL_PASSWORD")?,
+                printer: var("KPRINT_EMAIL_PRINTER")?,
+            })
+        })())
+    }
+}
+
+/// Takes every unread message out of the inbox, marking them read.
+fn fetch_unseen(config: &EmailConfig) -> anyhow::Result<Vec<Vec<u8>>> {
+    let tls = native_tls::TlsConnector::new()?;
+    let client = imap::connect((config.imap_host.as_str(), 993), &config.imap_host, &tls)?;
+    let mut session = client
+        .login(&config.username, &config.password)
+        .map_err(|(err, _)| err)?;
+    session.select("INBOX")?;
+    let uids = session.uid_search("UNSEEN")?;
+    let mut messages = vec![];
+    if !uids.is_empty() {
+        let uids = uids
+            .iter()
+            .map(|uid| uid.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        // Unlike BODY.PEEK[], this sets \Seen, so nothing gets printed twice
+        for fetch in session.uid_fetch(uids, "BODY[]")?.iter() {
+            if let Some(body) = fetch.body() {
+                messages.push(body.to_vec());
+            }
+        }
+    }
+    session.logout()?;
+    Ok(messages)
+}
+
+/// The member an address belongs to, if it's one of theirs.
+fn member(address: &str) -> Option<&str> {
+    let (username, domain) = address.rsplit_once('@')?;
+    domain
+        .eq_ignore_ascii_case(MEMBER_DOMAIN)
+        .then_some(username)
+}
+
+/// Whether our mail server vouched for the sender, since anyone can write
+/// anything in `From`.
+fn authenticated(message: &mail_parser::Message) -> bool {
+    message
+        .header_values("Authentication-Results")
+        .filter_map(|value| value.as_text())
+        .any(|results| results.contains("dmarc=pass"))
+}
+
+/// An attachment's name, cut down to fit in a job title if it has to be.
+fn title(name: &str) -> String {
+    let mut title = name.to_string();
+    while title.len() > MAX_TITLE_LENGTH {
+        title.pop();
+    }
+    title
+}
+
+/// Prints a message's PDFs, returning a reply saying how it went, or `None` if
+/// the sender shouldn't hear back at all.
+async fn print_message(
+    app_data: &AppState,
+    config: &EmailConfig,
+    raw: &[u8],
+) -> Option<lettre::Message> {
+    let message = MessageParser::default().parse(raw)?;
+    let from = message.from()?.first()?.address()?;
+    let Some(username) = member(from).filter(|_| authenticated(&message)) else {
+        log::info!(
+            "Ignoring an email from {}, who isn't a member or couldn't prove it",
+            Pii(from)
+        );
+        return None;
+    };
+    let subject = message.subject().unwrap_or_default();
+    let printer = subject
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .find(|word| app_data.printers.contains_key(word))
+        .unwrap_or_else(|| config.printer.clone());
+
+    let mut results = vec![];
+    for attachment in message.attachments() {
+        let is_pdf = attachment.content_type().is_some_and(|content_type| {
+            content_type.ctype().eq_ignore_ascii_case("application")
+                && content_type
+                    .subtype()
+                    .is_some_and(|subtype| subtype.eq_ignore_ascii_case("pdf"))
+        });
+        if !is_pdf {
+            continue;
+        }
+        let name = attachment
+            .attachment_name()
+            .unwrap_or("Untitled")
+            .to_string();
+        // Nobody can pick options over email, so these are the cheapest ones
+        let options = PrintOptions {
+            sides: DuplexMode::OneSided,
+            color_mode: ColorMode::Grayscale,
+            pages: PageSelection::all(),
+            copies: 1,
+            title: title(&name),
+            cover_sheet: false,
+            allow_fallback: false,
+            separate_jobs: false,
+            tags: vec![],
+            media: None,
+            margins: None,
+            offset: None,
+            poster: None,
+            envelope: None,
+        };
+        let document = attachment.contents().to_vec().into();
+        let result = match service::submit(
+            app_data,
+            username,
+            false,
+            // Emails don't say who's in what groups, so senders get the
+            // default quota tier
+            &[],
+            printer.clone(),
+            options,
+            document,
+        )
+        .await
+        {
+            Ok(Submitted { job, overrides, .. }) => {
+                let forced = overrides
+                    .iter()
+                    .map(|forced| {
+                        format!(
+                            " {printer} only prints with {} {}.",
+                            forced.option, forced.applied
+                        )
+                    })
+                    .collect::<String>();
+                format!(
+                    "Printed {name} on {printer}. Its job id is {}.{forced}",
+                    job.id
+                )
+            }
+            Err(err) => format!("Couldn't print {name}: {err}"),
+        };
+        log::info!("Email from {}: {result}", Pii(username));
+        results.push(result);
+    }
+    if results.is_empty() {
+        results.push("There weren't any PDFs attached to print.".to_string());
+    }
+
+    let mut reply = lettre::Message::builder()
+        .from(config.address.clone())
+        .to(from.parse().ok()?)
+        .subject(format!("Re: {subject}"));
+    if let Some(id) = message.message_id() {
+        reply = reply
+            .in_reply_to(format!("<{id}>"))
+            .references(format!("<{id}>"));
+    }
+    match reply.body(results.join("\n")) {
+        Ok(reply) => Some(reply),
+        Err(err) => {
+            log::warn!("Couldn't write a reply to {}: {err}", Pii(from));
+            None
+        }
+    }
+}
+
+/// Checks the mailbox forever, printing whatever arrives.
+pub async fn poll(app_data: Data<AppState>, config: EmailConfig) {
+    let config = std::sync::Arc::new(config);
+    let smtp = match AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host) {
+        Ok(smtp) => smtp
+            .credentials(Credentials::new(
+                config.username.clone(),
+                config.password.clone(),
+            ))
+            .build(),
+        Err(err) => {
+            log::error!("Email printing is off, since SMTP couldn't be set up: {err}");
+            return;
+        }
+    };
+    log::info!("Printing email sent to {}", config.address);
+    loop {
+        let fetching = config.clone();
+        let messages = match tokio::task::spawn_blocking(move || fetch_unseen(&fetching)).await {
+            Ok(Ok(messages)) => messages,
+            Ok(Err(err)) => {
+                log::warn!("Couldn't check for print emails: {err}");
+                vec![]
+            }
+            Err(err) => {
+                log::error!("Checking for print emails panicked: {err}");
+                vec![]
+            }
+        };
+        for raw in messages {
+            let Some(reply) = print_message(&app_data, &config, &raw).await else {
+                continue;
+            };
+            if let Err(err) = smtp.send(reply).await {
+                log::warn!("Couldn't reply to a print email: {err}");
+            }
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,163 @@
+//! Envelopes, without having to know how each printer wants them fed.
+//!
+//! The `envelope` option picks the envelope's size as the media, one-sided,
+//! fed short edge first, from the printer's envelope feeder. Which tray that
+//! is is guessed from what the printer says it has, trying `envelope`, then
+//! `manual`, then `by-pass-tray`, unless `KPRINT_ENVELOPE_TRAYS` says, as a
+//! space-separated list like `lounge:tray-1 library:manual`.
+//!
+//! There's also a page of just the addresses, laid out for an envelope, for
+//! anyone who doesn't have one to print already.
+
+use crate::error::{ErrorCode, FieldError};
+use crate::jobs::find_keywords;
+use crate::pdf;
+use ipp::prelude::IppAttributes;
+use kprint_client::models::{Envelope, EnvelopeAddress, Media};
+use lopdf::{dictionary, Dictionary, Document, Object, Stream};
+use std::collections::HashMap;
+
+/// Where printers tend to take envelopes from, best first
+const TRAYS: &[&str] = &["envelope", "manual", "by-pass-tray"];
+const FONT: &str = "KprintEnvelopeFont";
+/// How far the return address is in from the top left corner, in points
+const INSET: f32 = 27.0;
+/// Most lines either address can be, which is more than the post office
+/// wants anyway
+pub const MAX_ADDRESS_LINES: usize = 6;
+pub const MAX_LINE_LENGTH: usize = 60;
+
+/// Which tray each printer takes envelopes from, when it's not the one kprint
+/// would guess.
+#[derive(Default)]
+pub struct EnvelopeTrays(HashMap<String, String>);
+
+impl EnvelopeTrays {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let Ok(trays) = std::env::var("KPRINT_ENVELOPE_TRAYS") else {
+            return Ok(EnvelopeTrays::default());
+        };
+        trays
+            .split_whitespace()
+            .map(|entry| match entry.split_once(':') {
+                Some((printer, tray)) if !tray.is_empty() => {
+                    Ok((printer.to_string(), tray.to_string()))
+                }
+                _ => anyhow::bail!("{entry:?} should look like printer:tray"),
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(EnvelopeTrays)
+    }
+
+    pub fn get(&self, printer: &str) -> Option<&str> {
+        self.0.get(printer).map(String::as_str)
+    }
+}
+
+/// How big `envelope` is, short edge then long, in hundredths of a
+/// millimeter.
+pub fn size(envelope: Envelope) -> (u32, u32) {
+    match envelope {
+        Envelope::Number10 => (10478, 24130),
+        Envelope::Monarch => (9843, 19050),
+        Envelope::Dl => (11000, 22000),
+        Envelope::C5 => (16200, 22900),
+    }
+}
+
+/// What a printer with `attributes` prints `envelope` on, from `tray` if
+/// it's been set. Only what the printer says it has is asked for, so the
+/// size is left to pick the tray on printers that don't say.
+pub fn media(envelope: Envelope, tray: Option<&str>, attributes: &IppAttributes) -> Media {
+    let (x, y) = size(envelope);
+    let sources = find_keywords(attributes, "media-source-supported");
+    let source = tray.map(str::to_string).or_else(|| {
+        TRAYS
+            .iter()
+            .find(|tray| sources.iter().any(|source| source == *tray))
+            .map(|tray| tray.to_string())
+    });
+    let types = find_keywords(attributes, "media-type-supported");
+    let media_type = types
+        .iter()
+        .any(|media_type| media_type == "envelope")
+        .then(|| "envelope".to_string());
+    Media {
+        x_dimension: Some(x),
+        y_dimension: Some(y),
+        margins: None,
+        source,
+        media_type,
+    }
+}
+
+/// Everything wrong with `address`, which has to have somewhere for the
+/// envelope to go, and fit on it.
+pub fn check(address: &EnvelopeAddress) -> Vec<FieldError> {
+    let mut fields = vec![];
+    if address.to.iter().all(|line| line.trim().is_empty()) {
+        fields.push(FieldError::missing("to"));
+    }
+    for (field, lines) in [("from", &address.from), ("to", &address.to)] {
+        if lines.len() > MAX_ADDRESS_LINES
+            || lines
+                .iter()
+                .any(|line| line.chars().count() > MAX_LINE_LENGTH)
+        {
+            fields.push(FieldError::new(
+                field,
+                ErrorCode::ValueOutOfRange,
+                format!(
+                    "Addresses can be at most {MAX_ADDRESS_LINES} lines of {MAX_LINE_LENGTH} characters"
+                ),
+            ));
+        }
+    }
+    fields
+}
+
+/// Draws `address` on a page the size of its envelope, lying on its side the
+/// way it's read.
+pub fn render(address: &EnvelopeAddress) -> anyhow::Result<Vec<u8>> {
+    let (short, long) = size(address.envelope);
+    let [width, height] = [long, short].map(|length| length as f32 * 72.0 / 2540.0);
+
+    let mut content = String::new();
+    for (lines, size, x, top) in [
+        (&address.from, 9.0, INSET, height - INSET),
+        // The middle of the envelope is about where the post office looks
+        (&address.to, 12.0, width * 0.45, height * 0.55),
+    ] {
+        content += &format!("BT 0 g /{FONT} {size} Tf {x} {} Td ", top - size);
+        for line in lines {
+            content += &format!("({}) Tj 0 {} Td ", pdf::string(line), -size * 1.25);
+        }
+        content += "ET ";
+    }
+
+    let mut document = Document::with_version("1.5");
+    let pages_id = document.new_object_id();
+    let font = pdf::helvetica(&mut document);
+    let content = document.add_object(Stream::new(Dictionary::new(), content.into_bytes()));
+    let page = document.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => pages_id,
+        "Contents" => content,
+        "Resources" => dictionary! { "Font" => dictionary! { FONT => font } },
+    });
+    document.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page.into()],
+            "Count" => 1,
+            "MediaBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+        }),
+    );
+    let catalog = document.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    document.trailer.set("Root", catalog);
+    pdf::save(document)
+}
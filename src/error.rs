@@ -0,0 +1,557 @@
+use crate::ipp_client::IppError;
+use crate::request_id::RequestId;
+use crate::state_reasons::PrinterCondition;
+use crate::version::ApiVersion;
+use actix_web::{
+    body::MessageBody,
+    dev::ServiceResponse,
+    http::{header, StatusCode},
+    middleware::ErrorHandlerResponse,
+    HttpMessage, HttpResponse, ResponseError,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use ipp::model::StatusCode as IppStatus;
+use itertools::Itertools;
+use uuid::Uuid;
+
+pub use kprint_client::models::{ErrorBody, ErrorCode, FieldError};
+
+/// Best guess at a code for errors that didn't come from kprint itself,
+/// like actix's own 404s and extractor failures.
+fn code_for_status(status: StatusCode) -> ErrorCode {
+    match status {
+        StatusCode::UNAUTHORIZED => ErrorCode::Unauthenticated,
+        StatusCode::FORBIDDEN => ErrorCode::Forbidden,
+        StatusCode::NOT_FOUND => ErrorCode::NotFound,
+        StatusCode::METHOD_NOT_ALLOWED => ErrorCode::MethodNotAllowed,
+        StatusCode::NOT_ACCEPTABLE => ErrorCode::UnsupportedApiVersion,
+        status if status.is_client_error() => ErrorCode::InvalidRequest,
+        _ => ErrorCode::InternalError,
+    }
+}
+
+/// Why a printer that answered with `status` wouldn't do what it was asked,
+/// in words for whoever asked.
+fn refusal(status: IppStatus) -> &'static str {
+    match status {
+        IppStatus::ClientErrorDocumentFormatNotSupported => "it can't print documents of that type",
+        IppStatus::ClientErrorDocumentFormatError => "the document looks damaged",
+        IppStatus::ClientErrorDocumentAccessError => "it couldn't read the document",
+        IppStatus::ClientErrorCompressionNotSupported | IppStatus::ClientErrorCompressionError => {
+            "it couldn't uncompress the document"
+        }
+        IppStatus::ClientErrorAttributesOrValuesNotSupported => "it can't print with those options",
+        IppStatus::ClientErrorConflictingAttributes => "those options don't go together",
+        IppStatus::ClientErrorRequestEntityTooLong => "the document is too big for it",
+        IppStatus::ClientErrorRequestValueTooLong => "one of the options is too long",
+        IppStatus::ClientErrorForbidden
+        | IppStatus::ClientErrorNotAuthenticated
+        | IppStatus::ClientErrorNotAuthorized => "kprint isn't allowed to do that there",
+        IppStatus::ClientErrorNotPossible => "that isn't possible right now",
+        IppStatus::ClientErrorNotFound | IppStatus::ClientErrorGone => {
+            "the print server doesn't know about it"
+        }
+        IppStatus::ClientErrorTimeout => "the document took too long to arrive",
+        IppStatus::ServerErrorJobCanceled => "the job was canceled",
+        IppStatus::ServerErrorNotAcceptingJobs => "it isn't taking jobs right now",
+        IppStatus::ServerErrorDeviceError => "something's wrong with the printer itself",
+        IppStatus::ServerErrorBusy
+        | IppStatus::ServerErrorServiceUnavailable
+        | IppStatus::ServerErrorTemporaryError => "it's busy, try again later",
+        _ => "something went wrong on the print server",
+    }
+}
+
+/// The HTTP status for a printer refusing with `status`: the client's fault
+/// when it's the document or options, the print server's otherwise.
+fn refusal_status(status: IppStatus) -> StatusCode {
+    match status {
+        IppStatus::ClientErrorDocumentFormatNotSupported
+        | IppStatus::ClientErrorCompressionNotSupported => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        IppStatus::ClientErrorDocumentFormatError
+        | IppStatus::ClientErrorDocumentAccessError
+        | IppStatus::ClientErrorCompressionError
+        | IppStatus::ClientErrorAttributesOrValuesNotSupported
+        | IppStatus::ClientErrorConflictingAttributes
+        | IppStatus::ClientErrorRequestValueTooLong => StatusCode::UNPROCESSABLE_ENTITY,
+        IppStatus::ClientErrorRequestEntityTooLong => StatusCode::PAYLOAD_TOO_LARGE,
+        IppStatus::ClientErrorNotPossible | IppStatus::ServerErrorJobCanceled => {
+            StatusCode::CONFLICT
+        }
+        IppStatus::ClientErrorTimeout => StatusCode::GATEWAY_TIMEOUT,
+        IppStatus::ServerErrorNotAcceptingJobs
+        | IppStatus::ServerErrorBusy
+        | IppStatus::ServerErrorServiceUnavailable
+        | IppStatus::ServerErrorTemporaryError => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+/// `status` the way IPP writes it, like `client-error-document-format-error`.
+fn keyword(status: IppStatus) -> String {
+    let mut keyword = String::new();
+    for c in format!("{status:?}").chars() {
+        if c.is_uppercase() && !keyword.is_empty() {
+            keyword.push('-');
+        }
+        keyword.push(c.to_ascii_lowercase());
+    }
+    keyword
+}
+
+/// Builds the response for an error body. The body is also stashed in the
+/// response extensions so [`render_error`] can fill in the request id and
+/// pick the format the client negotiated.
+pub fn error_response(body: ErrorBody, status: StatusCode) -> HttpResponse {
+    let mut response = HttpResponse::build(status).json(&body);
+    response.extensions_mut().insert(body);
+    response
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum KprintError {
+    /// Something that shouldn't ever happen, like a task panicking
+    #[error("an unspecified internal error occurred: {0}")]
+    InternalError(anyhow::Error),
+    #[error("The print server said something kprint didn't expect: {0}")]
+    IppProtocol(String),
+    #[error("Couldn't reach the print server, try again later: {0}")]
+    CupsUnreachable(String),
+    /// The proxy in front of CUPS turned kprint away, which only an operator
+    /// can fix
+    #[error("The print server isn't letting kprint in, an admin has been told: {0}")]
+    CupsProxyAuth(String),
+    /// Reading or writing a document kprint's holding on to
+    #[error("Couldn't handle the document on disk: {0}")]
+    SpoolIo(std::io::Error),
+    #[error("Couldn't convert the document: {0}")]
+    Conversion(anyhow::Error),
+    /// Saving or loading kprint's own records
+    #[error("Couldn't save that, try again later: {0}")]
+    Database(anyhow::Error),
+    #[error("Actix error: {0}")]
+    Actix(#[from] actix_web::error::Error),
+    #[error("Invalid print options: {}", .0.iter().map(|err| &err.message).join("; "))]
+    Validation(Vec<FieldError>),
+    #[error("Printer named {0} doesn't exist!")]
+    PrinterNotFound(String),
+    #[error("No job with id {0}")]
+    JobNotFound(Uuid),
+    #[error("Job {0} can't be canceled, it has already finished")]
+    JobNotCancelable(Uuid),
+    #[error("Job {0} isn't waiting to print, so it can't be moved up")]
+    JobNotPending(Uuid),
+    #[error("Job {0} isn't open, so documents can't be added to it")]
+    JobNotOpen(Uuid),
+    #[error("Missing or invalid credentials")]
+    Unauthenticated,
+    #[error("Only kprint admins can do that")]
+    Forbidden,
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("Couldn't fetch the document: {0}")]
+    DocumentFetchFailed(String),
+    #[error("The document is bigger than the {limit} byte limit")]
+    DocumentTooLarge { limit: u64 },
+    #[error(
+        "Documents of type {0} can't be printed, try {}",
+        crate::sniff::printable_names()
+    )]
+    UnsupportedDocumentType(String),
+    #[error("The document looks infected with {0}, so it wasn't printed")]
+    DocumentInfected(String),
+    /// What went wrong is for the logs, not the person printing
+    #[error("Couldn't scan the document for viruses, try again later")]
+    ScannerUnavailable(String),
+    #[error("{0} is paused for maintenance, try another printer")]
+    PrinterPaused(String),
+    #[error("{printer} is down for maintenance: {message}")]
+    PrinterInMaintenance { printer: String, message: String },
+    #[error(
+        "The printer backend for {printer} is unhealthy, try again after {}",
+        retry_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+    )]
+    PrinterUnhealthy {
+        printer: String,
+        retry_at: DateTime<Utc>,
+    },
+    #[error("{0} couldn't be reached, try again later")]
+    PrinterUnavailable(String),
+    /// CUPS answered a request for `printer` with a failure `status`
+    #[error(
+        "{printer} turned that down: {}{}",
+        refusal(*status),
+        condition.summary().map(|summary| format!(" ({summary})")).unwrap_or_default()
+    )]
+    PrinterRefused {
+        printer: String,
+        status: IppStatus,
+        /// CUPS's own explanation, if it gave one
+        status_message: Option<String>,
+        /// How the printer was doing after it refused
+        condition: PrinterCondition,
+    },
+    #[error(
+        "{printer} has stopped: {}",
+        condition.summary().unwrap_or_else(|| "try another printer".to_string())
+    )]
+    PrinterStopped {
+        printer: String,
+        condition: PrinterCondition,
+    },
+    #[error("The printer wouldn't take the job: {0}")]
+    JobRejected(String),
+    #[error("Job {0} doesn't have a preview, at least not yet")]
+    PreviewNotFound(Uuid),
+    #[error("No upload with id {0}")]
+    UploadNotFound(Uuid),
+    #[error("No feature flag called {0}")]
+    FeatureFlagNotFound(String),
+    #[error("The document's {algorithm} digest is {computed}, not {expected}, so it was corrupted on the way")]
+    DigestMismatch {
+        algorithm: &'static str,
+        expected: String,
+        computed: String,
+    },
+    #[error("That took longer than {}s, so kprint gave up on it", .0.as_secs())]
+    RequestTimedOut(std::time::Duration),
+    #[error("The upload is at byte {expected}, so chunks have to start there")]
+    UploadOffsetMismatch { expected: u64 },
+    #[error("Only {offset} of the upload's {length} bytes have arrived")]
+    UploadIncomplete { offset: u64, length: u64 },
+    #[error(
+        "{}, until {}",
+        match needed {
+            Some(needed) => format!("That job takes {needed} units of quota, and you've only got {remaining} left"),
+            None => "You've printed as much as your quota allows".to_string(),
+        },
+        resets_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+    )]
+    QuotaExceeded {
+        remaining: u32,
+        /// What the job would have taken, if kprint could count it
+        needed: Option<u32>,
+        resets_at: DateTime<Utc>,
+    },
+    #[error("Couldn't check your print quota, try again later")]
+    QuotaUnavailable,
+    #[error("You can't print right now: {0}")]
+    NotEligible(String),
+    #[error(
+        "Your printing is suspended{}: {reason}",
+        match until {
+            Some(until) => format!(" until {}", until.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            None => String::new(),
+        }
+    )]
+    UserSuspended {
+        reason: String,
+        until: Option<DateTime<Utc>>,
+    },
+    #[error("Couldn't check whether you can print, try again later")]
+    EligibilityUnavailable,
+    #[error("That guest code doesn't exist, or has expired")]
+    GuestCodeInvalid,
+    #[error(
+        "That guest code only has {remaining} page{} left",
+        if *remaining == 1 { "" } else { "s" }
+    )]
+    GuestCodeUsedUp { remaining: u32 },
+    #[error("That pickup link isn't right, or has expired")]
+    PickupLinkInvalid,
+    #[error("That link to a job isn't right, or has expired")]
+    JobLinkInvalid,
+    #[error("Job {0} isn't being held, so there's nothing to release")]
+    JobNotHeld(Uuid),
+    #[error("API version {requested} is not supported, try one of: {supported}")]
+    UnsupportedApiVersion {
+        requested: String,
+        supported: String,
+    },
+}
+
+impl From<IppError> for KprintError {
+    fn from(err: IppError) -> Self {
+        match err {
+            IppError::Unreachable { .. } | IppError::TimedOut { .. } => {
+                Self::CupsUnreachable(err.to_string())
+            }
+            IppError::ProxyAuth { .. } => Self::CupsProxyAuth(err.to_string()),
+            IppError::Protocol { .. } => Self::IppProtocol(err.to_string()),
+            IppError::Null(err) => Self::InternalError(err),
+        }
+    }
+}
+
+impl KprintError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::InternalError(_) => ErrorCode::InternalError,
+            Self::IppProtocol(_) => ErrorCode::IppProtocolError,
+            Self::CupsUnreachable(_) => ErrorCode::CupsUnreachable,
+            Self::CupsProxyAuth(_) => ErrorCode::CupsProxyAuthFailed,
+            Self::SpoolIo(_) => ErrorCode::SpoolIoError,
+            Self::Conversion(_) => ErrorCode::ConversionFailed,
+            Self::Database(_) => ErrorCode::DatabaseError,
+            Self::Actix(err) => code_for_status(err.as_response_error().status_code()),
+            Self::Validation(_) => ErrorCode::ValidationFailed,
+            Self::PrinterNotFound(_) => ErrorCode::PrinterNotFound,
+            Self::JobNotFound(_) => ErrorCode::JobNotFound,
+            Self::JobNotCancelable(_) => ErrorCode::JobNotCancelable,
+            Self::JobNotPending(_) => ErrorCode::JobNotPending,
+            Self::JobNotOpen(_) => ErrorCode::JobNotOpen,
+            Self::Unauthenticated => ErrorCode::Unauthenticated,
+            Self::Forbidden => ErrorCode::Forbidden,
+            Self::InvalidRequest(_) => ErrorCode::InvalidRequest,
+            Self::DocumentFetchFailed(_) => ErrorCode::DocumentFetchFailed,
+            Self::DocumentTooLarge { .. } => ErrorCode::DocumentTooLarge,
+            Self::UnsupportedDocumentType(_) => ErrorCode::UnsupportedDocumentType,
+            Self::DocumentInfected(_) => ErrorCode::DocumentInfected,
+            Self::ScannerUnavailable(_) => ErrorCode::ScannerUnavailable,
+            Self::JobRejected(_) => ErrorCode::JobRejected,
+            Self::PrinterUnavailable(_) => ErrorCode::PrinterUnavailable,
+            Self::PrinterRefused { .. } => ErrorCode::PrinterRefused,
+            Self::PrinterStopped { .. } => ErrorCode::PrinterStopped,
+            Self::PrinterUnhealthy { .. } => ErrorCode::PrinterUnhealthy,
+            Self::PrinterPaused(_) => ErrorCode::PrinterPaused,
+            Self::PrinterInMaintenance { .. } => ErrorCode::PrinterInMaintenance,
+            Self::PreviewNotFound(_) => ErrorCode::PreviewNotFound,
+            Self::UploadNotFound(_) => ErrorCode::UploadNotFound,
+            Self::FeatureFlagNotFound(_) => ErrorCode::FeatureFlagNotFound,
+            Self::DigestMismatch { .. } => ErrorCode::DigestMismatch,
+            Self::RequestTimedOut(_) => ErrorCode::RequestTimedOut,
+            Self::UploadOffsetMismatch { .. } => ErrorCode::UploadOffsetMismatch,
+            Self::UploadIncomplete { .. } => ErrorCode::UploadIncomplete,
+            Self::QuotaExceeded { .. } => ErrorCode::QuotaExceeded,
+            Self::QuotaUnavailable => ErrorCode::QuotaUnavailable,
+            Self::NotEligible(_) => ErrorCode::NotEligible,
+            Self::UserSuspended { .. } => ErrorCode::UserSuspended,
+            Self::EligibilityUnavailable => ErrorCode::EligibilityUnavailable,
+            Self::GuestCodeInvalid => ErrorCode::GuestCodeInvalid,
+            Self::GuestCodeUsedUp { .. } => ErrorCode::GuestCodeUsedUp,
+            Self::PickupLinkInvalid => ErrorCode::PickupLinkInvalid,
+            Self::JobLinkInvalid => ErrorCode::JobLinkInvalid,
+            Self::JobNotHeld(_) => ErrorCode::JobNotHeld,
+            Self::UnsupportedApiVersion { .. } => ErrorCode::UnsupportedApiVersion,
+        }
+    }
+
+    fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            Self::Validation(fields) => Some(serde_json::json!({ "fields": fields })),
+            Self::PrinterNotFound(printer)
+            | Self::PrinterPaused(printer)
+            | Self::PrinterUnavailable(printer) => Some(serde_json::json!({ "printer": printer })),
+            Self::PrinterRefused {
+                printer,
+                status,
+                status_message,
+                condition,
+            } => {
+                let mut details = condition.details();
+                details["printer"] = printer.as_str().into();
+                details["ippStatus"] = keyword(*status).into();
+                details["statusMessage"] = status_message.as_deref().into();
+                Some(details)
+            }
+            Self::PrinterStopped { printer, condition } => {
+                let mut details = condition.details();
+                details["printer"] = printer.as_str().into();
+                Some(details)
+            }
+            // For whoever looks into it, since the person printing can't help
+            Self::CupsProxyAuth(_) => Some(serde_json::json!({
+                "hint": "Check KPRINT_CUPS_PROXY_TOKEN is set and hasn't expired or been revoked",
+            })),
+            Self::PrinterUnhealthy { printer, retry_at } => {
+                Some(serde_json::json!({ "printer": printer, "retryAt": retry_at }))
+            }
+            Self::PrinterInMaintenance { printer, message } => {
+                Some(serde_json::json!({ "printer": printer, "message": message }))
+            }
+            Self::DocumentTooLarge { limit } => Some(serde_json::json!({ "limit": limit })),
+            Self::DigestMismatch {
+                algorithm,
+                expected,
+                computed,
+            } => Some(serde_json::json!({
+                "algorithm": algorithm,
+                "expected": expected,
+                "computed": computed,
+            })),
+            Self::RequestTimedOut(timeout) => {
+                Some(serde_json::json!({ "timeoutSeconds": timeout.as_secs() }))
+            }
+            Self::FeatureFlagNotFound(_) => {
+                let flags = crate::flags::Flag::ALL.iter().map(|flag| flag.name());
+                Some(serde_json::json!({ "flags": flags.collect::<Vec<_>>() }))
+            }
+            Self::DocumentInfected(signature) => {
+                Some(serde_json::json!({ "signature": signature }))
+            }
+            Self::UploadOffsetMismatch { expected } => {
+                Some(serde_json::json!({ "offset": expected }))
+            }
+            Self::UploadIncomplete { offset, length } => {
+                Some(serde_json::json!({ "offset": offset, "length": length }))
+            }
+            Self::QuotaExceeded {
+                remaining,
+                needed,
+                resets_at,
+            } => Some(serde_json::json!({
+                "remaining": remaining,
+                "needed": needed,
+                "resetsAt": resets_at,
+            })),
+            Self::UnsupportedDocumentType(media_type) => {
+                let supported = crate::sniff::PRINTABLE
+                    .iter()
+                    .map(|format| format.media_type);
+                Some(serde_json::json!({
+                    "type": media_type,
+                    "supported": supported.collect::<Vec<_>>(),
+                }))
+            }
+            Self::UserSuspended { reason, until } => {
+                Some(serde_json::json!({ "reason": reason, "until": until }))
+            }
+            Self::GuestCodeUsedUp { remaining } => {
+                Some(serde_json::json!({ "remaining": remaining }))
+            }
+            Self::UnsupportedApiVersion { .. } => {
+                let supported = ApiVersion::SUPPORTED.iter().map(ApiVersion::number);
+                Some(serde_json::json!({ "supported": supported.collect::<Vec<_>>() }))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        let body = ErrorBody::new(self.code(), self.to_string());
+        match self.details() {
+            Some(details) => body.with_details(details),
+            None => body,
+        }
+    }
+}
+
+impl ResponseError for KprintError {
+    fn status_code(&self) -> StatusCode {
+        match &self {
+            Self::InternalError(_) | Self::SpoolIo(_) | Self::Database(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            Self::IppProtocol(_) | Self::CupsProxyAuth(_) => StatusCode::BAD_GATEWAY,
+            Self::CupsUnreachable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Conversion(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Actix(err) => err.as_response_error().status_code(),
+            Self::Validation(_) => StatusCode::BAD_REQUEST,
+            Self::PrinterNotFound(_)
+            | Self::JobNotFound(_)
+            | Self::PreviewNotFound(_)
+            | Self::UploadNotFound(_)
+            | Self::FeatureFlagNotFound(_) => StatusCode::NOT_FOUND,
+            Self::JobNotCancelable(_) | Self::JobNotPending(_) | Self::JobNotOpen(_) => {
+                StatusCode::CONFLICT
+            }
+            Self::JobRejected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::PrinterPaused(_)
+            | Self::PrinterStopped { .. }
+            | Self::PrinterInMaintenance { .. }
+            | Self::PrinterUnhealthy { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Unauthenticated => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::InvalidRequest(_) | Self::DigestMismatch { .. } => StatusCode::BAD_REQUEST,
+            Self::DocumentFetchFailed(_) | Self::PrinterUnavailable(_) => StatusCode::BAD_GATEWAY,
+            Self::PrinterRefused { status, .. } => refusal_status(*status),
+            Self::DocumentTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedDocumentType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::DocumentInfected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::ScannerUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::UploadOffsetMismatch { .. } | Self::UploadIncomplete { .. } => {
+                StatusCode::CONFLICT
+            }
+            Self::QuotaExceeded { .. } => StatusCode::FORBIDDEN,
+            Self::QuotaUnavailable => StatusCode::BAD_GATEWAY,
+            Self::NotEligible(_) | Self::UserSuspended { .. } => StatusCode::FORBIDDEN,
+            Self::EligibilityUnavailable => StatusCode::BAD_GATEWAY,
+            Self::GuestCodeInvalid => StatusCode::UNAUTHORIZED,
+            Self::GuestCodeUsedUp { .. } => StatusCode::FORBIDDEN,
+            Self::PickupLinkInvalid => StatusCode::UNAUTHORIZED,
+            Self::JobLinkInvalid => StatusCode::FORBIDDEN,
+            Self::JobNotHeld(_) => StatusCode::CONFLICT,
+            Self::UnsupportedApiVersion { .. } => StatusCode::NOT_ACCEPTABLE,
+            Self::RequestTimedOut(_) => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if let Self::Actix(err) = self {
+            err.as_response_error().error_response()
+        } else {
+            error_response(self.body(), self.status_code())
+        }
+    }
+}
+
+/// Error handler which puts every error response into the format the client
+/// asked for.
+///
+/// Legacy clients keep getting the plain text message they always have, while
+/// everyone else gets an [`ErrorBody`], including for errors actix generates
+/// on its own (unknown routes, extractor failures...).
+pub fn render_error<B: MessageBody>(
+    res: ServiceResponse<B>,
+) -> actix_web::Result<ErrorHandlerResponse<B>> {
+    let version = res.request().extensions().get::<ApiVersion>().copied();
+    let request_id = res.request().extensions().get::<RequestId>().copied();
+    let body = res.response().extensions().get::<ErrorBody>().cloned();
+
+    let body = match (version, body) {
+        // Not an API route and not something kprint produced, or a legacy
+        // client getting the same error it always has: leave it alone
+        (None | Some(ApiVersion::Legacy), None) => {
+            return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()))
+        }
+        (Some(ApiVersion::Legacy), Some(body)) => {
+            return Ok(replace_response(res, |status| {
+                HttpResponse::build(status).body(body.message)
+            }))
+        }
+        (_, Some(body)) => body,
+        (_, None) => {
+            let status = res.status();
+            let message = res
+                .response()
+                .error()
+                .map(|err| err.to_string())
+                .unwrap_or_else(|| status.canonical_reason().unwrap_or_default().to_string());
+            ErrorBody::new(code_for_status(status), message)
+        }
+    };
+
+    let body = ErrorBody {
+        request_id: request_id.map(|id| id.to_string()),
+        ..body
+    };
+    Ok(replace_response(res, |status| error_response(body, status)))
+}
+
+/// Swaps out the body of `res`, keeping the status and any headers that
+/// aren't describing the old body.
+fn replace_response<B>(
+    res: ServiceResponse<B>,
+    build: impl FnOnce(StatusCode) -> HttpResponse,
+) -> ErrorHandlerResponse<B> {
+    let (req, response) = res.into_parts();
+    let mut replacement = build(response.status());
+    for (name, value) in response.headers() {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            replacement
+                .headers_mut()
+                .insert(name.clone(), value.clone());
+        }
+    }
+    ErrorHandlerResponse::Response(ServiceResponse::new(req, replacement).map_into_right_body())
+}
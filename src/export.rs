@@ -0,0 +1,211 @@
+//! Writing the job history out for spreadsheets, a row at a time.
+
+use crate::error::{ErrorCode, FieldError};
+use actix_web::web::Bytes;
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use kprint_client::models::HistoryEntry;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::convert::Infallible;
+use utoipa::IntoParams;
+
+/// Every column there is, named like the fields of a history entry, in the
+/// order they're exported by default
+pub const COLUMNS: &[&str] = &[
+    "id",
+    "owner",
+    "printer",
+    "title",
+    "submittedAt",
+    "sides",
+    "colorMode",
+    "copies",
+    "pages",
+    "countedPages",
+    "state",
+    "failure",
+    "tags",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Json => "application/json",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Json => "json",
+        }
+    }
+}
+
+/// Which jobs to export, and how.
+#[derive(Debug, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    /// `csv` or `json`. Defaults to `csv`.
+    #[param(value_type = Option<String>, example = "csv")]
+    pub format: Option<String>,
+    /// RFC 3339. Defaults to the start of the history.
+    pub from: Option<DateTime<Utc>>,
+    /// RFC 3339. Defaults to now.
+    pub to: Option<DateTime<Utc>>,
+    /// Comma separated columns to include, in order, like
+    /// `submittedAt,owner,pages`. Defaults to every column:
+    /// id, owner, printer, title, submittedAt, sides, colorMode, copies,
+    /// pages, countedPages, state, failure, tags.
+    #[param(value_type = Option<String>)]
+    pub columns: Option<String>,
+    /// Only jobs with every word of this somewhere in their title, ignoring
+    /// case
+    pub search: Option<String>,
+    /// Only jobs with this tag, like `evals`
+    pub tag: Option<String>,
+}
+
+impl ExportQuery {
+    /// The lowercase words titles have to have.
+    pub fn terms(&self) -> Vec<String> {
+        self.search
+            .as_deref()
+            .unwrap_or_default()
+            .to_lowercase()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Checks the format and columns, returning every one that's wrong.
+    pub fn validate(&self) -> Result<(Format, Vec<&'static str>), Vec<FieldError>> {
+        let format = match self.format.as_deref().map(str::trim) {
+            None | Some("csv") => Ok(Format::Csv),
+            Some("json") => Ok(Format::Json),
+            Some(format) => Err(FieldError::new(
+                "format",
+                ErrorCode::UnsupportedValue,
+                format!("{format:?} isn't csv or json"),
+            )),
+        };
+        let columns = match self.columns.as_deref() {
+            None => Ok(COLUMNS.to_vec()),
+            Some(columns) => columns
+                .split(',')
+                .map(|column| {
+                    let column = column.trim();
+                    COLUMNS
+                        .iter()
+                        .find(|known| **known == column)
+                        .copied()
+                        .ok_or_else(|| {
+                            FieldError::new(
+                                "columns",
+                                ErrorCode::UnsupportedValue,
+                                format!("{column:?} isn't one of {}", COLUMNS.join(", ")),
+                            )
+                        })
+                })
+                .collect(),
+        };
+        match (format, columns) {
+            (Ok(format), Ok(columns)) => Ok((format, columns)),
+            (format, columns) => Err([format.err(), columns.err()]
+                .into_iter()
+                .flatten()
+                .collect()),
+        }
+    }
+}
+
+/// Quotes `field` for CSV, if it needs it.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn csv_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        // Spreadsheets run anything that looks like a formula, and titles
+        // come from whoever printed them
+        Value::String(value) if value.starts_with(['=', '+', '-', '@']) => {
+            csv_field(&format!("'{value}"))
+        }
+        Value::String(value) => csv_field(value),
+        // Tags, which can't start with anything a spreadsheet would run
+        Value::Array(values) => csv_field(
+            &values
+                .iter()
+                .map(|value| value.as_str().unwrap_or_default())
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        value => csv_field(&value.to_string()),
+    }
+}
+
+/// Just `columns` of `entry`, as JSON, in the order they were asked for.
+fn pick(entry: &HistoryEntry, columns: &[&'static str]) -> Vec<(&'static str, Value)> {
+    let Value::Object(mut all) = serde_json::to_value(entry).unwrap() else {
+        unreachable!("history entries are structs");
+    };
+    columns
+        .iter()
+        .map(|column| {
+            let value = all.remove(*column).unwrap_or(Value::Null);
+            (*column, value)
+        })
+        .collect()
+}
+
+/// `entries` in `format`, one row to a chunk, so nothing has to hold the
+/// whole export at once.
+pub fn rows(
+    entries: Vec<HistoryEntry>,
+    format: Format,
+    columns: Vec<&'static str>,
+) -> impl Stream<Item = Result<Bytes, Infallible>> {
+    let (open, close) = match format {
+        Format::Csv => (csv_row(columns.iter().map(|column| csv_field(column))), ""),
+        Format::Json => ("[".to_string(), "]"),
+    };
+    let rows = entries.into_iter().enumerate().map(move |(i, entry)| {
+        let row = pick(&entry, &columns);
+        match format {
+            Format::Csv => csv_row(row.iter().map(|(_, value)| csv_value(value))),
+            Format::Json => {
+                let separator = if i == 0 { "" } else { "," };
+                let row = row
+                    .into_iter()
+                    .map(|(column, value)| (column.to_string(), value))
+                    .collect::<Map<_, _>>();
+                format!("{separator}{}", Value::Object(row))
+            }
+        }
+    });
+    futures::stream::iter(
+        std::iter::once(open)
+            .chain(rows)
+            .chain(std::iter::once(close.to_string()))
+            .map(|chunk| Ok(Bytes::from(chunk))),
+    )
+}
+
+fn csv_row(fields: impl Iterator<Item = String>) -> String {
+    let mut row = fields.collect::<Vec<_>>().join(",");
+    row.push_str("\r\n");
+    row
+}
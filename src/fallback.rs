@@ -0,0 +1,176 @@
+//! Sending jobs on to another printer when the one they were sent to fails
+//! partway through them.
+//!
+//! Printers are grouped into pools with `KPRINT_PRINTER_POOLS`, a
+//! space-separated list of comma-separated printers like `lounge,library
+//! east,west`. Jobs sent with `allowFallback` to a printer with others in its
+//! pool are watched until they're done, with kprint holding on to the
+//! document. One that aborts because of the printer rather than the document
+//! is sent to the first other printer in the pool that's taking jobs and can
+//! print it the same way, and its status says where it went.
+
+use crate::app::AppState;
+use crate::document::Document;
+use crate::service;
+use actix_web::web::Data;
+use kprint_client::models::{JobState, JobStatus, PrintOptions};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How often watched jobs are checked on
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+/// How long a job is watched before its document is let go, however it's
+/// doing, so a job stuck in a queue doesn't hold on to it forever
+const WATCH_LIMIT: Duration = Duration::from_secs(24 * 60 * 60);
+
+struct Watched {
+    options: PrintOptions,
+    document: Document,
+    /// Every printer the job has been sent to
+    tried: Vec<String>,
+    since: Instant,
+}
+
+pub struct Fallback {
+    pools: Vec<Vec<String>>,
+    /// Where documents that were still streaming in are spooled, so they can
+    /// be sent again
+    dir: PathBuf,
+    watched: Mutex<HashMap<Uuid, Watched>>,
+}
+
+impl Fallback {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let pools = std::env::var("KPRINT_PRINTER_POOLS")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|pool| pool.split(',').map(str::to_string).collect())
+            .collect();
+        Ok(Fallback {
+            pools,
+            dir: crate::spool::spool_dir()?,
+            watched: Mutex::default(),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Every other printer sharing a pool with `printer`, in the order
+    /// they're configured.
+    pub fn alternates(&self, printer: &str) -> Vec<String> {
+        let mut alternates = vec![];
+        for pool in self
+            .pools
+            .iter()
+            .filter(|pool| pool.iter().any(|p| p == printer))
+        {
+            for alternate in pool {
+                if alternate != printer && !alternates.contains(alternate) {
+                    alternates.push(alternate.clone());
+                }
+            }
+        }
+        alternates
+    }
+
+    /// Keeps `document` until job `id` on `printer` is done with it.
+    pub fn watch(&self, id: Uuid, printer: &str, options: PrintOptions, document: Document) {
+        self.watched.lock().unwrap().insert(
+            id,
+            Watched {
+                options,
+                document,
+                tried: vec![printer.to_string()],
+                since: Instant::now(),
+            },
+        );
+    }
+
+    /// Checks on every watched job each [`POLL_INTERVAL`], for as long as
+    /// kprint runs.
+    pub fn watch_in_background(app_data: Data<AppState>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let ids = {
+                    let mut watched = app_data.fallback.watched.lock().unwrap();
+                    watched.retain(|_, job| job.since.elapsed() < WATCH_LIMIT);
+                    watched.keys().copied().collect::<Vec<_>>()
+                };
+                futures::future::join_all(ids.into_iter().map(|id| check(&app_data, id))).await;
+            }
+        });
+    }
+}
+
+/// Whether a job aborted because of the printer it was on, and so might
+/// print somewhere else.
+fn printer_failed(status: &JobStatus) -> bool {
+    status.state == JobState::Aborted
+        && !status
+            .state_reasons
+            .iter()
+            .any(|reason| reason.starts_with("document-"))
+}
+
+async fn check(app_data: &AppState, id: Uuid) {
+    let Some(job) = app_data.jobs.get(&id) else {
+        app_data.fallback.watched.lock().unwrap().remove(&id);
+        return;
+    };
+    let status = match service::job_status(app_data, &job.owner, id).await {
+        Ok(status) => status,
+        Err(err) => {
+            log::debug!("Couldn't check on job {id} to see if it needs to fall back: {err}");
+            return;
+        }
+    };
+    match status.state {
+        JobState::Aborted if printer_failed(&status) => {}
+        JobState::Aborted | JobState::Canceled | JobState::Completed => {
+            app_data.fallback.watched.lock().unwrap().remove(&id);
+            return;
+        }
+        _ => return,
+    }
+
+    // Out of the map while it's resent, so the next check can't resend it too
+    let Some(mut watched) = app_data.fallback.watched.lock().unwrap().remove(&id) else {
+        return;
+    };
+    let alternates = app_data
+        .fallback
+        .alternates(&job.printer)
+        .into_iter()
+        .filter(|alternate| !watched.tried.contains(alternate))
+        .collect::<Vec<_>>();
+    for alternate in alternates {
+        watched.tried.push(alternate.clone());
+        let Some(document) = watched.document.try_clone() else {
+            return;
+        };
+        match service::reroute(app_data, &job, &alternate, &watched.options, document).await {
+            Ok(()) => {
+                app_data
+                    .fallback
+                    .watched
+                    .lock()
+                    .unwrap()
+                    .insert(id, watched);
+                return;
+            }
+            Err(err) => log::info!("Couldn't fall back to {alternate} for job {id}: {err}"),
+        }
+    }
+    log::warn!(
+        "Job {id} failed on {}, with nowhere left to fall back to",
+        job.printer
+    );
+}
@@ -0,0 +1,187 @@
+//! Fetching documents from elsewhere on the web, for people who'd rather not
+//! upload something that's already online.
+//!
+//! Documents the print server can reach itself, like ones on a shared NFS
+//! path or an internal web server, aren't fetched at all. URLs starting with
+//! one of the prefixes in `KPRINT_PRINT_URI_PREFIXES`, like
+//! `file:///srv/share/` or `http://files.csh.rit.edu/`, are handed to the
+//! printer with Print-URI to fetch for itself, so big documents don't pass
+//! through kprint on the way.
+
+use crate::error::{ErrorCode, FieldError, KprintError};
+use crate::service;
+use crate::streaming::StreamTuning;
+use futures::{SinkExt, StreamExt};
+use ipp::prelude::IppPayload;
+use reqwest::{header, redirect, Url};
+use std::sync::Arc;
+
+/// Where documents can be fetched from when `KPRINT_FETCH_ALLOWED_HOSTS`
+/// doesn't say otherwise: Google Drive exports and raw GitHub files
+const DEFAULT_ALLOWED_HOSTS: &[&str] = &[
+    "docs.google.com",
+    "drive.google.com",
+    "drive.usercontent.google.com",
+    "raw.githubusercontent.com",
+    "gist.githubusercontent.com",
+];
+
+/// Biggest document fetched when `KPRINT_FETCH_MAX_BYTES` doesn't say otherwise
+const DEFAULT_MAX_BYTES: u64 = 100 * 1024 * 1024;
+
+/// Most redirects followed, all of which have to stay on allowed hosts
+const MAX_REDIRECTS: usize = 5;
+
+/// Types worth sending to a printer. GitHub serves PDFs as
+/// `application/octet-stream`, which CUPS sniffs the real type of.
+const FETCHABLE_TYPES: &[&str] = &[
+    "application/octet-stream",
+    "application/pdf",
+    "application/postscript",
+    "image/jpeg",
+    "image/png",
+    "text/plain",
+];
+
+/// What kprint is willing to fetch, and the client it fetches with.
+pub struct FetchPolicy {
+    allowed_hosts: Arc<Vec<String>>,
+    /// Where the printers fetch documents from themselves
+    referenced: Vec<String>,
+    max_bytes: u64,
+    http: reqwest::Client,
+}
+
+fn allowed(allowed_hosts: &[String], url: &Url) -> bool {
+    url.scheme() == "https"
+        && url
+            .host_str()
+            .is_some_and(|host| allowed_hosts.iter().any(|allowed| allowed == host))
+}
+
+impl FetchPolicy {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let allowed_hosts: Vec<String> = match std::env::var("KPRINT_FETCH_ALLOWED_HOSTS") {
+            Ok(hosts) => hosts.split_whitespace().map(str::to_string).collect(),
+            Err(_) => DEFAULT_ALLOWED_HOSTS
+                .iter()
+                .map(|host| host.to_string())
+                .collect(),
+        };
+        let allowed_hosts = Arc::new(allowed_hosts);
+        let referenced = match std::env::var("KPRINT_PRINT_URI_PREFIXES") {
+            Ok(prefixes) => prefixes
+                .split_whitespace()
+                .map(|prefix| Ok(Url::parse(prefix)?.to_string()))
+                .collect::<anyhow::Result<_>>()?,
+            Err(_) => vec![],
+        };
+        let max_bytes = match std::env::var("KPRINT_FETCH_MAX_BYTES") {
+            Ok(max_bytes) => max_bytes.parse()?,
+            Err(_) => DEFAULT_MAX_BYTES,
+        };
+
+        let redirect_hosts = allowed_hosts.clone();
+        let http = reqwest::Client::builder()
+            .redirect(redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    attempt.error("too many redirects")
+                } else if allowed(&redirect_hosts, attempt.url()) {
+                    attempt.follow()
+                } else {
+                    let url = attempt.url().to_string();
+                    attempt.error(format!("redirected to {url}, which isn't allowed"))
+                }
+            }))
+            .build()?;
+        Ok(FetchPolicy {
+            allowed_hosts,
+            referenced,
+            max_bytes,
+            http,
+        })
+    }
+
+    /// `url`, tidied up, if it's one the printers are to fetch themselves.
+    /// It's compared once any `..`s are resolved, so it can't climb out of the
+    /// directory it's meant to be in.
+    pub fn by_reference(&self, url: &str) -> Option<Url> {
+        let url = Url::parse(url).ok()?;
+        self.referenced
+            .iter()
+            .any(|prefix| url.as_str().starts_with(prefix))
+            .then_some(url)
+    }
+
+    /// Starts downloading `url`, handing back the document as it arrives.
+    ///
+    /// Everything that can be checked before the download is: the host, the
+    /// type, and the size if the server says it up front. Servers that don't
+    /// get cut off once they send more than the limit.
+    pub async fn fetch(&self, url: &str, tuning: &StreamTuning) -> Result<IppPayload, KprintError> {
+        let invalid = |message: String| {
+            KprintError::Validation(vec![FieldError::new(
+                "url",
+                ErrorCode::UnsupportedValue,
+                message,
+            )])
+        };
+        let url = Url::parse(url).map_err(|err| invalid(format!("{url:?} isn't a URL: {err}")))?;
+        if !allowed(&self.allowed_hosts, &url) {
+            return Err(invalid(format!(
+                "Documents can only be fetched over https from {}",
+                self.allowed_hosts.join(", ")
+            )));
+        }
+
+        let response = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|err| KprintError::DocumentFetchFailed(err.to_string()))?;
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .and_then(|content_type| content_type.split(';').next())
+            .unwrap_or("application/octet-stream")
+            .trim()
+            .to_ascii_lowercase();
+        if !FETCHABLE_TYPES.contains(&content_type.as_str()) {
+            return Err(KprintError::UnsupportedDocumentType(content_type));
+        }
+        let limit = self.max_bytes;
+        if response
+            .content_length()
+            .is_some_and(|length| length > limit)
+        {
+            return Err(KprintError::DocumentTooLarge { limit });
+        }
+
+        let (mut tx, document) = service::document_channel(tuning);
+        tokio::spawn(async move {
+            let mut fetched = 0;
+            let mut body = response.bytes_stream();
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk.map_err(std::io::Error::other).and_then(|chunk| {
+                    fetched += chunk.len() as u64;
+                    if fetched > limit {
+                        Err(std::io::Error::other(format!(
+                            "the document is bigger than the {limit} byte limit"
+                        )))
+                    } else {
+                        Ok(chunk)
+                    }
+                });
+                let failed = chunk.is_err();
+                if tx.send(chunk).await.is_err() || failed {
+                    log::warn!("Stopped fetching a document after {fetched} bytes");
+                    break;
+                }
+            }
+        });
+        Ok(document)
+    }
+}
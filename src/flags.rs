@@ -0,0 +1,149 @@
+//! Switches for parts of kprint that are new enough to want turning off
+//! without a rebuild, or turning on a deployment at a time.
+//!
+//! Each flag is on or off by default, which `KPRINT_FLAG_<NAME>` can change,
+//! like `KPRINT_FLAG_SPOOLED_UPLOADS=false`. Admins can override that while
+//! kprint's running, and when `KPRINT_FLAGS_FILE` is set their overrides are
+//! kept there as JSON, so they last across restarts.
+
+use crate::json_file::JsonFile;
+use chrono::Utc;
+use kprint_client::models::{FeatureFlag, FlagOverride};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Flag {
+    /// Turning jobs away once their owner's quota is used up
+    Quotas,
+    /// Answering uploads before they're printed, when `KPRINT_SPOOL_UPLOADS`
+    /// is set
+    SpooledUploads,
+    /// Sending jobs that fail on their printer to another one
+    Fallback,
+}
+
+impl Flag {
+    pub const ALL: &'static [Flag] = &[Flag::Quotas, Flag::SpooledUploads, Flag::Fallback];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Flag::Quotas => "quotas",
+            Flag::SpooledUploads => "spooled-uploads",
+            Flag::Fallback => "fallback",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|flag| flag.name() == name)
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Flag::Quotas => "Turn jobs away once their owner's quota is used up",
+            Flag::SpooledUploads => {
+                "Answer uploads before they're printed, when KPRINT_SPOOL_UPLOADS is set"
+            }
+            Flag::Fallback => "Send jobs that fail on their printer to another one",
+        }
+    }
+
+    /// Whether it's on when nothing says otherwise.
+    fn default(self) -> bool {
+        match self {
+            Flag::Quotas | Flag::SpooledUploads | Flag::Fallback => true,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Flags {
+    /// What the environment and config file say, for the flags they mention
+    configured: HashMap<Flag, bool>,
+    /// By flag name, so the file still loads after a flag's gone
+    overrides: JsonFile<HashMap<String, FlagOverride>>,
+}
+
+impl Flags {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut configured = HashMap::new();
+        for flag in Flag::ALL {
+            let var = format!(
+                "KPRINT_FLAG_{}",
+                flag.name().to_uppercase().replace('-', "_")
+            );
+            if let Ok(enabled) = std::env::var(&var) {
+                let enabled = enabled
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("{var} has to be true or false"))?;
+                configured.insert(*flag, enabled);
+            }
+        }
+        Ok(Flags {
+            configured,
+            overrides: JsonFile::from_env("KPRINT_FLAGS_FILE")?,
+        })
+    }
+
+    fn configured(&self, flag: Flag) -> bool {
+        self.configured
+            .get(&flag)
+            .copied()
+            .unwrap_or_else(|| flag.default())
+    }
+
+    pub fn enabled(&self, flag: Flag) -> bool {
+        self.overrides
+            .read(|overrides| overrides.get(flag.name()).map(|set| set.enabled))
+            .unwrap_or_else(|| self.configured(flag))
+    }
+
+    fn describe(&self, flag: Flag, overrides: &HashMap<String, FlagOverride>) -> FeatureFlag {
+        let overridden = overrides.get(flag.name()).cloned();
+        FeatureFlag {
+            name: flag.name().to_string(),
+            description: flag.description().to_string(),
+            enabled: overridden
+                .as_ref()
+                .map_or_else(|| self.configured(flag), |set| set.enabled),
+            configured: self.configured(flag),
+            overridden,
+        }
+    }
+
+    pub fn all(&self) -> Vec<FeatureFlag> {
+        self.overrides.read(|overrides| {
+            Flag::ALL
+                .iter()
+                .map(|flag| self.describe(*flag, overrides))
+                .collect()
+        })
+    }
+
+    /// Turns `flag` on or off whatever it's configured as, until it's
+    /// cleared.
+    pub async fn set(&self, flag: Flag, enabled: bool, admin: &str) -> anyhow::Result<FeatureFlag> {
+        self.overrides
+            .change(|overrides| {
+                overrides.insert(
+                    flag.name().to_string(),
+                    FlagOverride {
+                        enabled,
+                        set_by: admin.to_string(),
+                        since: Utc::now(),
+                    },
+                );
+                self.describe(flag, overrides)
+            })
+            .await
+    }
+
+    /// Puts `flag` back to how it's configured.
+    pub async fn clear(&self, flag: Flag) -> anyhow::Result<FeatureFlag> {
+        self.overrides
+            .change(|overrides| {
+                overrides.remove(flag.name());
+                self.describe(flag, overrides)
+            })
+            .await
+    }
+}
@@ -0,0 +1,237 @@
+//! GraphQL API for the status dashboard, which would otherwise need a REST
+//! round-trip per printer and job on every refresh.
+
+use crate::app::AppState;
+use crate::auth::AuthenticatedUser;
+use crate::error::KprintError;
+use crate::jobs::JobRecord;
+use crate::service;
+use actix_web::{
+    get, post,
+    web::{self, Data, Payload},
+    HttpRequest, HttpResponse,
+};
+use async_graphql::{
+    futures_util::Stream, Context, EmptyMutation, ErrorExtensions, Object, Schema, Subscription,
+};
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse, GraphQLSubscription};
+use chrono::{DateTime, Utc};
+use kprint_client::models::{JobState, JobStatus, PrinterStatus, Quota};
+use std::time::Duration;
+use uuid::Uuid;
+
+pub type KprintSchema = Schema<Query, EmptyMutation, SubscriptionRoot>;
+
+/// How often subscriptions ask the printer whether a job has moved on
+const JOB_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Whoever is asking, added to every request's data.
+struct Caller {
+    username: String,
+    groups: Vec<String>,
+}
+
+impl Caller {
+    fn new(user: &AuthenticatedUser) -> Self {
+        Caller {
+            username: user.username().to_string(),
+            groups: user.groups().to_vec(),
+        }
+    }
+}
+
+impl ErrorExtensions for KprintError {
+    fn extend(&self) -> async_graphql::Error {
+        let code = serde_json::to_value(self.code()).unwrap_or_default();
+        async_graphql::Error::new(self.to_string())
+            .extend_with(|_, extensions| extensions.set("code", code.as_str().unwrap_or_default()))
+    }
+}
+
+fn context<'a>(ctx: &Context<'a>) -> (&'a Data<AppState>, &'a str) {
+    let app_data = ctx.data_unchecked::<Data<AppState>>();
+    let caller = ctx.data_unchecked::<Caller>();
+    (app_data, &caller.username)
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// Every configured printer.
+    async fn printers(&self, ctx: &Context<'_>) -> Vec<Printer> {
+        let (app_data, _) = context(ctx);
+        service::printers(app_data)
+            .into_iter()
+            .map(Printer)
+            .collect()
+    }
+
+    async fn printer(&self, ctx: &Context<'_>, name: String) -> Option<Printer> {
+        let (app_data, _) = context(ctx);
+        app_data
+            .printers
+            .contains_key(&name)
+            .then_some(Printer(name))
+    }
+
+    /// Every job you've submitted, newest first.
+    async fn jobs(&self, ctx: &Context<'_>) -> Vec<Job> {
+        let (app_data, username) = context(ctx);
+        app_data
+            .jobs
+            .owned_by(username)
+            .into_iter()
+            .map(Job)
+            .collect()
+    }
+
+    async fn job(&self, ctx: &Context<'_>, id: Uuid) -> Option<Job> {
+        let (app_data, username) = context(ctx);
+        app_data
+            .jobs
+            .get(&id)
+            .filter(|job| job.owner == username)
+            .map(Job)
+    }
+
+    /// Where your quota stands this period.
+    async fn quota(&self, ctx: &Context<'_>) -> async_graphql::Result<Quota> {
+        let (app_data, username) = context(ctx);
+        let Caller { groups, .. } = ctx.data_unchecked::<Caller>();
+        app_data
+            .quotas
+            .quota(app_data, username, groups)
+            .await
+            .map_err(|err| err.extend())
+    }
+}
+
+pub struct Printer(String);
+
+#[Object]
+impl Printer {
+    async fn name(&self) -> &str {
+        &self.0
+    }
+
+    /// Asked of the printer when the field is requested, so it's always live.
+    /// Printers that don't answer in time show up as `UNREACHABLE`.
+    async fn status(&self, ctx: &Context<'_>) -> async_graphql::Result<PrinterStatus> {
+        let (app_data, _) = context(ctx);
+        service::status_or_unreachable(app_data, &self.0)
+            .await
+            .map_err(|err| err.extend())
+    }
+}
+
+pub struct Job(JobRecord);
+
+#[Object]
+impl Job {
+    async fn id(&self) -> Uuid {
+        self.0.id
+    }
+
+    /// The printer's own id for the job, for cross-referencing with CUPS
+    async fn ipp_job_id(&self) -> Option<i32> {
+        self.0.ipp_job_id
+    }
+
+    async fn printer(&self) -> &str {
+        &self.0.printer
+    }
+
+    async fn title(&self) -> &str {
+        &self.0.title
+    }
+
+    async fn submitted_at(&self) -> DateTime<Utc> {
+        self.0.submitted_at
+    }
+
+    /// Asked of the printer when the field is requested, so it's always live.
+    async fn status(&self, ctx: &Context<'_>) -> async_graphql::Result<JobStatus> {
+        let (app_data, username) = context(ctx);
+        service::job_status(app_data, username, self.0.id)
+            .await
+            .map_err(|err| err.extend())
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription(name = "Subscription")]
+impl SubscriptionRoot {
+    /// A job's status every time its state changes, ending once the job is
+    /// done one way or another.
+    async fn job_status(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+    ) -> impl Stream<Item = async_graphql::Result<JobStatus>> {
+        let (app_data, username) = context(ctx);
+        let (app_data, username) = (app_data.clone(), username.to_string());
+        // The previous state, or None once the stream should end
+        let initial: Option<Option<JobState>> = Some(None);
+        futures::stream::unfold(initial, move |previous| {
+            let (app_data, username) = (app_data.clone(), username.clone());
+            async move {
+                let previous = previous?;
+                loop {
+                    if previous.is_some() {
+                        tokio::time::sleep(JOB_POLL_INTERVAL).await;
+                    }
+                    let status = match service::job_status(&app_data, &username, id).await {
+                        Ok(status) => status,
+                        Err(err) => return Some((Err(err.extend()), None)),
+                    };
+                    if Some(status.state) == previous {
+                        continue;
+                    }
+                    let finished = matches!(
+                        status.state,
+                        JobState::Completed | JobState::Canceled | JobState::Aborted
+                    );
+                    let state = status.state;
+                    return Some((Ok(status), (!finished).then_some(Some(state))));
+                }
+            }
+        })
+    }
+}
+
+#[post("/graphql")]
+async fn graphql(
+    schema: Data<KprintSchema>,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    let request = request.into_inner().data(app_data).data(Caller::new(&user));
+    schema.execute(request).await.into()
+}
+
+/// Subscriptions, over a `graphql-ws` or `graphql-transport-ws` websocket.
+#[get("/graphql")]
+async fn graphql_subscriptions(
+    schema: Data<KprintSchema>,
+    app_data: Data<AppState>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+    payload: Payload,
+) -> actix_web::Result<HttpResponse> {
+    let mut data = async_graphql::Data::default();
+    data.insert(app_data);
+    data.insert(Caller::new(&user));
+    GraphQLSubscription::new(KprintSchema::clone(&schema))
+        .with_data(data)
+        .start(&req, payload)
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    let schema = Schema::build(Query, EmptyMutation, SubscriptionRoot).finish();
+    cfg.app_data(Data::new(schema))
+        .service(graphql)
+        .service(graphql_subscriptions);
+}
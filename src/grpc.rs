@@ -0,0 +1,264 @@
+//! The gRPC API, served on its own port next to the REST API and backed by the
+//! same [`service`](crate::service) layer.
+
+use crate::app::AppState;
+use crate::auth::AuthenticatedUser;
+use crate::error::{ErrorCode, KprintError};
+use crate::issuers::Issuers;
+use crate::options::{self, PrintQuery};
+use crate::service::{self, Submitted};
+use actix_web::web::{Bytes, Data};
+use futures::StreamExt;
+use kprint_client::models::JobState;
+use proto::kprint_server::{Kprint, KprintServer};
+use proto::submit_job_request::Part;
+use std::net::SocketAddr;
+use tonic::{Code, Request, Response, Status, Streaming};
+use uuid::Uuid;
+
+pub mod proto {
+    tonic::include_proto!("kprint.v1");
+}
+
+impl From<KprintError> for Status {
+    fn from(err: KprintError) -> Self {
+        let code = match err.code() {
+            ErrorCode::Unauthenticated
+            | ErrorCode::GuestCodeInvalid
+            | ErrorCode::PickupLinkInvalid => Code::Unauthenticated,
+            ErrorCode::Forbidden
+            | ErrorCode::NotEligible
+            | ErrorCode::UserSuspended
+            | ErrorCode::JobLinkInvalid => Code::PermissionDenied,
+            ErrorCode::InvalidRequest | ErrorCode::ValidationFailed | ErrorCode::JobRejected => {
+                Code::InvalidArgument
+            }
+            ErrorCode::NotFound
+            | ErrorCode::PrinterNotFound
+            | ErrorCode::JobNotFound
+            | ErrorCode::PreviewNotFound
+            | ErrorCode::FeatureFlagNotFound => Code::NotFound,
+            ErrorCode::JobNotCancelable
+            | ErrorCode::JobNotPending
+            | ErrorCode::JobNotOpen
+            | ErrorCode::JobNotHeld
+            | ErrorCode::PrinterRefused => Code::FailedPrecondition,
+            ErrorCode::QuotaExceeded | ErrorCode::GuestCodeUsedUp => Code::ResourceExhausted,
+            ErrorCode::DocumentInfected
+            | ErrorCode::ConversionFailed
+            | ErrorCode::DigestMismatch => Code::InvalidArgument,
+            ErrorCode::RequestTimedOut => Code::DeadlineExceeded,
+            ErrorCode::PrinterPaused
+            | ErrorCode::PrinterStopped
+            | ErrorCode::PrinterInMaintenance
+            | ErrorCode::PrinterUnavailable
+            | ErrorCode::PrinterUnhealthy
+            | ErrorCode::QuotaUnavailable
+            | ErrorCode::EligibilityUnavailable
+            | ErrorCode::ScannerUnavailable
+            | ErrorCode::CupsUnreachable
+            | ErrorCode::CupsProxyAuthFailed => Code::Unavailable,
+            _ => Code::Internal,
+        };
+        // The details carry the same JSON body the REST API would have sent,
+        // so clients can pick out the bad fields of a validation error
+        let body = serde_json::to_vec(&err.body()).unwrap_or_default();
+        Status::with_details(code, err.to_string(), body.into())
+    }
+}
+
+fn keyword(state: JobState) -> String {
+    serde_variant::to_variant_name(&state).unwrap().to_string()
+}
+
+fn username<T>(request: &Request<T>) -> Result<String, KprintError> {
+    let user = request
+        .extensions()
+        .get::<AuthenticatedUser>()
+        .ok_or(KprintError::Unauthenticated)?;
+    Ok(user.username().to_string())
+}
+
+pub struct KprintService {
+    app_data: Data<AppState>,
+}
+
+#[tonic::async_trait]
+impl Kprint for KprintService {
+    async fn submit_job(
+        &self,
+        request: Request<Streaming<proto::SubmitJobRequest>>,
+    ) -> Result<Response<proto::Job>, Status> {
+        let username = username(&request)?;
+        let user = request.extensions().get::<AuthenticatedUser>();
+        let admin = user.is_some_and(|user| user.is_admin(&self.app_data.admin_groups));
+        let groups = user.map(|user| user.groups().to_vec()).unwrap_or_default();
+        let mut parts = request.into_inner();
+        let Some(Part::Options(options)) = parts.message().await?.and_then(|part| part.part) else {
+            return Err(KprintError::InvalidRequest(
+                "The first message has to carry the print options".to_string(),
+            )
+            .into());
+        };
+        let validated = PrintQuery {
+            sides: options.sides,
+            color_mode: options.color_mode,
+            pages: options.pages,
+            copies: options.copies.map(|copies| copies.to_string()),
+            title: options.title,
+            cover_sheet: options
+                .cover_sheet
+                .map(|cover_sheet| cover_sheet.to_string()),
+            allow_fallback: options
+                .allow_fallback
+                .map(|allow_fallback| allow_fallback.to_string()),
+            separate_jobs: options
+                .separate_jobs
+                .map(|separate_jobs| separate_jobs.to_string()),
+            tags: Some(options.tags.join(",")),
+            media: None,
+            margins: None,
+            offset: None,
+            poster: None,
+            envelope: None,
+        }
+        .validate()
+        .map_err(KprintError::Validation)?;
+
+        let (tx, document) = service::document_channel(&self.app_data.streaming);
+        tokio::spawn(async move {
+            let chunks = parts.map(|part| match part.map(|part| part.part) {
+                Ok(Some(Part::Chunk(chunk))) => Ok(Ok(Bytes::from(chunk))),
+                Ok(_) => Ok(Err(std::io::Error::other(
+                    "Only the first message may carry options",
+                ))),
+                Err(status) => Ok(Err(std::io::Error::other(status))),
+            });
+            if let Err(err) = chunks.forward(tx).await {
+                log::warn!("Hung up! Cancelling the reader! {err}");
+            }
+        });
+
+        let Submitted {
+            job,
+            state,
+            pages,
+            overrides,
+            quota_consumed,
+            page_ranges,
+            copies_to_follow,
+        } = service::submit(
+            &self.app_data,
+            &username,
+            admin,
+            &groups,
+            options.printer,
+            validated,
+            document.into(),
+        )
+        .await?;
+        Ok(Response::new(proto::Job {
+            id: job.id.to_string(),
+            ipp_job_id: job.ipp_job_id,
+            printer: job.printer,
+            state: keyword(state),
+            pages,
+            submitted_at: job.submitted_at.to_rfc3339(),
+            overrides: overrides
+                .into_iter()
+                .map(|forced| proto::AppliedOverride {
+                    option: forced.option,
+                    requested: forced.requested,
+                    applied: forced.applied,
+                })
+                .collect(),
+            quota_consumed,
+            page_ranges: options::written_pages(&page_ranges),
+            copies_to_follow,
+        }))
+    }
+
+    async fn get_job(
+        &self,
+        request: Request<proto::GetJobRequest>,
+    ) -> Result<Response<proto::JobStatus>, Status> {
+        let username = username(&request)?;
+        let id = request.into_inner().id;
+        let id = Uuid::parse_str(&id)
+            .map_err(|_| KprintError::InvalidRequest(format!("{id:?} isn't a job id")))?;
+        let status = service::job_status(&self.app_data, &username, id).await?;
+        Ok(Response::new(proto::JobStatus {
+            id: status.id.to_string(),
+            ipp_job_id: status.ipp_job_id,
+            printer: status.printer,
+            title: status.title,
+            state: keyword(status.state),
+            state_reasons: status.state_reasons,
+            submitted_at: status.submitted_at.to_rfc3339(),
+            attributes: status.attributes.into_iter().collect(),
+            failure: status.failure,
+            rerouted_from: status.rerouted_from,
+        }))
+    }
+
+    async fn list_printers(
+        &self,
+        request: Request<proto::ListPrintersRequest>,
+    ) -> Result<Response<proto::ListPrintersResponse>, Status> {
+        username(&request)?;
+        let printers = service::fleet_status(&self.app_data)
+            .await
+            .into_iter()
+            .map(|status| proto::Printer {
+                name: status.name,
+                paused: status.paused,
+                maintenance_message: status.maintenance.map(|notice| notice.message),
+                unhealthy: status.unhealthy_until.is_some(),
+            })
+            .collect();
+        Ok(Response::new(proto::ListPrintersResponse { printers }))
+    }
+}
+
+/// Serves the gRPC API on `port` until the process exits, checking tokens
+/// with `issuers`. Without them, in a demo, everyone's the demo user.
+pub async fn serve(
+    app_data: Data<AppState>,
+    port: u16,
+    issuers: Option<Issuers>,
+) -> anyhow::Result<()> {
+    // tonic decides the interceptor's signature, Status and all
+    #[allow(clippy::result_large_err)]
+    let authenticate = {
+        let app_data = app_data.clone();
+        move |mut request: Request<()>| {
+            let Some(issuers) = &issuers else {
+                request.extensions_mut().insert(crate::demo::user());
+                return Ok(request);
+            };
+            let token = request
+                .metadata()
+                .get("authorization")
+                .and_then(|token| token.to_str().ok())
+                .map(|token| token.trim_start_matches("Bearer ").to_string());
+            let claims = token
+                .and_then(|token| issuers.verify(&token))
+                .ok_or(KprintError::Unauthenticated)?;
+            let user = AuthenticatedUser { claims };
+            crate::confirmations::remember(&app_data, &user);
+            request.extensions_mut().insert(user);
+            Ok(request)
+        }
+    };
+
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+    log::info!("Serving gRPC on {address}");
+    tonic::transport::Server::builder()
+        .add_service(KprintServer::with_interceptor(
+            KprintService { app_data },
+            authenticate,
+        ))
+        .serve(address)
+        .await?;
+    Ok(())
+}
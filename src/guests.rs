@@ -0,0 +1,166 @@
+//! Codes admins hand out so visitors without SSO accounts, like those at
+//! recruitment weekend, can print for a while on a member's behalf.
+//!
+//! Each code can print so many pages before it expires, and its jobs are
+//! printed as the member who sponsored it. Only a hash of the code is kept,
+//! so it's shown once, when it's made. When `KPRINT_GUEST_CODES_FILE` is set
+//! the codes are kept there as JSON, so they last across restarts.
+
+use crate::error::KprintError;
+use crate::json_file::JsonFile;
+use base64::Engine;
+use chrono::Utc;
+use kprint_client::models::GuestCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// What codes are made of, leaving out letters and digits that are easy to
+/// mistake for one another when read off a screen
+const ALPHABET: &[u8; 32] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+/// The bytes of a v4 UUID that are entirely random, the rest holding its
+/// version and variant
+const RANDOM_BYTES: [usize; 10] = [0, 1, 2, 3, 4, 5, 10, 11, 12, 13];
+
+#[derive(Serialize, Deserialize, Clone)]
+struct StoredCode {
+    /// SHA-256 of the code, in base64
+    hash: String,
+    #[serde(flatten)]
+    code: GuestCode,
+}
+
+#[derive(Default)]
+pub struct GuestCodes {
+    codes: JsonFile<HashMap<Uuid, StoredCode>>,
+}
+
+/// Hashes `code` the way it was written when it was made, so it can be typed
+/// in lowercase or without the dash.
+fn hash(code: &str) -> String {
+    let code = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_uppercase())
+        .collect::<String>();
+    base64::engine::general_purpose::STANDARD.encode(Sha256::digest(code.as_bytes()))
+}
+
+fn unexpired(code: &GuestCode) -> bool {
+    code.expires_at > Utc::now()
+}
+
+impl GuestCodes {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(GuestCodes {
+            codes: JsonFile::from_env("KPRINT_GUEST_CODES_FILE")?,
+        })
+    }
+
+    /// Keeps `code`, returning it with a newly made code filled in.
+    pub async fn create(&self, mut code: GuestCode) -> anyhow::Result<GuestCode> {
+        // fastrand isn't meant for secrets, but v4 UUIDs come from the OS
+        let bytes = Uuid::new_v4().into_bytes();
+        let (first, last) = RANDOM_BYTES.split_at(RANDOM_BYTES.len() / 2);
+        let chunk = |indices: &[usize]| {
+            indices
+                .iter()
+                .map(|&i| ALPHABET[bytes[i] as usize % ALPHABET.len()] as char)
+                .collect::<String>()
+        };
+        let secret = format!("{}-{}", chunk(first), chunk(last));
+
+        self.codes
+            .change(|codes| {
+                codes.retain(|_, stored| unexpired(&stored.code));
+                codes.insert(
+                    code.id,
+                    StoredCode {
+                        hash: hash(&secret),
+                        code: code.clone(),
+                    },
+                );
+            })
+            .await?;
+        code.code = Some(secret);
+        Ok(code)
+    }
+
+    /// Every code that hasn't expired, the soonest to expire first.
+    pub fn list(&self) -> Vec<GuestCode> {
+        let mut codes = self.codes.read(|codes| {
+            codes
+                .values()
+                .map(|stored| stored.code.clone())
+                .filter(unexpired)
+                .collect::<Vec<_>>()
+        });
+        codes.sort_by(|a, b| a.expires_at.cmp(&b.expires_at).then(a.id.cmp(&b.id)));
+        codes
+    }
+
+    /// Stops code `id` from working, returning whether it still did.
+    pub async fn revoke(&self, id: Uuid) -> anyhow::Result<bool> {
+        self.codes
+            .change(|codes| {
+                let revoked = codes
+                    .remove(&id)
+                    .is_some_and(|stored| unexpired(&stored.code));
+                codes.retain(|_, stored| unexpired(&stored.code));
+                revoked
+            })
+            .await
+    }
+
+    /// The guest code `code`, as long as it hasn't expired.
+    pub fn check(&self, code: &str) -> Result<GuestCode, KprintError> {
+        let hash = hash(code);
+        self.codes
+            .read(|codes| {
+                codes
+                    .values()
+                    .find(|stored| stored.hash == hash)
+                    .map(|stored| stored.code.clone())
+            })
+            .filter(unexpired)
+            .ok_or(KprintError::GuestCodeInvalid)
+    }
+
+    /// Takes `pages` off what `code` has left, turning the job away if it
+    /// doesn't have that many.
+    pub async fn reserve(&self, code: &str, pages: u32) -> Result<GuestCode, KprintError> {
+        let hash = hash(code);
+        self.codes
+            .change(|codes| {
+                let stored = codes
+                    .values_mut()
+                    .find(|stored| stored.hash == hash)
+                    .filter(|stored| unexpired(&stored.code))
+                    .ok_or(KprintError::GuestCodeInvalid)?;
+                let remaining = stored.code.pages.saturating_sub(stored.code.pages_used);
+                if pages > remaining {
+                    return Err(KprintError::GuestCodeUsedUp { remaining });
+                }
+                stored.code.pages_used += pages;
+                Ok(stored.code.clone())
+            })
+            .await
+            .map_err(KprintError::Database)?
+    }
+
+    /// Gives code `id` back `pages` it took for a job that didn't print.
+    pub async fn refund(&self, id: Uuid, pages: u32) {
+        let refunded = self
+            .codes
+            .change(|codes| {
+                if let Some(stored) = codes.get_mut(&id) {
+                    stored.code.pages_used = stored.code.pages_used.saturating_sub(pages);
+                }
+            })
+            .await;
+        if let Err(err) = refunded {
+            log::warn!("Couldn't save guest code {id} after giving back {pages} pages: {err}");
+        }
+    }
+}
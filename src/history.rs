@@ -0,0 +1,275 @@
+//! Every job kprint has sent on, kept long after the printer forgets them,
+//! for working out how the printers get used.
+//!
+//! When `KPRINT_HISTORY_FILE` is set each entry is appended there as a line
+//! of JSON. A job whose entry changes, like when it finishes, gets a line of
+//! its own, which replaces the earlier one when the file is read back.
+//!
+//! Titles are indexed by every three characters in a row they have, so
+//! searching for part of one only has to look at the entries that could
+//! match. Entries are indexed by owner too, so adding up what someone's
+//! printed only looks at their jobs.
+
+use crate::app::AppState;
+use crate::service;
+use actix_web::web::Data;
+use chrono::{Duration, Utc};
+use kprint_client::models::{HistoryEntry, JobState, JobStatus};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::sync::RwLock;
+use uuid::Uuid;
+
+/// How often unfinished jobs are checked on
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+/// How long a job is checked on for before it's left as it was last seen
+const FOLLOW_LIMIT: Duration = Duration::days(1);
+
+#[derive(Default)]
+struct Entries {
+    /// In the order they were first recorded
+    entries: Vec<HistoryEntry>,
+    /// Where each job's entry is in `entries`
+    index: HashMap<Uuid, usize>,
+    /// Which of `entries` have each trigram in their title, in order
+    trigrams: HashMap<String, Vec<usize>>,
+    /// Which of `entries` are each member's jobs, in order
+    owners: HashMap<String, Vec<usize>>,
+}
+
+/// Every run of three characters in `text`, ignoring case.
+fn trigrams(text: &str) -> impl Iterator<Item = String> {
+    let chars = text.to_lowercase().chars().collect::<Vec<_>>();
+    let trigrams = chars
+        .windows(3)
+        .map(|trigram| trigram.iter().collect::<String>())
+        .collect::<std::collections::HashSet<_>>();
+    trigrams.into_iter()
+}
+
+impl Entries {
+    fn upsert(&mut self, entry: HistoryEntry) {
+        let at = match self.index.get(&entry.id) {
+            Some(&at) if self.entries[at].title == entry.title => {
+                self.entries[at] = entry;
+                return;
+            }
+            Some(&at) => at,
+            None => {
+                self.index.insert(entry.id, self.entries.len());
+                self.owners
+                    .entry(entry.owner.clone())
+                    .or_default()
+                    .push(self.entries.len());
+                self.entries.len()
+            }
+        };
+        // Where an entry's title changes its old trigrams are left behind,
+        // which only costs a look at an entry that won't match
+        for trigram in trigrams(&entry.title) {
+            let postings = self.trigrams.entry(trigram).or_default();
+            if let Err(position) = postings.binary_search(&at) {
+                postings.insert(position, at);
+            }
+        }
+        if at == self.entries.len() {
+            self.entries.push(entry);
+        } else {
+            self.entries[at] = entry;
+        }
+    }
+
+    /// Positions of the entries that could have every term in their title.
+    /// Terms too short to have trigrams can't narrow it down, so with only
+    /// those it's every entry.
+    fn candidates(&self, terms: &[String]) -> Option<Vec<usize>> {
+        let mut candidates: Option<Vec<usize>> = None;
+        for trigram in terms.iter().flat_map(|term| trigrams(term)) {
+            let Some(postings) = self.trigrams.get(&trigram) else {
+                return Some(vec![]);
+            };
+            candidates = Some(match candidates {
+                None => postings.clone(),
+                Some(candidates) => candidates
+                    .into_iter()
+                    .filter(|at| postings.binary_search(at).is_ok())
+                    .collect(),
+            });
+        }
+        candidates
+    }
+}
+
+#[derive(Default)]
+pub struct History {
+    file: Option<std::fs::File>,
+    entries: RwLock<Entries>,
+}
+
+impl History {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let Ok(path) = std::env::var("KPRINT_HISTORY_FILE") else {
+            return Ok(Self::default());
+        };
+        let mut entries = Entries::default();
+        match std::fs::File::open(&path) {
+            Ok(file) => {
+                for line in std::io::BufReader::new(file).lines() {
+                    let line = line?;
+                    // A line cut short by a crash is the only one that can be
+                    // bad, and it's always the last
+                    match serde_json::from_str(&line) {
+                        Ok(entry) => entries.upsert(entry),
+                        Err(err) => log::warn!("Skipping a bad line in {path}: {err}"),
+                    }
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        Ok(History {
+            file: Some(file),
+            entries: RwLock::new(entries),
+        })
+    }
+
+    /// Records `entry`, replacing whatever was recorded for the same job.
+    pub fn record(&self, entry: HistoryEntry) {
+        let mut entries = self.entries.write().unwrap();
+        if let Some(mut file) = self.file.as_ref() {
+            let mut line = serde_json::to_vec(&entry).unwrap();
+            line.push(b'\n');
+            if let Err(err) = file.write_all(&line) {
+                log::error!("Couldn't add job {} to the history: {err}", entry.id);
+            }
+        }
+        entries.upsert(entry);
+    }
+
+    /// Updates a job's entry with what its printer last said about it, if
+    /// that's changed anything, returning the entry if that's the job
+    /// finishing.
+    pub fn observe(&self, status: &JobStatus) -> Option<HistoryEntry> {
+        let mut entry = self.get(&status.id)?;
+        let pages = status
+            .attributes
+            .get("job-impressions-completed")
+            .and_then(|pages| pages.parse().ok())
+            .filter(|pages| *pages > 0)
+            .or(entry.pages);
+        if entry.state == status.state && entry.pages == pages {
+            return None;
+        }
+        let finishing = !finished(entry.state) && finished(status.state);
+        entry.state = status.state;
+        entry.pages = pages;
+        self.record(entry.clone());
+        finishing.then_some(entry)
+    }
+
+    /// Checks on every recent unfinished job each [`POLL_INTERVAL`], for as
+    /// long as kprint runs, so the history learns how they ended and how many
+    /// pages they took.
+    pub fn follow_in_background(app_data: Data<AppState>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let since = Utc::now() - FOLLOW_LIMIT;
+                let unfinished = app_data.history.filter(|entry| {
+                    entry.submitted_at > since
+                        && entry.failure.is_none()
+                        && app_data.jobs.get(&entry.id).is_some()
+                        && !finished(entry.state)
+                });
+                futures::future::join_all(unfinished.iter().map(|entry| follow(&app_data, entry)))
+                    .await;
+            }
+        });
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<HistoryEntry> {
+        let entries = self.entries.read().unwrap();
+        let at = *entries.index.get(id)?;
+        Some(entries.entries[at].clone())
+    }
+
+    /// Every entry `keep` keeps, in the order the jobs were submitted.
+    pub fn filter(&self, keep: impl Fn(&HistoryEntry) -> bool) -> Vec<HistoryEntry> {
+        self.entries
+            .read()
+            .unwrap()
+            .entries
+            .iter()
+            .filter(|entry| keep(entry))
+            .cloned()
+            .collect()
+    }
+
+    /// Like [`History::filter`], but only entries for `owner`'s jobs, which
+    /// are found with the index.
+    pub fn owned_by(&self, owner: &str, keep: impl Fn(&HistoryEntry) -> bool) -> Vec<HistoryEntry> {
+        let entries = self.entries.read().unwrap();
+        let Some(owned) = entries.owners.get(owner) else {
+            return vec![];
+        };
+        owned
+            .iter()
+            .map(|&at| &entries.entries[at])
+            .filter(|entry| keep(entry))
+            .cloned()
+            .collect()
+    }
+
+    /// Like [`History::filter`], but only entries with every one of `terms`
+    /// somewhere in their title, which are found with the index.
+    pub fn search(
+        &self,
+        terms: &[String],
+        keep: impl Fn(&HistoryEntry) -> bool,
+    ) -> Vec<HistoryEntry> {
+        let entries = self.entries.read().unwrap();
+        let keep = |entry: &&HistoryEntry| title_matches(&entry.title, terms) && keep(entry);
+        match entries.candidates(terms) {
+            Some(candidates) => candidates
+                .into_iter()
+                .map(|at| &entries.entries[at])
+                .filter(keep)
+                .cloned()
+                .collect(),
+            None => entries.entries.iter().filter(keep).cloned().collect(),
+        }
+    }
+}
+
+/// Whether `title` has every one of `terms`, which are already lowercase,
+/// somewhere in it.
+pub fn title_matches(title: &str, terms: &[String]) -> bool {
+    let title = title.to_lowercase();
+    terms.iter().all(|term| title.contains(term.as_str()))
+}
+
+/// Checks on `entry`'s job, which records what it says.
+async fn follow(app_data: &AppState, entry: &HistoryEntry) {
+    if let Err(err) = service::job_status(app_data, &entry.owner, entry.id).await {
+        log::debug!("Couldn't check on job {} for the history: {err}", entry.id);
+    }
+}
+
+/// Whether a job in `state` is done with, one way or another.
+pub fn finished(state: JobState) -> bool {
+    matches!(
+        state,
+        JobState::Completed | JobState::Canceled | JobState::Aborted
+    )
+}
+
+/// Whether `entry` is for a job that didn't print.
+pub fn failed(entry: &HistoryEntry) -> bool {
+    entry.failure.is_some() || entry.state == JobState::Aborted
+}
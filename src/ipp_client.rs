@@ -0,0 +1,365 @@
+//! How kprint talks to CUPS.
+//!
+//! The ipp crate's client builds a new HTTP client, and so a new connection,
+//! for every request. Every printer here shares one pooled client instead, so
+//! a burst of jobs reuses a handful of kept-alive connections to CUPS rather
+//! than opening one per request.
+//!
+//! Every request is also given a time limit, so a printer that's stopped
+//! answering can't hold a job up for as long as the OS takes to give up on
+//! it. `KPRINT_IPP_CONNECT_TIMEOUT`, `KPRINT_IPP_SUBMIT_TIMEOUT`,
+//! `KPRINT_IPP_STATUS_TIMEOUT` and `KPRINT_IPP_ATTRIBUTES_TIMEOUT` set them in
+//! seconds for every printer, and `KPRINT_PRINTER_TIMEOUTS` changes them for
+//! some, like `plotter:submit=1800 lounge:connect=3`. Submitting covers
+//! sending the whole document, status covers looking up and cancelling jobs,
+//! and attributes covers asking about the printer itself.
+//!
+//! Requests to printers that want IPP spoken differently are rewritten to
+//! suit them on the way out, as [`crate::dialect`] describes.
+//!
+//! With `KPRINT_IPP_RECORD_DIR` set, every request and reply is also written
+//! out as it went over the wire, as [`crate::recording`] describes. Requests
+//! sent while serving one of kprint's own carry on its trace, as
+//! [`crate::trace`] describes.
+
+use crate::dialect::Dialect;
+use crate::metrics;
+use crate::null_printer::NullPrinter;
+use crate::recording::Recorder;
+use crate::trace::{self, TRACEPARENT, TRACESTATE};
+use anyhow::Context;
+use futures::io::Cursor;
+use futures::TryStreamExt;
+use ipp::parser::AsyncIppParser;
+use ipp::prelude::{IppRequestResponse, Operation, Uri};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+use tokio_util::io::ReaderStream;
+
+/// How long a connection sits unused before it's closed, kept under CUPS's
+/// own keep-alive timeout so kprint never sends on one CUPS already dropped
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Most of a request sent to CUPS in one go, big enough for the coalesced
+/// chunks of [`crate::streaming`] to go through whole
+const WRITE_SIZE: usize = 64 * 1024;
+
+/// How long requests to a printer get, by what they're doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    pub connect: Duration,
+    /// Sending a job, document and all, so it's long enough for big ones
+    pub submit: Duration,
+    pub status: Duration,
+    pub attributes: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Duration::from_secs(10),
+            submit: Duration::from_secs(10 * 60),
+            status: Duration::from_secs(30),
+            attributes: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Timeouts {
+    fn set(&mut self, operation: &str, seconds: &str) -> anyhow::Result<()> {
+        let timeout = Duration::from_secs(seconds.parse()?);
+        if timeout.is_zero() {
+            anyhow::bail!("The {operation} timeout can't be 0");
+        }
+        match operation {
+            "connect" => self.connect = timeout,
+            "submit" => self.submit = timeout,
+            "status" => self.status = timeout,
+            "attributes" => self.attributes = timeout,
+            _ => anyhow::bail!(
+                "{operation:?} doesn't have a timeout, try connect, submit, status or attributes"
+            ),
+        }
+        Ok(())
+    }
+
+    /// How long `request` gets, going by its operation.
+    fn of(&self, request: &IppRequestResponse) -> Duration {
+        let operation = request.header().operation_or_status;
+        if [
+            Operation::PrintJob,
+            Operation::PrintUri,
+            Operation::SendDocument,
+            Operation::SendUri,
+        ]
+        .into_iter()
+        .any(|submit| submit as u16 == operation)
+        {
+            self.submit
+        } else if operation == Operation::GetPrinterAttributes as u16 {
+            self.attributes
+        } else {
+            self.status
+        }
+    }
+}
+
+/// The timeouts for every printer.
+#[derive(Debug, Clone, Default)]
+pub struct PrinterTimeouts {
+    default: Timeouts,
+    printers: HashMap<String, Timeouts>,
+}
+
+impl PrinterTimeouts {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut default = Timeouts::default();
+        for operation in ["connect", "submit", "status", "attributes"] {
+            let var = format!("KPRINT_IPP_{}_TIMEOUT", operation.to_uppercase());
+            if let Ok(seconds) = std::env::var(&var) {
+                default.set(operation, &seconds).context(var)?;
+            }
+        }
+        let mut printers = HashMap::<String, Timeouts>::new();
+        if let Ok(timeouts) = std::env::var("KPRINT_PRINTER_TIMEOUTS") {
+            for entry in timeouts.split_whitespace() {
+                let Some((printer, (operation, seconds))) = entry
+                    .split_once(':')
+                    .and_then(|(printer, setting)| Some((printer, setting.split_once('=')?)))
+                else {
+                    anyhow::bail!("{entry:?} should look like printer:operation=seconds");
+                };
+                printers
+                    .entry(printer.to_string())
+                    .or_insert(default)
+                    .set(operation, seconds)
+                    .with_context(|| format!("in {entry:?}"))?;
+            }
+        }
+        Ok(PrinterTimeouts { default, printers })
+    }
+
+    pub fn of(&self, printer: &str) -> Timeouts {
+        self.printers.get(printer).copied().unwrap_or(self.default)
+    }
+}
+
+/// Builds the HTTP client printers send through, giving up on connecting
+/// after `connect_timeout`. `token`, when there is one, goes to the proxy in
+/// front of CUPS on every request.
+pub fn http_client(
+    token: Option<&str>,
+    connect_timeout: Duration,
+) -> anyhow::Result<reqwest::Client> {
+    let mut headers = HeaderMap::new();
+    if let Some(token) = token {
+        let mut token = HeaderValue::from_str(token)?;
+        token.set_sensitive(true);
+        headers.insert(AUTHORIZATION, token);
+    }
+    Ok(reqwest::Client::builder()
+        .connect_timeout(connect_timeout)
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .tcp_keepalive(POOL_IDLE_TIMEOUT)
+        .user_agent(concat!("kprint/", env!("CARGO_PKG_VERSION")))
+        .default_headers(headers)
+        .build()?)
+}
+
+/// What can go wrong sending a request to a printer.
+#[derive(thiserror::Error, Debug)]
+pub enum IppError {
+    /// CUPS couldn't be reached, or the HTTP request to it failed
+    #[error("{uri}: {source}")]
+    Unreachable { uri: String, source: reqwest::Error },
+    #[error("{uri} didn't answer within {timeout:?}")]
+    TimedOut {
+        uri: String,
+        timeout: Duration,
+        source: reqwest::Error,
+    },
+    /// The proxy in front of CUPS turned `KPRINT_CUPS_PROXY_TOKEN` down
+    #[error("The CUPS proxy at {uri} rejected kprint's token ({status})")]
+    ProxyAuth {
+        uri: String,
+        status: reqwest::StatusCode,
+    },
+    /// CUPS answered, with something that isn't IPP
+    #[error("{uri} didn't answer in IPP: {source}")]
+    Protocol {
+        uri: String,
+        source: ipp::parser::IppParseError,
+    },
+    /// A null printer couldn't do what it was asked
+    #[error(transparent)]
+    Null(anyhow::Error),
+}
+
+/// One printer on the CUPS server.
+#[derive(Clone)]
+pub struct IppClient {
+    uri: Uri,
+    backend: Backend,
+    timeouts: Timeouts,
+    dialect: Dialect,
+}
+
+#[derive(Clone)]
+enum Backend {
+    Cups {
+        http: reqwest::Client,
+        recorder: Option<Arc<Recorder>>,
+    },
+    /// Answered by kprint itself
+    Null(Arc<NullPrinter>),
+}
+
+/// Where to actually send requests for `uri`, since HTTP clients don't know
+/// the `ipp` schemes.
+fn http_url(uri: &Uri) -> String {
+    let (scheme, default_port) = match uri.scheme_str() {
+        Some("ipps") => ("https", 443),
+        Some("ipp") => ("http", 631),
+        _ => return uri.to_string(),
+    };
+    let Some(authority) = uri.authority() else {
+        return uri.to_string();
+    };
+    let port = authority.port_u16().unwrap_or(default_port);
+    let path = uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    format!("{scheme}://{}:{port}{path}", authority.host())
+}
+
+impl IppClient {
+    pub fn new(
+        uri: Uri,
+        http: reqwest::Client,
+        timeouts: Timeouts,
+        dialect: Dialect,
+        recorder: Option<Arc<Recorder>>,
+    ) -> Self {
+        IppClient {
+            uri,
+            backend: Backend::Cups { http, recorder },
+            timeouts,
+            dialect,
+        }
+    }
+
+    /// A printer kprint acts out itself, for developing without CUPS.
+    pub fn null(uri: Uri, printer: NullPrinter) -> Self {
+        IppClient {
+            uri,
+            backend: Backend::Null(Arc::new(printer)),
+            timeouts: Timeouts::default(),
+            dialect: Dialect::default(),
+        }
+    }
+
+    pub fn uri(&self) -> &Uri {
+        &self.uri
+    }
+
+    /// Whether it's a null printer, rather than one on the CUPS server.
+    pub fn is_null(&self) -> bool {
+        matches!(self.backend, Backend::Null(_))
+    }
+
+    /// Sends `request` to the printer, streaming any document along with it.
+    pub async fn send(
+        &self,
+        request: impl Into<IppRequestResponse>,
+    ) -> Result<IppRequestResponse, IppError> {
+        let mut request = request.into();
+        self.dialect.apply(&mut request);
+        let (http, recorder) = match &self.backend {
+            Backend::Cups { http, recorder } => (http, recorder),
+            Backend::Null(printer) => return printer.answer(request).await.map_err(IppError::Null),
+        };
+        let timeout = self.timeouts.of(&request);
+        let operation = request.header().operation_or_status;
+        let span = trace::current().map(|span| span.child());
+        let recording = recorder
+            .as_ref()
+            .map(|recorder| Arc::new(recorder.start(&self.uri.to_string(), &request)));
+        let capturing = recording.clone();
+        let body = ReaderStream::with_capacity(request.into_async_read().compat(), WRITE_SIZE)
+            .inspect_ok(move |chunk| {
+                if let Some(recording) = &capturing {
+                    recording.capture(chunk);
+                }
+            });
+        // Replies are only ever attributes, so they're read in whole, which
+        // also hands the connection straight back to the pool
+        let mut post = http
+            .post(http_url(&self.uri))
+            .header(CONTENT_TYPE, "application/ipp")
+            .timeout(timeout);
+        if let Some(span) = &span {
+            post = post.header(TRACEPARENT.as_str(), span.traceparent());
+            if let Some(state) = &span.state {
+                post = post.header(TRACESTATE.as_str(), state);
+            }
+        }
+        let started = Instant::now();
+        let response = async {
+            post.body(reqwest::Body::wrap_stream(body))
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await
+        }
+        .await;
+        if let Some(span) = &span {
+            let operation = crate::recording::operation_name(operation);
+            log::info!(
+                target: "kprint::trace",
+                "trace_id={} span_id={} parent_id={} name={operation:?} uri={} duration_ms={} ok={}",
+                span.trace_id(),
+                span.span_id(),
+                trace::current().map_or_else(String::new, |parent| parent.span_id()),
+                self.uri,
+                started.elapsed().as_millis(),
+                response.is_ok()
+            );
+        }
+        if let Some(recording) = &recording {
+            let response = response.as_deref().map_err(ToString::to_string);
+            recording.finish(response).await;
+        }
+        let uri = self.uri.to_string();
+        let response = response.map_err(|source: reqwest::Error| match source.status() {
+            Some(status @ (reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN)) => {
+                log::error!(
+                    "The CUPS proxy at {uri} answered {status}, \
+                     check KPRINT_CUPS_PROXY_TOKEN is set and hasn't expired or been revoked"
+                );
+                metrics::cups_proxy_auth_failed();
+                IppError::ProxyAuth {
+                    uri: uri.clone(),
+                    status,
+                }
+            }
+            _ if source.is_timeout() => IppError::TimedOut {
+                uri: uri.clone(),
+                timeout,
+                source,
+            },
+            _ => IppError::Unreachable {
+                uri: uri.clone(),
+                source,
+            },
+        })?;
+        AsyncIppParser::new(Cursor::new(response.to_vec()))
+            .parse()
+            .await
+            .map_err(|source| IppError::Protocol { uri, source })
+    }
+}
@@ -0,0 +1,589 @@
+//! kprint speaking IPP itself, so it can be added as a printer straight from
+//! an operating system's print dialog.
+//!
+//! Clients log in with HTTP Basic auth, using their username and a print
+//! password from `POST /api/v1/me/print-password`.
+
+use crate::api::forward_payload;
+use crate::app::AppState;
+use crate::error::{FieldError, KprintError};
+use crate::jobs::find_attribute;
+use crate::logging::Pii;
+use crate::options::PrintQuery;
+use crate::service::{self, Submitted};
+use actix_web::{
+    dev::ConnectionInfo,
+    http::header,
+    post,
+    web::{self, Data, Path, Payload},
+    HttpRequest, HttpResponse,
+};
+use ipp::{parser::AsyncIppParser, prelude::*};
+use kprint_client::models::{ColorMode, DuplexMode, JobState};
+use std::time::Instant;
+
+/// The media type of every IPP request and response body
+const IPP_MIME: &str = "application/ipp";
+
+/// Formats we pass straight through, leaving the rest for CUPS to sniff
+pub const DOCUMENT_FORMATS: &[&str] = &[
+    "application/octet-stream",
+    "application/pdf",
+    "application/postscript",
+    "image/jpeg",
+    "image/png",
+    "image/urf",
+    "text/plain",
+];
+
+const OPERATIONS: &[Operation] = &[
+    Operation::PrintJob,
+    Operation::ValidateJob,
+    Operation::GetJobs,
+    Operation::GetPrinterAttributes,
+];
+
+/// What we told clients `printer-up-time` counts from
+static STARTED: std::sync::OnceLock<Instant> = std::sync::OnceLock::new();
+
+/// The URI to give print dialogs for `printer`, as reached by this request.
+pub fn printer_uri(connection: &ConnectionInfo, printer: &str) -> String {
+    let scheme = if connection.scheme() == "https" {
+        "ipps"
+    } else {
+        "ipp"
+    };
+    format!("{scheme}://{}/ipp/printers/{printer}", connection.host())
+}
+
+/// What people see a printer called in their print dialog, like
+/// "CSH Lounge Printer".
+pub fn display_name(printer: &str) -> String {
+    let mut chars = printer.chars();
+    let capitalized = chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+        .unwrap_or_default();
+    format!("CSH {capitalized} Printer")
+}
+
+/// Checks the Basic credentials of an IPP request, returning the username.
+fn authenticate(app_data: &AppState, req: &HttpRequest) -> Option<String> {
+    use base64::Engine;
+    let credentials = req
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let credentials = base64::engine::general_purpose::STANDARD
+        .decode(credentials.trim())
+        .ok()?;
+    let credentials = String::from_utf8(credentials).ok()?;
+    let (username, password) = credentials.split_once(':')?;
+    app_data
+        .print_passwords
+        .verify(username, password)
+        .then(|| username.to_string())
+}
+
+fn respond(response: IppRequestResponse) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(IPP_MIME)
+        .body(response.to_bytes())
+}
+
+/// A response with just a status and an explanation for it.
+fn status_response(request: &IppHeader, status: StatusCode, message: &str) -> IppRequestResponse {
+    let mut response =
+        IppRequestResponse::new_response(request.version, status, request.request_id);
+    response.attributes_mut().add(
+        DelimiterTag::OperationAttributes,
+        IppAttribute::new(
+            IppAttribute::STATUS_MESSAGE,
+            IppValue::TextWithoutLanguage(message.to_string()),
+        ),
+    );
+    response
+}
+
+fn keyword(value: &str) -> IppValue {
+    IppValue::Keyword(value.to_string())
+}
+
+fn keywords<'a>(values: impl IntoIterator<Item = &'a str>) -> IppValue {
+    IppValue::Array(values.into_iter().map(keyword).collect())
+}
+
+/// The IPP keyword for one of our own enum values.
+fn variant<T: serde::Serialize>(value: &T) -> &'static str {
+    serde_variant::to_variant_name(value).unwrap()
+}
+
+/// Turns a job's IPP attributes into the same options the REST API takes, so
+/// both get validated identically. Anything a print dialog left out gets the
+/// cheapest reasonable default.
+fn print_query(attributes: &IppAttributes) -> PrintQuery {
+    let text = |name| match find_attribute(attributes, name) {
+        Some(IppValue::Keyword(value))
+        | Some(IppValue::NameWithoutLanguage(value))
+        | Some(IppValue::NameWithLanguage { name: value, .. }) => Some(value.clone()),
+        _ => None,
+    };
+    let color_mode = match text("print-color-mode").as_deref() {
+        Some("color") => variant(&ColorMode::Color),
+        _ => variant(&ColorMode::Grayscale),
+    };
+    let pages = match find_attribute(attributes, "page-ranges") {
+        Some(IppValue::RangeOfInteger { min, max }) => format!("{min}-{max}"),
+        Some(IppValue::Array(ranges)) => ranges
+            .iter()
+            .filter_map(|range| match range {
+                IppValue::RangeOfInteger { min, max } => Some(format!("{min}-{max}")),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join(","),
+        _ => String::new(),
+    };
+    let copies = match find_attribute(attributes, "copies") {
+        Some(IppValue::Integer(copies)) => copies.to_string(),
+        _ => "1".to_string(),
+    };
+    PrintQuery {
+        sides: Some(text("sides").unwrap_or_else(|| variant(&DuplexMode::OneSided).to_string())),
+        color_mode: Some(color_mode.to_string()),
+        pages: Some(pages),
+        copies: Some(copies),
+        title: Some(text(IppAttribute::JOB_NAME).unwrap_or_else(|| "Untitled".to_string())),
+        // Any banner at all gets kprint's cover sheet
+        cover_sheet: Some(
+            text("job-sheets")
+                .is_some_and(|sheets| sheets != "none")
+                .to_string(),
+        ),
+        allow_fallback: None,
+        separate_jobs: None,
+        tags: None,
+        media: None,
+        margins: None,
+        offset: None,
+        poster: None,
+        envelope: None,
+    }
+}
+
+/// Maps a field of [`PrintQuery`] back to the IPP attribute it came from.
+fn ipp_name(field: &str) -> &str {
+    match field {
+        "colorMode" => "print-color-mode",
+        "pages" => "page-ranges",
+        "title" => IppAttribute::JOB_NAME,
+        "coverSheet" => "job-sheets",
+        other => other,
+    }
+}
+
+/// The response to a job whose options didn't validate, listing the culprits
+/// in the unsupported attributes group like IPP expects.
+fn invalid_options(request: &IppHeader, fields: &[FieldError]) -> IppRequestResponse {
+    let message = fields
+        .iter()
+        .map(|field| field.message.as_str())
+        .collect::<Vec<_>>()
+        .join("; ");
+    let mut response = status_response(
+        request,
+        StatusCode::ClientErrorAttributesOrValuesNotSupported,
+        &message,
+    );
+    for field in fields {
+        response.attributes_mut().add(
+            DelimiterTag::UnsupportedAttributes,
+            IppAttribute::new(ipp_name(&field.field), IppValue::NoValue),
+        );
+    }
+    response
+}
+
+async fn printer_attributes(
+    app_data: &AppState,
+    request: &IppHeader,
+    printer: &str,
+    printer_uri: &str,
+) -> IppRequestResponse {
+    let status = service::printer_status(app_data, printer).await;
+    let mut response = IppRequestResponse::new_response(
+        request.version,
+        StatusCode::SuccessfulOk,
+        request.request_id,
+    );
+    let (state, reasons, accepting) = match &status {
+        Ok(status) => (
+            match status.state {
+                kprint_client::models::PrinterState::Processing => 4,
+                kprint_client::models::PrinterState::Stopped => 5,
+                _ => 3,
+            },
+            status.state_reasons.clone(),
+            status.accepting_jobs,
+        ),
+        // Printers that can't be reached still get listed, just stopped
+        Err(_) => (5, vec!["offline-report".to_string()], false),
+    };
+    let reasons = if reasons.is_empty() {
+        vec!["none".to_string()]
+    } else {
+        reasons
+    };
+    let up_time = STARTED.get_or_init(Instant::now).elapsed().as_secs().max(1);
+
+    let attributes = [
+        (
+            IppAttribute::PRINTER_URI_SUPPORTED,
+            IppValue::Uri(printer_uri.to_string()),
+        ),
+        (
+            IppAttribute::URI_SECURITY_SUPPORTED,
+            keyword(if printer_uri.starts_with("ipps") {
+                "tls"
+            } else {
+                "none"
+            }),
+        ),
+        (IppAttribute::URI_AUTHENTICATION_SUPPORTED, keyword("basic")),
+        (
+            IppAttribute::PRINTER_NAME,
+            IppValue::NameWithoutLanguage(printer.to_string()),
+        ),
+        (
+            IppAttribute::PRINTER_INFO,
+            IppValue::TextWithoutLanguage(display_name(printer)),
+        ),
+        (
+            IppAttribute::PRINTER_MAKE_AND_MODEL,
+            IppValue::TextWithoutLanguage("kprint".to_string()),
+        ),
+        (IppAttribute::PRINTER_STATE, IppValue::Enum(state)),
+        (
+            IppAttribute::PRINTER_STATE_REASONS,
+            keywords(reasons.iter().map(String::as_str)),
+        ),
+        (
+            IppAttribute::PRINTER_IS_ACCEPTING_JOBS,
+            IppValue::Boolean(accepting),
+        ),
+        (
+            IppAttribute::PRINTER_UP_TIME,
+            IppValue::Integer(up_time as i32),
+        ),
+        (
+            IppAttribute::IPP_VERSIONS_SUPPORTED,
+            keywords(["1.1", "2.0"]),
+        ),
+        (
+            IppAttribute::OPERATIONS_SUPPORTED,
+            IppValue::Array(
+                OPERATIONS
+                    .iter()
+                    .map(|op| IppValue::Enum(*op as i32))
+                    .collect(),
+            ),
+        ),
+        (
+            IppAttribute::CHARSET_CONFIGURED,
+            IppValue::Charset("utf-8".to_string()),
+        ),
+        (
+            IppAttribute::CHARSET_SUPPORTED,
+            IppValue::Charset("utf-8".to_string()),
+        ),
+        (
+            IppAttribute::NATURAL_LANGUAGE_CONFIGURED,
+            IppValue::NaturalLanguage("en".to_string()),
+        ),
+        (
+            IppAttribute::GENERATED_NATURAL_LANGUAGE_SUPPORTED,
+            IppValue::NaturalLanguage("en".to_string()),
+        ),
+        (
+            IppAttribute::DOCUMENT_FORMAT_DEFAULT,
+            IppValue::MimeMediaType("application/octet-stream".to_string()),
+        ),
+        (
+            IppAttribute::DOCUMENT_FORMAT_SUPPORTED,
+            IppValue::Array(
+                DOCUMENT_FORMATS
+                    .iter()
+                    .map(|format| IppValue::MimeMediaType(format.to_string()))
+                    .collect(),
+            ),
+        ),
+        (
+            IppAttribute::PDL_OVERRIDE_SUPPORTED,
+            keyword("not-attempted"),
+        ),
+        (IppAttribute::COMPRESSION_SUPPORTED, keyword("none")),
+        (
+            IppAttribute::SIDES_SUPPORTED,
+            keywords(DuplexMode::ALL.iter().map(variant)),
+        ),
+        (
+            IppAttribute::SIDES_DEFAULT,
+            keyword(variant(&DuplexMode::OneSided)),
+        ),
+        (
+            IppAttribute::PRINT_COLOR_MODE_SUPPORTED,
+            keywords(["monochrome", "color"]),
+        ),
+        (
+            IppAttribute::PRINT_COLOR_MODE_DEFAULT,
+            keyword("monochrome"),
+        ),
+        (IppAttribute::COLOR_SUPPORTED, IppValue::Boolean(true)),
+        (
+            IppAttribute::COPIES_SUPPORTED,
+            IppValue::RangeOfInteger {
+                min: 1,
+                max: crate::options::MAX_COPIES as i32,
+            },
+        ),
+        (IppAttribute::COPIES_DEFAULT, IppValue::Integer(1)),
+        ("page-ranges-supported", IppValue::Boolean(true)),
+        ("job-sheets-supported", keywords(["none", "standard"])),
+        ("job-sheets-default", keyword("none")),
+        ("multiple-document-jobs-supported", IppValue::Boolean(false)),
+    ];
+    for (name, value) in attributes {
+        response.attributes_mut().add(
+            DelimiterTag::PrinterAttributes,
+            IppAttribute::new(name, value),
+        );
+    }
+    response
+}
+
+/// The attributes describing one job, as Print-Job and Get-Jobs report them.
+fn job_attributes(job_id: i32, printer_uri: &str, state: JobState) -> IppAttributeGroup {
+    let state = match state {
+        JobState::Pending => 3,
+        JobState::PendingHeld => 4,
+        JobState::Processing => 5,
+        JobState::ProcessingStopped => 6,
+        JobState::Canceled => 7,
+        JobState::Aborted => 8,
+        JobState::Completed => 9,
+        // Nothing better to say about a job the printer won't describe
+        JobState::Unknown => 3,
+    };
+    let mut group = IppAttributeGroup::new(DelimiterTag::JobAttributes);
+    for attribute in [
+        IppAttribute::new(IppAttribute::JOB_ID, IppValue::Integer(job_id)),
+        IppAttribute::new(
+            IppAttribute::JOB_URI,
+            IppValue::Uri(format!("{printer_uri}/jobs/{job_id}")),
+        ),
+        IppAttribute::new(IppAttribute::JOB_STATE, IppValue::Enum(state)),
+        IppAttribute::new(
+            IppAttribute::JOB_STATE_REASONS,
+            keyword(if state == 3 { "none" } else { "job-printing" }),
+        ),
+    ] {
+        group
+            .attributes_mut()
+            .insert(attribute.name().to_string(), attribute);
+    }
+    group
+}
+
+async fn get_jobs(
+    app_data: &AppState,
+    request: &IppRequestResponse,
+    username: &str,
+    printer: &str,
+    printer_uri: &str,
+) -> IppRequestResponse {
+    let header = request.header();
+    let completed = matches!(
+        find_attribute(request.attributes(), "which-jobs"),
+        Some(IppValue::Keyword(which)) if which == "completed"
+    );
+    let jobs = app_data
+        .jobs
+        .owned_by(username)
+        .into_iter()
+        .filter(|job| job.printer == printer);
+    let statuses = futures::future::join_all(
+        jobs.map(|job| async move { service::job_status(app_data, username, job.id).await }),
+    )
+    .await;
+
+    let mut response = IppRequestResponse::new_response(
+        header.version,
+        StatusCode::SuccessfulOk,
+        header.request_id,
+    );
+    for status in statuses.into_iter().flatten() {
+        let Some(job_id) = status.ipp_job_id else {
+            continue;
+        };
+        let finished = matches!(
+            status.state,
+            JobState::Completed | JobState::Canceled | JobState::Aborted
+        );
+        if finished != completed {
+            continue;
+        }
+        let mut group = job_attributes(job_id, printer_uri, status.state);
+        for attribute in [
+            IppAttribute::new(
+                IppAttribute::JOB_NAME,
+                IppValue::NameWithoutLanguage(status.title),
+            ),
+            IppAttribute::new(
+                "job-originating-user-name",
+                IppValue::NameWithoutLanguage(username.to_string()),
+            ),
+        ] {
+            group
+                .attributes_mut()
+                .insert(attribute.name().to_string(), attribute);
+        }
+        response.attributes_mut().groups_mut().push(group);
+    }
+    response
+}
+
+async fn handle(
+    app_data: &AppState,
+    request: IppRequestResponse,
+    username: &str,
+    printer: String,
+    printer_uri: &str,
+) -> IppRequestResponse {
+    let header = request.header().clone();
+    if !app_data.printers.contains_key(&printer) {
+        return status_response(&header, StatusCode::ClientErrorNotFound, "No such printer");
+    }
+
+    match header.operation_or_status {
+        op if op == Operation::GetPrinterAttributes as u16 => {
+            printer_attributes(app_data, &header, &printer, printer_uri).await
+        }
+        op if op == Operation::GetJobs as u16 => {
+            get_jobs(app_data, &request, username, &printer, printer_uri).await
+        }
+        op if op == Operation::ValidateJob as u16 => {
+            match print_query(request.attributes()).validate() {
+                Ok(_) => IppRequestResponse::new_response(
+                    header.version,
+                    StatusCode::SuccessfulOk,
+                    header.request_id,
+                ),
+                Err(fields) => invalid_options(&header, &fields),
+            }
+        }
+        op if op == Operation::PrintJob as u16 => {
+            let options = match print_query(request.attributes()).validate() {
+                Ok(options) => options,
+                Err(fields) => return invalid_options(&header, &fields),
+            };
+            let document = request.into_payload();
+            // Print passwords don't say who's in what groups, so print
+            // dialogs get the default quota tier
+            match service::submit(
+                app_data,
+                username,
+                false,
+                &[],
+                printer,
+                options,
+                document.into(),
+            )
+            .await
+            {
+                Ok(Submitted { job, state, .. }) => {
+                    let mut response = IppRequestResponse::new_response(
+                        header.version,
+                        StatusCode::SuccessfulOk,
+                        header.request_id,
+                    );
+                    let job_id = job.ipp_job_id.unwrap_or_default();
+                    response.attributes_mut().groups_mut().push(job_attributes(
+                        job_id,
+                        printer_uri,
+                        state,
+                    ));
+                    response
+                }
+                Err(err) => {
+                    log::warn!("Inbound IPP job from {} failed: {err}", Pii(username));
+                    let status = match err {
+                        KprintError::PrinterNotFound(_) => StatusCode::ClientErrorNotFound,
+                        // Passed on as it came
+                        KprintError::PrinterRefused { status, .. } => status,
+                        KprintError::NotEligible(_) | KprintError::UserSuspended { .. } => {
+                            StatusCode::ClientErrorForbidden
+                        }
+                        KprintError::DocumentInfected(_) | KprintError::QuotaExceeded { .. } => {
+                            StatusCode::ClientErrorNotPossible
+                        }
+                        KprintError::PrinterPaused(_)
+                        | KprintError::PrinterStopped { .. }
+                        | KprintError::PrinterInMaintenance { .. } => {
+                            StatusCode::ServerErrorNotAcceptingJobs
+                        }
+                        KprintError::PrinterUnavailable(_)
+                        | KprintError::PrinterUnhealthy { .. }
+                        | KprintError::QuotaUnavailable
+                        | KprintError::EligibilityUnavailable
+                        | KprintError::ScannerUnavailable(_)
+                        | KprintError::CupsUnreachable(_)
+                        | KprintError::CupsProxyAuth(_) => {
+                            StatusCode::ServerErrorServiceUnavailable
+                        }
+                        _ => StatusCode::ServerErrorInternalError,
+                    };
+                    status_response(&header, status, &err.to_string())
+                }
+            }
+        }
+        _ => status_response(
+            &header,
+            StatusCode::ServerErrorOperationNotSupported,
+            "kprint doesn't support that operation",
+        ),
+    }
+}
+
+#[post("/ipp/printers/{printer}")]
+pub async fn ipp_printer(
+    printer: Path<String>,
+    app_data: Data<AppState>,
+    req: HttpRequest,
+    payload: Payload,
+) -> HttpResponse {
+    let Some(username) = authenticate(&app_data, &req) else {
+        return HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, "Basic realm=\"kprint\""))
+            .finish();
+    };
+
+    let (tx, body) = app_data.streaming.channel();
+    forward_payload(payload, tx);
+
+    let request = match AsyncIppParser::new(body).parse().await {
+        Ok(request) => request,
+        Err(err) => {
+            log::debug!("Got a malformed IPP request: {err}");
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    let printer = printer.into_inner();
+    let printer_uri = printer_uri(&req.connection_info(), &printer);
+    respond(handle(&app_data, request, &username, printer, &printer_uri).await)
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(ipp_printer);
+}
@@ -0,0 +1,207 @@
+//! The SSO realms kprint takes tokens from.
+//!
+//! Members sign in through the CSH realm, which is all kprint trusts unless
+//! `KPRINT_OIDC_ISSUERS` says otherwise. It has the settings of each issuer
+//! under a name of its own, like
+//! `csh:url=https://sso.csh.rit.edu/auth/realms/csh
+//! ci:url=https://sso.csh.rit.edu/auth/realms/ci ci:audience=printing`.
+//! `url` is where the issuer is, and what its tokens have as their `iss`,
+//! and every issuer needs one. `client_id` is who its tokens have to be for,
+//! `kprint` by default. `audience` is another audience they're allowed to
+//! have too, and can be given more than once; it's `account` when it isn't,
+//! which Keycloak puts in every token. Tokens are checked against whichever
+//! issuer their `iss` says made them, and tokens from anywhere else are
+//! turned away.
+
+use crate::auth::{CshIdToken, CshIdTokenClaims};
+use anyhow::Context;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use openidconnect::core::{CoreClient, CoreProviderMetadata};
+use openidconnect::reqwest::async_http_client;
+use openidconnect::{Audience, ClientId, IssuerUrl, Nonce, NonceVerifier};
+use std::str::FromStr;
+
+/// The realm members sign in through
+const CSH_ISSUER: &str = "https://sso.csh.rit.edu/auth/realms/csh";
+const DEFAULT_CLIENT_ID: &str = "kprint";
+const DEFAULT_AUDIENCE: &str = "account";
+
+/// One issuer's settings, before it's been asked about itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issuer {
+    pub name: String,
+    pub url: String,
+    pub client_id: String,
+    /// Audiences besides `client_id` its tokens can have
+    pub audiences: Vec<String>,
+}
+
+impl Issuer {
+    fn new(name: &str) -> Self {
+        Issuer {
+            name: name.to_string(),
+            url: String::new(),
+            client_id: DEFAULT_CLIENT_ID.to_string(),
+            audiences: vec![],
+        }
+    }
+
+    fn set(&mut self, setting: &str, value: &str) -> anyhow::Result<()> {
+        match setting {
+            "url" => {
+                IssuerUrl::new(value.to_string())
+                    .with_context(|| format!("{value:?} isn't a URL"))?;
+                self.url = value.trim_end_matches('/').to_string();
+            }
+            "client_id" => self.client_id = value.to_string(),
+            "audience" => self.audiences.push(value.to_string()),
+            _ => anyhow::bail!("{setting:?} isn't a setting, try url, client_id or audience"),
+        }
+        Ok(())
+    }
+}
+
+/// Every issuer kprint takes tokens from, in the order they were given.
+pub fn from_env() -> anyhow::Result<Vec<Issuer>> {
+    let Ok(issuers) = std::env::var("KPRINT_OIDC_ISSUERS") else {
+        let mut csh = Issuer::new("csh");
+        csh.url = CSH_ISSUER.to_string();
+        csh.audiences.push(DEFAULT_AUDIENCE.to_string());
+        return Ok(vec![csh]);
+    };
+    let mut parsed = Vec::<Issuer>::new();
+    for entry in issuers.split_whitespace() {
+        let Some((name, (setting, value))) = entry
+            .split_once(':')
+            .and_then(|(name, setting)| Some((name, setting.split_once('=')?)))
+        else {
+            anyhow::bail!("{entry:?} in KPRINT_OIDC_ISSUERS should look like name:setting=value");
+        };
+        let issuer = match parsed.iter().position(|issuer| issuer.name == name) {
+            Some(at) => &mut parsed[at],
+            None => {
+                parsed.push(Issuer::new(name));
+                parsed.last_mut().unwrap()
+            }
+        };
+        issuer
+            .set(setting, value)
+            .with_context(|| format!("Bad KPRINT_OIDC_ISSUERS for {name}"))?;
+    }
+    if parsed.is_empty() {
+        anyhow::bail!("KPRINT_OIDC_ISSUERS doesn't have any issuers in it");
+    }
+    for issuer in &mut parsed {
+        if issuer.url.is_empty() {
+            anyhow::bail!("KPRINT_OIDC_ISSUERS doesn't have a url for {}", issuer.name);
+        }
+        if issuer.audiences.is_empty() {
+            issuer.audiences.push(DEFAULT_AUDIENCE.to_string());
+        }
+    }
+    for (at, issuer) in parsed.iter().enumerate() {
+        if parsed[..at].iter().any(|other| other.url == issuer.url) {
+            anyhow::bail!("KPRINT_OIDC_ISSUERS has {} more than once", issuer.url);
+        }
+    }
+    Ok(parsed)
+}
+
+/// An issuer that's said how to check its tokens.
+#[derive(Clone)]
+struct Discovered {
+    issuer: Issuer,
+    client: CoreClient,
+}
+
+/// Checks tokens from every issuer kprint trusts.
+#[derive(Clone)]
+pub struct Issuers {
+    issuers: Vec<Discovered>,
+}
+
+impl Issuers {
+    /// Asks each of `issuers` how to check its tokens, all at once.
+    pub async fn discover(issuers: Vec<Issuer>) -> anyhow::Result<Self> {
+        let discovered = issuers.into_iter().map(|issuer| async move {
+            let url = IssuerUrl::new(issuer.url.clone())?;
+            let metadata = CoreProviderMetadata::discover_async(url, &async_http_client)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Couldn't get {}'s provider metadata from {}",
+                        issuer.name, issuer.url
+                    )
+                })?;
+            let client = CoreClient::from_provider_metadata(
+                metadata,
+                ClientId::new(issuer.client_id.clone()),
+                None,
+            );
+            anyhow::Ok(Discovered { issuer, client })
+        });
+        let issuers = futures::future::try_join_all(discovered).await?;
+        Ok(Issuers { issuers })
+    }
+
+    /// Checks a bearer token's signature and audience against the issuer it
+    /// says it's from, returning its claims if it's good.
+    pub fn verify(&self, token: &str) -> Option<CshIdTokenClaims> {
+        let Some(url) = unverified_issuer(token) else {
+            log::warn!("Token doesn't say who issued it");
+            return None;
+        };
+        let Some(discovered) = self
+            .issuers
+            .iter()
+            .find(|discovered| discovered.issuer.url == url.trim_end_matches('/'))
+        else {
+            log::warn!("Token is from {url}, which kprint doesn't trust");
+            return None;
+        };
+        let token = match CshIdToken::from_str(token) {
+            Ok(token) => token,
+            Err(err) => {
+                log::warn!("Token couldn't be parsed: {err}");
+                return None;
+            }
+        };
+        let audiences = &discovered.issuer.audiences;
+        let verifier = discovered
+            .client
+            .id_token_verifier()
+            .set_other_audience_verifier_fn(|audience| {
+                audiences
+                    .iter()
+                    .any(|allowed| audience == &Audience::new(allowed.clone()))
+            });
+        match token.into_claims(&verifier, NullNonceVerifier) {
+            Ok(claims) => Some(claims),
+            Err(err) => {
+                log::warn!(
+                    "Couldn't verify token from {}: {err}",
+                    discovered.issuer.name
+                );
+                None
+            }
+        }
+    }
+}
+
+/// The `iss` `token` claims to be from, before anything's checked, which is
+/// only good for picking who to check it with.
+pub fn unverified_issuer(token: &str) -> Option<String> {
+    let payload = token.split('.').nth(1)?;
+    let payload = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+    let claims = serde_json::from_slice::<serde_json::Value>(&payload).ok()?;
+    Some(claims.get("iss")?.as_str()?.to_string())
+}
+
+// Please don't use this... I just don't know how computers work :(
+struct NullNonceVerifier;
+impl NonceVerifier for NullNonceVerifier {
+    fn verify(self, _nonce: Option<&Nonce>) -> Result<(), String> {
+        Ok(())
+    }
+}
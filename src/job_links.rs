@@ -0,0 +1,181 @@
+//! Signed links to jobs, so knowing a job's id isn't enough to follow it.
+//!
+//! When `KPRINT_JOB_LINK_SECRET` is set, every link kprint hands back for a
+//! job has `expires` and `signature` in its query string: when the link
+//! stops working, `KPRINT_JOB_LINK_TTL` seconds after it was made, a day by
+//! default, and an HMAC of that, the job's id and what the link does, like
+//! cancelling the job or fetching its receipt. The job's endpoints then want
+//! both, signed for what they do, as well as the job being the caller's, so
+//! ids that turn up in logs or get guessed can't be looked up, links passed
+//! around stop working, and a link to look at a job can't cancel it. Listing
+//! jobs hands back fresh links to each.
+
+use crate::app::AppState;
+use crate::error::KprintError;
+use crate::signing::Signer;
+use actix_web::http::Method;
+use actix_web::web::{Data, Query};
+use actix_web::{FromRequest, HttpRequest};
+use chrono::{Duration, Utc};
+use futures::future::{ready, Ready};
+use kprint_client::models::JobLinks;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+const DEFAULT_TTL: Duration = Duration::days(1);
+
+/// What a link to a job lets its holder do, each signed for separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Status,
+    Cancel,
+    Documents,
+    Close,
+    Preview,
+    Receipt,
+}
+
+impl Action {
+    fn name(self) -> &'static str {
+        match self {
+            Action::Status => "status",
+            Action::Cancel => "cancel",
+            Action::Documents => "documents",
+            Action::Close => "close",
+            Action::Preview => "preview",
+            Action::Receipt => "receipt",
+        }
+    }
+
+    /// What `req`, to one of `job`'s endpoints, is doing to it.
+    fn of(req: &HttpRequest, job: &Uuid) -> Option<Self> {
+        let (_, last) = req.path().trim_end_matches('/').rsplit_once('/')?;
+        let method = req.method();
+        match last {
+            "documents" if method == Method::POST => Some(Action::Documents),
+            "close" if method == Method::POST => Some(Action::Close),
+            "preview" if method == Method::GET => Some(Action::Preview),
+            "receipt" if method == Method::GET => Some(Action::Receipt),
+            id if Uuid::try_parse(id).ok() == Some(*job) => match *method {
+                Method::GET => Some(Action::Status),
+                Method::DELETE => Some(Action::Cancel),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+pub struct JobLinkSigner {
+    signer: Signer,
+    ttl: Duration,
+}
+
+impl JobLinkSigner {
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Some(signer) = Signer::from_env("KPRINT_JOB_LINK_SECRET", b"job-link")? else {
+            return Ok(None);
+        };
+        let ttl = match std::env::var("KPRINT_JOB_LINK_TTL") {
+            Ok(seconds) => Duration::seconds(seconds.parse()?),
+            Err(_) => DEFAULT_TTL,
+        };
+        Ok(Some(JobLinkSigner { signer, ttl }))
+    }
+
+    /// The query string that lets `action` be done to `job` through a link,
+    /// until the TTL's up.
+    pub fn query(&self, job: &Uuid, action: Action) -> String {
+        let expires = (Utc::now() + self.ttl).timestamp();
+        let signature = self.signer.sign(*job, action.name(), expires);
+        format!("expires={expires}&signature={signature}")
+    }
+
+    /// Checks `link` was signed by kprint for `action` on `job`, and hasn't
+    /// expired.
+    pub fn verify(&self, job: &Uuid, action: Action, link: &LinkQuery) -> Result<(), KprintError> {
+        let (Some(expires), Some(signature)) = (link.expires, &link.signature) else {
+            return Err(KprintError::JobLinkInvalid);
+        };
+        if !self.signer.verify(*job, action.name(), expires, signature)
+            || expires <= Utc::now().timestamp()
+        {
+            return Err(KprintError::JobLinkInvalid);
+        }
+        Ok(())
+    }
+}
+
+/// `links` to `job`, each signed for what it does if links are signed.
+pub fn signed(app_data: &AppState, mut links: JobLinks, job: &Uuid) -> JobLinks {
+    let Some(signer) = &app_data.job_links else {
+        return links;
+    };
+    let sign = |link: &mut String, action| {
+        link.push(if link.contains('?') { '&' } else { '?' });
+        link.push_str(&signer.query(job, action));
+    };
+    sign(&mut links.status, Action::Status);
+    sign(&mut links.cancel, Action::Cancel);
+    sign(&mut links.preview, Action::Preview);
+    sign(&mut links.receipt, Action::Receipt);
+    if let Some(documents) = &mut links.documents {
+        sign(documents, Action::Documents);
+    }
+    if let Some(close) = &mut links.close {
+        sign(close, Action::Close);
+    }
+    links
+}
+
+/// `url`, a link to `job`, signed if links are.
+pub fn signed_url(app_data: &AppState, url: String, job: &Uuid) -> String {
+    match &app_data.job_links {
+        Some(signer) => format!("{url}?{}", signer.query(job, Action::Status)),
+        None => url,
+    }
+}
+
+/// The signature on a link to a job.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LinkQuery {
+    /// When the link stops working, in seconds since the epoch, if kprint
+    /// signs its job links
+    pub expires: Option<i64>,
+    /// The link's signature, if kprint signs its job links
+    pub signature: Option<String>,
+}
+
+/// The job in the path, from a link that's been checked, if kprint signs
+/// them.
+#[derive(Debug, Clone, Copy)]
+pub struct SignedJob(pub Uuid);
+
+impl FromRequest for SignedJob {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        ready(signed_job(req).map_err(Into::into))
+    }
+}
+
+fn signed_job(req: &HttpRequest) -> Result<SignedJob, KprintError> {
+    let job = req
+        .match_info()
+        .get("id")
+        .and_then(|id| Uuid::try_parse(id).ok())
+        .ok_or_else(|| KprintError::InvalidRequest("That isn't a job id".to_string()))?;
+    let signer = req
+        .app_data::<Data<AppState>>()
+        .and_then(|app_data| app_data.job_links.as_ref());
+    if let Some(signer) = signer {
+        let link = Query::<LinkQuery>::from_query(req.query_string())
+            .map_err(|_| KprintError::JobLinkInvalid)?;
+        let action = Action::of(req, &job).ok_or(KprintError::JobLinkInvalid)?;
+        signer.verify(&job, action, &link)?;
+    }
+    Ok(SignedJob(job))
+}
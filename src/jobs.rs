@@ -0,0 +1,183 @@
+use crate::version::ApiVersion;
+use chrono::{DateTime, Utc};
+use ipp::prelude::*;
+use kprint_client::models::{JobLinks, JobState, JobStatus, JobSummary, QuotaUnits};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use uuid::Uuid;
+
+/// Finds the `job-state` of an IPP response, if it has one.
+pub fn job_state(attributes: &IppAttributes) -> JobState {
+    find_attribute(attributes, IppAttribute::JOB_STATE)
+        .and_then(|state| match state {
+            IppValue::Enum(state) | IppValue::Integer(state) => Some(JobState::from_ipp(*state)),
+            _ => None,
+        })
+        .unwrap_or(JobState::Unknown)
+}
+
+/// Looks an attribute up in whichever group it happens to be in.
+pub fn find_attribute<'a>(attributes: &'a IppAttributes, name: &str) -> Option<&'a IppValue> {
+    attributes
+        .groups()
+        .iter()
+        .find_map(|group| group.attributes().get(name))
+        .map(|attribute| attribute.value())
+}
+
+/// Reads a `1setOf keyword` attribute, which printers send as a bare value
+/// when there's only one.
+pub fn find_keywords(attributes: &IppAttributes, name: &str) -> Vec<String> {
+    find_attribute(attributes, name)
+        .map(|keywords| match keywords {
+            IppValue::Array(keywords) => keywords.iter().map(ToString::to_string).collect(),
+            keyword => vec![keyword.to_string()],
+        })
+        .unwrap_or_default()
+}
+
+/// How far a job got towards the printer.
+#[derive(Debug, Clone)]
+pub enum Delivery {
+    /// The printer has it
+    Delivered,
+    /// Spooled to disk, and still being sent to the printer
+    Spooled,
+    /// Spooled, but the printer never took it, and why
+    Failed(String),
+    /// Made on the printer, and waiting for more documents until it's closed
+    Open,
+}
+
+/// Everything kprint remembers about a job it submitted.
+#[derive(Debug, Clone)]
+pub struct JobRecord {
+    pub id: Uuid,
+    pub printer: String,
+    /// The id the printer gave the job, if it told us
+    pub ipp_job_id: Option<i32>,
+    /// Username of whoever submitted the job
+    pub owner: String,
+    pub title: String,
+    pub submitted_at: DateTime<Utc>,
+    pub delivery: Delivery,
+    /// The printer the job was first sent to, when it failed there and was
+    /// sent on to `printer`
+    pub rerouted_from: Option<String>,
+    /// Pages counted out of the owner's quota before it was sent, if any were
+    pub counted_pages: Option<u32>,
+    /// What those pages took out of the owner's quota
+    pub quota_units: Option<QuotaUnits>,
+    pub tags: Vec<String>,
+}
+
+/// Jobs submitted through this instance, keyed by kprint's own id so that
+/// printer-side ids (which collide across printers) never leak into URLs.
+#[derive(Default)]
+pub struct JobStore {
+    jobs: RwLock<HashMap<Uuid, JobRecord>>,
+}
+
+impl JobStore {
+    pub fn insert(&self, job: JobRecord) {
+        self.jobs.write().unwrap().insert(job.id, job);
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<JobRecord> {
+        self.jobs.read().unwrap().get(id).cloned()
+    }
+
+    /// Notes the id the printer gave job `id` as soon as it's made, while its
+    /// document's still on its way, so it can be looked up and canceled on
+    /// the printer in the meantime. Jobs nobody's been told about yet are
+    /// left for whoever submitted them to insert.
+    pub fn opened(&self, id: &Uuid, ipp_job_id: i32) {
+        if let Some(job) = self.jobs.write().unwrap().get_mut(id) {
+            job.ipp_job_id = Some(ipp_job_id);
+        }
+    }
+
+    /// Every job that's spooled and still on its way to the printer.
+    pub fn spooled(&self) -> Vec<JobRecord> {
+        self.jobs
+            .read()
+            .unwrap()
+            .values()
+            .filter(|job| matches!(job.delivery, Delivery::Spooled))
+            .cloned()
+            .collect()
+    }
+
+    /// Every job `owner` submitted, newest first.
+    pub fn owned_by(&self, owner: &str) -> Vec<JobRecord> {
+        let mut jobs = self
+            .jobs
+            .read()
+            .unwrap()
+            .values()
+            .filter(|job| job.owner == owner)
+            .cloned()
+            .collect::<Vec<_>>();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.submitted_at));
+        jobs
+    }
+}
+
+/// kprint's own URL for a job, on the given API version's mount.
+pub fn job_url(version: ApiVersion, id: &Uuid) -> String {
+    match version {
+        ApiVersion::Legacy => format!("/api/jobs/{id}"),
+        ApiVersion::V1 => format!("/api/v1/jobs/{id}"),
+    }
+}
+
+impl From<JobRecord> for JobSummary {
+    fn from(job: JobRecord) -> Self {
+        JobSummary {
+            links: JobLinks::for_job(&job.id),
+            id: job.id,
+            ipp_job_id: job.ipp_job_id,
+            printer: job.printer,
+            title: job.title,
+            submitted_at: job.submitted_at,
+            tags: job.tags,
+        }
+    }
+}
+
+/// A job's current status, from kprint's record of it and the attributes
+/// the printer reported.
+pub fn status_of(job: JobRecord, attributes: &IppAttributes) -> JobStatus {
+    let (state, state_reasons, failure) = match job.delivery {
+        Delivery::Delivered => (
+            job_state(attributes),
+            find_keywords(attributes, IppAttribute::JOB_STATE_REASONS),
+            None,
+        ),
+        Delivery::Spooled | Delivery::Open => {
+            (JobState::Pending, vec!["job-incoming".to_string()], None)
+        }
+        Delivery::Failed(failure) => (
+            JobState::Aborted,
+            vec!["aborted-by-system".to_string()],
+            Some(failure),
+        ),
+    };
+    let attributes = attributes
+        .groups_of(DelimiterTag::JobAttributes)
+        .flat_map(|group| group.attributes().values())
+        .map(|attribute| (attribute.name().to_string(), attribute.value().to_string()))
+        .collect();
+    JobStatus {
+        id: job.id,
+        ipp_job_id: job.ipp_job_id,
+        printer: job.printer,
+        title: job.title,
+        state,
+        state_reasons,
+        submitted_at: job.submitted_at,
+        failure,
+        rerouted_from: job.rerouted_from,
+        attributes,
+    }
+}
@@ -0,0 +1,67 @@
+//! The little stores admins and members change while kprint's running, kept
+//! as a JSON file when their `*_FILE` setting is set, so they last across
+//! restarts.
+//!
+//! A change is made to a copy, which is written alongside the file and moved
+//! into place, so a crash can't leave half a file behind, and only once
+//! that's worked is it what everyone reads, so a change that couldn't be
+//! saved never happens. Changes wait their turn for the file, and writing it
+//! doesn't hold up anything reading the store.
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+#[derive(Default)]
+pub struct JsonFile<T> {
+    file: Option<PathBuf>,
+    value: RwLock<T>,
+    /// Held through each change, so they're saved in the order they're made
+    changing: tokio::sync::Mutex<()>,
+}
+
+impl<T> JsonFile<T>
+where
+    T: Serialize + DeserializeOwned + Default + Clone,
+{
+    /// What's kept in the file `var` names, which is nothing until it's
+    /// first saved. Without `var` it's only kept in memory.
+    pub fn from_env(var: &str) -> anyhow::Result<Self> {
+        let Ok(file) = std::env::var(var) else {
+            return Ok(Self::default());
+        };
+        let file = PathBuf::from(file);
+        let value = match std::fs::read(&file) {
+            Ok(contents) => serde_json::from_slice(&contents).with_context(|| {
+                format!("{} isn't what {var} should have in it", file.display())
+            })?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => T::default(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(JsonFile {
+            file: Some(file),
+            value: RwLock::new(value),
+            changing: tokio::sync::Mutex::default(),
+        })
+    }
+
+    pub fn read<R>(&self, read: impl FnOnce(&T) -> R) -> R {
+        read(&self.value.read().unwrap())
+    }
+
+    /// Makes `change`, saving it before anyone sees it, and gives back what
+    /// it returned. If it can't be saved nothing's changed.
+    pub async fn change<R>(&self, change: impl FnOnce(&mut T) -> R) -> anyhow::Result<R> {
+        let _changing = self.changing.lock().await;
+        let mut changed = self.read(T::clone);
+        let changed_by = change(&mut changed);
+        if let Some(file) = &self.file {
+            let partial = file.with_extension("partial");
+            tokio::fs::write(&partial, serde_json::to_vec_pretty(&changed)?).await?;
+            tokio::fs::rename(partial, file).await?;
+        }
+        *self.value.write().unwrap() = changed;
+        Ok(changed_by)
+    }
+}
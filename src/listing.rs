@@ -0,0 +1,276 @@
+//! Paging through job listings, which get long, a screenful at a time.
+//!
+//! Pages are marked with an opaque cursor rather than an offset, so jobs
+//! submitted while someone's paging don't shift what's on the next page. The
+//! next page's URL is in the response's `Link` header, and there isn't one
+//! on the last page.
+
+use crate::error::{ErrorCode, FieldError};
+use crate::history;
+use actix_web::http::header::{self, HeaderName};
+use actix_web::HttpRequest;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use kprint_client::models::JobState;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+/// Jobs on a page when the request doesn't say
+pub const DEFAULT_LIMIT: usize = 50;
+/// Most jobs a page can have
+pub const MAX_LIMIT: usize = 500;
+
+const CURSOR: base64::engine::GeneralPurpose = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+/// Which jobs to list, and in what order, exactly as they arrived in the
+/// query string.
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListQuery {
+    /// Where to carry on from. Follow the `next` link rather than making
+    /// these up.
+    pub cursor: Option<String>,
+    /// Most jobs to list, from 1 to 500. Defaults to 50.
+    #[param(value_type = Option<u32>, minimum = 1, maximum = 500)]
+    pub limit: Option<String>,
+    /// Only jobs sent to this printer
+    pub printer: Option<String>,
+    /// Only jobs in this state, as kprint last heard it
+    #[param(value_type = Option<JobState>)]
+    pub state: Option<String>,
+    /// Only jobs submitted at or after this time, in RFC 3339
+    #[param(value_type = Option<DateTime<Utc>>)]
+    pub from: Option<String>,
+    /// Only jobs submitted before this time, in RFC 3339
+    #[param(value_type = Option<DateTime<Utc>>)]
+    pub to: Option<String>,
+    /// `newest` or `oldest` first. Defaults to `newest`.
+    #[param(value_type = Option<String>, example = "newest")]
+    pub sort: Option<String>,
+    /// Only jobs this member submitted. Only admins can ask for anyone
+    /// else's.
+    pub user: Option<String>,
+    /// Only jobs with every word of this somewhere in their title, ignoring
+    /// case, like `lease` for "Apartment release form.pdf"
+    pub search: Option<String>,
+    /// Only jobs with this tag, like `evals`
+    pub tag: Option<String>,
+}
+
+/// What a job is listed by.
+pub struct Key<'a> {
+    pub submitted_at: DateTime<Utc>,
+    pub id: Uuid,
+    pub printer: &'a str,
+    pub title: &'a str,
+    pub state: JobState,
+    pub tags: &'a [String],
+}
+
+pub struct Listing {
+    /// The last job on the page before, if this isn't the first
+    after: Option<(DateTime<Utc>, Uuid)>,
+    limit: usize,
+    printer: Option<String>,
+    state: Option<JobState>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    oldest_first: bool,
+    pub user: Option<String>,
+    /// Lowercase words the titles have to have
+    pub search: Vec<String>,
+    tag: Option<String>,
+}
+
+/// One page of a listing, and the cursor for the next one if there's more.
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<String>,
+}
+
+fn cursor(submitted_at: DateTime<Utc>, id: Uuid) -> String {
+    CURSOR.encode(format!("{}/{id}", submitted_at.to_rfc3339()))
+}
+
+fn parse_cursor(cursor: &str) -> Option<(DateTime<Utc>, Uuid)> {
+    let cursor = String::from_utf8(CURSOR.decode(cursor.trim()).ok()?).ok()?;
+    let (submitted_at, id) = cursor.split_once('/')?;
+    Some((
+        DateTime::parse_from_rfc3339(submitted_at).ok()?.to_utc(),
+        id.parse().ok()?,
+    ))
+}
+
+fn parse_time(
+    field: &'static str,
+    time: Option<&str>,
+) -> Result<Option<DateTime<Utc>>, FieldError> {
+    time.map(|time| {
+        DateTime::parse_from_rfc3339(time.trim())
+            .map(|time| time.to_utc())
+            .map_err(|err| {
+                FieldError::new(
+                    field,
+                    ErrorCode::UnsupportedValue,
+                    format!("{time:?} isn't an RFC 3339 time: {err}"),
+                )
+            })
+    })
+    .transpose()
+}
+
+impl ListQuery {
+    /// Checks every parameter, returning all the ones that are wrong rather
+    /// than just the first.
+    pub fn validate(self) -> Result<Listing, Vec<FieldError>> {
+        let after = self.cursor.as_deref().map(|value| {
+            parse_cursor(value).ok_or_else(|| {
+                FieldError::new(
+                    "cursor",
+                    ErrorCode::UnsupportedValue,
+                    "That isn't a cursor kprint gave out",
+                )
+            })
+        });
+        let limit = match self.limit.as_deref().map(str::trim) {
+            None => Ok(DEFAULT_LIMIT),
+            Some(limit) => match limit.parse() {
+                Ok(limit @ 1..=MAX_LIMIT) => Ok(limit),
+                _ => Err(FieldError::new(
+                    "limit",
+                    ErrorCode::ValueOutOfRange,
+                    format!(
+                        "Limits must be a whole number from 1 to {MAX_LIMIT}, but got {limit:?}"
+                    ),
+                )),
+            },
+        };
+        let state = self.state.as_deref().map(|state| {
+            JobState::deserialize(
+                serde::de::value::StrDeserializer::<serde::de::value::Error>::new(state.trim()),
+            )
+            .ok()
+            .filter(|state| *state != JobState::Unknown)
+            .ok_or_else(|| {
+                FieldError::new(
+                    "state",
+                    ErrorCode::UnsupportedValue,
+                    format!("{state:?} isn't a job state"),
+                )
+            })
+        });
+        let from = parse_time("from", self.from.as_deref());
+        let to = parse_time("to", self.to.as_deref());
+        let oldest_first = match self.sort.as_deref().map(str::trim) {
+            None | Some("newest") => Ok(false),
+            Some("oldest") => Ok(true),
+            Some(sort) => Err(FieldError::new(
+                "sort",
+                ErrorCode::UnsupportedValue,
+                format!("{sort:?} isn't newest or oldest"),
+            )),
+        };
+
+        match (
+            after.transpose(),
+            limit,
+            state.transpose(),
+            from,
+            to,
+            oldest_first,
+        ) {
+            (Ok(after), Ok(limit), Ok(state), Ok(from), Ok(to), Ok(oldest_first)) => Ok(Listing {
+                after,
+                limit,
+                printer: self.printer,
+                state,
+                from,
+                to,
+                oldest_first,
+                user: self.user,
+                search: self
+                    .search
+                    .unwrap_or_default()
+                    .to_lowercase()
+                    .split_whitespace()
+                    .map(str::to_string)
+                    .collect(),
+                tag: self.tag.map(|tag| tag.trim().to_lowercase()),
+            }),
+            (after, limit, state, from, to, oldest_first) => Err([
+                after.err(),
+                limit.err(),
+                state.err(),
+                from.err(),
+                to.err(),
+                oldest_first.err(),
+            ]
+            .into_iter()
+            .flatten()
+            .collect()),
+        }
+    }
+}
+
+impl Listing {
+    fn keeps(&self, key: &Key) -> bool {
+        self.printer
+            .as_deref()
+            .is_none_or(|printer| key.printer == printer)
+            && self.state.is_none_or(|state| key.state == state)
+            && self.from.is_none_or(|from| key.submitted_at >= from)
+            && self.to.is_none_or(|to| key.submitted_at < to)
+            && history::title_matches(key.title, &self.search)
+            && self
+                .tag
+                .as_deref()
+                .is_none_or(|tag| key.tags.iter().any(|tagged| tagged == tag))
+            && self.after.is_none_or(|after| {
+                let key = (key.submitted_at, key.id);
+                if self.oldest_first {
+                    key > after
+                } else {
+                    key < after
+                }
+            })
+    }
+
+    /// The page of `items` this listing is after, each listed by `key`.
+    pub fn page<T>(&self, items: Vec<T>, key: impl Fn(&T) -> Key) -> Page<T> {
+        let mut items = items
+            .into_iter()
+            .filter(|item| self.keeps(&key(item)))
+            .collect::<Vec<_>>();
+        items.sort_by_key(|item| {
+            let key = key(item);
+            (key.submitted_at, key.id)
+        });
+        if !self.oldest_first {
+            items.reverse();
+        }
+        let next = (items.len() > self.limit).then(|| {
+            let last = key(&items[self.limit - 1]);
+            cursor(last.submitted_at, last.id)
+        });
+        items.truncate(self.limit);
+        Page { items, next }
+    }
+}
+
+/// A `Link` header pointing at the page after `req`'s, which starts after
+/// `next`.
+pub fn next_link(req: &HttpRequest, next: &str) -> (HeaderName, String) {
+    let cursor = format!("cursor={next}");
+    let query = req
+        .query_string()
+        .split('&')
+        .filter(|param| !param.is_empty() && !param.starts_with("cursor="))
+        .chain([cursor.as_str()])
+        .collect::<Vec<_>>()
+        .join("&");
+    (
+        header::LINK,
+        format!("<{}?{query}>; rel=\"next\"", req.path()),
+    )
+}
@@ -0,0 +1,544 @@
+//! Where kprint's logs go.
+//!
+//! They go to stderr unless they're sent elsewhere, filtered by `RUST_LOG`
+//! wherever they go. `KPRINT_LOG_FILE` also has them written to a file, for
+//! machines with nothing collecting stderr, which is rotated once it's
+//! `KPRINT_LOG_FILE_MAX_BYTES` long and, if `KPRINT_LOG_FILE_ROTATE` is
+//! `hourly` or `daily`, that often too. The last `KPRINT_LOG_FILE_KEEP` are
+//! kept beside it, as `kprint.log.1` for the most recent and so on. In the
+//! config file, that's
+//!
+//! ```toml
+//! [log]
+//! file = "/var/log/kprint/kprint.log"
+//! file_rotate = "daily"
+//! ```
+//!
+//! Under systemd, `KPRINT_LOG_TARGET=journald` sends them to the journal
+//! instead, with their level as the priority and where they're from in
+//! fields of their own, so `journalctl -p warning` or
+//! `journalctl KPRINT_TARGET=kprint::audit` pick them out.
+//! `KPRINT_LOG_TARGET=syslog` does the same for a syslog daemon, as far as
+//! syslog can.
+//!
+//! Admins can swap the `RUST_LOG` filter for another while kprint's running,
+//! though never so far as to stop the audit log.
+//!
+//! Usernames and job titles are personal, and logs are kept wherever they're
+//! collected for as long as that keeps them, so `KPRINT_LOG_REDACT` can keep
+//! them out. `hash` writes each as `#` and a short HMAC of it, the same every
+//! time for as long as `KPRINT_LOG_REDACT_SECRET` is, or for as long as kprint
+//! runs without one, so lines about the same member can still be put
+//! together. `truncate` writes their first two characters and no more. Either
+//! way the access log has them taken out of the paths and query strings it
+//! writes. The audit log, which is kept apart and locked down, always has
+//! them in full.
+
+use actix_web::dev::ServiceRequest;
+use anyhow::Context;
+use base64::Engine;
+use chrono::Local;
+use env_filter::Filter;
+use hmac::{Hmac, Mac};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use sha2::Sha256;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_KEEP: usize = 5;
+/// How many characters of a hash or a truncated name are kept
+const HASH_LENGTH: usize = 8;
+const TRUNCATED_LENGTH: usize = 2;
+/// Query parameters with names or titles in them, or cursors made from them
+const PERSONAL_PARAMETERS: &[&str] = &[
+    "title", "user", "username", "owner", "sponsor", "search", "cursor",
+];
+
+/// How often a log file's rotated regardless of how long it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotate {
+    Hourly,
+    Daily,
+}
+
+impl Rotate {
+    /// What stays the same for as long as a file's written to.
+    fn period(self) -> String {
+        let format = match self {
+            Rotate::Hourly => "%Y-%m-%d %H",
+            Rotate::Daily => "%Y-%m-%d",
+        };
+        Local::now().format(format).to_string()
+    }
+}
+
+/// A log file that moves itself aside when it's time for a new one.
+pub struct LogFile {
+    path: PathBuf,
+    max_bytes: u64,
+    rotate: Option<Rotate>,
+    keep: usize,
+    file: File,
+    written: u64,
+    period: Option<String>,
+}
+
+fn open(path: &Path) -> std::io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let written = file.metadata()?.len();
+    Ok((file, written))
+}
+
+impl LogFile {
+    pub fn open(
+        path: PathBuf,
+        max_bytes: u64,
+        rotate: Option<Rotate>,
+        keep: usize,
+    ) -> std::io::Result<Self> {
+        let (file, written) = open(&path)?;
+        Ok(LogFile {
+            path,
+            max_bytes,
+            rotate,
+            keep,
+            file,
+            written,
+            period: rotate.map(Rotate::period),
+        })
+    }
+
+    /// The log file in `KPRINT_LOG_FILE`, if there is one.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(path) = std::env::var("KPRINT_LOG_FILE") else {
+            return Ok(None);
+        };
+        let max_bytes = match std::env::var("KPRINT_LOG_FILE_MAX_BYTES") {
+            Ok(bytes) => bytes.parse().context("Bad KPRINT_LOG_FILE_MAX_BYTES")?,
+            Err(_) => DEFAULT_MAX_BYTES,
+        };
+        let rotate = match std::env::var("KPRINT_LOG_FILE_ROTATE").as_deref() {
+            Ok("hourly") => Some(Rotate::Hourly),
+            Ok("daily") => Some(Rotate::Daily),
+            Ok("never") | Err(_) => None,
+            Ok(rotate) => anyhow::bail!(
+                "KPRINT_LOG_FILE_ROTATE can be hourly, daily or never, not {rotate:?}"
+            ),
+        };
+        let keep = match std::env::var("KPRINT_LOG_FILE_KEEP") {
+            Ok(keep) => keep.parse().context("Bad KPRINT_LOG_FILE_KEEP")?,
+            Err(_) => DEFAULT_KEEP,
+        };
+        let file = LogFile::open(path.clone().into(), max_bytes, rotate, keep)
+            .with_context(|| format!("Couldn't open log file {path}"))?;
+        Ok(Some(file))
+    }
+
+    /// Where the `n`th most recent old file goes.
+    fn old(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+
+    /// Moves every old file one further back, dropping the oldest, and
+    /// starts a new one.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        match self.keep {
+            0 => std::fs::remove_file(&self.path)?,
+            keep => {
+                for n in (1..keep).rev() {
+                    let old = self.old(n);
+                    if old.exists() {
+                        std::fs::rename(old, self.old(n + 1))?;
+                    }
+                }
+                std::fs::rename(&self.path, self.old(1))?;
+            }
+        }
+        (self.file, self.written) = open(&self.path)?;
+        Ok(())
+    }
+
+    fn due(&mut self, incoming: usize) -> bool {
+        let period = self.rotate.map(Rotate::period);
+        let new_period = period != self.period;
+        self.period = period;
+        let too_long = self.written > 0 && self.written + incoming as u64 > self.max_bytes;
+        (new_period && self.written > 0) || too_long
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.due(buf.len()) {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Writes to stderr and a log file both.
+struct Tee(LogFile);
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        std::io::stderr().write_all(buf)?;
+        // Not being able to write the file shouldn't lose the line on stderr
+        // too, or make logging give up on everything after it
+        if let Err(err) = self.0.write_all(buf) {
+            eprintln!("Couldn't write to the log file: {err}");
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()?;
+        self.0.flush()
+    }
+}
+
+/// Where log lines are sent, other than stderr.
+enum Sink {
+    Journal(UnixDatagram),
+    Syslog(UnixDatagram),
+}
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+const SYSLOG_SOCKET: &str = "/dev/log";
+/// What syslog calls system daemons
+const DAEMON_FACILITY: u8 = 3;
+
+/// How bad `level` is, the way syslog and the journal count it.
+fn priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Adds field `name` to a journal entry, in the native protocol, which has
+/// to give the length of values that run over more than one line.
+fn field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+/// `record` the way the journal takes it.
+pub fn journal_entry(record: &Record) -> Vec<u8> {
+    let mut entry = vec![];
+    field(&mut entry, "MESSAGE", &record.args().to_string());
+    field(
+        &mut entry,
+        "PRIORITY",
+        &priority(record.level()).to_string(),
+    );
+    field(&mut entry, "SYSLOG_IDENTIFIER", "kprint");
+    field(&mut entry, "KPRINT_TARGET", record.target());
+    if let Some(file) = record.file() {
+        field(&mut entry, "CODE_FILE", file);
+    }
+    if let Some(line) = record.line() {
+        field(&mut entry, "CODE_LINE", &line.to_string());
+    }
+    if let Some(module) = record.module_path() {
+        field(&mut entry, "CODE_FUNC", module);
+    }
+    entry
+}
+
+impl Sink {
+    fn connect(socket: &str) -> anyhow::Result<UnixDatagram> {
+        let datagram = UnixDatagram::unbound()?;
+        datagram
+            .connect(socket)
+            .with_context(|| format!("Couldn't connect to {socket}"))?;
+        Ok(datagram)
+    }
+
+    fn send(&self, record: &Record) -> std::io::Result<()> {
+        match self {
+            Sink::Journal(journal) => {
+                journal.send(&journal_entry(record))?;
+            }
+            Sink::Syslog(syslog) => {
+                let line = format!(
+                    "<{}>kprint[{}]: {}: {}",
+                    DAEMON_FACILITY * 8 + priority(record.level()),
+                    std::process::id(),
+                    record.target(),
+                    record.args(),
+                );
+                syslog.send(line.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sends log lines to the journal or syslog.
+struct SystemLogger(Sink);
+
+impl Log for SystemLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        // Better on stderr than nowhere, if the journal's gone away
+        if let Err(err) = self.0.send(record) {
+            eprintln!("Couldn't log {:?}: {err}", record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// What's logged when `RUST_LOG` doesn't say, same as `env_logger`
+const DEFAULT_FILTER: &str = "error";
+
+/// Which lines get logged, which admins can change while kprint's running.
+struct Filtered {
+    /// What the filter was made from, to show it back
+    filter: RwLock<(String, Filter)>,
+    inner: Box<dyn Log>,
+}
+
+fn is_audit(target: &str) -> bool {
+    target == "kprint::audit"
+}
+
+impl Log for Filtered {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Turning logging down mustn't be a way to hide what admins do
+        is_audit(metadata.target()) || self.filter.read().unwrap().1.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if is_audit(record.target()) || self.filter.read().unwrap().1.matches(record) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+static LOGGER: OnceLock<&'static Filtered> = OnceLock::new();
+
+fn parse(filter: &str) -> Result<Filter, String> {
+    Ok(env_filter::Builder::new()
+        .try_parse(filter)
+        .map_err(|err| err.to_string())?
+        .build())
+}
+
+/// Sets how much `log` lets through, which has to be at least as much as the
+/// filter does, and enough for the audit log.
+fn set_max_level(filter: &Filter) {
+    log::set_max_level(filter.filter().max(LevelFilter::Warn));
+}
+
+/// Starts logging what `filter` lets through instead, like
+/// `kprint::api=debug,info`. Gives back what it was before.
+pub fn set_filter(filter: &str) -> Result<String, String> {
+    let parsed = parse(filter)?;
+    let logger = LOGGER.get().ok_or("Logging hasn't started")?;
+    let mut current = logger.filter.write().unwrap();
+    set_max_level(&parsed);
+    let previous = std::mem::replace(&mut *current, (filter.to_string(), parsed));
+    Ok(previous.0)
+}
+
+/// How usernames and job titles are written in the logs.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Redaction {
+    /// In full
+    #[default]
+    Off,
+    /// As an HMAC with this key
+    Hash(Vec<u8>),
+    Truncate,
+}
+
+impl Redaction {
+    pub fn from_env() -> anyhow::Result<Self> {
+        match std::env::var("KPRINT_LOG_REDACT").as_deref() {
+            Ok("off") | Err(_) => Ok(Redaction::Off),
+            Ok("hash") => {
+                let key = match std::env::var("KPRINT_LOG_REDACT_SECRET") {
+                    Ok(secret) => secret.into_bytes(),
+                    Err(_) => (0..32).map(|_| fastrand::u8(..)).collect(),
+                };
+                Ok(Redaction::Hash(key))
+            }
+            Ok("truncate") => Ok(Redaction::Truncate),
+            Ok(redact) => {
+                anyhow::bail!("KPRINT_LOG_REDACT can be off, hash or truncate, not {redact:?}")
+            }
+        }
+    }
+
+    /// `text`, a username or title, as the logs have it.
+    pub fn apply(&self, text: &str) -> String {
+        match self {
+            Redaction::Off => text.to_string(),
+            Redaction::Hash(key) => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes any key");
+                mac.update(text.as_bytes());
+                let hash = base64::engine::general_purpose::URL_SAFE_NO_PAD
+                    .encode(mac.finalize().into_bytes());
+                format!("#{}", &hash[..HASH_LENGTH])
+            }
+            Redaction::Truncate => {
+                let kept = text.chars().take(TRUNCATED_LENGTH).collect::<String>();
+                match kept.len() < text.len() {
+                    true => format!("{kept}…"),
+                    false => kept,
+                }
+            }
+        }
+    }
+
+    /// `path`, with a query string or not, with whatever's personal in it
+    /// redacted: anyone's name under `/users/`, and the values of
+    /// [`PERSONAL_PARAMETERS`].
+    pub fn apply_to_path(&self, path: &str) -> String {
+        if *self == Redaction::Off {
+            return path.to_string();
+        }
+        let (path, query) = match path.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (path, None),
+        };
+        let mut segments = vec![];
+        let mut after_users = false;
+        for segment in path.split('/') {
+            segments.push(match after_users {
+                true => self.apply(segment),
+                false => segment.to_string(),
+            });
+            after_users = segment == "users";
+        }
+        let mut written = segments.join("/");
+        if let Some(query) = query {
+            let pairs = query
+                .split('&')
+                .map(|pair| match pair.split_once('=') {
+                    Some((name, value)) if PERSONAL_PARAMETERS.contains(&name) => {
+                        format!("{name}={}", self.apply(value))
+                    }
+                    _ => pair.to_string(),
+                })
+                .collect::<Vec<_>>();
+            written.push('?');
+            written.push_str(&pairs.join("&"));
+        }
+        written
+    }
+}
+
+static REDACTION: OnceLock<Redaction> = OnceLock::new();
+
+/// A username or title, written the way `KPRINT_LOG_REDACT` says when it's
+/// logged.
+pub struct Pii<'a>(pub &'a str);
+
+impl fmt::Display for Pii<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match REDACTION.get() {
+            Some(redaction) if *redaction != Redaction::Off => {
+                f.write_str(&redaction.apply(self.0))
+            }
+            _ => f.write_str(self.0),
+        }
+    }
+}
+
+impl fmt::Debug for Pii<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match REDACTION.get() {
+            Some(redaction) if *redaction != Redaction::Off => {
+                write!(f, "{:?}", redaction.apply(self.0))
+            }
+            _ => write!(f, "{:?}", self.0),
+        }
+    }
+}
+
+/// `path`, with a query string or not, as the logs have it.
+pub fn path(path: &str) -> String {
+    REDACTION.get().map_or_else(
+        || path.to_string(),
+        |redaction| redaction.apply_to_path(path),
+    )
+}
+
+/// The request line of `req`, like `%r` in the access log, but redacted.
+pub fn request_line(req: &ServiceRequest) -> String {
+    let uri = req.uri();
+    let target = uri
+        .path_and_query()
+        .map_or(uri.path(), |target| target.as_str());
+    format!("{} {} {:?}", req.method(), path(target), req.version())
+}
+
+/// Starts logging wherever it's been set up to go.
+pub fn init() -> anyhow::Result<()> {
+    let _ = REDACTION.set(Redaction::from_env()?);
+    let sink = match std::env::var("KPRINT_LOG_TARGET").as_deref() {
+        Ok("stderr") | Err(_) => None,
+        Ok("journald") => Some(Sink::Journal(Sink::connect(JOURNAL_SOCKET)?)),
+        Ok("syslog") => Some(Sink::Syslog(Sink::connect(SYSLOG_SOCKET)?)),
+        Ok(target) => {
+            anyhow::bail!("KPRINT_LOG_TARGET can be stderr, journald or syslog, not {target:?}")
+        }
+    };
+    let inner: Box<dyn Log> = match sink {
+        Some(sink) => Box::new(SystemLogger(sink)),
+        None => {
+            // Everything's let through here, since it's been filtered already
+            let mut logger = env_logger::Builder::new();
+            logger.filter_level(LevelFilter::Trace);
+            if let Ok(style) = std::env::var("RUST_LOG_STYLE") {
+                logger.parse_write_style(&style);
+            }
+            if let Some(file) = LogFile::from_env()? {
+                logger.target(env_logger::Target::Pipe(Box::new(Tee(file))));
+            }
+            Box::new(logger.build())
+        }
+    };
+    let spec = std::env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_FILTER.to_string());
+    let filter = parse(&spec).map_err(|err| anyhow::anyhow!("Bad RUST_LOG: {err}"))?;
+    set_max_level(&filter);
+    let logger = Box::leak(Box::new(Filtered {
+        filter: RwLock::new((spec, filter)),
+        inner,
+    }));
+    log::set_logger(logger)?;
+    let _ = LOGGER.set(logger);
+    Ok(())
+}
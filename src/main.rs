@@ -1,37 +1,234 @@
 use actix_cors::Cors;
-use actix_web::{http, middleware::Logger, App, HttpServer};
+use actix_web::{
+    http,
+    middleware::{ErrorHandlers, Logger},
+    web, App, HttpServer,
+};
 use dotenvy::dotenv;
 
+mod admin;
 mod api;
 mod app;
+mod audit;
 mod auth;
+mod balances;
+mod breaker;
+mod build_info;
+mod clamav;
+mod config;
+mod confirmations;
+mod copies;
+mod cover;
+mod cups;
+mod demo;
+mod dialect;
+mod digest;
+mod document;
+mod eligibility;
+mod email;
+mod envelope;
+mod error;
+mod export;
+mod fallback;
+mod fetch;
+mod flags;
+mod graphql;
+mod grpc;
+mod guests;
+mod history;
+mod ipp_client;
+mod ipp_server;
+mod issuers;
+mod job_links;
+mod jobs;
+mod json_file;
+mod listing;
+mod logging;
+mod maintenance;
+mod margins;
+mod mdns;
+mod metrics;
+mod mirror;
+mod null_printer;
+mod openapi;
+mod options;
+mod overrides;
+mod packets;
+mod passwords;
+mod pdf;
+mod pickup;
+mod poster;
+mod preferences;
+mod preview;
+mod printer_cache;
+mod probe;
+mod queue_cache;
+mod quota;
+mod receipt;
+mod recording;
+mod reports;
+mod request_id;
+mod retry;
+mod service;
+mod signing;
+mod sniff;
+mod spool;
+mod stamp;
+mod state_reasons;
+mod stats;
+mod streaming;
+mod suspensions;
+mod test_page;
+#[cfg(test)]
+mod tests;
+mod timeout;
+mod trace;
+mod typescript;
+mod version;
 use app::{configure_app, get_app_data};
-use auth::CSHAuth;
+use clap::Parser;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
-    env_logger::init();
+    let cli = config::Cli::parse();
+    let (checking, args) = match cli.command {
+        None => (false, config::ConfigArgs::default()),
+        Some(config::Command::Serve(args)) => (false, args),
+        Some(config::Command::CheckConfig(args)) => (true, args),
+        Some(config::Command::Gen(config::Generate::Types { out })) => {
+            let types = typescript::generate();
+            return match out {
+                Some(out) => std::fs::write(out, types),
+                None => {
+                    print!("{types}");
+                    Ok(())
+                }
+            };
+        }
+    };
+    let settings = config::load(&args).map_err(|err| std::io::Error::other(format!("{err:#}")))?;
+    logging::init().map_err(|err| std::io::Error::other(format!("{err:#}")))?;
+    if checking {
+        settings.print();
+        return config::check().await.map_err(|err| {
+            std::io::Error::other(format!("kprint couldn't start like this: {err:#}"))
+        });
+    }
+    let port = config::port().map_err(|err| std::io::Error::other(format!("{err:#}")))?;
+    // Built once up front rather than per worker, so every worker sees the
+    // same jobs
+    let app_data = web::Data::new(
+        get_app_data()
+            .await
+            .map_err(|err| std::io::Error::other(format!("Couldn't load app data: {err}")))?,
+    );
+    if demo::enabled() {
+        log::warn!(
+            "Serving a demo, where everyone's {} and nothing's really printed",
+            demo::USER
+        );
+        demo::seed(&app_data).await;
+    }
+    // Discovered before anything's served, so SSO being down stops kprint
+    // starting rather than failing its first requests
+    let issuers = auth::issuers()
+        .await
+        .map_err(|err| std::io::Error::other(format!("Couldn't set up SSO: {err:#}")))?;
+    let probing = probe::enabled()
+        .map_err(|err| std::io::Error::other(format!("Bad KPRINT_PROBE_PRINTERS: {err}")))?;
+    if probing {
+        probe::probe(&app_data).await;
+    }
+    app_data.printer_cache.refresh_in_background(
+        app_data
+            .printers
+            .iter()
+            .map(|(name, printer)| (name.clone(), printer.clone()))
+            .collect(),
+    );
+    fallback::Fallback::watch_in_background(app_data.clone());
+    copies::SeparateCopies::follow_in_background(app_data.clone());
+    history::History::follow_in_background(app_data.clone());
+    quota::Quotas::roll_in_background(app_data.clone());
+    if let Ok(port) = std::env::var("KPRINT_GRPC_PORT") {
+        let port = port
+            .parse()
+            .map_err(|err| std::io::Error::other(format!("Bad KPRINT_GRPC_PORT: {err}")))?;
+        let app_data = app_data.clone();
+        let issuers = issuers.clone();
+        actix_web::rt::spawn(async move {
+            if let Err(err) = grpc::serve(app_data, port, issuers).await {
+                log::error!("The gRPC server stopped: {err}");
+            }
+        });
+    }
+    if let Some(config) = email::EmailConfig::from_env() {
+        let config = config.map_err(|err| std::io::Error::other(err.to_string()))?;
+        actix_web::rt::spawn(email::poll(app_data.clone(), config));
+    }
+    if let Some(config) = reports::ReportConfig::from_env() {
+        let config = config.map_err(|err| std::io::Error::other(err.to_string()))?;
+        actix_web::rt::spawn(reports::schedule(app_data.clone(), config));
+    }
+    // The hostname is this machine's mDNS name, like `kprint.local.`. Only worth
+    // turning on where the network is trusted, since it invites anyone who can
+    // hear it to send print passwords over plain HTTP
+    let _mdns = match std::env::var("KPRINT_MDNS_HOSTNAME") {
+        Ok(hostname) => Some(
+            mdns::advertise(app_data.printers.keys(), &hostname, port).map_err(|err| {
+                std::io::Error::other(format!("Couldn't advertise over mDNS: {err}"))
+            })?,
+        ),
+        Err(_) => None,
+    };
     HttpServer::new(move || {
         App::new()
-            .wrap(CSHAuth::new("kprint".to_string()))
+            .wrap(ErrorHandlers::new().default_handler(error::render_error))
             .wrap(
                 Cors::default()
                     .allowed_origin("http://localhost:8081")
-                    .allowed_methods(vec!["GET", "POST"])
+                    .allowed_methods(vec!["GET", "HEAD", "POST", "PATCH", "DELETE"])
                     .allowed_headers(vec![
                         http::header::AUTHORIZATION,
                         http::header::ACCEPT,
                         http::header::CONTENT_TYPE,
+                        version::ACCEPT_VERSION,
+                        request_id::REQUEST_ID,
+                        trace::TRACEPARENT,
+                        trace::TRACESTATE,
+                        digest::REPR_DIGEST,
+                        digest::DIGEST,
+                        spool::UPLOAD_LENGTH,
+                        spool::UPLOAD_OFFSET,
+                        spool::TUS_RESUMABLE,
+                    ])
+                    .expose_headers(vec![
+                        http::header::LOCATION,
+                        version::API_VERSION,
+                        version::DEPRECATION,
+                        http::header::LINK,
+                        request_id::REQUEST_ID,
+                        digest::REPR_DIGEST,
+                        digest::DIGEST,
+                        spool::UPLOAD_LENGTH,
+                        spool::UPLOAD_OFFSET,
+                        spool::TUS_RESUMABLE,
                     ]),
             )
-            .wrap(Logger::new(
-                "%a \"%r\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %T",
-            ))
-            .configure(configure_app)
-            .data_factory(get_app_data)
+            .wrap(request_id::RequestIdentifier)
+            .wrap(trace::Tracing)
+            .wrap(
+                Logger::new(
+                    "%a \"%{request}xi\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %T %{X-Request-Id}o",
+                )
+                // Like `%r`, but without names and titles when they're redacted
+                .custom_request_replace("request", logging::request_line),
+            )
+            .configure(|cfg| configure_app(cfg, issuers.clone()))
+            .app_data(app_data.clone())
     })
-    .bind(("0.0.0.0", 8080))?
+    .bind(("0.0.0.0", port))?
     .run()
     .await
 }
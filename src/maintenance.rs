@@ -0,0 +1,42 @@
+//! Printers admins have taken out of service, which kprint turns jobs away
+//! from without involving CUPS.
+//!
+//! When `KPRINT_MAINTENANCE_FILE` is set the notices are kept there as JSON,
+//! so a printer that's out until Tuesday stays out across restarts.
+
+use crate::json_file::JsonFile;
+use kprint_client::models::MaintenanceNotice;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct Maintenance {
+    notices: JsonFile<HashMap<String, MaintenanceNotice>>,
+}
+
+impl Maintenance {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Maintenance {
+            notices: JsonFile::from_env("KPRINT_MAINTENANCE_FILE")?,
+        })
+    }
+
+    pub fn get(&self, printer: &str) -> Option<MaintenanceNotice> {
+        self.notices.read(|notices| notices.get(printer).cloned())
+    }
+
+    /// Puts `printer` into maintenance, replacing any notice it already had.
+    pub async fn set(&self, printer: &str, notice: MaintenanceNotice) -> anyhow::Result<()> {
+        self.notices
+            .change(|notices| {
+                notices.insert(printer.to_string(), notice);
+            })
+            .await
+    }
+
+    /// Takes `printer` out of maintenance, returning whether it was in it.
+    pub async fn clear(&self, printer: &str) -> anyhow::Result<bool> {
+        self.notices
+            .change(|notices| notices.remove(printer).is_some())
+            .await
+    }
+}
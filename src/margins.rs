@@ -0,0 +1,72 @@
+//! Margins and offsets drawn into the PDF, for printers that ignore them in
+//! `media-col` and `x-image-shift`, so documents designed edge to edge don't
+//! lose their borders to the printer's own.
+//!
+//! Turned on per printer with `KPRINT_PDF_MARGINS`, a space-separated list of
+//! printers like `lounge library`. Each page's drawing is shrunk to fit
+//! inside the margins, centred in what they leave, then moved by the offset,
+//! and neither is sent to the printer. Only PDFs can be changed this way;
+//! anything else sent to those printers is printed as it was sent.
+
+use crate::pdf;
+use kprint_client::models::{MediaMargins, PrintOffset};
+use lopdf::{Dictionary, Object, Stream};
+use std::collections::HashSet;
+
+/// Which printers get margins and offsets drawn in.
+#[derive(Default)]
+pub struct PdfMargins(HashSet<String>);
+
+impl PdfMargins {
+    pub fn from_env() -> Self {
+        let printers = std::env::var("KPRINT_PDF_MARGINS").unwrap_or_default();
+        PdfMargins(printers.split_whitespace().map(str::to_string).collect())
+    }
+
+    pub fn applies_to(&self, printer: &str) -> bool {
+        self.0.contains(printer)
+    }
+}
+
+/// A length in hundredths of a millimeter, in PDF points.
+fn points(length: i32) -> f32 {
+    length as f32 * 72.0 / 2540.0
+}
+
+/// Fits every page of `pdf` inside `margins`, then moves it by `offset`.
+pub fn fit(pdf: &[u8], margins: MediaMargins, offset: PrintOffset) -> anyhow::Result<Vec<u8>> {
+    let mut document = pdf::load(pdf)?;
+    let [top, bottom, left, right] = [margins.top, margins.bottom, margins.left, margins.right]
+        .map(|margin| points(margin as i32));
+    let restore = document.add_object(Stream::new(Dictionary::new(), b"Q".to_vec()));
+
+    for page_id in document.get_pages().into_values() {
+        let [x0, y0, x1, y1] = pdf::media_box(&document, page_id);
+        let (width, height) = (x1 - x0, y1 - y0);
+        let scale = ((width - left - right) / width).min((height - top - bottom) / height);
+        if !(width > 0.0 && height > 0.0 && scale > 0.0) {
+            anyhow::bail!("the margins leave nothing of a {width} by {height} point page");
+        }
+        // PDF measures up from the bottom, and offsets down from the top
+        let x = x0 + left + (width - left - right - scale * width) / 2.0 - scale * x0
+            + points(offset.x);
+        let y = y0 + bottom + (height - top - bottom - scale * height) / 2.0
+            - scale * y0
+            - points(offset.y);
+        let transform = format!("q {scale} 0 0 {scale} {x} {y} cm");
+        let transform = document.add_object(Stream::new(Dictionary::new(), transform.into_bytes()));
+
+        let mut contents = document
+            .get_page_contents(page_id)
+            .into_iter()
+            .map(Object::Reference)
+            .collect::<Vec<_>>();
+        contents.insert(0, Object::Reference(transform));
+        contents.push(Object::Reference(restore));
+        document
+            .get_dictionary_mut(page_id)?
+            .set("Contents", contents);
+    }
+
+    pdf::save(document)
+}
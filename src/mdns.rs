@@ -0,0 +1,47 @@
+//! Bonjour/AirPrint advertisement of the inbound IPP printers, so phones and
+//! laptops on the network find them without anyone typing in a URI.
+
+use crate::ipp_server::{display_name, DOCUMENT_FORMATS};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+
+/// Plain IPP, plus the subtype iOS looks for before it'll offer a printer
+const SERVICE_TYPE: &str = "_universal._sub._ipp._tcp.local.";
+
+/// Advertises every one of `printers` as reachable at `hostname:port`,
+/// answering from every interface this machine has.
+///
+/// The returned daemon keeps answering queries until it's shut down, and
+/// should live as long as the server does.
+pub fn advertise<'a>(
+    printers: impl IntoIterator<Item = &'a String>,
+    hostname: &str,
+    port: u16,
+) -> anyhow::Result<ServiceDaemon> {
+    let daemon = ServiceDaemon::new()?;
+    let pdl = DOCUMENT_FORMATS.join(",");
+    for printer in printers {
+        let name = display_name(printer);
+        let resource = format!("ipp/printers/{printer}");
+        let properties = [
+            ("txtvers", "1"),
+            ("qtotal", "1"),
+            ("rp", resource.as_str()),
+            ("ty", name.as_str()),
+            ("product", "(kprint)"),
+            ("pdl", pdl.as_str()),
+            // What AirPrint expects of image/urf: 8-bit grey and sRGB, both
+            // kinds of duplex, any number of copies
+            ("URF", "W8,SRGB24,CP255,DM1"),
+            ("Color", "T"),
+            ("Duplex", "T"),
+            ("kind", "document"),
+            // Tells clients to prompt for the Basic auth print password
+            ("air", "username,password"),
+        ];
+        let service = ServiceInfo::new(SERVICE_TYPE, &name, hostname, "", port, &properties[..])?
+            .enable_addr_auto();
+        daemon.register(service)?;
+        log::info!("Advertising {name} over mDNS");
+    }
+    Ok(daemon)
+}
@@ -0,0 +1,192 @@
+//! Counts of things operators want alerted on, served for Prometheus.
+//!
+//! The counts live in process-wide atomics rather than `AppState`, since
+//! what bumps them, like the IPP client, doesn't always have the app to hand.
+//! Gauges of how backed up things are, like the spool and the printers'
+//! breakers, are read off the app when they're scraped instead.
+
+use crate::app::AppState;
+use crate::breaker::BreakerState;
+use actix_web::{get, web::Data, HttpResponse};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static CUPS_PROXY_AUTH_FAILURES: AtomicU64 = AtomicU64::new(0);
+static MIRRORED_JOBS: AtomicU64 = AtomicU64::new(0);
+static MIRROR_REJECTIONS: AtomicU64 = AtomicU64::new(0);
+
+/// The proxy in front of CUPS turned kprint's token down.
+pub fn cups_proxy_auth_failed() {
+    CUPS_PROXY_AUTH_FAILURES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn cups_proxy_auth_failures() -> u64 {
+    CUPS_PROXY_AUTH_FAILURES.load(Ordering::Relaxed)
+}
+
+/// A job was sent to the test printer as well as its own.
+pub fn job_mirrored() {
+    MIRRORED_JOBS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The test printer turned down a job its own printer was sent.
+pub fn mirror_rejected() {
+    MIRROR_REJECTIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// What's in the spool directory.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SpoolUsage {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Adds up everything under `dir`. It's read off the disk rather than
+/// counted as it's written, so documents a crash left behind count too.
+pub fn spool_usage(dir: &Path) -> std::io::Result<SpoolUsage> {
+    let mut usage = SpoolUsage::default();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            let inner = spool_usage(&entry.path())?;
+            usage.files += inner.files;
+            usage.bytes += inner.bytes;
+        } else {
+            usage.files += 1;
+            usage.bytes += metadata.len();
+        }
+    }
+    Ok(usage)
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Writes out one counter in the Prometheus text format.
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, help, "counter");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    header(out, name, help, "gauge");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+/// Everything there is to scrape, with `spool` left out when the spool
+/// directory couldn't be read, rather than claiming it's empty.
+pub fn render(app_data: &AppState, spool: Option<SpoolUsage>) -> String {
+    let mut out = String::new();
+    counter(
+        &mut out,
+        "kprint_cups_proxy_auth_failures_total",
+        "Requests the proxy in front of CUPS turned down for a bad token",
+        cups_proxy_auth_failures(),
+    );
+    counter(
+        &mut out,
+        "kprint_mirrored_jobs_total",
+        "Jobs sent to KPRINT_MIRROR_PRINTER as well as their own printer",
+        MIRRORED_JOBS.load(Ordering::Relaxed),
+    );
+    counter(
+        &mut out,
+        "kprint_mirror_rejections_total",
+        "Mirrored jobs KPRINT_MIRROR_PRINTER turned down",
+        MIRROR_REJECTIONS.load(Ordering::Relaxed),
+    );
+
+    if let Some(spool) = spool {
+        gauge(
+            &mut out,
+            "kprint_spool_files",
+            "Files in the spool directory",
+            spool.files,
+        );
+        gauge(
+            &mut out,
+            "kprint_spool_bytes",
+            "Size of everything in the spool directory",
+            spool.bytes,
+        );
+    }
+
+    let mut printers = app_data
+        .printers
+        .keys()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    printers.sort();
+    let spooled = app_data.jobs.spooled();
+    // Every printer's listed, so a queue emptying reads as 0 rather than as
+    // a series that's gone
+    let mut depths = printers
+        .iter()
+        .map(|printer| (*printer, 0))
+        .collect::<HashMap<_, _>>();
+    for job in &spooled {
+        *depths.entry(&job.printer).or_default() += 1;
+    }
+    let name = "kprint_spooled_jobs";
+    header(
+        &mut out,
+        name,
+        "Spooled jobs still on their way to each printer",
+        "gauge",
+    );
+    for printer in &printers {
+        let _ = writeln!(out, "{name}{{printer=\"{printer}\"}} {}", depths[printer]);
+    }
+    let oldest = spooled.iter().map(|job| job.submitted_at).min();
+    let age = oldest.map_or(0.0, |oldest| {
+        (Utc::now() - oldest).num_milliseconds() as f64 / 1000.0
+    });
+    gauge(
+        &mut out,
+        "kprint_oldest_spooled_job_age_seconds",
+        "How long the longest-waiting spooled job has waited, or 0 if none are",
+        age,
+    );
+
+    let name = "kprint_breaker_state";
+    header(
+        &mut out,
+        name,
+        "Whether each printer's breaker is in each state, as 1 or 0",
+        "gauge",
+    );
+    for printer in &printers {
+        let current = app_data.breakers.state(printer);
+        for state in BreakerState::ALL {
+            let _ = writeln!(
+                out,
+                "{name}{{printer=\"{printer}\",state=\"{}\"}} {}",
+                state.name(),
+                (state == current) as u8
+            );
+        }
+    }
+    out
+}
+
+#[get("/metrics")]
+pub async fn metrics(app_data: Data<AppState>) -> HttpResponse {
+    let spool = tokio::task::spawn_blocking(|| spool_usage(&crate::spool::spool_dir()?));
+    let spool = match spool.await {
+        Ok(Ok(spool)) => Some(spool),
+        Ok(Err(err)) => {
+            log::warn!("Couldn't add up what's in the spool directory: {err}");
+            None
+        }
+        Err(_) => None,
+    };
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(render(&app_data, spool))
+}
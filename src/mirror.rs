@@ -0,0 +1,145 @@
+//! Sending a sample of jobs to a test printer as well as their own, for
+//! trying changes to how options become IPP attributes out on real traffic
+//! before anyone relies on them.
+//!
+//! `KPRINT_MIRROR_PRINTER` names the test printer the way `KPRINT_PRINTERS`
+//! would, like `null:shadow`, but nobody's offered it to print to.
+//! `KPRINT_MIRROR_PERCENT` of jobs, none by default, are checked over by it
+//! with Validate-Job, with the same attributes they were sent to their own
+//! printer with. With `KPRINT_MIRROR_DOCUMENTS` set they're printed on it
+//! instead, document and all, as long as the document was kept rather than
+//! streamed through. Admins can change both while kprint's running. What the
+//! test printer says only ever goes in the log, under `kprint::mirror`, so
+//! it can't hold up or fail anyone's job.
+
+use crate::error::{ErrorCode, FieldError, KprintError};
+use crate::ipp_client::IppClient;
+use crate::jobs::find_attribute;
+use crate::metrics;
+use crate::trace;
+use ipp::prelude::*;
+use kprint_client::models::{Mirroring, SetMirroring};
+use std::sync::RwLock;
+use uuid::Uuid;
+
+pub struct Mirror {
+    /// The test printer, by name
+    printer: Option<(String, IppClient)>,
+    settings: RwLock<SetMirroring>,
+}
+
+/// A job picked to be mirrored.
+pub struct Sample {
+    pub printer: String,
+    pub client: IppClient,
+    /// Whether to print it, if its document was kept, rather than just have
+    /// its attributes checked
+    pub documents: bool,
+}
+
+impl Mirror {
+    pub fn from_env(printer: Option<(String, IppClient)>) -> anyhow::Result<Self> {
+        let percent = match std::env::var("KPRINT_MIRROR_PERCENT") {
+            Ok(percent) => percent.parse()?,
+            Err(_) => 0,
+        };
+        let documents = match std::env::var("KPRINT_MIRROR_DOCUMENTS") {
+            Ok(documents) => documents.parse()?,
+            Err(_) => false,
+        };
+        let settings = SetMirroring { percent, documents };
+        check(&printer, &settings).map_err(|err| anyhow::anyhow!("{err}"))?;
+        Ok(Mirror {
+            printer,
+            settings: RwLock::new(settings),
+        })
+    }
+
+    pub fn status(&self) -> Mirroring {
+        let settings = self.settings.read().unwrap();
+        Mirroring {
+            printer: self.printer.as_ref().map(|(name, _)| name.clone()),
+            percent: settings.percent,
+            documents: settings.documents,
+        }
+    }
+
+    /// Changes how much is mirrored, until kprint restarts.
+    pub fn set(&self, settings: SetMirroring) -> Result<Mirroring, KprintError> {
+        check(&self.printer, &settings)?;
+        *self.settings.write().unwrap() = settings;
+        Ok(self.status())
+    }
+
+    /// Whether the job with id `job` is one to mirror. Ids are random, so
+    /// picking by them picks the right share of jobs, and always the same
+    /// way for a job that's sent twice.
+    pub fn sample(&self, job: Uuid) -> Option<Sample> {
+        let (printer, client) = self.printer.as_ref()?;
+        let settings = self.settings.read().unwrap();
+        (job.as_u128() % 100 < settings.percent as u128).then(|| Sample {
+            printer: printer.clone(),
+            client: client.clone(),
+            documents: settings.documents,
+        })
+    }
+}
+
+fn check(
+    printer: &Option<(String, IppClient)>,
+    settings: &SetMirroring,
+) -> Result<(), KprintError> {
+    if settings.percent > 100 {
+        return Err(KprintError::Validation(vec![FieldError::new(
+            "percent",
+            ErrorCode::ValueOutOfRange,
+            "percent can't be more than 100",
+        )]));
+    }
+    if printer.is_none() && settings.percent > 0 {
+        return Err(KprintError::InvalidRequest(
+            "There's no KPRINT_MIRROR_PRINTER to mirror jobs to".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+impl Sample {
+    /// Sends `request`, a copy of job `job`'s, to the test printer in the
+    /// background, and logs what it made of it next to whether `printer` took
+    /// the real one.
+    pub fn send(self, job: Uuid, printer: &str, accepted: bool, request: IppRequestResponse) {
+        let printer = printer.to_string();
+        let operation = match request.header().operation_or_status {
+            operation if operation == Operation::PrintJob as u16 => "print",
+            _ => "validate",
+        };
+        tokio::spawn(trace::linked("mirror job", async move {
+            metrics::job_mirrored();
+            let mirror = &self.printer;
+            match self.client.send(request).await {
+                Ok(response) if response.header().status_code().is_success() => log::info!(
+                    target: "kprint::mirror",
+                    "job={job} printer={printer} accepted={accepted} mirror={mirror} operation={operation} mirror_accepted=true"
+                ),
+                Ok(response) => {
+                    metrics::mirror_rejected();
+                    let status = response.header().status_code();
+                    let message =
+                        find_attribute(response.attributes(), IppAttribute::STATUS_MESSAGE)
+                            .map(ToString::to_string)
+                            .unwrap_or_else(|| status.to_string());
+                    log::warn!(
+                        target: "kprint::mirror",
+                        "job={job} printer={printer} accepted={accepted} mirror={mirror} operation={operation} mirror_accepted=false status={status:?} message={message:?}"
+                    );
+                }
+                Err(err) => log::warn!(
+                    target: "kprint::mirror",
+                    "job={job} printer={printer} mirror={mirror} operation={operation} error={:?}",
+                    err.to_string()
+                ),
+            }
+        }));
+    }
+}
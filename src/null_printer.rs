@@ -0,0 +1,498 @@
+//! Printers that aren't really there, for developing against kprint without
+//! a printer or CUPS.
+//!
+//! Printers listed in `KPRINT_PRINTERS` as `null:` and a name, like
+//! `null:dev`, are answered by kprint itself instead of CUPS. They take any
+//! job, write its document to `KPRINT_NULL_PRINTER_DIR`, `kprint-null` in the
+//! temp directory by default, and then act out printing it: jobs wait their
+//! turn, print for `KPRINT_NULL_PRINT_SECONDS`, 5 by default, and complete.
+//! They can be paused and resumed, and their jobs held, released, bumped and
+//! canceled, the same as CUPS's. Everything above the IPP client treats them
+//! like any other printer, so they're listed and reported on as usual.
+
+use crate::jobs::find_attribute;
+use crate::logging::Pii;
+use crate::sniff;
+use chrono::Utc;
+use ipp::model::{JobState, PrinterState, StatusCode};
+use ipp::prelude::*;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio_util::compat::TokioAsyncWriteCompatExt;
+
+const DEFAULT_PRINT_TIME: Duration = Duration::from_secs(5);
+/// Set-Job-Attributes, which the ipp crate has no [`Operation`] for
+const SET_JOB_ATTRIBUTES: u16 = 0x0014;
+const DEFAULT_PRIORITY: i32 = 50;
+
+/// What every null printer shares.
+#[derive(Debug, Clone)]
+pub struct NullPrinters {
+    dir: PathBuf,
+    print_time: Duration,
+}
+
+impl NullPrinters {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let dir = match std::env::var("KPRINT_NULL_PRINTER_DIR") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => std::env::temp_dir().join("kprint-null"),
+        };
+        let print_time = match std::env::var("KPRINT_NULL_PRINT_SECONDS") {
+            Ok(seconds) => Duration::try_from_secs_f64(seconds.parse()?)?,
+            Err(_) => DEFAULT_PRINT_TIME,
+        };
+        Ok(NullPrinters { dir, print_time })
+    }
+
+    /// A null printer called `name`, writing its jobs out with the rest.
+    pub fn printer(&self, name: &str) -> anyhow::Result<NullPrinter> {
+        std::fs::create_dir_all(&self.dir)?;
+        log::info!(
+            "{name} is a null printer, writing what it's sent to {}",
+            self.dir.display()
+        );
+        Ok(NullPrinter {
+            name: name.to_string(),
+            shared: self.clone(),
+            state: Mutex::default(),
+        })
+    }
+}
+
+struct Job {
+    id: i32,
+    name: String,
+    user: String,
+    priority: i32,
+    state: JobState,
+    /// Made with Create-Job, and still waiting for its last document
+    incoming: bool,
+    /// Documents written out for it so far
+    documents: u32,
+    /// When it could first start printing, once it was sent or released
+    ready_at: Instant,
+    started_at: Option<Instant>,
+}
+
+#[derive(Default)]
+struct State {
+    jobs: Vec<Job>,
+    paused: bool,
+    /// The id the last job sent was given
+    last_id: i32,
+    /// When the job before finished, which the next can't start before
+    free_at: Option<Instant>,
+}
+
+impl State {
+    /// Acts out everything that would've happened by `now`.
+    fn advance(&mut self, print_time: Duration, now: Instant) {
+        loop {
+            if let Some(job) = self
+                .jobs
+                .iter_mut()
+                .find(|job| job.state == JobState::Processing)
+            {
+                let done_at = job.started_at.unwrap_or(now) + print_time;
+                if done_at > now {
+                    return;
+                }
+                job.state = JobState::Completed;
+                self.free_at = Some(done_at);
+            }
+            if self.paused {
+                return;
+            }
+            let free_at = self.free_at;
+            // Highest priority first, then the order they were sent in
+            let Some(next) = self
+                .jobs
+                .iter_mut()
+                .filter(|job| job.state == JobState::Pending && !job.incoming)
+                .min_by_key(|job| (-job.priority, job.id))
+            else {
+                return;
+            };
+            let starts_at = free_at.map_or(next.ready_at, |free_at| free_at.max(next.ready_at));
+            if starts_at > now {
+                return;
+            }
+            next.state = JobState::Processing;
+            next.started_at = Some(starts_at);
+        }
+    }
+
+    fn printing(&self) -> bool {
+        self.jobs
+            .iter()
+            .any(|job| job.state == JobState::Processing)
+    }
+}
+
+pub struct NullPrinter {
+    name: String,
+    shared: NullPrinters,
+    state: Mutex<State>,
+}
+
+fn integer(request: &IppRequestResponse, name: &str) -> Option<i32> {
+    match find_attribute(request.attributes(), name) {
+        Some(IppValue::Integer(value) | IppValue::Enum(value)) => Some(*value),
+        _ => None,
+    }
+}
+
+fn text(request: &IppRequestResponse, name: &str) -> Option<String> {
+    find_attribute(request.attributes(), name).map(ToString::to_string)
+}
+
+fn keyword(value: &str) -> IppValue {
+    IppValue::Keyword(value.to_string())
+}
+
+/// What a file written out in `media_type` should end in.
+fn extension(media_type: Option<&str>) -> &'static str {
+    match media_type {
+        Some(media_type) if media_type == sniff::PDF.media_type => "pdf",
+        Some(media_type) if media_type == sniff::POSTSCRIPT.media_type => "ps",
+        Some(media_type) if media_type == sniff::JPEG.media_type => "jpg",
+        Some(media_type) if media_type == sniff::PNG.media_type => "png",
+        Some(media_type) if media_type == sniff::URF.media_type => "urf",
+        Some(media_type) if media_type == sniff::TEXT.media_type => "txt",
+        _ => "prn",
+    }
+}
+
+fn job_attributes(job: &Job) -> Vec<IppAttribute> {
+    let reasons = match job.state {
+        _ if job.incoming => "job-incoming",
+        JobState::PendingHeld => "job-hold-until-specified",
+        JobState::Processing => "job-printing",
+        JobState::Canceled => "job-canceled-by-user",
+        JobState::Completed => "job-completed-successfully",
+        _ => "none",
+    };
+    vec![
+        IppAttribute::new(IppAttribute::JOB_ID, IppValue::Integer(job.id)),
+        IppAttribute::new(IppAttribute::JOB_STATE, IppValue::Enum(job.state as i32)),
+        IppAttribute::new(IppAttribute::JOB_STATE_REASONS, keyword(reasons)),
+        IppAttribute::new(
+            IppAttribute::JOB_NAME,
+            IppValue::NameWithoutLanguage(job.name.clone()),
+        ),
+        IppAttribute::new(
+            "job-originating-user-name",
+            IppValue::NameWithoutLanguage(job.user.clone()),
+        ),
+        IppAttribute::new("job-priority", IppValue::Integer(job.priority)),
+    ]
+}
+
+impl NullPrinter {
+    /// Answers `request` the way a printer would.
+    pub async fn answer(&self, request: IppRequestResponse) -> anyhow::Result<IppRequestResponse> {
+        let header = request.header().clone();
+        let respond =
+            |status| IppRequestResponse::new_response(header.version, status, header.request_id);
+        let operation = header.operation_or_status;
+        if operation == Operation::PrintJob as u16 {
+            let id = self.create(&request);
+            return self
+                .receive(id, request, respond(StatusCode::SuccessfulOk), true)
+                .await;
+        }
+        if operation == Operation::CreateJob as u16 {
+            let id = self.create(&request);
+            let mut response = respond(StatusCode::SuccessfulOk);
+            let state = self.state.lock().unwrap();
+            let job = state.jobs.iter().find(|job| job.id == id).unwrap();
+            for attribute in job_attributes(job) {
+                response
+                    .attributes_mut()
+                    .add(DelimiterTag::JobAttributes, attribute);
+            }
+            return Ok(response);
+        }
+        if operation == Operation::SendDocument as u16 {
+            let id = integer(&request, IppAttribute::JOB_ID);
+            let status = {
+                let state = self.state.lock().unwrap();
+                match id.and_then(|id| state.jobs.iter().find(|job| job.id == id)) {
+                    Some(job)
+                        if job.incoming
+                            && matches!(job.state, JobState::Pending | JobState::PendingHeld) =>
+                    {
+                        StatusCode::SuccessfulOk
+                    }
+                    Some(_) => StatusCode::ClientErrorNotPossible,
+                    None => StatusCode::ClientErrorNotFound,
+                }
+            };
+            let last = matches!(
+                find_attribute(request.attributes(), "last-document"),
+                Some(IppValue::Boolean(true))
+            );
+            return match (id, status) {
+                (Some(id), StatusCode::SuccessfulOk) => {
+                    self.receive(id, request, respond(status), last).await
+                }
+                _ => Ok(respond(status)),
+            };
+        }
+
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.advance(self.shared.print_time, now);
+        if operation == Operation::GetPrinterAttributes as u16 {
+            let mut response = respond(StatusCode::SuccessfulOk);
+            for attribute in self.printer_attributes(&state) {
+                response
+                    .attributes_mut()
+                    .add(DelimiterTag::PrinterAttributes, attribute);
+            }
+            return Ok(response);
+        }
+        if operation == Operation::ValidateJob as u16 {
+            return Ok(respond(StatusCode::SuccessfulOk));
+        }
+        if operation == Operation::PausePrinter as u16
+            || operation == Operation::ResumePrinter as u16
+        {
+            state.paused = operation == Operation::PausePrinter as u16;
+            if !state.paused {
+                // Resumed jobs print from now, not from when it was paused
+                state.free_at = state.free_at.map(|free_at| free_at.max(now));
+                state.advance(self.shared.print_time, now);
+            }
+            log::info!(
+                "Null printer {} {}",
+                self.name,
+                if state.paused { "paused" } else { "resumed" }
+            );
+            return Ok(respond(StatusCode::SuccessfulOk));
+        }
+        if operation == Operation::GetJobs as u16 {
+            let mut response = respond(StatusCode::SuccessfulOk);
+            for job in state.jobs.iter().filter(|job| {
+                matches!(
+                    job.state,
+                    JobState::Pending | JobState::PendingHeld | JobState::Processing
+                )
+            }) {
+                let mut group = IppAttributeGroup::new(DelimiterTag::JobAttributes);
+                for attribute in job_attributes(job) {
+                    group
+                        .attributes_mut()
+                        .insert(attribute.name().to_string(), attribute);
+                }
+                response.attributes_mut().groups_mut().push(group);
+            }
+            return Ok(response);
+        }
+
+        let Some(job) = integer(&request, IppAttribute::JOB_ID)
+            .and_then(|id| state.jobs.iter_mut().find(|job| job.id == id))
+        else {
+            return Ok(respond(StatusCode::ClientErrorNotFound));
+        };
+        let status = match operation {
+            operation if operation == Operation::GetJobAttributes as u16 => {
+                let mut response = respond(StatusCode::SuccessfulOk);
+                for attribute in job_attributes(job) {
+                    response
+                        .attributes_mut()
+                        .add(DelimiterTag::JobAttributes, attribute);
+                }
+                return Ok(response);
+            }
+            operation if operation == Operation::CancelJob as u16 => match job.state {
+                JobState::Pending | JobState::PendingHeld => {
+                    job.state = JobState::Canceled;
+                    StatusCode::SuccessfulOk
+                }
+                JobState::Processing => {
+                    job.state = JobState::Canceled;
+                    // The next one can start straight away
+                    state.free_at = Some(now);
+                    StatusCode::SuccessfulOk
+                }
+                _ => StatusCode::ClientErrorNotPossible,
+            },
+            operation if operation == Operation::ReleaseJob as u16 => match job.state {
+                JobState::PendingHeld => {
+                    job.state = JobState::Pending;
+                    job.ready_at = now;
+                    StatusCode::SuccessfulOk
+                }
+                _ => StatusCode::ClientErrorNotPossible,
+            },
+            SET_JOB_ATTRIBUTES => match job.state {
+                JobState::Pending | JobState::PendingHeld => {
+                    job.priority = integer(&request, "job-priority").unwrap_or(job.priority);
+                    StatusCode::SuccessfulOk
+                }
+                _ => StatusCode::ClientErrorNotPossible,
+            },
+            _ => StatusCode::ServerErrorOperationNotSupported,
+        };
+        state.advance(self.shared.print_time, now);
+        Ok(respond(status))
+    }
+
+    /// Makes the job `request` asks for, waiting for its document.
+    fn create(&self, request: &IppRequestResponse) -> i32 {
+        let name = text(request, IppAttribute::JOB_NAME).unwrap_or_default();
+        let user = text(request, IppAttribute::REQUESTING_USER_NAME).unwrap_or_default();
+        let held = text(request, "job-hold-until").is_some_and(|until| until != "no-hold");
+        let mut state = self.state.lock().unwrap();
+        state.last_id += 1;
+        let id = state.last_id;
+        state.jobs.push(Job {
+            id,
+            name,
+            user,
+            priority: DEFAULT_PRIORITY,
+            state: match held {
+                true => JobState::PendingHeld,
+                false => JobState::Pending,
+            },
+            incoming: true,
+            documents: 0,
+            ready_at: Instant::now(),
+            started_at: None,
+        });
+        id
+    }
+
+    /// Writes out a document of job `id`'s, and queues the job if it's the
+    /// `last` one.
+    async fn receive(
+        &self,
+        id: i32,
+        request: IppRequestResponse,
+        mut response: IppRequestResponse,
+        last: bool,
+    ) -> anyhow::Result<IppRequestResponse> {
+        let (name, user, documents) = {
+            let state = self.state.lock().unwrap();
+            let job = state.jobs.iter().find(|job| job.id == id).unwrap();
+            (job.name.clone(), job.user.clone(), job.documents)
+        };
+        let format = text(&request, "document-format");
+        // Every document after the first is numbered
+        let number = match documents {
+            0 => String::new(),
+            documents => format!("-{}", documents + 1),
+        };
+        let file = self.shared.dir.join(format!(
+            "{}-{}-{id}{number}.{}",
+            self.name,
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            extension(format.as_deref())
+        ));
+
+        let mut written = tokio::fs::File::create(&file).await?.compat_write();
+        let bytes = match futures::io::copy(request.into_payload(), &mut written).await {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(job) = state.jobs.iter_mut().find(|job| job.id == id) {
+                    // As much as arrived is left behind, like CUPS would
+                    job.state = JobState::Aborted;
+                }
+                return Err(err.into());
+            }
+        };
+        // Closing a job can come without a document of its own
+        let empty = bytes == 0 && last && documents > 0;
+        if empty {
+            let _ = tokio::fs::remove_file(&file).await;
+        } else {
+            log::info!(
+                "Null printer {} took {}'s {:?}, {bytes} bytes, as {}",
+                self.name,
+                Pii(&user),
+                Pii(&name),
+                file.display()
+            );
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let job = state.jobs.iter_mut().find(|job| job.id == id).unwrap();
+        job.documents += !empty as u32;
+        if last {
+            job.incoming = false;
+            job.ready_at = Instant::now();
+        }
+        state.advance(self.shared.print_time, Instant::now());
+        let job = state.jobs.iter().find(|job| job.id == id).unwrap();
+        for attribute in job_attributes(job) {
+            response
+                .attributes_mut()
+                .add(DelimiterTag::JobAttributes, attribute);
+        }
+        Ok(response)
+    }
+
+    fn printer_attributes(&self, state: &State) -> Vec<IppAttribute> {
+        let (printer_state, reasons) = match (state.paused, state.printing()) {
+            (true, _) => (PrinterState::Stopped, "paused"),
+            (false, true) => (PrinterState::Processing, "none"),
+            (false, false) => (PrinterState::Idle, "none"),
+        };
+        let keywords =
+            |values: &[&str]| IppValue::Array(values.iter().map(|value| keyword(value)).collect());
+        vec![
+            IppAttribute::new(
+                IppAttribute::PRINTER_NAME,
+                IppValue::NameWithoutLanguage(self.name.clone()),
+            ),
+            IppAttribute::new(
+                IppAttribute::PRINTER_STATE,
+                IppValue::Enum(printer_state as i32),
+            ),
+            IppAttribute::new(IppAttribute::PRINTER_STATE_REASONS, keyword(reasons)),
+            IppAttribute::new(
+                IppAttribute::PRINTER_STATE_MESSAGE,
+                IppValue::TextWithoutLanguage(String::new()),
+            ),
+            IppAttribute::new(
+                IppAttribute::PRINTER_IS_ACCEPTING_JOBS,
+                IppValue::Boolean(true),
+            ),
+            IppAttribute::new(
+                IppAttribute::PRINTER_MAKE_AND_MODEL,
+                IppValue::TextWithoutLanguage("kprint null printer".to_string()),
+            ),
+            IppAttribute::new(
+                IppAttribute::DOCUMENT_FORMAT_SUPPORTED,
+                IppValue::Array(
+                    sniff::PRINTABLE
+                        .iter()
+                        .map(|format| IppValue::MimeMediaType(format.media_type.to_string()))
+                        .collect(),
+                ),
+            ),
+            IppAttribute::new(
+                IppAttribute::SIDES_SUPPORTED,
+                keywords(&["one-sided", "two-sided-long-edge", "two-sided-short-edge"]),
+            ),
+            IppAttribute::new(
+                IppAttribute::PRINT_COLOR_MODE_SUPPORTED,
+                keywords(&["monochrome", "color"]),
+            ),
+            IppAttribute::new(
+                "queued-job-count",
+                IppValue::Integer(
+                    state
+                        .jobs
+                        .iter()
+                        .filter(|job| matches!(job.state, JobState::Pending | JobState::Processing))
+                        .count() as i32,
+                ),
+            ),
+        ]
+    }
+}
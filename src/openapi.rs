@@ -0,0 +1,104 @@
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi, ToSchema,
+};
+
+/// Stand-in for a raw uploaded document, so the spec describes the body as
+/// binary instead of as an array of numbers.
+#[derive(ToSchema)]
+#[schema(value_type = String, format = Binary)]
+pub struct Document(#[allow(dead_code)] Vec<u8>);
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "kprint", description = "Print to CSH printers from anywhere"),
+    servers((url = "/api/v1")),
+    paths(
+        crate::api::list_printers,
+        crate::api::print,
+        crate::api::print_default,
+        crate::api::print_broadcast,
+        crate::api::dry_run,
+        crate::api::print_inline,
+        crate::api::print_url,
+        crate::api::lay_out_envelope,
+        crate::api::create_upload,
+        crate::api::upload_status,
+        crate::api::upload_chunk,
+        crate::api::print_upload,
+        crate::api::delete_upload,
+        crate::api::list_jobs,
+        crate::api::job_status,
+        crate::api::job_preview,
+        crate::api::job_receipt,
+        crate::api::cancel_job,
+        crate::api::open_job,
+        crate::api::add_document,
+        crate::api::close_job,
+        crate::api::create_print_password,
+        crate::api::revoke_print_password,
+        crate::api::get_preferences,
+        crate::api::set_preferences,
+        crate::api::get_quota,
+        crate::api::print_as_guest,
+        crate::api::pickup_job,
+        crate::api::release_job,
+        crate::admin::test_page,
+        crate::admin::purge_queue,
+        crate::admin::cancel_job,
+        crate::admin::bump_job,
+        crate::admin::move_job,
+        crate::admin::list_classes,
+        crate::admin::pause_printer,
+        crate::admin::resume_printer,
+        crate::admin::set_maintenance,
+        crate::admin::clear_maintenance,
+        crate::admin::set_log_level,
+        crate::admin::show_config,
+        crate::admin::list_flags,
+        crate::admin::set_flag,
+        crate::admin::clear_flag,
+        crate::admin::show_mirroring,
+        crate::admin::set_mirroring,
+        crate::admin::list_suspensions,
+        crate::admin::suspend_user,
+        crate::admin::unsuspend_user,
+        crate::admin::list_guest_codes,
+        crate::admin::create_guest_code,
+        crate::admin::revoke_guest_code,
+        crate::admin::stats,
+        crate::admin::report,
+        crate::admin::quota_periods,
+        crate::admin::export_history,
+        crate::admin::list_history,
+    ),
+    modifiers(&CshSsoScheme),
+    tags(
+        (name = "printers", description = "Which printers there are and how they're doing"),
+        (name = "printing", description = "Submitting print jobs"),
+        (name = "jobs", description = "Keeping track of submitted jobs"),
+        (name = "admin", description = "Looking after the printers, for kprint admins only"),
+    ),
+)]
+pub struct ApiDoc;
+
+/// Registers the bearer token scheme the handlers refer to as `csh_sso`.
+struct CshSsoScheme;
+
+impl Modify for CshSsoScheme {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(
+                "csh_sso",
+                SecurityScheme::Http(
+                    HttpBuilder::new()
+                        .scheme(HttpAuthScheme::Bearer)
+                        .bearer_format("JWT")
+                        .description(Some("An ID token issued by sso.csh.rit.edu"))
+                        .build(),
+                ),
+            );
+    }
+}
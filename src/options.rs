@@ -0,0 +1,477 @@
+use kprint_client::models::{
+    ColorMode, DuplexMode, Envelope, ErrorCode, FieldError, Media, MediaMargins, ParsePosterError,
+    Poster, Preferences, PrintOffset, PrintOptions,
+};
+use kprint_client::pages::{PageSelection, ParsePagesError};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use utoipa::IntoParams;
+
+/// Most copies a single job may ask for, so a typo can't empty the paper closet
+pub const MAX_COPIES: u32 = 100;
+/// IPP `name` values are capped at 255 octets
+pub const MAX_TITLE_LENGTH: usize = 255;
+/// Most tags a single job can have
+pub const MAX_TAGS: usize = 10;
+pub const MAX_TAG_LENGTH: usize = 32;
+/// Longest media can be either way, in hundredths of a millimeter, which is
+/// a 50 meter plotter roll
+pub const MAX_MEDIA_DIMENSION: u32 = 5_000_000;
+/// Furthest a job can be moved either way, in hundredths of a millimeter
+pub const MAX_OFFSET: i32 = 10_000;
+/// Most sheets a poster can be across or down
+pub const MAX_POSTER_SHEETS: u32 = 8;
+
+/// The print options exactly as they arrived in the query string.
+///
+/// Everything is optional text here so that deserializing can't fail, and
+/// [`PrintQuery::validate`] gets to report every problem at once. Required
+/// options can still be left out if the member saved a preference for them.
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct PrintQuery {
+    /// "sides": A keyword that specifies whether to do two sided printing. Values include 'one-sided', 'two-sided-long-edge' (typical 2-sided printing for portrait Documents), and 'two-sided-short-edge' (2-sided printing for landscape Documents). Required unless you've saved a preference for it.
+    #[param(value_type = Option<DuplexMode>)]
+    pub sides: Option<String>,
+    /// "print-color-mode": A keyword specifying the color printing mode to use. The value 'color' specifies a full-color print, 'monochrome' specifies a grayscale print, and 'bi-level' specifies a black-and-white (no shades of gray) print. Required unless you've saved a preference for it.
+    #[param(value_type = Option<ColorMode>)]
+    pub color_mode: Option<String>,
+    /// Comma separated page ranges to print, like `1-3,5`. Empty prints every page.
+    #[param(value_type = String, required = true, example = "1-3,5")]
+    pub pages: Option<String>,
+    #[param(value_type = u32, required = true, minimum = 1, maximum = 100)]
+    pub copies: Option<String>,
+    #[param(value_type = String, required = true, max_length = 255)]
+    pub title: Option<String>,
+    /// Print a page in front saying who printed the document, and what it is.
+    /// Only PDFs get one. Defaults to your saved preference, or off.
+    #[param(value_type = Option<bool>)]
+    pub cover_sheet: Option<String>,
+    /// If the printer fails partway through the job, send it to another
+    /// printer in the same pool that can print it the same way.
+    #[param(value_type = Option<bool>)]
+    pub allow_fallback: Option<String>,
+    /// Print each copy as a job of its own, sent once the one before has
+    /// printed, so other people's jobs can get in between on a long run.
+    #[param(value_type = Option<bool>)]
+    pub separate_jobs: Option<String>,
+    /// Comma separated labels kept with the job in the history, like
+    /// `evals,opcomm`, for telling what it was printed for. They're made of
+    /// lowercase letters, digits, `-` and `_`, and never reach the printer.
+    #[param(value_type = Option<String>, example = "evals")]
+    pub tags: Option<String>,
+    /// The paper to print on, as a JSON `Media` object like
+    /// `{"xDimension":91440,"yDimension":121920,"source":"main-roll"}`, for
+    /// printers that need it spelled out. Left out, it's the printer's
+    /// default paper.
+    #[param(value_type = Option<String>)]
+    pub media: Option<String>,
+    /// How far in from each edge to print, as a JSON `MediaMargins` object
+    /// like `{"top":635,"bottom":635,"left":635,"right":635}`, in hundredths
+    /// of a millimeter. Left out, it's the printer's own margins.
+    #[param(value_type = Option<String>)]
+    pub margins: Option<String>,
+    /// How far to move what's printed, as a JSON `PrintOffset` object like
+    /// `{"x":200,"y":-100}`, in hundredths of a millimeter right and down.
+    #[param(value_type = Option<String>)]
+    pub offset: Option<String>,
+    /// Blow each page up across a grid of sheets, columns by rows, like
+    /// `3x2`, with crop marks to trim them to before taping them together.
+    /// Only PDFs can be made into posters.
+    #[param(value_type = Option<String>, example = "3x2")]
+    pub poster: Option<String>,
+    /// Print on an envelope of this size, one-sided and fed from wherever the
+    /// printer takes envelopes, so none of that has to be worked out. It
+    /// picks the media, so can't be given with `media` or `poster`.
+    #[param(value_type = Option<Envelope>)]
+    pub envelope: Option<String>,
+}
+
+/// Parses an IPP keyword using the same names serde gives the enum.
+fn parse_keyword<T: DeserializeOwned + Serialize>(
+    field: &'static str,
+    value: Option<&str>,
+    all: &[T],
+) -> Result<T, FieldError> {
+    let value = value.ok_or_else(|| FieldError::missing(field))?;
+    T::deserialize(serde::de::value::StrDeserializer::<serde::de::value::Error>::new(value.trim()))
+        .map_err(|_| {
+            let supported = all
+                .iter()
+                .map(|variant| serde_variant::to_variant_name(variant).unwrap())
+                .collect::<Vec<_>>();
+            FieldError::new(
+                field,
+                ErrorCode::UnsupportedValue,
+                format!("{value:?} isn't one of {}", supported.join(", ")),
+            )
+        })
+}
+
+fn parse_pages(pages: Option<&str>) -> Result<PageSelection, FieldError> {
+    let pages = pages.ok_or_else(|| FieldError::missing("pages"))?;
+    pages.parse().map_err(|err: ParsePagesError| {
+        FieldError::new("pages", ErrorCode::PageRangeInvalid, err.to_string())
+    })
+}
+
+/// `pages` written out like `1-3,5`, or `None` for every page.
+pub fn written_pages(pages: &PageSelection) -> Option<String> {
+    (!pages.is_all()).then(|| pages.to_string())
+}
+
+fn parse_copies(copies: Option<&str>) -> Result<u32, FieldError> {
+    let copies = copies.ok_or_else(|| FieldError::missing("copies"))?;
+    match copies.trim().parse() {
+        Ok(copies @ 1..=MAX_COPIES) => Ok(copies),
+        _ => Err(FieldError::new(
+            "copies",
+            ErrorCode::ValueOutOfRange,
+            format!("Copies must be a whole number from 1 to {MAX_COPIES}, but got {copies:?}"),
+        )),
+    }
+}
+
+fn parse_title(title: Option<&str>) -> Result<String, FieldError> {
+    let title = title.ok_or_else(|| FieldError::missing("title"))?;
+    if title.len() > MAX_TITLE_LENGTH {
+        return Err(FieldError::new(
+            "title",
+            ErrorCode::ValueTooLong,
+            format!("Titles can be at most {MAX_TITLE_LENGTH} bytes long"),
+        ));
+    }
+    Ok(title.to_string())
+}
+
+fn parse_tags(tags: Option<&str>) -> Result<Vec<String>, FieldError> {
+    let mut parsed = Vec::<String>::new();
+    for tag in tags.unwrap_or_default().split(',') {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() || parsed.contains(&tag) {
+            continue;
+        }
+        if tag.len() > MAX_TAG_LENGTH {
+            return Err(FieldError::new(
+                "tags",
+                ErrorCode::ValueTooLong,
+                format!("Tags can be at most {MAX_TAG_LENGTH} characters long, but {tag:?} isn't"),
+            ));
+        }
+        let valid = tag.starts_with(|c: char| c.is_ascii_alphanumeric())
+            && tag
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_'));
+        if !valid {
+            return Err(FieldError::new(
+                "tags",
+                ErrorCode::UnsupportedValue,
+                format!("{tag:?} isn't a tag, which are letters, digits, - and _"),
+            ));
+        }
+        parsed.push(tag);
+    }
+    if parsed.len() > MAX_TAGS {
+        return Err(FieldError::new(
+            "tags",
+            ErrorCode::ValueOutOfRange,
+            format!("Jobs can have at most {MAX_TAGS} tags"),
+        ));
+    }
+    Ok(parsed)
+}
+
+/// Whether `keyword` could be an IPP keyword, like `main-roll`.
+fn is_keyword(keyword: &str) -> bool {
+    keyword.starts_with(|c: char| c.is_ascii_lowercase())
+        && keyword
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_' | '.'))
+}
+
+fn parse_media(media: Option<&str>) -> Result<Option<Media>, FieldError> {
+    let Some(media) = media.filter(|media| !media.trim().is_empty()) else {
+        return Ok(None);
+    };
+    let invalid = |message: String| FieldError::new("media", ErrorCode::UnsupportedValue, message);
+    let media: Media = serde_json::from_str(media)
+        .map_err(|err| invalid(format!("media isn't a media object: {err}")))?;
+    let size = match (media.x_dimension, media.y_dimension) {
+        (Some(x), Some(y)) => Some((x, y)),
+        (None, None) => None,
+        _ => return Err(invalid("xDimension and yDimension go together".to_string())),
+    };
+    if let Some((x, y)) = size {
+        if !(1..=MAX_MEDIA_DIMENSION).contains(&x) || !(1..=MAX_MEDIA_DIMENSION).contains(&y) {
+            return Err(FieldError::new(
+                "media",
+                ErrorCode::ValueOutOfRange,
+                format!("Media can be from 1 to {MAX_MEDIA_DIMENSION} hundredths of a millimeter each way"),
+            ));
+        }
+        if let Some(margins) = media.margins {
+            if margins.left + margins.right >= x || margins.top + margins.bottom >= y {
+                return Err(FieldError::new(
+                    "media",
+                    ErrorCode::ValueOutOfRange,
+                    "The margins leave nothing to print on".to_string(),
+                ));
+            }
+        }
+    }
+    for keyword in [&media.source, &media.media_type].into_iter().flatten() {
+        if !is_keyword(keyword) {
+            return Err(invalid(format!("{keyword:?} isn't an IPP keyword")));
+        }
+    }
+    if media == Media::default() {
+        return Ok(None);
+    }
+    Ok(Some(media))
+}
+
+/// Parses margins given by themselves, which can't also be given with the
+/// media.
+fn parse_margins(
+    margins: Option<&str>,
+    media: Option<&Media>,
+) -> Result<Option<MediaMargins>, FieldError> {
+    let Some(margins) = margins.filter(|margins| !margins.trim().is_empty()) else {
+        return Ok(None);
+    };
+    let margins: MediaMargins = serde_json::from_str(margins).map_err(|err| {
+        FieldError::new(
+            "margins",
+            ErrorCode::UnsupportedValue,
+            format!("margins isn't a margins object: {err}"),
+        )
+    })?;
+    if media.is_some_and(|media| media.margins.is_some()) {
+        return Err(FieldError::new(
+            "margins",
+            ErrorCode::UnsupportedValue,
+            "The media already has margins".to_string(),
+        ));
+    }
+    let leaves_nothing = match media.and_then(|media| media.x_dimension.zip(media.y_dimension)) {
+        Some((x, y)) => margins.left + margins.right >= x || margins.top + margins.bottom >= y,
+        None => [margins.top, margins.bottom, margins.left, margins.right]
+            .into_iter()
+            .any(|margin| margin > MAX_MEDIA_DIMENSION),
+    };
+    if leaves_nothing {
+        return Err(FieldError::new(
+            "margins",
+            ErrorCode::ValueOutOfRange,
+            "The margins leave nothing to print on".to_string(),
+        ));
+    }
+    Ok(Some(margins))
+}
+
+fn parse_offset(offset: Option<&str>) -> Result<Option<PrintOffset>, FieldError> {
+    let Some(offset) = offset.filter(|offset| !offset.trim().is_empty()) else {
+        return Ok(None);
+    };
+    let offset: PrintOffset = serde_json::from_str(offset).map_err(|err| {
+        FieldError::new(
+            "offset",
+            ErrorCode::UnsupportedValue,
+            format!("offset isn't an offset object: {err}"),
+        )
+    })?;
+    if offset.x.abs() > MAX_OFFSET || offset.y.abs() > MAX_OFFSET {
+        return Err(FieldError::new(
+            "offset",
+            ErrorCode::ValueOutOfRange,
+            format!("Jobs can be moved at most {MAX_OFFSET} hundredths of a millimeter either way"),
+        ));
+    }
+    Ok((offset != PrintOffset::default()).then_some(offset))
+}
+
+fn parse_poster(poster: Option<&str>) -> Result<Option<Poster>, FieldError> {
+    let Some(poster) = poster.filter(|poster| !poster.trim().is_empty()) else {
+        return Ok(None);
+    };
+    let poster: Poster = poster.parse().map_err(|err: ParsePosterError| {
+        FieldError::new("poster", ErrorCode::UnsupportedValue, err.to_string())
+    })?;
+    if !(1..=MAX_POSTER_SHEETS).contains(&poster.columns)
+        || !(1..=MAX_POSTER_SHEETS).contains(&poster.rows)
+    {
+        return Err(FieldError::new(
+            "poster",
+            ErrorCode::ValueOutOfRange,
+            format!("Posters can be from 1 to {MAX_POSTER_SHEETS} sheets each way"),
+        ));
+    }
+    // A poster of one sheet is just the page
+    Ok((poster.sheets() > 1).then_some(poster))
+}
+
+fn parse_envelope(
+    envelope: Option<&str>,
+    media: Option<&Media>,
+    poster: Option<&Poster>,
+) -> Result<Option<Envelope>, FieldError> {
+    let Some(envelope) = envelope.filter(|envelope| !envelope.trim().is_empty()) else {
+        return Ok(None);
+    };
+    let envelope = parse_keyword("envelope", Some(envelope), Envelope::ALL)?;
+    let conflict = match (media, poster) {
+        (Some(_), _) => "media",
+        (_, Some(_)) => "a poster",
+        (None, None) => return Ok(Some(envelope)),
+    };
+    Err(FieldError::new(
+        "envelope",
+        ErrorCode::UnsupportedValue,
+        format!("Envelopes can't be printed with {conflict} as well"),
+    ))
+}
+
+/// Parses a flag that's off unless it's asked for.
+fn parse_flag(field: &'static str, value: Option<&str>) -> Result<bool, FieldError> {
+    match value.map(str::trim) {
+        None | Some("false") => Ok(false),
+        Some("true") => Ok(true),
+        Some(value) => Err(FieldError::new(
+            field,
+            ErrorCode::UnsupportedValue,
+            format!("{value:?} isn't true or false"),
+        )),
+    }
+}
+
+impl PrintQuery {
+    /// Fills in whatever was left out from `preferences`.
+    pub fn or_preferences(self, preferences: &Preferences) -> Self {
+        fn variant<T: Serialize>(value: &T) -> String {
+            serde_variant::to_variant_name(value).unwrap().to_string()
+        }
+        PrintQuery {
+            sides: self
+                .sides
+                .or_else(|| preferences.sides.as_ref().map(variant)),
+            color_mode: self
+                .color_mode
+                .or_else(|| preferences.color_mode.as_ref().map(variant)),
+            cover_sheet: self.cover_sheet.or_else(|| {
+                preferences
+                    .cover_sheet
+                    .map(|cover_sheet| cover_sheet.to_string())
+            }),
+            ..self
+        }
+    }
+
+    /// Checks every option, returning all the ones that are wrong rather than
+    /// just the first.
+    pub fn validate(self) -> Result<PrintOptions, Vec<FieldError>> {
+        let sides = parse_keyword("sides", self.sides.as_deref(), DuplexMode::ALL);
+        let color_mode = parse_keyword("colorMode", self.color_mode.as_deref(), ColorMode::ALL);
+        let pages = parse_pages(self.pages.as_deref());
+        let copies = parse_copies(self.copies.as_deref());
+        let title = parse_title(self.title.as_deref());
+        let cover_sheet = parse_flag("coverSheet", self.cover_sheet.as_deref());
+        let allow_fallback = parse_flag("allowFallback", self.allow_fallback.as_deref());
+        let separate_jobs = parse_flag("separateJobs", self.separate_jobs.as_deref());
+        let tags = parse_tags(self.tags.as_deref());
+        let media = parse_media(self.media.as_deref());
+        let margins = parse_margins(
+            self.margins.as_deref(),
+            media.as_ref().ok().and_then(Option::as_ref),
+        );
+        let offset = parse_offset(self.offset.as_deref());
+        let poster = parse_poster(self.poster.as_deref());
+        let envelope = parse_envelope(
+            self.envelope.as_deref(),
+            media.as_ref().ok().and_then(Option::as_ref),
+            poster.as_ref().ok().and_then(Option::as_ref),
+        );
+
+        match (
+            sides,
+            color_mode,
+            pages,
+            copies,
+            title,
+            cover_sheet,
+            allow_fallback,
+            separate_jobs,
+            tags,
+            media,
+            margins,
+            offset,
+            poster,
+            envelope,
+        ) {
+            (
+                Ok(sides),
+                Ok(color_mode),
+                Ok(pages),
+                Ok(copies),
+                Ok(title),
+                Ok(cover_sheet),
+                Ok(allow_fallback),
+                Ok(separate_jobs),
+                Ok(tags),
+                Ok(media),
+                Ok(margins),
+                Ok(offset),
+                Ok(poster),
+                Ok(envelope),
+            ) => Ok(PrintOptions {
+                sides,
+                color_mode,
+                pages,
+                copies,
+                title,
+                cover_sheet,
+                allow_fallback,
+                separate_jobs,
+                tags,
+                media,
+                margins,
+                offset,
+                poster,
+                envelope,
+            }),
+            (
+                sides,
+                color_mode,
+                pages,
+                copies,
+                title,
+                cover_sheet,
+                allow_fallback,
+                separate_jobs,
+                tags,
+                media,
+                margins,
+                offset,
+                poster,
+                envelope,
+            ) => Err([
+                sides.err(),
+                color_mode.err(),
+                pages.err(),
+                copies.err(),
+                title.err(),
+                cover_sheet.err(),
+                allow_fallback.err(),
+                separate_jobs.err(),
+                tags.err(),
+                media.err(),
+                margins.err(),
+                offset.err(),
+                poster.err(),
+                envelope.err(),
+            ]
+            .into_iter()
+            .flatten()
+            .collect()),
+        }
+    }
+}
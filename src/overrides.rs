@@ -0,0 +1,134 @@
+//! Options some printers force on every job, whatever was asked for, like
+//! keeping the expensive printer in grayscale.
+//!
+//! Set with `KPRINT_PRINTER_OVERRIDES`, a space-separated list like
+//! `plotter:colorMode=grayscale lounge:sides=two-sided-long-edge:unless-admin`.
+//! `sides`, `colorMode` and `coverSheet` can be forced, and `unless-admin`
+//! lets kprint admins ask for something else. Jobs that already ask for
+//! something close enough are left alone, so forcing two-sided printing
+//! doesn't flip a short-edge job to the long edge, and forcing grayscale
+//! doesn't turn monochrome into grayscale.
+
+use kprint_client::models::{AppliedOverride, ColorMode, DuplexMode, PrintOptions};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Forced {
+    Sides(DuplexMode),
+    ColorMode(ColorMode),
+    CoverSheet(bool),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Override {
+    forced: Forced,
+    /// Whether admins get what they asked for anyway
+    unless_admin: bool,
+}
+
+#[derive(Default)]
+pub struct Overrides(HashMap<String, Vec<Override>>);
+
+fn keyword<T: DeserializeOwned>(option: &str, value: &str) -> anyhow::Result<T> {
+    T::deserialize(serde::de::value::StrDeserializer::<serde::de::value::Error>::new(value))
+        .map_err(|_| anyhow::anyhow!("{value:?} isn't something {option} can be forced to"))
+}
+
+fn variant<T: Serialize>(value: &T) -> String {
+    serde_variant::to_variant_name(value).unwrap().to_string()
+}
+
+impl Overrides {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let Ok(overrides) = std::env::var("KPRINT_PRINTER_OVERRIDES") else {
+            return Ok(Overrides::default());
+        };
+        let mut printers = HashMap::<String, Vec<Override>>::new();
+        for entry in overrides.split_whitespace() {
+            let mut parts = entry.split(':');
+            let (Some(printer), Some(setting)) = (parts.next(), parts.next()) else {
+                anyhow::bail!("{entry:?} should look like printer:option=value");
+            };
+            let unless_admin = match parts.next() {
+                None => false,
+                Some("unless-admin") => true,
+                Some(other) => anyhow::bail!("{other:?} isn't unless-admin, in {entry:?}"),
+            };
+            let Some((option, value)) = setting.split_once('=') else {
+                anyhow::bail!("{setting:?} should look like option=value, in {entry:?}");
+            };
+            let forced = match option {
+                "sides" => Forced::Sides(keyword(option, value)?),
+                "colorMode" => Forced::ColorMode(keyword(option, value)?),
+                "coverSheet" => Forced::CoverSheet(value.parse()?),
+                _ => {
+                    anyhow::bail!("{option:?} can't be forced, try sides, colorMode or coverSheet")
+                }
+            };
+            printers
+                .entry(printer.to_string())
+                .or_default()
+                .push(Override {
+                    forced,
+                    unless_admin,
+                });
+        }
+        Ok(Overrides(printers))
+    }
+
+    /// Forces whatever `printer` forces onto `options`, saying what changed.
+    pub fn apply(
+        &self,
+        printer: &str,
+        options: &mut PrintOptions,
+        admin: bool,
+    ) -> Vec<AppliedOverride> {
+        let mut applied = vec![];
+        let overrides = self.0.get(printer).map(Vec::as_slice).unwrap_or_default();
+        for Override {
+            forced,
+            unless_admin,
+        } in overrides
+        {
+            if admin && *unless_admin {
+                continue;
+            }
+            match *forced {
+                Forced::Sides(sides) => {
+                    let two_sided = |sides| sides != DuplexMode::OneSided;
+                    if two_sided(sides) != two_sided(options.sides) {
+                        applied.push(AppliedOverride {
+                            option: "sides".to_string(),
+                            requested: variant(&options.sides),
+                            applied: variant(&sides),
+                        });
+                        options.sides = sides;
+                    }
+                }
+                Forced::ColorMode(color_mode) => {
+                    let color = |mode| mode == ColorMode::Color;
+                    if color(color_mode) != color(options.color_mode) {
+                        applied.push(AppliedOverride {
+                            option: "colorMode".to_string(),
+                            requested: variant(&options.color_mode),
+                            applied: variant(&color_mode),
+                        });
+                        options.color_mode = color_mode;
+                    }
+                }
+                Forced::CoverSheet(cover_sheet) => {
+                    if cover_sheet != options.cover_sheet {
+                        applied.push(AppliedOverride {
+                            option: "coverSheet".to_string(),
+                            requested: options.cover_sheet.to_string(),
+                            applied: cover_sheet.to_string(),
+                        });
+                        options.cover_sheet = cover_sheet;
+                    }
+                }
+            }
+        }
+        applied
+    }
+}
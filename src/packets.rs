@@ -0,0 +1,114 @@
+//! Jobs made of several documents, added one at a time, so a packet of
+//! separate PDFs prints as one job, collated and stapled together.
+//!
+//! Opening a job makes it on the printer straight away with Create-Job, with
+//! the print options it's opened with. Each document added is sent on to it
+//! with Send-Document as it arrives, and closing the job tells the printer
+//! that was the last one, so it can start printing. A job that's left open
+//! for more than 15 minutes without a document being added is canceled,
+//! since CUPS stops waiting for the rest of it about then anyway.
+
+use crate::error::KprintError;
+use chrono::{DateTime, Duration, Utc};
+use kprint_client::models::PrintOptions;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// How long a job can sit open without a document being added, matching
+/// CUPS's default `MultipleOperationTimeout`
+const OPEN_LIFETIME: Duration = Duration::minutes(15);
+
+#[derive(Debug, Clone)]
+pub struct Packet {
+    /// kprint's id for the job, whose record has the rest
+    pub id: Uuid,
+    /// The id the printer gave the job when it was made
+    pub ipp_job_id: i32,
+    pub options: PrintOptions,
+    /// Documents added so far
+    pub documents: u32,
+    /// Quota units the documents added so far come to, taken out of the
+    /// owner's quota once the job's closed
+    pub quota_consumed: Option<u32>,
+    pub expires_at: DateTime<Utc>,
+    /// Held while a document's being sent, so documents added at once go
+    /// one at a time
+    sending: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Packet {
+    pub fn new(id: Uuid, ipp_job_id: i32, options: PrintOptions) -> Self {
+        Packet {
+            id,
+            ipp_job_id,
+            options,
+            documents: 0,
+            quota_consumed: None,
+            expires_at: Utc::now() + OPEN_LIFETIME,
+            sending: Arc::default(),
+        }
+    }
+}
+
+/// Jobs that are open for documents, by kprint's id for them.
+#[derive(Default)]
+pub struct PacketStore {
+    packets: Mutex<HashMap<Uuid, Packet>>,
+}
+
+impl PacketStore {
+    pub fn open(&self, packet: Packet) {
+        self.packets.lock().unwrap().insert(packet.id, packet);
+    }
+
+    /// Takes out every job that's been left open too long, to be canceled.
+    pub fn expired(&self) -> Vec<Packet> {
+        let now = Utc::now();
+        let mut packets = self.packets.lock().unwrap();
+        let expired = packets
+            .values()
+            .filter(|packet| packet.expires_at < now)
+            .map(|packet| packet.id)
+            .collect::<Vec<_>>();
+        expired.iter().filter_map(|id| packets.remove(id)).collect()
+    }
+
+    /// Waits for any document already being added to job `id` to finish,
+    /// then hands back the job as it is now, along with a guard that keeps
+    /// anything else from being added until it's dropped. It's
+    /// [`KprintError::JobNotOpen`] for jobs that aren't open, or have closed
+    /// while waiting.
+    pub async fn lock(
+        &self,
+        id: Uuid,
+    ) -> Result<(Packet, tokio::sync::OwnedMutexGuard<()>), KprintError> {
+        let sending = self.get(id)?.sending;
+        let guard = sending.lock_owned().await;
+        Ok((self.get(id)?, guard))
+    }
+
+    fn get(&self, id: Uuid) -> Result<Packet, KprintError> {
+        match self.packets.lock().unwrap().get(&id) {
+            Some(packet) if packet.expires_at > Utc::now() => Ok(packet.clone()),
+            _ => Err(KprintError::JobNotOpen(id)),
+        }
+    }
+
+    /// Notes another document was added to job `id`, coming to `units` more
+    /// of its owner's quota, if they could be counted.
+    pub fn added(&self, id: Uuid, units: Option<u32>) {
+        if let Some(packet) = self.packets.lock().unwrap().get_mut(&id) {
+            packet.documents += 1;
+            if let Some(units) = units {
+                packet.quota_consumed = Some(packet.quota_consumed.unwrap_or_default() + units);
+            }
+            packet.expires_at = Utc::now() + OPEN_LIFETIME;
+        }
+    }
+
+    /// Takes job `id` out of the store, once it's been closed or canceled.
+    pub fn remove(&self, id: &Uuid) -> Option<Packet> {
+        self.packets.lock().unwrap().remove(id)
+    }
+}
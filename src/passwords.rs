@@ -0,0 +1,54 @@
+//! Per-user passwords for the inbound IPP endpoint, since operating systems'
+//! print dialogs can only do HTTP Basic auth, not SSO.
+//!
+//! Only hashes are kept, and when `KPRINT_PRINT_PASSWORDS_FILE` is set
+//! they're kept there as JSON, so the printers members have added keep
+//! working across restarts.
+
+use crate::json_file::JsonFile;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// The passwords are long and random, so a plain SHA-256 is plenty; there's
+/// nothing to brute force that's easier than guessing.
+#[derive(Default)]
+pub struct PrintPasswords {
+    /// SHA-256 of each member's password, in base64
+    hashes: JsonFile<HashMap<String, String>>,
+}
+
+fn hash(password: &str) -> String {
+    base64::engine::general_purpose::STANDARD.encode(Sha256::digest(password.as_bytes()))
+}
+
+impl PrintPasswords {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(PrintPasswords {
+            hashes: JsonFile::from_env("KPRINT_PRINT_PASSWORDS_FILE")?,
+        })
+    }
+
+    /// Makes a new password for `username`, replacing any old one.
+    pub async fn generate(&self, username: &str) -> anyhow::Result<String> {
+        let password = Uuid::new_v4().simple().to_string();
+        self.hashes
+            .change(|hashes| hashes.insert(username.to_string(), hash(&password)))
+            .await?;
+        Ok(password)
+    }
+
+    pub async fn revoke(&self, username: &str) -> anyhow::Result<()> {
+        self.hashes
+            .change(|hashes| {
+                hashes.remove(username);
+            })
+            .await
+    }
+
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        self.hashes
+            .read(|hashes| hashes.get(username) == Some(&hash(password)))
+    }
+}
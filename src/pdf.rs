@@ -0,0 +1,146 @@
+//! The bits of PDF surgery kprint does before documents reach a printer.
+
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId};
+
+/// Page size for pages that somehow don't have one: US Letter
+const DEFAULT_MEDIA_BOX: [f32; 4] = [0.0, 0.0, 612.0, 792.0];
+
+/// Parses a PDF that's about to be changed.
+pub fn load(pdf: &[u8]) -> anyhow::Result<Document> {
+    let document = Document::load_mem(pdf)?;
+    if document.is_encrypted() {
+        anyhow::bail!("the PDF is encrypted");
+    }
+    Ok(document)
+}
+
+/// How many pages `pdf` has. Unlike changing it, counting works on encrypted
+/// PDFs too.
+pub fn page_count(pdf: &[u8]) -> anyhow::Result<u32> {
+    Ok(Document::load_mem(pdf)?.get_pages().len() as u32)
+}
+
+/// Adds Helvetica to `document`, which every PDF reader has built in.
+pub fn helvetica(document: &mut Document) -> ObjectId {
+    document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    })
+}
+
+/// Looks up a page attribute, which can be inherited from anywhere up the
+/// page tree.
+pub fn inherited<'a>(document: &'a Document, page_id: ObjectId, key: &[u8]) -> Option<&'a Object> {
+    let mut node = document.get_dictionary(page_id).ok()?;
+    // Bounded, in case of a page tree that loops back on itself
+    for _ in 0..32 {
+        if let Ok(value) = node.get(key) {
+            return Some(value);
+        }
+        let parent = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = document.get_dictionary(parent).ok()?;
+    }
+    None
+}
+
+/// The page's size, as `[x0, y0, x1, y1]` with the corners in order.
+pub fn media_box(document: &Document, page_id: ObjectId) -> [f32; 4] {
+    let media_box = inherited(document, page_id, b"MediaBox")
+        .and_then(|media_box| document.dereference(media_box).ok())
+        .and_then(|(_, media_box)| media_box.as_array().ok())
+        .map(|corners| {
+            corners
+                .iter()
+                .filter_map(|corner| corner.as_float().ok())
+                .collect::<Vec<_>>()
+        });
+    match media_box.as_deref() {
+        Some(&[x0, y0, x1, y1]) => [x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)],
+        _ => DEFAULT_MEDIA_BOX,
+    }
+}
+
+/// The page's own resource dictionary, wherever it's kept.
+fn resources_mut(document: &mut Document, page_id: ObjectId) -> lopdf::Result<&mut Dictionary> {
+    match document.get_dictionary(page_id)?.get(b"Resources")? {
+        Object::Reference(id) => {
+            let id = *id;
+            document.get_dictionary_mut(id)
+        }
+        _ => document
+            .get_dictionary_mut(page_id)?
+            .get_mut(b"Resources")?
+            .as_dict_mut(),
+    }
+}
+
+/// Adds `id` to the page's resources under `category`, as `name`.
+pub fn add_resource(
+    document: &mut Document,
+    page_id: ObjectId,
+    category: &str,
+    name: &str,
+    id: ObjectId,
+) -> lopdf::Result<()> {
+    if !document.get_dictionary(page_id)?.has(b"Resources") {
+        // Pages without their own resources use their parent's, which have
+        // to be kept, so the stamp's go alongside them
+        let resources = inherited(document, page_id, b"Resources")
+            .cloned()
+            .unwrap_or_else(|| Dictionary::new().into());
+        document
+            .get_dictionary_mut(page_id)?
+            .set("Resources", resources);
+    }
+    let resources = resources_mut(document, page_id)?;
+    let entries = match resources.get(category.as_bytes()) {
+        Ok(Object::Reference(entries)) => Some(*entries),
+        Ok(_) => None,
+        Err(_) => {
+            resources.set(category, Dictionary::new());
+            None
+        }
+    };
+    let entries = match entries {
+        Some(entries) => document.get_dictionary_mut(entries)?,
+        None => resources_mut(document, page_id)?
+            .get_mut(category.as_bytes())?
+            .as_dict_mut()?,
+    };
+    entries.set(name, Object::Reference(id));
+    Ok(())
+}
+
+/// Escapes the characters that mean something inside a PDF string.
+pub fn string(text: &str) -> String {
+    text.chars()
+        .filter(char::is_ascii)
+        .flat_map(|c| match c {
+            '(' | ')' | '\\' => vec!['\\', c],
+            c => vec![c],
+        })
+        .collect()
+}
+
+/// Writes out a changed PDF.
+pub fn save(mut document: Document) -> anyhow::Result<Vec<u8>> {
+    // It's written back out with a plain xref table, which the leftovers of
+    // an xref stream or earlier revisions in the trailer would contradict
+    for key in [
+        "Prev",
+        "XRefStm",
+        "Type",
+        "W",
+        "Index",
+        "Filter",
+        "DecodeParms",
+        "Length",
+    ] {
+        document.trailer.remove(key.as_bytes());
+    }
+    let mut pdf = vec![];
+    document.save_to(&mut pdf)?;
+    Ok(pdf)
+}
@@ -0,0 +1,84 @@
+//! Pickup links, for printers with a release station next to them.
+//!
+//! Jobs sent to any of `KPRINT_PICKUP_PRINTERS` are held by the printer
+//! until they're released, and the reply to printing one carries a link to
+//! it signed with `KPRINT_PICKUP_SECRET`. The member shows the link's QR code
+//! at the station, which opens and releases exactly that job with it, without
+//! logging in or looking anyone up. Links last `KPRINT_PICKUP_TTL` seconds.
+
+use crate::error::KprintError;
+use crate::signing::Signer;
+use chrono::{DateTime, Duration, Utc};
+use kprint_client::models::PickupLink;
+use uuid::Uuid;
+
+const DEFAULT_TTL: Duration = Duration::minutes(30);
+/// Tokens open a job at the station and release it, and nothing else
+const ACTION: &str = "release";
+
+pub struct Pickup {
+    signer: Signer,
+    ttl: Duration,
+    printers: Vec<String>,
+}
+
+impl Pickup {
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Some(signer) = Signer::from_env("KPRINT_PICKUP_SECRET", b"pickup")? else {
+            return Ok(None);
+        };
+        let ttl = match std::env::var("KPRINT_PICKUP_TTL") {
+            Ok(seconds) => Duration::seconds(seconds.parse()?),
+            Err(_) => DEFAULT_TTL,
+        };
+        let printers = std::env::var("KPRINT_PICKUP_PRINTERS")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+        Ok(Some(Pickup {
+            signer,
+            ttl,
+            printers,
+        }))
+    }
+
+    /// Whether `printer` holds its jobs until they're picked up.
+    pub fn holds(&self, printer: &str) -> bool {
+        self.printers.iter().any(|held| held == printer)
+    }
+
+    /// A link to pick up `job` with, good for the next `KPRINT_PICKUP_TTL`.
+    pub fn link(&self, job: Uuid) -> PickupLink {
+        let expires_at = Utc::now() + self.ttl;
+        let timestamp = expires_at.timestamp();
+        let signature = self.signer.sign(job, ACTION, timestamp);
+        let token = format!("{}.{timestamp}.{signature}", job.simple());
+        PickupLink {
+            url: format!("/api/v1/pickup/{token}"),
+            token,
+            expires_at: DateTime::from_timestamp(timestamp, 0).unwrap_or(expires_at),
+        }
+    }
+
+    /// The job `token` picks up, as long as kprint signed it and it hasn't
+    /// expired.
+    pub fn verify(&self, token: &str) -> Result<Uuid, KprintError> {
+        let mut parts = token.split('.');
+        let (Some(job), Some(timestamp), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(KprintError::PickupLinkInvalid);
+        };
+        let job = Uuid::try_parse(job).map_err(|_| KprintError::PickupLinkInvalid)?;
+        let timestamp = timestamp
+            .parse::<i64>()
+            .map_err(|_| KprintError::PickupLinkInvalid)?;
+        if !self.signer.verify(job, ACTION, timestamp, signature)
+            || timestamp <= Utc::now().timestamp()
+        {
+            return Err(KprintError::PickupLinkInvalid);
+        }
+        Ok(job)
+    }
+}
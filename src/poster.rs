@@ -0,0 +1,114 @@
+//! Posters, with each page of a PDF blown up across a grid of sheets the
+//! same size, so banners can come off the letter-size laser when the plotter
+//! is down.
+//!
+//! Every sheet has a quarter inch left around its piece of the page, with
+//! crop marks at the corners to trim it to so the pieces butt together. The
+//! page is made as big as the grid can hold and centred in it, and its
+//! sheets come out a row at a time from the top left. Pages are taken as
+//! they're stored, so ones meant to be shown rotated come out sideways.
+
+use crate::pdf;
+use kprint_client::models::Poster;
+use lopdf::{dictionary, Object, Stream};
+
+/// What the page is called on each of its sheets, picked so it won't clash
+/// with anything else
+const FORM: &str = "KprintPoster";
+/// What's left around each sheet's piece of the page for the crop marks,
+/// in points
+const GUTTER: f32 = 18.0;
+/// How far the crop marks stop short of the piece they mark, in points
+const MARK_GAP: f32 = 3.0;
+
+/// Crop marks pointing out from the corners of a `width` by `height` piece
+/// of the page, the gutter in from the sheet's corner.
+fn crop_marks(width: f32, height: f32) -> String {
+    let mut marks = String::new();
+    for (x, out_x) in [(GUTTER, -1.0), (GUTTER + width, 1.0)] {
+        for (y, out_y) in [(GUTTER, -1.0), (GUTTER + height, 1.0)] {
+            let (near, far) = (MARK_GAP, GUTTER - MARK_GAP);
+            marks += &format!(
+                "{} {y} m {} {y} l {x} {} m {x} {} l ",
+                x + out_x * near,
+                x + out_x * far,
+                y + out_y * near,
+                y + out_y * far,
+            );
+        }
+    }
+    marks
+}
+
+/// Replaces every page of `pdf` with the sheets of its poster.
+pub fn tile(pdf: &[u8], poster: Poster) -> anyhow::Result<Vec<u8>> {
+    let mut document = pdf::load(pdf)?;
+    let pages_id = document.catalog()?.get(b"Pages")?.as_reference()?;
+    let (columns, rows) = (poster.columns as f32, poster.rows as f32);
+
+    let mut sheets = vec![];
+    for page_id in document.get_pages().into_values() {
+        let media_box = pdf::media_box(&document, page_id);
+        let [x0, y0, x1, y1] = media_box;
+        let (width, height) = (x1 - x0, y1 - y0);
+        // Each sheet's piece of the page
+        let (piece_width, piece_height) = (width - 2.0 * GUTTER, height - 2.0 * GUTTER);
+        if piece_width <= 0.0 || piece_height <= 0.0 {
+            anyhow::bail!("a {width} by {height} point page is too small to make a poster of");
+        }
+
+        let resources = pdf::inherited(&document, page_id, b"Resources")
+            .cloned()
+            .unwrap_or_else(|| dictionary! {}.into());
+        let form = Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Form",
+                "BBox" => media_box.map(Object::Real).to_vec(),
+                "Resources" => resources,
+            },
+            document.get_page_content(page_id)?,
+        );
+        let form = document.add_object(form);
+
+        let scale = (columns * piece_width / width).min(rows * piece_height / height);
+        // Where the page starts in the grid, from its bottom left
+        let left = (columns * piece_width - scale * width) / 2.0;
+        let bottom = (rows * piece_height - scale * height) / 2.0;
+        for row in 0..poster.rows {
+            for column in 0..poster.columns {
+                let x = GUTTER + left - column as f32 * piece_width - scale * x0;
+                let y =
+                    GUTTER + bottom - (poster.rows - 1 - row) as f32 * piece_height - scale * y0;
+                let content = format!(
+                    "q {GUTTER} {GUTTER} {piece_width} {piece_height} re W n \
+                     {scale} 0 0 {scale} {x} {y} cm /{FORM} Do Q \
+                     q 0.5 w 0 G {}S Q",
+                    crop_marks(piece_width, piece_height)
+                );
+                let content =
+                    document.add_object(Stream::new(dictionary! {}, content.into_bytes()));
+                sheets.push(document.add_object(dictionary! {
+                    "Type" => "Page",
+                    "Parent" => pages_id,
+                    "MediaBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+                    "Resources" => dictionary! {
+                        "XObject" => dictionary! { FORM => form },
+                    },
+                    "Contents" => content,
+                }));
+            }
+        }
+    }
+
+    let pages = document.get_dictionary_mut(pages_id)?;
+    pages.set("Count", sheets.len() as i64);
+    pages.set(
+        "Kids",
+        sheets
+            .into_iter()
+            .map(Object::Reference)
+            .collect::<Vec<_>>(),
+    );
+    pdf::save(document)
+}
@@ -0,0 +1,41 @@
+//! The options each member wants when they leave them out of a print request.
+//!
+//! When `KPRINT_PREFERENCES_FILE` is set they're kept there as JSON, so they
+//! survive restarts.
+
+use crate::json_file::JsonFile;
+use kprint_client::models::Preferences;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct PreferenceStore {
+    preferences: JsonFile<HashMap<String, Preferences>>,
+}
+
+impl PreferenceStore {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(PreferenceStore {
+            preferences: JsonFile::from_env("KPRINT_PREFERENCES_FILE")?,
+        })
+    }
+
+    /// `username`'s preferences, which are all empty until they set some.
+    pub fn get(&self, username: &str) -> Preferences {
+        self.preferences
+            .read(|all| all.get(username).cloned())
+            .unwrap_or_default()
+    }
+
+    /// Replaces all of `username`'s preferences.
+    pub async fn set(&self, username: &str, preferences: Preferences) -> anyhow::Result<()> {
+        self.preferences
+            .change(|all| {
+                if preferences == Preferences::default() {
+                    all.remove(username);
+                } else {
+                    all.insert(username.to_string(), preferences);
+                }
+            })
+            .await
+    }
+}
@@ -0,0 +1,98 @@
+//! Thumbnails of the first page of PDF jobs, so people can check they printed
+//! what they meant to.
+//!
+//! Turned on by pointing `KPRINT_PDFTOPPM` at poppler's `pdftoppm`, which
+//! does the rendering. Previews are rendered in the background once the job
+//! has gone to the printer, so they never hold up printing, from the spooled
+//! or buffered copy of the document. Documents streamed straight through to
+//! the printer aren't kept, so they don't get one.
+
+use crate::document::Document;
+use actix_web::web::Bytes;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// How big previews are along their longest side, in pixels
+const PREVIEW_SIZE: u32 = 320;
+
+#[derive(Clone)]
+pub struct Previews {
+    pdftoppm: PathBuf,
+    spool: PathBuf,
+    previews: Arc<Mutex<HashMap<Uuid, Bytes>>>,
+}
+
+impl Previews {
+    /// The previews `KPRINT_PDFTOPPM` renders, or `None` if previews are
+    /// turned off.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(pdftoppm) = std::env::var("KPRINT_PDFTOPPM") else {
+            return Ok(None);
+        };
+        Ok(Some(Previews {
+            pdftoppm: pdftoppm.into(),
+            spool: crate::spool::spool_dir()?,
+            previews: Arc::default(),
+        }))
+    }
+
+    pub fn get(&self, job: &Uuid) -> Option<Bytes> {
+        self.previews.lock().unwrap().get(job).cloned()
+    }
+
+    /// Renders the first page of `pdf` as a PNG.
+    async fn render(&self, pdf: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let input = self.spool.join(format!("preview-{}.pdf", Uuid::new_v4()));
+        // pdftoppm adds the extension itself
+        let output = input.with_extension("");
+        tokio::fs::write(&input, pdf).await?;
+        let rendered = tokio::process::Command::new(&self.pdftoppm)
+            .args(["-png", "-singlefile", "-f", "1", "-l", "1", "-scale-to"])
+            .arg(PREVIEW_SIZE.to_string())
+            .arg(&input)
+            .arg(&output)
+            .kill_on_drop(true)
+            .output()
+            .await;
+        let _ = tokio::fs::remove_file(&input).await;
+        let rendered = rendered?;
+        if !rendered.status.success() {
+            anyhow::bail!(
+                "pdftoppm failed: {}",
+                String::from_utf8_lossy(&rendered.stderr).trim()
+            );
+        }
+        let png = output.with_extension("png");
+        let preview = tokio::fs::read(&png).await;
+        let _ = tokio::fs::remove_file(&png).await;
+        Ok(preview?)
+    }
+
+    /// Starts rendering a preview of `job` from `document`, if it's a PDF,
+    /// which will show up once it's done.
+    pub fn render_later(&self, job: Uuid, document: Document) {
+        let previews = self.clone();
+        tokio::spawn(async move {
+            let pdf = match document.into_bytes().await {
+                Ok(pdf) if pdf.starts_with(b"%PDF-") => pdf,
+                Ok(_) => return,
+                Err(err) => {
+                    log::warn!("Couldn't read job {job} back for a preview: {err}");
+                    return;
+                }
+            };
+            match previews.render(&pdf).await {
+                Ok(preview) => {
+                    previews
+                        .previews
+                        .lock()
+                        .unwrap()
+                        .insert(job, preview.into());
+                }
+                Err(err) => log::warn!("Couldn't render a preview of job {job}: {err}"),
+            }
+        });
+    }
+}
@@ -0,0 +1,149 @@
+//! What each printer last said about itself, so status checks don't cost a
+//! round trip to CUPS every time.
+//!
+//! Every printer is asked again each `KPRINT_PRINTER_CACHE_TTL` seconds in the
+//! background. If that falls behind, a stale answer is still handed out while
+//! a fresh one is fetched, up until it's `KPRINT_PRINTER_CACHE_MAX_STALE`
+//! seconds old, after which callers wait for the printer themselves.
+
+use crate::error::KprintError;
+use crate::ipp_client::IppClient;
+use ipp::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const DEFAULT_TTL: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_STALE: Duration = Duration::from_secs(300);
+
+struct Entry {
+    attributes: Arc<IppAttributes>,
+    fetched_at: Instant,
+    /// Whether a background refresh is already on its way
+    refreshing: bool,
+}
+
+#[derive(Clone)]
+pub struct PrinterCache {
+    ttl: Duration,
+    max_stale: Duration,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+fn seconds(name: &str, default: Duration) -> anyhow::Result<Duration> {
+    match std::env::var(name) {
+        Ok(seconds) => Ok(Duration::from_secs(seconds.parse()?)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Asks `printer` for all of its attributes.
+async fn fetch(printer: &IppClient) -> Result<IppAttributes, KprintError> {
+    let operation = IppOperationBuilder::get_printer_attributes(printer.uri().clone())
+        .attribute("all")
+        .build();
+    let response = printer.send(operation).await?;
+    if !response.header().status_code().is_success() {
+        return Err(KprintError::IppProtocol(format!(
+            "Get-Printer-Attributes failed: {:?}",
+            response.header().status_code()
+        )));
+    }
+    Ok(response.attributes().clone())
+}
+
+impl PrinterCache {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(PrinterCache {
+            ttl: seconds("KPRINT_PRINTER_CACHE_TTL", DEFAULT_TTL)?,
+            max_stale: seconds("KPRINT_PRINTER_CACHE_MAX_STALE", DEFAULT_MAX_STALE)?,
+            entries: Arc::default(),
+        })
+    }
+
+    /// `name`'s attributes, from the cache if they're recent enough.
+    pub async fn get(
+        &self,
+        name: &str,
+        printer: &IppClient,
+    ) -> Result<Arc<IppAttributes>, KprintError> {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            if let Some(entry) = entries.get_mut(name) {
+                let age = entry.fetched_at.elapsed();
+                if age < self.ttl {
+                    return Ok(entry.attributes.clone());
+                }
+                if age < self.max_stale {
+                    if !entry.refreshing {
+                        entry.refreshing = true;
+                        self.refresh_later(name.to_string(), printer.clone());
+                    }
+                    return Ok(entry.attributes.clone());
+                }
+            }
+        }
+        self.refresh(name, printer).await
+    }
+
+    /// Asks `printer` for its attributes now, whatever's cached, for when
+    /// something's just changed them.
+    pub async fn refresh(
+        &self,
+        name: &str,
+        printer: &IppClient,
+    ) -> Result<Arc<IppAttributes>, KprintError> {
+        let fetched = fetch(printer).await;
+        let mut entries = self.entries.lock().unwrap();
+        match fetched {
+            Ok(attributes) => {
+                let attributes = Arc::new(attributes);
+                entries.insert(
+                    name.to_string(),
+                    Entry {
+                        attributes: attributes.clone(),
+                        fetched_at: Instant::now(),
+                        refreshing: false,
+                    },
+                );
+                Ok(attributes)
+            }
+            Err(err) => {
+                // Leaves whatever was there to go stale, so the next caller
+                // tries again
+                if let Some(entry) = entries.get_mut(name) {
+                    entry.refreshing = false;
+                }
+                Err(err)
+            }
+        }
+    }
+
+    fn refresh_later(&self, name: String, printer: IppClient) {
+        let cache = self.clone();
+        tokio::spawn(async move {
+            if let Err(err) = cache.refresh(&name, &printer).await {
+                log::warn!("Couldn't refresh what {name} says about itself: {err}");
+            }
+        });
+    }
+
+    /// Keeps every one of `printers` fresh for as long as kprint runs.
+    pub fn refresh_in_background(&self, printers: Vec<(String, IppClient)>) {
+        let cache = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(cache.ttl);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let cache = &cache;
+            loop {
+                interval.tick().await;
+                futures::future::join_all(printers.iter().map(|(name, printer)| async move {
+                    if let Err(err) = cache.refresh(name, printer).await {
+                        log::debug!("Couldn't refresh what {name} says about itself: {err}");
+                    }
+                }))
+                .await;
+            }
+        });
+    }
+}
@@ -0,0 +1,56 @@
+//! Checking every printer can be reached when kprint starts, when
+//! `KPRINT_PROBE_PRINTERS` is set, so a CUPS that's down or a printer that's
+//! been renamed turns up in the logs rather than in someone's failed job.
+//!
+//! Printers that don't answer aren't fatal, since CUPS may well come back,
+//! but their breakers are tripped so jobs for them fail fast until the
+//! cooldown's up and they're given another go.
+
+use crate::app::AppState;
+
+/// Whether kprint's been asked to probe its printers.
+pub fn enabled() -> anyhow::Result<bool> {
+    match std::env::var("KPRINT_PROBE_PRINTERS") {
+        Ok(probe) => Ok(probe.parse()?),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Asks every printer for its attributes, which also fills the printer
+/// cache, and marks those that don't answer as degraded. Gives back the
+/// names of those.
+pub async fn probe(app_data: &AppState) -> Vec<String> {
+    let probes = app_data.printers.iter().map(|(name, printer)| async move {
+        match app_data.printer_cache.refresh(name, printer).await {
+            Ok(_) => None,
+            Err(err) => {
+                log::warn!(
+                    target: "kprint::probe",
+                    "printer={name} uri={} code={:?} error=\"{err}\" Printer didn't answer, marking it degraded",
+                    printer.uri(),
+                    err.code(),
+                );
+                app_data.breakers.trip(name);
+                Some(name.clone())
+            }
+        }
+    });
+    let mut degraded = futures::future::join_all(probes)
+        .await
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    degraded.sort();
+    match degraded.is_empty() {
+        true => log::info!(
+            target: "kprint::probe",
+            "Every one of {} printers answered",
+            app_data.printers.len()
+        ),
+        false => log::warn!(
+            target: "kprint::probe",
+            "degraded={degraded:?} Not every printer answered"
+        ),
+    }
+    degraded
+}
@@ -0,0 +1,137 @@
+//! What's waiting on and printing at each printer, as of a moment ago, so
+//! clients polling their jobs' status don't each cost a round trip to CUPS.
+//!
+//! A printer's queue is asked for with one Get-Jobs, which is kept for
+//! `KPRINT_QUEUE_CACHE_TTL` seconds, 2 by default, or not at all if it's 0.
+//! It's meant to be short, just enough that a page of clients polling at once
+//! see the same answer, which is only fetched once however many ask for it at
+//! the same time. A printer's queue is forgotten as soon as kprint changes it,
+//! and admins can ask for it fresh whenever they need to be sure.
+
+use crate::error::KprintError;
+use crate::ipp_client::IppClient;
+use ipp::operation::IppOperation;
+use ipp::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const DEFAULT_TTL: Duration = Duration::from_secs(2);
+
+struct Entry {
+    jobs: Arc<IppAttributes>,
+    fetched_at: Instant,
+}
+
+#[derive(Default)]
+pub struct QueueCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Entry>>,
+    /// Held while a printer's queue is being fetched, so everyone else who
+    /// wants it waits for that answer rather than asking again
+    fetching: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+/// Asks `printer` for every attribute of each of the jobs it hasn't finished.
+async fn fetch(printer: &IppClient) -> Result<IppAttributes, KprintError> {
+    let mut request = IppOperationBuilder::get_jobs(printer.uri().clone())
+        .build()
+        .into_ipp_request();
+    request.attributes_mut().add(
+        DelimiterTag::OperationAttributes,
+        IppAttribute::new(
+            IppAttribute::REQUESTED_ATTRIBUTES,
+            IppValue::Keyword("all".to_string()),
+        ),
+    );
+    let response = printer.send(request).await?;
+    if !response.header().status_code().is_success() {
+        return Err(KprintError::IppProtocol(format!(
+            "Get-Jobs failed: {:?}",
+            response.header().status_code()
+        )));
+    }
+    Ok(response.attributes().clone())
+}
+
+impl QueueCache {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let ttl = match std::env::var("KPRINT_QUEUE_CACHE_TTL") {
+            Ok(seconds) => Duration::from_secs(seconds.parse()?),
+            Err(_) => DEFAULT_TTL,
+        };
+        Ok(QueueCache {
+            ttl,
+            ..QueueCache::default()
+        })
+    }
+
+    fn cached(&self, name: &str) -> Option<Arc<IppAttributes>> {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(name)
+            .filter(|entry| entry.fetched_at.elapsed() < self.ttl)
+            .map(|entry| entry.jobs.clone())
+    }
+
+    /// The jobs on `name`'s queue, from the cache if they're recent enough.
+    pub async fn get(
+        &self,
+        name: &str,
+        printer: &IppClient,
+    ) -> Result<Arc<IppAttributes>, KprintError> {
+        if let Some(jobs) = self.cached(name) {
+            return Ok(jobs);
+        }
+        let fetching = self
+            .fetching
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone();
+        let _fetching = fetching.lock().await;
+        // Someone else may have fetched it while this waited
+        if let Some(jobs) = self.cached(name) {
+            return Ok(jobs);
+        }
+        self.refresh(name, printer).await
+    }
+
+    /// Asks `printer` for its queue now, whatever's cached.
+    pub async fn refresh(
+        &self,
+        name: &str,
+        printer: &IppClient,
+    ) -> Result<Arc<IppAttributes>, KprintError> {
+        let jobs = Arc::new(fetch(printer).await?);
+        self.entries.lock().unwrap().insert(
+            name.to_string(),
+            Entry {
+                jobs: jobs.clone(),
+                fetched_at: Instant::now(),
+            },
+        );
+        Ok(jobs)
+    }
+
+    /// Drops what's cached of `name`'s queue, once something's changed it.
+    pub fn forget(&self, name: &str) {
+        self.entries.lock().unwrap().remove(name);
+    }
+}
+
+/// Job `ipp_job_id`'s attributes in a Get-Jobs response, by themselves, if
+/// it's there.
+pub fn find_job(jobs: &IppAttributes, ipp_job_id: i32) -> Option<IppAttributes> {
+    let group = jobs.groups_of(DelimiterTag::JobAttributes).find(|group| {
+        matches!(
+            group.attributes().get(IppAttribute::JOB_ID).map(IppAttribute::value),
+            Some(IppValue::Integer(id)) if *id == ipp_job_id
+        )
+    })?;
+    let mut attributes = IppAttributes::new();
+    attributes.groups_mut().push(group.clone());
+    Some(attributes)
+}
@@ -0,0 +1,556 @@
+//! How much members can print each month or semester, depending on which of
+//! their groups they're in.
+//!
+//! Quotas are in units, which are pages unless `KPRINT_QUOTA_COSTS` says
+//! otherwise, like `grayscale=1 color=4 duplex-discount=1` for color pages
+//! costing four times as much, and a unit off for every sheet printed on both
+//! sides.
+//!
+//! Tiers are set with `KPRINT_QUOTA_TIERS`, a space-separated list like
+//! `eboard:eboard=unlimited actives:active,rtp=500 freshmen:intro=200`: the
+//! tier's name, the groups in it, and the units it allows each period.
+//! Members in more than one get the most generous, and members in none get
+//! `KPRINT_QUOTA_DEFAULT_TIER`, or no limit at all if that isn't set.
+//!
+//! Periods are calendar months, unless `KPRINT_QUOTA_PERIOD` is `semester`,
+//! which starts new ones on January 1st and August 1st, or something like
+//! `semester:01-15,08-25` for other days. When one ends, what everyone used
+//! in it is archived, and kept in `KPRINT_QUOTA_ARCHIVE_FILE` as JSON when
+//! that's set.
+//!
+//! Deployments with their own accounting can set `KPRINT_QUOTA_PROVIDER_URL`
+//! instead of tiers, to have [`crate::balances`] ask it what members have
+//! left and tell it what they spend.
+//!
+//! What's been used is added up from the history, so it only survives a
+//! restart when `KPRINT_HISTORY_FILE` is set. Jobs are charged the pages
+//! kprint counts in them before they're sent, which it can for PDFs, until
+//! the printer says how many it actually printed. Those are set aside as the
+//! job's let through, so jobs sent at once can't spend the same units twice.
+//! Jobs that failed aren't charged at all.
+
+use crate::app::AppState;
+use crate::balances::HttpProvider;
+use crate::error::KprintError;
+use crate::history::{self, History};
+use crate::jobs::JobRecord;
+use crate::json_file::JsonFile;
+use crate::logging::Pii;
+use crate::stats::midnight;
+use actix_web::web::Data;
+use chrono::{DateTime, Datelike, Local, Months, NaiveDate, Utc};
+use futures::future::BoxFuture;
+use kprint_client::models::{
+    ColorMode, DuplexMode, HistoryEntry, Quota, QuotaPeriod, QuotaUnits, QuotaUsage,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone)]
+pub struct Tier {
+    pub name: String,
+    /// Units allowed each period, or `None` for as many as they like
+    pub limit: Option<u32>,
+}
+
+/// What printing costs, in quota units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Costs {
+    pub grayscale: u32,
+    pub color: u32,
+    /// Taken off for every sheet printed on both sides
+    pub duplex_discount: u32,
+}
+
+impl Default for Costs {
+    fn default() -> Self {
+        Costs {
+            grayscale: 1,
+            color: 1,
+            duplex_discount: 0,
+        }
+    }
+}
+
+impl Costs {
+    fn from_env() -> anyhow::Result<Self> {
+        let mut costs = Costs::default();
+        let Ok(setting) = std::env::var("KPRINT_QUOTA_COSTS") else {
+            return Ok(costs);
+        };
+        for entry in setting.split_whitespace() {
+            let Some((what, units)) = entry.split_once('=') else {
+                anyhow::bail!("{entry:?} should look like color=4");
+            };
+            let units = units
+                .parse()
+                .map_err(|_| anyhow::anyhow!("{units:?} isn't a number of units, in {entry:?}"))?;
+            match what {
+                "grayscale" => costs.grayscale = units,
+                "color" => costs.color = units,
+                "duplex-discount" => costs.duplex_discount = units,
+                _ => anyhow::bail!(
+                    "{what:?} doesn't have a cost, try grayscale, color or duplex-discount"
+                ),
+            }
+        }
+        Ok(costs)
+    }
+
+    /// What printing `pages` on `sheets` of paper costs.
+    pub fn of(&self, pages: u32, sheets: u32, color_mode: ColorMode) -> QuotaUnits {
+        let mut units = QuotaUnits::default();
+        if color_mode == ColorMode::Color {
+            units.color = pages * self.color;
+        } else {
+            units.grayscale = pages * self.grayscale;
+        }
+        // Every sheet with both sides printed has one page more than sheets
+        // does
+        let both_sides = pages.saturating_sub(sheets);
+        units.duplex_discount =
+            (both_sides * self.duplex_discount).min(units.grayscale + units.color);
+        units.total = units.grayscale + units.color - units.duplex_discount;
+        units
+    }
+
+    /// What `entry` cost its owner, which is nothing if it failed.
+    pub fn of_entry(&self, entry: &HistoryEntry) -> QuotaUnits {
+        if history::failed(entry) {
+            return QuotaUnits::default();
+        }
+        let pages = entry.pages.or(entry.counted_pages).unwrap_or_default();
+        let sheets = match entry.sides {
+            DuplexMode::OneSided => pages,
+            // The printer only says how many pages there were in all, but
+            // each copy starts on a new sheet
+            _ => {
+                let copies = entry.copies.max(1);
+                pages.div_ceil(copies).div_ceil(2) * copies
+            }
+        };
+        self.of(pages, sheets.min(pages), entry.color_mode)
+    }
+}
+
+/// Adds `units` on to `total`.
+pub fn add(total: &mut QuotaUnits, units: QuotaUnits) {
+    total.grayscale += units.grayscale;
+    total.color += units.color;
+    total.duplex_discount += units.duplex_discount;
+    total.total += units.total;
+}
+
+/// How long members get to use their quota before it starts again.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Period {
+    #[default]
+    Monthly,
+    /// Starting on each of these months and days, in order
+    Semester(Vec<(u32, u32)>),
+}
+
+impl Period {
+    fn parse(period: &str) -> anyhow::Result<Self> {
+        let starts = match period.split_once(':') {
+            None if period == "monthly" => return Ok(Period::Monthly),
+            None if period == "semester" => "01-01,08-01",
+            Some(("semester", starts)) => starts,
+            _ => anyhow::bail!("{period:?} isn't monthly or semester"),
+        };
+        let mut starts = starts
+            .split(',')
+            .map(|start| {
+                let day = start
+                    .split_once('-')
+                    .and_then(|(month, day)| Some((month.parse().ok()?, day.parse().ok()?)))
+                    .filter(|&(month, day)| NaiveDate::from_ymd_opt(2024, month, day).is_some());
+                day.ok_or_else(|| anyhow::anyhow!("{start:?} isn't a month and day like 08-25"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        starts.sort();
+        starts.dedup();
+        Ok(Period::Semester(starts))
+    }
+
+    /// The first day of the period `day` is in, and of the one after.
+    fn around(&self, day: NaiveDate) -> (NaiveDate, NaiveDate) {
+        match self {
+            Period::Monthly => {
+                let start = day.with_day(1).unwrap();
+                (start, start + Months::new(1))
+            }
+            Period::Semester(starts) => {
+                // February 29th starts semesters on March 1st in other years
+                let on = |year, &(month, day)| {
+                    NaiveDate::from_ymd_opt(year, month, day)
+                        .unwrap_or_else(|| NaiveDate::from_ymd_opt(year, month + 1, 1).unwrap())
+                };
+                let year = day.year();
+                let candidates = [year - 1, year, year + 1]
+                    .into_iter()
+                    .flat_map(|year| starts.iter().map(move |start| on(year, start)))
+                    .collect::<Vec<_>>();
+                let at = candidates.partition_point(|start| *start <= day);
+                (candidates[at - 1], candidates[at])
+            }
+        }
+    }
+}
+
+/// Keeps track of what members have left to print with.
+pub trait QuotaProvider: Send + Sync {
+    /// Where `username`, who's in `groups`, stands this period.
+    fn quota<'a>(
+        &'a self,
+        app_data: &'a AppState,
+        username: &'a str,
+        groups: &'a [String],
+    ) -> BoxFuture<'a, anyhow::Result<Quota>>;
+
+    /// Takes `units` out of the quota of whoever sent `job`, now that it's
+    /// been sent to the printer.
+    fn debit<'a>(&'a self, job: &'a JobRecord, units: u32) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Whether kprint keeps track of what's used itself, so its periods need
+    /// archiving.
+    fn keeps_usage(&self) -> bool;
+}
+
+/// Quotas from tiers of groups, with what's used added up from the history.
+#[derive(Default)]
+pub struct Tiers {
+    tiers: Vec<Tier>,
+    /// Which of `tiers` each group is in
+    groups: HashMap<String, Vec<usize>>,
+    default: Option<usize>,
+}
+
+impl Tiers {
+    fn from_env() -> anyhow::Result<Self> {
+        let mut tiers = Tiers::default();
+        let Ok(setting) = std::env::var("KPRINT_QUOTA_TIERS") else {
+            return Ok(tiers);
+        };
+        for entry in setting.split_whitespace() {
+            let Some((name, setting)) = entry.split_once(':') else {
+                anyhow::bail!("{entry:?} should look like tier:group,group=units");
+            };
+            let Some((groups, limit)) = setting.split_once('=') else {
+                anyhow::bail!("{setting:?} should look like group,group=units, in {entry:?}");
+            };
+            let limit = match limit {
+                "unlimited" => None,
+                limit => Some(limit.parse().map_err(|_| {
+                    anyhow::anyhow!("{limit:?} isn't a number of units or unlimited, in {entry:?}")
+                })?),
+            };
+            if tiers.tiers.iter().any(|tier| tier.name == name) {
+                anyhow::bail!("There's more than one {name:?} tier");
+            }
+            let at = tiers.tiers.len();
+            tiers.tiers.push(Tier {
+                name: name.to_string(),
+                limit,
+            });
+            for group in groups.split(',').filter(|group| !group.is_empty()) {
+                tiers.groups.entry(group.to_string()).or_default().push(at);
+            }
+        }
+        if let Ok(default) = std::env::var("KPRINT_QUOTA_DEFAULT_TIER") {
+            let at = tiers.tiers.iter().position(|tier| tier.name == default);
+            tiers.default = Some(at.ok_or_else(|| {
+                anyhow::anyhow!("KPRINT_QUOTA_DEFAULT_TIER is {default:?}, which isn't a tier")
+            })?);
+        }
+        Ok(tiers)
+    }
+
+    /// The tier `groups` put a member in, if any does.
+    pub fn tier(&self, groups: &[String]) -> Option<&Tier> {
+        let tiers = groups
+            .iter()
+            .filter_map(|group| self.groups.get(group))
+            .flatten()
+            .map(|&at| &self.tiers[at])
+            .collect::<Vec<_>>();
+        if tiers.is_empty() {
+            return self.default.map(|at| &self.tiers[at]);
+        }
+        // Unlimited beats any limit
+        tiers
+            .into_iter()
+            .max_by_key(|tier| tier.limit.map_or(u64::MAX, u64::from))
+    }
+}
+
+impl QuotaProvider for Tiers {
+    fn quota<'a>(
+        &'a self,
+        app_data: &'a AppState,
+        username: &'a str,
+        groups: &'a [String],
+    ) -> BoxFuture<'a, anyhow::Result<Quota>> {
+        let quotas = &app_data.quotas;
+        let tier = self.tier(groups);
+        let (period_start, period_end) = quotas.period(Utc::now());
+        let used = app_data
+            .history
+            .owned_by(username, |entry| {
+                (period_start..period_end).contains(&entry.submitted_at)
+            })
+            .iter()
+            .map(|entry| quotas.costs.of_entry(entry).total)
+            .sum::<u32>();
+        let limit = tier.and_then(|tier| tier.limit);
+        Box::pin(futures::future::ok(Quota {
+            tier: tier.map(|tier| tier.name.clone()),
+            limit,
+            used,
+            remaining: limit.map(|limit| limit.saturating_sub(used)),
+            period_start,
+            period_end,
+        }))
+    }
+
+    fn debit<'a>(&'a self, _job: &'a JobRecord, _units: u32) -> BoxFuture<'a, anyhow::Result<()>> {
+        // The job's in the history, which is where what's used comes from
+        Box::pin(futures::future::ok(()))
+    }
+
+    fn keeps_usage(&self) -> bool {
+        !self.tiers.is_empty()
+    }
+}
+
+pub struct Quotas {
+    period: Period,
+    pub costs: Costs,
+    archive: Archive,
+    provider: Box<dyn QuotaProvider>,
+    /// Units set aside for each member's jobs on their way to a printer,
+    /// which their quota doesn't count as used yet
+    held: Mutex<HashMap<String, u32>>,
+    /// Held while a job's units are set aside, so two jobs can't both be let
+    /// through on the same units
+    reserving: tokio::sync::Mutex<()>,
+}
+
+impl Default for Quotas {
+    fn default() -> Self {
+        Quotas {
+            period: Period::default(),
+            costs: Costs::default(),
+            archive: Archive::default(),
+            provider: Box::new(Tiers::default()),
+            held: Mutex::default(),
+            reserving: tokio::sync::Mutex::default(),
+        }
+    }
+}
+
+/// What everyone used in the periods that are over, oldest first.
+#[derive(Default)]
+struct Archive {
+    periods: JsonFile<Vec<QuotaPeriod>>,
+}
+
+impl Archive {
+    fn from_env() -> anyhow::Result<Self> {
+        Ok(Archive {
+            periods: JsonFile::from_env("KPRINT_QUOTA_ARCHIVE_FILE")?,
+        })
+    }
+
+    async fn add(&self, period: QuotaPeriod) -> anyhow::Result<()> {
+        self.periods.change(|periods| periods.push(period)).await
+    }
+}
+
+impl Quotas {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let provider: Box<dyn QuotaProvider> = match HttpProvider::from_env() {
+            Some(provider) => {
+                if std::env::var("KPRINT_QUOTA_TIERS").is_ok() {
+                    anyhow::bail!(
+                        "KPRINT_QUOTA_TIERS doesn't do anything with KPRINT_QUOTA_PROVIDER_URL"
+                    );
+                }
+                Box::new(provider?)
+            }
+            None => Box::new(Tiers::from_env()?),
+        };
+        let period = match std::env::var("KPRINT_QUOTA_PERIOD") {
+            Ok(period) => Period::parse(&period)?,
+            Err(_) => Period::default(),
+        };
+        Ok(Quotas {
+            period,
+            costs: Costs::from_env()?,
+            archive: Archive::from_env()?,
+            provider,
+            held: Mutex::default(),
+            reserving: tokio::sync::Mutex::default(),
+        })
+    }
+
+    /// The period `time` is in.
+    pub fn period(&self, time: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        let (start, end) = self.period.around(time.with_timezone(&Local).date_naive());
+        (midnight(start), midnight(end))
+    }
+
+    /// Where `username`, who's in `groups`, stands this period.
+    pub async fn quota(
+        &self,
+        app_data: &AppState,
+        username: &str,
+        groups: &[String],
+    ) -> Result<Quota, KprintError> {
+        self.provider
+            .quota(app_data, username, groups)
+            .await
+            .map_err(|err| {
+                log::warn!("Couldn't get {}'s quota: {err}", Pii(username));
+                KprintError::QuotaUnavailable
+            })
+    }
+
+    /// What `username` has left once the units held for their other jobs
+    /// are taken off, or `None` if there's no limit. A job that needs more
+    /// than that is turned away, as is one kprint can't count the units of,
+    /// when `needed` is `None`, if there's nothing left at all.
+    pub async fn check(
+        &self,
+        app_data: &AppState,
+        username: &str,
+        groups: &[String],
+        needed: Option<u32>,
+    ) -> Result<Option<u32>, KprintError> {
+        let quota = self.quota(app_data, username, groups).await?;
+        let Some(remaining) = quota.remaining else {
+            return Ok(None);
+        };
+        let held = self.held.lock().unwrap().get(username).copied();
+        let remaining = remaining.saturating_sub(held.unwrap_or_default());
+        if needed.map_or(remaining == 0, |needed| needed > remaining) {
+            return Err(KprintError::QuotaExceeded {
+                remaining,
+                needed,
+                resets_at: quota.period_end,
+            });
+        }
+        Ok(Some(remaining))
+    }
+
+    /// Sets `units` of `username`'s quota aside for a job on its way to a
+    /// printer, turning it away if they don't have that many left. They're
+    /// taken for good by [`Quotas::debit`] once it's there, or given back by
+    /// [`Quotas::refund`] if it never gets there.
+    pub async fn reserve(
+        &self,
+        app_data: &AppState,
+        username: &str,
+        groups: &[String],
+        units: u32,
+    ) -> Result<(), KprintError> {
+        let _reserving = self.reserving.lock().await;
+        self.check(app_data, username, groups, Some(units)).await?;
+        *self
+            .held
+            .lock()
+            .unwrap()
+            .entry(username.to_string())
+            .or_default() += units;
+        Ok(())
+    }
+
+    /// Gives back `units` set aside for one of `username`'s jobs.
+    pub fn refund(&self, username: &str, units: u32) {
+        let mut held = self.held.lock().unwrap();
+        if let Some(left) = held.get_mut(username) {
+            *left = left.saturating_sub(units);
+            if *left == 0 {
+                held.remove(username);
+            }
+        }
+    }
+
+    /// Takes the `units` set aside for `job` out of its owner's quota. It's
+    /// been printed by now, so there's nothing to do if that fails but say
+    /// so.
+    pub async fn debit(&self, job: &JobRecord, units: u32) {
+        if let Err(err) = self.provider.debit(job, units).await {
+            log::error!(
+                "Couldn't take {units} units out of {}'s quota for job {}: {err}",
+                Pii(&job.owner),
+                job.id
+            );
+        }
+        // They're in what the provider says is used now
+        self.refund(&job.owner, units);
+    }
+
+    /// Every period that's over, newest first.
+    pub fn archived(&self) -> Vec<QuotaPeriod> {
+        let mut periods = self.archive.periods.read(Vec::clone);
+        periods.reverse();
+        periods
+    }
+
+    /// Archives what everyone used from `start` to `end`.
+    async fn archive(&self, history: &History, start: DateTime<Utc>, end: DateTime<Utc>) {
+        let mut users = BTreeMap::<String, QuotaUsage>::new();
+        for entry in history.filter(|entry| (start..end).contains(&entry.submitted_at)) {
+            let user = users
+                .entry(entry.owner.clone())
+                .or_insert_with(|| QuotaUsage {
+                    username: entry.owner.clone(),
+                    jobs: 0,
+                    units: 0,
+                });
+            user.jobs += 1;
+            user.units += self.costs.of_entry(&entry).total;
+        }
+        let users = users.into_values().collect::<Vec<_>>();
+        log::info!(
+            "Quota period {start} to {end} is over, archiving what {} members used",
+            users.len()
+        );
+        if let Err(err) = self.archive.add(QuotaPeriod { start, end, users }).await {
+            log::error!("Couldn't archive quota period {start} to {end}: {err}");
+        }
+    }
+
+    /// Archives each period as it ends, for as long as kprint runs, starting
+    /// with any that ended while it wasn't.
+    pub fn roll_in_background(app_data: Data<AppState>) {
+        let quotas = &app_data.quotas;
+        if !quotas.provider.keeps_usage() {
+            return;
+        }
+        let last = quotas
+            .archive
+            .periods
+            .read(|periods| periods.last().map(|last| last.end));
+        let (mut start, mut end) = quotas.period(Utc::now());
+        tokio::spawn(async move {
+            if let Some(mut missed) = last {
+                while missed < start {
+                    let (_, until) = app_data.quotas.period(missed);
+                    app_data
+                        .quotas
+                        .archive(&app_data.history, missed, until)
+                        .await;
+                    missed = until;
+                }
+            }
+            loop {
+                let wait = (end - Utc::now()).to_std().unwrap_or_default();
+                tokio::time::sleep(wait).await;
+                app_data.quotas.archive(&app_data.history, start, end).await;
+                (start, end) = app_data.quotas.period(end);
+            }
+        });
+    }
+}
@@ -0,0 +1,183 @@
+//! Receipts for jobs, for members printing for a committee who need to show
+//! what they printed to get paid back.
+//!
+//! A receipt says who sent the job, what it was and where it printed, when,
+//! how many pages came out, and what it took out of their quota. It comes as
+//! a page of PDF to hand in, or as JSON for anything filling in a form.
+
+use crate::app::AppState;
+use crate::error::{ErrorCode, FieldError};
+use crate::history;
+use crate::pdf;
+use chrono::{Local, Utc};
+use kprint_client::models::{HistoryEntry, JobReceipt};
+use lopdf::{dictionary, Dictionary, Document, Object, Stream};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+const FONT: &str = "KprintReceiptFont";
+
+/// US Letter, with the receipt in the top corner
+const WIDTH: f32 = 612.0;
+const HEIGHT: f32 = 792.0;
+const MARGIN: f32 = 72.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Pdf,
+    Json,
+}
+
+/// How to hand back a receipt.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ReceiptQuery {
+    /// `pdf` or `json`. Defaults to `pdf`.
+    #[param(value_type = Option<String>, example = "pdf")]
+    pub format: Option<String>,
+}
+
+impl ReceiptQuery {
+    pub fn validate(&self) -> Result<Format, Vec<FieldError>> {
+        match self.format.as_deref().map(str::trim) {
+            None | Some("pdf") => Ok(Format::Pdf),
+            Some("json") => Ok(Format::Json),
+            Some(format) => Err(vec![FieldError::new(
+                "format",
+                ErrorCode::UnsupportedValue,
+                format!("{format:?} isn't pdf or json"),
+            )]),
+        }
+    }
+}
+
+/// The receipt for `entry`'s job, as of now, with what was taken out of its
+/// owner's quota when it was sent. Jobs from before kprint kept that are
+/// worked out at what printing costs now.
+pub fn of(app_data: &AppState, entry: HistoryEntry) -> JobReceipt {
+    let quota_units = entry
+        .quota_units
+        .filter(|_| !history::failed(&entry))
+        .unwrap_or_else(|| app_data.quotas.costs.of_entry(&entry));
+    JobReceipt {
+        id: entry.id,
+        owner: entry.owner,
+        printer: entry.printer,
+        title: entry.title,
+        submitted_at: entry.submitted_at,
+        sides: entry.sides,
+        color_mode: entry.color_mode,
+        copies: entry.copies,
+        pages: entry.pages.or(entry.counted_pages),
+        state: entry.state,
+        failure: entry.failure,
+        tags: entry.tags,
+        quota_units,
+        issued_at: Utc::now(),
+    }
+}
+
+/// What's written on `receipt`, a line at a time, with each line's size.
+pub fn lines(receipt: &JobReceipt) -> Vec<(f32, String)> {
+    let local = |time: chrono::DateTime<Utc>| {
+        time.with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M")
+            .to_string()
+    };
+    let sides = serde_variant::to_variant_name(&receipt.sides).unwrap();
+    let color_mode = serde_variant::to_variant_name(&receipt.color_mode).unwrap();
+    let mut lines = vec![
+        (20.0, "kprint receipt".to_string()),
+        (9.0, format!("Job {}", receipt.id)),
+        (10.0, String::new()),
+        (11.0, format!("Sent by: {}", receipt.owner)),
+        (11.0, format!("Title: {}", receipt.title)),
+        (11.0, format!("Printer: {}", receipt.printer)),
+        (11.0, format!("Submitted: {}", local(receipt.submitted_at))),
+        (11.0, format!("State: {:?}", receipt.state)),
+    ];
+    if let Some(failure) = &receipt.failure {
+        lines.push((11.0, format!("Failed: {failure}")));
+    }
+    lines.push((
+        11.0,
+        format!("Copies: {}, {sides}, {color_mode}", receipt.copies),
+    ));
+    lines.push((
+        11.0,
+        match receipt.pages {
+            Some(pages) => format!("Pages: {pages}"),
+            None => "Pages: not known yet".to_string(),
+        },
+    ));
+    if !receipt.tags.is_empty() {
+        lines.push((11.0, format!("Tags: {}", receipt.tags.join(", "))));
+    }
+    let units = &receipt.quota_units;
+    lines.extend([
+        (10.0, String::new()),
+        (
+            11.0,
+            format!(
+                "Quota: {} grayscale + {} color - {} duplex discount",
+                units.grayscale, units.color, units.duplex_discount
+            ),
+        ),
+        (14.0, format!("Total: {} units", units.total)),
+        (10.0, String::new()),
+        (
+            9.0,
+            format!(
+                "Issued {} by kprint {}",
+                local(receipt.issued_at),
+                env!("CARGO_PKG_VERSION")
+            ),
+        ),
+    ]);
+    lines
+}
+
+fn content(receipt: &JobReceipt) -> String {
+    let mut content = format!("BT 0 g {MARGIN} {} Td ", HEIGHT - MARGIN);
+    for (size, line) in lines(receipt) {
+        content += &format!(
+            "/{FONT} {size} Tf 0 {} Td ({}) Tj ",
+            -size * 1.4,
+            pdf::string(&line)
+        );
+    }
+    content += "ET";
+    content
+}
+
+/// Draws `receipt` on a page.
+pub fn render(receipt: &JobReceipt) -> anyhow::Result<Vec<u8>> {
+    let mut document = Document::with_version("1.5");
+    let pages_id = document.new_object_id();
+    let font = pdf::helvetica(&mut document);
+    let content = document.add_object(Stream::new(
+        Dictionary::new(),
+        content(receipt).into_bytes(),
+    ));
+    let page = document.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => pages_id,
+        "Contents" => content,
+        "Resources" => dictionary! { "Font" => dictionary! { FONT => font } },
+    });
+    document.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page.into()],
+            "Count" => 1,
+            "MediaBox" => vec![0.into(), 0.into(), WIDTH.into(), HEIGHT.into()],
+        }),
+    );
+    let catalog = document.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    document.trailer.set("Root", catalog);
+    pdf::save(document)
+}
@@ -0,0 +1,203 @@
+//! Recording exactly what kprint and CUPS say to each other, for tracking
+//! down printers that choke on some attribute.
+//!
+//! With `KPRINT_IPP_RECORD_DIR` set, every request sent to a printer is
+//! written there along with CUPS's reply, byte for byte, in a JSON file of
+//! its own named after when it was sent, the printer and the operation.
+//! Documents are cut off after `KPRINT_IPP_RECORD_PAYLOAD_BYTES`, 4KiB by
+//! default, since the attributes are what's usually wrong and the documents
+//! are usually someone's. It's a debugging aid, so it's off unless asked for.
+//!
+//! `ipp-replay` reads the recordings back, and sends them again to whatever
+//! printer it's pointed at, so a bug can be reproduced from a desk instead of
+//! standing at the printer.
+
+use chrono::{DateTime, Utc};
+use ipp::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+const DEFAULT_PAYLOAD_BYTES: usize = 4 * 1024;
+
+/// One request and its reply.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Exchange {
+    pub at: DateTime<Utc>,
+    /// Where the request was sent
+    pub uri: String,
+    pub operation: String,
+    /// The request in base64, as far as it was recorded
+    #[serde(with = "base64_bytes")]
+    pub request: Vec<u8>,
+    /// How long the request was in full, document and all
+    pub request_length: u64,
+    /// Whether the document was cut off, so `request` isn't all of it
+    pub truncated: bool,
+    /// CUPS's reply in base64, if there was one
+    #[serde(default, with = "base64_bytes::optional")]
+    pub response: Option<Vec<u8>>,
+    /// What went wrong instead, if there wasn't
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+mod base64_bytes {
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const BASE64: base64::engine::GeneralPurpose = base64::engine::general_purpose::STANDARD;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        BASE64
+            .decode(String::deserialize(deserializer)?)
+            .map_err(serde::de::Error::custom)
+    }
+
+    pub mod optional {
+        use base64::Engine;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            bytes: &Option<Vec<u8>>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match bytes {
+                Some(bytes) => super::serialize(bytes, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Vec<u8>>, D::Error> {
+            Option::<String>::deserialize(deserializer)?
+                .map(|encoded| {
+                    super::BASE64
+                        .decode(encoded)
+                        .map_err(serde::de::Error::custom)
+                })
+                .transpose()
+        }
+    }
+}
+
+/// The name IPP gives `operation`, or its number if it's not one kprint knows.
+pub fn operation_name(operation: u16) -> String {
+    match Operation::from_u16(operation) {
+        Some(operation) => format!("{operation:?}"),
+        None => format!("{operation:#06x}"),
+    }
+}
+
+pub struct Recorder {
+    dir: PathBuf,
+    payload_bytes: usize,
+    /// Numbers each recording, so two sent at the same moment can't share a
+    /// file
+    sent: AtomicU64,
+}
+
+impl Recorder {
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(dir) = std::env::var("KPRINT_IPP_RECORD_DIR") else {
+            return Ok(None);
+        };
+        let payload_bytes = match std::env::var("KPRINT_IPP_RECORD_PAYLOAD_BYTES") {
+            Ok(bytes) => bytes.parse()?,
+            Err(_) => DEFAULT_PAYLOAD_BYTES,
+        };
+        let dir = PathBuf::from(dir);
+        std::fs::create_dir_all(&dir)?;
+        log::warn!(
+            "Recording every request to CUPS in {}, which can include parts of documents",
+            dir.display()
+        );
+        Ok(Some(Recorder {
+            dir,
+            payload_bytes,
+            sent: AtomicU64::new(0),
+        }))
+    }
+
+    /// Starts recording `request` on its way to `uri`. Everything but the
+    /// document is already known, so that's all that's left for
+    /// [`Recording::capture`] to take in.
+    pub fn start(&self, uri: &str, request: &IppRequestResponse) -> Recording {
+        let head = request.to_bytes();
+        Recording {
+            dir: self.dir.clone(),
+            at: Utc::now(),
+            uri: uri.to_string(),
+            operation: operation_name(request.header().operation_or_status),
+            number: self.sent.fetch_add(1, Ordering::Relaxed),
+            limit: head.len() + self.payload_bytes,
+            request: Mutex::new((Vec::with_capacity(head.len()), 0)),
+        }
+    }
+}
+
+/// A request on its way, recorded as it's sent.
+pub struct Recording {
+    dir: PathBuf,
+    at: DateTime<Utc>,
+    uri: String,
+    operation: String,
+    number: u64,
+    /// Most of the request that's kept
+    limit: usize,
+    /// What's kept of the request so far, and how much was sent in all
+    request: Mutex<(Vec<u8>, u64)>,
+}
+
+impl Recording {
+    /// Takes in the next `chunk` of the request as it's sent.
+    pub fn capture(&self, chunk: &[u8]) {
+        let (kept, length) = &mut *self.request.lock().unwrap();
+        let room = self.limit.saturating_sub(kept.len());
+        kept.extend_from_slice(&chunk[..room.min(chunk.len())]);
+        *length += chunk.len() as u64;
+    }
+
+    /// Writes out the exchange, with CUPS's `response` or why there wasn't
+    /// one. Failing to is only logged, since it mustn't fail the job.
+    pub async fn finish(&self, response: Result<&[u8], String>) {
+        let (request, request_length) = std::mem::take(&mut *self.request.lock().unwrap());
+        let (response, error) = match response {
+            Ok(response) => (Some(response.to_vec()), None),
+            Err(err) => (None, Some(err)),
+        };
+        let printer = self.uri.rsplit('/').next().unwrap_or_default().to_string();
+        let exchange = Exchange {
+            at: self.at,
+            uri: self.uri.clone(),
+            truncated: (request.len() as u64) < request_length,
+            operation: self.operation.clone(),
+            request,
+            request_length,
+            response,
+            error,
+        };
+        let file = self.dir.join(format!(
+            "{}-{printer}-{}-{}.json",
+            exchange.at.format("%Y%m%dT%H%M%S%.3fZ"),
+            exchange.operation,
+            self.number
+        ));
+        let written = match serde_json::to_vec_pretty(&exchange) {
+            Ok(json) => tokio::fs::write(&file, json)
+                .await
+                .map_err(anyhow::Error::from),
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = written {
+            log::warn!("Couldn't record a request to {}: {err}", file.display());
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! A summary of how the printers were used, sent to admins every week or
+//! month.
+//!
+//! Turned on by setting `KPRINT_REPORT_SCHEDULE` to `weekly`, which goes out
+//! Monday mornings about the week before, or `monthly`, which goes out on the
+//! first about the month before. It's emailed to the comma-separated
+//! addresses in `KPRINT_REPORT_EMAIL`, through the SMTP server email printing
+//! uses (`KPRINT_EMAIL_SMTP_HOST`, `KPRINT_EMAIL_ADDRESS`,
+//! `KPRINT_EMAIL_USERNAME` and `KPRINT_EMAIL_PASSWORD`), and posted as JSON to
+//! `KPRINT_REPORT_WEBHOOK`, whichever are set.
+
+use crate::app::AppState;
+use crate::history;
+use crate::quota;
+use crate::service;
+use crate::stats::{self, midnight, StatsQuery};
+use actix_web::web::Data;
+use chrono::{DateTime, Datelike, Days, Local, Months, NaiveDate, NaiveTime, Utc};
+use kprint_client::models::{QuotaUnits, SupplyWarning, UsageReport};
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Tokio1Executor,
+};
+use std::fmt::Write;
+
+/// How many of the members who printed the most a report names
+const TOP_USERS: usize = 10;
+/// What time of day, in kprint's time zone, reports go out
+const SEND_AT: NaiveTime = NaiveTime::from_hms_opt(8, 0, 0).unwrap();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    Weekly,
+    Monthly,
+}
+
+impl Schedule {
+    /// The first day of the period `day` is in.
+    fn start_of(self, day: NaiveDate) -> NaiveDate {
+        match self {
+            Schedule::Weekly => day - Days::new(day.weekday().num_days_from_monday().into()),
+            Schedule::Monthly => day.with_day(1).unwrap(),
+        }
+    }
+
+    fn next(self, start: NaiveDate) -> NaiveDate {
+        match self {
+            Schedule::Weekly => start + Days::new(7),
+            Schedule::Monthly => start + Months::new(1),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Schedule::Weekly => "Weekly",
+            Schedule::Monthly => "Monthly",
+        }
+    }
+}
+
+struct Mail {
+    smtp: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+pub struct ReportConfig {
+    schedule: Schedule,
+    mail: Option<Mail>,
+    webhook: Option<reqwest::Url>,
+}
+
+impl ReportConfig {
+    /// Reads how reports go out, or `None` if they're not turned on.
+    pub fn from_env() -> Option<anyhow::Result<Self>> {
+        let schedule = std::env::var("KPRINT_REPORT_SCHEDULE").ok()?;
+        let var = |name: &str| {
+            std::env::var(name).map_err(|_| anyhow::anyhow!("Emailing reports needs {name}"))
+        };
+        Some((|| {
+            let schedule = match schedule.as_str() {
+                "weekly" => Schedule::Weekly,
+                "monthly" => Schedule::Monthly,
+                _ => anyhow::bail!("{schedule:?} isn't weekly or monthly"),
+            };
+            let mail = match std::env::var("KPRINT_REPORT_EMAIL") {
+                Ok(to) => Some(Mail {
+                    smtp: AsyncSmtpTransport::<Tokio1Executor>::relay(&var(
+                        "KPRINT_EMAIL_SMTP_HOST",
+                    )?)?
+                    .credentials(Credentials::new(
+                        var("KPRINT_EMAIL_USERNAME")?,
+                        var("KPRINT_EMAIL_PASSWORD")?,
+                    ))
+                    .build(),
+                    from: var("KPRINT_EMAIL_ADDRESS")?.parse()?,
+                    to: to
+                        .split(',')
+                        .map(|to| to.trim().parse())
+                        .collect::<Result<_, _>>()?,
+                }),
+                Err(_) => None,
+            };
+            let webhook = match std::env::var("KPRINT_REPORT_WEBHOOK") {
+                Ok(url) => Some(url.parse()?),
+                Err(_) => None,
+            };
+            if mail.is_none() && webhook.is_none() {
+                anyhow::bail!("Reports need KPRINT_REPORT_EMAIL or KPRINT_REPORT_WEBHOOK");
+            }
+            Ok(ReportConfig {
+                schedule,
+                mail,
+                webhook,
+            })
+        })())
+    }
+}
+
+/// Whether the printer's `reason` is about running out of something.
+fn supply_warning(reason: &str) -> bool {
+    ["-low", "-empty", "-almost-full", "-full"]
+        .iter()
+        .any(|warning| reason.contains(warning))
+}
+
+/// What happened to the jobs submitted in `query`'s window.
+pub async fn report(app_data: &AppState, query: StatsQuery) -> UsageReport {
+    let tag = query.tag.clone();
+    let usage = stats::usage(&app_data.history, query);
+    let (from, to) = (usage.from, usage.to);
+    let mut printers = app_data.printers.keys().collect::<Vec<_>>();
+    printers.sort();
+    let mut supply_warnings = vec![];
+    for printer in printers {
+        match service::status_or_unreachable(app_data, printer).await {
+            Ok(status) => {
+                let reasons = status
+                    .state_reasons
+                    .into_iter()
+                    .filter(|reason| supply_warning(reason))
+                    .collect::<Vec<_>>();
+                if !reasons.is_empty() {
+                    supply_warnings.push(SupplyWarning {
+                        printer: printer.clone(),
+                        reasons,
+                    });
+                }
+            }
+            Err(err) => log::warn!("Couldn't check {printer}'s supplies for a report: {err}"),
+        }
+    }
+    let entries = app_data.history.filter(|entry| {
+        (from..to).contains(&entry.submitted_at) && stats::tagged(entry, tag.as_deref())
+    });
+    let mut units = QuotaUnits::default();
+    for entry in &entries {
+        quota::add(&mut units, app_data.quotas.costs.of_entry(entry));
+    }
+    UsageReport {
+        from,
+        to,
+        jobs: usage.printers.iter().map(|printer| printer.jobs).sum(),
+        pages: usage.printers.iter().map(|printer| printer.pages).sum(),
+        failed_jobs: entries
+            .iter()
+            .filter(|entry| history::failed(entry))
+            .count() as u32,
+        units,
+        top_users: stats::top_users(&app_data.history, from, to, tag.as_deref(), TOP_USERS),
+        printers: usage.printers,
+        supply_warnings,
+    }
+}
+
+/// `report` for people, rather than for spreadsheets.
+fn render(report: &UsageReport) -> String {
+    let local = |time: DateTime<Utc>| time.with_timezone(&Local).format("%B %-d, %Y");
+    let mut text = String::new();
+    let _ = writeln!(
+        text,
+        "kprint usage from {} to {}\n",
+        local(report.from),
+        local(report.to)
+    );
+    let _ = writeln!(
+        text,
+        "{} jobs, {} pages, {} failed",
+        report.jobs, report.pages, report.failed_jobs
+    );
+    let units = &report.units;
+    let _ = writeln!(
+        text,
+        "{} quota units: {} for grayscale, {} for color, {} off for two-sided",
+        units.total, units.grayscale, units.color, units.duplex_discount
+    );
+    if !report.printers.is_empty() {
+        let _ = writeln!(text, "\nBy printer:");
+        for printer in &report.printers {
+            let _ = writeln!(
+                text,
+                "  {}: {} jobs, {} pages ({} color, {} mono), {:.1}% failed",
+                printer.printer,
+                printer.jobs,
+                printer.pages,
+                printer.color_pages,
+                printer.mono_pages,
+                printer.failure_rate * 100.0
+            );
+        }
+    }
+    if !report.top_users.is_empty() {
+        let _ = writeln!(text, "\nMost pages:");
+        for user in &report.top_users {
+            let _ = writeln!(
+                text,
+                "  {}: {} pages in {} jobs",
+                user.username, user.pages, user.jobs
+            );
+        }
+    }
+    if !report.supply_warnings.is_empty() {
+        let _ = writeln!(text, "\nRunning low:");
+        for warning in &report.supply_warnings {
+            let _ = writeln!(
+                text,
+                "  {}: {}",
+                warning.printer,
+                warning.reasons.join(", ")
+            );
+        }
+    }
+    text
+}
+
+async fn send(config: &ReportConfig, report: &UsageReport) {
+    if let Some(mail) = &config.mail {
+        for to in &mail.to {
+            let message = lettre::Message::builder()
+                .from(mail.from.clone())
+                .to(to.clone())
+                .subject(format!("{} kprint report", config.schedule.name()))
+                .body(render(report));
+            let sent = match message {
+                Ok(message) => mail
+                    .smtp
+                    .send(message)
+                    .await
+                    .map(|_| ())
+                    .map_err(Into::into),
+                Err(err) => Err(anyhow::Error::from(err)),
+            };
+            if let Err(err) = sent {
+                log::warn!("Couldn't email the report to {to}: {err}");
+            }
+        }
+    }
+    if let Some(webhook) = &config.webhook {
+        let posted = reqwest::Client::new()
+            .post(webhook.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(report).unwrap())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(err) = posted {
+            log::warn!("Couldn't post the report to {webhook}: {err}");
+        }
+    }
+}
+
+/// Sends a report at the start of every period, forever.
+pub async fn schedule(app_data: Data<AppState>, config: ReportConfig) {
+    log::info!("Sending {} reports", config.schedule.name().to_lowercase());
+    loop {
+        let send_at = |start| midnight(start) + (SEND_AT - NaiveTime::MIN);
+        let mut start = config.schedule.start_of(Local::now().date_naive());
+        // This period's report has gone out already, unless it's still early
+        // on its first day
+        if send_at(start) <= Utc::now() {
+            start = config.schedule.next(start);
+        }
+        let wait = (send_at(start) - Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+
+        let period_start = config.schedule.start_of(start - Days::new(1));
+        let query = StatsQuery {
+            from: Some(midnight(period_start)),
+            to: Some(midnight(start)),
+            tag: None,
+        };
+        let report = report(&app_data, query).await;
+        send(&config, &report).await;
+        log::info!(
+            "Sent the report for {period_start} to {start}: {} jobs, {} pages",
+            report.jobs,
+            report.pages
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use actix_web::body::MessageBody;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue},
+    HttpMessage,
+};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use std::fmt::{Display, Formatter};
+use std::task::{Context, Poll};
+use uuid::Uuid;
+
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Identifies a single request across the access log, debug logs, and any
+/// error body handed back to the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestId(Uuid);
+
+impl Display for RequestId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Middleware which tags every request with a [`RequestId`], reusing one
+/// handed to us by a reverse proxy when it looks sane.
+pub struct RequestIdentifier;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestIdentifier
+where
+    B: MessageBody + 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = RequestIdentifierService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIdentifierService { service }))
+    }
+}
+
+pub struct RequestIdentifierService<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdentifierService<S>
+where
+    B: MessageBody + 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let id = req
+            .headers()
+            .get(REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| Uuid::parse_str(value).ok())
+            .map(RequestId)
+            .unwrap_or_else(|| RequestId(Uuid::new_v4()));
+        req.extensions_mut().insert(id);
+
+        let future = self.service.call(req);
+        Box::pin(async move {
+            let mut response = future.await?;
+            response.headers_mut().insert(
+                REQUEST_ID,
+                HeaderValue::from_str(&id.to_string()).expect("UUIDs are valid header values"),
+            );
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,82 @@
+//! Sending a job again when CUPS has a moment's trouble.
+//!
+//! Failures that can clear up on their own, like a refused connection or CUPS
+//! saying it's busy, are retried up to `KPRINT_IPP_RETRIES` times. The wait
+//! starts at `KPRINT_IPP_RETRY_DELAY_MS` and doubles each time, plus up to half
+//! as much again at random so a burst of jobs doesn't all come back at once.
+//! Only documents kprint holds whole can be sent again; one still streaming in
+//! from whoever's uploading it gets the one try. A job whose document fails
+//! partway is canceled on the printer, and sent again as a new one.
+
+use crate::ipp_client::IppError;
+use ipp::prelude::StatusCode;
+use std::time::Duration;
+
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_DELAY: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Tries after the first, so 0 never retries
+    pub retries: u32,
+    /// Wait before the first retry
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: DEFAULT_RETRIES,
+            delay: DEFAULT_DELAY,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let default = Self::default();
+        let retries = match std::env::var("KPRINT_IPP_RETRIES") {
+            Ok(retries) => retries.parse()?,
+            Err(_) => default.retries,
+        };
+        let delay = match std::env::var("KPRINT_IPP_RETRY_DELAY_MS") {
+            Ok(delay) => Duration::from_millis(delay.parse()?),
+            Err(_) => default.delay,
+        };
+        Ok(RetryPolicy { retries, delay })
+    }
+
+    /// How long to wait before retry number `retry`, counting from 0.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self.delay.saturating_mul(1 << retry.min(16));
+        backoff + backoff.mul_f64(fastrand::f64() / 2.0)
+    }
+}
+
+/// Whether `err`, from sending a request to CUPS, might not happen again.
+/// Only failures that mean CUPS never got the job count, so a retry can't
+/// print it twice.
+pub fn is_transient(err: &IppError) -> bool {
+    match err {
+        IppError::TimedOut { .. } => true,
+        IppError::Unreachable { source, .. } => {
+            source.is_connect()
+                || source
+                    .status()
+                    .is_some_and(|status| status.is_server_error())
+        }
+        // A new token won't turn up by asking again
+        IppError::ProxyAuth { .. } | IppError::Protocol { .. } | IppError::Null(_) => false,
+    }
+}
+
+/// Whether CUPS turned a request away with `status` for now, rather than
+/// for good.
+pub fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::ServerErrorBusy
+            | StatusCode::ServerErrorServiceUnavailable
+            | StatusCode::ServerErrorTemporaryError
+    )
+}
@@ -0,0 +1,2761 @@
+//! What kprint actually does, independent of whether the request came in over
+//! HTTP or gRPC. Handlers parse their input, call in here, and phrase the
+//! result in their own protocol.
+
+use crate::app::AppState;
+use crate::audit::{self, AuditEvent};
+use crate::confirmations;
+use crate::cover::Cover;
+use crate::cups;
+use crate::document::Document;
+use crate::envelope;
+use crate::error::{ErrorCode, FieldError, KprintError};
+use crate::flags::Flag;
+use crate::ipp_client::IppClient;
+use crate::jobs::{find_attribute, find_keywords, job_state, status_of, Delivery, JobRecord};
+use crate::logging::Pii;
+use crate::margins;
+use crate::packets::Packet;
+use crate::pdf;
+use crate::poster;
+use crate::queue_cache;
+use crate::quota;
+use crate::receipt;
+use crate::retry;
+use crate::sniff;
+use crate::stamp;
+use crate::state_reasons::PrinterCondition;
+use crate::streaming::{Chunk, StreamTuning};
+use crate::trace;
+use actix_web::web::{Bytes, Data};
+use chrono::{Local, Utc};
+use futures::{channel::mpsc, future::join_all};
+use ipp::prelude::*;
+use itertools::Itertools;
+use kprint_client::models::{
+    AppliedOverride, ColorMode, CreateGuestCode, DuplexMode, GuestCode, HistoryEntry, JobReceipt,
+    JobState, JobStatus, MaintenanceNotice, Media, PageSelection, PrintOptions, PrinterClass,
+    PrinterState, PrinterStatus, SetSuspension, Suspension,
+};
+use reqwest::Url;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// A successful submission.
+pub struct Submitted {
+    pub job: JobRecord,
+    pub state: JobState,
+    /// Pages the printer expects to print, when it reports that up front
+    pub pages: Option<i32>,
+    /// Options the printer forced on the job
+    pub overrides: Vec<AppliedOverride>,
+    /// Units taken out of the owner's quota up front, when they have one
+    pub quota_consumed: Option<u32>,
+    /// The pages being printed, once clamped to the document, or empty for
+    /// every page
+    pub page_ranges: PageSelection,
+    /// Copies that'll each be sent as a job of their own, after this one
+    pub copies_to_follow: u32,
+}
+
+/// What would happen to a job, if it were submitted.
+pub struct DryRun {
+    /// Pages in the document, when kprint can count them
+    pub document_pages: Option<u32>,
+    /// Pages that would be printed, after page ranges, copies, and any
+    /// cover sheet
+    pub impressions: Option<u32>,
+    pub sheets: Option<u32>,
+    pub overrides: Vec<AppliedOverride>,
+    /// What it'd take out of a quota
+    pub quota_units: Option<u32>,
+    /// The pages that would be printed, once clamped to the document, or
+    /// empty for every page
+    pub page_ranges: PageSelection,
+}
+
+/// A channel whose receiving end is a document the printer can read from.
+///
+/// Frontends feed the sending end from their own upload stream, which keeps
+/// their (often `!Send`) bodies out of the IPP client.
+pub fn document_channel(tuning: &StreamTuning) -> (mpsc::Sender<Chunk>, IppPayload) {
+    let (tx, reader) = tuning.channel();
+    (tx, IppPayload::new_async(reader))
+}
+
+/// Hands `document` to `rewrite` if it's a PDF, saying whether it was
+/// rewritten. PDFs that can't be are printed as they are, since everything
+/// kprint does to them is a courtesy.
+async fn rewrite_pdf(
+    document: Document,
+    what: &str,
+    rewrite: impl FnOnce(&[u8]) -> anyhow::Result<Vec<u8>> + Send + 'static,
+) -> Result<(Document, bool), KprintError> {
+    let document_bytes = document.into_bytes().await?;
+    if !document_bytes.starts_with(b"%PDF-") {
+        return Ok((document_bytes.into(), false));
+    }
+
+    let (rewriting, original) = tokio::task::spawn_blocking(move || {
+        let rewriting = rewrite(&document_bytes);
+        (rewriting, document_bytes)
+    })
+    .await
+    .map_err(|err| KprintError::InternalError(err.into()))?;
+    match rewriting {
+        Ok(rewritten) => Ok((rewritten.into(), true)),
+        Err(err) => {
+            log::warn!("Printing a PDF without its {what}, which failed: {err}");
+            Ok((original.into(), false))
+        }
+    }
+}
+
+/// Turns page ranges into ranges of the sheets those pages became, once
+/// each page is a poster of `sheets` sheets.
+fn poster_sheets(pages: PageSelection, sheets: i32) -> PageSelection {
+    PageSelection::new(
+        pages
+            .ranges()
+            .iter()
+            .map(|(start, end)| ((start - 1) * sheets + 1, end * sheets)),
+    )
+}
+
+/// Moves page ranges back past `cover_pages` pages put in front of the
+/// document, and selects those too.
+fn behind_cover(pages: PageSelection, cover_pages: i32) -> PageSelection {
+    if pages.is_all() {
+        // Every page is still every page
+        return pages;
+    }
+    PageSelection::new(
+        std::iter::once((1, cover_pages)).chain(
+            pages
+                .ranges()
+                .iter()
+                .map(|(start, end)| (start + cover_pages, end + cover_pages)),
+        ),
+    )
+}
+
+fn printer<'a>(app_data: &'a AppState, name: &str) -> Result<&'a IppClient, KprintError> {
+    app_data
+        .printers
+        .get(name)
+        .ok_or_else(|| KprintError::PrinterNotFound(name.to_string()))
+}
+
+/// How long a printer gets to say how it's doing before it's listed as
+/// unreachable
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Names of every configured printer, alphabetically.
+pub fn printers(app_data: &AppState) -> Vec<String> {
+    app_data.printers.keys().sorted().cloned().collect()
+}
+
+/// How a printer is doing, going by what it last said about itself.
+pub async fn printer_status(app_data: &AppState, name: &str) -> Result<PrinterStatus, KprintError> {
+    let printer = printer(app_data, name)?;
+    let attributes = app_data.printer_cache.get(name, printer).await?;
+    Ok(status_from(app_data, name, &attributes))
+}
+
+/// How a printer is doing, or [`PrinterState::Unreachable`] if it can't say
+/// within [`STATUS_TIMEOUT`], so one hung printer can't hold up a listing of
+/// all of them.
+pub async fn status_or_unreachable(
+    app_data: &AppState,
+    name: &str,
+) -> Result<PrinterStatus, KprintError> {
+    match tokio::time::timeout(STATUS_TIMEOUT, printer_status(app_data, name)).await {
+        Ok(Ok(status)) => Ok(status),
+        Ok(Err(err @ KprintError::PrinterNotFound(_))) => Err(err),
+        Ok(Err(err)) => {
+            log::warn!("Couldn't get the status of {name}: {err}");
+            Ok(unreachable(app_data, name))
+        }
+        Err(_) => {
+            log::warn!("{name} didn't say how it's doing within {STATUS_TIMEOUT:?}");
+            Ok(unreachable(app_data, name))
+        }
+    }
+}
+
+fn unreachable(app_data: &AppState, name: &str) -> PrinterStatus {
+    PrinterStatus {
+        name: name.to_string(),
+        state: PrinterState::Unreachable,
+        state_reasons: vec![],
+        message: None,
+        accepting_jobs: false,
+        paused: false,
+        maintenance: app_data.maintenance.get(name),
+        unhealthy_until: app_data.breakers.open_until(name),
+    }
+}
+
+/// How every printer is doing, asking them all at once.
+pub async fn fleet_status(app_data: &AppState) -> Vec<PrinterStatus> {
+    let names = printers(app_data);
+    join_all(names.iter().map(|name| async move {
+        status_or_unreachable(app_data, name)
+            .await
+            .unwrap_or_else(|_| unreachable(app_data, name))
+    }))
+    .await
+}
+
+fn status_from(app_data: &AppState, name: &str, attributes: &IppAttributes) -> PrinterStatus {
+    let state_reasons = find_keywords(attributes, IppAttribute::PRINTER_STATE_REASONS);
+    let state = find_attribute(attributes, IppAttribute::PRINTER_STATE)
+        .and_then(|state| match state {
+            IppValue::Enum(state) | IppValue::Integer(state) => {
+                Some(PrinterState::from_ipp(*state))
+            }
+            _ => None,
+        })
+        .unwrap_or(PrinterState::Unknown);
+    PrinterStatus {
+        name: name.to_string(),
+        state,
+        paused: state_reasons.iter().any(|reason| reason == "paused"),
+        maintenance: app_data.maintenance.get(name),
+        unhealthy_until: app_data.breakers.open_until(name),
+        state_reasons,
+        message: find_attribute(attributes, IppAttribute::PRINTER_STATE_MESSAGE)
+            .map(ToString::to_string)
+            .filter(|message| !message.is_empty()),
+        accepting_jobs: matches!(
+            find_attribute(attributes, IppAttribute::PRINTER_IS_ACCEPTING_JOBS),
+            Some(IppValue::Boolean(true))
+        ),
+    }
+}
+
+/// Turns jobs away up front from printers in maintenance or that keep
+/// failing, and from paused or stopped printers rather than leaving them to
+/// sit in the queue until someone resumes or fixes it. A printer that won't
+/// say how it's doing gets the benefit of the doubt.
+async fn check_available(app_data: &AppState, name: &str) -> Result<(), KprintError> {
+    app_data.breakers.check(name)?;
+    if let Some(notice) = app_data.maintenance.get(name) {
+        return Err(KprintError::PrinterInMaintenance {
+            printer: name.to_string(),
+            message: notice.message,
+        });
+    }
+    match printer_status(app_data, name).await {
+        Ok(status) if status.paused => Err(KprintError::PrinterPaused(name.to_string())),
+        Ok(status) if status.state == PrinterState::Stopped => Err(KprintError::PrinterStopped {
+            printer: name.to_string(),
+            condition: PrinterCondition::of(&status),
+        }),
+        Ok(_) => Ok(()),
+        Err(err @ KprintError::PrinterNotFound(_)) => Err(err),
+        Err(err) => {
+            log::debug!("Couldn't check whether {name} is paused: {err}");
+            Ok(())
+        }
+    }
+}
+
+/// Puts `printer_name` into maintenance on behalf of an admin, so jobs are
+/// turned away with `message` until it's taken out again.
+pub async fn set_maintenance(
+    app_data: &AppState,
+    admin: &str,
+    printer_name: &str,
+    message: String,
+) -> Result<MaintenanceNotice, KprintError> {
+    printer(app_data, printer_name)?;
+    let message = message.trim().to_string();
+    if message.is_empty() {
+        return Err(KprintError::Validation(vec![FieldError::missing(
+            "message",
+        )]));
+    }
+    let notice = MaintenanceNotice {
+        message,
+        set_by: admin.to_string(),
+        since: Utc::now(),
+    };
+    app_data
+        .maintenance
+        .set(printer_name, notice.clone())
+        .await
+        .map_err(KprintError::Database)?;
+    audit::record(AuditEvent::AdminSetMaintenance {
+        admin,
+        printer: printer_name,
+        message: &notice.message,
+    });
+    Ok(notice)
+}
+
+/// Takes `printer_name` back out of maintenance on behalf of an admin.
+pub async fn clear_maintenance(
+    app_data: &AppState,
+    admin: &str,
+    printer_name: &str,
+) -> Result<(), KprintError> {
+    printer(app_data, printer_name)?;
+    if app_data
+        .maintenance
+        .clear(printer_name)
+        .await
+        .map_err(KprintError::Database)?
+    {
+        audit::record(AuditEvent::AdminClearedMaintenance {
+            admin,
+            printer: printer_name,
+        });
+    }
+    Ok(())
+}
+
+/// Stops `username` from printing on behalf of an admin, until `until` or
+/// until it's lifted.
+pub async fn suspend(
+    app_data: &AppState,
+    admin: &str,
+    username: &str,
+    SetSuspension { reason, until }: SetSuspension,
+) -> Result<Suspension, KprintError> {
+    let reason = reason.trim().to_string();
+    let mut errors = vec![];
+    if reason.is_empty() {
+        errors.push(FieldError::missing("reason"));
+    }
+    let since = Utc::now();
+    if until.is_some_and(|until| until <= since) {
+        errors.push(FieldError::new(
+            "until",
+            ErrorCode::ValueOutOfRange,
+            "Suspensions have to end in the future",
+        ));
+    }
+    if !errors.is_empty() {
+        return Err(KprintError::Validation(errors));
+    }
+    let suspension = Suspension {
+        username: username.to_string(),
+        reason,
+        set_by: admin.to_string(),
+        since,
+        until,
+    };
+    app_data
+        .suspensions
+        .set(suspension.clone())
+        .await
+        .map_err(KprintError::Database)?;
+    audit::record(AuditEvent::AdminSuspendedUser {
+        admin,
+        username,
+        reason: &suspension.reason,
+        until,
+    });
+    Ok(suspension)
+}
+
+/// Lets `username` print again on behalf of an admin.
+pub async fn unsuspend(
+    app_data: &AppState,
+    admin: &str,
+    username: &str,
+) -> Result<(), KprintError> {
+    if app_data
+        .suspensions
+        .clear(username)
+        .await
+        .map_err(KprintError::Database)?
+    {
+        audit::record(AuditEvent::AdminUnsuspendedUser { admin, username });
+    }
+    Ok(())
+}
+
+/// The most pages a guest code can print
+const MAX_GUEST_PAGES: u32 = 500;
+/// The longest a guest code can last
+const MAX_GUEST_CODE_LIFETIME: chrono::Duration = chrono::Duration::days(7);
+
+/// Makes a code for a guest to print with on behalf of an admin, sponsored by
+/// whoever they say or else by the admin themselves.
+pub async fn create_guest_code(
+    app_data: &AppState,
+    admin: &str,
+    CreateGuestCode {
+        sponsor,
+        pages,
+        expires_at,
+        note,
+    }: CreateGuestCode,
+) -> Result<GuestCode, KprintError> {
+    let sponsor = sponsor
+        .map(|sponsor| sponsor.trim().to_string())
+        .filter(|sponsor| !sponsor.is_empty())
+        .unwrap_or_else(|| admin.to_string());
+    let note = note
+        .map(|note| note.trim().to_string())
+        .filter(|note| !note.is_empty());
+    let mut errors = vec![];
+    if !(1..=MAX_GUEST_PAGES).contains(&pages) {
+        errors.push(FieldError::new(
+            "pages",
+            ErrorCode::ValueOutOfRange,
+            format!("Guest codes can print from 1 to {MAX_GUEST_PAGES} pages"),
+        ));
+    }
+    let created_at = Utc::now();
+    if expires_at <= created_at || expires_at > created_at + MAX_GUEST_CODE_LIFETIME {
+        errors.push(FieldError::new(
+            "expiresAt",
+            ErrorCode::ValueOutOfRange,
+            format!(
+                "Guest codes have to expire in the next {} days",
+                MAX_GUEST_CODE_LIFETIME.num_days()
+            ),
+        ));
+    }
+    if !errors.is_empty() {
+        return Err(KprintError::Validation(errors));
+    }
+    let code = app_data
+        .guests
+        .create(GuestCode {
+            id: Uuid::new_v4(),
+            code: None,
+            sponsor,
+            created_by: admin.to_string(),
+            note,
+            pages,
+            pages_used: 0,
+            created_at,
+            expires_at,
+        })
+        .await
+        .map_err(KprintError::Database)?;
+    audit::record(AuditEvent::AdminCreatedGuestCode {
+        admin,
+        code: code.id,
+        sponsor: &code.sponsor,
+        pages,
+        expires_at,
+    });
+    Ok(code)
+}
+
+/// Stops guest code `id` from working, on behalf of an admin.
+pub async fn revoke_guest_code(
+    app_data: &AppState,
+    admin: &str,
+    id: Uuid,
+) -> Result<(), KprintError> {
+    if app_data
+        .guests
+        .revoke(id)
+        .await
+        .map_err(KprintError::Database)?
+    {
+        audit::record(AuditEvent::AdminRevokedGuestCode { admin, code: id });
+    }
+    Ok(())
+}
+
+/// Which way an admin is switching a printer.
+#[derive(Debug, Clone, Copy)]
+pub enum Pause {
+    Pause,
+    Resume,
+}
+
+/// Pauses or resumes `printer_name` on behalf of an admin, returning how it's
+/// doing afterwards.
+pub async fn set_paused(
+    app_data: &AppState,
+    admin: &str,
+    printer_name: &str,
+    pause: Pause,
+) -> Result<PrinterStatus, KprintError> {
+    let printer = printer(app_data, printer_name)?;
+    let operation = match pause {
+        Pause::Pause => Operation::PausePrinter,
+        Pause::Resume => Operation::ResumePrinter,
+    };
+    let mut request =
+        IppRequestResponse::new(IppVersion::v1_1(), operation, Some(printer.uri().clone()));
+    request.attributes_mut().add(
+        DelimiterTag::OperationAttributes,
+        IppAttribute::new(
+            IppAttribute::REQUESTING_USER_NAME,
+            IppValue::NameWithoutLanguage(admin.to_string()),
+        ),
+    );
+    let response = printer.send(request).await?;
+    if !response.header().status_code().is_success() {
+        return Err(refused(
+            printer_name,
+            &response,
+            PrinterCondition::default(),
+        ));
+    }
+    audit::record(match pause {
+        Pause::Pause => AuditEvent::AdminPausedPrinter {
+            admin,
+            printer: printer_name,
+        },
+        Pause::Resume => AuditEvent::AdminResumedPrinter {
+            admin,
+            printer: printer_name,
+        },
+    });
+    // Asked again rather than waiting for the cache, so the change shows at once
+    let attributes = app_data
+        .printer_cache
+        .refresh(printer_name, printer)
+        .await?;
+    Ok(status_from(app_data, printer_name, &attributes))
+}
+
+/// The IPP job attributes asking for `options`.
+fn job_attributes(options: &PrintOptions) -> Vec<IppAttribute> {
+    // Already sorted and merged, which IPP needs them to be. They're all one
+    // attribute, since a second with the same name would replace the first
+    let mut ranges = options
+        .pages
+        .ranges()
+        .iter()
+        .map(|&(min, max)| IppValue::RangeOfInteger { min, max })
+        .collect::<Vec<_>>();
+    let page_ranges = match ranges.len() {
+        0 => None,
+        1 => ranges.pop(),
+        _ => Some(IppValue::Array(ranges)),
+    }
+    .map(|ranges| IppAttribute::new("page-ranges", ranges));
+    // IPP's name for grayscale
+    let color_mode = match options.color_mode {
+        ColorMode::Grayscale => "monochrome",
+        ColorMode::Color => "color",
+    };
+
+    let media_col =
+        sent_media(options).map(|media| IppAttribute::new("media-col", media_col(&media)));
+    // Envelopes go in narrow end first, the way feeders take them
+    let feed_orientation = options.envelope.map(|_| {
+        IppAttribute::new(
+            "feed-orientation",
+            IppValue::Keyword("short-edge-first".to_string()),
+        )
+    });
+    let image_shift = options.offset.into_iter().flat_map(|offset| {
+        [("x-image-shift", offset.x), ("y-image-shift", offset.y)]
+            .map(|(name, shift)| IppAttribute::new(name, IppValue::Integer(shift)))
+    });
+
+    [
+        IppAttribute::new(
+            "sides",
+            IppValue::Keyword(
+                serde_variant::to_variant_name(&options.sides)
+                    .unwrap()
+                    .to_string(),
+            ),
+        ),
+        IppAttribute::new(
+            "print-color-mode",
+            IppValue::Keyword(color_mode.to_string()),
+        ),
+        IppAttribute::new("copies", IppValue::Integer(options.copies as i32)),
+    ]
+    .into_iter()
+    .chain(page_ranges)
+    .chain(media_col)
+    .chain(feed_orientation)
+    .chain(image_shift)
+    .collect()
+}
+
+/// The media `options` ask for, with any margins given by themselves.
+fn sent_media(options: &PrintOptions) -> Option<Media> {
+    match options.margins {
+        Some(margins) => Some(Media {
+            margins: Some(margins),
+            ..options.media.clone().unwrap_or_default()
+        }),
+        None => options.media.clone(),
+    }
+}
+
+/// `media` as the `media-col` collection printers take it as.
+fn media_col(media: &Media) -> IppValue {
+    let mut members = BTreeMap::new();
+    if let (Some(x), Some(y)) = (media.x_dimension, media.y_dimension) {
+        let size = [("x-dimension", x), ("y-dimension", y)]
+            .map(|(name, length)| (name.to_string(), IppValue::Integer(length as i32)));
+        members.insert(
+            "media-size".to_string(),
+            IppValue::Collection(size.into_iter().collect()),
+        );
+    }
+    if let Some(margins) = media.margins {
+        for (name, margin) in [
+            ("media-top-margin", margins.top),
+            ("media-bottom-margin", margins.bottom),
+            ("media-left-margin", margins.left),
+            ("media-right-margin", margins.right),
+        ] {
+            members.insert(name.to_string(), IppValue::Integer(margin as i32));
+        }
+    }
+    for (name, keyword) in [
+        ("media-source", &media.source),
+        ("media-type", &media.media_type),
+    ] {
+        if let Some(keyword) = keyword {
+            members.insert(name.to_string(), IppValue::Keyword(keyword.clone()));
+        }
+    }
+    IppValue::Collection(members)
+}
+
+/// Turns an envelope `options` ask for into how `printer_name` takes it:
+/// the envelope's size as the media, from its envelope tray, one-sided.
+async fn envelope_options(
+    app_data: &AppState,
+    printer_name: &str,
+    options: &mut PrintOptions,
+) -> Result<(), KprintError> {
+    let Some(envelope) = options.envelope else {
+        return Ok(());
+    };
+    let printer = printer(app_data, printer_name)?;
+    let attributes = app_data.printer_cache.get(printer_name, printer).await?;
+    let tray = app_data.envelope_trays.get(printer_name);
+    options.media = Some(envelope::media(envelope, tray, &attributes));
+    options.sides = DuplexMode::OneSided;
+    Ok(())
+}
+
+/// Checks `printer_name` has the paper `options` ask for, going by what it
+/// says it has. Printers that don't say are left to turn the job down
+/// themselves.
+async fn check_media(
+    app_data: &AppState,
+    printer_name: &str,
+    options: &PrintOptions,
+) -> Result<(), KprintError> {
+    // Margins drawn into the PDF never reach the printer
+    let media = match app_data.pdf_margins.applies_to(printer_name) {
+        true => options.media.clone().map(|media| Media {
+            margins: None,
+            ..media
+        }),
+        false => sent_media(options),
+    };
+    let Some(media) = media.filter(|media| *media != Media::default()) else {
+        return Ok(());
+    };
+    let printer = printer(app_data, printer_name)?;
+    let attributes = app_data.printer_cache.get(printer_name, printer).await?;
+    match missing_media(printer_name, &attributes, &media) {
+        Some(message) => Err(KprintError::Validation(vec![FieldError::new(
+            "media",
+            ErrorCode::UnsupportedValue,
+            message,
+        )])),
+        None => Ok(()),
+    }
+}
+
+/// What about `media` a printer with `attributes` says it can't do, if
+/// anything.
+fn missing_media(printer_name: &str, attributes: &IppAttributes, media: &Media) -> Option<String> {
+    let IppValue::Collection(members) = media_col(media) else {
+        unreachable!("media-col is always a collection");
+    };
+    let supported = find_keywords(attributes, "media-col-supported");
+    if let Some(member) = members
+        .keys()
+        .find(|member| !supported.is_empty() && !supported.contains(member))
+    {
+        return Some(format!("{printer_name} can't be told {member}"));
+    }
+    [
+        ("media-source-supported", &media.source),
+        ("media-type-supported", &media.media_type),
+    ]
+    .into_iter()
+    .find_map(|(attribute, keyword)| {
+        let keyword = keyword.as_ref()?;
+        let supported = find_keywords(attributes, attribute);
+        (!supported.is_empty() && !supported.contains(keyword)).then(|| {
+            format!(
+                "{printer_name} doesn't have {keyword}, only {}",
+                supported.join(", ")
+            )
+        })
+    })
+}
+
+/// Says what `request`'s document is, if that's known. The ipp crate's
+/// builders put everything with the job's attributes, but this is an
+/// operation attribute.
+fn add_format(request: &mut IppRequestResponse, format: Option<&str>) {
+    if let Some(format) = format {
+        request.attributes_mut().add(
+            DelimiterTag::OperationAttributes,
+            IppAttribute::new(
+                "document-format",
+                IppValue::MimeMediaType(format.to_string()),
+            ),
+        );
+    }
+}
+
+/// Holds `request`'s job until it's picked up at the release station, if
+/// `held`.
+fn add_hold(request: &mut IppRequestResponse, held: bool) {
+    if held {
+        request.attributes_mut().add(
+            DelimiterTag::JobAttributes,
+            IppAttribute::new(
+                "job-hold-until",
+                IppValue::Keyword("indefinite".to_string()),
+            ),
+        );
+    }
+}
+
+/// A Validate-Job or Create-Job for a job printed the way `options` ask, to
+/// the printer at `uri`.
+fn job_request(
+    operation: Operation,
+    uri: Uri,
+    username: &str,
+    options: &PrintOptions,
+    format: Option<&str>,
+) -> IppRequestResponse {
+    let mut request = IppRequestResponse::new(IppVersion::v1_1(), operation, Some(uri));
+    let operation_attributes = [
+        IppAttribute::new(
+            IppAttribute::REQUESTING_USER_NAME,
+            IppValue::NameWithoutLanguage(username.to_string()),
+        ),
+        IppAttribute::new(
+            IppAttribute::JOB_NAME,
+            IppValue::NameWithoutLanguage(options.title.clone()),
+        ),
+    ];
+    for attribute in operation_attributes {
+        request
+            .attributes_mut()
+            .add(DelimiterTag::OperationAttributes, attribute);
+    }
+    add_format(&mut request, format);
+    for attribute in job_attributes(options) {
+        request
+            .attributes_mut()
+            .add(DelimiterTag::JobAttributes, attribute);
+    }
+    request
+}
+
+/// A Validate-Job asking the printer at `uri` whether it'd print a job the
+/// way it's asked for.
+fn validate_request(
+    uri: Uri,
+    username: &str,
+    options: &PrintOptions,
+    format: Option<&str>,
+) -> IppRequestResponse {
+    job_request(Operation::ValidateJob, uri, username, options, format)
+}
+
+/// The Print-Job sending `document` to the printer at `uri`, held until it's
+/// picked up if `held`.
+fn print_request(
+    uri: Uri,
+    document: IppPayload,
+    username: &str,
+    options: &PrintOptions,
+    format: Option<&str>,
+    held: bool,
+) -> IppRequestResponse {
+    let mut request: IppRequestResponse = IppOperationBuilder::print_job(uri, document)
+        .user_name(username)
+        .job_title(options.title.clone())
+        .attributes(job_attributes(options))
+        .build()
+        .into();
+    add_format(&mut request, format);
+    add_hold(&mut request, held);
+    request
+}
+
+/// The Create-Job starting a job on the printer at `uri` that its document
+/// is sent to afterwards.
+fn create_request(
+    uri: Uri,
+    username: &str,
+    options: &PrintOptions,
+    held: bool,
+) -> IppRequestResponse {
+    let mut request = job_request(Operation::CreateJob, uri, username, options, None);
+    add_hold(&mut request, held);
+    request
+}
+
+/// The Send-Document sending `document` to job `job_id` on the printer at
+/// `uri`, saying whether it's the `last` one.
+fn send_document_request(
+    uri: Uri,
+    job_id: i32,
+    document: IppPayload,
+    username: &str,
+    format: Option<&str>,
+    last: bool,
+) -> IppRequestResponse {
+    let mut request: IppRequestResponse = IppOperationBuilder::send_document(uri, job_id, document)
+        .user_name(username)
+        .last(last)
+        .build()
+        .into();
+    add_format(&mut request, format);
+    request
+}
+
+/// The Print-URI having the printer at `uri` fetch `document` itself, held
+/// until it's picked up if `held`.
+fn print_uri_request(
+    uri: Uri,
+    document: &Url,
+    username: &str,
+    options: &PrintOptions,
+    held: bool,
+) -> IppRequestResponse {
+    let mut request = job_request(Operation::PrintUri, uri, username, options, None);
+    request.attributes_mut().add(
+        DelimiterTag::OperationAttributes,
+        IppAttribute::new("document-uri", IppValue::Uri(document.to_string())),
+    );
+    add_hold(&mut request, held);
+    request
+}
+
+/// Counts the pages of a PDF, or returns `None` for documents kprint can't
+/// count the pages of.
+async fn document_pages(document: Bytes) -> Result<Option<u32>, KprintError> {
+    if !document.starts_with(b"%PDF-") {
+        return Ok(None);
+    }
+    let pages = tokio::task::spawn_blocking(move || pdf::page_count(&document))
+        .await
+        .map_err(|err| KprintError::InternalError(err.into()))?;
+    match pages {
+        Ok(pages) => Ok(Some(pages)),
+        Err(err) => {
+            log::info!("Couldn't count the pages of a PDF: {err}");
+            Ok(None)
+        }
+    }
+}
+
+/// Turns away documents that aren't in a format the printers can print,
+/// going by how `head`, their first [`sniff::HEAD_SIZE`] bytes, starts.
+fn check_format(head: &[u8]) -> Result<sniff::Format, KprintError> {
+    sniff::detect(head)
+        .map_err(|media_type| KprintError::UnsupportedDocumentType(media_type.to_string()))
+}
+
+/// Everything [`submit`] would do short of printing: `username` has to be
+/// allowed to print there, the options go past the printer with
+/// Validate-Job, and the document, if there is one, gets its pages counted.
+pub async fn dry_run(
+    app_data: &AppState,
+    username: &str,
+    admin: bool,
+    groups: &[String],
+    printer_name: &str,
+    mut options: PrintOptions,
+    document: IppPayload,
+) -> Result<DryRun, KprintError> {
+    let (_, overrides) = new_job(
+        app_data,
+        username,
+        admin,
+        printer_name.to_string(),
+        &mut options,
+    )
+    .await?;
+    let printer = printer(app_data, printer_name)?;
+
+    let request = validate_request(printer.uri().clone(), username, &options, None);
+    let response = printer.send(request).await?;
+    let status = response.header().status_code();
+    if !status.is_success() {
+        let message = find_attribute(response.attributes(), IppAttribute::STATUS_MESSAGE)
+            .map(ToString::to_string)
+            .unwrap_or_else(|| status.to_string());
+        return Err(KprintError::JobRejected(message));
+    }
+
+    let document = Document::from(document).into_bytes().await?;
+    check_format(&document[..document.len().min(sniff::HEAD_SIZE)])?;
+    let Some(document_pages) = document_pages(document.into()).await? else {
+        check_quota(app_data, username, groups, None).await?;
+        return Ok(DryRun {
+            document_pages: None,
+            impressions: None,
+            sheets: None,
+            overrides,
+            quota_units: None,
+            page_ranges: options.pages,
+        });
+    };
+    clamp_pages(&mut options, document_pages)?;
+    let (impressions, sheets) = impressions(document_pages, &options);
+    let units = app_data
+        .quotas
+        .costs
+        .of(impressions, sheets, options.color_mode);
+    check_quota(app_data, username, groups, Some(units.total)).await?;
+    Ok(DryRun {
+        document_pages: Some(document_pages),
+        impressions: Some(impressions),
+        sheets: Some(sheets),
+        overrides,
+        quota_units: Some(units.total),
+        page_ranges: options.pages,
+    })
+}
+
+/// Counts the pages of `document`, if it's a PDF. Documents that are still
+/// arriving have to be read in whole for that.
+async fn count_pages(mut document: Document) -> Result<(Document, Option<u32>), KprintError> {
+    if !document.head(5).await?.starts_with(b"%PDF-") {
+        return Ok((document, None));
+    }
+    let (document, bytes) = match document {
+        Document::Spooled(file) => {
+            let bytes = Document::Spooled(file.clone()).into_bytes().await?;
+            (Document::Spooled(file), Bytes::from(bytes))
+        }
+        document => {
+            let bytes = Bytes::from(document.into_bytes().await?);
+            (Document::Buffered(bytes.clone()), bytes)
+        }
+    };
+    Ok((document, document_pages(bytes).await?))
+}
+
+/// Trims `options.pages` to a document with `document_pages` pages, dropping
+/// ranges that start past its end, and turns the job away if that leaves
+/// nothing to print.
+fn clamp_pages(options: &mut PrintOptions, document_pages: u32) -> Result<(), KprintError> {
+    let Some(clamped) = options.pages.clamp(document_pages as i32) else {
+        return Err(KprintError::Validation(vec![FieldError::new(
+            "pages",
+            ErrorCode::PageRangeInvalid,
+            format!(
+                "The document only has {document_pages} page{}, so {} doesn't pick any of them",
+                if document_pages == 1 { "" } else { "s" },
+                options.pages
+            ),
+        )]));
+    };
+    options.pages = clamped;
+    Ok(())
+}
+
+/// Clamps the page ranges `options` asks for to `document`, when it has any
+/// and kprint can count the document's pages.
+async fn select_pages(
+    options: &mut PrintOptions,
+    document: Document,
+) -> Result<(Document, Option<u32>), KprintError> {
+    if options.pages.is_all() {
+        return Ok((document, None));
+    }
+    let (document, document_pages) = count_pages(document).await?;
+    if let Some(document_pages) = document_pages {
+        clamp_pages(options, document_pages)?;
+    }
+    Ok((document, document_pages))
+}
+
+/// The pages and sheets of paper a job with `document_pages` pages would
+/// print, after page ranges, copies, and any cover sheet.
+fn impressions(document_pages: u32, options: &PrintOptions) -> (u32, u32) {
+    let selected =
+        options.pages.count(document_pages) * options.poster.map_or(1, |poster| poster.sheets());
+    let two_sided = options.sides != DuplexMode::OneSided;
+    let cover_pages = match (options.cover_sheet, two_sided) {
+        (false, _) => 0,
+        (true, false) => 1,
+        (true, true) => 2,
+    };
+    let per_copy = selected + cover_pages;
+    let sheets = if two_sided {
+        per_copy.div_ceil(2)
+    } else {
+        per_copy
+    };
+    (per_copy * options.copies, sheets * options.copies)
+}
+
+/// Turns away a job needing `needed` units, or that can't be counted, the
+/// way [`charge_quota`] would, without setting anything aside.
+async fn check_quota(
+    app_data: &AppState,
+    username: &str,
+    groups: &[String],
+    needed: Option<u32>,
+) -> Result<(), KprintError> {
+    if app_data.flags.enabled(Flag::Quotas) {
+        app_data
+            .quotas
+            .check(app_data, username, groups, needed)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Counts the units `job` will take out of its owner's quota and sets them
+/// aside, turning it away if they don't have that many left. Documents that
+/// aren't PDFs can't be counted, so they're only turned away once the quota's
+/// all used, and are charged what the printer says it printed. Whatever's
+/// set aside has to be debited once the job's printed, or refunded if it
+/// isn't.
+async fn charge_quota(
+    app_data: &AppState,
+    job: &mut JobRecord,
+    groups: &[String],
+    options: &PrintOptions,
+    document: Document,
+    document_pages: Option<u32>,
+) -> Result<(Document, Option<u32>), KprintError> {
+    if !app_data.flags.enabled(Flag::Quotas) {
+        return Ok((document, None));
+    }
+    let quotas = &app_data.quotas;
+    if quotas
+        .check(app_data, &job.owner, groups, None)
+        .await?
+        .is_none()
+    {
+        return Ok((document, None));
+    }
+    let (document, document_pages) = match document_pages {
+        Some(document_pages) => (document, Some(document_pages)),
+        None => count_pages(document).await?,
+    };
+    let Some(document_pages) = document_pages else {
+        return Ok((document, None));
+    };
+    let (pages, sheets) = impressions(document_pages, options);
+    let units = quotas.costs.of(pages, sheets, options.color_mode);
+    quotas
+        .reserve(app_data, &job.owner, groups, units.total)
+        .await?;
+    job.counted_pages = Some(pages);
+    job.quota_units = Some(units);
+    Ok((document, Some(units.total)))
+}
+
+/// Sends `document` to `printer_name` on behalf of `username`, and records
+/// the job.
+pub async fn submit(
+    app_data: &AppState,
+    username: &str,
+    admin: bool,
+    groups: &[String],
+    printer_name: String,
+    mut options: PrintOptions,
+    mut document: Document,
+) -> Result<Submitted, KprintError> {
+    let (mut job, overrides) =
+        new_job(app_data, username, admin, printer_name, &mut options).await?;
+    check_format(&document.head(sniff::HEAD_SIZE).await?)?;
+    let (document, document_pages) = select_pages(&mut options, document).await?;
+    let (document, quota_consumed) = charge_quota(
+        app_data,
+        &mut job,
+        groups,
+        &options,
+        document,
+        document_pages,
+    )
+    .await?;
+    let sent = async {
+        let (document, following) = separate_copies(&mut options, document).await?;
+        let submitted = deliver_watched(app_data, job, options.clone(), document).await?;
+        Ok((submitted, following))
+    };
+    let (submitted, following) = match sent.await {
+        Ok(sent) => sent,
+        Err(err) => {
+            if let Some(units) = quota_consumed {
+                app_data.quotas.refund(username, units);
+            }
+            return Err(err);
+        }
+    };
+    if let Some(units) = quota_consumed {
+        app_data.quotas.debit(&submitted.job, units).await;
+    }
+    let copies_to_follow = match following {
+        Some((remaining, document)) => {
+            app_data
+                .copies
+                .follow(submitted.job.id, options, document, remaining);
+            remaining
+        }
+        None => 0,
+    };
+    Ok(Submitted {
+        overrides,
+        quota_consumed,
+        copies_to_follow,
+        ..submitted
+    })
+}
+
+/// Has `printer_name` fetch the document at `document` itself with
+/// Print-URI, rather than kprint passing it along, and records the job.
+/// There are no pages to count, so the job's charged what the printer says
+/// it printed, like any other document kprint can't count.
+pub async fn submit_uri(
+    app_data: &AppState,
+    username: &str,
+    admin: bool,
+    groups: &[String],
+    printer_name: String,
+    mut options: PrintOptions,
+    document: &Url,
+) -> Result<Submitted, KprintError> {
+    let (mut job, overrides) =
+        new_job(app_data, username, admin, printer_name, &mut options).await?;
+    let nothing = Document::Buffered(Bytes::new());
+    // Nothing to count, so nothing's set aside
+    charge_quota(app_data, &mut job, groups, &options, nothing, None).await?;
+    let printer_name = &*job.printer;
+    let printer = printer(app_data, printer_name)?;
+    check_fetches(app_data, printer_name, printer, document).await?;
+    let held = app_data
+        .pickup
+        .as_ref()
+        .is_some_and(|pickup| pickup.holds(printer_name));
+
+    let uri = printer.uri().clone();
+    let retries = app_data.retries.retries;
+    let response = send_retrying(app_data, printer_name, printer, retries, || {
+        print_uri_request(uri.clone(), document, username, &options, held)
+    })
+    .await;
+    let response = match response {
+        Ok(response) => {
+            app_data.breakers.succeeded(printer_name);
+            response
+        }
+        Err(err) => {
+            if let KprintError::PrinterUnavailable(_) = err {
+                app_data.breakers.failed(printer_name);
+            }
+            record_history(app_data, &job, &options, JobState::Aborted, Some(&err));
+            return Err(err);
+        }
+    };
+    let attributes = response.attributes();
+    job.ipp_job_id = find_attribute(attributes, IppAttribute::JOB_ID)
+        .and_then(|job_id| job_id.as_integer().copied());
+    job.delivery = Delivery::Delivered;
+    app_data.jobs.insert(job.clone());
+    record_history(app_data, &job, &options, job_state(attributes), None);
+    Ok(Submitted {
+        job,
+        state: job_state(attributes),
+        pages: find_attribute(attributes, "job-impressions")
+            .and_then(|pages| pages.as_integer().copied()),
+        overrides,
+        quota_consumed: None,
+        page_ranges: options.pages,
+        copies_to_follow: 0,
+    })
+}
+
+/// Checks `printer_name` says it can fetch documents like `document` itself.
+async fn check_fetches(
+    app_data: &AppState,
+    printer_name: &str,
+    printer: &IppClient,
+    document: &Url,
+) -> Result<(), KprintError> {
+    let attributes = app_data.printer_cache.get(printer_name, printer).await?;
+    let print_uri = match find_attribute(&attributes, IppAttribute::OPERATIONS_SUPPORTED) {
+        Some(IppValue::Array(operations)) => {
+            operations.contains(&IppValue::Enum(Operation::PrintUri as i32))
+        }
+        Some(operation) => *operation == IppValue::Enum(Operation::PrintUri as i32),
+        None => false,
+    };
+    let scheme = document.scheme();
+    let message = if !print_uri {
+        format!("{printer_name} can't fetch documents itself")
+    } else if !find_keywords(&attributes, "reference-uri-schemes-supported")
+        .iter()
+        .any(|supported| supported == scheme)
+    {
+        format!("{printer_name} can't fetch {scheme} URLs")
+    } else {
+        return Ok(());
+    };
+    Err(KprintError::Validation(vec![FieldError::new(
+        "url",
+        ErrorCode::UnsupportedValue,
+        message,
+    )]))
+}
+
+/// Takes every copy but the first off `options` when they're to be printed
+/// as jobs of their own, returning how many that is along with a copy of
+/// `document` to print them from.
+async fn separate_copies(
+    options: &mut PrintOptions,
+    document: Document,
+) -> Result<(Document, Option<(u32, Document)>), KprintError> {
+    if !options.separate_jobs || options.copies < 2 {
+        return Ok((document, None));
+    }
+    let document = match document {
+        Document::Streaming(payload) => {
+            let dir = crate::spool::spool_dir().map_err(KprintError::SpoolIo)?;
+            crate::document::spool(payload, &dir).await?
+        }
+        document => document,
+    };
+    let kept = document
+        .try_clone()
+        .expect("only streaming documents can't be cloned");
+    let remaining = options.copies - 1;
+    options.copies = 1;
+    Ok((document, Some((remaining, kept))))
+}
+
+/// Sends another copy of `job`'s document as a job of its own, returning the
+/// new job's id.
+pub async fn print_copy(
+    app_data: &AppState,
+    job: &JobRecord,
+    options: PrintOptions,
+    document: Document,
+) -> Result<Uuid, KprintError> {
+    check_available(app_data, &job.printer).await?;
+    let copy = JobRecord {
+        id: Uuid::new_v4(),
+        ipp_job_id: None,
+        submitted_at: Utc::now(),
+        delivery: Delivery::Spooled,
+        rerouted_from: None,
+        ..job.clone()
+    };
+    let submitted = deliver_watched(app_data, copy, options, document).await?;
+    Ok(submitted.job.id)
+}
+
+/// Sends the same `document` to every printer in `printers` at once, as a
+/// job of its own on each, returning how each went in the same order.
+pub async fn broadcast(
+    app_data: &AppState,
+    username: &str,
+    admin: bool,
+    groups: &[String],
+    printers: Vec<String>,
+    options: PrintOptions,
+    mut document: Document,
+) -> Result<Vec<(String, Result<Submitted, KprintError>)>, KprintError> {
+    // Checked once up front, rather than failing the same way on every printer
+    check_format(&document.head(sniff::HEAD_SIZE).await?)?;
+    let jobs = printers.iter().map(|printer_name| {
+        let document = document
+            .try_clone()
+            .expect("broadcast documents are spooled");
+        submit(
+            app_data,
+            username,
+            admin,
+            groups,
+            printer_name.clone(),
+            options.clone(),
+            document,
+        )
+    });
+    let submitted = join_all(jobs).await;
+    Ok(printers.into_iter().zip(submitted).collect())
+}
+
+/// Sends `document` to `printer_name` for a guest with `code`, as the member
+/// who sponsored it. The pages come out of what the code has left, so only
+/// documents kprint can count the pages of are taken.
+pub async fn submit_as_guest(
+    app_data: &AppState,
+    code: &str,
+    printer_name: String,
+    mut options: PrintOptions,
+    mut document: Document,
+) -> Result<Submitted, KprintError> {
+    let guest = app_data.guests.check(code)?;
+    if !options.tags.iter().any(|tag| tag == "guest") {
+        options.tags.push("guest".to_string());
+    }
+    let (mut job, overrides) =
+        new_job(app_data, &guest.sponsor, false, printer_name, &mut options).await?;
+    check_format(&document.head(sniff::HEAD_SIZE).await?)?;
+    let (document, document_pages) = count_pages(document).await?;
+    let Some(document_pages) = document_pages else {
+        return Err(KprintError::InvalidRequest(
+            "guests can only print PDFs, so their pages can be counted".to_string(),
+        ));
+    };
+    clamp_pages(&mut options, document_pages)?;
+    let (pages, _) = impressions(document_pages, &options);
+    let guest = app_data.guests.reserve(code, pages).await?;
+    job.counted_pages = Some(pages);
+
+    let (document, following) = separate_copies(&mut options, document).await?;
+    let submitted = match deliver_watched(app_data, job, options.clone(), document).await {
+        Ok(submitted) => submitted,
+        Err(err) => {
+            app_data.guests.refund(guest.id, pages).await;
+            return Err(err);
+        }
+    };
+    audit::record(AuditEvent::GuestPrinted {
+        code: guest.id,
+        sponsor: &guest.sponsor,
+        job: submitted.job.id,
+        printer: &submitted.job.printer,
+        pages,
+    });
+    let copies_to_follow = match following {
+        Some((remaining, document)) => {
+            app_data
+                .copies
+                .follow(submitted.job.id, options, document, remaining);
+            remaining
+        }
+        None => 0,
+    };
+    Ok(Submitted {
+        overrides,
+        copies_to_follow,
+        ..submitted
+    })
+}
+
+/// Like [`submit`], but only waits to hear the printer's taking jobs before
+/// answering. `document` is sent on in the background, and how that went is
+/// in the job's status.
+pub async fn submit_spooled(
+    app_data: Data<AppState>,
+    username: &str,
+    admin: bool,
+    groups: &[String],
+    printer_name: String,
+    mut options: PrintOptions,
+    mut document: Document,
+) -> Result<Submitted, KprintError> {
+    let (mut job, overrides) =
+        new_job(&app_data, username, admin, printer_name, &mut options).await?;
+    check_format(&document.head(sniff::HEAD_SIZE).await?)?;
+    let (document, document_pages) = select_pages(&mut options, document).await?;
+    let (document, quota_consumed) = charge_quota(
+        &app_data,
+        &mut job,
+        groups,
+        &options,
+        document,
+        document_pages,
+    )
+    .await?;
+    let page_ranges = options.pages.clone();
+    let (document, following) = match separate_copies(&mut options, document).await {
+        Ok(separated) => separated,
+        Err(err) => {
+            if let Some(units) = quota_consumed {
+                app_data.quotas.refund(username, units);
+            }
+            return Err(err);
+        }
+    };
+    let copies_to_follow = following.as_ref().map_or(0, |(remaining, _)| *remaining);
+    app_data.jobs.insert(job.clone());
+
+    let spooled = job.clone();
+    tokio::spawn(trace::linked("deliver spooled job", async move {
+        // Errors aren't Send, so they can't be held on to past an await
+        let delivered = deliver_watched(&app_data, spooled.clone(), options.clone(), document)
+            .await
+            .map_err(|err| err.to_string());
+        match delivered {
+            Ok(submitted) => {
+                if let Some(units) = quota_consumed {
+                    app_data.quotas.debit(&submitted.job, units).await;
+                }
+                if let Some((remaining, document)) = following {
+                    app_data
+                        .copies
+                        .follow(submitted.job.id, options, document, remaining);
+                }
+            }
+            Err(err) => {
+                log::warn!(
+                    "Couldn't send spooled job {} on to {}: {err}",
+                    spooled.id,
+                    spooled.printer
+                );
+                if let Some(units) = quota_consumed {
+                    app_data.quotas.refund(&spooled.owner, units);
+                }
+                app_data.jobs.insert(JobRecord {
+                    delivery: Delivery::Failed(err),
+                    ..spooled
+                });
+            }
+        }
+    }));
+    Ok(Submitted {
+        job,
+        state: JobState::Pending,
+        pages: None,
+        overrides,
+        quota_consumed,
+        page_ranges,
+        copies_to_follow,
+    })
+}
+
+/// Opens a job on `printer_name` for documents to be added to one at a time,
+/// which prints once it's closed. The printer makes the job now, so mistakes
+/// in the options show up before any documents are sent.
+pub async fn open_job(
+    app_data: &AppState,
+    username: &str,
+    admin: bool,
+    printer_name: String,
+    mut options: PrintOptions,
+) -> Result<Submitted, KprintError> {
+    cancel_expired(app_data);
+    let (mut job, overrides) =
+        new_job(app_data, username, admin, printer_name, &mut options).await?;
+    let printer_name = &*job.printer;
+    let printer = printer(app_data, printer_name)?;
+    let held = app_data
+        .pickup
+        .as_ref()
+        .is_some_and(|pickup| pickup.holds(printer_name));
+    let uri = printer.uri().clone();
+    let retries = app_data.retries.retries;
+    let created = send_retrying(app_data, printer_name, printer, retries, || {
+        create_request(uri.clone(), username, &options, held)
+    })
+    .await;
+    let created = match created {
+        Ok(created) => {
+            app_data.breakers.succeeded(printer_name);
+            created
+        }
+        Err(err) => {
+            if let KprintError::PrinterUnavailable(_) = err {
+                app_data.breakers.failed(printer_name);
+            }
+            return Err(err);
+        }
+    };
+    let ipp_job_id = find_attribute(created.attributes(), IppAttribute::JOB_ID)
+        .and_then(|job_id| job_id.as_integer().copied())
+        .ok_or_else(|| {
+            KprintError::IppProtocol(format!("{printer_name} didn't say which job it made"))
+        })?;
+
+    let packet = Packet::new(job.id, ipp_job_id, options.clone());
+    job.ipp_job_id = Some(ipp_job_id);
+    job.delivery = Delivery::Open;
+    app_data.jobs.insert(job.clone());
+    app_data.packets.open(packet);
+    Ok(Submitted {
+        job,
+        state: job_state(created.attributes()),
+        pages: None,
+        overrides,
+        quota_consumed: None,
+        page_ranges: options.pages,
+        copies_to_follow: 0,
+    })
+}
+
+/// Adds `document` to `username`'s open job `id`, sending it straight on to
+/// the printer. If it doesn't get there, the whole job's canceled, since the
+/// printer can't print the rest without it.
+pub async fn add_document(
+    app_data: &AppState,
+    username: &str,
+    groups: &[String],
+    id: Uuid,
+    mut document: Document,
+) -> Result<(), KprintError> {
+    let mut job = owned_job(app_data, id, username)?;
+    let (packet, _sending) = app_data.packets.lock(id).await?;
+    check_format(&document.head(sniff::HEAD_SIZE).await?)?;
+    let document = scanned(app_data, &job, document).await?;
+    let (counted, charged) = (job.counted_pages, job.quota_units);
+    // The job's earlier documents still have their units set aside, so
+    // they're counted against this one
+    let (mut document, units) =
+        charge_quota(app_data, &mut job, groups, &packet.options, document, None).await?;
+    if let (Some(counted), Some(pages)) = (counted, job.counted_pages) {
+        job.counted_pages = Some(counted + pages);
+    }
+    if let (Some(mut charged), Some(units)) = (charged, job.quota_units) {
+        quota::add(&mut charged, units);
+        job.quota_units = Some(charged);
+    }
+    let refund = || {
+        if let Some(units) = units {
+            app_data.quotas.refund(username, units);
+        }
+    };
+    let printer_name = &*job.printer;
+    let printer = printer(app_data, printer_name).inspect_err(|_| refund())?;
+    let format = document_format(app_data, printer_name, printer, &mut document)
+        .await
+        .inspect_err(|_| refund())?;
+
+    let unfinished = Unfinished {
+        printer: printer.clone(),
+        printer_name: printer_name.to_string(),
+        username: username.to_string(),
+        job_id: packet.ipp_job_id,
+        done: false,
+    };
+    let uri = printer.uri().clone();
+    let request = send_document_request(
+        uri,
+        packet.ipp_job_id,
+        document.payload(),
+        username,
+        format,
+        false,
+    );
+    let err = match printer.send(request).await {
+        Ok(response) if response.header().status_code().is_success() => {
+            unfinished.finish();
+            app_data.jobs.insert(job);
+            app_data.packets.added(id, units);
+            return Ok(());
+        }
+        Ok(response) => {
+            unfinished.cancel().await;
+            let condition = condition_after_refusal(app_data, printer_name, printer).await;
+            refused(printer_name, &response, condition)
+        }
+        Err(err) => {
+            log::warn!("A document didn't all get to {printer_name} for job {id}: {err}");
+            unfinished.cancel().await;
+            match retry::is_transient(&err) {
+                true => KprintError::PrinterUnavailable(printer_name.to_string()),
+                false => err.into(),
+            }
+        }
+    };
+    refund();
+    abandon(app_data, job, &packet, &err);
+    Err(err)
+}
+
+/// Closes `username`'s open job `id`, telling the printer it has every
+/// document, so it prints them.
+pub async fn close_job(
+    app_data: &AppState,
+    username: &str,
+    id: Uuid,
+) -> Result<JobStatus, KprintError> {
+    let mut job = owned_job(app_data, id, username)?;
+    let (packet, _sending) = app_data.packets.lock(id).await?;
+    if packet.documents == 0 {
+        return Err(KprintError::InvalidRequest(
+            "Add a document to the job before closing it".to_string(),
+        ));
+    }
+    let printer_name = &*job.printer;
+    let printer = printer(app_data, printer_name)?;
+    let uri = printer.uri().clone();
+    let request = send_document_request(
+        uri,
+        packet.ipp_job_id,
+        IppPayload::empty(),
+        username,
+        None,
+        true,
+    );
+    let unfinished = Unfinished {
+        printer: printer.clone(),
+        printer_name: printer_name.to_string(),
+        username: username.to_string(),
+        job_id: packet.ipp_job_id,
+        done: false,
+    };
+    let err = match printer.send(request).await {
+        Ok(response) if response.header().status_code().is_success() => {
+            unfinished.finish();
+            app_data.packets.remove(&id);
+            job.delivery = Delivery::Delivered;
+            app_data.jobs.insert(job.clone());
+            let state = job_state(response.attributes());
+            record_history(app_data, &job, &packet.options, state, None);
+            if let Some(units) = packet.quota_consumed {
+                app_data.quotas.debit(&job, units).await;
+            }
+            return Ok(status_of(job, response.attributes()));
+        }
+        Ok(response) => {
+            unfinished.cancel().await;
+            let condition = condition_after_refusal(app_data, printer_name, printer).await;
+            refused(printer_name, &response, condition)
+        }
+        Err(err) => {
+            log::warn!("Couldn't tell {printer_name} job {id} has all its documents: {err}");
+            unfinished.cancel().await;
+            match retry::is_transient(&err) {
+                true => KprintError::PrinterUnavailable(printer_name.to_string()),
+                false => err.into(),
+            }
+        }
+    };
+    abandon(app_data, job, &packet, &err);
+    Err(err)
+}
+
+/// Gives up on open job `job`, which `packet` is the rest of, because of
+/// `err`, once it's been canceled on the printer.
+fn abandon(app_data: &AppState, mut job: JobRecord, packet: &Packet, err: &KprintError) {
+    app_data.packets.remove(&job.id);
+    if let Some(units) = packet.quota_consumed {
+        app_data.quotas.refund(&job.owner, units);
+    }
+    job.delivery = Delivery::Failed(err.to_string());
+    app_data.jobs.insert(job.clone());
+    record_history(
+        app_data,
+        &job,
+        &packet.options,
+        JobState::Aborted,
+        Some(err),
+    );
+}
+
+/// Cancels every job that's been left open too long, in the background.
+fn cancel_expired(app_data: &AppState) {
+    for packet in app_data.packets.expired() {
+        let Some(job) = app_data.jobs.get(&packet.id) else {
+            continue;
+        };
+        log::info!(
+            "Canceling job {}, which {} left open without another document",
+            job.id,
+            Pii(&job.owner)
+        );
+        if let Ok(printer) = printer(app_data, &job.printer) {
+            let cancel = cancel_unfinished(
+                printer.clone(),
+                job.printer.clone(),
+                job.owner.clone(),
+                packet.ipp_job_id,
+            );
+            tokio::spawn(trace::linked("cancel abandoned job", cancel));
+        }
+        let err = KprintError::InvalidRequest(
+            "The job was left open too long without another document".to_string(),
+        );
+        abandon(app_data, job, &packet, &err);
+    }
+}
+
+/// Checks `printer_name` can take a job, forces whatever it forces onto
+/// `options`, and starts a record of it.
+async fn new_job(
+    app_data: &AppState,
+    username: &str,
+    admin: bool,
+    printer_name: String,
+    options: &mut PrintOptions,
+) -> Result<(JobRecord, Vec<AppliedOverride>), KprintError> {
+    printer(app_data, &printer_name)?;
+    if let Some(suspension) = app_data.suspensions.get(username) {
+        return Err(KprintError::UserSuspended {
+            reason: suspension.reason,
+            until: suspension.until,
+        });
+    }
+    if let Some(eligibility) = app_data.eligibility.as_ref().filter(|_| !admin) {
+        eligibility.check(username).await?;
+    }
+    check_available(app_data, &printer_name).await?;
+    envelope_options(app_data, &printer_name, options).await?;
+    check_media(app_data, &printer_name, options).await?;
+    let overrides = app_data.overrides.apply(&printer_name, options, admin);
+    for AppliedOverride {
+        option,
+        requested,
+        applied,
+    } in &overrides
+    {
+        log::info!(
+            "{printer_name} printed {}'s job with {option} {applied}, not {requested}",
+            Pii(username)
+        );
+    }
+    let job = JobRecord {
+        // Picked up front so the cover sheet can link to the job
+        id: Uuid::new_v4(),
+        printer: printer_name,
+        ipp_job_id: None,
+        owner: username.to_string(),
+        title: options.title.clone(),
+        submitted_at: Utc::now(),
+        delivery: Delivery::Spooled,
+        rerouted_from: None,
+        counted_pages: None,
+        quota_units: None,
+        tags: options.tags.clone(),
+    };
+    Ok((job, overrides))
+}
+
+/// Delivers `job`, holding on to `document` to send elsewhere if it asked to
+/// be and there's somewhere else to send it.
+async fn deliver_watched(
+    app_data: &AppState,
+    job: JobRecord,
+    options: PrintOptions,
+    document: Document,
+) -> Result<Submitted, KprintError> {
+    if !options.allow_fallback
+        || !app_data.flags.enabled(Flag::Fallback)
+        || app_data.fallback.alternates(&job.printer).is_empty()
+    {
+        return deliver(app_data, job, options, document).await;
+    }
+    let document = match document {
+        Document::Streaming(payload) => {
+            crate::document::spool(payload, app_data.fallback.dir()).await?
+        }
+        document => document,
+    };
+    let kept = document.try_clone();
+    let submitted = deliver(app_data, job, options.clone(), document).await?;
+    if let Some(kept) = kept {
+        let job = &submitted.job;
+        app_data.fallback.watch(job.id, &job.printer, options, kept);
+    }
+    Ok(submitted)
+}
+
+/// Sends `job`, which failed on its printer, to `printer_name` instead, if
+/// it's taking jobs and can print it the way it was asked for.
+pub async fn reroute(
+    app_data: &AppState,
+    job: &JobRecord,
+    printer_name: &str,
+    options: &PrintOptions,
+    document: Document,
+) -> Result<(), KprintError> {
+    check_available(app_data, printer_name).await?;
+    // Whoever sent it isn't around to say if they're an admin
+    let mut options = options.clone();
+    app_data.overrides.apply(printer_name, &mut options, false);
+    if !supports(app_data, printer_name, &options).await? {
+        return Err(KprintError::JobRejected(format!(
+            "{printer_name} can't print it the same way"
+        )));
+    }
+    let rerouted = JobRecord {
+        printer: printer_name.to_string(),
+        ipp_job_id: None,
+        rerouted_from: Some(
+            job.rerouted_from
+                .clone()
+                .unwrap_or_else(|| job.printer.clone()),
+        ),
+        ..job.clone()
+    };
+    deliver(app_data, rerouted, options, document).await?;
+    log::info!(
+        "Job {} failed on {}, so it was sent to {printer_name} instead",
+        job.id,
+        job.printer
+    );
+    Ok(())
+}
+
+/// Whether `name` says it can print on the sides and in the color `options`
+/// ask for. Printers that don't say are assumed to.
+async fn supports(
+    app_data: &AppState,
+    name: &str,
+    options: &PrintOptions,
+) -> Result<bool, KprintError> {
+    let printer = printer(app_data, name)?;
+    let attributes = app_data.printer_cache.get(name, printer).await?;
+    let offers = |attribute: &str, value: &str| {
+        let supported = find_keywords(&attributes, attribute);
+        supported.is_empty() || supported.iter().any(|supported| supported == value)
+    };
+    let sides = serde_variant::to_variant_name(&options.sides).unwrap();
+    // Anything can print in grayscale, whatever it calls it
+    let color =
+        options.color_mode != ColorMode::Color || offers("print-color-mode-supported", "color");
+    Ok(offers("sides-supported", sides) && color)
+}
+
+/// Adds how `job` went to the history, replacing anything about it there
+/// already.
+fn record_history(
+    app_data: &AppState,
+    job: &JobRecord,
+    options: &PrintOptions,
+    state: JobState,
+    failure: Option<&KprintError>,
+) {
+    app_data.history.record(HistoryEntry {
+        id: job.id,
+        owner: job.owner.clone(),
+        printer: job.printer.clone(),
+        title: job.title.clone(),
+        submitted_at: job.submitted_at,
+        sides: options.sides,
+        color_mode: options.color_mode,
+        copies: options.copies,
+        pages: None,
+        counted_pages: job.counted_pages,
+        quota_units: job.quota_units,
+        state,
+        failure: failure.map(ToString::to_string),
+        tags: job.tags.clone(),
+    });
+}
+
+/// `document`, once the virus scanner's passed it, if there is one.
+async fn scanned(
+    app_data: &AppState,
+    job: &JobRecord,
+    document: Document,
+) -> Result<Document, KprintError> {
+    let Some(scanner) = &app_data.scanner else {
+        return Ok(document);
+    };
+    match scanner.scan(document).await {
+        Err(KprintError::DocumentInfected(signature)) => {
+            audit::record(AuditEvent::InfectedDocument {
+                username: &job.owner,
+                printer: &job.printer,
+                title: &job.title,
+                signature: &signature,
+            });
+            Err(KprintError::DocumentInfected(signature))
+        }
+        Err(KprintError::ScannerUnavailable(error)) => {
+            audit::record(AuditEvent::ScanFailed {
+                username: &job.owner,
+                printer: &job.printer,
+                title: &job.title,
+                error: &error,
+            });
+            Err(KprintError::ScannerUnavailable(error))
+        }
+        scanned => scanned,
+    }
+}
+
+/// Gets `document` to the printer as `job`, and records that it did.
+async fn deliver(
+    app_data: &AppState,
+    mut job: JobRecord,
+    mut options: PrintOptions,
+    document: Document,
+) -> Result<Submitted, KprintError> {
+    let printer = printer(app_data, &job.printer)?;
+    let (id, username, printer_name, title) = (job.id, &*job.owner, &*job.printer, &*job.title);
+    // Before any cover sheet moves them along
+    let page_ranges = options.pages.clone();
+
+    let document = scanned(app_data, &job, document).await?;
+    // Previews are of the document as it was sent, without anything added.
+    // Only spooled and buffered documents can be read again for one, so
+    // streamed ones go straight through.
+    let preview = app_data
+        .previews
+        .as_ref()
+        .and_then(|_| document.try_clone());
+    let document = match options.poster {
+        Some(poster) => {
+            let tile = move |pdf: &[u8]| poster::tile(pdf, poster);
+            let (document, tiled) = rewrite_pdf(document, "poster", tile).await?;
+            if tiled {
+                options.pages = poster_sheets(options.pages, poster.sheets() as i32);
+            }
+            document
+        }
+        None => document,
+    };
+    let document = match app_data.pdf_margins.applies_to(printer_name) {
+        true => {
+            let margins = options.margins.take().or_else(|| {
+                options
+                    .media
+                    .as_mut()
+                    .and_then(|media| media.margins.take())
+            });
+            options.media = options.media.filter(|media| *media != Media::default());
+            match (margins, options.offset.take()) {
+                (None, None) => document,
+                (margins, offset) => {
+                    let (margins, offset) =
+                        (margins.unwrap_or_default(), offset.unwrap_or_default());
+                    let fit = move |pdf: &[u8]| margins::fit(pdf, margins, offset);
+                    rewrite_pdf(document, "margins", fit).await?.0
+                }
+            }
+        }
+        false => document,
+    };
+    let document = match app_data.stamps.get(printer_name) {
+        Some(style) => {
+            let username = username.to_string();
+            let stamp = move |pdf: &[u8]| stamp::stamp(pdf, style, &username, Local::now());
+            rewrite_pdf(document, "stamp", stamp).await?.0
+        }
+        None => document,
+    };
+    let document = if options.cover_sheet {
+        let cover = Cover {
+            job: id,
+            username: username.to_string(),
+            title: title.to_string(),
+            printer: printer_name.to_string(),
+            at: Local::now(),
+        };
+        let blank_back = options.sides != DuplexMode::OneSided;
+        let covers = app_data.covers.clone();
+        let prepend = move |pdf: &[u8]| covers.prepend(pdf, &cover, blank_back);
+        let (document, covered) = rewrite_pdf(document, "cover sheet", prepend).await?;
+        if covered {
+            options.pages = behind_cover(options.pages, 1 + blank_back as i32);
+        }
+        document
+    } else {
+        document
+    };
+
+    let mut document = document;
+    let format = document_format(app_data, printer_name, printer, &mut document).await?;
+    let held = app_data
+        .pickup
+        .as_ref()
+        .is_some_and(|pickup| pickup.holds(printer_name));
+    // Copied before sending uses it up
+    let mirrored = app_data.mirror.sample(id).map(|sample| {
+        let copy = document.try_clone().filter(|_| sample.documents);
+        (sample, copy)
+    });
+    let open = OpenJob {
+        id,
+        printer_name,
+        username,
+        options: &options,
+        format,
+        held,
+    };
+    let response = create_and_send(app_data, printer, open, document).await;
+    if let Some((sample, copy)) = mirrored {
+        let uri = sample.client.uri().clone();
+        // Nobody's there to pick it up
+        let request = match copy {
+            Some(mut copy) => print_request(uri, copy.payload(), username, &options, format, false),
+            None => validate_request(uri, username, &options, format),
+        };
+        sample.send(id, printer_name, response.is_ok(), request);
+    }
+    let response = match response {
+        Ok(response) => {
+            app_data.breakers.succeeded(printer_name);
+            response
+        }
+        Err(err) => {
+            if let KprintError::PrinterUnavailable(_) = err {
+                app_data.breakers.failed(printer_name);
+            }
+            record_history(app_data, &job, &options, JobState::Aborted, Some(&err));
+            return Err(err);
+        }
+    };
+    let attributes = response.attributes();
+    let job_id = find_attribute(attributes, IppAttribute::JOB_ID)
+        .and_then(|job_id| job_id.as_integer().copied());
+
+    log::debug!(
+        "Reply from print server! Header: {:?} Attributes {:?} Payload {:?}",
+        response.header(),
+        response.attributes(),
+        response.to_bytes()
+    );
+
+    job.ipp_job_id = job_id;
+    job.delivery = Delivery::Delivered;
+    app_data.jobs.insert(job.clone());
+    record_history(app_data, &job, &options, job_state(attributes), None);
+    if let (Some(previews), Some(document)) = (&app_data.previews, preview) {
+        previews.render_later(id, document);
+    }
+
+    Ok(Submitted {
+        job,
+        state: job_state(attributes),
+        pages: find_attribute(attributes, "job-impressions")
+            .and_then(|pages| pages.as_integer().copied()),
+        overrides: vec![],
+        quota_consumed: None,
+        page_ranges,
+        copies_to_follow: 0,
+    })
+}
+
+/// The `document-format` to send `document` to `printer_name` with: what it
+/// looks like, as long as the printer says it takes that. Otherwise it's left
+/// out, and CUPS works out the type itself.
+async fn document_format(
+    app_data: &AppState,
+    printer_name: &str,
+    printer: &IppClient,
+    document: &mut Document,
+) -> Result<Option<&'static str>, KprintError> {
+    let Ok(format) = sniff::detect(&document.head(sniff::HEAD_SIZE).await?) else {
+        return Ok(None);
+    };
+    let attributes = match app_data.printer_cache.get(printer_name, printer).await {
+        Ok(attributes) => attributes,
+        Err(err) => {
+            log::debug!("Couldn't check what formats {printer_name} takes: {err}");
+            return Ok(None);
+        }
+    };
+    let supported = find_keywords(&attributes, IppAttribute::DOCUMENT_FORMAT_SUPPORTED);
+    if !supported
+        .iter()
+        .any(|supported| supported == format.media_type)
+    {
+        log::debug!(
+            "{printer_name} doesn't say it takes {}, leaving CUPS to work it out",
+            format.media_type
+        );
+        return Ok(None);
+    }
+    Ok(Some(format.media_type))
+}
+
+/// What `printer_name` answering with a failed `response` means for whoever
+/// asked.
+fn refused(
+    printer_name: &str,
+    response: &IppRequestResponse,
+    condition: PrinterCondition,
+) -> KprintError {
+    KprintError::PrinterRefused {
+        printer: printer_name.to_string(),
+        status: response.header().status_code(),
+        status_message: find_attribute(response.attributes(), IppAttribute::STATUS_MESSAGE)
+            .map(ToString::to_string),
+        condition,
+    }
+}
+
+/// How `printer_name` is doing right after it refused a job, since what's
+/// wrong with the printer is often why.
+async fn condition_after_refusal(
+    app_data: &AppState,
+    printer_name: &str,
+    printer: &IppClient,
+) -> PrinterCondition {
+    match app_data.printer_cache.refresh(printer_name, printer).await {
+        Ok(attributes) => PrinterCondition::of(&status_from(app_data, printer_name, &attributes)),
+        Err(err) => {
+            log::debug!("Couldn't check how {printer_name} is doing after it refused a job: {err}");
+            PrinterCondition::default()
+        }
+    }
+}
+
+/// Sends the Print-Job `build` makes for `document`, trying again after
+/// failures that might clear up, if the document can be sent again. The
+/// printer turning it down for good comes back as
+/// [`KprintError::PrinterRefused`].
+async fn print_job<R: Into<IppRequestResponse>>(
+    app_data: &AppState,
+    printer_name: &str,
+    printer: &IppClient,
+    mut document: Document,
+    build: impl Fn(IppPayload) -> R,
+) -> Result<IppRequestResponse, KprintError> {
+    let retries = match document.replayable() {
+        true => app_data.retries.retries,
+        false => 0,
+    };
+    send_retrying(app_data, printer_name, printer, retries, || {
+        build(document.payload())
+    })
+    .await
+}
+
+/// A job to be made on a printer before its document's sent.
+struct OpenJob<'a> {
+    /// kprint's id for it
+    id: Uuid,
+    printer_name: &'a str,
+    username: &'a str,
+    options: &'a PrintOptions,
+    format: Option<&'a str>,
+    held: bool,
+}
+
+/// Sends `document` as a Create-Job and then a Send-Document, so the job's
+/// id is known before any of the document is, and a spooled job can be
+/// looked up and canceled while it's still being sent. If the document
+/// never all gets there, because whoever's uploading it hung up or it didn't
+/// match its digest, the job's canceled, rather than left for the printer to
+/// print as much of it as there was. Documents that can be read again are
+/// sent again as a new job after failures that might clear up. Printers that
+/// can't Create-Job are sent a Print-Job instead.
+async fn create_and_send(
+    app_data: &AppState,
+    printer: &IppClient,
+    job: OpenJob<'_>,
+    mut document: Document,
+) -> Result<IppRequestResponse, KprintError> {
+    let OpenJob {
+        id,
+        printer_name,
+        username,
+        options,
+        format,
+        held,
+    } = job;
+    let uri = printer.uri().clone();
+    let policy = &app_data.retries;
+    let retries = match document.replayable() {
+        true => policy.retries,
+        false => 0,
+    };
+    let mut retry = 0;
+    loop {
+        // Nothing's been read yet, so it can be tried again like anything else
+        let created = send_retrying(app_data, printer_name, printer, policy.retries, || {
+            create_request(uri.clone(), username, options, held)
+        });
+        // Errors aren't Send, so they can't be held on to past an await
+        let created = match created.await {
+            Err(KprintError::PrinterRefused {
+                status: ipp::model::StatusCode::ServerErrorOperationNotSupported,
+                ..
+            }) => None,
+            created => Some(created?),
+        };
+        let Some(created) = created else {
+            log::debug!("{printer_name} can't Create-Job, sending a Print-Job instead");
+            return print_job(app_data, printer_name, printer, document, |document| {
+                print_request(uri.clone(), document, username, options, format, held)
+            })
+            .await;
+        };
+        let job_id = find_attribute(created.attributes(), IppAttribute::JOB_ID)
+            .and_then(|job_id| job_id.as_integer().copied())
+            .ok_or_else(|| {
+                KprintError::IppProtocol(format!("{printer_name} didn't say which job it made"))
+            })?;
+        app_data.jobs.opened(&id, job_id);
+
+        let unfinished = Unfinished {
+            printer: printer.clone(),
+            printer_name: printer_name.to_string(),
+            username: username.to_string(),
+            job_id,
+            done: false,
+        };
+        let request = send_document_request(
+            uri.clone(),
+            job_id,
+            document.payload(),
+            username,
+            format,
+            true,
+        );
+        let failure = match printer.send(request).await {
+            Ok(response) if retry::is_transient_status(response.header().status_code()) => {
+                unfinished.cancel().await;
+                response.header().status_code().to_string()
+            }
+            Ok(response) if !response.header().status_code().is_success() => {
+                unfinished.cancel().await;
+                let condition = condition_after_refusal(app_data, printer_name, printer).await;
+                return Err(refused(printer_name, &response, condition));
+            }
+            Ok(response) => {
+                unfinished.finish();
+                return Ok(response);
+            }
+            Err(err) if retry::is_transient(&err) => {
+                log::warn!("Job {job_id}'s document didn't all get to {printer_name}: {err}");
+                unfinished.cancel().await;
+                err.to_string()
+            }
+            Err(err) => {
+                log::warn!("Job {job_id}'s document didn't all get to {printer_name}: {err}");
+                unfinished.cancel().await;
+                return Err(err.into());
+            }
+        };
+        if retry == retries {
+            log::warn!("Giving up on sending a job to {printer_name}, after: {failure}");
+            return Err(KprintError::PrinterUnavailable(printer_name.to_string()));
+        }
+        let backoff = policy.backoff(retry);
+        log::info!(
+            "Sending a job's document to {printer_name} failed, sending it as a new job in {backoff:?}: {failure}"
+        );
+        tokio::time::sleep(backoff).await;
+        retry += 1;
+    }
+}
+
+/// A job made on a printer that hasn't been sent all of its document yet.
+/// It's canceled if it's dropped before it has been, which is what happens
+/// when whoever's uploading it hangs up and takes the request with them.
+struct Unfinished {
+    printer: IppClient,
+    printer_name: String,
+    username: String,
+    job_id: i32,
+    done: bool,
+}
+
+impl Unfinished {
+    fn finish(mut self) {
+        self.done = true;
+    }
+
+    async fn cancel(mut self) {
+        self.canceling().await;
+    }
+
+    /// Cancels the job, with nothing left for dropping it to do.
+    fn canceling(&mut self) -> impl Future<Output = ()> + Send + 'static {
+        self.done = true;
+        let (printer, job_id) = (self.printer.clone(), self.job_id);
+        let printer_name = std::mem::take(&mut self.printer_name);
+        let username = std::mem::take(&mut self.username);
+        cancel_unfinished(printer, printer_name, username, job_id)
+    }
+}
+
+impl Drop for Unfinished {
+    fn drop(&mut self) {
+        if !self.done {
+            let cancel = self.canceling();
+            tokio::spawn(trace::linked("cancel unfinished job", cancel));
+        }
+    }
+}
+
+async fn cancel_unfinished(
+    printer: IppClient,
+    printer_name: String,
+    username: String,
+    job_id: i32,
+) {
+    let operation = IppOperationBuilder::cancel_job(printer.uri().clone(), job_id)
+        .user_name(&username)
+        .build();
+    match printer.send(operation).await {
+        Ok(response) if response.header().status_code().is_success() => {
+            log::info!("Canceled job {job_id} on {printer_name}, since its document never all arrived");
+        }
+        Ok(response) => log::warn!(
+            "{printer_name} wouldn't cancel job {job_id}, whose document never all arrived: {}",
+            response.header().status_code()
+        ),
+        Err(err) => log::warn!(
+            "Couldn't cancel job {job_id} on {printer_name}, whose document never all arrived: {err}"
+        ),
+    }
+}
+
+/// Sends what `build` makes, trying again up to `retries` times after
+/// failures that might clear up. The printer turning it down for good comes
+/// back as [`KprintError::PrinterRefused`].
+async fn send_retrying<R: Into<IppRequestResponse>>(
+    app_data: &AppState,
+    printer_name: &str,
+    printer: &IppClient,
+    retries: u32,
+    mut build: impl FnMut() -> R,
+) -> Result<IppRequestResponse, KprintError> {
+    let policy = &app_data.retries;
+    let mut retry = 0;
+    loop {
+        log::debug!("Sending operation to printer!");
+        let failure = match printer.send(build()).await {
+            Ok(response) if retry::is_transient_status(response.header().status_code()) => {
+                response.header().status_code().to_string()
+            }
+            Ok(response) if !response.header().status_code().is_success() => {
+                let condition = condition_after_refusal(app_data, printer_name, printer).await;
+                return Err(refused(printer_name, &response, condition));
+            }
+            Ok(response) => return Ok(response),
+            Err(err) if retry::is_transient(&err) => err.to_string(),
+            Err(err) => return Err(err.into()),
+        };
+        if retry == retries {
+            log::warn!("Giving up on sending a job to {printer_name}, after: {failure}");
+            return Err(KprintError::PrinterUnavailable(printer_name.to_string()));
+        }
+        let backoff = policy.backoff(retry);
+        log::info!(
+            "Sending a job to {printer_name} failed, trying again in {backoff:?}: {failure}"
+        );
+        tokio::time::sleep(backoff).await;
+        retry += 1;
+    }
+}
+
+/// Looks up a job on behalf of `username`. Other people's jobs look exactly
+/// like missing ones, so ids can't be probed.
+fn owned_job(app_data: &AppState, id: Uuid, username: &str) -> Result<JobRecord, KprintError> {
+    match app_data.jobs.get(&id) {
+        Some(job) if job.owner == username => Ok(job),
+        _ => Err(KprintError::JobNotFound(id)),
+    }
+}
+
+/// The preview of `username`'s job, if there's one yet.
+pub fn job_preview(app_data: &AppState, username: &str, id: Uuid) -> Result<Bytes, KprintError> {
+    owned_job(app_data, id, username)?;
+    app_data
+        .previews
+        .as_ref()
+        .and_then(|previews| previews.get(&id))
+        .ok_or(KprintError::PreviewNotFound(id))
+}
+
+/// The receipt for `username`'s job, from the history, so it's there long
+/// after the printer's forgotten the job.
+pub fn job_receipt(
+    app_data: &AppState,
+    username: &str,
+    id: Uuid,
+) -> Result<JobReceipt, KprintError> {
+    match app_data.history.get(&id) {
+        Some(entry) if entry.owner == username => Ok(receipt::of(app_data, entry)),
+        _ => Err(KprintError::JobNotFound(id)),
+    }
+}
+
+/// Asks the printer how `username`'s job is doing, going by its queue as of
+/// a moment ago while the job's on it.
+pub async fn job_status(
+    app_data: &AppState,
+    username: &str,
+    id: Uuid,
+) -> Result<JobStatus, KprintError> {
+    status(app_data, username, id, false).await
+}
+
+/// Like [`job_status`], but asking the printer now, for admins checking
+/// kprint and the printer agree.
+pub async fn fresh_job_status(
+    app_data: &AppState,
+    username: &str,
+    id: Uuid,
+) -> Result<JobStatus, KprintError> {
+    status(app_data, username, id, true).await
+}
+
+async fn status(
+    app_data: &AppState,
+    username: &str,
+    id: Uuid,
+    fresh: bool,
+) -> Result<JobStatus, KprintError> {
+    let job = owned_job(app_data, id, username)?;
+    let Some(ipp_job_id) = job.ipp_job_id else {
+        // Without the printer's id there's nothing to ask about
+        return Ok(status_of(job, &IppAttributes::new()));
+    };
+    let printer = printer(app_data, &job.printer)?;
+
+    if !fresh {
+        match app_data.queue_cache.get(&job.printer, printer).await {
+            Ok(jobs) => {
+                if let Some(attributes) = queue_cache::find_job(&jobs, ipp_job_id) {
+                    let status = status_of(job, &attributes);
+                    observe(app_data, &status);
+                    return Ok(status);
+                }
+            }
+            Err(err) => log::debug!("Couldn't get {}'s queue: {err}", job.printer),
+        }
+    }
+    // Finished jobs are off the queue, so they're asked about one at a time
+    let operation = IppOperationBuilder::get_job_attributes(printer.uri().clone(), ipp_job_id)
+        .user_name(username)
+        .build();
+    let response = printer.send(operation).await?;
+    match response.header().status_code() {
+        status if status.is_success() => {
+            let status = status_of(job, response.attributes());
+            observe(app_data, &status);
+            Ok(status)
+        }
+        ipp::model::StatusCode::ClientErrorNotFound => Err(KprintError::JobNotFound(id)),
+        _ => Err(refused(
+            &job.printer,
+            &response,
+            PrinterCondition::default(),
+        )),
+    }
+}
+
+/// Records `status` in the history, confirming the job with its owner if
+/// that's it finished.
+fn observe(app_data: &AppState, status: &JobStatus) {
+    if let Some(entry) = app_data.history.observe(status) {
+        confirmations::finished(app_data, entry);
+    }
+}
+
+pub async fn cancel_job(app_data: &AppState, username: &str, id: Uuid) -> Result<(), KprintError> {
+    let job = owned_job(app_data, id, username)?;
+    cancel(app_data, username, &job).await
+}
+
+/// Cancels a job on behalf of an admin, whoever it belongs to.
+pub async fn cancel_any_job(app_data: &AppState, admin: &str, id: Uuid) -> Result<(), KprintError> {
+    let job = app_data.jobs.get(&id).ok_or(KprintError::JobNotFound(id))?;
+    cancel(app_data, admin, &job).await?;
+    audit::record(AuditEvent::AdminCanceledJob {
+        admin,
+        job: id,
+        owner: &job.owner,
+        printer: &job.printer,
+    });
+    Ok(())
+}
+
+async fn cancel(app_data: &AppState, username: &str, job: &JobRecord) -> Result<(), KprintError> {
+    let id = job.id;
+    let ipp_job_id = job.ipp_job_id.ok_or_else(|| {
+        KprintError::IppProtocol(format!("The printer never told us job {id}'s id"))
+    })?;
+    let printer = printer(app_data, &job.printer)?;
+
+    let operation = IppOperationBuilder::cancel_job(printer.uri().clone(), ipp_job_id)
+        .user_name(username)
+        .build();
+    let response = printer.send(operation).await?;
+    app_data.queue_cache.forget(&job.printer);
+    match response.header().status_code() {
+        status if status.is_success() => {
+            if let Some(packet) = app_data.packets.remove(&id) {
+                if let Some(units) = packet.quota_consumed {
+                    app_data.quotas.refund(&job.owner, units);
+                }
+                // The printer can say how it went from here
+                let job = JobRecord {
+                    delivery: Delivery::Delivered,
+                    ..job.clone()
+                };
+                app_data.jobs.insert(job.clone());
+                record_history(app_data, &job, &packet.options, JobState::Canceled, None);
+            }
+            Ok(())
+        }
+        ipp::model::StatusCode::ClientErrorNotFound => Err(KprintError::JobNotFound(id)),
+        ipp::model::StatusCode::ClientErrorNotPossible => Err(KprintError::JobNotCancelable(id)),
+        _ => Err(refused(
+            &job.printer,
+            &response,
+            PrinterCondition::default(),
+        )),
+    }
+}
+
+/// The job a pickup link is for, and how it's doing.
+pub async fn pickup_status(app_data: &AppState, token: &str) -> Result<JobStatus, KprintError> {
+    let job = picked_up(app_data, token)?;
+    job_status(app_data, &job.owner, job.id).await
+}
+
+/// Prints the held job a pickup link is for.
+pub async fn release_job(app_data: &AppState, token: &str) -> Result<(), KprintError> {
+    let job = picked_up(app_data, token)?;
+    let id = job.id;
+    let ipp_job_id = job.ipp_job_id.ok_or_else(|| {
+        KprintError::IppProtocol(format!("The printer never told us job {id}'s id"))
+    })?;
+    let printer = printer(app_data, &job.printer)?;
+
+    let mut request = IppRequestResponse::new(
+        IppVersion::v1_1(),
+        Operation::ReleaseJob,
+        Some(printer.uri().clone()),
+    );
+    for attribute in [
+        IppAttribute::new(IppAttribute::JOB_ID, IppValue::Integer(ipp_job_id)),
+        IppAttribute::new(
+            IppAttribute::REQUESTING_USER_NAME,
+            IppValue::NameWithoutLanguage(job.owner.clone()),
+        ),
+    ] {
+        request
+            .attributes_mut()
+            .add(DelimiterTag::OperationAttributes, attribute);
+    }
+    let response = printer.send(request).await?;
+    app_data.queue_cache.forget(&job.printer);
+    match response.header().status_code() {
+        status if status.is_success() => {}
+        ipp::model::StatusCode::ClientErrorNotFound => return Err(KprintError::JobNotFound(id)),
+        ipp::model::StatusCode::ClientErrorNotPossible => return Err(KprintError::JobNotHeld(id)),
+        _ => {
+            return Err(refused(
+                &job.printer,
+                &response,
+                PrinterCondition::default(),
+            ))
+        }
+    }
+    audit::record(AuditEvent::JobReleased {
+        job: id,
+        owner: &job.owner,
+        printer: &job.printer,
+    });
+    Ok(())
+}
+
+/// The job `token` is a pickup link for.
+fn picked_up(app_data: &AppState, token: &str) -> Result<JobRecord, KprintError> {
+    let pickup = app_data
+        .pickup
+        .as_ref()
+        .ok_or(KprintError::PickupLinkInvalid)?;
+    let id = pickup.verify(token)?;
+    app_data.jobs.get(&id).ok_or(KprintError::JobNotFound(id))
+}
+
+/// Set-Job-Attributes, which the ipp crate has no [`Operation`] for
+const SET_JOB_ATTRIBUTES: u16 = 0x0014;
+/// The highest `job-priority` IPP allows, which puts a job ahead of everything
+/// queued at the default of 50
+const TOP_PRIORITY: i32 = 100;
+
+/// Moves a job that's waiting to print to the front of its printer's queue,
+/// on behalf of an admin.
+pub async fn bump_job(app_data: &AppState, admin: &str, id: Uuid) -> Result<(), KprintError> {
+    let job = app_data.jobs.get(&id).ok_or(KprintError::JobNotFound(id))?;
+    let ipp_job_id = job.ipp_job_id.ok_or_else(|| {
+        KprintError::IppProtocol(format!("The printer never told us job {id}'s id"))
+    })?;
+    let printer = printer(app_data, &job.printer)?;
+
+    // The operation is swapped in after, since the constructor only takes
+    // operations the crate knows about
+    let mut request = IppRequestResponse::new(
+        IppVersion::v1_1(),
+        Operation::GetJobAttributes,
+        Some(printer.uri().clone()),
+    );
+    request.header_mut().operation_or_status = SET_JOB_ATTRIBUTES;
+    for attribute in [
+        IppAttribute::new(IppAttribute::JOB_ID, IppValue::Integer(ipp_job_id)),
+        IppAttribute::new(
+            IppAttribute::REQUESTING_USER_NAME,
+            IppValue::NameWithoutLanguage(admin.to_string()),
+        ),
+    ] {
+        request
+            .attributes_mut()
+            .add(DelimiterTag::OperationAttributes, attribute);
+    }
+    request.attributes_mut().add(
+        DelimiterTag::JobAttributes,
+        IppAttribute::new("job-priority", IppValue::Integer(TOP_PRIORITY)),
+    );
+    let response = printer.send(request).await?;
+    app_data.queue_cache.forget(&job.printer);
+    match response.header().status_code() {
+        status if status.is_success() => {}
+        ipp::model::StatusCode::ClientErrorNotFound => return Err(KprintError::JobNotFound(id)),
+        ipp::model::StatusCode::ClientErrorNotPossible => {
+            return Err(KprintError::JobNotPending(id))
+        }
+        _ => {
+            return Err(refused(
+                &job.printer,
+                &response,
+                PrinterCondition::default(),
+            ))
+        }
+    }
+    audit::record(AuditEvent::AdminBumpedJob {
+        admin,
+        job: id,
+        owner: &job.owner,
+        printer: &job.printer,
+    });
+    Ok(())
+}
+
+/// A printer on the CUPS server that CUPS's own operations can be sent
+/// through, if there are any that aren't null printers.
+fn cups_server(app_data: &AppState) -> Option<&IppClient> {
+    app_data
+        .printers
+        .iter()
+        .filter(|(_, printer)| !printer.is_null())
+        .min_by_key(|(name, _)| *name)
+        .map(|(_, printer)| printer)
+}
+
+/// The CUPS server's default printer, if it has one and it's one of kprint's.
+pub async fn default_printer(app_data: &AppState) -> Result<Option<String>, KprintError> {
+    let Some(cups) = cups_server(app_data) else {
+        return Ok(None);
+    };
+    let default = cups::get_default(cups).await?;
+    Ok(default.filter(|name| app_data.printers.contains_key(name)))
+}
+
+/// Every class of printers on the CUPS server.
+pub async fn printer_classes(app_data: &AppState) -> Result<Vec<PrinterClass>, KprintError> {
+    match cups_server(app_data) {
+        Some(cups) => cups::get_classes(cups).await,
+        None => Ok(vec![]),
+    }
+}
+
+/// Moves a job that's waiting to print over to printer `to`, on behalf of an
+/// admin, for when its printer's jammed or out of toner and the job can't
+/// wait. Both have to be on the CUPS server, since it's CUPS that moves it.
+pub async fn move_job(
+    app_data: &AppState,
+    admin: &str,
+    id: Uuid,
+    to: &str,
+) -> Result<(), KprintError> {
+    let job = app_data.jobs.get(&id).ok_or(KprintError::JobNotFound(id))?;
+    let ipp_job_id = job.ipp_job_id.ok_or_else(|| {
+        KprintError::IppProtocol(format!("The printer never told us job {id}'s id"))
+    })?;
+    let from = printer(app_data, &job.printer)?;
+    let destination = printer(app_data, to)?;
+    if from.is_null() || destination.is_null() {
+        return Err(KprintError::InvalidRequest(
+            "Only jobs on CUPS printers can be moved, and only to other CUPS printers".to_string(),
+        ));
+    }
+
+    let response = cups::move_job(from, ipp_job_id, destination.uri(), admin).await?;
+    match response.header().status_code() {
+        status if status.is_success() => {}
+        ipp::model::StatusCode::ClientErrorNotFound => return Err(KprintError::JobNotFound(id)),
+        ipp::model::StatusCode::ClientErrorNotPossible => {
+            return Err(KprintError::JobNotPending(id))
+        }
+        _ => {
+            return Err(refused(
+                &job.printer,
+                &response,
+                PrinterCondition::default(),
+            ))
+        }
+    }
+    app_data.queue_cache.forget(&job.printer);
+    app_data.queue_cache.forget(to);
+    app_data.jobs.insert(JobRecord {
+        printer: to.to_string(),
+        ..job.clone()
+    });
+    audit::record(AuditEvent::AdminMovedJob {
+        admin,
+        job: id,
+        owner: &job.owner,
+        from: &job.printer,
+        to,
+    });
+    Ok(())
+}
+
+/// Cancels every job still waiting on or printing at `printer_name`, whoever
+/// sent it and whether or not it came through kprint, returning how many were
+/// canceled.
+pub async fn purge_queue(
+    app_data: &AppState,
+    admin: &str,
+    printer_name: &str,
+) -> Result<u32, KprintError> {
+    let printer = printer(app_data, printer_name)?;
+    // Whatever's only just been queued has to go too
+    let jobs = app_data.queue_cache.refresh(printer_name, printer).await?;
+    let ipp_job_ids = jobs
+        .groups_of(DelimiterTag::JobAttributes)
+        .filter_map(
+            |group| match group.attributes().get(IppAttribute::JOB_ID)?.value() {
+                IppValue::Integer(id) => Some(*id),
+                _ => None,
+            },
+        )
+        .collect::<Vec<_>>();
+
+    let mut canceled = 0;
+    for ipp_job_id in ipp_job_ids {
+        let operation = IppOperationBuilder::cancel_job(printer.uri().clone(), ipp_job_id)
+            .user_name(admin)
+            .build();
+        match printer.send(operation).await {
+            Ok(response) if response.header().status_code().is_success() => canceled += 1,
+            // It finished while the others were being canceled
+            Ok(response)
+                if matches!(
+                    response.header().status_code(),
+                    ipp::model::StatusCode::ClientErrorNotFound
+                        | ipp::model::StatusCode::ClientErrorNotPossible
+                ) => {}
+            Ok(response) => log::warn!(
+                "Couldn't cancel job {ipp_job_id} on {printer_name}: {:?}",
+                response.header().status_code()
+            ),
+            Err(err) => log::warn!("Couldn't cancel job {ipp_job_id} on {printer_name}: {err}"),
+        }
+    }
+    app_data.queue_cache.forget(printer_name);
+    audit::record(AuditEvent::AdminPurgedQueue {
+        admin,
+        printer: printer_name,
+        canceled,
+    });
+    Ok(canceled)
+}
@@ -0,0 +1,66 @@
+//! HMAC signatures on links to a job that work without logging in, good
+//! until they expire.
+//!
+//! What's signed starts with what kind of link it is and what it lets
+//! through, so a secret set for more than one kind of link can't turn one
+//! kind into another, and a link for one thing can't be used for another.
+
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// Signatures go in paths and query strings, so they're written without `/`
+/// or `+`
+const BASE64: base64::engine::GeneralPurpose = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+pub struct Signer {
+    secret: Vec<u8>,
+    /// What the links are for, like `b"pickup"`
+    purpose: &'static [u8],
+}
+
+impl Signer {
+    /// Signs with the secret in `var`, or `None` if it isn't set.
+    pub fn from_env(var: &str, purpose: &'static [u8]) -> anyhow::Result<Option<Self>> {
+        let Ok(secret) = std::env::var(var) else {
+            return Ok(None);
+        };
+        if secret.len() < 32 {
+            anyhow::bail!("{var} has to be at least 32 characters");
+        }
+        Ok(Some(Signer {
+            secret: secret.into_bytes(),
+            purpose,
+        }))
+    }
+
+    fn mac(&self, job: Uuid, action: &str, expires: i64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC takes any key");
+        mac.update(self.purpose);
+        mac.update(b"\0");
+        mac.update(action.as_bytes());
+        mac.update(b"\0");
+        mac.update(format!("{}.{expires}", job.simple()).as_bytes());
+        mac
+    }
+
+    /// The signature letting `action` be done to `job`, until `expires`
+    /// seconds since the epoch.
+    pub fn sign(&self, job: Uuid, action: &str, expires: i64) -> String {
+        BASE64.encode(self.mac(job, action, expires).finalize().into_bytes())
+    }
+
+    /// Whether `signature` is what kprint signed `action` on `job` with,
+    /// until `expires`. It's up to the caller whether that's passed.
+    pub fn verify(&self, job: Uuid, action: &str, expires: i64, signature: &str) -> bool {
+        let Ok(signature) = BASE64.decode(signature) else {
+            return false;
+        };
+        // Checked in constant time, so the signature can't be guessed a byte
+        // at a time
+        self.mac(job, action, expires)
+            .verify_slice(&signature)
+            .is_ok()
+    }
+}
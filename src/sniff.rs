@@ -0,0 +1,117 @@
+//! Telling what a document is from its first few bytes, so things printers
+//! can't make sense of, like archives and programs, are turned away before
+//! they come out as a hundred pages of gibberish.
+
+/// How much of a document is looked at
+pub const HEAD_SIZE: usize = 1024;
+
+/// A kind of document the printers can print.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub media_type: &'static str,
+    /// What people call it, for error messages
+    pub name: &'static str,
+}
+
+pub const PDF: Format = Format {
+    media_type: "application/pdf",
+    name: "PDF",
+};
+pub const POSTSCRIPT: Format = Format {
+    media_type: "application/postscript",
+    name: "PostScript",
+};
+pub const JPEG: Format = Format {
+    media_type: "image/jpeg",
+    name: "JPEG",
+};
+pub const PNG: Format = Format {
+    media_type: "image/png",
+    name: "PNG",
+};
+/// The raster format AirPrint sends
+pub const URF: Format = Format {
+    media_type: "image/urf",
+    name: "Apple raster",
+};
+pub const TEXT: Format = Format {
+    media_type: "text/plain",
+    name: "plain text",
+};
+
+/// Every format that can be printed
+pub const PRINTABLE: &[Format] = &[PDF, POSTSCRIPT, JPEG, PNG, URF, TEXT];
+
+/// Formats that are easy to mistake for documents, by their magic numbers
+const UNPRINTABLE: &[(&[u8], &str)] = &[
+    // Word, PowerPoint and OpenDocument files are all really zips
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"Rar!\x1a\x07", "application/vnd.rar"),
+    (b"MZ", "application/vnd.microsoft.portable-executable"),
+    (b"\x7fELF", "application/x-elf"),
+    (b"\xcf\xfa\xed\xfe", "application/x-mach-binary"),
+    (b"\xca\xfe\xba\xbe", "application/x-mach-binary"),
+    (b"GIF8", "image/gif"),
+    // Older Word, PowerPoint and Excel files
+    (
+        b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1",
+        "application/x-ole-storage",
+    ),
+];
+
+/// What the document starting with `head` is, or the media type of what it
+/// looks like instead when it can't be printed.
+pub fn detect(head: &[u8]) -> Result<Format, &'static str> {
+    // PDF readers look for the header anywhere near the start, and so do
+    // printers, so some software leaves junk in front of it
+    if head.windows(5).any(|window| window == b"%PDF-") {
+        return Ok(PDF);
+    }
+    let format = if head.starts_with(b"%!") {
+        POSTSCRIPT
+    } else if head.starts_with(b"\xff\xd8\xff") {
+        JPEG
+    } else if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        PNG
+    } else if head.starts_with(b"UNIRAST\0") {
+        URF
+    } else if let Some((_, media_type)) = UNPRINTABLE
+        .iter()
+        .find(|(magic, _)| head.starts_with(magic))
+    {
+        return Err(media_type);
+    } else if text(head) {
+        TEXT
+    } else {
+        return Err("application/octet-stream");
+    };
+    Ok(format)
+}
+
+/// Whether `head` reads as text, allowing for it having been cut off in the
+/// middle of a character.
+fn text(head: &[u8]) -> bool {
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&head[..err.valid_up_to()]).unwrap()
+        }
+        Err(_) => return false,
+    };
+    text.chars()
+        .all(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r' | '\x0c'))
+}
+
+/// The names of every printable format, for error messages.
+pub fn printable_names() -> String {
+    let names = PRINTABLE
+        .iter()
+        .map(|format| format.name)
+        .collect::<Vec<_>>();
+    match names.split_last() {
+        Some((last, rest)) => format!("{} or {last}", rest.join(", ")),
+        None => String::new(),
+    }
+}
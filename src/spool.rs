@@ -0,0 +1,269 @@
+//! Documents being uploaded a chunk at a time, kept on disk until they're
+//! complete and printed.
+//!
+//! Uploads follow the core of the [tus](https://tus.io) protocol: the client
+//! says up front how big the document is, then sends it in `PATCH`es which each
+//! say the offset they start at. A dropped connection only loses the chunk that
+//! was in flight, and `HEAD` tells the client where to pick up from.
+
+use crate::error::KprintError;
+use actix_web::{http::header::HeaderName, web::Bytes};
+use chrono::{DateTime, Duration, Utc};
+use futures::{Stream, StreamExt};
+use kprint_client::models::PrintOptions;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+/// How far into the document the server has, on every upload response.
+pub const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+/// How big the document will be, given when the upload is created.
+pub const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+/// The tus protocol version spoken, so tus clients know they can use it.
+pub const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+pub const TUS_VERSION: &str = "1.0.0";
+
+/// How long an upload can sit untouched before it's thrown away
+const UPLOAD_LIFETIME: Duration = Duration::hours(24);
+
+/// Biggest upload accepted when `KPRINT_UPLOAD_MAX_BYTES` doesn't say
+/// otherwise. Posters get big.
+const DEFAULT_MAX_BYTES: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct Upload {
+    pub id: Uuid,
+    /// Username of whoever started the upload
+    pub owner: String,
+    pub printer: String,
+    /// Checked when the upload was created, so bad options fail before any
+    /// of the document is sent
+    pub options: PrintOptions,
+    pub length: u64,
+    pub offset: u64,
+    pub expires_at: DateTime<Utc>,
+    /// Whether a chunk is being written right now
+    busy: bool,
+}
+
+pub struct UploadStore {
+    dir: PathBuf,
+    max_bytes: u64,
+    uploads: Mutex<HashMap<Uuid, Upload>>,
+}
+
+/// Where documents are kept while they're on disk: `KPRINT_SPOOL_DIR`, or a
+/// directory under the system's temporary directory.
+pub fn spool_dir() -> std::io::Result<PathBuf> {
+    let dir = match std::env::var("KPRINT_SPOOL_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => std::env::temp_dir().join("kprint-spool"),
+    };
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+impl UploadStore {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let dir = spool_dir()?;
+        let max_bytes = match std::env::var("KPRINT_UPLOAD_MAX_BYTES") {
+            Ok(max_bytes) => max_bytes.parse()?,
+            Err(_) => DEFAULT_MAX_BYTES,
+        };
+        Ok(UploadStore {
+            dir,
+            max_bytes,
+            uploads: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn path(&self, id: &Uuid) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+
+    /// Forgets about uploads nobody has touched in a while.
+    async fn sweep(&self) {
+        let now = Utc::now();
+        let expired = {
+            let mut uploads = self.uploads.lock().unwrap();
+            let expired = uploads
+                .values()
+                .filter(|upload| upload.expires_at < now && !upload.busy)
+                .map(|upload| upload.id)
+                .collect::<Vec<_>>();
+            for id in &expired {
+                uploads.remove(id);
+            }
+            expired
+        };
+        for id in expired {
+            log::debug!("Throwing away abandoned upload {id}");
+            let _ = tokio::fs::remove_file(self.path(&id)).await;
+        }
+    }
+
+    pub async fn create(
+        &self,
+        owner: &str,
+        printer: String,
+        options: PrintOptions,
+        length: u64,
+    ) -> Result<Upload, KprintError> {
+        if length > self.max_bytes {
+            return Err(KprintError::DocumentTooLarge {
+                limit: self.max_bytes,
+            });
+        }
+        self.sweep().await;
+        let upload = Upload {
+            id: Uuid::new_v4(),
+            owner: owner.to_string(),
+            printer,
+            options,
+            length,
+            offset: 0,
+            expires_at: Utc::now() + UPLOAD_LIFETIME,
+            busy: false,
+        };
+        tokio::fs::File::create(self.path(&upload.id))
+            .await
+            .map_err(KprintError::SpoolIo)?;
+        self.uploads
+            .lock()
+            .unwrap()
+            .insert(upload.id, upload.clone());
+        Ok(upload)
+    }
+
+    /// Looks up one of `owner`'s uploads. Other people's look exactly like
+    /// missing ones, the same as jobs.
+    pub fn get(&self, owner: &str, id: Uuid) -> Result<Upload, KprintError> {
+        match self.uploads.lock().unwrap().get(&id) {
+            Some(upload) if upload.owner == owner && upload.expires_at > Utc::now() => {
+                Ok(upload.clone())
+            }
+            _ => Err(KprintError::UploadNotFound(id)),
+        }
+    }
+
+    /// Writes a chunk that claims to start at `offset`.
+    ///
+    /// Whatever arrives is kept even if the chunk is cut off partway, which is
+    /// what lets the client resume from wherever it got to.
+    pub async fn append(
+        &self,
+        owner: &str,
+        id: Uuid,
+        offset: u64,
+        mut chunk: impl Stream<Item = std::io::Result<Bytes>> + Unpin,
+    ) -> Result<Upload, KprintError> {
+        let upload = {
+            let mut uploads = self.uploads.lock().unwrap();
+            let upload = uploads
+                .get_mut(&id)
+                .filter(|upload| upload.owner == owner)
+                .ok_or(KprintError::UploadNotFound(id))?;
+            if upload.busy || upload.offset != offset {
+                return Err(KprintError::UploadOffsetMismatch {
+                    expected: upload.offset,
+                });
+            }
+            upload.busy = true;
+            upload.clone()
+        };
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(self.path(&id))
+            .await;
+        let mut written = upload.offset;
+        let mut result = Ok(());
+        match &mut file {
+            Ok(file) => {
+                while let Some(bytes) = chunk.next().await {
+                    let bytes = match bytes {
+                        Ok(bytes) => bytes,
+                        Err(err) => {
+                            log::info!("Upload {id} was cut off at {written} bytes: {err}");
+                            break;
+                        }
+                    };
+                    // Anything past the length the client promised is dropped
+                    let room = (upload.length - written).min(bytes.len() as u64) as usize;
+                    if let Err(err) = file.write_all(&bytes[..room]).await {
+                        // Some of the chunk may have made it, which would put
+                        // the file ahead of the offset the client is told
+                        let _ = file.set_len(written).await;
+                        result = Err(KprintError::SpoolIo(err));
+                        break;
+                    }
+                    written += room as u64;
+                    if room < bytes.len() {
+                        result = Err(KprintError::DocumentTooLarge {
+                            limit: upload.length,
+                        });
+                        break;
+                    }
+                }
+                if let Err(err) = file.flush().await {
+                    result = Err(KprintError::SpoolIo(err));
+                }
+            }
+            Err(err) => {
+                let err =
+                    std::io::Error::new(err.kind(), format!("Couldn't open upload {id}: {err}"));
+                result = Err(KprintError::SpoolIo(err));
+            }
+        }
+
+        let mut uploads = self.uploads.lock().unwrap();
+        let upload = uploads
+            .get_mut(&id)
+            .ok_or(KprintError::UploadNotFound(id))?;
+        upload.busy = false;
+        upload.offset = written;
+        upload.expires_at = Utc::now() + UPLOAD_LIFETIME;
+        result.map(|_| upload.clone())
+    }
+
+    /// Takes a finished upload out of the store, handing back the document.
+    pub async fn finish(
+        &self,
+        owner: &str,
+        id: Uuid,
+    ) -> Result<(Upload, tokio::fs::File), KprintError> {
+        let upload = {
+            let mut uploads = self.uploads.lock().unwrap();
+            match uploads.get(&id) {
+                Some(upload) if upload.owner != owner => {
+                    return Err(KprintError::UploadNotFound(id))
+                }
+                Some(upload) if upload.busy || upload.offset < upload.length => {
+                    return Err(KprintError::UploadIncomplete {
+                        offset: upload.offset,
+                        length: upload.length,
+                    })
+                }
+                Some(_) => uploads.remove(&id).unwrap(),
+                None => return Err(KprintError::UploadNotFound(id)),
+            }
+        };
+        let path = self.path(&id);
+        let file = tokio::fs::File::open(&path)
+            .await
+            .map_err(KprintError::SpoolIo)?;
+        // The open file stays readable after this, and its space is freed as
+        // soon as the printer's done with it
+        let _ = tokio::fs::remove_file(&path).await;
+        Ok((upload, file))
+    }
+
+    pub async fn remove(&self, owner: &str, id: Uuid) -> Result<(), KprintError> {
+        self.get(owner, id)?;
+        self.uploads.lock().unwrap().remove(&id);
+        let _ = tokio::fs::remove_file(self.path(&id)).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+//! Stamping who printed something, and when, onto every page, so printouts
+//! left on the tray can find their way back to their owners.
+//!
+//! Turned on per printer with `KPRINT_STAMP`, a space-separated list like
+//! `lounge:margin library:watermark`. Only PDFs get stamped; anything else is
+//! printed as it was sent.
+
+use crate::pdf;
+use chrono::{DateTime, Local};
+use lopdf::{dictionary, Dictionary, Object, Stream};
+use std::collections::HashMap;
+
+/// What resources are called on stamped pages, picked so they won't clash
+/// with the document's own
+const FONT: &str = "KprintStampFont";
+const GRAPHICS_STATE: &str = "KprintStampState";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StampStyle {
+    /// Small print along the bottom margin
+    Margin,
+    /// Big, faint, and diagonally across the middle of the page
+    Watermark,
+}
+
+/// Which printers stamp their pages, and how.
+#[derive(Default)]
+pub struct Stamps(HashMap<String, StampStyle>);
+
+impl Stamps {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let Ok(stamps) = std::env::var("KPRINT_STAMP") else {
+            return Ok(Stamps::default());
+        };
+        stamps
+            .split_whitespace()
+            .map(|stamp| {
+                let (printer, style) = stamp.split_once(':').unwrap_or((stamp, "margin"));
+                let style = match style {
+                    "margin" => StampStyle::Margin,
+                    "watermark" => StampStyle::Watermark,
+                    _ => anyhow::bail!("{style:?} isn't a stamp style, try margin or watermark"),
+                };
+                Ok((printer.to_string(), style))
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(Stamps)
+    }
+
+    pub fn get(&self, printer: &str) -> Option<StampStyle> {
+        self.0.get(printer).copied()
+    }
+}
+
+/// Drawing instructions for the stamp on a page of the given size.
+fn stamp_content(style: StampStyle, text: &str, [x0, y0, x1, y1]: [f32; 4]) -> String {
+    let text = pdf::string(text);
+    match style {
+        StampStyle::Margin => {
+            format!(
+                "Q q BT /{FONT} 7 Tf 0 g 1 0 0 1 {} {} Tm ({text}) Tj ET Q",
+                x0 + 18.0,
+                y0 + 8.0
+            )
+        }
+        StampStyle::Watermark => {
+            let (width, height) = (x1 - x0, y1 - y0);
+            let size = 40.0;
+            // Helvetica averages about half an em per character, which is
+            // close enough to centre it
+            let length = 0.5 * size * text.len() as f32;
+            let angle = height.atan2(width);
+            let (sin, cos) = angle.sin_cos();
+            let x = x0 + width / 2.0 - cos * length / 2.0 + sin * size / 3.0;
+            let y = y0 + height / 2.0 - sin * length / 2.0 - cos * size / 3.0;
+            format!(
+                "Q q /{GRAPHICS_STATE} gs BT /{FONT} {size} Tf 0.5 g {cos} {sin} {} {cos} {x} {y} Tm ({text}) Tj ET Q",
+                -sin
+            )
+        }
+    }
+}
+
+/// Stamps every page of `pdf` with who printed it and when.
+pub fn stamp(
+    pdf: &[u8],
+    style: StampStyle,
+    username: &str,
+    at: DateTime<Local>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut document = pdf::load(pdf)?;
+    let text = format!("Printed by {username} on {}", at.format("%Y-%m-%d %H:%M"));
+
+    let font = pdf::helvetica(&mut document);
+    let graphics_state = document.add_object(dictionary! {
+        "Type" => "ExtGState",
+        "ca" => 0.15,
+    });
+    // Keeps whatever state the page leaves behind from moving the stamp
+    let save = document.add_object(Stream::new(Dictionary::new(), b"q".to_vec()));
+
+    for page_id in document.get_pages().into_values() {
+        pdf::add_resource(&mut document, page_id, "Font", FONT, font)?;
+        pdf::add_resource(
+            &mut document,
+            page_id,
+            "ExtGState",
+            GRAPHICS_STATE,
+            graphics_state,
+        )?;
+        let content = stamp_content(style, &text, pdf::media_box(&document, page_id));
+        let content = document.add_object(Stream::new(Dictionary::new(), content.into_bytes()));
+
+        let mut contents = document
+            .get_page_contents(page_id)
+            .into_iter()
+            .map(Object::Reference)
+            .collect::<Vec<_>>();
+        contents.insert(0, Object::Reference(save));
+        contents.push(Object::Reference(content));
+        document
+            .get_dictionary_mut(page_id)?
+            .set("Contents", contents);
+    }
+
+    pdf::save(document)
+}
@@ -0,0 +1,93 @@
+//! Putting what a printer says is wrong with it into words.
+//!
+//! Printers explain their state with `printer-state-reasons` keywords like
+//! `media-empty-error`, which mean something to CUPS but not to someone
+//! standing at the printer. Errors about a printer that's stopped, or that
+//! turned a job down, carry the keywords along with what to do about them,
+//! so people find out to add paper rather than that something went wrong.
+
+use kprint_client::models::PrinterStatus;
+
+/// What a printer last said about itself.
+#[derive(Debug, Clone, Default)]
+pub struct PrinterCondition {
+    /// Its `printer-state-reasons`, leaving out `none`
+    pub reasons: Vec<String>,
+    /// Its `printer-state-message`, which is often the most specific of all,
+    /// like which tray is out of paper
+    pub message: Option<String>,
+}
+
+impl PrinterCondition {
+    pub fn of(status: &PrinterStatus) -> Self {
+        PrinterCondition {
+            reasons: status
+                .state_reasons
+                .iter()
+                .filter(|reason| *reason != "none")
+                .cloned()
+                .collect(),
+            message: status.message.clone(),
+        }
+    }
+
+    /// What someone could do about each of the reasons kprint knows, once
+    /// each.
+    pub fn hints(&self) -> Vec<&'static str> {
+        let mut hints = vec![];
+        for hint in self.reasons.iter().filter_map(|reason| hint(reason)) {
+            if !hints.contains(&hint) {
+                hints.push(hint);
+            }
+        }
+        hints
+    }
+
+    /// The whole condition in a few words, or `None` if the printer didn't
+    /// say anything useful.
+    pub fn summary(&self) -> Option<String> {
+        let hints = self.hints();
+        match (hints.is_empty(), &self.message) {
+            (false, _) => Some(hints.join("; ")),
+            (true, Some(message)) => Some(message.clone()),
+            (true, None) => None,
+        }
+    }
+
+    pub fn details(&self) -> serde_json::Value {
+        serde_json::json!({
+            "stateReasons": self.reasons,
+            "stateMessage": self.message,
+            "hints": self.hints(),
+        })
+    }
+}
+
+/// What to do about `reason`, for the reasons worth doing something about.
+/// Reasons can end in `-error`, `-warning` or `-report` to say how bad they
+/// are, which makes no difference to what fixes them.
+fn hint(reason: &str) -> Option<&'static str> {
+    let reason = ["-error", "-warning", "-report"]
+        .iter()
+        .find_map(|severity| reason.strip_suffix(severity))
+        .unwrap_or(reason);
+    Some(match reason {
+        "media-empty" | "media-needed" => "add paper",
+        "media-low" => "paper is running low",
+        "media-jam" => "clear the paper jam",
+        "toner-empty" | "marker-supply-empty" => "replace the toner",
+        "toner-low" | "marker-supply-low" => "toner is running low",
+        "marker-waste-full" => "empty the waste toner",
+        "door-open" | "cover-open" | "interlock-open" => "close the printer's doors and covers",
+        "input-tray-missing" => "put the paper tray back",
+        "output-area-full" => "empty the output tray",
+        "output-tray-missing" => "put the output tray back",
+        "offline" | "timed-out" | "connecting-to-device" => {
+            "the printer is offline, check it's on and connected"
+        }
+        "shutdown" => "the printer is shut down",
+        "spool-area-full" => "the print server is out of space",
+        "paused" => "an admin has paused it",
+        _ => return None,
+    })
+}
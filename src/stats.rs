@@ -0,0 +1,149 @@
+//! Adding the history up into how much each printer gets used.
+
+use crate::history::{self, History};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveTime, TimeZone, Timelike, Utc};
+use kprint_client::models::{
+    ColorMode, DailyUsage, HistoryEntry, HourlyUsage, PrinterUsage, UsageStats, UserUsage,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use utoipa::IntoParams;
+
+/// How far back stats go when the request doesn't say
+const DEFAULT_WINDOW: Duration = Duration::days(30);
+
+/// Which jobs to count, by when they were submitted.
+#[derive(Debug, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatsQuery {
+    /// RFC 3339. Defaults to 30 days before `to`.
+    pub from: Option<DateTime<Utc>>,
+    /// RFC 3339. Defaults to now.
+    pub to: Option<DateTime<Utc>>,
+    /// Only jobs with this tag, like `evals`
+    pub tag: Option<String>,
+}
+
+/// Whether `entry` was tagged with `tag`, if there is one.
+pub fn tagged(entry: &HistoryEntry, tag: Option<&str>) -> bool {
+    tag.is_none_or(|tag| {
+        entry
+            .tags
+            .iter()
+            .any(|tagged| tagged.eq_ignore_ascii_case(tag.trim()))
+    })
+}
+
+#[derive(Default)]
+struct Tally {
+    usage: Option<PrinterUsage>,
+    days: BTreeMap<NaiveDate, DailyUsage>,
+    hours: [u32; 24],
+}
+
+/// Midnight at the start of `day`, in kprint's time zone.
+pub fn midnight(day: NaiveDate) -> DateTime<Utc> {
+    let midnight = day.and_time(NaiveTime::MIN);
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .unwrap_or_else(|| midnight.and_utc().with_timezone(&Local))
+        .to_utc()
+}
+
+pub fn usage(history: &History, query: StatsQuery) -> UsageStats {
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or(to - DEFAULT_WINDOW);
+    let tag = query.tag.as_deref();
+    let entries =
+        history.filter(|entry| (from..to).contains(&entry.submitted_at) && tagged(entry, tag));
+
+    let mut printers = BTreeMap::<String, Tally>::new();
+    for entry in &entries {
+        let tally = printers.entry(entry.printer.clone()).or_default();
+        let usage = tally.usage.get_or_insert_with(|| PrinterUsage {
+            printer: entry.printer.clone(),
+            jobs: 0,
+            pages: 0,
+            jobs_without_pages: 0,
+            color_jobs: 0,
+            color_pages: 0,
+            mono_jobs: 0,
+            mono_pages: 0,
+            failed_jobs: 0,
+            failure_rate: 0.0,
+            days: vec![],
+            busiest_hours: vec![],
+        });
+        let pages = entry.pages.unwrap_or_default();
+        usage.jobs += 1;
+        usage.pages += pages;
+        usage.jobs_without_pages += entry.pages.is_none() as u32;
+        if entry.color_mode == ColorMode::Color {
+            usage.color_jobs += 1;
+            usage.color_pages += pages;
+        } else {
+            usage.mono_jobs += 1;
+            usage.mono_pages += pages;
+        }
+        usage.failed_jobs += history::failed(entry) as u32;
+
+        let local = entry.submitted_at.with_timezone(&Local);
+        let date = local.date_naive();
+        let day = tally.days.entry(date).or_insert(DailyUsage {
+            date,
+            jobs: 0,
+            pages: 0,
+        });
+        day.jobs += 1;
+        day.pages += pages;
+        tally.hours[local.hour() as usize] += 1;
+    }
+
+    let printers = printers
+        .into_values()
+        .filter_map(|tally| {
+            let mut usage = tally.usage?;
+            usage.failure_rate = usage.failed_jobs as f64 / usage.jobs as f64;
+            usage.days = tally.days.into_values().collect();
+            let mut hours = (0..24)
+                .zip(tally.hours)
+                .filter(|(_, jobs)| *jobs > 0)
+                .map(|(hour, jobs)| HourlyUsage { hour, jobs })
+                .collect::<Vec<_>>();
+            hours.sort_by_key(|hour| std::cmp::Reverse(hour.jobs));
+            usage.busiest_hours = hours;
+            Some(usage)
+        })
+        .collect();
+    UsageStats { from, to, printers }
+}
+
+/// The `limit` members who printed the most pages between `from` and `to`,
+/// then the most jobs.
+pub fn top_users(
+    history: &History,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    tag: Option<&str>,
+    limit: usize,
+) -> Vec<UserUsage> {
+    let mut users = BTreeMap::<String, UserUsage>::new();
+    for entry in
+        history.filter(|entry| (from..to).contains(&entry.submitted_at) && tagged(entry, tag))
+    {
+        let user = users
+            .entry(entry.owner.clone())
+            .or_insert_with(|| UserUsage {
+                username: entry.owner.clone(),
+                jobs: 0,
+                pages: 0,
+            });
+        user.jobs += 1;
+        user.pages += entry.pages.unwrap_or_default();
+    }
+    let mut users = users.into_values().collect::<Vec<_>>();
+    users.sort_by_key(|user| std::cmp::Reverse((user.pages, user.jobs)));
+    users.truncate(limit);
+    users
+}
@@ -0,0 +1,137 @@
+//! How documents flow from whoever's uploading them to the printer.
+//!
+//! Uploads arrive as a stream of chunks, often small ones, which go through a
+//! channel to the IPP client. `KPRINT_STREAM_BUFFER` says how many chunks can
+//! wait in that channel, so the upload doesn't stall every time the printer
+//! side is busy, and `KPRINT_STREAM_COALESCE_BYTES` how much of what's waiting
+//! is merged into each write to the printer.
+//!
+//! `benches/streaming.rs` measures the difference they make. The buffer is
+//! most of it, about three times the throughput of a one-chunk channel. The
+//! copying coalescing takes costs more than it saves over loopback, so it's
+//! off unless asked for, for links where fewer, bigger writes pay off.
+//!
+//! Slow printers still hold the upload to their pace, so with
+//! `KPRINT_SPOOL_UPLOADS=true` the print endpoint writes the whole upload to
+//! disk first, answers, and streams it on to the printer from there. That
+//! trip through the disk costs most of the throughput, which is the price of
+//! not keeping anyone waiting on the printer.
+
+use actix_web::web::Bytes;
+use futures::{channel::mpsc, Stream, StreamExt};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use tokio_util::io::StreamReader;
+
+const DEFAULT_BUFFER: usize = 16;
+const DEFAULT_COALESCE_BYTES: usize = 0;
+
+pub type Chunk = std::io::Result<Bytes>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct StreamTuning {
+    /// Chunks that can wait between the upload and the printer
+    pub buffer: usize,
+    /// Most bytes of waiting chunks merged into one, or 0 to pass chunks
+    /// through as they came
+    pub coalesce_bytes: usize,
+}
+
+impl Default for StreamTuning {
+    fn default() -> Self {
+        StreamTuning {
+            buffer: DEFAULT_BUFFER,
+            coalesce_bytes: DEFAULT_COALESCE_BYTES,
+        }
+    }
+}
+
+impl StreamTuning {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let default = Self::default();
+        let buffer = match std::env::var("KPRINT_STREAM_BUFFER") {
+            Ok(buffer) => buffer.parse()?,
+            Err(_) => default.buffer,
+        };
+        let coalesce_bytes = match std::env::var("KPRINT_STREAM_COALESCE_BYTES") {
+            Ok(bytes) => bytes.parse()?,
+            Err(_) => default.coalesce_bytes,
+        };
+        Ok(StreamTuning {
+            buffer,
+            coalesce_bytes,
+        })
+    }
+
+    /// A channel whose receiving end is read as one continuous document.
+    pub fn channel(
+        &self,
+    ) -> (
+        mpsc::Sender<Chunk>,
+        impl futures::AsyncRead + Send + Sync + Unpin + 'static,
+    ) {
+        // A channel's capacity is in addition to one slot per sender, so 0
+        // still lets a chunk through at a time
+        let (tx, rx) = mpsc::channel(self.buffer.saturating_sub(1));
+        let chunks = Coalesce {
+            chunks: rx,
+            max: self.coalesce_bytes,
+            pending: None,
+        };
+        (tx, StreamReader::new(chunks).compat())
+    }
+}
+
+/// Merges chunks that are already waiting, up to `max` bytes at a time,
+/// without ever waiting for more to arrive.
+struct Coalesce<S> {
+    chunks: S,
+    max: usize,
+    /// A chunk that was ready but didn't fit, to start the next one with
+    pending: Option<Chunk>,
+}
+
+impl<S: Stream<Item = Chunk> + Unpin> Stream for Coalesce<S> {
+    type Item = Chunk;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Chunk>> {
+        let first = match self.pending.take() {
+            Some(chunk) => chunk,
+            None => match self.chunks.poll_next_unpin(cx) {
+                Poll::Ready(Some(chunk)) => chunk,
+                other => return other,
+            },
+        };
+        let Ok(first) = first else {
+            return Poll::Ready(Some(first));
+        };
+        if first.len() >= self.max {
+            return Poll::Ready(Some(Ok(first)));
+        }
+
+        let mut merged: Option<Vec<u8>> = None;
+        loop {
+            let size = merged.as_ref().map_or(first.len(), Vec::len);
+            match self.chunks.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(chunk))) if size + chunk.len() <= self.max => {
+                    merged
+                        .get_or_insert_with(|| {
+                            let mut merged = Vec::with_capacity(self.max);
+                            merged.extend_from_slice(&first);
+                            merged
+                        })
+                        .extend_from_slice(&chunk);
+                }
+                // Errors and chunks that don't fit wait for the next poll,
+                // and the end of the stream shows up again once it's drained
+                Poll::Ready(Some(chunk)) => {
+                    self.pending = Some(chunk);
+                    break;
+                }
+                Poll::Ready(None) | Poll::Pending => break,
+            }
+        }
+        Poll::Ready(Some(Ok(merged.map_or(first, Bytes::from))))
+    }
+}
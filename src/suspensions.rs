@@ -0,0 +1,81 @@
+//! Members admins have stopped from printing for a while, without touching
+//! their SSO accounts.
+//!
+//! When `KPRINT_SUSPENSIONS_FILE` is set the suspensions are kept there as
+//! JSON, so they last across restarts. Ones that have run out are left until
+//! the next change, and ignored until then.
+
+use crate::json_file::JsonFile;
+use chrono::Utc;
+use kprint_client::models::Suspension;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct Suspensions {
+    suspensions: JsonFile<HashMap<String, Suspension>>,
+}
+
+fn in_effect(suspension: &Suspension) -> bool {
+    suspension.until.is_none_or(|until| until > Utc::now())
+}
+
+impl Suspensions {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Suspensions {
+            suspensions: JsonFile::from_env("KPRINT_SUSPENSIONS_FILE")?,
+        })
+    }
+
+    /// `username`'s suspension, if they're suspended right now.
+    pub fn get(&self, username: &str) -> Option<Suspension> {
+        self.suspensions.read(|suspensions| {
+            suspensions
+                .get(username)
+                .filter(|suspension| in_effect(suspension))
+                .cloned()
+        })
+    }
+
+    /// Every suspension in effect, the soonest to run out first.
+    pub fn list(&self) -> Vec<Suspension> {
+        let mut suspensions = self.suspensions.read(|suspensions| {
+            suspensions
+                .values()
+                .filter(|suspension| in_effect(suspension))
+                .cloned()
+                .collect::<Vec<_>>()
+        });
+        suspensions.sort_by(|a, b| {
+            let until = |suspension: &Suspension| (suspension.until.is_none(), suspension.until);
+            until(a)
+                .cmp(&until(b))
+                .then_with(|| a.username.cmp(&b.username))
+        });
+        suspensions
+    }
+
+    /// Suspends the member in `suspension`, replacing any suspension they
+    /// already had.
+    pub async fn set(&self, suspension: Suspension) -> anyhow::Result<()> {
+        self.suspensions
+            .change(|suspensions| {
+                suspensions.retain(|_, suspension| in_effect(suspension));
+                suspensions.insert(suspension.username.clone(), suspension);
+            })
+            .await
+    }
+
+    /// Lifts `username`'s suspension, returning whether they had one in
+    /// effect.
+    pub async fn clear(&self, username: &str) -> anyhow::Result<bool> {
+        self.suspensions
+            .change(|suspensions| {
+                let cleared = suspensions
+                    .remove(username)
+                    .is_some_and(|suspension| in_effect(&suspension));
+                suspensions.retain(|_, suspension| in_effect(suspension));
+                cleared
+            })
+            .await
+    }
+}
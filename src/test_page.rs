@@ -0,0 +1,158 @@
+//! A diagnostic page for checking over a printer: a grid to show how the
+//! page lines up, color bars and a gray ramp to show how it prints, and what
+//! kprint knows about the printer.
+
+use crate::pdf;
+use chrono::Local;
+use kprint_client::models::PrinterStatus;
+use lopdf::{dictionary, Dictionary, Document, Object, Stream};
+
+const FONT: &str = "KprintTestFont";
+
+/// US Letter, which is what's in every tray
+const WIDTH: f32 = 612.0;
+const HEIGHT: f32 = 792.0;
+/// How far in the border is drawn; anything the printer can't reach shows up
+/// as a gap in it
+const BORDER: f32 = 18.0;
+/// Spacing of the grid, which is half an inch
+const GRID: f32 = 36.0;
+
+/// Cyan, magenta, yellow, red, green, blue, and black
+const COLOR_BARS: &[(f32, f32, f32)] = &[
+    (0.0, 1.0, 1.0),
+    (1.0, 0.0, 1.0),
+    (1.0, 1.0, 0.0),
+    (1.0, 0.0, 0.0),
+    (0.0, 1.0, 0.0),
+    (0.0, 0.0, 1.0),
+    (0.0, 0.0, 0.0),
+];
+const GRAY_STEPS: usize = 11;
+
+fn content(printer: &str, status: Option<&PrinterStatus>, uri: &str, admin: &str) -> String {
+    let mut content = String::new();
+
+    // The grid, then the border over it
+    content += "q 0.8 G 0.25 w ";
+    let mut x = BORDER + GRID;
+    while x < WIDTH - BORDER {
+        content += &format!("{x} {BORDER} m {x} {} l ", HEIGHT - BORDER);
+        x += GRID;
+    }
+    let mut y = BORDER + GRID;
+    while y < HEIGHT - BORDER {
+        content += &format!("{BORDER} {y} m {} {y} l ", WIDTH - BORDER);
+        y += GRID;
+    }
+    content += &format!(
+        "S 0 G 1 w {BORDER} {BORDER} {} {} re S Q ",
+        WIDTH - 2.0 * BORDER,
+        HEIGHT - 2.0 * BORDER
+    );
+
+    // Color bars across the middle, and a gray ramp under them
+    let bar_width = (WIDTH - 4.0 * GRID) / COLOR_BARS.len() as f32;
+    for (i, (r, g, b)) in COLOR_BARS.iter().enumerate() {
+        content += &format!(
+            "{r} {g} {b} rg {} {} {bar_width} {} re f ",
+            2.0 * GRID + i as f32 * bar_width,
+            HEIGHT / 2.0,
+            2.0 * GRID
+        );
+    }
+    let step_width = (WIDTH - 4.0 * GRID) / GRAY_STEPS as f32;
+    for i in 0..GRAY_STEPS {
+        content += &format!(
+            "{} g {} {} {step_width} {GRID} re f ",
+            i as f32 / (GRAY_STEPS - 1) as f32,
+            2.0 * GRID + i as f32 * step_width,
+            HEIGHT / 2.0 - GRID
+        );
+    }
+    content += &format!(
+        "0 G 0.5 w {} {} {} {GRID} re S ",
+        2.0 * GRID,
+        HEIGHT / 2.0 - GRID,
+        WIDTH - 4.0 * GRID
+    );
+
+    let mut lines = vec![
+        (24.0, "kprint test page".to_string()),
+        (14.0, printer.to_string()),
+        (10.0, String::new()),
+        (10.0, format!("CUPS: {uri}")),
+    ];
+    match status {
+        Some(status) => {
+            lines.push((10.0, format!("State: {:?}", status.state)));
+            if !status.state_reasons.is_empty() {
+                lines.push((
+                    10.0,
+                    format!("Reasons: {}", status.state_reasons.join(", ")),
+                ));
+            }
+            if let Some(message) = &status.message {
+                lines.push((10.0, format!("Message: {message}")));
+            }
+            lines.push((10.0, format!("Accepting jobs: {}", status.accepting_jobs)));
+        }
+        None => lines.push((10.0, "State: couldn't be asked".to_string())),
+    }
+    lines.extend([
+        (10.0, String::new()),
+        (
+            10.0,
+            format!(
+                "Printed by {admin} on {}",
+                Local::now().format("%Y-%m-%d %H:%M")
+            ),
+        ),
+        (10.0, format!("kprint {}", env!("CARGO_PKG_VERSION"))),
+    ]);
+    content += &format!("BT 0 g {} {} Td ", 2.0 * GRID, HEIGHT - 2.0 * GRID);
+    for (size, line) in lines {
+        content += &format!(
+            "/{FONT} {size} Tf 0 {} Td ({}) Tj ",
+            -size * 1.4,
+            pdf::string(&line)
+        );
+    }
+    content += "ET";
+    content
+}
+
+/// Draws a test page for `printer`, printed by `admin`.
+pub fn render(
+    printer: &str,
+    status: Option<&PrinterStatus>,
+    uri: &str,
+    admin: &str,
+) -> anyhow::Result<Vec<u8>> {
+    let mut document = Document::with_version("1.5");
+    let pages_id = document.new_object_id();
+    let font = pdf::helvetica(&mut document);
+    let content = content(printer, status, uri, admin);
+    let content = document.add_object(Stream::new(Dictionary::new(), content.into_bytes()));
+    let page = document.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => pages_id,
+        "Contents" => content,
+        "Resources" => dictionary! { "Font" => dictionary! { FONT => font } },
+    });
+    document.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page.into()],
+            "Count" => 1,
+            "MediaBox" => vec![0.into(), 0.into(), WIDTH.into(), HEIGHT.into()],
+        }),
+    );
+    let catalog = document.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    document.trailer.set("Root", catalog);
+    pdf::save(document)
+}
@@ -0,0 +1,149 @@
+use super::{app_with, USER};
+use crate::app::AppState;
+use crate::auth::{AuthenticatedUser, CshClaims};
+use crate::confirmations;
+use chrono::{Duration, Utc};
+use kprint_client::models::{
+    ColorMode, DuplexMode, HistoryEntry, JobState, JobStatus, Preferences,
+};
+use openidconnect::{
+    Audience, EndUserEmail, EndUserUsername, IdTokenClaims, IssuerUrl, StandardClaims,
+    SubjectIdentifier,
+};
+use uuid::Uuid;
+
+const ADDRESS: &str = "skyz@csh.rit.edu";
+
+async fn app(vars: &[(&str, &str)]) -> AppState {
+    app_with("http://cups.invalid", "null:dev", vars).await
+}
+
+async fn sending() -> AppState {
+    app(&[
+        ("KPRINT_CONFIRMATION_EMAILS", "true"),
+        ("KPRINT_EMAIL_SMTP_HOST", "smtp.invalid"),
+        ("KPRINT_EMAIL_ADDRESS", "kprint@csh.rit.edu"),
+        ("KPRINT_EMAIL_USERNAME", "kprint"),
+        ("KPRINT_EMAIL_PASSWORD", "hunter2"),
+    ])
+    .await
+}
+
+fn user(email: Option<&str>) -> AuthenticatedUser {
+    let now = Utc::now();
+    let claims = IdTokenClaims::new(
+        IssuerUrl::new("https://sso.csh.rit.edu/auth/realms/csh".to_string()).unwrap(),
+        vec![Audience::new("kprint".to_string())],
+        now + Duration::hours(1),
+        now,
+        StandardClaims::new(SubjectIdentifier::new(USER.to_string()))
+            .set_preferred_username(Some(EndUserUsername::new(USER.to_string())))
+            .set_email(email.map(|email| EndUserEmail::new(email.to_string())))
+            .set_email_verified(Some(true)),
+        CshClaims {
+            groups: vec!["member".to_string()],
+            uuid: Uuid::new_v4(),
+        },
+    );
+    AuthenticatedUser { claims }
+}
+
+async fn want_confirmations(app: &AppState) {
+    let preferences = Preferences {
+        confirmation_email: Some(true),
+        ..Preferences::default()
+    };
+    app.preferences.set(USER, preferences).await.unwrap();
+}
+
+fn entry(state: JobState) -> HistoryEntry {
+    HistoryEntry {
+        id: Uuid::new_v4(),
+        owner: USER.to_string(),
+        printer: "dev".to_string(),
+        title: "Imagine RIT poster".to_string(),
+        submitted_at: Utc::now(),
+        sides: DuplexMode::OneSided,
+        color_mode: ColorMode::Grayscale,
+        copies: 1,
+        pages: Some(2),
+        counted_pages: None,
+        quota_units: None,
+        state,
+        failure: None,
+        tags: vec![],
+    }
+}
+
+#[tokio::test]
+async fn members_who_want_one_get_the_receipt() {
+    let app = sending().await;
+    want_confirmations(&app).await;
+    confirmations::remember(&app, &user(Some(ADDRESS)));
+
+    let message = confirmations::confirmation(&app, entry(JobState::Completed)).unwrap();
+
+    let to = message.envelope().to();
+    assert_eq!(to.len(), 1);
+    assert_eq!(to[0].to_string(), ADDRESS);
+    let formatted = String::from_utf8(message.formatted()).unwrap();
+    assert!(
+        formatted.contains("Subject: Printed: Imagine RIT poster"),
+        "{formatted}"
+    );
+    assert!(formatted.contains("Content-Type: application/pdf"));
+    assert!(formatted.contains("kprint-receipt-"));
+}
+
+#[tokio::test]
+async fn the_subject_says_when_a_job_didnt_print() {
+    let app = sending().await;
+    want_confirmations(&app).await;
+    confirmations::remember(&app, &user(Some(ADDRESS)));
+
+    let message = confirmations::confirmation(&app, entry(JobState::Aborted)).unwrap();
+
+    let formatted = String::from_utf8(message.formatted()).unwrap();
+    assert!(formatted.contains("Subject: Didn't print: Imagine RIT poster"));
+}
+
+#[tokio::test]
+async fn nobody_gets_one_without_asking_or_an_address() {
+    let unasked = sending().await;
+    confirmations::remember(&unasked, &user(Some(ADDRESS)));
+    assert!(confirmations::confirmation(&unasked, entry(JobState::Completed)).is_none());
+
+    let no_address = sending().await;
+    want_confirmations(&no_address).await;
+    confirmations::remember(&no_address, &user(None));
+    assert!(confirmations::confirmation(&no_address, entry(JobState::Completed)).is_none());
+
+    let off = app(&[]).await;
+    want_confirmations(&off).await;
+    confirmations::remember(&off, &user(Some(ADDRESS)));
+    assert!(confirmations::confirmation(&off, entry(JobState::Completed)).is_none());
+}
+
+#[tokio::test]
+async fn jobs_are_confirmed_once_as_they_finish() {
+    let app = app(&[]).await;
+    let printing = entry(JobState::Processing);
+    app.history.record(printing.clone());
+    let status = |state| JobStatus {
+        id: printing.id,
+        ipp_job_id: Some(1),
+        printer: printing.printer.clone(),
+        title: printing.title.clone(),
+        state,
+        state_reasons: vec![],
+        submitted_at: printing.submitted_at,
+        failure: None,
+        rerouted_from: None,
+        attributes: Default::default(),
+    };
+
+    assert!(app.history.observe(&status(JobState::Processing)).is_none());
+    let finished = app.history.observe(&status(JobState::Completed)).unwrap();
+    assert_eq!(finished.state, JobState::Completed);
+    assert!(app.history.observe(&status(JobState::Completed)).is_none());
+}
@@ -0,0 +1,62 @@
+use super::{options, setup, text, USER};
+use crate::error::KprintError;
+use crate::service;
+use ipp::model::JobState as IppJobState;
+use ipp::prelude::*;
+
+#[tokio::test]
+async fn finds_the_servers_default_printer_and_classes() {
+    let (cups, app) = setup().await;
+    assert_eq!(service::default_printer(&app).await.unwrap(), None);
+    assert!(service::printer_classes(&app).await.unwrap().is_empty());
+
+    cups.set_default("b");
+    cups.add_class("everywhere", &["a", "b"]);
+
+    assert_eq!(
+        service::default_printer(&app).await.unwrap().as_deref(),
+        Some("b")
+    );
+    let classes = service::printer_classes(&app).await.unwrap();
+    assert_eq!(classes.len(), 1);
+    assert_eq!(classes[0].name, "everywhere");
+    assert_eq!(classes[0].members, ["a", "b"]);
+
+    // Not one kprint prints to
+    cups.set_default("somewhere-else");
+    assert_eq!(service::default_printer(&app).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn moves_waiting_jobs_to_another_printer() {
+    let (cups, app) = setup().await;
+    let id = service::submit(&app, USER, false, &[], "a".to_string(), options(), text())
+        .await
+        .unwrap()
+        .job
+        .id;
+
+    service::move_job(&app, "admin", id, "b").await.unwrap();
+
+    let moved = cups.received_of(Operation::CupsMoveJob);
+    assert_eq!(moved.len(), 1);
+    assert_eq!(moved[0].printer, "a");
+    assert_eq!(
+        moved[0].attribute(IppAttribute::JOB_ID),
+        Some(&IppValue::Integer(100))
+    );
+    assert!(matches!(
+        moved[0].attribute("job-printer-uri"),
+        Some(IppValue::Uri(uri)) if uri.ends_with("/printers/b")
+    ));
+    assert_eq!(app.jobs.get(&id).unwrap().printer, "b");
+
+    // It's asked about where it is now
+    service::job_status(&app, USER, id).await.unwrap();
+    assert_eq!(cups.received_of(Operation::GetJobs)[0].printer, "b");
+
+    cups.update("b", |printer| printer.job_state = IppJobState::Processing);
+    let err = service::move_job(&app, "admin", id, "a").await.unwrap_err();
+    assert!(matches!(err, KprintError::JobNotPending(_)));
+    assert_eq!(app.jobs.get(&id).unwrap().printer, "b");
+}
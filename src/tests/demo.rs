@@ -0,0 +1,94 @@
+use super::app_with;
+use crate::demo;
+use crate::service;
+use kprint_client::models::JobState;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn a_demo_has_history_and_jobs_to_show() {
+    let dir = std::env::temp_dir().join(format!("kprint-demo-{}", Uuid::new_v4()));
+    let app = app_with(
+        "http://cups.invalid",
+        "null:hulk null:thor",
+        &[("KPRINT_NULL_PRINTER_DIR", dir.to_str().unwrap())],
+    )
+    .await;
+
+    demo::seed(&app).await;
+
+    let history = app.history.filter(|_| true);
+    assert!(history.len() > 50, "{}", history.len());
+    assert!(history
+        .windows(2)
+        .all(|pair| pair[0].submitted_at <= pair[1].submitted_at));
+    assert!(history
+        .iter()
+        .any(|entry| entry.state == JobState::Completed && entry.pages.is_some()));
+    let printed = history
+        .iter()
+        .filter(|entry| entry.owner == demo::USER)
+        .collect::<Vec<_>>();
+    assert_eq!(printed.len(), 4);
+    assert!(printed.iter().all(|entry| entry.failure.is_none()));
+    let printers = printed
+        .iter()
+        .map(|entry| entry.printer.as_str())
+        .collect::<std::collections::HashSet<_>>();
+    assert_eq!(printers.len(), 2);
+    let status = service::job_status(&app, demo::USER, printed[0].id)
+        .await
+        .unwrap();
+    assert_ne!(status.state, JobState::Unknown);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn a_history_kept_between_demos_isnt_added_to() {
+    let dir = std::env::temp_dir().join(format!("kprint-demo-{}", Uuid::new_v4()));
+    let app = app_with(
+        "http://cups.invalid",
+        "null:hulk",
+        &[("KPRINT_NULL_PRINTER_DIR", dir.to_str().unwrap())],
+    )
+    .await;
+
+    demo::seed(&app).await;
+    let seeded = app.history.filter(|_| true).len();
+    demo::seed(&app).await;
+
+    assert_eq!(app.history.filter(|_| true).len(), seeded + 4);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn everyone_in_a_demo_is_an_admin() {
+    let user = demo::user();
+
+    assert_eq!(user.username(), demo::USER);
+    assert!(user.is_admin(&["rtp".to_string()]));
+}
+
+#[test]
+fn demos_only_have_null_printers() {
+    assert!(demo::check_printer("null:hulk").is_ok());
+    let err = demo::check_printer("lounge").unwrap_err();
+    assert!(
+        err.to_string().contains("lounge isn't a null printer"),
+        "{err}"
+    );
+}
+
+#[tokio::test]
+async fn no_setting_makes_a_demo() {
+    app_with(
+        "http://cups.invalid",
+        "null:hulk",
+        &[("KPRINT_DEMO", "true")],
+    )
+    .await;
+
+    assert!(!demo::enabled());
+    assert!(demo::SETTINGS
+        .iter()
+        .all(|(name, _)| *name != "KPRINT_DEMO"));
+}
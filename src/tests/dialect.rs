@@ -0,0 +1,75 @@
+use super::mock_cups::MockCups;
+use super::{app_with, options, text, try_app_with, USER};
+use crate::service;
+use ipp::prelude::*;
+use kprint_client::models::PrintOptions;
+
+#[tokio::test]
+async fn old_printers_are_spoken_to_how_they_want() {
+    let cups = MockCups::start(&["a", "b"]).await;
+    let dialects = "a:version=1.0 a:charset=us-ascii a:language=en-US";
+    let app = app_with(cups.url(), "a b", &[("KPRINT_PRINTER_DIALECTS", dialects)]).await;
+    let options = PrintOptions {
+        title: "Café menu ☕".to_string(),
+        ..options()
+    };
+
+    for printer in ["a", "b"] {
+        service::submit(
+            &app,
+            USER,
+            false,
+            &[],
+            printer.to_string(),
+            options.clone(),
+            text(),
+        )
+        .await
+        .unwrap();
+    }
+
+    let created = cups.received_of(Operation::CreateJob);
+    let (old, new) = (&created[0], &created[1]);
+    assert_eq!(old.version, IppVersion::v1_0());
+    assert_eq!(
+        old.attribute(IppAttribute::ATTRIBUTES_CHARSET),
+        Some(&IppValue::Charset("us-ascii".to_string()))
+    );
+    assert_eq!(
+        old.attribute(IppAttribute::ATTRIBUTES_NATURAL_LANGUAGE),
+        Some(&IppValue::NaturalLanguage("en-us".to_string()))
+    );
+    assert_eq!(
+        old.text(IppAttribute::JOB_NAME).as_deref(),
+        Some("Caf? menu ?")
+    );
+    // Everything sent to it, not just jobs
+    assert!(cups
+        .received()
+        .iter()
+        .filter(|received| received.printer == "a")
+        .all(|received| received.version == IppVersion::v1_0()));
+
+    assert_eq!(new.version, IppVersion::v1_1());
+    assert_eq!(
+        new.attribute(IppAttribute::ATTRIBUTES_CHARSET),
+        Some(&IppValue::Charset("utf-8".to_string()))
+    );
+    assert_eq!(
+        new.text(IppAttribute::JOB_NAME).as_deref(),
+        Some("Café menu ☕")
+    );
+}
+
+#[tokio::test]
+async fn dialects_kprint_cant_speak_dont_start() {
+    for dialects in ["a:version=3.0", "a:charset=latin1", "a:volume=11", "a"] {
+        let started = try_app_with(
+            "http://localhost:631",
+            "a",
+            &[("KPRINT_PRINTER_DIALECTS", dialects)],
+        )
+        .await;
+        assert!(started.is_err(), "{dialects}");
+    }
+}
@@ -0,0 +1,96 @@
+use crate::digest::{self, Verification};
+use crate::error::KprintError;
+use actix_web::test::TestRequest;
+use actix_web::{HttpResponse, Responder};
+
+/// `sha-256` of "hello", in base64
+const HELLO: &str = "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=";
+
+fn expected(name: &str, value: &str) -> Result<Option<digest::Expected>, KprintError> {
+    let req = TestRequest::default()
+        .insert_header((name, value))
+        .to_http_request();
+    digest::expected(req.headers())
+}
+
+fn verify(name: &str, value: &str, document: &[u8]) -> Verification {
+    let verification = Verification::new(expected(name, value).unwrap());
+    let mut hasher = verification.expected().unwrap().hasher();
+    hasher.update(document);
+    verification.finish(hasher);
+    verification
+}
+
+#[test]
+fn digests_are_read_from_either_header() {
+    assert!(expected("repr-digest", &format!("sha-256=:{HELLO}:"))
+        .unwrap()
+        .is_some());
+    assert!(expected("digest", &format!("SHA-256={HELLO}"))
+        .unwrap()
+        .is_some());
+    // Ones kprint can't check are skipped over
+    assert!(
+        expected("repr-digest", &format!("md5=:abc:, sha-256=:{HELLO}:"))
+            .unwrap()
+            .is_some()
+    );
+    let req = TestRequest::default().to_http_request();
+    assert!(digest::expected(req.headers()).unwrap().is_none());
+    for (name, bad) in [
+        ("repr-digest", "md5=:abc:".to_string()),
+        ("repr-digest", format!("sha-256={HELLO}")),
+        ("repr-digest", "sha-256=:aGVsbG8=:".to_string()),
+        ("digest", "SHA-256".to_string()),
+    ] {
+        assert!(
+            matches!(expected(name, &bad), Err(KprintError::InvalidRequest(_))),
+            "{bad}"
+        );
+    }
+}
+
+#[actix_web::test]
+async fn matching_uploads_are_answered_with_their_digest() {
+    let verification = verify("repr-digest", &format!("sha-256=:{HELLO}:"), b"hello");
+    assert!(verification.check().is_ok());
+    let req = TestRequest::default().to_http_request();
+    let response = verification
+        .respond(Ok(HttpResponse::Created().finish()))
+        .unwrap()
+        .respond_to(&req);
+    assert_eq!(
+        response.headers().get(digest::REPR_DIGEST).unwrap(),
+        &format!("sha-256=:{HELLO}:")
+    );
+
+    let verification = verify("digest", &format!("sha-256={HELLO}"), b"hello");
+    let response = verification
+        .respond(Ok(HttpResponse::Created().finish()))
+        .unwrap()
+        .respond_to(&req);
+    assert_eq!(
+        response.headers().get(digest::DIGEST).unwrap(),
+        &format!("SHA-256={HELLO}")
+    );
+}
+
+#[test]
+fn mismatches_win_over_whatever_else_went_wrong() {
+    let verification = verify("repr-digest", &format!("sha-256=:{HELLO}:"), b"hellp");
+    let failed = verification.respond::<HttpResponse>(Err(KprintError::PrinterUnavailable(
+        "The document ended early".to_string(),
+    )));
+    match failed {
+        Err(KprintError::DigestMismatch {
+            algorithm,
+            expected,
+            computed,
+        }) => {
+            assert_eq!(algorithm, "sha-256");
+            assert_eq!(expected, HELLO);
+            assert_ne!(computed, HELLO);
+        }
+        _ => panic!("Should have been a digest mismatch"),
+    }
+}
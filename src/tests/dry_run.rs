@@ -0,0 +1,61 @@
+use super::{app_with, options, pdf, USER};
+use crate::document::Document;
+use crate::error::KprintError;
+use crate::service;
+use kprint_client::models::SetSuspension;
+
+#[tokio::test]
+async fn dry_runs_turn_away_what_printing_would() {
+    let app = app_with("http://cups.invalid", "null:dev", &[]).await;
+    let dry_run = || {
+        let document = Document::Buffered(pdf().into()).payload();
+        service::dry_run(&app, USER, false, &[], "dev", options(), document)
+    };
+    assert_eq!(dry_run().await.unwrap().document_pages, Some(1));
+
+    let suspension = SetSuspension {
+        reason: "Printed the whole library".to_string(),
+        until: None,
+    };
+    service::suspend(&app, "mom", USER, suspension)
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        dry_run().await,
+        Err(KprintError::UserSuspended { .. })
+    ));
+}
+
+#[tokio::test]
+async fn dry_runs_say_when_theres_not_enough_quota_left() {
+    let app = app_with(
+        "http://cups.invalid",
+        "null:dev",
+        &[
+            ("KPRINT_QUOTA_TIERS", "members:member=1"),
+            ("KPRINT_QUOTA_DEFAULT_TIER", "members"),
+        ],
+    )
+    .await;
+    let dry_run = || {
+        let document = Document::Buffered(pdf().into()).payload();
+        let mut options = options();
+        options.copies = 2;
+        service::dry_run(&app, USER, false, &[], "dev", options, document)
+    };
+
+    assert!(matches!(
+        dry_run().await,
+        Err(KprintError::QuotaExceeded {
+            remaining: 1,
+            needed: Some(2),
+            ..
+        })
+    ));
+    // Nothing's set aside by asking
+    assert_eq!(
+        app.quotas.check(&app, USER, &[], None).await.unwrap(),
+        Some(1)
+    );
+}
@@ -0,0 +1,146 @@
+use super::mock_cups::MockCups;
+use super::{app_with, options, pdf, setup, USER};
+use crate::envelope;
+use crate::options::PrintQuery;
+use crate::service;
+use ipp::prelude::*;
+use kprint_client::models::{DuplexMode, Envelope, EnvelopeAddress, PrintOptions};
+
+fn envelope_options() -> PrintOptions {
+    PrintOptions {
+        sides: DuplexMode::TwoSidedLongEdge,
+        envelope: Some(Envelope::Number10),
+        ..options()
+    }
+}
+
+#[tokio::test]
+async fn envelopes_are_fed_from_the_envelope_tray() {
+    let (cups, app) = setup().await;
+    cups.update("a", |printer| {
+        printer.media_sources = vec!["tray-1", "manual"]
+    });
+
+    service::submit(
+        &app,
+        USER,
+        false,
+        &[],
+        "a".to_string(),
+        envelope_options(),
+        pdf().into(),
+    )
+    .await
+    .unwrap();
+
+    let printed = cups.received_of(Operation::CreateJob).remove(0);
+    assert_eq!(printed.text("sides").as_deref(), Some("one-sided"));
+    assert_eq!(
+        printed.text("feed-orientation").as_deref(),
+        Some("short-edge-first")
+    );
+    let Some(IppValue::Collection(media)) = printed.attribute("media-col") else {
+        panic!("no media-col in {:?}", printed.attribute("media-col"));
+    };
+    let Some(IppValue::Collection(size)) = media.get("media-size") else {
+        panic!("no media-size in {media:?}");
+    };
+    assert_eq!(size.get("x-dimension"), Some(&IppValue::Integer(10478)));
+    assert_eq!(size.get("y-dimension"), Some(&IppValue::Integer(24130)));
+    assert_eq!(
+        media.get("media-source"),
+        Some(&IppValue::Keyword("manual".to_string()))
+    );
+    // It doesn't say it has envelopes as a type of paper
+    assert_eq!(media.get("media-type"), None);
+}
+
+#[tokio::test]
+async fn envelope_trays_can_be_set_per_printer() {
+    let cups = MockCups::start(&["a", "b"]).await;
+    let vars = [("KPRINT_ENVELOPE_TRAYS", "a:tray-1")];
+    let app = app_with(cups.url(), "a b", &vars).await;
+
+    for printer in ["a", "b"] {
+        service::submit(
+            &app,
+            USER,
+            false,
+            &[],
+            printer.to_string(),
+            envelope_options(),
+            pdf().into(),
+        )
+        .await
+        .unwrap();
+    }
+
+    let sources = cups
+        .received_of(Operation::CreateJob)
+        .iter()
+        .map(|printed| match printed.attribute("media-col") {
+            Some(IppValue::Collection(media)) => media.get("media-source").cloned(),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        sources,
+        [Some(IppValue::Keyword("tray-1".to_string())), None]
+    );
+}
+
+#[test]
+fn envelopes_pick_the_media_themselves() {
+    let with = |envelope: &str, poster: Option<&str>| {
+        PrintQuery {
+            sides: Some("one-sided".to_string()),
+            color_mode: Some("grayscale".to_string()),
+            pages: Some(String::new()),
+            copies: Some("1".to_string()),
+            title: Some("Letter".to_string()),
+            poster: poster.map(str::to_string),
+            envelope: Some(envelope.to_string()),
+            ..PrintQuery::default()
+        }
+        .validate()
+    };
+
+    assert_eq!(with("dl", None).unwrap().envelope, Some(Envelope::Dl));
+    for (envelope, poster) in [("legal", None), ("c5", Some("2x2"))] {
+        let fields = with(envelope, poster).unwrap_err();
+        assert_eq!(fields.len(), 1, "{envelope}");
+        assert_eq!(fields[0].field, "envelope", "{envelope}");
+    }
+}
+
+#[test]
+fn lays_out_addresses_on_the_envelope() {
+    let mut address = EnvelopeAddress {
+        envelope: Envelope::Number10,
+        from: vec!["Computer Science House".to_string()],
+        to: vec![],
+    };
+    let fields = envelope::check(&address);
+    assert_eq!(fields.len(), 1);
+    assert_eq!(fields[0].field, "to");
+
+    address.to = vec![
+        "Rochester Institute of Technology".to_string(),
+        "Rochester, NY 14623".to_string(),
+    ];
+    assert!(envelope::check(&address).is_empty());
+    let pdf = envelope::render(&address).unwrap();
+    let document = lopdf::Document::load_mem(&pdf).unwrap();
+    let pages = document.get_pages();
+    assert_eq!(pages.len(), 1);
+    let page = *pages.values().next().unwrap();
+    let [x0, y0, x1, y1] = crate::pdf::media_box(&document, page);
+    // On its side, 9½ by 4⅛ inches
+    assert_eq!([x0, y0], [0.0, 0.0]);
+    assert!((x1 - 684.0).abs() < 0.1 && (y1 - 297.0).abs() < 0.1);
+    let content = String::from_utf8(document.get_page_content(page).unwrap()).unwrap();
+    assert!(content.contains("(Rochester, NY 14623) Tj"), "{content}");
+
+    address.from = vec!["x".repeat(61)];
+    assert_eq!(envelope::check(&address)[0].field, "from");
+}
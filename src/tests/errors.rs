@@ -0,0 +1,300 @@
+use super::mock_cups::MockCups;
+use super::{app_with, options, setup, text, USER};
+use crate::document::Document;
+use crate::error::KprintError;
+use crate::metrics;
+use crate::service;
+use actix_web::ResponseError;
+use ipp::model::{PrinterState, StatusCode};
+use ipp::prelude::*;
+use kprint_client::models::{ErrorCode, JobState};
+use uuid::Uuid;
+
+async fn submit_to(app: &crate::app::AppState, printer: &str) -> Result<Uuid, KprintError> {
+    submit_document(app, printer, text()).await
+}
+
+async fn submit_document(
+    app: &crate::app::AppState,
+    printer: &str,
+    document: Document,
+) -> Result<Uuid, KprintError> {
+    service::submit(
+        app,
+        USER,
+        false,
+        &[],
+        printer.to_string(),
+        options(),
+        document,
+    )
+    .await
+    .map(|submitted| submitted.job.id)
+}
+
+#[tokio::test]
+async fn unknown_printers_are_not_found() {
+    let (cups, app) = setup().await;
+
+    let err = submit_to(&app, "lounge").await.unwrap_err();
+
+    assert!(matches!(err, KprintError::PrinterNotFound(ref name) if name == "lounge"));
+    assert!(cups.received().is_empty());
+}
+
+#[tokio::test]
+async fn cups_going_away_leaves_the_printer_unavailable() {
+    // A port that was free a moment ago, so nothing's listening on it
+    let closed = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let app = app_with(&format!("http://{closed}"), "a", &[]).await;
+
+    let err = submit_to(&app, "a").await.unwrap_err();
+
+    assert!(matches!(err, KprintError::PrinterUnavailable(ref name) if name == "a"));
+}
+
+#[tokio::test]
+async fn asking_after_printers_cups_lost_says_cups_is_unreachable() {
+    let closed = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let app = app_with(&format!("http://{closed}"), "a", &[]).await;
+
+    let err = service::printer_status(&app, "a").await.unwrap_err();
+
+    assert!(matches!(err, KprintError::CupsUnreachable(_)));
+    assert_eq!(
+        err.status_code(),
+        actix_web::http::StatusCode::SERVICE_UNAVAILABLE
+    );
+}
+
+#[tokio::test]
+async fn clamd_going_away_leaves_the_scanner_unavailable() {
+    // A socket that was listened on a moment ago, so nothing answers it
+    let socket = std::env::temp_dir().join(format!("kprint-clamd-{}", Uuid::new_v4()));
+    drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+    let (cups, _) = setup().await;
+    let app = app_with(
+        cups.url(),
+        "a b",
+        &[("KPRINT_CLAMD_ADDRESS", socket.to_str().unwrap())],
+    )
+    .await;
+
+    let err = submit_to(&app, "a").await.unwrap_err();
+    let _ = std::fs::remove_file(&socket);
+
+    assert!(matches!(err, KprintError::ScannerUnavailable(_)));
+    assert_eq!(err.code(), ErrorCode::ScannerUnavailable);
+    assert_eq!(
+        err.status_code(),
+        actix_web::http::StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert!(cups.received_of(Operation::PrintJob).is_empty());
+}
+
+#[tokio::test]
+async fn expired_proxy_tokens_are_told_apart_from_cups_being_down() {
+    let cups = MockCups::start(&["a"]).await;
+    cups.require_token("renewed");
+    let app = app_with(cups.url(), "a", &[("KPRINT_CUPS_PROXY_TOKEN", "expired")]).await;
+    let before = metrics::cups_proxy_auth_failures();
+
+    let err = service::printer_status(&app, "a").await.unwrap_err();
+
+    assert!(matches!(err, KprintError::CupsProxyAuth(_)));
+    assert_eq!(err.status_code(), actix_web::http::StatusCode::BAD_GATEWAY);
+    assert!(err.body().details.unwrap()["hint"]
+        .as_str()
+        .unwrap()
+        .contains("KPRINT_CUPS_PROXY_TOKEN"));
+    assert!(metrics::cups_proxy_auth_failures() > before);
+}
+
+#[tokio::test]
+async fn busy_printers_are_tried_again() {
+    let (cups, app) = setup().await;
+    cups.update("a", |printer| printer.busy_for = 2);
+
+    let id = submit_to(&app, "a").await.unwrap();
+
+    assert_eq!(cups.received_of(Operation::CreateJob).len(), 3);
+    assert_eq!(app.jobs.get(&id).unwrap().ipp_job_id, Some(100));
+}
+
+#[tokio::test]
+async fn documents_that_dont_get_through_are_sent_again_as_new_jobs() {
+    let (cups, app) = setup().await;
+    cups.update("a", |printer| printer.send_busy_for = 1);
+
+    let id = submit_to(&app, "a").await.unwrap();
+
+    assert_eq!(cups.received_of(Operation::CreateJob).len(), 2);
+    assert_eq!(cups.received_of(Operation::SendDocument).len(), 2);
+    let canceled = cups.received_of(Operation::CancelJob);
+    assert_eq!(canceled.len(), 1);
+    assert_eq!(
+        canceled[0].attribute("job-id"),
+        Some(&IppValue::Integer(100))
+    );
+    assert_eq!(app.jobs.get(&id).unwrap().ipp_job_id, Some(101));
+}
+
+#[tokio::test]
+async fn printers_that_cant_create_jobs_are_sent_print_jobs() {
+    let (cups, app) = setup().await;
+    cups.update("a", |printer| printer.creates_jobs = false);
+
+    let id = submit_to(&app, "a").await.unwrap();
+
+    assert_eq!(cups.received_of(Operation::PrintJob).len(), 1);
+    assert!(cups.received_of(Operation::SendDocument).is_empty());
+    assert_eq!(app.jobs.get(&id).unwrap().ipp_job_id, Some(100));
+}
+
+#[tokio::test]
+async fn printers_that_stay_busy_are_given_up_on() {
+    let (cups, app) = setup().await;
+    cups.update("a", |printer| printer.busy_for = u32::MAX);
+
+    let err = submit_to(&app, "a").await.unwrap_err();
+
+    assert!(matches!(err, KprintError::PrinterUnavailable(ref name) if name == "a"));
+    // The first try and every retry
+    assert_eq!(cups.received_of(Operation::CreateJob).len(), 4);
+    let history = app.history.filter(|_| true);
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].state, JobState::Aborted);
+    assert!(history[0].failure.is_some());
+}
+
+#[tokio::test]
+async fn printers_refusing_jobs_say_why() {
+    let (cups, app) = setup().await;
+    cups.update("a", |printer| {
+        printer.print_status = StatusCode::ClientErrorDocumentFormatNotSupported;
+        printer.state_reasons = vec!["toner-low-report"];
+    });
+
+    let err = submit_to(&app, "a").await.unwrap_err();
+
+    assert!(matches!(
+        err,
+        KprintError::PrinterRefused { ref printer, status, .. }
+            if printer == "a" && status == StatusCode::ClientErrorDocumentFormatNotSupported
+    ));
+    assert_eq!(
+        err.status_code(),
+        actix_web::http::StatusCode::UNSUPPORTED_MEDIA_TYPE
+    );
+    let details = err.body().details.unwrap();
+    assert_eq!(
+        details["ippStatus"],
+        "client-error-document-format-not-supported"
+    );
+    assert_eq!(details["statusMessage"], "Document format not supported");
+    assert_eq!(details["stateReasons"][0], "toner-low-report");
+    assert_eq!(details["hints"][0], "toner is running low");
+    // Refusals aren't retried
+    assert_eq!(cups.received_of(Operation::CreateJob).len(), 1);
+    let history = app.history.filter(|_| true);
+    assert_eq!(history[0].state, JobState::Aborted);
+}
+
+#[tokio::test]
+async fn paused_printers_turn_jobs_away() {
+    let (cups, app) = setup().await;
+    cups.update("a", |printer| {
+        printer.state = PrinterState::Stopped;
+        printer.state_reasons = vec!["paused"];
+    });
+
+    let err = submit_to(&app, "a").await.unwrap_err();
+
+    assert!(matches!(err, KprintError::PrinterPaused(ref name) if name == "a"));
+    assert!(cups.received_of(Operation::CreateJob).is_empty());
+}
+
+#[tokio::test]
+async fn stopped_printers_say_what_to_fix() {
+    let (cups, app) = setup().await;
+    cups.update("a", |printer| {
+        printer.state = PrinterState::Stopped;
+        printer.state_reasons = vec!["media-empty-error", "door-open-error"];
+    });
+
+    let err = submit_to(&app, "a").await.unwrap_err();
+
+    assert!(matches!(err, KprintError::PrinterStopped { ref printer, .. } if printer == "a"));
+    assert_eq!(
+        err.to_string(),
+        "a has stopped: add paper; close the printer's doors and covers"
+    );
+    let details = err.body().details.unwrap();
+    assert_eq!(
+        details["stateReasons"],
+        serde_json::json!(["media-empty-error", "door-open-error"])
+    );
+    assert!(cups.received_of(Operation::CreateJob).is_empty());
+}
+
+#[tokio::test]
+async fn unprintable_documents_never_reach_the_printer() {
+    let (cups, app) = setup().await;
+    let zip = Document::Buffered(b"PK\x03\x04 a Word document".to_vec().into());
+
+    let err = submit_document(&app, "a", zip).await.unwrap_err();
+
+    assert!(
+        matches!(err, KprintError::UnsupportedDocumentType(ref media_type) if media_type == "application/zip")
+    );
+    assert!(cups.received_of(Operation::CreateJob).is_empty());
+}
+
+#[tokio::test]
+async fn jobs_the_printer_forgot_are_not_found() {
+    let (cups, app) = setup().await;
+    let id = submit_to(&app, "a").await.unwrap();
+    // As if CUPS restarted and started counting again
+    let mut job = app.jobs.get(&id).unwrap();
+    job.ipp_job_id = Some(7);
+    app.jobs.insert(job);
+
+    let status = service::job_status(&app, USER, id).await.unwrap_err();
+    let cancel = service::cancel_job(&app, USER, id).await.unwrap_err();
+
+    assert!(matches!(status, KprintError::JobNotFound(found) if found == id));
+    assert!(matches!(cancel, KprintError::JobNotFound(found) if found == id));
+    assert_eq!(cups.received_of(Operation::CancelJob).len(), 1);
+}
+
+#[tokio::test]
+async fn other_peoples_jobs_look_missing() {
+    let (cups, app) = setup().await;
+    let id = submit_to(&app, "a").await.unwrap();
+
+    let err = service::cancel_job(&app, "someone-else", id)
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, KprintError::JobNotFound(found) if found == id));
+    assert!(cups.received_of(Operation::CancelJob).is_empty());
+}
+
+#[tokio::test]
+async fn jobs_past_canceling_say_so() {
+    let (cups, app) = setup().await;
+    cups.update("a", |printer| {
+        printer.cancel_status = StatusCode::ClientErrorNotPossible
+    });
+    let id = submit_to(&app, "a").await.unwrap();
+
+    let err = service::cancel_job(&app, USER, id).await.unwrap_err();
+
+    assert!(matches!(err, KprintError::JobNotCancelable(found) if found == id));
+}
@@ -0,0 +1,34 @@
+use super::app_with;
+use super::mock_cups::MockCups;
+use crate::flags::{Flag, Flags};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn admins_overrides_outlast_a_restart_until_cleared() {
+    let cups = MockCups::start(&["a"]).await;
+    let file = std::env::temp_dir().join(format!("kprint-flags-{}.json", Uuid::new_v4()));
+    let vars = [
+        ("KPRINT_FLAGS_FILE", file.to_str().unwrap()),
+        ("KPRINT_FLAG_FALLBACK", "false"),
+    ];
+    let app = app_with(cups.url(), "a", &vars).await;
+    assert!(!app.flags.enabled(Flag::Fallback));
+
+    app.flags.set(Flag::Fallback, true, "skyz").await.unwrap();
+    app.flags.set(Flag::Quotas, false, "skyz").await.unwrap();
+    let restarted = app_with(cups.url(), "a", &vars).await;
+
+    assert!(restarted.flags.enabled(Flag::Fallback));
+    assert!(!restarted.flags.enabled(Flag::Quotas));
+    let cleared = restarted.flags.clear(Flag::Fallback).await.unwrap();
+    assert!(!cleared.enabled && !cleared.configured);
+    assert!(cleared.overridden.is_none());
+}
+
+#[test]
+fn every_flag_goes_by_its_name() {
+    for flag in Flag::ALL {
+        assert_eq!(Flag::from_name(flag.name()), Some(*flag));
+    }
+    assert!(Flags::default().enabled(Flag::SpooledUploads));
+}
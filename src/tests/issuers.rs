@@ -0,0 +1,73 @@
+use super::ENV;
+use crate::issuers::{self, Issuer};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+
+/// What [`issuers::from_env`] makes of `KPRINT_OIDC_ISSUERS` being `value`.
+async fn issuers_from(value: Option<&str>) -> anyhow::Result<Vec<Issuer>> {
+    let _env = ENV.lock().await;
+    match value {
+        Some(value) => std::env::set_var("KPRINT_OIDC_ISSUERS", value),
+        None => std::env::remove_var("KPRINT_OIDC_ISSUERS"),
+    }
+    let issuers = issuers::from_env();
+    std::env::remove_var("KPRINT_OIDC_ISSUERS");
+    issuers
+}
+
+#[tokio::test]
+async fn only_the_csh_realm_is_trusted_by_default() {
+    let issuers = issuers_from(None).await.unwrap();
+
+    assert_eq!(
+        issuers,
+        vec![Issuer {
+            name: "csh".to_string(),
+            url: "https://sso.csh.rit.edu/auth/realms/csh".to_string(),
+            client_id: "kprint".to_string(),
+            audiences: vec!["account".to_string()],
+        }]
+    );
+}
+
+#[tokio::test]
+async fn each_issuer_has_its_own_audiences() {
+    let issuers = issuers_from(Some(
+        "csh:url=https://sso.csh.rit.edu/auth/realms/csh \
+         ci:url=https://sso.csh.rit.edu/auth/realms/ci/ ci:client_id=kprint-ci \
+         ci:audience=printing ci:audience=builds",
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(issuers.len(), 2);
+    assert_eq!(issuers[0].audiences, vec!["account"]);
+    assert_eq!(issuers[1].url, "https://sso.csh.rit.edu/auth/realms/ci");
+    assert_eq!(issuers[1].client_id, "kprint-ci");
+    assert_eq!(issuers[1].audiences, vec!["printing", "builds"]);
+}
+
+#[tokio::test]
+async fn bad_issuers_stop_kprint_starting() {
+    for bad in [
+        "ci:client_id=kprint-ci",
+        "ci:url=not a url",
+        "ci:colour=blue",
+        "ci",
+        "a:url=https://sso.example a:url=https://sso.example b:url=https://sso.example",
+    ] {
+        assert!(issuers_from(Some(bad)).await.is_err(), "{bad}");
+    }
+}
+
+#[test]
+fn tokens_are_checked_by_the_issuer_they_say_theyre_from() {
+    let payload = URL_SAFE_NO_PAD.encode(r#"{"iss":"https://sso.csh.rit.edu/auth/realms/ci"}"#);
+    let token = format!("e30.{payload}.c2ln");
+
+    assert_eq!(
+        issuers::unverified_issuer(&token).as_deref(),
+        Some("https://sso.csh.rit.edu/auth/realms/ci")
+    );
+    assert_eq!(issuers::unverified_issuer("not a token"), None);
+}
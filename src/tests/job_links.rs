@@ -0,0 +1,181 @@
+use super::app_with;
+use crate::app::AppState;
+use crate::error::KprintError;
+use crate::job_links::{self, SignedJob};
+use actix_web::http::Method;
+use actix_web::test::TestRequest;
+use actix_web::web::Data;
+use actix_web::FromRequest;
+use kprint_client::models::JobLinks;
+use uuid::Uuid;
+
+const SECRET: &str = "a secret that's long enough to sign with";
+
+async fn app(vars: &[(&str, &str)]) -> Data<AppState> {
+    Data::new(app_with("http://cups.invalid", "null:dev", vars).await)
+}
+
+/// What the extractor makes of a request to `link`, a link to `job`.
+async fn extract(app: &Data<AppState>, job: Uuid, link: &str) -> Result<Uuid, KprintError> {
+    extract_as(app, Method::GET, job, link).await
+}
+
+/// What the extractor makes of a `method` request to `link`.
+async fn extract_as(
+    app: &Data<AppState>,
+    method: Method,
+    job: Uuid,
+    link: &str,
+) -> Result<Uuid, KprintError> {
+    let req = TestRequest::default()
+        .method(method)
+        .uri(link)
+        .param("id", job.to_string())
+        .app_data(app.clone())
+        .to_http_request();
+    match SignedJob::extract(&req).await {
+        Ok(SignedJob(id)) => Ok(id),
+        Err(err) => Err(match err.as_error::<KprintError>() {
+            Some(KprintError::JobLinkInvalid) => KprintError::JobLinkInvalid,
+            _ => panic!("{err}"),
+        }),
+    }
+}
+
+#[tokio::test]
+async fn the_links_handed_back_get_the_job_through() {
+    let app = app(&[("KPRINT_JOB_LINK_SECRET", SECRET)]).await;
+    let job = Uuid::new_v4();
+
+    let links = job_links::signed(&app, JobLinks::for_open_job(&job), &job);
+
+    for (method, link) in [
+        (Method::GET, &links.status),
+        (Method::DELETE, &links.cancel),
+    ] {
+        assert!(link.contains("?expires="), "{link}");
+        assert_eq!(extract_as(&app, method, job, link).await.unwrap(), job);
+    }
+    assert!(links.close.unwrap().contains("/close?expires="));
+    let other = Uuid::new_v4();
+    assert!(matches!(
+        extract(&app, other, &links.status).await,
+        Err(KprintError::JobLinkInvalid)
+    ));
+}
+
+#[tokio::test]
+async fn unsigned_tampered_and_expired_links_are_turned_away() {
+    let app = app(&[("KPRINT_JOB_LINK_SECRET", SECRET)]).await;
+    let job = Uuid::new_v4();
+    let status = job_links::signed(&app, JobLinks::for_job(&job), &job).status;
+    let expires = status
+        .split("expires=")
+        .nth(1)
+        .and_then(|rest| rest.split('&').next())
+        .unwrap();
+    let later = (expires.parse::<i64>().unwrap() + 60).to_string();
+    let expired = app_with(
+        "http://cups.invalid",
+        "null:dev",
+        &[
+            ("KPRINT_JOB_LINK_SECRET", SECRET),
+            ("KPRINT_JOB_LINK_TTL", "-60"),
+        ],
+    )
+    .await;
+    let expired = job_links::signed(&expired, JobLinks::for_job(&job), &job).status;
+
+    for link in [
+        format!("/api/v1/jobs/{job}"),
+        status.replace(expires, &later),
+        status.replace("signature=", "signature=A"),
+        expired,
+    ] {
+        assert!(
+            matches!(
+                extract(&app, job, &link).await,
+                Err(KprintError::JobLinkInvalid)
+            ),
+            "{link}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn links_are_left_as_they_are_without_a_secret() {
+    let app = app(&[]).await;
+    let job = Uuid::new_v4();
+
+    let links = job_links::signed(&app, JobLinks::for_job(&job), &job);
+
+    assert_eq!(links.status, format!("/api/v1/jobs/{job}"));
+    assert_eq!(extract(&app, job, &links.status).await.unwrap(), job);
+}
+
+#[tokio::test]
+async fn job_links_arent_pickup_tokens_with_the_same_secret() {
+    let app = app(&[
+        ("KPRINT_JOB_LINK_SECRET", SECRET),
+        ("KPRINT_PICKUP_SECRET", SECRET),
+    ])
+    .await;
+    let job = Uuid::new_v4();
+    let status = job_links::signed(&app, JobLinks::for_job(&job), &job).status;
+    let query = status.split_once('?').unwrap().1;
+    let (expires, signature) = query.split_once('&').unwrap();
+    let token = format!(
+        "{}.{}.{}",
+        job.simple(),
+        expires.strip_prefix("expires=").unwrap(),
+        signature.strip_prefix("signature=").unwrap()
+    );
+
+    let pickup = app.pickup.as_ref().unwrap();
+    assert!(matches!(
+        pickup.verify(&token),
+        Err(KprintError::PickupLinkInvalid)
+    ));
+    let picked_up = pickup.verify(&pickup.link(job).token).unwrap();
+    assert_eq!(picked_up, job);
+}
+
+#[tokio::test]
+async fn each_link_only_does_what_it_was_signed_for() {
+    let app = app(&[("KPRINT_JOB_LINK_SECRET", SECRET)]).await;
+    let job = Uuid::new_v4();
+    let links = job_links::signed(&app, JobLinks::for_open_job(&job), &job);
+    let url = format!("/api/v1/jobs/{job}");
+    let routes = [
+        (Method::GET, url.clone(), &links.status),
+        (Method::DELETE, url.clone(), &links.cancel),
+        (
+            Method::POST,
+            format!("{url}/documents"),
+            links.documents.as_ref().unwrap(),
+        ),
+        (
+            Method::POST,
+            format!("{url}/close"),
+            links.close.as_ref().unwrap(),
+        ),
+        (Method::GET, format!("{url}/preview"), &links.preview),
+        (Method::GET, format!("{url}/receipt"), &links.receipt),
+    ];
+
+    for (signed_for, (_, _, link)) in routes.iter().enumerate() {
+        let query = link.split_once('?').unwrap().1;
+        for (used_for, (method, route, _)) in routes.iter().enumerate() {
+            let link = format!("{route}?{query}");
+            let extracted = extract_as(&app, method.clone(), job, &link).await;
+            if used_for == signed_for {
+                assert_eq!(extracted.unwrap(), job, "{method} {link}");
+            } else {
+                assert!(
+                    matches!(extracted, Err(KprintError::JobLinkInvalid)),
+                    "{method} {link}"
+                );
+            }
+        }
+    }
+}
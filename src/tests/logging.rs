@@ -0,0 +1,99 @@
+use crate::logging::{self, journal_entry, LogFile, Redaction};
+use std::io::Write;
+use uuid::Uuid;
+
+#[test]
+fn long_log_files_are_moved_aside_keeping_the_most_recent() {
+    let dir = std::env::temp_dir().join(format!("kprint-logs-{}", Uuid::new_v4()));
+    std::fs::create_dir(&dir).unwrap();
+    let path = dir.join("kprint.log");
+    let mut log = LogFile::open(path.clone(), 10, None, 2).unwrap();
+
+    for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+        log.write_all(line.as_bytes()).unwrap();
+    }
+    log.flush().unwrap();
+
+    let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+    assert_eq!(read("kprint.log"), "fourth\n");
+    assert_eq!(read("kprint.log.1"), "third\n");
+    assert_eq!(read("kprint.log.2"), "second\n");
+    assert!(!dir.join("kprint.log.3").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn journal_entries_carry_the_level_and_where_lines_are_from() {
+    let entry = journal_entry(
+        &log::Record::builder()
+            .args(format_args!("Out of paper\nin tray 2"))
+            .level(log::Level::Warn)
+            .target("kprint::service")
+            .line(Some(12))
+            .build(),
+    );
+
+    let mut expected = b"MESSAGE\n".to_vec();
+    expected.extend_from_slice(&22u64.to_le_bytes());
+    expected.extend_from_slice(b"Out of paper\nin tray 2\n");
+    expected.extend_from_slice(b"PRIORITY=4\nSYSLOG_IDENTIFIER=kprint\n");
+    expected.extend_from_slice(b"KPRINT_TARGET=kprint::service\nCODE_LINE=12\n");
+    assert_eq!(entry, expected);
+}
+
+#[test]
+fn the_filter_can_be_changed_while_running_but_not_to_nonsense() {
+    // Nothing else in the tests starts logging, so this is the first
+    logging::init().unwrap();
+
+    let previous = logging::set_filter("kprint::api=debug,info").unwrap();
+    let err = logging::set_filter("kprint=loudly").unwrap_err();
+
+    let started = std::env::var("RUST_LOG").unwrap_or_else(|_| "error".to_string());
+    assert_eq!(previous, started);
+    assert!(err.contains("loudly"), "{err}");
+    assert_eq!(log::max_level(), log::LevelFilter::Debug);
+    assert!(log::log_enabled!(target: "kprint::api", log::Level::Debug));
+    assert!(!log::log_enabled!(target: "kprint::service", log::Level::Debug));
+    assert!(log::log_enabled!(target: "kprint::service", log::Level::Info));
+    logging::set_filter("off").unwrap();
+    // Still there for the audit log
+    assert_eq!(log::max_level(), log::LevelFilter::Warn);
+    assert!(log::log_enabled!(target: "kprint::audit", log::Level::Warn));
+}
+
+#[test]
+fn redacted_names_are_hashed_the_same_way_each_time_or_cut_short() {
+    let hash = Redaction::Hash(b"a secret".to_vec());
+
+    let skyz = hash.apply("skyz");
+    assert!(skyz.starts_with('#') && skyz.len() == 9, "{skyz}");
+    assert_eq!(hash.apply("skyz"), skyz);
+    assert_ne!(hash.apply("mom"), skyz);
+    assert_ne!(Redaction::Hash(b"another".to_vec()).apply("skyz"), skyz);
+    assert_eq!(Redaction::Truncate.apply("skyz"), "sk…");
+    assert_eq!(Redaction::Truncate.apply("é"), "é");
+    assert_eq!(Redaction::Off.apply("skyz"), "skyz");
+}
+
+#[test]
+fn names_and_titles_are_taken_out_of_logged_paths() {
+    let truncate = Redaction::Truncate;
+
+    assert_eq!(
+        truncate.apply_to_path("/api/v1/printers/hulk/print?title=Resume&copies=2"),
+        "/api/v1/printers/hulk/print?title=Re…&copies=2"
+    );
+    assert_eq!(
+        truncate.apply_to_path("/api/v1/admin/users/skyz/suspension"),
+        "/api/v1/admin/users/sk…/suspension"
+    );
+    assert_eq!(
+        truncate.apply_to_path("/api/v1/admin/history?user=skyz&search=lab"),
+        "/api/v1/admin/history?user=sk…&search=la…"
+    );
+    assert_eq!(
+        Redaction::Off.apply_to_path("/api/v1/jobs?user=skyz"),
+        "/api/v1/jobs?user=skyz"
+    );
+}
@@ -0,0 +1,43 @@
+use super::app_with;
+use chrono::Utc;
+use kprint_client::models::MaintenanceNotice;
+use uuid::Uuid;
+
+fn notice() -> MaintenanceNotice {
+    MaintenanceNotice {
+        message: "Out of toner until Tuesday".to_string(),
+        set_by: "skyz".to_string(),
+        since: Utc::now(),
+    }
+}
+
+#[tokio::test]
+async fn notices_outlast_a_restart() {
+    let file = std::env::temp_dir().join(format!("kprint-maintenance-{}.json", Uuid::new_v4()));
+    let vars = [("KPRINT_MAINTENANCE_FILE", file.to_str().unwrap())];
+    let app = app_with("http://cups.invalid", "null:dev", &vars).await;
+
+    app.maintenance.set("dev", notice()).await.unwrap();
+    let restarted = app_with("http://cups.invalid", "null:dev", &vars).await;
+
+    assert_eq!(
+        restarted.maintenance.get("dev").unwrap().message,
+        notice().message
+    );
+    assert!(restarted.maintenance.clear("dev").await.unwrap());
+    assert!(restarted.maintenance.get("dev").is_none());
+    std::fs::remove_file(&file).unwrap();
+}
+
+#[tokio::test]
+async fn changes_that_cant_be_saved_dont_happen() {
+    let file = std::env::temp_dir()
+        .join(format!("kprint-missing-{}", Uuid::new_v4()))
+        .join("maintenance.json");
+    let vars = [("KPRINT_MAINTENANCE_FILE", file.to_str().unwrap())];
+    let app = app_with("http://cups.invalid", "null:dev", &vars).await;
+
+    assert!(app.maintenance.set("dev", notice()).await.is_err());
+
+    assert!(app.maintenance.get("dev").is_none());
+}
@@ -0,0 +1,27 @@
+use super::setup;
+use crate::metrics::{self, SpoolUsage};
+
+#[tokio::test]
+async fn every_printer_has_a_queue_depth_and_breaker_state() {
+    let (_cups, app) = setup().await;
+    app.breakers.trip("b");
+    let spool = SpoolUsage {
+        files: 2,
+        bytes: 1024,
+    };
+
+    let scraped = metrics::render(&app, Some(spool));
+
+    let lines = scraped.lines().collect::<Vec<_>>();
+    for line in [
+        "kprint_spool_bytes 1024",
+        "kprint_spooled_jobs{printer=\"a\"} 0",
+        "kprint_spooled_jobs{printer=\"b\"} 0",
+        "kprint_oldest_spooled_job_age_seconds 0",
+        "kprint_breaker_state{printer=\"a\",state=\"closed\"} 1",
+        "kprint_breaker_state{printer=\"b\",state=\"closed\"} 0",
+        "kprint_breaker_state{printer=\"b\",state=\"open\"} 1",
+    ] {
+        assert!(lines.contains(&line), "{line} in:\n{scraped}");
+    }
+}
@@ -0,0 +1,89 @@
+use super::mock_cups::{MockCups, Received};
+use super::{app_with, options, pdf, USER};
+use crate::service;
+use ipp::prelude::*;
+use std::time::Duration;
+
+/// What the test printer `b` has been sent, once it's been sent anything.
+async fn mirrored(cups: &MockCups) -> Vec<Received> {
+    for _ in 0..100 {
+        let received = cups
+            .received()
+            .into_iter()
+            .filter(|received| received.printer == "b")
+            .collect::<Vec<_>>();
+        if !received.is_empty() {
+            return received;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    vec![]
+}
+
+#[tokio::test]
+async fn mirrored_jobs_are_checked_with_the_same_attributes() {
+    let cups = MockCups::start(&["a", "b"]).await;
+    let vars = [
+        ("KPRINT_MIRROR_PRINTER", "b"),
+        ("KPRINT_MIRROR_PERCENT", "100"),
+    ];
+    let app = app_with(cups.url(), "a", &vars).await;
+
+    service::submit(
+        &app,
+        USER,
+        false,
+        &[],
+        "a".to_string(),
+        options(),
+        pdf().into(),
+    )
+    .await
+    .unwrap();
+
+    let mirrored = mirrored(&cups).await;
+    assert_eq!(mirrored.len(), 1);
+    assert_eq!(mirrored[0].operation, Some(Operation::ValidateJob));
+    assert!(mirrored[0].document.is_empty());
+    let printed = &cups.received_of(Operation::CreateJob)[0];
+    for attribute in [
+        IppAttribute::JOB_NAME,
+        "sides",
+        "print-color-mode",
+        "copies",
+    ] {
+        assert_eq!(
+            mirrored[0].text(attribute),
+            printed.text(attribute),
+            "{attribute}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn mirroring_documents_prints_them_on_the_test_printer() {
+    let cups = MockCups::start(&["a", "b"]).await;
+    let app = app_with(cups.url(), "a", &[("KPRINT_MIRROR_PRINTER", "b")]).await;
+    assert!(app.mirror.sample(uuid::Uuid::new_v4()).is_none());
+    let body = kprint_client::models::SetMirroring {
+        percent: 100,
+        documents: true,
+    };
+    app.mirror.set(body).unwrap();
+
+    service::submit(
+        &app,
+        USER,
+        false,
+        &[],
+        "a".to_string(),
+        options(),
+        pdf().into(),
+    )
+    .await
+    .unwrap();
+
+    let mirrored = mirrored(&cups).await;
+    assert_eq!(mirrored[0].operation, Some(Operation::PrintJob));
+    assert_eq!(mirrored[0].document, pdf());
+}
@@ -0,0 +1,563 @@
+//! A CUPS server that lives in the test, for kprint to send its requests to.
+//!
+//! It speaks just enough HTTP and IPP to answer what kprint asks of CUPS, and
+//! keeps every request it's sent so tests can look at exactly what went over
+//! the wire. How each printer behaves is up to the test.
+
+use ipp::model::{JobState, PrinterState, StatusCode};
+use ipp::parser::IppParser;
+use ipp::prelude::*;
+use ipp::reader::IppReader;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream};
+
+/// How one printer behaves.
+#[derive(Debug, Clone)]
+pub struct MockPrinter {
+    pub state: PrinterState,
+    pub state_reasons: Vec<&'static str>,
+    pub accepting_jobs: bool,
+    pub formats: Vec<&'static str>,
+    pub sides: Vec<&'static str>,
+    pub color_modes: Vec<&'static str>,
+    /// Print-Jobs and Create-Jobs answered with `server-error-busy` before
+    /// one is taken
+    pub busy_for: u32,
+    /// What Print-Job and Create-Job answer with once it's not busy, with no
+    /// job made unless it's a success
+    pub print_status: StatusCode,
+    /// Send-Documents answered with `server-error-busy` before one is taken
+    pub send_busy_for: u32,
+    /// Whether it does Create-Job, rather than only Print-Job
+    pub creates_jobs: bool,
+    /// What sort of URLs it fetches documents from itself with Print-URI,
+    /// if it does at all
+    pub uri_schemes: Vec<&'static str>,
+    /// Where it says it can take paper from, if it says
+    pub media_sources: Vec<&'static str>,
+    /// What Get-Job-Attributes and Get-Jobs say its jobs are doing, with
+    /// Get-Jobs only listing them while they're waiting or printing
+    pub job_state: JobState,
+    /// What Cancel-Job answers with
+    pub cancel_status: StatusCode,
+}
+
+impl Default for MockPrinter {
+    fn default() -> Self {
+        MockPrinter {
+            state: PrinterState::Idle,
+            state_reasons: vec!["none"],
+            accepting_jobs: true,
+            formats: vec!["application/pdf", "text/plain"],
+            sides: vec!["one-sided", "two-sided-long-edge", "two-sided-short-edge"],
+            color_modes: vec!["monochrome", "color"],
+            busy_for: 0,
+            print_status: StatusCode::SuccessfulOk,
+            send_busy_for: 0,
+            creates_jobs: true,
+            uri_schemes: vec![],
+            media_sources: vec![],
+            job_state: JobState::Pending,
+            cancel_status: StatusCode::SuccessfulOk,
+        }
+    }
+}
+
+/// A request kprint sent.
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub printer: String,
+    pub operation: Option<Operation>,
+    /// The IPP version it said it was
+    pub version: IppVersion,
+    pub attributes: IppAttributes,
+    pub document: Vec<u8>,
+    /// Its `traceparent` header
+    pub traceparent: Option<String>,
+}
+
+impl Received {
+    /// The value of attribute `name`, in whichever group it's in.
+    pub fn attribute(&self, name: &str) -> Option<&IppValue> {
+        crate::jobs::find_attribute(&self.attributes, name)
+    }
+
+    /// Attribute `name` written out the way IPP tools show it.
+    pub fn text(&self, name: &str) -> Option<String> {
+        self.attribute(name).map(ToString::to_string)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    printers: HashMap<String, MockPrinter>,
+    received: Vec<Received>,
+    /// Which printer each job went to, by its job-id
+    jobs: HashMap<i32, String>,
+    next_job_id: i32,
+    /// What the proxy in front wants in `Authorization`, if there is one
+    token: Option<String>,
+    /// The server's default printer, if it has one
+    default: Option<String>,
+    /// Its classes, and the printers in each
+    classes: Vec<(String, Vec<String>)>,
+}
+
+#[derive(Clone)]
+pub struct MockCups {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockCups {
+    /// Starts answering on a port of its own, with `printers` set up the
+    /// default way.
+    pub async fn start(printers: &[&str]) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State {
+            printers: printers
+                .iter()
+                .map(|name| (name.to_string(), MockPrinter::default()))
+                .collect(),
+            next_job_id: 100,
+            ..State::default()
+        }));
+        let cups = MockCups { url, state };
+        let serving = cups.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(serving.clone().serve(socket));
+            }
+        });
+        cups
+    }
+
+    /// What to set `KPRINT_CUPS_URL` to.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Changes how `printer` behaves from here on.
+    pub fn update(&self, printer: &str, change: impl FnOnce(&mut MockPrinter)) {
+        let mut state = self.state.lock().unwrap();
+        change(
+            state
+                .printers
+                .get_mut(printer)
+                .expect("no such mock printer"),
+        );
+    }
+
+    /// Makes `printer` the server's default.
+    pub fn set_default(&self, printer: &str) {
+        self.state.lock().unwrap().default = Some(printer.to_string());
+    }
+
+    /// Adds a class of `members`.
+    pub fn add_class(&self, name: &str, members: &[&str]) {
+        let members = members.iter().map(ToString::to_string).collect();
+        self.state
+            .lock()
+            .unwrap()
+            .classes
+            .push((name.to_string(), members));
+    }
+
+    /// Puts a proxy in front that turns away requests without `token`.
+    pub fn require_token(&self, token: &str) {
+        self.state.lock().unwrap().token = Some(token.to_string());
+    }
+
+    /// Every request kprint sent, oldest first.
+    pub fn received(&self) -> Vec<Received> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    /// Every `operation` kprint sent, oldest first.
+    pub fn received_of(&self, operation: Operation) -> Vec<Received> {
+        self.received()
+            .into_iter()
+            .filter(|received| received.operation == Some(operation))
+            .collect()
+    }
+
+    async fn serve(self, socket: TcpStream) {
+        let (reader, mut writer) = socket.into_split();
+        let mut reader = BufReader::new(reader);
+        // Kept alive for as long as kprint's connection pool wants it
+        while let Some(request) = read_request(&mut reader).await {
+            let token = self.state.lock().unwrap().token.clone();
+            if token.is_some() && token != request.authorization {
+                let head = "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n";
+                if writer.write_all(head.as_bytes()).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+            let response = self.answer(request);
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/ipp\r\nContent-Length: {}\r\n\r\n",
+                response.len()
+            );
+            if writer.write_all(head.as_bytes()).await.is_err()
+                || writer.write_all(&response).await.is_err()
+            {
+                return;
+            }
+        }
+    }
+
+    fn answer(&self, request: HttpRequest) -> Vec<u8> {
+        let printer = request.path.rsplit('/').next().unwrap_or_default();
+        let printer = printer.to_string();
+        let body = Cursor::new(request.body);
+        let (header, attributes, reader) = IppParser::new(IppReader::new(body))
+            .parse_parts()
+            .expect("kprint sent something that isn't IPP");
+        // Whatever's after the attributes is the document
+        let body = reader.into_inner();
+        let document = body.get_ref()[body.position() as usize..].to_vec();
+        let operation = Operation::from_u16(header.operation_or_status);
+
+        let mut state = self.state.lock().unwrap();
+        let received = Received {
+            printer: printer.clone(),
+            operation,
+            version: header.version,
+            attributes,
+            document,
+            traceparent: request.traceparent,
+        };
+        let respond =
+            |status| IppRequestResponse::new_response(header.version, status, header.request_id);
+        let Some(config) = state.printers.get(&printer).cloned() else {
+            state.received.push(received);
+            return respond(StatusCode::ClientErrorNotFound).to_bytes().to_vec();
+        };
+        let job_id = match received.attribute(IppAttribute::JOB_ID) {
+            Some(IppValue::Integer(id)) => Some(*id),
+            _ => None,
+        };
+        let known_job = job_id.is_some_and(|id| state.jobs.get(&id) == Some(&printer));
+
+        let response = match operation {
+            Some(Operation::CreateJob) if !config.creates_jobs => {
+                respond(StatusCode::ServerErrorOperationNotSupported)
+            }
+            Some(Operation::SendDocument) if known_job && config.send_busy_for > 0 => {
+                state.printers.get_mut(&printer).unwrap().send_busy_for -= 1;
+                respond(StatusCode::ServerErrorBusy)
+            }
+            Some(Operation::PrintJob | Operation::PrintUri | Operation::CreateJob)
+                if config.busy_for > 0 =>
+            {
+                state.printers.get_mut(&printer).unwrap().busy_for -= 1;
+                respond(StatusCode::ServerErrorBusy)
+            }
+            Some(Operation::PrintJob | Operation::PrintUri | Operation::CreateJob)
+                if !config.print_status.is_success() =>
+            {
+                let mut response = respond(config.print_status);
+                response.attributes_mut().add(
+                    DelimiterTag::OperationAttributes,
+                    IppAttribute::new(
+                        IppAttribute::STATUS_MESSAGE,
+                        IppValue::TextWithoutLanguage(config.print_status.to_string()),
+                    ),
+                );
+                response
+            }
+            Some(
+                Operation::PrintJob
+                | Operation::PrintUri
+                | Operation::CreateJob
+                | Operation::SendDocument,
+            ) => {
+                let id = match operation {
+                    Some(Operation::SendDocument) if known_job => job_id.unwrap(),
+                    Some(Operation::SendDocument) => {
+                        state.received.push(received);
+                        return respond(StatusCode::ClientErrorNotFound).to_bytes().to_vec();
+                    }
+                    _ => {
+                        let id = state.next_job_id;
+                        state.next_job_id += 1;
+                        state.jobs.insert(id, printer.clone());
+                        id
+                    }
+                };
+                let mut response = respond(StatusCode::SuccessfulOk);
+                for attribute in [
+                    IppAttribute::new(IppAttribute::JOB_ID, IppValue::Integer(id)),
+                    IppAttribute::new(
+                        IppAttribute::JOB_STATE,
+                        IppValue::Enum(JobState::Pending as i32),
+                    ),
+                ] {
+                    response
+                        .attributes_mut()
+                        .add(DelimiterTag::JobAttributes, attribute);
+                }
+                response
+            }
+            Some(Operation::GetJobAttributes) if known_job => {
+                let mut response = respond(StatusCode::SuccessfulOk);
+                for attribute in [
+                    IppAttribute::new(IppAttribute::JOB_ID, IppValue::Integer(job_id.unwrap())),
+                    IppAttribute::new(
+                        IppAttribute::JOB_STATE,
+                        IppValue::Enum(config.job_state as i32),
+                    ),
+                ] {
+                    response
+                        .attributes_mut()
+                        .add(DelimiterTag::JobAttributes, attribute);
+                }
+                response
+            }
+            Some(Operation::GetJobs) => {
+                let mut response = respond(StatusCode::SuccessfulOk);
+                let waiting = matches!(
+                    config.job_state,
+                    JobState::Pending | JobState::PendingHeld | JobState::Processing
+                );
+                let mut ids = state
+                    .jobs
+                    .iter()
+                    .filter(|(_, on)| **on == printer && waiting)
+                    .map(|(id, _)| *id)
+                    .collect::<Vec<_>>();
+                ids.sort();
+                for id in ids {
+                    let mut group = IppAttributeGroup::new(DelimiterTag::JobAttributes);
+                    for attribute in [
+                        IppAttribute::new(IppAttribute::JOB_ID, IppValue::Integer(id)),
+                        IppAttribute::new(
+                            IppAttribute::JOB_STATE,
+                            IppValue::Enum(config.job_state as i32),
+                        ),
+                    ] {
+                        group
+                            .attributes_mut()
+                            .insert(attribute.name().to_string(), attribute);
+                    }
+                    response.attributes_mut().groups_mut().push(group);
+                }
+                response
+            }
+            Some(Operation::CupsGetDefault) => match &state.default {
+                Some(default) => {
+                    let mut response = respond(StatusCode::SuccessfulOk);
+                    response.attributes_mut().add(
+                        DelimiterTag::PrinterAttributes,
+                        IppAttribute::new(
+                            IppAttribute::PRINTER_NAME,
+                            IppValue::NameWithoutLanguage(default.clone()),
+                        ),
+                    );
+                    response
+                }
+                None => respond(StatusCode::ClientErrorNotFound),
+            },
+            Some(Operation::CupsGetClasses) => {
+                let mut response = respond(StatusCode::SuccessfulOk);
+                for (name, members) in &state.classes {
+                    let mut group = IppAttributeGroup::new(DelimiterTag::PrinterAttributes);
+                    let members = members
+                        .iter()
+                        .map(|member| IppValue::NameWithoutLanguage(member.clone()))
+                        .collect();
+                    for attribute in [
+                        IppAttribute::new(
+                            IppAttribute::PRINTER_NAME,
+                            IppValue::NameWithoutLanguage(name.clone()),
+                        ),
+                        IppAttribute::new("member-names", IppValue::Array(members)),
+                    ] {
+                        group
+                            .attributes_mut()
+                            .insert(attribute.name().to_string(), attribute);
+                    }
+                    response.attributes_mut().groups_mut().push(group);
+                }
+                response
+            }
+            Some(Operation::CupsMoveJob) if known_job => {
+                let waiting = matches!(config.job_state, JobState::Pending | JobState::PendingHeld);
+                let to = match received.attribute("job-printer-uri") {
+                    Some(IppValue::Uri(uri)) => uri.rsplit('/').next().map(str::to_string),
+                    _ => None,
+                };
+                match to {
+                    Some(to) if waiting && state.printers.contains_key(&to) => {
+                        state.jobs.insert(job_id.unwrap(), to);
+                        respond(StatusCode::SuccessfulOk)
+                    }
+                    Some(_) if !waiting => respond(StatusCode::ClientErrorNotPossible),
+                    _ => respond(StatusCode::ClientErrorNotFound),
+                }
+            }
+            Some(Operation::CancelJob) if known_job => respond(config.cancel_status),
+            Some(Operation::GetJobAttributes | Operation::CancelJob) => {
+                respond(StatusCode::ClientErrorNotFound)
+            }
+            Some(Operation::GetPrinterAttributes) => {
+                let mut response = respond(StatusCode::SuccessfulOk);
+                for attribute in printer_attributes(&config) {
+                    response
+                        .attributes_mut()
+                        .add(DelimiterTag::PrinterAttributes, attribute);
+                }
+                response
+            }
+            _ => respond(StatusCode::SuccessfulOk),
+        };
+        state.received.push(received);
+        response.to_bytes().to_vec()
+    }
+}
+
+fn keywords(keywords: &[&str]) -> IppValue {
+    IppValue::Array(
+        keywords
+            .iter()
+            .map(|keyword| IppValue::Keyword(keyword.to_string()))
+            .collect(),
+    )
+}
+
+fn printer_attributes(config: &MockPrinter) -> Vec<IppAttribute> {
+    let formats = config
+        .formats
+        .iter()
+        .map(|format| IppValue::MimeMediaType(format.to_string()))
+        .collect();
+    let mut attributes = vec![
+        IppAttribute::new(
+            IppAttribute::PRINTER_STATE,
+            IppValue::Enum(config.state as i32),
+        ),
+        IppAttribute::new(
+            IppAttribute::PRINTER_STATE_REASONS,
+            keywords(&config.state_reasons),
+        ),
+        IppAttribute::new(
+            IppAttribute::PRINTER_IS_ACCEPTING_JOBS,
+            IppValue::Boolean(config.accepting_jobs),
+        ),
+        IppAttribute::new(
+            IppAttribute::DOCUMENT_FORMAT_SUPPORTED,
+            IppValue::Array(formats),
+        ),
+        IppAttribute::new("sides-supported", keywords(&config.sides)),
+        IppAttribute::new("print-color-mode-supported", keywords(&config.color_modes)),
+    ];
+    if !config.uri_schemes.is_empty() {
+        let schemes = config
+            .uri_schemes
+            .iter()
+            .map(|scheme| IppValue::UriScheme(scheme.to_string()))
+            .collect();
+        let operations = [
+            Operation::PrintJob,
+            Operation::PrintUri,
+            Operation::CreateJob,
+        ]
+        .into_iter()
+        .map(|operation| IppValue::Enum(operation as i32))
+        .collect();
+        attributes.push(IppAttribute::new(
+            "reference-uri-schemes-supported",
+            IppValue::Array(schemes),
+        ));
+        attributes.push(IppAttribute::new(
+            IppAttribute::OPERATIONS_SUPPORTED,
+            IppValue::Array(operations),
+        ));
+    }
+    if !config.media_sources.is_empty() {
+        attributes.push(IppAttribute::new(
+            "media-source-supported",
+            keywords(&config.media_sources),
+        ));
+    }
+    attributes
+}
+
+/// The parts of an HTTP request the mock pays attention to.
+struct HttpRequest {
+    path: String,
+    authorization: Option<String>,
+    traceparent: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Reads one HTTP request, or returns `None` once the connection's closed,
+/// including partway through a request.
+async fn read_request(reader: &mut BufReader<OwnedReadHalf>) -> Option<HttpRequest> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await.ok()? == 0 {
+        return None;
+    }
+    let path = line.split_whitespace().nth(1)?.to_string();
+    let (mut length, mut chunked) = (0, false);
+    let (mut authorization, mut traceparent) = (None, None);
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await.ok()? == 0 {
+            return None;
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':')?;
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => length = value.trim().parse().ok()?,
+            "transfer-encoding" => chunked = value.trim().eq_ignore_ascii_case("chunked"),
+            "authorization" => authorization = Some(value.trim().to_string()),
+            "traceparent" => traceparent = Some(value.trim().to_string()),
+            _ => {}
+        }
+    }
+
+    let mut body = vec![];
+    if !chunked {
+        body.resize(length, 0);
+        reader.read_exact(&mut body).await.ok()?;
+        return Some(HttpRequest {
+            path,
+            authorization,
+            traceparent,
+            body,
+        });
+    }
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await.ok()? == 0 {
+            return None;
+        }
+        let size = usize::from_str_radix(line.trim().split(';').next()?, 16).ok()?;
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).await.ok()?;
+        // The line break after the chunk, or the end of the last one
+        line.clear();
+        if reader.read_line(&mut line).await.ok()? == 0 {
+            return None;
+        }
+        if size == 0 {
+            return Some(HttpRequest {
+                path,
+                authorization,
+                traceparent,
+                body,
+            });
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Tests of kprint end to end, against a CUPS server of their own.
+
+mod config;
+mod confirmations;
+mod cups;
+mod demo;
+mod dialect;
+mod digest;
+mod dry_run;
+mod envelope;
+mod errors;
+mod flags;
+mod issuers;
+mod job_links;
+mod logging;
+mod maintenance;
+mod metrics;
+mod mirror;
+mod mock_cups;
+mod null_printer;
+mod options;
+mod packets;
+mod preview;
+mod print_passwords;
+mod printing;
+mod probe;
+mod queue_cache;
+mod quota;
+mod receipt;
+mod recording;
+mod streaming;
+mod timeout;
+mod trace;
+mod typescript;
+
+use crate::app::AppState;
+use crate::document::Document;
+use kprint_client::models::{ColorMode, DuplexMode, PageSelection, PrintOptions};
+use mock_cups::MockCups;
+
+/// Held while the environment's set up for an app, since it's shared by every
+/// test running at once
+static ENV: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+pub const USER: &str = "skyz";
+
+/// An app printing to `printers` on the CUPS server at `cups`, retrying
+/// quickly, and set up by `vars` on top of that.
+pub async fn app_with(cups: &str, printers: &str, vars: &[(&str, &str)]) -> AppState {
+    try_app_with(cups, printers, vars).await.unwrap()
+}
+
+/// What [`app_with`] makes, for when it's meant not to start.
+pub async fn try_app_with(
+    cups: &str,
+    printers: &str,
+    vars: &[(&str, &str)],
+) -> anyhow::Result<AppState> {
+    let _env = ENV.lock().await;
+    std::env::set_var("KPRINT_PRINTERS", printers);
+    std::env::set_var("KPRINT_CUPS_URL", cups);
+    std::env::set_var("KPRINT_IPP_RETRIES", "3");
+    std::env::set_var("KPRINT_IPP_RETRY_DELAY_MS", "1");
+    for (name, value) in vars {
+        std::env::set_var(name, value);
+    }
+    let app = crate::app::get_app_data().await;
+    // Only for this app
+    for (name, _) in vars {
+        std::env::remove_var(name);
+    }
+    app
+}
+
+/// A mock CUPS with printers `a` and `b`, and an app that prints to them.
+pub async fn setup() -> (MockCups, AppState) {
+    let cups = MockCups::start(&["a", "b"]).await;
+    let app = app_with(cups.url(), "a b", &[]).await;
+    (cups, app)
+}
+
+pub fn options() -> PrintOptions {
+    PrintOptions {
+        sides: DuplexMode::OneSided,
+        color_mode: ColorMode::Grayscale,
+        pages: PageSelection::all(),
+        copies: 1,
+        title: "Constitution".to_string(),
+        cover_sheet: false,
+        allow_fallback: false,
+        separate_jobs: false,
+        tags: vec![],
+        media: None,
+        margins: None,
+        offset: None,
+        poster: None,
+        envelope: None,
+    }
+}
+
+/// A PDF with one page.
+pub fn pdf() -> Vec<u8> {
+    crate::test_page::render("a", None, "kprint", USER).unwrap()
+}
+
+/// A little plain text.
+pub fn text() -> Document {
+    Document::Buffered(b"Hello from the tests\n".to_vec().into())
+}
@@ -0,0 +1,108 @@
+use super::{app_with, options, pdf, USER};
+use crate::app::AppState;
+use crate::error::KprintError;
+use crate::service::{self, Pause};
+use kprint_client::models::{JobState, PrinterState};
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// An app with only null printer `dev`, which takes `print_seconds` over each
+/// job, and where it writes them.
+async fn null_app(print_seconds: &str) -> (AppState, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("kprint-null-{}", Uuid::new_v4()));
+    let app = app_with(
+        "http://cups.invalid",
+        "null:dev",
+        &[
+            ("KPRINT_NULL_PRINTER_DIR", dir.to_str().unwrap()),
+            ("KPRINT_NULL_PRINT_SECONDS", print_seconds),
+        ],
+    )
+    .await;
+    (app, dir)
+}
+
+async fn print(app: &AppState) -> Uuid {
+    service::submit(
+        app,
+        USER,
+        false,
+        &[],
+        "dev".to_string(),
+        options(),
+        pdf().into(),
+    )
+    .await
+    .unwrap()
+    .job
+    .id
+}
+
+async fn state(app: &AppState, id: Uuid) -> JobState {
+    service::job_status(app, USER, id).await.unwrap().state
+}
+
+#[tokio::test]
+async fn writes_out_what_its_sent_and_prints_it() {
+    let (app, dir) = null_app("0").await;
+
+    let id = print(&app).await;
+
+    let written = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|file| file.unwrap().path())
+        .collect::<Vec<_>>();
+    assert_eq!(written.len(), 1);
+    assert_eq!(written[0].extension().unwrap(), "pdf");
+    assert_eq!(std::fs::read(&written[0]).unwrap(), pdf());
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(state(&app, id).await, JobState::Completed);
+    assert_eq!(service::printers(&app), vec!["dev"]);
+    let status = service::printer_status(&app, "dev").await.unwrap();
+    assert_eq!(status.state, PrinterState::Idle);
+    assert!(status.accepting_jobs);
+}
+
+#[tokio::test]
+async fn prints_one_job_at_a_time() {
+    let (app, dir) = null_app("60").await;
+
+    let first = print(&app).await;
+    let second = print(&app).await;
+
+    assert_eq!(state(&app, first).await, JobState::Processing);
+    assert_eq!(state(&app, second).await, JobState::Pending);
+    service::cancel_job(&app, USER, first).await.unwrap();
+    assert_eq!(state(&app, first).await, JobState::Canceled);
+    assert_eq!(state(&app, second).await, JobState::Processing);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn turns_jobs_away_while_paused() {
+    let (app, dir) = null_app("0").await;
+
+    let status = service::set_paused(&app, "admin", "dev", Pause::Pause)
+        .await
+        .unwrap();
+    let err = service::submit(
+        &app,
+        USER,
+        false,
+        &[],
+        "dev".to_string(),
+        options(),
+        pdf().into(),
+    )
+    .await
+    .map(|_| ())
+    .unwrap_err();
+
+    assert!(status.paused);
+    assert!(matches!(err, KprintError::PrinterPaused(ref name) if name == "dev"));
+    service::set_paused(&app, "admin", "dev", Pause::Resume)
+        .await
+        .unwrap();
+    print(&app).await;
+    std::fs::remove_dir_all(&dir).unwrap();
+}
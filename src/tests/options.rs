@@ -0,0 +1,337 @@
+use super::mock_cups::{MockCups, Received};
+use super::{app_with, options, pdf, setup, text, USER};
+use crate::error::KprintError;
+use crate::options::PrintQuery;
+use crate::service;
+use ipp::prelude::*;
+use kprint_client::models::{
+    ColorMode, DuplexMode, FieldError, MediaMargins, PrintOffset, PrintOptions,
+};
+
+/// The Create-Job `options` make, and the Send-Document for its document of
+/// plain text.
+async fn print_with(options: PrintOptions) -> (Received, Received) {
+    let (cups, app) = setup().await;
+    service::submit(&app, USER, false, &[], "a".to_string(), options, text())
+        .await
+        .unwrap();
+    (
+        cups.received_of(Operation::CreateJob).remove(0),
+        cups.received_of(Operation::SendDocument).remove(0),
+    )
+}
+
+#[tokio::test]
+async fn maps_sides_color_and_copies() {
+    let (printed, _) = print_with(PrintOptions {
+        sides: DuplexMode::TwoSidedLongEdge,
+        color_mode: ColorMode::Color,
+        copies: 3,
+        ..options()
+    })
+    .await;
+
+    assert_eq!(
+        printed.text("sides").as_deref(),
+        Some("two-sided-long-edge")
+    );
+    assert_eq!(printed.text("print-color-mode").as_deref(), Some("color"));
+    assert_eq!(printed.attribute("copies"), Some(&IppValue::Integer(3)));
+}
+
+#[tokio::test]
+async fn grayscale_is_monochrome_to_the_printer() {
+    let (printed, _) = print_with(options()).await;
+
+    assert_eq!(printed.text("sides").as_deref(), Some("one-sided"));
+    assert_eq!(
+        printed.text("print-color-mode").as_deref(),
+        Some("monochrome")
+    );
+}
+
+#[tokio::test]
+async fn sends_every_page_range() {
+    let (printed, _) = print_with(PrintOptions {
+        pages: "1-3,5,8-9".parse().unwrap(),
+        ..options()
+    })
+    .await;
+
+    assert_eq!(
+        printed.attribute("page-ranges"),
+        Some(&IppValue::Array(vec![
+            IppValue::RangeOfInteger { min: 1, max: 3 },
+            IppValue::RangeOfInteger { min: 5, max: 5 },
+            IppValue::RangeOfInteger { min: 8, max: 9 },
+        ]))
+    );
+}
+
+#[tokio::test]
+async fn leaves_page_ranges_out_for_every_page() {
+    let (printed, _) = print_with(options()).await;
+
+    assert_eq!(printed.attribute("page-ranges"), None);
+}
+
+#[tokio::test]
+async fn says_what_the_document_is_when_the_printer_takes_it() {
+    let (_, sent) = print_with(options()).await;
+
+    assert_eq!(sent.text("document-format").as_deref(), Some("text/plain"));
+}
+
+#[tokio::test]
+async fn leaves_the_format_to_cups_when_the_printer_doesnt_list_it() {
+    let (cups, app) = setup().await;
+    cups.update("a", |printer| printer.formats = vec!["application/pdf"]);
+
+    service::submit(&app, USER, false, &[], "a".to_string(), options(), text())
+        .await
+        .unwrap();
+
+    let sent = cups.received_of(Operation::SendDocument).remove(0);
+    assert_eq!(sent.attribute("document-format"), None);
+}
+
+/// The default options, with `media` parsed out of the JSON a print request
+/// would have.
+fn with_media(media: &str) -> Result<PrintOptions, Vec<FieldError>> {
+    PrintQuery {
+        sides: Some("one-sided".to_string()),
+        color_mode: Some("grayscale".to_string()),
+        pages: Some(String::new()),
+        copies: Some("1".to_string()),
+        title: Some("Constitution".to_string()),
+        media: Some(media.to_string()),
+        ..PrintQuery::default()
+    }
+    .validate()
+}
+
+#[tokio::test]
+async fn spells_media_out_as_a_media_col() {
+    let options = with_media(
+        r#"{"xDimension":91440,"yDimension":121920,"margins":{"top":0,"bottom":0,"left":500,"right":500},"source":"main-roll","type":"bond"}"#,
+    )
+    .unwrap();
+    let (printed, _) = print_with(options).await;
+
+    let Some(IppValue::Collection(media)) = printed.attribute("media-col") else {
+        panic!("no media-col in {:?}", printed.attribute("media-col"));
+    };
+    let Some(IppValue::Collection(size)) = media.get("media-size") else {
+        panic!("no media-size in {media:?}");
+    };
+    assert_eq!(size.get("x-dimension"), Some(&IppValue::Integer(91440)));
+    assert_eq!(size.get("y-dimension"), Some(&IppValue::Integer(121920)));
+    assert_eq!(
+        media.get("media-left-margin"),
+        Some(&IppValue::Integer(500))
+    );
+    assert_eq!(media.get("media-top-margin"), Some(&IppValue::Integer(0)));
+    assert_eq!(
+        media.get("media-source"),
+        Some(&IppValue::Keyword("main-roll".to_string()))
+    );
+    assert_eq!(
+        media.get("media-type"),
+        Some(&IppValue::Keyword("bond".to_string()))
+    );
+    assert_eq!(printed.attribute("media"), None);
+}
+
+#[tokio::test]
+async fn media_has_to_make_sense_and_be_there() {
+    for media in [
+        r#"{"xDimension":91440}"#,
+        r#"{"xDimension":1000,"yDimension":1000,"margins":{"top":0,"bottom":0,"left":500,"right":500}}"#,
+        r#"{"source":"Main Roll"}"#,
+        r#"{"colour":"blue"}"#,
+        "A4",
+    ] {
+        let fields = with_media(media).unwrap_err();
+        assert_eq!(fields.len(), 1, "{media}");
+        assert_eq!(fields[0].field, "media", "{media}");
+    }
+    assert!(with_media("").unwrap().media.is_none());
+
+    let (cups, app) = setup().await;
+    cups.update("a", |printer| printer.media_sources = vec!["main-roll"]);
+    let err = service::submit(
+        &app,
+        USER,
+        false,
+        &[],
+        "a".to_string(),
+        with_media(r#"{"source":"tray-9"}"#).unwrap(),
+        text(),
+    )
+    .await
+    .err()
+    .unwrap();
+    assert!(matches!(err, KprintError::Validation(fields) if fields[0].field == "media"));
+    assert!(cups.received_of(Operation::CreateJob).is_empty());
+}
+
+#[tokio::test]
+async fn sends_margins_and_offsets_to_printers_that_take_them() {
+    let margins = MediaMargins {
+        top: 635,
+        bottom: 635,
+        left: 0,
+        right: 0,
+    };
+    let (printed, _) = print_with(PrintOptions {
+        margins: Some(margins),
+        offset: Some(PrintOffset { x: 200, y: -100 }),
+        ..options()
+    })
+    .await;
+
+    let Some(IppValue::Collection(media)) = printed.attribute("media-col") else {
+        panic!("no media-col in {:?}", printed.attribute("media-col"));
+    };
+    assert_eq!(media.get("media-top-margin"), Some(&IppValue::Integer(635)));
+    assert_eq!(media.get("media-left-margin"), Some(&IppValue::Integer(0)));
+    assert_eq!(media.get("media-size"), None);
+    assert_eq!(
+        printed.attribute("x-image-shift"),
+        Some(&IppValue::Integer(200))
+    );
+    assert_eq!(
+        printed.attribute("y-image-shift"),
+        Some(&IppValue::Integer(-100))
+    );
+
+    let with = |margins: &str, offset: &str| {
+        PrintQuery {
+            sides: Some("one-sided".to_string()),
+            color_mode: Some("grayscale".to_string()),
+            pages: Some(String::new()),
+            copies: Some("1".to_string()),
+            title: Some("Constitution".to_string()),
+            media: Some(r#"{"xDimension":1000,"yDimension":1000}"#.to_string()),
+            margins: Some(margins.to_string()),
+            offset: Some(offset.to_string()),
+            ..PrintQuery::default()
+        }
+        .validate()
+    };
+    let fields = with(
+        r#"{"top":0,"bottom":0,"left":500,"right":500}"#,
+        r#"{"x":20000,"y":0}"#,
+    )
+    .unwrap_err();
+    let fields = fields.iter().map(|field| &*field.field).collect::<Vec<_>>();
+    assert_eq!(fields, ["margins", "offset"]);
+    let options = with("", r#"{"x":0,"y":0}"#).unwrap();
+    assert!(options.margins.is_none() && options.offset.is_none());
+}
+
+#[tokio::test]
+async fn draws_margins_and_offsets_in_for_printers_that_ignore_them() {
+    let cups = MockCups::start(&["a", "b"]).await;
+    let app = app_with(cups.url(), "a b", &[("KPRINT_PDF_MARGINS", "a")]).await;
+    let options = PrintOptions {
+        margins: Some(MediaMargins {
+            top: 2540,
+            bottom: 2540,
+            left: 2540,
+            right: 2540,
+        }),
+        offset: Some(PrintOffset { x: 0, y: 2540 }),
+        ..options()
+    };
+
+    service::submit(
+        &app,
+        USER,
+        false,
+        &[],
+        "a".to_string(),
+        options,
+        pdf().into(),
+    )
+    .await
+    .unwrap();
+
+    let printed = cups.received_of(Operation::CreateJob).remove(0);
+    assert_eq!(printed.attribute("media-col"), None);
+    assert_eq!(printed.attribute("y-image-shift"), None);
+    let sent = cups.received_of(Operation::SendDocument).remove(0);
+    let document = lopdf::Document::load_mem(&sent.document).unwrap();
+    let page = *document.get_pages().values().next().unwrap();
+    let content = String::from_utf8(document.get_page_content(page).unwrap()).unwrap();
+    // A letter page, an inch in from each side and an inch further down
+    let scale = (612.0f32 - 144.0) / 612.0;
+    let x = 72.0 + ((612.0 - 144.0) - scale * 612.0) / 2.0;
+    let y = 72.0 + ((792.0 - 144.0) - scale * 792.0) / 2.0 - 72.0;
+    assert!(
+        content.starts_with(&format!("q {scale} 0 0 {scale} {x} {y} cm")),
+        "{content}"
+    );
+    assert!(content.ends_with('Q'), "{content}");
+}
+
+#[tokio::test]
+async fn makes_posters_of_pdfs() {
+    let (cups, app) = setup().await;
+    let options = PrintOptions {
+        pages: "1".parse().unwrap(),
+        poster: Some("3x2".parse().unwrap()),
+        ..options()
+    };
+
+    service::submit(
+        &app,
+        USER,
+        false,
+        &[],
+        "a".to_string(),
+        options,
+        pdf().into(),
+    )
+    .await
+    .unwrap();
+
+    let printed = cups.received_of(Operation::CreateJob).remove(0);
+    assert_eq!(
+        printed.attribute("page-ranges"),
+        Some(&IppValue::RangeOfInteger { min: 1, max: 6 })
+    );
+    let sent = cups.received_of(Operation::SendDocument).remove(0);
+    let document = lopdf::Document::load_mem(&sent.document).unwrap();
+    let sheets = document.get_pages();
+    assert_eq!(sheets.len(), 6);
+    for sheet in sheets.into_values() {
+        let content = String::from_utf8(document.get_page_content(sheet).unwrap()).unwrap();
+        assert!(content.contains("/KprintPoster Do"), "{content}");
+    }
+
+    let with = |poster: &str| {
+        PrintQuery {
+            sides: Some("one-sided".to_string()),
+            color_mode: Some("grayscale".to_string()),
+            pages: Some(String::new()),
+            copies: Some("1".to_string()),
+            title: Some("Banner".to_string()),
+            poster: Some(poster.to_string()),
+            ..PrintQuery::default()
+        }
+        .validate()
+    };
+    assert_eq!(
+        with(" 2X3 ")
+            .unwrap()
+            .poster
+            .map(|poster| poster.to_string()),
+        Some("2x3".to_string())
+    );
+    assert_eq!(with("1x1").unwrap().poster, None);
+    for poster in ["9x1", "0x2", "big", "2x"] {
+        let fields = with(poster).unwrap_err();
+        assert_eq!(fields[0].field, "poster", "{poster}");
+    }
+}
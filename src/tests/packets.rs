@@ -0,0 +1,94 @@
+use super::{options, pdf, setup, text, USER};
+use crate::error::KprintError;
+use crate::jobs::Delivery;
+use crate::service;
+use ipp::prelude::*;
+use kprint_client::models::JobState;
+
+#[tokio::test]
+async fn documents_go_to_one_job_that_prints_once_closed() {
+    let (cups, app) = setup().await;
+
+    let opened = service::open_job(&app, USER, false, "a".to_string(), options())
+        .await
+        .unwrap();
+    let id = opened.job.id;
+    assert_eq!(opened.job.ipp_job_id, Some(100));
+    service::add_document(&app, USER, &[], id, text())
+        .await
+        .unwrap();
+    service::add_document(&app, USER, &[], id, pdf().into())
+        .await
+        .unwrap();
+    assert!(matches!(
+        app.jobs.get(&id).unwrap().delivery,
+        Delivery::Open
+    ));
+    let status = service::close_job(&app, USER, id).await.unwrap();
+
+    assert_eq!(status.state, JobState::Pending);
+    assert!(matches!(
+        app.jobs.get(&id).unwrap().delivery,
+        Delivery::Delivered
+    ));
+    assert_eq!(cups.received_of(Operation::CreateJob).len(), 1);
+    assert!(cups.received_of(Operation::PrintJob).is_empty());
+    let sent = cups.received_of(Operation::SendDocument);
+    assert_eq!(sent.len(), 3);
+    for (sent, last) in sent.iter().zip([false, false, true]) {
+        assert_eq!(sent.attribute("job-id"), Some(&IppValue::Integer(100)));
+        assert_eq!(
+            sent.attribute("last-document"),
+            Some(&IppValue::Boolean(last))
+        );
+    }
+    assert_eq!(sent[1].document, pdf());
+    assert!(sent[2].document.is_empty());
+    let history = app.history.filter(|_| true);
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].id, id);
+
+    let err = service::add_document(&app, USER, &[], id, text())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, KprintError::JobNotOpen(job) if job == id));
+}
+
+#[tokio::test]
+async fn open_jobs_need_a_document_and_their_owner() {
+    let (cups, app) = setup().await;
+    let id = service::open_job(&app, USER, false, "a".to_string(), options())
+        .await
+        .unwrap()
+        .job
+        .id;
+
+    let err = service::close_job(&app, USER, id).await.unwrap_err();
+    assert!(matches!(err, KprintError::InvalidRequest(_)));
+    let err = service::add_document(&app, "someone-else", &[], id, text())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, KprintError::JobNotFound(_)));
+    assert!(cups.received_of(Operation::SendDocument).is_empty());
+}
+
+#[tokio::test]
+async fn canceling_an_open_job_closes_it() {
+    let (cups, app) = setup().await;
+    let id = service::open_job(&app, USER, false, "a".to_string(), options())
+        .await
+        .unwrap()
+        .job
+        .id;
+    service::add_document(&app, USER, &[], id, text())
+        .await
+        .unwrap();
+
+    service::cancel_job(&app, USER, id).await.unwrap();
+
+    assert_eq!(cups.received_of(Operation::CancelJob).len(), 1);
+    let err = service::close_job(&app, USER, id).await.unwrap_err();
+    assert!(matches!(err, KprintError::JobNotOpen(_)));
+    let history = app.history.filter(|_| true);
+    assert_eq!(history[0].state, JobState::Canceled);
+}
@@ -0,0 +1,65 @@
+use super::{app_with, options, pdf, USER};
+use crate::app::AppState;
+use crate::document::Document;
+use crate::service;
+use ipp::prelude::IppPayload;
+use std::os::unix::fs::PermissionsExt;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// A stand-in for pdftoppm that draws every preview as the same few bytes.
+fn pdftoppm(dir: &std::path::Path) -> std::path::PathBuf {
+    std::fs::create_dir_all(dir).unwrap();
+    let script = dir.join("pdftoppm");
+    std::fs::write(
+        &script,
+        "#!/bin/sh\nfor last; do :; done\nprintf png > \"$last.png\"\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    script
+}
+
+async fn print(app: &AppState, document: Document) -> Uuid {
+    let submitted = service::submit(
+        app,
+        USER,
+        false,
+        &[],
+        "dev".to_string(),
+        options(),
+        document,
+    );
+    submitted.await.unwrap().job.id
+}
+
+async fn preview(app: &AppState, job: Uuid) -> Option<Vec<u8>> {
+    for _ in 0..50 {
+        if let Ok(preview) = service::job_preview(app, USER, job) {
+            return Some(preview.to_vec());
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    None
+}
+
+#[tokio::test]
+async fn only_documents_kprint_kept_get_previews() {
+    let dir = std::env::temp_dir().join(format!("kprint-preview-{}", Uuid::new_v4()));
+    let script = pdftoppm(&dir);
+    let app = app_with(
+        "http://cups.invalid",
+        "null:dev",
+        &[("KPRINT_PDFTOPPM", script.to_str().unwrap())],
+    )
+    .await;
+
+    let buffered = print(&app, pdf().into()).await;
+    let streamed = IppPayload::new_async(futures::io::Cursor::new(pdf()));
+    let streamed = print(&app, Document::Streaming(streamed)).await;
+
+    assert_eq!(preview(&app, buffered).await.unwrap(), b"png");
+    // Streamed straight to the printer, not held on to for one
+    assert!(preview(&app, streamed).await.is_none());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,19 @@
+use super::{app_with, USER};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn print_passwords_outlast_a_restart_until_revoked() {
+    let file = std::env::temp_dir().join(format!("kprint-passwords-{}.json", Uuid::new_v4()));
+    let vars = [("KPRINT_PRINT_PASSWORDS_FILE", file.to_str().unwrap())];
+    let app = app_with("http://cups.invalid", "null:dev", &vars).await;
+
+    let password = app.print_passwords.generate(USER).await.unwrap();
+    let restarted = app_with("http://cups.invalid", "null:dev", &vars).await;
+
+    assert!(restarted.print_passwords.verify(USER, &password));
+    assert!(!restarted.print_passwords.verify(USER, "hunter2"));
+    assert!(!std::fs::read_to_string(&file).unwrap().contains(&password));
+    restarted.print_passwords.revoke(USER).await.unwrap();
+    assert!(!restarted.print_passwords.verify(USER, &password));
+    std::fs::remove_file(&file).unwrap();
+}
@@ -0,0 +1,147 @@
+use super::mock_cups::MockCups;
+use super::{app_with, options, pdf, setup, text, USER};
+use crate::error::KprintError;
+use crate::service;
+use ipp::model::JobState as IppJobState;
+use ipp::prelude::*;
+use kprint_client::models::JobState;
+
+#[tokio::test]
+async fn sends_the_document_as_it_was_uploaded() {
+    let (cups, app) = setup().await;
+    let document = pdf();
+
+    let submitted = service::submit(
+        &app,
+        USER,
+        false,
+        &[],
+        "a".to_string(),
+        options(),
+        document.clone().into(),
+    )
+    .await
+    .unwrap();
+
+    assert!(cups.received_of(Operation::PrintJob).is_empty());
+    let created = cups.received_of(Operation::CreateJob);
+    assert_eq!(created.len(), 1);
+    assert_eq!(created[0].printer, "a");
+    assert_eq!(
+        created[0]
+            .text(IppAttribute::REQUESTING_USER_NAME)
+            .as_deref(),
+        Some(USER)
+    );
+    assert_eq!(
+        created[0].text(IppAttribute::JOB_NAME).as_deref(),
+        Some("Constitution")
+    );
+    let sent = cups.received_of(Operation::SendDocument);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].document, document);
+    assert_eq!(sent[0].attribute("job-id"), Some(&IppValue::Integer(100)));
+    assert_eq!(submitted.job.ipp_job_id, Some(100));
+    assert_eq!(submitted.state, JobState::Pending);
+}
+
+#[tokio::test]
+async fn keeps_the_job_with_the_printers_id_for_it() {
+    let (_cups, app) = setup().await;
+
+    let submitted = service::submit(&app, USER, false, &[], "b".to_string(), options(), text())
+        .await
+        .unwrap();
+
+    let job = app.jobs.get(&submitted.job.id).unwrap();
+    assert_eq!(job.ipp_job_id, Some(100));
+    assert_eq!(job.owner, USER);
+    assert_eq!(job.printer, "b");
+    let entry = app.history.get(&submitted.job.id).unwrap();
+    assert_eq!(entry.state, JobState::Pending);
+    assert_eq!(entry.failure, None);
+}
+
+#[tokio::test]
+async fn printers_fetch_documents_they_can_reach_themselves() {
+    let cups = MockCups::start(&["a", "b"]).await;
+    cups.update("a", |printer| printer.uri_schemes = vec!["file"]);
+    let vars = [("KPRINT_PRINT_URI_PREFIXES", "file:///srv/share/")];
+    let app = app_with(cups.url(), "a b", &vars).await;
+    assert!(app
+        .fetch
+        .by_reference("file:///srv/share/../secrets/passwd")
+        .is_none());
+    assert!(app
+        .fetch
+        .by_reference("https://example.com/a.pdf")
+        .is_none());
+    let url = app
+        .fetch
+        .by_reference("file:///srv/share/flyers/./gm.pdf")
+        .unwrap();
+
+    let submitted = service::submit_uri(&app, USER, false, &[], "a".to_string(), options(), &url)
+        .await
+        .unwrap();
+
+    let printed = cups.received_of(Operation::PrintUri);
+    assert_eq!(printed.len(), 1);
+    assert_eq!(
+        printed[0].text("document-uri").as_deref(),
+        Some("file:///srv/share/flyers/gm.pdf")
+    );
+    assert!(printed[0].document.is_empty());
+    assert_eq!(submitted.job.ipp_job_id, Some(100));
+
+    let err = service::submit_uri(&app, USER, false, &[], "b".to_string(), options(), &url)
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, KprintError::Validation(_)));
+    assert_eq!(cups.received_of(Operation::PrintUri).len(), 1);
+}
+
+#[tokio::test]
+async fn asks_the_printer_how_jobs_are_doing() {
+    let (cups, app) = setup().await;
+    let submitted = service::submit(&app, USER, false, &[], "a".to_string(), options(), text())
+        .await
+        .unwrap();
+    cups.update("a", |printer| printer.job_state = IppJobState::Completed);
+
+    let status = service::job_status(&app, USER, submitted.job.id)
+        .await
+        .unwrap();
+
+    assert_eq!(status.state, JobState::Completed);
+    let asked = cups.received_of(Operation::GetJobAttributes);
+    assert_eq!(
+        asked[0].attribute(IppAttribute::JOB_ID),
+        Some(&IppValue::Integer(100))
+    );
+    assert_eq!(
+        app.history.get(&submitted.job.id).unwrap().state,
+        JobState::Completed
+    );
+}
+
+#[tokio::test]
+async fn cancels_jobs_on_the_printer() {
+    let (cups, app) = setup().await;
+    let submitted = service::submit(&app, USER, false, &[], "a".to_string(), options(), text())
+        .await
+        .unwrap();
+
+    service::cancel_job(&app, USER, submitted.job.id)
+        .await
+        .unwrap();
+
+    let canceled = cups.received_of(Operation::CancelJob);
+    assert_eq!(canceled.len(), 1);
+    assert_eq!(canceled[0].printer, "a");
+    assert_eq!(
+        canceled[0].attribute(IppAttribute::JOB_ID),
+        Some(&IppValue::Integer(100))
+    );
+}
@@ -0,0 +1,27 @@
+use super::mock_cups::MockCups;
+use super::{app_with, try_app_with};
+use crate::probe;
+
+#[tokio::test]
+async fn printers_that_dont_answer_are_marked_degraded() {
+    let cups = MockCups::start(&["a"]).await;
+    let app = app_with(cups.url(), "a gone", &[]).await;
+
+    let degraded = probe::probe(&app).await;
+
+    assert_eq!(degraded, vec!["gone"]);
+    assert!(app.breakers.open_until("gone").is_some());
+    assert!(app.breakers.open_until("a").is_none());
+    // What it said is kept for the first person to print
+    assert!(app.printer_cache.get("a", &app.printers["a"]).await.is_ok());
+    assert_eq!(cups.received().len(), 2);
+}
+
+#[tokio::test]
+async fn cups_urls_printers_cant_be_reached_at_are_turned_down() {
+    for cups in ["ftp://cups.csh.rit.edu", "http://", "not a url"] {
+        let err = try_app_with(cups, "a", &[]).await.map(|_| ()).unwrap_err();
+
+        assert!(err.to_string().contains("KPRINT_CUPS_URL"), "{cups}: {err}");
+    }
+}
@@ -0,0 +1,39 @@
+use super::{options, setup, text, USER};
+use crate::service;
+use ipp::prelude::*;
+use kprint_client::models::JobState;
+
+#[tokio::test]
+async fn polling_jobs_asks_for_their_printers_queue_once() {
+    let (cups, app) = setup().await;
+    let mut ids = vec![];
+    for _ in 0..2 {
+        let submitted = service::submit(&app, USER, false, &[], "a".to_string(), options(), text())
+            .await
+            .unwrap();
+        ids.push(submitted.job.id);
+    }
+
+    let statuses = futures::future::join_all(
+        ids.iter()
+            .cycle()
+            .take(6)
+            .map(|id| service::job_status(&app, USER, *id)),
+    )
+    .await;
+
+    for status in statuses {
+        assert_eq!(status.unwrap().state, JobState::Pending);
+    }
+    assert_eq!(cups.received_of(Operation::GetJobs).len(), 1);
+    assert!(cups.received_of(Operation::GetJobAttributes).is_empty());
+
+    // Admins checking up on it go straight to the printer
+    service::fresh_job_status(&app, USER, ids[0]).await.unwrap();
+    assert_eq!(cups.received_of(Operation::GetJobAttributes).len(), 1);
+
+    // Canceling one changes the queue, so it's asked for again
+    service::cancel_job(&app, USER, ids[0]).await.unwrap();
+    service::job_status(&app, USER, ids[1]).await.unwrap();
+    assert_eq!(cups.received_of(Operation::GetJobs).len(), 2);
+}
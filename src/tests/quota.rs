@@ -0,0 +1,84 @@
+use super::{app_with, USER};
+use crate::app::AppState;
+use crate::error::KprintError;
+use chrono::{Duration, Utc};
+use kprint_client::models::{ColorMode, DuplexMode, HistoryEntry, JobState};
+use uuid::Uuid;
+
+async fn app() -> AppState {
+    app_with(
+        "http://cups.invalid",
+        "null:dev",
+        &[
+            ("KPRINT_QUOTA_TIERS", "members:member=10"),
+            ("KPRINT_QUOTA_DEFAULT_TIER", "members"),
+        ],
+    )
+    .await
+}
+
+#[tokio::test]
+async fn jobs_on_their_way_cant_spend_the_same_units() {
+    let app = app().await;
+    let quotas = &app.quotas;
+
+    quotas.reserve(&app, USER, &[], 8).await.unwrap();
+
+    assert!(matches!(
+        quotas.reserve(&app, USER, &[], 4).await,
+        Err(KprintError::QuotaExceeded {
+            remaining: 2,
+            needed: Some(4),
+            ..
+        })
+    ));
+    assert_eq!(quotas.check(&app, USER, &[], None).await.unwrap(), Some(2));
+    quotas.reserve(&app, USER, &[], 2).await.unwrap();
+    assert!(quotas.check(&app, USER, &[], None).await.is_err());
+}
+
+#[tokio::test]
+async fn refunds_give_units_back() {
+    let app = app().await;
+    let quotas = &app.quotas;
+
+    quotas.reserve(&app, USER, &[], 10).await.unwrap();
+    quotas.refund(USER, 10);
+
+    quotas.reserve(&app, USER, &[], 10).await.unwrap();
+    // Nobody else's quota is touched
+    quotas.reserve(&app, "mom", &[], 10).await.unwrap();
+}
+
+fn printed(owner: &str, days_ago: i64) -> HistoryEntry {
+    HistoryEntry {
+        id: Uuid::new_v4(),
+        owner: owner.to_string(),
+        printer: "dev".to_string(),
+        title: "Constitution".to_string(),
+        submitted_at: Utc::now() - Duration::days(days_ago),
+        sides: DuplexMode::OneSided,
+        color_mode: ColorMode::Grayscale,
+        copies: 1,
+        pages: Some(3),
+        counted_pages: None,
+        quota_units: None,
+        state: JobState::Completed,
+        failure: None,
+        tags: vec![],
+    }
+}
+
+#[tokio::test]
+async fn only_a_members_own_jobs_this_period_are_used() {
+    let app = app().await;
+    app.history.record(printed(USER, 0));
+    app.history.record(printed("mom", 0));
+    // Whatever the period, it's over by now
+    app.history.record(printed(USER, 400));
+
+    let quota = app.quotas.quota(&app, USER, &[]).await.unwrap();
+
+    assert_eq!(quota.used, 3);
+    assert_eq!(quota.remaining, Some(7));
+}
@@ -0,0 +1,130 @@
+use super::{app_with, USER};
+use crate::app::AppState;
+use crate::error::KprintError;
+use crate::receipt::{self, Format, ReceiptQuery};
+use crate::service;
+use chrono::Utc;
+use kprint_client::models::{ColorMode, DuplexMode, HistoryEntry, JobState, QuotaUnits};
+use uuid::Uuid;
+
+async fn app() -> AppState {
+    app_with(
+        "http://cups.invalid",
+        "null:dev",
+        &[("KPRINT_QUOTA_COSTS", "color=4 duplex-discount=1")],
+    )
+    .await
+}
+
+fn entry(state: JobState, failure: Option<&str>) -> HistoryEntry {
+    HistoryEntry {
+        id: Uuid::new_v4(),
+        owner: USER.to_string(),
+        printer: "dev".to_string(),
+        title: "Financial committee budget".to_string(),
+        submitted_at: Utc::now(),
+        sides: DuplexMode::TwoSidedLongEdge,
+        color_mode: ColorMode::Color,
+        copies: 1,
+        pages: Some(4),
+        counted_pages: None,
+        quota_units: None,
+        state,
+        failure: failure.map(str::to_string),
+        tags: vec!["financial".to_string()],
+    }
+}
+
+#[tokio::test]
+async fn receipts_say_what_the_job_cost() {
+    let app = app().await;
+    let printed = entry(JobState::Completed, None);
+    app.history.record(printed.clone());
+
+    let receipt = service::job_receipt(&app, USER, printed.id).unwrap();
+
+    assert_eq!(receipt.owner, USER);
+    assert_eq!(receipt.pages, Some(4));
+    assert_eq!(receipt.tags, ["financial"]);
+    // 4 pages on 2 sheets
+    assert_eq!(receipt.quota_units.color, 16);
+    assert_eq!(receipt.quota_units.duplex_discount, 2);
+    assert_eq!(receipt.quota_units.total, 14);
+    let lines = receipt::lines(&receipt)
+        .into_iter()
+        .map(|(_, line)| line)
+        .collect::<Vec<_>>();
+    assert!(lines.contains(&format!("Sent by: {USER}")), "{lines:?}");
+    assert!(lines.contains(&"Total: 14 units".to_string()), "{lines:?}");
+    let pdf = receipt::render(&receipt).unwrap();
+    assert_eq!(crate::pdf::page_count(&pdf).unwrap(), 1);
+}
+
+#[tokio::test]
+async fn receipts_say_what_was_charged_when_it_was_sent() {
+    let app = app().await;
+    // Back when color only cost double
+    let charged = QuotaUnits {
+        grayscale: 0,
+        color: 8,
+        duplex_discount: 2,
+        total: 6,
+    };
+    let printed = HistoryEntry {
+        quota_units: Some(charged),
+        ..entry(JobState::Completed, None)
+    };
+    app.history.record(printed.clone());
+
+    let receipt = service::job_receipt(&app, USER, printed.id).unwrap();
+
+    assert_eq!(receipt.quota_units, charged);
+}
+
+#[tokio::test]
+async fn failed_jobs_cost_nothing() {
+    let app = app().await;
+    let failed = HistoryEntry {
+        quota_units: Some(QuotaUnits {
+            total: 14,
+            ..QuotaUnits::default()
+        }),
+        ..entry(JobState::Unknown, Some("The printer couldn't be reached"))
+    };
+    app.history.record(failed.clone());
+
+    let receipt = service::job_receipt(&app, USER, failed.id).unwrap();
+
+    assert_eq!(receipt.quota_units.total, 0);
+    assert_eq!(
+        receipt.failure.as_deref(),
+        Some("The printer couldn't be reached")
+    );
+}
+
+#[tokio::test]
+async fn only_the_owner_gets_a_receipt() {
+    let app = app().await;
+    let printed = entry(JobState::Completed, None);
+    app.history.record(printed.clone());
+
+    assert!(matches!(
+        service::job_receipt(&app, "mom", printed.id),
+        Err(KprintError::JobNotFound(_))
+    ));
+    assert!(matches!(
+        service::job_receipt(&app, USER, Uuid::new_v4()),
+        Err(KprintError::JobNotFound(_))
+    ));
+}
+
+#[test]
+fn receipts_are_pdf_unless_json_is_asked_for() {
+    let query = |format: Option<&str>| ReceiptQuery {
+        format: format.map(str::to_string),
+    };
+    assert_eq!(query(None).validate().unwrap(), Format::Pdf);
+    assert_eq!(query(Some("json")).validate().unwrap(), Format::Json);
+    let errors = query(Some("xml")).validate().unwrap_err();
+    assert_eq!(errors[0].field, "format");
+}
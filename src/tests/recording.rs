@@ -0,0 +1,64 @@
+use super::mock_cups::MockCups;
+use super::{app_with, options, USER};
+use crate::document::Document;
+use crate::recording::Exchange;
+use crate::service;
+use ipp::prelude::*;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn records_requests_with_their_documents_cut_short() {
+    let cups = MockCups::start(&["a"]).await;
+    let dir = std::env::temp_dir().join(format!("kprint-recordings-{}", Uuid::new_v4()));
+    let app = app_with(
+        cups.url(),
+        "a",
+        &[
+            ("KPRINT_IPP_RECORD_DIR", dir.to_str().unwrap()),
+            ("KPRINT_IPP_RECORD_PAYLOAD_BYTES", "10"),
+        ],
+    )
+    .await;
+    let document = "A document longer than what's recorded of it\n".repeat(10);
+
+    service::submit(
+        &app,
+        USER,
+        false,
+        &[],
+        "a".to_string(),
+        options(),
+        Document::Buffered(document.clone().into()),
+    )
+    .await
+    .unwrap();
+
+    let exchanges = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|file| serde_json::from_slice(&std::fs::read(file.unwrap().path()).unwrap()).unwrap())
+        .collect::<Vec<Exchange>>();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let printed = exchanges
+        .iter()
+        .find(|exchange| exchange.operation == "SendDocument")
+        .expect("the Send-Document wasn't recorded");
+    let sent = cups.received_of(Operation::SendDocument).remove(0);
+    assert!(printed.truncated);
+    assert!(printed.request.ends_with(&document.as_bytes()[..10]));
+    assert_eq!(
+        printed.request_length as usize,
+        printed.request.len() - 10 + sent.document.len()
+    );
+    assert!(printed.uri.ends_with("/printers/a"));
+    let response = printed.response.as_ref().unwrap();
+    let response = ipp::parser::IppParser::new(ipp::reader::IppReader::new(std::io::Cursor::new(
+        response.clone(),
+    )))
+    .parse()
+    .unwrap();
+    assert!(response.header().status_code().is_success());
+    // Asking the printer what it takes is recorded too
+    assert!(exchanges
+        .iter()
+        .any(|exchange| exchange.operation == "GetPrinterAttributes"));
+}
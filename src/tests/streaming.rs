@@ -0,0 +1,122 @@
+use super::{options, pdf, setup, USER};
+use crate::service;
+use crate::streaming::StreamTuning;
+use actix_web::web::Bytes;
+use futures::SinkExt;
+use ipp::prelude::*;
+use kprint_client::models::JobState;
+use std::time::Duration;
+
+#[tokio::test]
+async fn documents_arriving_in_chunks_are_sent_whole() {
+    let (cups, app) = setup().await;
+    let document = pdf();
+    let (mut tx, payload) = service::document_channel(&StreamTuning::default());
+    let chunks = document
+        .chunks(100)
+        .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+        .collect::<Vec<_>>();
+    tokio::spawn(async move {
+        for chunk in chunks {
+            tx.send(chunk).await.unwrap();
+        }
+    });
+
+    service::submit(
+        &app,
+        USER,
+        false,
+        &[],
+        "a".to_string(),
+        options(),
+        payload.into(),
+    )
+    .await
+    .unwrap();
+
+    // The job's made first, so there's one to cancel if the upload breaks off
+    let created = cups.received_of(Operation::CreateJob);
+    assert_eq!(created.len(), 1);
+    assert!(created[0].document.is_empty());
+    let sent = cups.received_of(Operation::SendDocument);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].document, document);
+    assert_eq!(sent[0].text("last-document").as_deref(), Some("true"));
+    assert!(cups.received_of(Operation::PrintJob).is_empty());
+}
+
+#[tokio::test]
+async fn uploads_that_break_off_are_not_printed() {
+    let (cups, app) = setup().await;
+    let (mut tx, payload) = service::document_channel(&StreamTuning::default());
+    tokio::spawn(async move {
+        // Past the start kprint checks the format by, so it's on its way to
+        // the printer by the time the upload breaks off
+        let first_half = "The first half of a document\n".repeat(100);
+        tx.send(Ok(Bytes::from(first_half))).await.unwrap();
+        // What the upload's forwarded as when the uploader hangs up
+        tx.send(Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionAborted,
+            "hung up",
+        )))
+        .await
+        .unwrap();
+    });
+
+    let err = service::submit(
+        &app,
+        USER,
+        false,
+        &[],
+        "a".to_string(),
+        options(),
+        payload.into(),
+    )
+    .await;
+
+    assert!(err.is_err());
+    // It can't be sent again, so it's given up on after the first try, and
+    // the job it was going to be is canceled so none of it prints
+    assert!(cups.received_of(Operation::SendDocument).is_empty());
+    let created = cups.received_of(Operation::CreateJob).len();
+    assert_eq!(created, 1);
+    let canceled = cups.received_of(Operation::CancelJob);
+    assert_eq!(canceled.len(), 1);
+    assert_eq!(canceled[0].text("job-id").as_deref(), Some("100"));
+    let history = app.history.filter(|_| true);
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].state, JobState::Aborted);
+}
+
+#[tokio::test]
+async fn jobs_are_canceled_when_the_uploader_takes_the_request_with_them() {
+    let (cups, app) = setup().await;
+    let (mut tx, payload) = service::document_channel(&StreamTuning::default());
+    let first_half = "The first half of a document\n".repeat(100);
+    tx.send(Ok(Bytes::from(first_half))).await.unwrap();
+
+    // Like actix dropping the handler when the connection closes, with the
+    // rest of the document never arriving
+    let submit = service::submit(
+        &app,
+        USER,
+        false,
+        &[],
+        "a".to_string(),
+        options(),
+        payload.into(),
+    );
+    let timed_out = tokio::time::timeout(Duration::from_millis(500), submit).await;
+    assert!(timed_out.is_err());
+    drop(tx);
+
+    for _ in 0..50 {
+        if !cups.received_of(Operation::CancelJob).is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let canceled = cups.received_of(Operation::CancelJob);
+    assert_eq!(canceled.len(), 1);
+    assert_eq!(canceled[0].text("job-id").as_deref(), Some("100"));
+}
@@ -0,0 +1,54 @@
+use super::app_with;
+use crate::timeout::Timeouts;
+use actix_web::{test, web, App, HttpResponse};
+use std::time::Duration;
+
+async fn slow() -> HttpResponse {
+    actix_web::rt::time::sleep(Duration::from_millis(1500)).await;
+    HttpResponse::Ok().finish()
+}
+
+#[actix_web::test]
+async fn slow_requests_are_given_up_on_unless_theyre_uploads() {
+    let app = app_with(
+        "http://localhost:631",
+        "null:a",
+        &[
+            ("KPRINT_REQUEST_TIMEOUT", "1"),
+            ("KPRINT_ADMIN_REQUEST_TIMEOUT", "0"),
+        ],
+    )
+    .await;
+    let service = test::init_service(
+        App::new().app_data(web::Data::new(app)).service(
+            web::scope("/api/v1")
+                .wrap(Timeouts)
+                .service(web::resource("/jobs").name("list_jobs").to(slow))
+                .service(web::resource("/uploads/{id}").name("upload_chunk").to(slow))
+                .service(web::resource("/admin/stats").name("stats").to(slow)),
+        ),
+    )
+    .await;
+
+    // Answered as an error, which the server turns into the response
+    let err = test::try_call_service(
+        &service,
+        test::TestRequest::get().uri("/api/v1/jobs").to_request(),
+    )
+    .await
+    .unwrap_err();
+    let response = err.error_response();
+    assert_eq!(response.status(), 504);
+    let body = actix_web::body::to_bytes(response.into_body())
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["code"], "REQUEST_TIMED_OUT");
+    assert_eq!(body["details"]["timeoutSeconds"], 1);
+
+    for uri in ["/api/v1/uploads/1", "/api/v1/admin/stats"] {
+        let response =
+            test::call_service(&service, test::TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(response.status(), 200, "{uri}");
+    }
+}
@@ -0,0 +1,43 @@
+use super::setup;
+use crate::service;
+use crate::trace::{self, SpanContext};
+
+const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+#[test]
+fn traceparents_are_read_the_way_the_spec_says() {
+    let span = SpanContext::parse(TRACEPARENT, Some("congo=t61rcWkgMzE")).unwrap();
+    assert_eq!(span.traceparent(), TRACEPARENT);
+    assert_eq!(span.state.as_deref(), Some("congo=t61rcWkgMzE"));
+    // Later versions can add fields
+    assert!(SpanContext::parse(&format!("cc{}-what-not", &TRACEPARENT[2..]), None).is_some());
+    for bad in [
+        "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+        "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+        "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+    ] {
+        assert!(SpanContext::parse(bad, None).is_none(), "{bad}");
+    }
+}
+
+#[tokio::test]
+async fn requests_to_cups_carry_on_the_trace_in_a_span_of_their_own() {
+    let (cups, app) = setup().await;
+    let parent = SpanContext::parse(TRACEPARENT, None).unwrap();
+
+    trace::scope(parent.clone(), service::printer_status(&app, "a"))
+        .await
+        .unwrap();
+    service::printer_status(&app, "b").await.unwrap();
+
+    let received = cups.received();
+    let sent = received[0].traceparent.as_deref().unwrap();
+    let sent = SpanContext::parse(sent, None).unwrap();
+    assert_eq!(sent.trace_id, parent.trace_id);
+    assert_ne!(sent.span_id, parent.span_id);
+    // Outside of any request there's no trace to carry on
+    assert_eq!(received.last().unwrap().traceparent, None);
+}
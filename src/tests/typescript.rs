@@ -0,0 +1,39 @@
+use crate::typescript;
+
+#[test]
+fn writes_a_definition_of_every_model() {
+    let types = typescript::generate();
+
+    assert!(
+        types.contains("\nexport interface JobStatus {\n"),
+        "{types}"
+    );
+    assert!(
+        types.contains("\nexport type Envelope = \"number-10\" | \"monarch\" | \"dl\" | \"c5\";\n")
+    );
+    let media = types
+        .split("\nexport interface MediaMargins {\n")
+        .nth(1)
+        .and_then(|rest| rest.split("\n}\n").next())
+        .unwrap();
+    for side in ["top", "bottom", "left", "right"] {
+        assert!(media.contains(&format!("  {side}: number;")), "{media}");
+    }
+    assert!(types.contains("  media?: (null | Media);"), "{types}");
+    assert_eq!(types.matches('{').count(), types.matches('}').count());
+}
+
+#[test]
+fn the_print_options_are_a_print_query() {
+    let types = typescript::generate();
+    let query = types
+        .split("\nexport interface PrintQuery {\n")
+        .nth(1)
+        .and_then(|rest| rest.split("\n}\n").next())
+        .unwrap();
+
+    assert!(query.contains("  title: string;"), "{query}");
+    assert!(query.contains("  copies: number;"), "{query}");
+    assert!(query.contains("  sides?: DuplexMode;"), "{query}");
+    assert!(query.contains("  poster?: string;"), "{query}");
+}
@@ -0,0 +1,183 @@
+//! Giving up on requests that take too long, so a wedged printer or database
+//! can't hold connections open forever.
+//!
+//! Requests get `KPRINT_REQUEST_TIMEOUT` seconds, 30 by default, to be
+//! answered, and admin ones `KPRINT_ADMIN_REQUEST_TIMEOUT`, 120 by default,
+//! since reports and exports read a lot of history. Either can be 0 to never
+//! give up. Requests that upload or print a document aren't timed out at all,
+//! since a big document on a slow connection is supposed to take a while,
+//! and the IPP client already gives up on printers that don't answer. A
+//! request that runs out of time is answered with `REQUEST_TIMED_OUT` and
+//! what it was doing is dropped, though anything it started in the
+//! background, like sending a spooled job on, carries on.
+
+use crate::app::AppState;
+use crate::error::{error_response, ErrorBody, KprintError};
+use crate::request_id::{RequestId, REQUEST_ID};
+use crate::version::ApiVersion;
+use actix_web::body::MessageBody;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::InternalError,
+    http::header::HeaderValue,
+    web::Data,
+    HttpMessage, HttpResponse, ResponseError,
+};
+use anyhow::Context as _;
+use futures::future::{ready, LocalBoxFuture, Ready};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// Handlers, by name, that take a document or wait on a printer to print one.
+const EXEMPT: &[&str] = &[
+    "print",
+    "print_default",
+    "print_broadcast",
+    "dry_run",
+    "print_inline",
+    "print_url",
+    "create_upload",
+    "upload_chunk",
+    "print_upload",
+    "add_document",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestTimeouts {
+    /// `None` for never
+    pub default: Option<Duration>,
+    pub admin: Option<Duration>,
+}
+
+impl Default for RequestTimeouts {
+    fn default() -> Self {
+        RequestTimeouts {
+            default: Some(Duration::from_secs(30)),
+            admin: Some(Duration::from_secs(120)),
+        }
+    }
+}
+
+fn seconds(var: &str, default: Option<Duration>) -> anyhow::Result<Option<Duration>> {
+    let Ok(seconds) = std::env::var(var) else {
+        return Ok(default);
+    };
+    let seconds = seconds.parse().context(var.to_string())?;
+    Ok((seconds > 0).then(|| Duration::from_secs(seconds)))
+}
+
+impl RequestTimeouts {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let default = RequestTimeouts::default();
+        Ok(RequestTimeouts {
+            default: seconds("KPRINT_REQUEST_TIMEOUT", default.default)?,
+            admin: seconds("KPRINT_ADMIN_REQUEST_TIMEOUT", default.admin)?,
+        })
+    }
+
+    /// How long `req` gets, if it's to be timed out at all.
+    pub fn of(&self, req: &ServiceRequest) -> Option<Duration> {
+        if req.match_name().is_some_and(|name| EXEMPT.contains(&name)) {
+            return None;
+        }
+        let admin = req
+            .match_pattern()
+            .is_some_and(|pattern| pattern.contains("/admin/"));
+        if admin {
+            self.admin
+        } else {
+            self.default
+        }
+    }
+}
+
+/// Middleware timing out the requests [`RequestTimeouts`] says to.
+pub struct Timeouts;
+
+impl<S, B> Transform<S, ServiceRequest> for Timeouts
+where
+    B: MessageBody + 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = TimeoutsService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TimeoutsService { service }))
+    }
+}
+
+pub struct TimeoutsService<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for TimeoutsService<S>
+where
+    B: MessageBody + 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let timeout = req
+            .app_data::<Data<AppState>>()
+            .and_then(|app_data| app_data.request_timeouts.of(&req));
+        let Some(timeout) = timeout else {
+            return Box::pin(self.service.call(req));
+        };
+        let path = req.path().to_string();
+        let version = req.extensions().get::<ApiVersion>().copied();
+        let request_id = req.extensions().get::<RequestId>().copied();
+        let future = self.service.call(req);
+        Box::pin(async move {
+            match actix_web::rt::time::timeout(timeout, future).await {
+                Ok(response) => response,
+                Err(_) => {
+                    log::warn!("Gave up on {path} after {}s", timeout.as_secs());
+                    Err(timed_out(timeout, version, request_id))
+                }
+            }
+        })
+    }
+}
+
+/// The error for a request that ran out of time. The request went with what
+/// was dropped, so there's nothing to answer with but an error, which skips
+/// the middleware that'd usually finish it off. It's finished off here
+/// instead, the way [`crate::error::render_error`] would have.
+fn timed_out(
+    timeout: Duration,
+    version: Option<ApiVersion>,
+    request_id: Option<RequestId>,
+) -> actix_web::Error {
+    let error = KprintError::RequestTimedOut(timeout);
+    let status = error.status_code();
+    let mut response = match version {
+        Some(ApiVersion::Legacy) => HttpResponse::build(status).body(error.to_string()),
+        _ => {
+            let body = ErrorBody {
+                request_id: request_id.map(|id| id.to_string()),
+                ..error.body()
+            };
+            error_response(body, status)
+        }
+    };
+    if let Some(id) = request_id {
+        response.headers_mut().insert(
+            REQUEST_ID,
+            HeaderValue::from_str(&id.to_string()).expect("UUIDs are valid header values"),
+        );
+    }
+    InternalError::from_response(error, response).into()
+}
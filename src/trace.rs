@@ -0,0 +1,236 @@
+//! W3C trace context, so a slow job can be followed from kprint through the
+//! proxy in front of CUPS to CUPS itself.
+//!
+//! Each request kprint serves is a span, a child of the one in its
+//! `traceparent` header if it came with one, or the start of a new trace if
+//! it didn't. Every IPP request sent while serving it is a child span of its
+//! own, sent along in a `traceparent` header of its own with whatever
+//! `tracestate` came in, and logged under `kprint::trace` once it's answered.
+//! The proxy passes the headers on to CUPS. Work that carries on after the
+//! request is answered, like sending a spooled job on, is a new span linked
+//! back to the request's, and says so in the log.
+
+use crate::logging;
+use actix_web::body::MessageBody;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::HeaderName,
+    HttpMessage,
+};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::task::{Context, Poll};
+use uuid::Uuid;
+
+pub const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+pub const TRACESTATE: HeaderName = HeaderName::from_static("tracestate");
+
+/// Set on spans that are being recorded
+const SAMPLED: u8 = 0x01;
+
+tokio::task_local! {
+    static CURRENT: SpanContext;
+}
+
+/// Where a span sits in its trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub flags: u8,
+    /// Passed on untouched, since it's for tracing systems and not kprint
+    pub state: Option<String>,
+}
+
+fn random_span_id() -> [u8; 8] {
+    let mut id = [0; 8];
+    // Version 4 UUIDs are random bar a few bits in the middle
+    id.copy_from_slice(&Uuid::new_v4().as_bytes()[..8]);
+    id
+}
+
+fn parse_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != N * 2 || !hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+impl SpanContext {
+    /// The first span of a trace of its own.
+    pub fn root() -> Self {
+        SpanContext {
+            trace_id: *Uuid::new_v4().as_bytes(),
+            span_id: random_span_id(),
+            flags: SAMPLED,
+            state: None,
+        }
+    }
+
+    /// Reads a `traceparent` header, or returns `None` if it isn't one. Later
+    /// versions may add fields, which are ignored, but have to start with
+    /// these.
+    pub fn parse(traceparent: &str, tracestate: Option<&str>) -> Option<Self> {
+        let mut fields = traceparent.trim().split('-');
+        let version = parse_hex::<1>(fields.next()?)?[0];
+        let trace_id = parse_hex::<16>(fields.next()?)?;
+        let span_id = parse_hex::<8>(fields.next()?)?;
+        let flags = parse_hex::<1>(fields.next()?)?[0];
+        let extra = fields.next().is_some();
+        if version == 0xff || (version == 0 && extra) {
+            return None;
+        }
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+        Some(SpanContext {
+            trace_id,
+            span_id,
+            flags,
+            state: tracestate.map(str::to_string),
+        })
+    }
+
+    /// A span under this one, in the same trace.
+    pub fn child(&self) -> Self {
+        SpanContext {
+            span_id: random_span_id(),
+            ..self.clone()
+        }
+    }
+
+    /// What to send as `traceparent`.
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            hex(&self.trace_id),
+            hex(&self.span_id),
+            self.flags
+        )
+    }
+
+    pub fn trace_id(&self) -> String {
+        hex(&self.trace_id)
+    }
+
+    pub fn span_id(&self) -> String {
+        hex(&self.span_id)
+    }
+}
+
+impl Display for SpanContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.traceparent())
+    }
+}
+
+/// The span of whatever's being done right now, if it's part of a trace.
+pub fn current() -> Option<SpanContext> {
+    CURRENT.try_with(Clone::clone).ok()
+}
+
+/// Runs `future` as part of `span`.
+pub async fn scope<F: Future>(span: SpanContext, future: F) -> F::Output {
+    CURRENT.scope(span, future).await
+}
+
+/// Makes `future`, which carries on after the request that started it is
+/// answered, a new span linked back to the current one, if there is one.
+/// Anything spawned has to go through this to stay in the trace, and before
+/// it's spawned, since spawned tasks don't see the current span.
+pub fn linked<F: Future>(what: &'static str, future: F) -> impl Future<Output = F::Output> {
+    let from = current();
+    async move {
+        match from {
+            Some(from) => {
+                let span = from.child();
+                log::info!(
+                    target: "kprint::trace",
+                    "trace_id={} span_id={} linked_from={} name={what:?}",
+                    span.trace_id(),
+                    span.span_id(),
+                    from.span_id()
+                );
+                scope(span, future).await
+            }
+            None => future.await,
+        }
+    }
+}
+
+/// Middleware making each request a span, carrying on the trace from the
+/// client's `traceparent` when there is one.
+pub struct Tracing;
+
+impl<S, B> Transform<S, ServiceRequest> for Tracing
+where
+    B: MessageBody + 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = TracingService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TracingService { service }))
+    }
+}
+
+pub struct TracingService<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for TracingService<S>
+where
+    B: MessageBody + 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        let parent = header(TRACEPARENT)
+            .and_then(|traceparent| SpanContext::parse(traceparent, header(TRACESTATE)));
+        let span = match &parent {
+            Some(parent) => parent.child(),
+            None => SpanContext::root(),
+        };
+        log::info!(
+            target: "kprint::trace",
+            "trace_id={} span_id={} parent_id={} name={:?}",
+            span.trace_id(),
+            span.span_id(),
+            parent.map_or_else(String::new, |parent| parent.span_id()),
+            format!("{} {}", req.method(), logging::path(req.path()))
+        );
+        req.extensions_mut().insert(span.clone());
+
+        // Some of the handling happens straight away, rather than when the
+        // future's polled
+        let future = CURRENT.sync_scope(span.clone(), || self.service.call(req));
+        Box::pin(scope(span, future))
+    }
+}
@@ -0,0 +1,224 @@
+//! TypeScript definitions of everything the API takes and gives back, for
+//! the web frontend, written with `kprint gen types`.
+//!
+//! They're made from the same OpenAPI document `/api/openapi.json` serves,
+//! so they can't say anything the API doesn't: every schema becomes an
+//! interface, or a type for the ones that aren't objects, and the print
+//! endpoint's query parameters become `PrintQuery`.
+
+use crate::openapi::ApiDoc;
+use serde_json::{Map, Value};
+use std::fmt::Write;
+use utoipa::OpenApi;
+
+/// The endpoint whose query parameters are the print options
+const PRINT_PATH: &str = "/printers/{printer}/print";
+
+/// `text` as a doc comment, indented by `indent`.
+fn comment(text: Option<&Value>, indent: &str) -> String {
+    let Some(text) = text.and_then(Value::as_str).filter(|text| !text.is_empty()) else {
+        return String::new();
+    };
+    let mut comment = format!("{indent}/**\n");
+    for line in text.lines() {
+        // Nothing in a description gets to end the comment early
+        let line = line.replace("*/", "*\\/");
+        match line.is_empty() {
+            true => comment += &format!("{indent} *\n"),
+            false => comment += &format!("{indent} * {line}\n"),
+        }
+    }
+    comment + &format!("{indent} */\n")
+}
+
+/// `name` as a property name, quoted if it has to be.
+fn property(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match plain {
+        true => name.to_string(),
+        false => Value::from(name).to_string(),
+    }
+}
+
+/// The members of object `schema`, one per line, indented by `indent`.
+fn members(schema: &Map<String, Value>, indent: &str) -> String {
+    let empty = Map::new();
+    let properties = schema
+        .get("properties")
+        .and_then(Value::as_object)
+        .unwrap_or(&empty);
+    let required = schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let mut members = String::new();
+    for (name, property_schema) in properties {
+        let optional = match required.iter().any(|required| required == name) {
+            true => "",
+            false => "?",
+        };
+        members += &comment(property_schema.get("description"), indent);
+        members += &format!(
+            "{indent}{}{optional}: {};\n",
+            property(name),
+            type_of(property_schema, indent)
+        );
+    }
+    if let Some(values) = schema
+        .get("additionalProperties")
+        .filter(|values| values.is_object())
+    {
+        members += &format!("{indent}[key: string]: {};\n", type_of(values, indent));
+    }
+    members
+}
+
+/// Joins `schemas` into one type with `separator`, which is ` | ` or ` & `.
+fn combined(schemas: &[Value], separator: &str, indent: &str) -> String {
+    let types = schemas
+        .iter()
+        .map(|schema| type_of(schema, indent))
+        .collect::<Vec<_>>();
+    match types.len() {
+        0 => "unknown".to_string(),
+        1 => types.into_iter().next().unwrap(),
+        _ => format!("({})", types.join(separator)),
+    }
+}
+
+/// The TypeScript type of JSON Schema `schema`, nested `indent` deep.
+fn type_of(schema: &Value, indent: &str) -> String {
+    let Some(schema) = schema.as_object() else {
+        return "unknown".to_string();
+    };
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        return reference
+            .rsplit('/')
+            .next()
+            .unwrap_or(reference)
+            .to_string();
+    }
+    if let Some(value) = schema.get("const") {
+        return value.to_string();
+    }
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        return values
+            .iter()
+            .map(Value::to_string)
+            .collect::<Vec<_>>()
+            .join(" | ");
+    }
+    for (keyword, separator) in [("oneOf", " | "), ("anyOf", " | "), ("allOf", " & ")] {
+        if let Some(schemas) = schema.get(keyword).and_then(Value::as_array) {
+            return combined(schemas, separator, indent);
+        }
+    }
+    let types = match schema.get("type") {
+        Some(Value::String(name)) => vec![name.as_str()],
+        Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    };
+    if types.is_empty() {
+        return "unknown".to_string();
+    }
+    types
+        .into_iter()
+        .map(|name| match name {
+            "string" => "string".to_string(),
+            "integer" | "number" => "number".to_string(),
+            "boolean" => "boolean".to_string(),
+            "null" => "null".to_string(),
+            "array" => {
+                let items = schema
+                    .get("items")
+                    .map_or("unknown".to_string(), |items| type_of(items, indent));
+                match items.contains(' ') {
+                    true => format!("Array<{items}>"),
+                    false => format!("{items}[]"),
+                }
+            }
+            "object" => {
+                let members = members(schema, &format!("{indent}  "));
+                match members.is_empty() {
+                    true => "Record<string, unknown>".to_string(),
+                    false => format!("{{\n{members}{indent}}}"),
+                }
+            }
+            _ => "unknown".to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+/// One exported definition of schema `name`.
+fn definition(name: &str, schema: &Value) -> String {
+    let mut definition = comment(schema.get("description"), "");
+    let is_interface = schema.get("type").and_then(Value::as_str) == Some("object")
+        && schema.get("properties").is_some();
+    match (is_interface, schema.as_object()) {
+        (true, Some(object)) => {
+            definition += &format!("export interface {name} {{\n{}}}\n", members(object, "  "))
+        }
+        _ => definition += &format!("export type {name} = {};\n", type_of(schema, "")),
+    }
+    definition
+}
+
+/// The print endpoint's query parameters, as the schema of an object with
+/// them as its properties.
+fn print_query(spec: &Value) -> Option<Value> {
+    let parameters = spec
+        .pointer(&format!(
+            "/paths/{}/post/parameters",
+            PRINT_PATH.replace('/', "~1")
+        ))?
+        .as_array()?;
+    let mut properties = Map::new();
+    let mut required = vec![];
+    for parameter in parameters {
+        if parameter.get("in").and_then(Value::as_str) != Some("query") {
+            continue;
+        }
+        let name = parameter.get("name")?.as_str()?;
+        let mut schema = parameter.get("schema").cloned().unwrap_or_default();
+        if let (Some(description), Some(schema)) =
+            (parameter.get("description"), schema.as_object_mut())
+        {
+            schema.insert("description".to_string(), description.clone());
+        }
+        properties.insert(name.to_string(), schema);
+        if parameter.get("required").and_then(Value::as_bool) == Some(true) {
+            required.push(Value::from(name));
+        }
+    }
+    Some(serde_json::json!({
+        "type": "object",
+        "description": "The print options, as the print endpoints take them in the query string.",
+        "properties": properties,
+        "required": required,
+    }))
+}
+
+/// Every definition, as a `.ts` file.
+pub fn generate() -> String {
+    let spec = serde_json::to_value(ApiDoc::openapi()).expect("the OpenAPI document serializes");
+    let mut schemas = spec
+        .pointer("/components/schemas")
+        .and_then(Value::as_object)
+        .cloned()
+        .unwrap_or_default();
+    if let Some(query) = print_query(&spec) {
+        schemas.insert("PrintQuery".to_string(), query);
+    }
+
+    let mut file = format!(
+        "// Generated by `kprint gen types` from kprint {}, so don't edit it by hand.\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    for (name, schema) in &schemas {
+        let _ = write!(file, "\n{}", definition(name, schema));
+    }
+    file
+}
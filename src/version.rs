@@ -0,0 +1,176 @@
+use crate::error::KprintError;
+use actix_web::body::MessageBody;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue},
+    FromRequest, HttpMessage, ResponseError,
+};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use itertools::Itertools;
+use std::fmt::{Display, Formatter};
+use std::task::{Context, Poll};
+
+/// Request header a client can use to ask for a specific API version,
+/// regardless of which prefix the request came in on.
+pub const ACCEPT_VERSION: HeaderName = HeaderName::from_static("accept-version");
+/// Response header naming the API version that actually served the request.
+pub const API_VERSION: HeaderName = HeaderName::from_static("api-version");
+pub const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+
+/// The API versions kprint knows how to speak, oldest first.
+///
+/// `Legacy` is the unversioned `/api` surface the original frontend was written
+/// against. It sticks around so old clients keep working while they migrate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ApiVersion {
+    Legacy,
+    V1,
+}
+
+impl ApiVersion {
+    pub const SUPPORTED: &'static [ApiVersion] = &[ApiVersion::Legacy, ApiVersion::V1];
+    pub const LATEST: ApiVersion = ApiVersion::V1;
+
+    pub fn number(&self) -> u32 {
+        match self {
+            Self::Legacy => 0,
+            Self::V1 => 1,
+        }
+    }
+
+    pub fn from_number(number: u32) -> Option<Self> {
+        Self::SUPPORTED
+            .iter()
+            .copied()
+            .find(|version| version.number() == number)
+    }
+
+    pub fn is_deprecated(&self) -> bool {
+        *self < Self::LATEST
+    }
+}
+
+impl Display for ApiVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.number())
+    }
+}
+
+impl FromRequest for ApiVersion {
+    type Error = actix_web::error::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        _payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        ready(Ok(req
+            .extensions()
+            .get::<Self>()
+            .copied()
+            .unwrap_or(ApiVersion::Legacy)))
+    }
+}
+
+/// Middleware which decides what [`ApiVersion`] a request is served with.
+///
+/// Each mount point has a default version, which the client can override by
+/// sending `Accept-Version: <n>`. Unknown versions are refused with a 406 rather
+/// than silently falling back, so a client never gets a response shape it
+/// didn't ask for.
+pub struct ApiVersioning {
+    default: ApiVersion,
+}
+
+impl ApiVersioning {
+    pub fn new(default: ApiVersion) -> Self {
+        ApiVersioning { default }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ApiVersioning
+where
+    B: MessageBody + 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<actix_web::body::BoxBody>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = ApiVersioningService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ApiVersioningService {
+            service,
+            default: self.default,
+        }))
+    }
+}
+
+pub struct ApiVersioningService<S> {
+    service: S,
+    default: ApiVersion,
+}
+
+impl<S, B> Service<ServiceRequest> for ApiVersioningService<S>
+where
+    B: MessageBody + 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<actix_web::body::BoxBody>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let requested = req
+            .headers()
+            .get(ACCEPT_VERSION)
+            .map(|value| value.to_str().map(|value| value.trim().to_string()));
+        let version = match requested {
+            None => self.default,
+            Some(Ok(requested)) => match requested.parse().ok().and_then(ApiVersion::from_number) {
+                Some(version) => version,
+                None => {
+                    log::debug!("Client asked for unsupported API version {requested:?}");
+                    let error = KprintError::UnsupportedApiVersion {
+                        requested,
+                        supported: ApiVersion::SUPPORTED.iter().join(", "),
+                    };
+                    return Box::pin(async move { Ok(req.into_response(error.error_response())) });
+                }
+            },
+            Some(Err(_)) => {
+                let error = KprintError::InvalidRequest(
+                    "Accept-Version header isn't valid text".to_string(),
+                );
+                return Box::pin(async move { Ok(req.into_response(error.error_response())) });
+            }
+        };
+
+        req.extensions_mut().insert(version);
+
+        // Requests arriving on a legacy mount are deprecated even when they
+        // negotiated their way up to a current version
+        let deprecated = version.is_deprecated() || self.default.is_deprecated();
+        let future = self.service.call(req);
+        Box::pin(async move {
+            let mut response = future.await?;
+            let headers = response.headers_mut();
+            headers.insert(API_VERSION, HeaderValue::from(version.number()));
+            if deprecated {
+                headers.insert(DEPRECATION, HeaderValue::from_static("true"));
+                headers.insert(
+                    actix_web::http::header::LINK,
+                    HeaderValue::from_static("</api/v1>; rel=\"successor-version\""),
+                );
+            }
+            Ok(response.map_into_boxed_body())
+        })
+    }
+}